@@ -0,0 +1,21 @@
+// Feeds arbitrary config files to the config parser and every value parser, none of which
+// may panic on a malformed ion.cfg.
+
+#![no_main]
+
+use ion_core::config::{self, BootProtocol, Line};
+use ion_core::uri;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    for line in config::lines(data) {
+        if let Line::Option(_, value) = line {
+            let _ = BootProtocol::parse(value);
+            let _ = config::parse_bool(value);
+            let _ = config::parse_timeout(value);
+            let _ = config::parse_resolution(value);
+            let _ = config::parse_splash(value);
+            let _ = uri::parse_uri(value);
+        }
+    }
+});
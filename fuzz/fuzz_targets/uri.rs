@@ -0,0 +1,14 @@
+// Feeds arbitrary URIs to the URI parser and checks the invariants of the URIs it accepts.
+
+#![no_main]
+
+use ion_core::uri::parse_uri;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    if let Ok(uri) = parse_uri(data) {
+        assert!(!uri.resource().is_empty());
+        assert!(uri.partition().map_or(true, |partition| partition < 256));
+        assert!(!uri.path().contains('/'));
+    }
+});
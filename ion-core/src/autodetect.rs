@@ -0,0 +1,119 @@
+use alloc::format;
+
+/// The directories of `\EFI` that are not scanned for EFI applications. The fallback
+/// directory usually contains Ion itself and Windows is detected separately.
+const EXCLUDED_EFI_DIRS: &[&str] = &["BOOT", "ion", "Microsoft"];
+
+/// The helper applications that shim installs next to itself, which are not meant to be
+/// booted directly.
+const SHIM_HELPERS: &[&str] = &[
+    "mmx64.efi",
+    "fbx64.efi",
+    "mmia32.efi",
+    "fbia32.efi",
+    "mmaa64.efi",
+    "fbaa64.efi",
+];
+
+/// The path of the Windows Boot Manager relative to the root of the EFI system partition.
+pub const WINDOWS_BOOT_MANAGER: &str = "EFI/Microsoft/Boot/bootmgfw.efi";
+
+/// Returns true if the file with the provided name is a Linux kernel (e.g. `vmlinuz-linux`
+/// or `vmlinuz-5.10.0-8-amd64`).
+pub fn is_linux_kernel(name: &str) -> bool {
+    name.starts_with("vmlinuz") && !name.ends_with(".sig")
+}
+
+/// Returns the initrd among the provided file names that belongs to the provided kernel.
+/// The naming schemes of the common distributions are supported, e.g. `initrd.img-<version>`
+/// (Debian) and `initramfs-<version>.img` (Arch Linux, Fedora).
+pub fn matching_initrd<'a>(kernel: &str, files: &[&'a str]) -> Option<&'a str> {
+    // The version includes the leading dash, if there is one.
+    let version = kernel.strip_prefix("vmlinuz")?;
+
+    let candidates = [
+        format!("initrd.img{}", version),
+        format!("initramfs{}.img", version),
+        format!("initrd{}.img", version),
+        format!("initrd{}", version),
+    ];
+
+    // File names are compared case-insensitively, as FAT is case-insensitive.
+    candidates.iter().find_map(|candidate| {
+        files
+            .iter()
+            .find(|file| file.eq_ignore_ascii_case(candidate))
+            .copied()
+    })
+}
+
+/// Returns true if the applications in the `\EFI` subdirectory with the provided name
+/// should be detected.
+pub fn is_scanned_efi_dir(name: &str) -> bool {
+    !EXCLUDED_EFI_DIRS
+        .iter()
+        .any(|excluded| excluded.eq_ignore_ascii_case(name))
+}
+
+/// Returns true if the file with the provided name is an EFI application that can be
+/// booted.
+pub fn is_efi_application(name: &str) -> bool {
+    let is_efi = name.len() > 4
+        && name.is_char_boundary(name.len() - 4)
+        && name[name.len() - 4..].eq_ignore_ascii_case(".efi");
+
+    is_efi
+        && !SHIM_HELPERS
+            .iter()
+            .any(|helper| helper.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linux_kernels() {
+        assert!(is_linux_kernel("vmlinuz-linux"));
+        assert!(is_linux_kernel("vmlinuz"));
+        assert!(!is_linux_kernel("vmlinuz-linux.sig"));
+        assert!(!is_linux_kernel("initramfs-linux.img"));
+    }
+
+    #[test]
+    fn initrds() {
+        let files = [
+            "vmlinuz-linux",
+            "initramfs-linux.img",
+            "initramfs-linux-fallback.img",
+            "vmlinuz-5.10.0-8-amd64",
+            "initrd.img-5.10.0-8-amd64",
+            "vmlinuz",
+            "INITRD",
+        ];
+
+        assert_eq!(
+            matching_initrd("vmlinuz-linux", &files),
+            Some("initramfs-linux.img")
+        );
+        assert_eq!(
+            matching_initrd("vmlinuz-5.10.0-8-amd64", &files),
+            Some("initrd.img-5.10.0-8-amd64")
+        );
+        assert_eq!(matching_initrd("vmlinuz", &files), Some("INITRD"));
+        assert_eq!(matching_initrd("vmlinuz-lts", &files), None);
+    }
+
+    #[test]
+    fn efi_applications() {
+        assert!(is_scanned_efi_dir("debian"));
+        assert!(!is_scanned_efi_dir("boot"));
+        assert!(!is_scanned_efi_dir("Microsoft"));
+
+        assert!(is_efi_application("shimx64.efi"));
+        assert!(is_efi_application("grubx64.EFI"));
+        assert!(!is_efi_application("mmx64.efi"));
+        assert!(!is_efi_application("grub.cfg"));
+        assert!(!is_efi_application(".efi"));
+    }
+}
@@ -0,0 +1,238 @@
+// Parsing of the entries of the Boot Loader Specification (type #1), which distributions
+// install as drop-in files in `\loader\entries` along with their kernels.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// The directory containing the entries, relative to the root of the volume.
+pub const ENTRIES_DIR: &str = "loader\\entries";
+
+/// The extension of the entry files.
+const ENTRY_EXTENSION: &str = ".conf";
+
+/// An entry of the Boot Loader Specification. The paths are relative to the root of the
+/// volume that contains the entry.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The name of the entry file without its extension.
+    pub id: &'a str,
+    pub title: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub machine_id: Option<&'a str>,
+    pub sort_key: Option<&'a str>,
+    pub linux: Option<&'a str>,
+    /// The initrds of the entry. The key can be specified multiple times.
+    pub initrd: Vec<&'a str>,
+    pub efi: Option<&'a str>,
+    /// The parts of the command line. The key can be specified multiple times.
+    pub options: Vec<&'a str>,
+    pub devicetree: Option<&'a str>,
+    pub architecture: Option<&'a str>,
+}
+
+impl<'a> Entry<'a> {
+    /// Parses the entry file with the provided name. Each line consists of a key and a
+    /// value, which are separated by whitespace. Lines starting with `#` are comments.
+    pub fn parse(file_name: &'a str, contents: &'a str) -> Self {
+        let mut entry = Self {
+            id: entry_id(file_name).unwrap_or(file_name),
+            ..Self::default()
+        };
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find(char::is_whitespace) {
+                Some(index) => (&line[..index], line[index..].trim()),
+                None => (line, ""),
+            };
+
+            match key {
+                "title" => entry.title = Some(value),
+                "version" => entry.version = Some(value),
+                "machine-id" => entry.machine_id = Some(value),
+                "sort-key" => entry.sort_key = Some(value),
+                "linux" => entry.linux = Some(value),
+                "initrd" => entry.initrd.push(value),
+                "efi" => entry.efi = Some(value),
+                "options" => entry.options.push(value),
+                "devicetree" => entry.devicetree = Some(value),
+                "architecture" => entry.architecture = Some(value),
+                _ => (),
+            }
+        }
+
+        entry
+    }
+
+    /// Returns the name of the entry shown in the menu, which includes the version if the
+    /// entry has a title. Otherwise the ID of the entry is used.
+    pub fn name(&self) -> String {
+        match (self.title, self.version) {
+            (Some(title), Some(version)) => alloc::format!("{} ({})", title, version),
+            (Some(name), None) | (None, Some(name)) => String::from(name),
+            (None, None) => String::from(self.id),
+        }
+    }
+
+    /// Returns the command line of the entry, which consists of all of its options.
+    pub fn command_line(&self) -> String {
+        self.options.join(" ")
+    }
+
+    /// Returns true if the entry can be booted on the firmware with the provided
+    /// architecture (e.g. `x64`). Entries without an architecture can be booted anywhere.
+    pub fn supports(&self, architecture: &str) -> bool {
+        match self.architecture {
+            Some(value) => value.eq_ignore_ascii_case(architecture),
+            None => true,
+        }
+    }
+}
+
+/// Returns the ID of the entry with the provided file name, if it is an entry file.
+pub fn entry_id(file_name: &str) -> Option<&str> {
+    let split = file_name.len().checked_sub(ENTRY_EXTENSION.len())?;
+
+    if split == 0 || !file_name.is_char_boundary(split) {
+        return None;
+    }
+
+    let (id, extension) = file_name.split_at(split);
+
+    if extension.eq_ignore_ascii_case(ENTRY_EXTENSION) {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Returns the provided run of digits without its leading zeros.
+fn strip_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&digit| digit == b'0').count();
+    &digits[zeros..]
+}
+
+/// Compares two versions, comparing runs of digits by their numeric value (e.g. `6.10` is
+/// newer than `6.9`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_len, b_len) = (digits(a), digits(b));
+
+                let (x, y) = (strip_zeros(&a[..a_len]), strip_zeros(&b[..b_len]));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+/// Compares two entries in the order in which they are shown in the menu. Entries with a
+/// sort key come first, ordered by their sort key, their machine ID and their version,
+/// with the newest version first. The other entries are ordered by their ID, again with
+/// the newest version first.
+pub fn compare(a: &Entry, b: &Entry) -> Ordering {
+    match (a.sort_key, b.sort_key) {
+        (Some(a_key), Some(b_key)) => a_key
+            .cmp(b_key)
+            .then_with(|| a.machine_id.cmp(&b.machine_id))
+            .then_with(|| compare_versions(b.version.unwrap_or(""), a.version.unwrap_or(""))),
+
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => compare_versions(b.id, a.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let entry = Entry::parse(
+            "6a9857a393724b7a981ebb5b8495b9ea-6.8.9-300.fc40.x86_64.conf",
+            "# Fedora\n\
+             title Fedora Linux 40 (Workstation Edition)\n\
+             version 6.8.9-300.fc40.x86_64\n\
+             linux /vmlinuz-6.8.9-300.fc40.x86_64\n\
+             initrd /initramfs-6.8.9-300.fc40.x86_64.img\n\
+             options root=UUID=8d4e2c5a ro\r\n\
+             options  rhgb quiet\n\
+             architecture x64\n",
+        );
+
+        assert_eq!(
+            entry.id,
+            "6a9857a393724b7a981ebb5b8495b9ea-6.8.9-300.fc40.x86_64"
+        );
+        assert_eq!(
+            entry.name(),
+            "Fedora Linux 40 (Workstation Edition) (6.8.9-300.fc40.x86_64)"
+        );
+        assert_eq!(entry.linux, Some("/vmlinuz-6.8.9-300.fc40.x86_64"));
+        assert_eq!(entry.initrd, ["/initramfs-6.8.9-300.fc40.x86_64.img"]);
+        assert_eq!(entry.command_line(), "root=UUID=8d4e2c5a ro rhgb quiet");
+        assert!(entry.supports("x64"));
+        assert!(!entry.supports("aa64"));
+
+        let entry = Entry::parse("arch.conf", "linux /vmlinuz-linux");
+        assert_eq!(entry.name(), "arch");
+        assert!(entry.supports("x64"));
+    }
+
+    #[test]
+    fn entry_files() {
+        assert_eq!(entry_id("arch.conf"), Some("arch"));
+        assert_eq!(entry_id("ARCH.CONF"), Some("ARCH"));
+        assert_eq!(entry_id(".conf"), None);
+        assert_eq!(entry_id("arch.conf.bak"), None);
+    }
+
+    #[test]
+    fn ordering() {
+        assert_eq!(compare_versions("6.10.1", "6.9.12"), Ordering::Greater);
+        assert_eq!(compare_versions("6.08", "6.8"), Ordering::Equal);
+        assert_eq!(compare_versions("6.8-rc1", "6.8"), Ordering::Greater);
+        assert_eq!(compare_versions("linux", "linux-lts"), Ordering::Less);
+
+        let mut entries = [
+            Entry::parse("linux-6.9.conf", ""),
+            Entry::parse("b.conf", "sort-key fedora\nversion 6.9"),
+            Entry::parse("linux-6.10.conf", ""),
+            Entry::parse("a.conf", "sort-key fedora\nversion 6.10"),
+        ];
+
+        entries.sort_by(compare);
+
+        let ids = entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+        assert_eq!(ids, ["a", "b", "linux-6.10", "linux-6.9"]);
+    }
+}
@@ -0,0 +1,117 @@
+// The boot options of the firmware (`Boot####` and `BootOrder`), which Ion falls back to
+// when none of its own entries can be booted.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The attribute of a load option that makes it bootable.
+const LOAD_OPTION_ACTIVE: u32 = 1;
+
+/// Returns the name of the boot option variable with the provided number.
+pub fn boot_option_name(number: u16) -> String {
+    alloc::format!("Boot{:04X}", number)
+}
+
+/// Parses a list of boot option numbers, which is the format of `BootOrder`.
+pub fn parse_boot_order(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|number| u16::from_le_bytes([number[0], number[1]]))
+        .collect()
+}
+
+/// The parts of an `EFI_LOAD_OPTION` that Ion uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOption {
+    pub attributes: u32,
+    pub description: String,
+}
+
+impl LoadOption {
+    /// Parses the provided `EFI_LOAD_OPTION`, which starts with its attributes, the length
+    /// of its device path and its NUL-terminated UCS-2 description.
+    pub fn parse(option: &[u8]) -> Option<Self> {
+        let attributes = u32::from_le_bytes([
+            *option.first()?,
+            *option.get(1)?,
+            *option.get(2)?,
+            *option.get(3)?,
+        ]);
+
+        let units = option
+            .get(6..)?
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0);
+
+        let description = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        Some(Self {
+            attributes,
+            description,
+        })
+    }
+
+    /// Returns true if the firmware boots the option when it is reached in the boot order.
+    pub fn is_active(&self) -> bool {
+        self.attributes & LOAD_OPTION_ACTIVE != 0
+    }
+}
+
+/// Returns the boot option that the firmware would have tried next if Ion had failed to
+/// start, which is the first active option after the current one (`BootCurrent`) in the
+/// boot order. If Ion has not been started from the boot order (e.g. using `BootNext`), the
+/// first active option is returned.
+pub fn fallback_option(
+    order: &[u16],
+    current: Option<u16>,
+    mut is_active: impl FnMut(u16) -> bool,
+) -> Option<u16> {
+    let candidates = match current.and_then(|current| order.iter().position(|&n| n == current)) {
+        Some(index) => &order[index + 1..],
+        None => order,
+    };
+
+    candidates.iter().copied().find(|&number| is_active(number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_options() {
+        let mut option = LOAD_OPTION_ACTIVE.to_le_bytes().to_vec();
+        option.extend_from_slice(&4u16.to_le_bytes());
+
+        for unit in "Windows Boot Manager\0".encode_utf16() {
+            option.extend_from_slice(&unit.to_le_bytes());
+        }
+
+        option.extend_from_slice(&[0x7f, 0xff, 0x04, 0x00]);
+
+        let parsed = LoadOption::parse(&option).unwrap();
+        assert_eq!(parsed.description, "Windows Boot Manager");
+        assert!(parsed.is_active());
+
+        assert_eq!(LoadOption::parse(&[1, 0]), None);
+        assert_eq!(boot_option_name(0x1a), "Boot001A");
+    }
+
+    #[test]
+    fn fallback() {
+        let order = parse_boot_order(&[3, 0, 1, 0, 2, 0, 5, 0]);
+        assert_eq!(order, [3, 1, 2, 5]);
+
+        let active = |number| number != 2;
+
+        assert_eq!(fallback_option(&order, Some(1), active), Some(5));
+        assert_eq!(fallback_option(&order, Some(5), active), None);
+        assert_eq!(fallback_option(&order, Some(3), active), Some(1));
+
+        // Ion has been started using `BootNext` or from the boot menu of the firmware.
+        assert_eq!(fallback_option(&order, Some(7), active), Some(3));
+        assert_eq!(fallback_option(&order, None, active), Some(3));
+    }
+}
@@ -0,0 +1,250 @@
+// The checksum that `tools/embed_checksum.py` embeds into the built image of Ion, so that
+// Ion can detect a corrupted copy on the EFI system partition instead of misbehaving in
+// mysterious ways.
+
+/// The magic bytes at the start of the checksum record.
+pub const MAGIC: [u8; 8] = *b"IONCRC32";
+
+/// The size of the checksum record: the magic bytes followed by the size of the image and
+/// its CRC32, both as little endian u32s.
+pub const RECORD_SIZE: usize = 16;
+
+/// The name of the PE section that contains the checksum record.
+const SECTION_NAME: &[u8] = b".ioncrc";
+
+/// The size of each entry of the PE section table.
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Returns the record of an image that has not been checksummed yet, which is what the
+/// build produces.
+pub const fn empty_record() -> [u8; RECORD_SIZE] {
+    let mut record = [0; RECORD_SIZE];
+    let mut i = 0;
+
+    while i < MAGIC.len() {
+        record[i] = MAGIC[i];
+        i += 1;
+    }
+
+    record
+}
+
+/// The size and the CRC32 of the image, as recorded after the build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub size: u32,
+    pub crc: u32,
+}
+
+impl Record {
+    /// Parses the provided checksum record. Returns [`None`] if the record is malformed or
+    /// the checksum has not been embedded, which is the case for development builds.
+    pub fn parse(record: &[u8]) -> Option<Self> {
+        if record.len() < RECORD_SIZE || !record.starts_with(&MAGIC) {
+            return None;
+        }
+
+        let size = u32::from_le_bytes([record[8], record[9], record[10], record[11]]);
+        let crc = u32::from_le_bytes([record[12], record[13], record[14], record[15]]);
+
+        if size == 0 {
+            None
+        } else {
+            Some(Self { size, crc })
+        }
+    }
+}
+
+/// The reason why an image does not match its checksum record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The image does not contain the checksum record, so it is not an image of Ion.
+    MissingRecord,
+    Size {
+        expected: u32,
+        actual: usize,
+    },
+    Crc {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+/// Updates the provided CRC32 (as used by zlib and GPT) with the provided data.
+fn update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !update(!0, data)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([
+        *data.get(offset)?,
+        *data.get(offset + 1)?,
+    ]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(data.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// Returns the file offset of the checksum record in the provided PE image, which is the
+/// start of its `.ioncrc` section.
+pub fn record_offset(image: &[u8]) -> Option<usize> {
+    let pe = read_u32(image, 0x3c)? as usize;
+
+    if image.get(pe..pe + 4)? != b"PE\0\0" {
+        return None;
+    }
+
+    let section_count = read_u16(image, pe + 6)? as usize;
+    let optional_header_size = read_u16(image, pe + 20)? as usize;
+    let sections = pe + 24 + optional_header_size;
+
+    (0..section_count).find_map(|i| {
+        let header = image.get(sections + i * SECTION_HEADER_SIZE..)?;
+        let name = header.get(..8)?;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        if &name[..len] == SECTION_NAME {
+            read_u32(header, 20).map(|offset| offset as usize)
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the CRC32 of the provided image, excluding the size and the CRC32 in its
+/// checksum record at the provided offset, which are treated as zeros.
+pub fn image_crc32(image: &[u8], record_offset: usize) -> u32 {
+    let start = (record_offset + MAGIC.len()).min(image.len());
+    let end = (record_offset + RECORD_SIZE).min(image.len());
+
+    let crc = update(!0, &image[..start]);
+    let crc = update(crc, &[0; RECORD_SIZE - 8][..end - start]);
+    !update(crc, &image[end..])
+}
+
+/// Checks the provided image, as read from the disk, against the checksum record that has
+/// been embedded into the running copy of Ion.
+pub fn verify(image: &[u8], expected: Record) -> Result<(), Mismatch> {
+    if image.len() != expected.size as usize {
+        return Err(Mismatch::Size {
+            expected: expected.size,
+            actual: image.len(),
+        });
+    }
+
+    let offset = record_offset(image).ok_or(Mismatch::MissingRecord)?;
+    let actual = image_crc32(image, offset);
+
+    if actual != expected.crc {
+        return Err(Mismatch::Crc {
+            expected: expected.crc,
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+
+    /// Returns a minimal PE image with a `.text` section and a `.ioncrc` section containing
+    /// the checksum record.
+    fn image() -> (alloc::vec::Vec<u8>, usize) {
+        let mut image = vec![0u8; 0x400];
+        image[0..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x80..0x84].copy_from_slice(b"PE\0\0");
+        image[0x86..0x88].copy_from_slice(&2u16.to_le_bytes());
+        image[0x94..0x96].copy_from_slice(&0xf0u16.to_le_bytes());
+
+        let sections = 0x80 + 24 + 0xf0;
+        image[sections..sections + 5].copy_from_slice(b".text");
+        image[sections + 20..sections + 24].copy_from_slice(&0x200u32.to_le_bytes());
+
+        let section = sections + SECTION_HEADER_SIZE;
+        image[section..section + 7].copy_from_slice(b".ioncrc");
+        image[section + 20..section + 24].copy_from_slice(&0x300u32.to_le_bytes());
+
+        image[0x200..0x205].copy_from_slice(b"\x55\x48\x89\xe5\xc3");
+        image[0x300..0x310].copy_from_slice(&empty_record());
+        (image, 0x300)
+    }
+
+    /// Embeds the checksum the same way as `tools/embed_checksum.py`.
+    fn embed(image: &mut [u8], offset: usize) -> Record {
+        let record = Record {
+            size: image.len() as u32,
+            crc: image_crc32(image, offset),
+        };
+
+        image[offset + 8..offset + 12].copy_from_slice(&record.size.to_le_bytes());
+        image[offset + 12..offset + 16].copy_from_slice(&record.crc.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn records() {
+        let (mut image, offset) = image();
+
+        assert_eq!(record_offset(&image), Some(offset));
+        assert_eq!(Record::parse(&empty_record()), None);
+
+        let record = embed(&mut image, offset);
+        assert_eq!(Record::parse(&image[offset..]), Some(record));
+        assert_eq!(verify(&image, record), Ok(()));
+
+        // Embedding the checksum does not change it.
+        assert_eq!(image_crc32(&image, offset), record.crc);
+    }
+
+    #[test]
+    fn corruption() {
+        let (mut image, offset) = image();
+        let record = embed(&mut image, offset);
+
+        let mut corrupted = image.clone();
+        corrupted[0x201] ^= 0x01;
+        assert!(matches!(
+            verify(&corrupted, record),
+            Err(Mismatch::Crc { .. })
+        ));
+
+        assert_eq!(
+            verify(&image[..0x200], record),
+            Err(Mismatch::Size {
+                expected: 0x400,
+                actual: 0x200
+            })
+        );
+
+        let mut foreign = image.clone();
+        foreign[0x80] = b'X';
+        assert_eq!(verify(&foreign, record), Err(Mismatch::MissingRecord));
+    }
+}
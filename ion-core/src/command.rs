@@ -0,0 +1,107 @@
+/// A command of the serial debug channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    /// Prints the firmware memory map.
+    Memmap,
+    /// Prints the device paths of the volumes with a file system.
+    Volumes,
+    /// Prints the control registers of the current CPU.
+    Regs,
+    /// Prints the identification and the features of the processor.
+    Cpu,
+    /// Boots the boot entry with the provided index.
+    Boot(usize),
+    /// Prints a hex dump of the provided amount of bytes starting at the provided address.
+    Dump(u64, u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandParseError {
+    /// The line does not contain a command.
+    Empty,
+    UnknownCommand,
+    /// An argument is missing or is not a number.
+    InvalidArgument,
+}
+
+/// Parses a number in either decimal or hexadecimal (prefixed with `0x`) notation.
+fn parse_number(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Parses a line received over the serial debug channel. The command and its arguments
+/// are separated by whitespace (e.g. `dump 0x1000 64`).
+pub fn parse_command(line: &str) -> Result<Command, CommandParseError> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or(CommandParseError::Empty)?;
+
+    let mut argument = || {
+        words
+            .next()
+            .and_then(parse_number)
+            .ok_or(CommandParseError::InvalidArgument)
+    };
+
+    match command {
+        "help" => Ok(Command::Help),
+        "memmap" => Ok(Command::Memmap),
+        "volumes" => Ok(Command::Volumes),
+        "regs" => Ok(Command::Regs),
+        "cpu" => Ok(Command::Cpu),
+        "boot" => Ok(Command::Boot(argument()? as usize)),
+        "dump" => {
+            let address = argument()?;
+            let length = argument()?;
+
+            Ok(Command::Dump(address, length))
+        }
+
+        _ => Err(CommandParseError::UnknownCommand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command("memmap"), Ok(Command::Memmap));
+        assert_eq!(parse_command("  regs  "), Ok(Command::Regs));
+        assert_eq!(parse_command("volumes"), Ok(Command::Volumes));
+        assert_eq!(parse_command("cpu"), Ok(Command::Cpu));
+        assert_eq!(parse_command("boot 2"), Ok(Command::Boot(2)));
+        assert_eq!(
+            parse_command("dump 0x1000 64"),
+            Ok(Command::Dump(0x1000, 64))
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        assert_eq!(parse_command(""), Err(CommandParseError::Empty));
+        assert_eq!(
+            parse_command("reboot"),
+            Err(CommandParseError::UnknownCommand)
+        );
+        assert_eq!(
+            parse_command("boot"),
+            Err(CommandParseError::InvalidArgument)
+        );
+        assert_eq!(
+            parse_command("dump 0x1000"),
+            Err(CommandParseError::InvalidArgument)
+        );
+        assert_eq!(
+            parse_command("dump 0xzz 16"),
+            Err(CommandParseError::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use alloc::format;
+use alloc::string::String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootProtocol {
+    Stivale2,
+    Stivale,
+    Multiboot,
+    Multiboot2,
+    Linux,
+    /// Loads an EFI application (e.g. another boot manager) using the firmware.
+    Chainload,
+}
+
+impl BootProtocol {
+    /// Parses the value of the `PROTOCOL=` config key.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "stivale2" => Some(Self::Stivale2),
+            "stivale1" => Some(Self::Stivale),
+            "stivale" => Some(Self::Stivale),
+
+            "multiboot" => Some(Self::Multiboot),
+            "multiboot1" => Some(Self::Multiboot),
+            "multiboot2" => Some(Self::Multiboot2),
+
+            "linux" => Some(Self::Linux),
+            "chainload" | "efi" => Some(Self::Chainload),
+
+            _ => None,
+        }
+    }
+}
+
+/// A line of the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// The start of a new entry, which takes the form of `:<name>`.
+    Entry(&'a str),
+    /// A key and its value, which take the form of `<key>=<value>`. Keys before the first
+    /// entry are global, all other keys belong to the last entry.
+    Option(&'a str, &'a str),
+}
+
+/// Returns an iterator over the entries and options of the provided config file. Lines
+/// that are neither are skipped.
+pub fn lines(config: &str) -> impl Iterator<Item = Line<'_>> {
+    config.split('\n').filter_map(|line| {
+        // Config files that have been written on Windows use CRLF line endings.
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if let Some(name) = line.strip_prefix(':') {
+            Some(Line::Entry(name))
+        } else {
+            let key_idx = line.find('=')?;
+            Some(Line::Option(&line[..key_idx], &line[key_idx + 1..]))
+        }
+    })
+}
+
+/// Parses a boolean config value. Only `yes` enables the option.
+pub fn parse_bool(value: &str) -> bool {
+    value == "yes"
+}
+
+/// Parses the value of the `TIMEOUT=` config key. `no` disables the countdown and invalid
+/// values fall back to the default of 5 seconds.
+pub fn parse_timeout(value: &str) -> usize {
+    value
+        .parse::<usize>()
+        .unwrap_or_else(|_| if value.eq("no") { 0 } else { 5 })
+}
+
+/// Parses the value of the `RESOLUTION=` config key, which takes the form of
+/// `<width>x<height>`.
+pub fn parse_resolution(value: &str) -> Option<(usize, usize)> {
+    let mut parts = value.split('x');
+
+    let width = parts.next()?.trim().parse().ok()?;
+    let height = parts.next()?.trim().parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((width, height))
+}
+
+/// The graphics output that Ion uses if the machine has several displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Display<'a> {
+    /// The output with the provided index, in the order in which the firmware reports them
+    /// (starting at 0).
+    Index(usize),
+    /// The output whose device path (e.g. `PciRoot(0x0)/Pci(0x2,0x0)`) is the provided one
+    /// or one of its children.
+    DevicePath(&'a str),
+}
+
+impl<'a> Display<'a> {
+    /// Parses the value of the `DISPLAY=` config key, which is either the index or the
+    /// device path of the output.
+    pub fn parse(value: &'a str) -> Option<Self> {
+        let value = value.trim();
+
+        if value.is_empty() {
+            None
+        } else if let Ok(index) = value.parse() {
+            Some(Self::Index(index))
+        } else {
+            Some(Self::DevicePath(value))
+        }
+    }
+}
+
+/// Parses the value of the `SPLASH=` config key, which is either `yes`, `no` or the URI of
+/// the logo. Returns the URI of the logo if the splash screen is enabled, which is empty if
+/// the splash screen should not show a logo.
+pub fn parse_splash(value: &str) -> Option<&str> {
+    match value {
+        "no" => None,
+        "yes" => Some(""),
+        uri => Some(uri),
+    }
+}
+
+/// The keys that interrupt the countdown of the boot menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountdownKeys {
+    /// Any key interrupts the countdown.
+    Any,
+    /// Only ESC and the arrow keys interrupt the countdown, while Enter boots the default
+    /// entry right away. Other keys are ignored.
+    Designated,
+}
+
+impl CountdownKeys {
+    /// Parses the value of the `COUNTDOWN_KEYS=` config key.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "any" => Some(Self::Any),
+            "designated" => Some(Self::Designated),
+            _ => None,
+        }
+    }
+}
+
+/// What the boot menu does once it has been left idle for the timeout given by
+/// `MENU_IDLE_TIMEOUT=`, e.g. after booting the default entry failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Turn the machine off.
+    Shutdown,
+    /// Restart the machine, which tries booting the default entry again.
+    Reboot,
+    /// Boot the default entry.
+    BootDefault,
+}
+
+impl IdleAction {
+    /// Parses the value of the `MENU_IDLE_ACTION=` config key. `no` disables the action.
+    pub fn parse(value: &str) -> Option<Option<Self>> {
+        match value {
+            "shutdown" => Some(Some(Self::Shutdown)),
+            "reboot" => Some(Some(Self::Reboot)),
+            "boot-default" => Some(Some(Self::BootDefault)),
+            "no" => Some(None),
+            _ => None,
+        }
+    }
+}
+
+/// Prepends the value of the `PREFIX=` config key to the provided path, if the path is
+/// relative. Returns [`None`] if the path is a URI or an absolute path, which are used as
+/// they are.
+pub fn apply_prefix(prefix: &str, path: &str) -> Option<String> {
+    if path.is_empty() || path.contains(':') || path.starts_with('/') {
+        return None;
+    }
+
+    Some(format!("{}/{}", prefix.trim_end_matches('/'), path))
+}
+
+/// A macro in the command line of an entry (e.g. `${ROOT_UUID}`), which is expanded from
+/// the partition table when the entry is booted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandLineMacro {
+    /// The unique GUID of the GPT partition that Ion has been loaded from.
+    RootUuid,
+    /// The unique GUID of the GPT partition with the provided number on the disk that Ion
+    /// has been loaded from.
+    PartGuid(u32),
+}
+
+impl CommandLineMacro {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ROOT_UUID" => Some(Self::RootUuid),
+            _ => {
+                let number = name.strip_prefix("PART_GUID:")?.trim().parse().ok()?;
+                Some(Self::PartGuid(number))
+            }
+        }
+    }
+}
+
+/// Errors that can occur while expanding the macros of a command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroError {
+    /// The macro is not terminated by `}`.
+    Unterminated,
+    /// The name of the macro is not known.
+    Unknown,
+    /// The value of the macro could not be determined.
+    Unresolved,
+}
+
+impl MacroError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unterminated => "missing }",
+            Self::Unknown => "unknown macro",
+            Self::Unresolved => "no such partition",
+        }
+    }
+}
+
+/// Expands the `${ROOT_UUID}` and `${PART_GUID:<n>}` macros of the provided command line
+/// using the provided function. Returns the macro that could not be expanded on failure.
+pub fn expand_command_line(
+    command_line: &str,
+    mut resolve: impl FnMut(CommandLineMacro) -> Option<String>,
+) -> Result<String, (&str, MacroError)> {
+    let mut result = String::with_capacity(command_line.len());
+    let mut remaining = command_line;
+
+    while let Some(start) = remaining.find("${") {
+        result.push_str(&remaining[..start]);
+        remaining = &remaining[start..];
+
+        let end = remaining
+            .find('}')
+            .ok_or((remaining, MacroError::Unterminated))?;
+        let text = &remaining[..=end];
+
+        let value = CommandLineMacro::parse(&text[2..end])
+            .ok_or((text, MacroError::Unknown))
+            .and_then(|name| resolve(name).ok_or((text, MacroError::Unresolved)))?;
+
+        result.push_str(&value);
+        remaining = &remaining[end + 1..];
+    }
+
+    result.push_str(remaining);
+    Ok(result)
+}
+
+/// A request of the running OS to boot an entry once on the next boot, which is stored in
+/// the `IonBootNext` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootNext<'a> {
+    /// The name of the entry that should be booted.
+    pub entry: &'a str,
+    /// The command line that replaces the command line of the entry, if any.
+    pub command_line: Option<&'a str>,
+}
+
+/// Parses the value of the `IonBootNext` variable, which uses the syntax of the config
+/// file: `ENTRY=<name>` followed by an optional `CMDLINE=<command line>`.
+pub fn parse_boot_next(value: &str) -> Option<BootNext<'_>> {
+    let mut entry = None;
+    let mut command_line = None;
+
+    for line in lines(value) {
+        match line {
+            Line::Option("ENTRY", name) => entry = Some(name),
+            Line::Option("CMDLINE", value) => command_line = Some(value),
+            _ => (),
+        }
+    }
+
+    Some(BootNext {
+        entry: entry?,
+        command_line,
+    })
+}
+
+/// The preferences that have been changed in the menu, which are stored in the
+/// `IonPreferences` variable instead of the config file. They use the syntax and the keys
+/// of the config file and take precedence over it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Preferences<'a> {
+    pub resolution: Option<&'a str>,
+    pub log_level: Option<&'a str>,
+    pub keymap: Option<&'a str>,
+    /// The name of the entry that has been booted last, which is selected initially.
+    pub last_entry: Option<&'a str>,
+}
+
+impl<'a> Preferences<'a> {
+    /// Parses the value of the `IonPreferences` variable. Unknown keys are ignored.
+    pub fn parse(value: &'a str) -> Self {
+        let mut preferences = Self::default();
+
+        for line in lines(value) {
+            if let Line::Option(key, value) = line {
+                preferences.set(key, value);
+            }
+        }
+
+        preferences
+    }
+
+    /// Sets the preference with the provided key. Returns false if the key is unknown.
+    pub fn set(&mut self, key: &str, value: &'a str) -> bool {
+        let preference = match key {
+            "RESOLUTION" => &mut self.resolution,
+            "LOG_LEVEL" => &mut self.log_level,
+            "KEYMAP" => &mut self.keymap,
+            "LAST_ENTRY" => &mut self.last_entry,
+            _ => return false,
+        };
+
+        *preference = Some(value);
+        true
+    }
+
+    /// Encodes the preferences in the format of the `IonPreferences` variable.
+    pub fn encode(&self) -> String {
+        let preferences = [
+            ("RESOLUTION", self.resolution),
+            ("LOG_LEVEL", self.log_level),
+            ("KEYMAP", self.keymap),
+            ("LAST_ENTRY", self.last_entry),
+        ];
+
+        let mut value = String::new();
+
+        for (key, preference) in preferences.iter() {
+            if let Some(preference) = preference {
+                value.push_str(&format!("{}={}\n", key, preference));
+            }
+        }
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_lines() {
+        let config =
+            "TIMEOUT=3\r\n\n:Aero\nPROTOCOL=stivale2\nKERNEL_PATH=boot:///boot/a=b.elf\nignored\n";
+
+        assert_eq!(
+            lines(config).collect::<Vec<_>>(),
+            [
+                Line::Option("TIMEOUT", "3"),
+                Line::Entry("Aero"),
+                Line::Option("PROTOCOL", "stivale2"),
+                Line::Option("KERNEL_PATH", "boot:///boot/a=b.elf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            BootProtocol::parse("multiboot1"),
+            Some(BootProtocol::Multiboot)
+        );
+        assert_eq!(BootProtocol::parse("efi"), Some(BootProtocol::Chainload));
+        assert_eq!(BootProtocol::parse("elf"), None);
+
+        assert!(parse_bool("yes"));
+        assert!(!parse_bool("no"));
+        assert!(!parse_bool("Yes"));
+
+        assert_eq!(parse_timeout("10"), 10);
+        assert_eq!(parse_timeout("no"), 0);
+        assert_eq!(parse_timeout("soon"), 5);
+
+        assert_eq!(parse_resolution("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_resolution("1920 x 1080"), Some((1920, 1080)));
+        assert_eq!(parse_resolution("1920x1080x32"), None);
+        assert_eq!(parse_resolution("1920"), None);
+
+        assert_eq!(Display::parse("1"), Some(Display::Index(1)));
+        assert_eq!(
+            Display::parse("PciRoot(0x0)/Pci(0x2,0x0)"),
+            Some(Display::DevicePath("PciRoot(0x0)/Pci(0x2,0x0)"))
+        );
+        assert_eq!(Display::parse(""), None);
+
+        assert_eq!(
+            IdleAction::parse("boot-default"),
+            Some(Some(IdleAction::BootDefault))
+        );
+        assert_eq!(IdleAction::parse("no"), Some(None));
+
+        assert_eq!(
+            CountdownKeys::parse("designated"),
+            Some(CountdownKeys::Designated)
+        );
+        assert_eq!(CountdownKeys::parse("esc"), None);
+        assert_eq!(IdleAction::parse("poweroff"), None);
+
+        assert_eq!(parse_splash("no"), None);
+        assert_eq!(parse_splash("yes"), Some(""));
+        assert_eq!(parse_splash("boot:///logo.bmp"), Some("boot:///logo.bmp"));
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(
+            apply_prefix("boot:///boot/6.8.1/", "vmlinuz").as_deref(),
+            Some("boot:///boot/6.8.1/vmlinuz")
+        );
+        assert_eq!(
+            apply_prefix("/boot/6.8.1", "initrd.img").as_deref(),
+            Some("/boot/6.8.1/initrd.img")
+        );
+        assert_eq!(apply_prefix("/boot/6.8.1", "/vmlinuz"), None);
+        assert_eq!(apply_prefix("/boot/6.8.1", "boot:///vmlinuz"), None);
+        assert_eq!(apply_prefix("/boot/6.8.1", ""), None);
+    }
+
+    #[test]
+    fn command_line_macros() {
+        let resolve = |name| match name {
+            CommandLineMacro::RootUuid => Some(String::from("root")),
+            CommandLineMacro::PartGuid(2) => Some(String::from("second")),
+            CommandLineMacro::PartGuid(_) => None,
+        };
+
+        assert_eq!(
+            expand_command_line("root=PARTUUID=${ROOT_UUID} quiet", resolve).as_deref(),
+            Ok("root=PARTUUID=root quiet")
+        );
+        assert_eq!(
+            expand_command_line("resume=PARTUUID=${PART_GUID:2}$", resolve).as_deref(),
+            Ok("resume=PARTUUID=second$")
+        );
+        assert_eq!(
+            expand_command_line("a=${PART_GUID:3} b", resolve),
+            Err(("${PART_GUID:3}", MacroError::Unresolved))
+        );
+        assert_eq!(
+            expand_command_line("a=${HOME}", resolve),
+            Err(("${HOME}", MacroError::Unknown))
+        );
+        assert_eq!(
+            expand_command_line("a=${ROOT_UUID", resolve),
+            Err(("${ROOT_UUID", MacroError::Unterminated))
+        );
+    }
+
+    #[test]
+    fn boot_next() {
+        assert_eq!(
+            parse_boot_next("ENTRY=Aero\nCMDLINE=init=/bin/recovery"),
+            Some(BootNext {
+                entry: "Aero",
+                command_line: Some("init=/bin/recovery"),
+            })
+        );
+        assert_eq!(
+            parse_boot_next("ENTRY=Arch Linux\r\n"),
+            Some(BootNext {
+                entry: "Arch Linux",
+                command_line: None,
+            })
+        );
+        assert_eq!(parse_boot_next("CMDLINE=quiet"), None);
+        assert_eq!(parse_boot_next(""), None);
+    }
+
+    #[test]
+    fn preferences() {
+        let mut preferences =
+            Preferences::parse("LOG_LEVEL=debug\nTIMEOUT=3\nLAST_ENTRY=Arch Linux");
+
+        assert_eq!(preferences.log_level, Some("debug"));
+        assert_eq!(preferences.last_entry, Some("Arch Linux"));
+        assert_eq!(preferences.resolution, None);
+
+        assert!(preferences.set("KEYMAP", "azerty"));
+        assert!(!preferences.set("TIMEOUT", "5"));
+
+        assert_eq!(
+            preferences.encode(),
+            "LOG_LEVEL=debug\nKEYMAP=azerty\nLAST_ENTRY=Arch Linux\n"
+        );
+        assert_eq!(Preferences::parse(&preferences.encode()), preferences);
+    }
+
+    #[test]
+    fn preferences_resolution_and_keymap() {
+        let mut preferences = Preferences::parse(
+            "RESOLUTION=1024x768
+KEYMAP=qwertz
+LOG_LEVEL=info",
+        );
+
+        assert_eq!(preferences.resolution, Some("1024x768"));
+        assert_eq!(preferences.keymap, Some("qwertz"));
+        assert_eq!(
+            preferences.resolution.and_then(parse_resolution),
+            Some((1024, 768))
+        );
+
+        // Changing a preference in the menu replaces the stored value and keeps the others.
+        assert!(preferences.set("RESOLUTION", "1920x1080"));
+        assert!(preferences.set("KEYMAP", "dvorak"));
+
+        let encoded = preferences.encode();
+
+        assert_eq!(
+            encoded,
+            "RESOLUTION=1920x1080\nLOG_LEVEL=info\nKEYMAP=dvorak\n"
+        );
+
+        let merged = Preferences::parse(&encoded);
+
+        assert_eq!(
+            merged.resolution.and_then(parse_resolution),
+            Some((1920, 1080))
+        );
+        assert_eq!(merged.keymap, Some("dvorak"));
+        assert_eq!(merged.log_level, Some("info"));
+
+        // The last occurrence of a key wins, as in the config file.
+        let preferences = Preferences::parse("KEYMAP=us\nKEYMAP=azerty\nRESOLUTION=800x600x2");
+
+        assert_eq!(preferences.keymap, Some("azerty"));
+        assert_eq!(preferences.resolution.and_then(parse_resolution), None);
+    }
+}
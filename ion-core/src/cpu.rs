@@ -0,0 +1,192 @@
+// The processor features that entries can require with `REQUIRES=`, so that entries which
+// cannot run on the machine are greyed out in the menu instead of triple faulting.
+
+use core::fmt;
+
+/// A processor feature that is reported by CPUID. The names are the ones used by
+/// `/proc/cpuinfo` on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    LongMode,
+    NoExecute,
+    Pages1G,
+    La57,
+    X2Apic,
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Popcnt,
+    Avx,
+    Avx2,
+    Avx512F,
+    Bmi1,
+    Bmi2,
+    Tsx,
+    RdRand,
+    RdSeed,
+    Smep,
+    Smap,
+    Umip,
+    FsGsBase,
+    Xsave,
+}
+
+/// Every feature along with its name.
+const FEATURES: &[(Feature, &str)] = &[
+    (Feature::LongMode, "lm"),
+    (Feature::NoExecute, "nx"),
+    (Feature::Pages1G, "pdpe1gb"),
+    (Feature::La57, "la57"),
+    (Feature::X2Apic, "x2apic"),
+    (Feature::Sse3, "sse3"),
+    (Feature::Ssse3, "ssse3"),
+    (Feature::Sse41, "sse4_1"),
+    (Feature::Sse42, "sse4_2"),
+    (Feature::Popcnt, "popcnt"),
+    (Feature::Avx, "avx"),
+    (Feature::Avx2, "avx2"),
+    (Feature::Avx512F, "avx512f"),
+    (Feature::Bmi1, "bmi1"),
+    (Feature::Bmi2, "bmi2"),
+    (Feature::Tsx, "rtm"),
+    (Feature::RdRand, "rdrand"),
+    (Feature::RdSeed, "rdseed"),
+    (Feature::Smep, "smep"),
+    (Feature::Smap, "smap"),
+    (Feature::Umip, "umip"),
+    (Feature::FsGsBase, "fsgsbase"),
+    (Feature::Xsave, "xsave"),
+];
+
+/// Other names that are commonly used for the features.
+const ALIASES: &[(Feature, &str)] = &[
+    (Feature::Pages1G, "1gpages"),
+    (Feature::Sse3, "pni"),
+    (Feature::Sse41, "sse4.1"),
+    (Feature::Sse42, "sse4.2"),
+    (Feature::Tsx, "tsx"),
+];
+
+impl Feature {
+    /// Parses the name of a feature, ignoring its case.
+    pub fn parse(name: &str) -> Option<Self> {
+        FEATURES
+            .iter()
+            .chain(ALIASES)
+            .find(|(_, other)| other.eq_ignore_ascii_case(name))
+            .map(|&(feature, _)| feature)
+    }
+
+    /// Returns the name of the feature.
+    pub fn name(self) -> &'static str {
+        FEATURES
+            .iter()
+            .find(|&&(feature, _)| feature == self)
+            .map_or("", |&(_, name)| name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// A set of processor features.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn insert(&mut self, feature: Feature) {
+        self.0 |= feature.bit();
+    }
+
+    /// Inserts the provided feature if `supported` is true.
+    pub fn set(&mut self, feature: Feature, supported: bool) {
+        if supported {
+            self.insert(feature);
+        }
+    }
+
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the features of this set that are not in the other set, e.g. the required
+    /// features that the processor does not support.
+    pub fn missing_from(&self, other: Features) -> Features {
+        Self(self.0 & !other.0)
+    }
+
+    /// Returns an iterator over the features of the set.
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        FEATURES
+            .iter()
+            .map(|&(feature, _)| feature)
+            .filter(move |&feature| self.contains(feature))
+    }
+
+    /// Parses the value of the `REQUIRES=` config key, which is a comma separated list of
+    /// feature names (e.g. `avx2,la57,tsx`). Returns [`None`] if any of the names is
+    /// unknown.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut features = Self::empty();
+
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            features.insert(Feature::parse(name)?);
+        }
+
+        Some(features)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, feature) in self.iter().enumerate() {
+            if i != 0 {
+                write!(f, ",")?;
+            }
+
+            write!(f, "{}", feature.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requirements() {
+        let required = Features::parse("avx2, LA57,tsx").unwrap();
+
+        assert!(required.contains(Feature::Avx2));
+        assert!(required.contains(Feature::La57));
+        assert!(required.contains(Feature::Tsx));
+        assert!(!required.contains(Feature::Avx));
+
+        assert_eq!(Features::parse(""), Some(Features::empty()));
+        assert_eq!(Features::parse("avx2,avx1024"), None);
+
+        let mut supported = Features::empty();
+        supported.insert(Feature::Avx2);
+        supported.set(Feature::La57, false);
+
+        let missing = required.missing_from(supported);
+        assert_eq!(alloc::format!("{}", missing), "la57,rtm");
+        assert!(required.missing_from(required).is_empty());
+    }
+}
@@ -0,0 +1,570 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt::Write;
+
+const HARDWARE_PATH: u8 = 0x01;
+const ACPI_PATH: u8 = 0x02;
+const MESSAGING_PATH: u8 = 0x03;
+const MEDIA_PATH: u8 = 0x04;
+const END_PATH: u8 = 0x7f;
+
+const HARDWARE_PCI: u8 = 0x01;
+const ACPI_ACPI: u8 = 0x01;
+const MESSAGING_ATAPI: u8 = 0x01;
+const MESSAGING_SCSI: u8 = 0x02;
+const MESSAGING_USB: u8 = 0x05;
+const MESSAGING_SATA: u8 = 0x12;
+const MESSAGING_NVME: u8 = 0x17;
+const MEDIA_HARD_DRIVE: u8 = 0x01;
+const MEDIA_CDROM: u8 = 0x02;
+const MEDIA_FILE_PATH: u8 = 0x04;
+const END_INSTANCE: u8 = 0x01;
+const END_ENTIRE: u8 = 0xff;
+
+/// The size of the type, the subtype and the length of each node.
+const NODE_HEADER_SIZE: usize = 4;
+
+/// The compressed EISA IDs of the PCI and PCI Express root bridges (`PNP0A03` and
+/// `PNP0A08`).
+const PCI_ROOT_HID: u32 = 0x0a0341d0;
+const PCIE_ROOT_HID: u32 = 0x0a0841d0;
+
+/// A single node of a UEFI device path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node<'a> {
+    pub ty: u8,
+    pub subtype: u8,
+    /// The data of the node following its header.
+    pub data: &'a [u8],
+}
+
+/// Returns the size of the device path starting at the provided bytes in bytes, including
+/// the end node. Returns [`None`] if the device path is malformed.
+pub fn len(bytes: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+
+    loop {
+        let header = bytes.get(offset..offset + NODE_HEADER_SIZE)?;
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+        if length < NODE_HEADER_SIZE || offset + length > bytes.len() {
+            return None;
+        }
+
+        offset += length;
+
+        if header[0] == END_PATH && header[1] == END_ENTIRE {
+            return Some(offset);
+        }
+    }
+}
+
+/// Returns an iterator over the nodes of the provided device path, excluding the end node.
+/// The iteration stops early if the device path is malformed.
+pub fn nodes(bytes: &[u8]) -> impl Iterator<Item = Node<'_>> {
+    let mut remaining = bytes;
+
+    core::iter::from_fn(move || {
+        let header = remaining.get(..NODE_HEADER_SIZE)?;
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+        if header[0] == END_PATH || length < NODE_HEADER_SIZE || length > remaining.len() {
+            return None;
+        }
+
+        let node = Node {
+            ty: header[0],
+            subtype: header[1],
+            data: &remaining[NODE_HEADER_SIZE..length],
+        };
+
+        remaining = &remaining[length..];
+        Some(node)
+    })
+}
+
+/// Returns an iterator over the instances of the provided multi-instance device path (e.g.
+/// the `ConOut` variable), which are separated by end instance nodes. The instances do not
+/// include their end node. The iteration stops early if the device path is malformed.
+pub fn instances(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut remaining = Some(bytes);
+
+    core::iter::from_fn(move || {
+        let bytes = remaining.take()?;
+        let mut offset = 0;
+
+        loop {
+            let header = bytes.get(offset..offset + NODE_HEADER_SIZE)?;
+            let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+            if length < NODE_HEADER_SIZE || offset + length > bytes.len() {
+                return None;
+            }
+
+            if header[0] == END_PATH {
+                if header[1] == END_INSTANCE {
+                    remaining = Some(&bytes[offset + length..]);
+                }
+
+                return Some(&bytes[..offset]);
+            }
+
+            offset += length;
+        }
+    })
+}
+
+/// Returns true if the provided device path starts with the nodes of the provided prefix,
+/// i.e. it describes the same device or one of its children. An empty prefix does not
+/// describe any device.
+pub fn starts_with(path: &[u8], prefix: &[u8]) -> bool {
+    let mut path = nodes(path);
+    let mut prefix = nodes(prefix).peekable();
+
+    prefix.peek().is_some() && prefix.all(|node| path.next() == Some(node))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Formats a GUID stored in its mixed-endian binary encoding.
+fn format_guid(guid: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8],
+        guid[9],
+        guid[10],
+        guid[11],
+        guid[12],
+        guid[13],
+        guid[14],
+        guid[15]
+    )
+}
+
+/// Returns the text representation of a node defined by the UEFI specification, if the
+/// node is known and well formed.
+fn format_known_node(node: &Node) -> Option<String> {
+    let data = node.data;
+
+    let text = match (node.ty, node.subtype) {
+        (HARDWARE_PATH, HARDWARE_PCI) => {
+            format!("Pci({:#x},{:#x})", data.get(1)?, data.first()?)
+        }
+
+        (ACPI_PATH, ACPI_ACPI) => {
+            let (hid, uid) = (read_u32(data, 0)?, read_u32(data, 4)?);
+
+            match hid {
+                PCI_ROOT_HID => format!("PciRoot({:#x})", uid),
+                PCIE_ROOT_HID => format!("PcieRoot({:#x})", uid),
+                _ => format!("Acpi({:#x},{:#x})", hid, uid),
+            }
+        }
+
+        (MESSAGING_PATH, MESSAGING_ATAPI) => {
+            let channel = ["Primary", "Secondary"][(*data.first()? != 0) as usize];
+            let device = ["Master", "Slave"][(*data.get(1)? != 0) as usize];
+
+            format!("Ata({},{},{:#x})", channel, device, read_u16(data, 2)?)
+        }
+
+        (MESSAGING_PATH, MESSAGING_SCSI) => {
+            format!("Scsi({:#x},{:#x})", read_u16(data, 0)?, read_u16(data, 2)?)
+        }
+
+        (MESSAGING_PATH, MESSAGING_USB) => {
+            format!("Usb({:#x},{:#x})", data.first()?, data.get(1)?)
+        }
+
+        (MESSAGING_PATH, MESSAGING_SATA) => format!(
+            "Sata({:#x},{:#x},{:#x})",
+            read_u16(data, 0)?,
+            read_u16(data, 2)?,
+            read_u16(data, 4)?
+        ),
+
+        (MESSAGING_PATH, MESSAGING_NVME) => {
+            let eui = data.get(4..12)?;
+            let mut text = format!("NVMe({:#x},", read_u32(data, 0)?);
+
+            for (i, byte) in eui.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "-" };
+                let _ = write!(text, "{}{:02X}", separator, byte);
+            }
+
+            text.push(')');
+            text
+        }
+
+        (MEDIA_PATH, MEDIA_HARD_DRIVE) => {
+            let partition = read_u32(data, 0)?;
+            let (start, size) = (read_u64(data, 4)?, read_u64(data, 12)?);
+            let signature = data.get(20..36)?;
+
+            match (data.get(36)?, data.get(37)?) {
+                // GPT partition with a GUID signature.
+                (2, 2) => format!(
+                    "HD({},GPT,{},{:#x},{:#x})",
+                    partition,
+                    format_guid(signature),
+                    start,
+                    size
+                ),
+
+                // MBR partition with a 32-bit disk signature.
+                (1, 1) => format!(
+                    "HD({},MBR,{:#010x},{:#x},{:#x})",
+                    partition,
+                    read_u32(signature, 0)?,
+                    start,
+                    size
+                ),
+
+                _ => return None,
+            }
+        }
+
+        (MEDIA_PATH, MEDIA_CDROM) => format!("CDROM({:#x})", read_u32(data, 0)?),
+
+        (MEDIA_PATH, MEDIA_FILE_PATH) => {
+            let units = data
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect::<Vec<_>>();
+
+            String::from_utf16(&units).ok()?
+        }
+
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+/// Returns the text representation of the provided node, using the generic `Path()` form
+/// for nodes that are not known.
+pub fn format_node(node: &Node) -> String {
+    format_known_node(node).unwrap_or_else(|| {
+        let mut text = format!("Path({},{},", node.ty, node.subtype);
+
+        for byte in node.data {
+            let _ = write!(text, "{:02X}", byte);
+        }
+
+        text.push(')');
+        text
+    })
+}
+
+/// Returns the text representation of the provided device path (e.g.
+/// `PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)`), as described by the UEFI
+/// specification.
+pub fn format(bytes: &[u8]) -> String {
+    nodes(bytes)
+        .map(|node| format_node(&node))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Normalizes the provided text representation of a device path for comparisons, which
+/// ignore case and whitespace as the specification does not mandate either.
+fn normalize_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Returns true if the provided text representations describe the same device path.
+pub fn text_matches(a: &str, b: &str) -> bool {
+    normalize_text(a) == normalize_text(b)
+}
+
+/// Returns true if the device path with the provided text representation is the device path
+/// with the other text representation or one of its children (e.g.
+/// `PciRoot(0x0)/Pci(0x2,0x0)/AcpiAdr(0x80010100)` starts with `PciRoot(0x0)/Pci(0x2,0x0)`).
+pub fn text_starts_with(path: &str, prefix: &str) -> bool {
+    let (path, prefix) = (normalize_text(path), normalize_text(prefix));
+
+    match path.strip_prefix(prefix.as_str()) {
+        Some(rest) => !prefix.is_empty() && (rest.is_empty() || rest.starts_with('/')),
+        None => false,
+    }
+}
+
+/// Returns true if the provided component of a path is a node of the text representation
+/// of a device path (e.g. `Pci(0x1,0x1)`).
+pub fn is_text_node(component: &str) -> bool {
+    match component.find('(') {
+        Some(start) => start > 0 && component.ends_with(')'),
+        None => false,
+    }
+}
+
+fn push_node(path: &mut Vec<u8>, ty: u8, subtype: u8, data: &[u8]) {
+    path.push(ty);
+    path.push(subtype);
+    path.extend_from_slice(&((NODE_HEADER_SIZE + data.len()) as u16).to_le_bytes());
+    path.extend_from_slice(data);
+}
+
+/// Returns true if the provided device path describes removable media that live systems
+/// are usually booted from, which are optical discs (El Torito) and USB mass storage.
+pub fn is_removable_media(bytes: &[u8]) -> bool {
+    nodes(bytes).any(|node| {
+        matches!(
+            (node.ty, node.subtype),
+            (MEDIA_PATH, MEDIA_CDROM) | (MESSAGING_PATH, MESSAGING_USB)
+        )
+    })
+}
+
+/// A GPT partition described by a hard drive node of a device path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptPartition {
+    /// The number of the partition, starting at 1.
+    pub number: u32,
+    /// The unique GUID of the partition in lowercase (e.g. for `root=PARTUUID=`).
+    pub guid: String,
+    /// The offset of the hard drive node, so that the device path of the disk precedes it.
+    pub offset: usize,
+}
+
+/// Returns the GPT partition described by the provided device path, if it ends in a
+/// partition of a disk with a GUID partition table.
+pub fn gpt_partition(bytes: &[u8]) -> Option<GptPartition> {
+    let mut offset = 0;
+
+    for node in nodes(bytes) {
+        if (node.ty, node.subtype) == (MEDIA_PATH, MEDIA_HARD_DRIVE) {
+            let data = node.data;
+
+            if (data.get(36)?, data.get(37)?) != (&2, &2) {
+                return None;
+            }
+
+            return Some(GptPartition {
+                number: read_u32(data, 0)?,
+                guid: format_guid(data.get(20..36)?).to_ascii_lowercase(),
+                offset,
+            });
+        }
+
+        offset += NODE_HEADER_SIZE + node.data.len();
+    }
+
+    None
+}
+
+/// Returns a copy of the provided device path with a file path node for the provided path
+/// (using `\` as the separator) appended, e.g. to describe an image that is loaded from the
+/// device.
+pub fn append_file_path(device: &[u8], path: &str) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    for node in nodes(device) {
+        push_node(&mut result, node.ty, node.subtype, node.data);
+    }
+
+    let path = path
+        .encode_utf16()
+        .chain(core::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    push_node(&mut result, MEDIA_PATH, MEDIA_FILE_PATH, &path);
+    push_node(&mut result, END_PATH, END_ENTIRE, &[]);
+    result
+}
+
+/// Returns the path of the file described by the file path nodes of the provided device
+/// path (e.g. `\EFI\BOOT\BOOTX64.EFI`), such as the path of a loaded image. Some firmware
+/// splits the path into several nodes. Returns [`None`] if there are no file path nodes.
+pub fn file_path(bytes: &[u8]) -> Option<String> {
+    let mut path = String::new();
+
+    for node in nodes(bytes).filter(|node| (node.ty, node.subtype) == (MEDIA_PATH, MEDIA_FILE_PATH))
+    {
+        let component = format_known_node(&node)?;
+
+        if !path.is_empty() && !path.ends_with('\\') && !component.starts_with('\\') {
+            path.push('\\');
+        }
+
+        path.push_str(&component);
+    }
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_path() -> Vec<u8> {
+        let mut path = Vec::new();
+
+        push_node(
+            &mut path,
+            ACPI_PATH,
+            ACPI_ACPI,
+            &[0xd0, 0x41, 0x03, 0x0a, 0, 0, 0, 0],
+        );
+        push_node(&mut path, HARDWARE_PATH, HARDWARE_PCI, &[0x02, 0x1f]);
+        push_node(
+            &mut path,
+            MESSAGING_PATH,
+            MESSAGING_SATA,
+            &[0, 0, 0xff, 0xff, 0, 0],
+        );
+
+        let mut hard_drive = Vec::new();
+        hard_drive.extend_from_slice(&1u32.to_le_bytes());
+        hard_drive.extend_from_slice(&0x800u64.to_le_bytes());
+        hard_drive.extend_from_slice(&0x10000u64.to_le_bytes());
+        hard_drive.extend_from_slice(&[0xaa; 16]);
+        hard_drive.extend_from_slice(&[2, 2]);
+
+        push_node(&mut path, MEDIA_PATH, MEDIA_HARD_DRIVE, &hard_drive);
+        push_node(&mut path, END_PATH, END_ENTIRE, &[]);
+        path
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            format(&device_path()),
+            "PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/\
+             HD(1,GPT,AAAAAAAA-AAAA-AAAA-AAAA-AAAAAAAAAAAA,0x800,0x10000)"
+        );
+
+        let mut unknown = Vec::new();
+        push_node(&mut unknown, 0x05, 0x01, &[0x12, 0x34]);
+        assert_eq!(format(&unknown), "Path(5,1,1234)");
+    }
+
+    #[test]
+    fn lengths() {
+        let path = device_path();
+        assert_eq!(len(&path), Some(path.len()));
+
+        // A node cannot be shorter than its header.
+        assert_eq!(len(&[0x01, 0x01, 0x02, 0x00]), None);
+        // The end node is missing.
+        assert_eq!(len(&path[..path.len() - 4]), None);
+    }
+
+    #[test]
+    fn file_paths() {
+        let path = append_file_path(&device_path(), "\\EFI\\BOOT\\BOOTX64.EFI");
+
+        assert_eq!(len(&path), Some(path.len()));
+        assert!(format(&path).ends_with("/\\EFI\\BOOT\\BOOTX64.EFI"));
+        assert_eq!(
+            file_path(&path).as_deref(),
+            Some("\\EFI\\BOOT\\BOOTX64.EFI")
+        );
+
+        let split = append_file_path(&append_file_path(&[], "\\EFI"), "ION.EFI");
+        assert_eq!(file_path(&split).as_deref(), Some("\\EFI\\ION.EFI"));
+        assert_eq!(file_path(&device_path()), None);
+    }
+
+    #[test]
+    fn consoles() {
+        let device = device_path();
+        let disk = &device[..gpt_partition(&device).unwrap().offset];
+
+        let mut serial = Vec::new();
+        push_node(&mut serial, HARDWARE_PATH, HARDWARE_PCI, &[0x00, 0x16]);
+
+        // The ConOut variable with the disk controller and a serial console.
+        let mut console = disk.to_vec();
+        push_node(&mut console, END_PATH, END_INSTANCE, &[]);
+        console.extend_from_slice(&serial);
+        push_node(&mut console, END_PATH, END_ENTIRE, &[]);
+
+        let instances = instances(&console).collect::<Vec<_>>();
+        assert_eq!(instances, [disk, &serial[..]]);
+
+        assert!(starts_with(&device, instances[0]));
+        assert!(!starts_with(instances[0], &device));
+        assert!(!starts_with(&device, instances[1]));
+        assert!(!starts_with(&device, &[]));
+    }
+
+    #[test]
+    fn removable_media() {
+        assert!(!is_removable_media(&device_path()));
+
+        let mut usb = Vec::new();
+        push_node(&mut usb, MESSAGING_PATH, MESSAGING_USB, &[0x01, 0x00]);
+        push_node(&mut usb, END_PATH, END_ENTIRE, &[]);
+        assert!(is_removable_media(&usb));
+    }
+
+    #[test]
+    fn partitions() {
+        let path = device_path();
+        let partition = gpt_partition(&path).unwrap();
+
+        assert_eq!(partition.number, 1);
+        assert_eq!(partition.guid, "aaaaaaaa-aaaa-aaaa-aaaa-aaaaaaaaaaaa");
+        assert_eq!(
+            format(&path[..partition.offset]),
+            "PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)"
+        );
+
+        assert_eq!(gpt_partition(&path[..partition.offset]), None);
+    }
+
+    #[test]
+    fn text() {
+        assert!(text_matches(
+            "PciRoot(0x0)/Pci(0x1F,0x2)",
+            "pciroot(0x0)/pci(0x1f, 0x2)"
+        ));
+        assert!(!text_matches("PciRoot(0x0)", "PciRoot(0x1)"));
+
+        assert!(text_starts_with(
+            "PciRoot(0x0)/Pci(0x2,0x0)/AcpiAdr(0x80010100)",
+            "pciroot(0x0)/pci(0x2,0x0)"
+        ));
+        assert!(text_starts_with("PciRoot(0x0)", "PciRoot(0x0)"));
+        assert!(!text_starts_with(
+            "PciRoot(0x0)/Pci(0x2,0x0)",
+            "PciRoot(0x0)/Pci(0x2"
+        ));
+        assert!(!text_starts_with("PciRoot(0x0)", ""));
+
+        assert!(is_text_node("Pci(0x1,0x1)"));
+        assert!(!is_text_node("boot"));
+        assert!(!is_text_node("(0x1)"));
+    }
+}
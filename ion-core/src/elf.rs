@@ -0,0 +1,184 @@
+/// The size of the stivale2 header (the entry point, the stack, the flags and the address
+/// of the first header tag).
+pub const STIVALE2_HEADER_SIZE: u64 = 32;
+
+/// The maximum amount of header tags that are walked, so that a corrupted tag list cannot
+/// make us loop forever.
+const MAX_HEADER_TAGS: usize = 64;
+
+/// A `PT_LOAD` segment of the kernel, used to translate virtual addresses into offsets into
+/// the ELF file.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadSegment {
+    pub virtual_addr: u64,
+    pub offset: u64,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The size of the header section does not match the size of the header.
+    InvalidSize,
+    /// The stack requested by the header is not 16-byte aligned.
+    MisalignedStack,
+}
+
+impl HeaderError {
+    /// Returns a description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidSize => "section .stivale2hdr does not match the size of the struct",
+            Self::MisalignedStack => "requested stack is not 16-byte aligned",
+        }
+    }
+}
+
+/// Reads the little endian 64-bit value at the provided offset, if it is in bounds.
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+
+    let mut value = [0; 8];
+    value.copy_from_slice(bytes);
+
+    Some(u64::from_le_bytes(value))
+}
+
+/// Checks the contents of the `.stivale2hdr` section of the kernel.
+pub fn check_stivale2_header(header: &[u8]) -> Result<(), HeaderError> {
+    if header.len() as u64 != STIVALE2_HEADER_SIZE {
+        return Err(HeaderError::InvalidSize);
+    }
+
+    // The stack address follows the entry point. The stivale2 specs says the stack has to
+    // be 16-byte aligned.
+    let stack = read_u64(header, 8).unwrap_or(0);
+
+    if stack & (16 - 1) != 0 {
+        return Err(HeaderError::MisalignedStack);
+    }
+
+    Ok(())
+}
+
+/// Translates the provided virtual address into an offset into the ELF file, if it is
+/// backed by the file contents of one of the provided segments.
+pub fn file_offset<I>(segments: I, address: u64) -> Option<usize>
+where
+    I: IntoIterator<Item = LoadSegment>,
+{
+    segments
+        .into_iter()
+        .find(|segment| {
+            address >= segment.virtual_addr && address - segment.virtual_addr < segment.file_size
+        })
+        .map(|segment| (segment.offset + (address - segment.virtual_addr)) as usize)
+}
+
+/// Returns the header tag with the provided identifier from the stivale2 header of the
+/// kernel, starting at the tag header, if there is any. The tags are linked using their
+/// virtual addresses, which are translated into offsets into the ELF file using the
+/// provided segments.
+pub fn find_header_tag<'a, I>(
+    input: &'a [u8],
+    segments: I,
+    header: &[u8],
+    identifier: u64,
+) -> Option<&'a [u8]>
+where
+    I: IntoIterator<Item = LoadSegment> + Clone,
+{
+    // The address of the first tag follows the entry point, the stack and the flags.
+    let mut tag = read_u64(header, 24).unwrap_or(0);
+
+    for _ in 0..MAX_HEADER_TAGS {
+        if tag == 0 {
+            return None;
+        }
+
+        let offset = file_offset(segments.clone(), tag)?;
+
+        match (read_u64(input, offset), read_u64(input, offset + 8)) {
+            (Some(id), _) if id == identifier => return Some(&input[offset..]),
+            (Some(_), Some(next)) => tag = next,
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEGMENT: LoadSegment = LoadSegment {
+        virtual_addr: 0xffff_ffff_8000_0000,
+        offset: 0x1000,
+        file_size: 0x1000,
+    };
+
+    fn header(stack: u64, first_tag: u64) -> [u8; 32] {
+        let mut header = [0; 32];
+
+        header[8..16].copy_from_slice(&stack.to_le_bytes());
+        header[24..32].copy_from_slice(&first_tag.to_le_bytes());
+        header
+    }
+
+    fn write_tag(input: &mut [u8], offset: usize, identifier: u64, next: u64) {
+        input[offset..offset + 8].copy_from_slice(&identifier.to_le_bytes());
+        input[offset + 8..offset + 16].copy_from_slice(&next.to_le_bytes());
+    }
+
+    #[test]
+    fn header_checks() {
+        assert_eq!(check_stivale2_header(&header(0, 0)), Ok(()));
+        assert_eq!(check_stivale2_header(&header(0x8000, 0)), Ok(()));
+        assert_eq!(
+            check_stivale2_header(&header(0x8008, 0)),
+            Err(HeaderError::MisalignedStack)
+        );
+        assert_eq!(
+            check_stivale2_header(&[0; 24]),
+            Err(HeaderError::InvalidSize)
+        );
+    }
+
+    #[test]
+    fn file_offsets() {
+        assert_eq!(file_offset([SEGMENT], SEGMENT.virtual_addr), Some(0x1000));
+        assert_eq!(
+            file_offset([SEGMENT], SEGMENT.virtual_addr + 0xfff),
+            Some(0x1fff)
+        );
+        assert_eq!(file_offset([SEGMENT], SEGMENT.virtual_addr + 0x1000), None);
+        assert_eq!(file_offset([SEGMENT], 0x1000), None);
+    }
+
+    #[test]
+    fn header_tags_are_followed() {
+        let mut input = [0; 0x2000];
+
+        write_tag(&mut input, 0x1100, 1, SEGMENT.virtual_addr + 0x200);
+        write_tag(&mut input, 0x1200, 2, 0);
+
+        let header = header(0, SEGMENT.virtual_addr + 0x100);
+
+        let tag = find_header_tag(&input, [SEGMENT], &header, 2).unwrap();
+        assert_eq!(read_u64(tag, 0), Some(2));
+
+        assert!(find_header_tag(&input, [SEGMENT], &header, 1).is_some());
+        assert!(find_header_tag(&input, [SEGMENT], &header, 3).is_none());
+    }
+
+    #[test]
+    fn header_tag_loops_terminate() {
+        let mut input = [0; 0x2000];
+
+        // The tag links to itself.
+        write_tag(&mut input, 0x1100, 1, SEGMENT.virtual_addr + 0x100);
+
+        let header = header(0, SEGMENT.virtual_addr + 0x100);
+        assert!(find_header_tag(&input, [SEGMENT], &header, 2).is_none());
+    }
+}
@@ -0,0 +1,97 @@
+/// The size of the first page of a Linux swap partition, which contains the swap header.
+pub const SWAP_HEADER_SIZE: usize = 4096;
+
+/// The signature is stored in the last 10 bytes of the swap header.
+const SWAP_SIGNATURE_LEN: usize = 10;
+
+/// The signatures that replace `SWAPSPACE2` while the swap partition contains a hibernation
+/// image: the in-kernel swsusp, the legacy swsusp and the userspace uswsusp signatures.
+const SWSUSP_SIGNATURES: &[&[u8]] = &[
+    b"S1SUSPEND\0",
+    b"S2SUSPEND\0",
+    b"ULSUSPEND\0",
+    b"LINHIB0001",
+];
+
+/// The signatures at the start of the Windows hibernation file (`hiberfil.sys`) while the
+/// image has not been resumed yet. Once Windows has resumed, the signature is replaced with
+/// `wake` or cleared.
+const HIBERFIL_SIGNATURES: &[&[u8; 4]] = &[b"hibr", b"rstr"];
+
+/// What the boot menu does if an entry has a pending hibernation image, which is set by the
+/// `RESUME_ACTION=` config key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeAction {
+    /// Select the entry, so that it is booted once the countdown expires.
+    Select,
+    /// Warn the user and disable the countdown, so that an entry has to be picked manually.
+    Warn,
+}
+
+impl ResumeAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "select" => Some(Self::Select),
+            "warn" => Some(Self::Warn),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if the provided swap header contains a Linux hibernation image signature.
+pub fn swap_hibernated(header: &[u8]) -> bool {
+    if header.len() < SWAP_HEADER_SIZE {
+        return false;
+    }
+
+    let signature = &header[SWAP_HEADER_SIZE - SWAP_SIGNATURE_LEN..SWAP_HEADER_SIZE];
+    SWSUSP_SIGNATURES.contains(&signature)
+}
+
+/// Returns true if the provided start of a Windows hibernation file describes an image that
+/// has not been resumed yet. The signature is compared case-insensitively, as older versions
+/// of Windows use upper case signatures.
+pub fn hiberfil_pending(header: &[u8]) -> bool {
+    match header.get(..4) {
+        Some(signature) => HIBERFIL_SIGNATURES
+            .iter()
+            .any(|expected| signature.eq_ignore_ascii_case(&expected[..])),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap_header(signature: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; SWAP_HEADER_SIZE];
+        header[SWAP_HEADER_SIZE - signature.len()..].copy_from_slice(signature);
+        header
+    }
+
+    #[test]
+    fn swap_signatures() {
+        assert!(swap_hibernated(&swap_header(b"S1SUSPEND\0")));
+        assert!(swap_hibernated(&swap_header(b"LINHIB0001")));
+        assert!(!swap_hibernated(&swap_header(b"SWAPSPACE2")));
+        assert!(!swap_hibernated(&swap_header(b"S1SUSPEND\0")[..512]));
+    }
+
+    #[test]
+    fn hiberfil_signatures() {
+        assert!(hiberfil_pending(b"hibr\0\0\0\0"));
+        assert!(hiberfil_pending(b"HIBR"));
+        assert!(hiberfil_pending(b"RSTR"));
+        assert!(!hiberfil_pending(b"wake"));
+        assert!(!hiberfil_pending(&[0; 4]));
+        assert!(!hiberfil_pending(b"hib"));
+    }
+
+    #[test]
+    fn resume_actions() {
+        assert_eq!(ResumeAction::parse("select"), Some(ResumeAction::Select));
+        assert_eq!(ResumeAction::parse("warn"), Some(ResumeAction::Warn));
+        assert_eq!(ResumeAction::parse("yes"), None);
+    }
+}
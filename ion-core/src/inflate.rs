@@ -0,0 +1,344 @@
+// A decoder for the DEFLATE compressed data format (RFC 1951) and the zlib format (RFC 1950)
+// wrapping it, which is used by PNG images. The Huffman codes are decoded bit by bit, which
+// is slow but simple and fast enough for small files such as icons.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The base lengths and the amount of extra bits of the length symbols 257 to 285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The base distances and the amount of extra bits of the distance symbols.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order in which the lengths of the code length code are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// The symbol that ends a compressed block.
+const END_OF_BLOCK: u16 = 256;
+
+/// Reads the bits of the compressed data, starting at the least significant bit of each
+/// byte.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// The offset of the next bit.
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.position / 8)?;
+        let bit = (byte >> (self.position % 8)) & 1;
+
+        self.position += 1;
+        Some(bit as u32)
+    }
+
+    /// Reads a value of the provided amount of bits, which is stored least significant bit
+    /// first.
+    fn bits(&mut self, count: u8) -> Option<u32> {
+        (0..count).try_fold(0, |value, i| Some(value | self.bit()? << i))
+    }
+
+    /// Skips the remaining bits of the current byte.
+    fn align(&mut self) {
+        self.position = (self.position + 7) & !7;
+    }
+
+    /// Skips to the start of the next byte and reads the provided amount of bytes.
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        self.align();
+
+        let start = self.position / 8;
+        let bytes = self.data.get(start..start + len)?;
+
+        self.position = (start + len) * 8;
+        Some(bytes)
+    }
+}
+
+/// A canonical Huffman code, given by the amount of codes of each length and the symbols
+/// ordered by their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Creates the code with the provided code lengths of the symbols. Symbols with a
+    /// length of 0 are not used.
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0; 16];
+
+        for &length in lengths {
+            *counts.get_mut(length as usize)? += 1;
+        }
+
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Some(Self { counts, symbols })
+    }
+
+    /// Reads the next symbol. The codes of each length are consecutive, so the symbol is
+    /// found once the code read so far is within the codes of its length.
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+
+        for &count in &self.counts[1..] {
+            code |= reader.bit()? as usize;
+
+            if code < first + count as usize {
+                return self.symbols.get(index + code - first).copied();
+            }
+
+            index += count as usize;
+            first = (first + count as usize) << 1;
+            code <<= 1;
+        }
+
+        None
+    }
+}
+
+/// Returns the codes used by blocks compressed with fixed Huffman codes.
+fn fixed_codes() -> Option<(Huffman, Huffman)> {
+    let mut lengths = [0; 288];
+
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    Some((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+/// Reads the codes of a block compressed with dynamic Huffman codes, whose code lengths
+/// are compressed with another Huffman code.
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+
+    let code_length_code = Huffman::new(&code_lengths)?;
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return None,
+        };
+
+        lengths.resize(lengths.len() + repeat as usize, length);
+    }
+
+    if lengths.len() != literal_count + distance_count {
+        return None;
+    }
+
+    let (literals, distances) = lengths.split_at(literal_count);
+    Some((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+/// Decompresses a block using the provided literal/length and distance codes.
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(reader)?;
+
+        if symbol < END_OF_BLOCK {
+            output.push(symbol as u8);
+            continue;
+        } else if symbol == END_OF_BLOCK {
+            return Some(());
+        }
+
+        let index = (symbol - END_OF_BLOCK - 1) as usize;
+        let length = *LENGTH_BASE.get(index)? as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+
+        let index = distances.decode(reader)? as usize;
+        let distance =
+            *DISTANCE_BASE.get(index)? as usize + reader.bits(DISTANCE_EXTRA[index])? as usize;
+
+        let start = output.len().checked_sub(distance)?;
+
+        // The copied bytes might overlap with the bytes that are being written.
+        for i in start..start + length {
+            output.push(output[i]);
+        }
+    }
+}
+
+/// Decompresses the provided DEFLATE stream and returns the decompressed data along with
+/// the size of the stream in bytes.
+fn inflate_stream(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bit()? == 1;
+
+        match reader.bits(2)? {
+            // Stored block, which follows its length and the complement of its length.
+            0 => {
+                let header = reader.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return None;
+                }
+
+                output.extend_from_slice(reader.bytes(len as usize)?);
+            }
+
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+
+            _ => return None,
+        }
+
+        if last {
+            reader.align();
+            return Some((output, reader.position / 8));
+        }
+    }
+}
+
+/// Decompresses the provided DEFLATE stream. Returns [`None`] if the stream is malformed.
+pub fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    inflate_stream(data).map(|(output, _)| output)
+}
+
+/// Returns the Adler-32 checksum of the provided data, which is used by the zlib format.
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    (b << 16) | a
+}
+
+/// Decompresses the provided zlib stream and checks its checksum. Returns [`None`] if the
+/// stream is malformed or uses a preset dictionary.
+pub fn zlib_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let (method, flags) = (*data.first()?, *data.get(1)?);
+
+    // The header is a multiple of 31 and DEFLATE is the only defined compression method.
+    if method & 0xf != 8 || u16::from_be_bytes([method, flags]) % 31 != 0 || flags & 0x20 != 0 {
+        return None;
+    }
+
+    let (output, len) = inflate_stream(&data[2..])?;
+    let checksum = data.get(2 + len..2 + len + 4)?;
+
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&output)
+    {
+        return None;
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_blocks() {
+        let data = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        assert_eq!(inflate(&data).as_deref(), Some(&b"hello"[..]));
+
+        // The complement of the length does not match.
+        let data = [0x01, 0x05, 0x00, 0xfa, 0xfe, b'h', b'e', b'l', b'l', b'o'];
+        assert_eq!(inflate(&data), None);
+    }
+
+    #[test]
+    fn fixed_blocks() {
+        // zlib.compress(b"hello hello hello")
+        let data = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x3a, 0x2e,
+            0x06, 0x7d,
+        ];
+
+        assert_eq!(
+            zlib_decompress(&data).as_deref(),
+            Some(&b"hello hello hello"[..])
+        );
+
+        let mut corrupted = data;
+        corrupted[15] ^= 1;
+        assert_eq!(zlib_decompress(&corrupted), None);
+    }
+
+    #[test]
+    fn dynamic_blocks() {
+        let text = b"Ion is a modern, fast and simple UEFI bootloader. Ion is a modern, \
+                     fast and simple UEFI bootloader with support for stivale, stivale2, \
+                     multiboot, multiboot2 and linux kernels.";
+
+        // zlib.compress(text, 9)
+        let data = [
+            0x78, 0xda, 0x95, 0x8d, 0x41, 0x0e, 0x83, 0x30, 0x0c, 0x04, 0xbf, 0xb2, 0x0f, 0x88,
+            0x38, 0xf0, 0x07, 0x90, 0xf2, 0x80, 0x3e, 0xc0, 0x15, 0x41, 0xb5, 0x70, 0xe2, 0x28,
+            0x76, 0x28, 0xcf, 0x87, 0x56, 0x42, 0xea, 0xb5, 0xb7, 0x39, 0xec, 0xcc, 0x46, 0x2d,
+            0x60, 0x03, 0x21, 0xeb, 0x92, 0x5a, 0x09, 0x58, 0xc9, 0x1c, 0x54, 0x16, 0x18, 0xe7,
+            0x2a, 0x09, 0x8f, 0x69, 0x8e, 0x78, 0xaa, 0xba, 0x28, 0x5d, 0x8b, 0x01, 0xf1, 0x4f,
+            0x03, 0x6f, 0xf6, 0x17, 0xac, 0xd7, 0xaa, 0xcd, 0xb1, 0x6a, 0x83, 0x39, 0xef, 0x24,
+            0x29, 0xdc, 0x30, 0x06, 0xe4, 0x2e, 0xce, 0x1f, 0xe7, 0x07, 0xc7, 0x6f, 0x54, 0xb8,
+            0xf4, 0x03, 0xdb, 0xf5, 0x94, 0xc4, 0x86, 0x13, 0x0c, 0x43, 0x3e, 0x70,
+        ];
+
+        assert_eq!(zlib_decompress(&data).as_deref(), Some(&text[..]));
+    }
+}
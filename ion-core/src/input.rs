@@ -0,0 +1,141 @@
+// The keyboard handling of Ion that does not depend on the firmware: the modifier state
+// reported by the Simple Text Input Ex protocol and the synthesized key repeat.
+
+/// The shift state is only valid if this bit is set.
+const SHIFT_STATE_VALID: u32 = 0x8000_0000;
+const RIGHT_SHIFT_PRESSED: u32 = 0x0000_0001;
+const LEFT_SHIFT_PRESSED: u32 = 0x0000_0002;
+const RIGHT_CONTROL_PRESSED: u32 = 0x0000_0004;
+const LEFT_CONTROL_PRESSED: u32 = 0x0000_0008;
+const RIGHT_ALT_PRESSED: u32 = 0x0000_0010;
+const LEFT_ALT_PRESSED: u32 = 0x0000_0020;
+
+/// The modifier keys that are held down while a key is pressed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    /// Decodes the shift state reported by the Simple Text Input Ex protocol. Firmware that
+    /// does not report the shift state reports no modifiers.
+    pub fn from_shift_state(shift_state: u32) -> Self {
+        if shift_state & SHIFT_STATE_VALID == 0 {
+            return Self::default();
+        }
+
+        let pressed = |mask: u32| shift_state & mask != 0;
+
+        Self {
+            shift: pressed(LEFT_SHIFT_PRESSED | RIGHT_SHIFT_PRESSED),
+            ctrl: pressed(LEFT_CONTROL_PRESSED | RIGHT_CONTROL_PRESSED),
+            alt: pressed(LEFT_ALT_PRESSED | RIGHT_ALT_PRESSED),
+        }
+    }
+}
+
+/// The time in milliseconds within which a key that is reported again is considered to be
+/// held down, which covers the initial delay of the firmware's key repeat.
+const HOLD_WINDOW_MS: u64 = 600;
+
+/// The interval in milliseconds at which held keys are repeated.
+const REPEAT_INTERVAL_MS: u64 = 33;
+
+/// The maximum amount of key presses that are reported for a single report of the
+/// firmware, so that a stalled machine does not flood the menu with key presses.
+const MAX_REPEATS: usize = 8;
+
+/// Synthesizes the repeat of held keys at a consistent rate. Firmware repeats held keys at
+/// its own rate, which is often too slow to move through long lists or command lines, so
+/// each report of a held key stands for all of the repeats since the previous report.
+/// Repeats are only synthesized when the firmware reports the key again, so they stop as
+/// soon as the key is released.
+#[derive(Debug, Default)]
+pub struct KeyRepeat {
+    /// The key that has been reported last and the time at which it was reported.
+    last: Option<(u32, u64)>,
+    /// Whether the firmware has already repeated the last key, which means that the
+    /// initial delay is over.
+    held: bool,
+}
+
+impl KeyRepeat {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            held: false,
+        }
+    }
+
+    /// Records that the key with the provided ID has been reported at the provided time in
+    /// milliseconds. Returns the amount of key presses that the report stands for and true
+    /// if the key is held down.
+    pub fn report(&mut self, key: u32, now: u64) -> (usize, bool) {
+        let previous = self.last.replace((key, now));
+
+        match previous {
+            Some((previous_key, reported)) if previous_key == key => {
+                let elapsed = now.saturating_sub(reported);
+
+                if elapsed > HOLD_WINDOW_MS {
+                    self.held = false;
+                    return (1, false);
+                }
+
+                // The first repeat ends the initial delay, which is not sped up.
+                if !self.held {
+                    self.held = true;
+                    return (1, true);
+                }
+
+                let repeats = (elapsed / REPEAT_INTERVAL_MS) as usize;
+                (repeats.clamp(1, MAX_REPEATS), true)
+            }
+
+            _ => {
+                self.held = false;
+                (1, false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(
+            Modifiers::from_shift_state(LEFT_CONTROL_PRESSED),
+            Modifiers::default()
+        );
+        assert_eq!(
+            Modifiers::from_shift_state(SHIFT_STATE_VALID | RIGHT_SHIFT_PRESSED | LEFT_ALT_PRESSED),
+            Modifiers {
+                shift: true,
+                ctrl: false,
+                alt: true,
+            }
+        );
+    }
+
+    #[test]
+    fn repeat() {
+        let mut repeat = KeyRepeat::new();
+
+        // The first press and a press after the key has been released.
+        assert_eq!(repeat.report(b'a' as u32, 0), (1, false));
+        assert_eq!(repeat.report(b'a' as u32, 1000), (1, false));
+
+        // The firmware repeats the held key after its initial delay and then every 100ms.
+        assert_eq!(repeat.report(b'a' as u32, 1500), (1, true));
+        assert_eq!(repeat.report(b'a' as u32, 1600), (3, true));
+        assert_eq!(repeat.report(b'a' as u32, 1610), (1, true));
+        assert_eq!(repeat.report(b'a' as u32, 2000), (8, true));
+
+        assert_eq!(repeat.report(b'b' as u32, 2010), (1, false));
+    }
+}
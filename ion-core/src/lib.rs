@@ -0,0 +1,25 @@
+// The logic of Ion that does not depend on the firmware, so that it can be unit tested on
+// the host using `cargo test`.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod autodetect;
+pub mod bls;
+pub mod bootorder;
+pub mod checksum;
+pub mod command;
+pub mod config;
+pub mod cpu;
+pub mod devpath;
+pub mod elf;
+pub mod hibernation;
+pub mod inflate;
+pub mod input;
+pub mod linux;
+pub mod mmap;
+pub mod multiboot;
+pub mod multiboot2;
+pub mod png;
+pub mod smbios;
+pub mod uri;
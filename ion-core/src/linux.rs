@@ -0,0 +1,578 @@
+use core::convert::TryInto;
+
+/// The magic value of the setup header ("HdrS").
+const HEADER_MAGIC: u32 = 0x5372_6448;
+
+/// The oldest boot protocol version that provides the 64-bit entry point.
+pub const MIN_VERSION: u16 = 0x020c;
+
+/// The size of the zero page (`struct boot_params`).
+pub const BOOT_PARAMS_SIZE: usize = 0x1000;
+
+/// The offset of the 64-bit entry point from the start of the protected-mode kernel.
+pub const ENTRY_64_OFFSET: u64 = 0x200;
+
+/// The offsets of the fields of the setup header in the kernel image and in the zero page.
+const SETUP_SECTS: usize = 0x1f1;
+const HEADER_END: usize = 0x201;
+const HEADER: usize = 0x202;
+const VERSION: usize = 0x206;
+const TYPE_OF_LOADER: usize = 0x210;
+const CODE32_START: usize = 0x214;
+const RAMDISK_IMAGE: usize = 0x218;
+const RAMDISK_SIZE: usize = 0x21c;
+const CMD_LINE_PTR: usize = 0x228;
+const INITRD_ADDR_MAX: usize = 0x22c;
+const KERNEL_ALIGNMENT: usize = 0x230;
+const RELOCATABLE_KERNEL: usize = 0x234;
+const XLOADFLAGS: usize = 0x236;
+const CMDLINE_SIZE: usize = 0x238;
+const SETUP_DATA: usize = 0x250;
+const PREF_ADDRESS: usize = 0x258;
+const INIT_SIZE: usize = 0x260;
+
+/// The offsets of the fields of the zero page outside of the setup header.
+const SCREEN_INFO: usize = 0x000;
+const ACPI_RSDP_ADDR: usize = 0x070;
+const EXT_RAMDISK_IMAGE: usize = 0x0c0;
+const EXT_RAMDISK_SIZE: usize = 0x0c4;
+const EXT_CMD_LINE_PTR: usize = 0x0c8;
+const EFI_INFO: usize = 0x1c0;
+const E820_ENTRIES: usize = 0x1e8;
+const E820_TABLE: usize = 0x2d0;
+
+/// The amount of entries of the memory map in the zero page. The remaining entries are
+/// passed using a [`SETUP_E820_EXT`] setup data node.
+pub const E820_MAX_ENTRIES: usize = 128;
+
+/// The size of a memory map entry.
+pub const E820_ENTRY_SIZE: usize = 20;
+
+/// The memory map entry types, which are the same as the ones of Multiboot.
+pub const E820_RAM: u32 = 1;
+pub const E820_RESERVED: u32 = 2;
+pub const E820_ACPI: u32 = 3;
+pub const E820_NVS: u32 = 4;
+pub const E820_UNUSABLE: u32 = 5;
+
+/// The `xloadflags` telling that the kernel has a 64-bit entry point and that the kernel,
+/// the boot parameters, the command line and the initrd can be placed above 4 GiB.
+const XLF_KERNEL_64: u16 = 1 << 0;
+pub const XLF_CAN_BE_LOADED_ABOVE_4G: u16 = 1 << 1;
+
+/// The lowest address that relocatable kernels are placed at, as the memory below 1 MiB
+/// is needed by the kernel for other purposes (e.g. the SMP trampoline).
+const MIN_LOAD_ADDRESS: u64 = 0x100000;
+
+/// The alignment of relocatable kernels that do not specify a valid alignment.
+const DEFAULT_ALIGNMENT: u64 = 0x200000;
+
+/// The `type_of_loader` of boot loaders without an assigned identifier.
+const LOADER_TYPE_UNDEFINED: u8 = 0xff;
+
+/// The signature of the EFI information block of 64-bit boot loaders ("EL64").
+const EFI_LOADER_SIGNATURE: u32 = 0x3436_4c45;
+
+/// The `orig_video_isVGA` value of framebuffers provided by the EFI graphics output protocol.
+const VIDEO_TYPE_EFI: u8 = 0x70;
+
+/// The capability of the screen information telling that `ext_lfb_base` is valid.
+const VIDEO_CAPABILITY_64BIT_BASE: u32 = 1 << 1;
+
+/// The setup data types defined by the boot protocol.
+pub const SETUP_E820_EXT: u32 = 1;
+pub const SETUP_RNG_SEED: u32 = 9;
+
+/// The size of the header of a setup data node, which precedes its data.
+pub const SETUP_DATA_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The image does not contain a setup header.
+    NotLinux,
+    /// The boot protocol version of the kernel is too old.
+    UnsupportedVersion,
+    /// The kernel does not provide a 64-bit entry point.
+    Not64Bit,
+}
+
+impl HeaderError {
+    /// Returns a description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotLinux => "image is not a Linux bzImage",
+            Self::UnsupportedVersion => "linux boot protocol version is older than 2.12",
+            Self::Not64Bit => "kernel does not provide a 64-bit entry point",
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// The fields of the setup header of a bzImage that tell how it has to be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupHeader {
+    /// The size of the real-mode setup code, which is followed by the protected-mode kernel.
+    pub setup_size: usize,
+    /// The end of the setup header in the image, which is copied to the zero page.
+    header_end: usize,
+    pub version: u16,
+    pub xloadflags: u16,
+    /// The highest address that the initrd may end at.
+    pub initrd_addr_max: u32,
+    pub kernel_alignment: u32,
+    pub relocatable: bool,
+    /// The maximum length of the command line, excluding the terminating NUL.
+    pub cmdline_size: u32,
+    pub pref_address: u64,
+    /// The amount of memory that the kernel needs at its load address to decompress itself.
+    pub init_size: u32,
+}
+
+impl SetupHeader {
+    /// Parses the setup header of the provided bzImage and checks that it can be booted
+    /// using the 64-bit boot protocol.
+    pub fn parse(kernel: &[u8]) -> Result<Self, HeaderError> {
+        if kernel.len() < INIT_SIZE + 4 || read_u32(kernel, HEADER) != HEADER_MAGIC {
+            return Err(HeaderError::NotLinux);
+        }
+
+        let version = read_u16(kernel, VERSION);
+
+        if version < MIN_VERSION {
+            return Err(HeaderError::UnsupportedVersion);
+        }
+
+        let xloadflags = read_u16(kernel, XLOADFLAGS);
+
+        if xloadflags & XLF_KERNEL_64 == 0 {
+            return Err(HeaderError::Not64Bit);
+        }
+
+        // For compatibility, 0 setup sectors means 4.
+        let setup_sects = match kernel[SETUP_SECTS] {
+            0 => 4,
+            sects => sects as usize,
+        };
+
+        let setup_size = (setup_sects + 1) * 512;
+        let header_end = HEADER + kernel[HEADER_END] as usize;
+
+        if setup_size >= kernel.len() || header_end > kernel.len() {
+            return Err(HeaderError::NotLinux);
+        }
+
+        Ok(Self {
+            setup_size,
+            header_end,
+            version,
+            xloadflags,
+            initrd_addr_max: read_u32(kernel, INITRD_ADDR_MAX),
+            kernel_alignment: read_u32(kernel, KERNEL_ALIGNMENT),
+            relocatable: kernel[RELOCATABLE_KERNEL] != 0,
+            cmdline_size: read_u32(kernel, CMDLINE_SIZE),
+            pref_address: read_u64(kernel, PREF_ADDRESS),
+            init_size: read_u32(kernel, INIT_SIZE),
+        })
+    }
+
+    /// Returns where the protected-mode kernel can be placed. Kernels that are not
+    /// relocatable have to be placed at their preferred address.
+    pub fn placement(&self) -> Placement {
+        let alignment = if self.kernel_alignment.is_power_of_two() {
+            (self.kernel_alignment as u64).max(0x1000)
+        } else {
+            DEFAULT_ALIGNMENT
+        };
+
+        let max_address = if self.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G != 0 {
+            u64::MAX
+        } else {
+            1 << 32
+        };
+
+        Placement {
+            preferred: self.pref_address,
+            relocatable: self.relocatable,
+            alignment,
+            max_address,
+        }
+    }
+}
+
+/// Describes where the protected-mode kernel can be placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    /// The address that the kernel has been linked for, which avoids relocating it.
+    pub preferred: u64,
+    /// Whether the kernel may be placed at any other address with the required alignment.
+    pub relocatable: bool,
+    pub alignment: u64,
+    /// The highest address that the kernel may end at.
+    pub max_address: u64,
+}
+
+impl Placement {
+    /// Returns the lowest address within the free memory range from `start` to `end` that
+    /// the relocatable kernel of the provided size can be placed at.
+    pub fn fit(&self, start: u64, end: u64, size: u64) -> Option<u64> {
+        let start = start.max(MIN_LOAD_ADDRESS);
+        let address = start.checked_add(self.alignment - 1)? & !(self.alignment - 1);
+        let kernel_end = address.checked_add(size)?;
+
+        if kernel_end > end.min(self.max_address) {
+            return None;
+        }
+
+        Some(address)
+    }
+}
+
+/// A memory map entry of the zero page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct E820Entry {
+    pub address: u64,
+    pub size: u64,
+    pub kind: u32,
+}
+
+impl E820Entry {
+    /// Returns the entry in the layout used by the zero page and [`SETUP_E820_EXT`].
+    pub fn to_bytes(&self) -> [u8; E820_ENTRY_SIZE] {
+        let mut bytes = [0; E820_ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.address.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.size.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.kind.to_le_bytes());
+        bytes
+    }
+}
+
+/// Describes a linear framebuffer for the screen information of the zero page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenInfo {
+    pub address: u64,
+    pub size: u32,
+    pub width: u16,
+    pub height: u16,
+    pub bits_per_pixel: u16,
+    /// The number of bytes between the start of a line and the start of the next.
+    pub pitch: u16,
+    /// The masks of the red, green and blue color components of a pixel.
+    pub masks: (u32, u32, u32),
+}
+
+impl ScreenInfo {
+    /// Returns the size and the position of the provided color component mask.
+    fn component(mask: u32) -> [u8; 2] {
+        [mask.count_ones() as u8, mask.trailing_zeros() as u8]
+    }
+}
+
+/// Builds the zero page (`struct boot_params`) that is passed to the kernel.
+pub struct BootParams<'a> {
+    data: &'a mut [u8],
+}
+
+impl<'a> BootParams<'a> {
+    /// Clears the provided zero page and copies the setup header of the kernel into it.
+    pub fn new(data: &'a mut [u8], kernel: &[u8], header: &SetupHeader) -> Self {
+        data[..BOOT_PARAMS_SIZE].fill(0);
+        data[SETUP_SECTS..header.header_end]
+            .copy_from_slice(&kernel[SETUP_SECTS..header.header_end]);
+        data[TYPE_OF_LOADER] = LOADER_TYPE_UNDEFINED;
+
+        Self { data }
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Sets the physical address that the protected-mode kernel has been placed at.
+    pub fn set_kernel_address(&mut self, address: u64) {
+        self.write(CODE32_START, &(address as u32).to_le_bytes());
+    }
+
+    /// Sets the physical address of the NUL terminated command line.
+    pub fn set_command_line(&mut self, address: u64) {
+        self.write(CMD_LINE_PTR, &(address as u32).to_le_bytes());
+        self.write(EXT_CMD_LINE_PTR, &((address >> 32) as u32).to_le_bytes());
+    }
+
+    /// Sets the physical address and the size of the initrd.
+    pub fn set_ramdisk(&mut self, address: u64, size: u64) {
+        self.write(RAMDISK_IMAGE, &(address as u32).to_le_bytes());
+        self.write(RAMDISK_SIZE, &(size as u32).to_le_bytes());
+        self.write(EXT_RAMDISK_IMAGE, &((address >> 32) as u32).to_le_bytes());
+        self.write(EXT_RAMDISK_SIZE, &((size >> 32) as u32).to_le_bytes());
+    }
+
+    /// Sets the physical address of the RSDP, so that the kernel does not have to find it
+    /// using the EFI system table.
+    pub fn set_acpi_rsdp(&mut self, address: u64) {
+        self.write(ACPI_RSDP_ADDR, &address.to_le_bytes());
+    }
+
+    /// Fills in the EFI information block with the system table and the memory map that was
+    /// returned when exiting the boot services.
+    pub fn set_efi_info(
+        &mut self,
+        system_table: u64,
+        memory_map: u64,
+        memory_map_size: u32,
+        descriptor_size: u32,
+        descriptor_version: u32,
+    ) {
+        let fields = [
+            EFI_LOADER_SIGNATURE,
+            system_table as u32,
+            descriptor_size,
+            descriptor_version,
+            memory_map as u32,
+            memory_map_size,
+            (system_table >> 32) as u32,
+            (memory_map >> 32) as u32,
+        ];
+
+        for (i, field) in fields.iter().enumerate() {
+            self.write(EFI_INFO + i * 4, &field.to_le_bytes());
+        }
+    }
+
+    /// Describes the framebuffer in the screen information, which is used by `efifb` and
+    /// the early console.
+    pub fn set_screen_info(&mut self, info: &ScreenInfo) {
+        let (red, green, blue) = info.masks;
+        let used = if info.bits_per_pixel >= 32 {
+            u32::MAX
+        } else {
+            (1 << info.bits_per_pixel) - 1
+        };
+
+        let mut components = [0; 8];
+        components[0..2].copy_from_slice(&ScreenInfo::component(red));
+        components[2..4].copy_from_slice(&ScreenInfo::component(green));
+        components[4..6].copy_from_slice(&ScreenInfo::component(blue));
+        components[6..8].copy_from_slice(&ScreenInfo::component(used & !(red | green | blue)));
+
+        self.data[SCREEN_INFO + 0x0f] = VIDEO_TYPE_EFI;
+        self.write(SCREEN_INFO + 0x12, &info.width.to_le_bytes());
+        self.write(SCREEN_INFO + 0x14, &info.height.to_le_bytes());
+        self.write(SCREEN_INFO + 0x16, &info.bits_per_pixel.to_le_bytes());
+        self.write(SCREEN_INFO + 0x18, &(info.address as u32).to_le_bytes());
+        self.write(SCREEN_INFO + 0x1c, &info.size.to_le_bytes());
+        self.write(SCREEN_INFO + 0x24, &info.pitch.to_le_bytes());
+        self.write(SCREEN_INFO + 0x26, &components);
+        self.write(
+            SCREEN_INFO + 0x36,
+            &VIDEO_CAPABILITY_64BIT_BASE.to_le_bytes(),
+        );
+        self.write(
+            SCREEN_INFO + 0x3a,
+            &((info.address >> 32) as u32).to_le_bytes(),
+        );
+    }
+
+    /// Appends the provided entry to the memory map of the zero page. Returns false if the
+    /// memory map is full.
+    pub fn push_e820(&mut self, entry: E820Entry) -> bool {
+        let count = self.data[E820_ENTRIES] as usize;
+
+        if count == E820_MAX_ENTRIES {
+            return false;
+        }
+
+        self.write(E820_TABLE + count * E820_ENTRY_SIZE, &entry.to_bytes());
+        self.data[E820_ENTRIES] = count as u8 + 1;
+        true
+    }
+
+    /// Returns the amount of entries of the memory map in the zero page.
+    pub fn e820_entries(&self) -> usize {
+        self.data[E820_ENTRIES] as usize
+    }
+
+    /// Prepends the setup data node at the provided physical address to the setup data
+    /// chain. The node has to start with [`SETUP_DATA_HEADER_SIZE`] bytes of space for its
+    /// header, which are filled in, followed by its data.
+    pub fn link_setup_data(&mut self, node: &mut [u8], address: u64, kind: u32) {
+        let next = read_u64(self.data, SETUP_DATA);
+        let len = (node.len() - SETUP_DATA_HEADER_SIZE) as u32;
+
+        node[0..8].copy_from_slice(&next.to_le_bytes());
+        node[8..12].copy_from_slice(&kind.to_le_bytes());
+        node[12..16].copy_from_slice(&len.to_le_bytes());
+
+        self.write(SETUP_DATA, &address.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a bzImage with a setup header of the provided version and `xloadflags`.
+    fn kernel(version: u16, xloadflags: u16) -> Vec<u8> {
+        let mut kernel = vec![0; 0x3000];
+
+        kernel[SETUP_SECTS] = 3;
+        kernel[HEADER_END] = 0x6a;
+        kernel[HEADER..HEADER + 4].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        kernel[VERSION..VERSION + 2].copy_from_slice(&version.to_le_bytes());
+        kernel[XLOADFLAGS..XLOADFLAGS + 2].copy_from_slice(&xloadflags.to_le_bytes());
+        kernel[CMDLINE_SIZE..CMDLINE_SIZE + 4].copy_from_slice(&2047u32.to_le_bytes());
+        kernel[INIT_SIZE..INIT_SIZE + 4].copy_from_slice(&0x80_0000u32.to_le_bytes());
+        kernel
+    }
+
+    #[test]
+    fn setup_header_is_parsed() {
+        let header = SetupHeader::parse(&kernel(0x020f, XLF_KERNEL_64)).unwrap();
+
+        assert_eq!(header.setup_size, 0x800);
+        assert_eq!(header.header_end, 0x26c);
+        assert_eq!(header.cmdline_size, 2047);
+        assert_eq!(header.init_size, 0x80_0000);
+    }
+
+    #[test]
+    fn unsupported_kernels_are_rejected() {
+        assert_eq!(SetupHeader::parse(&[0; 0x1000]), Err(HeaderError::NotLinux));
+        assert_eq!(
+            SetupHeader::parse(&kernel(0x020b, XLF_KERNEL_64)),
+            Err(HeaderError::UnsupportedVersion)
+        );
+        assert_eq!(
+            SetupHeader::parse(&kernel(0x020f, 0)),
+            Err(HeaderError::Not64Bit)
+        );
+    }
+
+    #[test]
+    fn placement_follows_the_setup_header() {
+        let mut kernel = kernel(0x020f, XLF_KERNEL_64);
+        kernel[PREF_ADDRESS..PREF_ADDRESS + 8].copy_from_slice(&0x100_0000u64.to_le_bytes());
+        kernel[KERNEL_ALIGNMENT..KERNEL_ALIGNMENT + 4].copy_from_slice(&0x20_0000u32.to_le_bytes());
+        kernel[RELOCATABLE_KERNEL] = 1;
+
+        let placement = SetupHeader::parse(&kernel).unwrap().placement();
+
+        assert_eq!(placement.preferred, 0x100_0000);
+        assert!(placement.relocatable);
+        assert_eq!(placement.alignment, 0x20_0000);
+        assert_eq!(placement.max_address, 1 << 32);
+
+        // The kernel is placed at the next aligned address above 1 MiB that it fits at.
+        assert_eq!(placement.fit(0, 0x80_0000, 0x40_0000), Some(0x20_0000));
+        assert_eq!(
+            placement.fit(0x30_1000, 0x90_0000, 0x40_0000),
+            Some(0x40_0000)
+        );
+        assert_eq!(placement.fit(0x30_1000, 0x70_0000, 0x40_0000), None);
+        assert_eq!(placement.fit(0xffe0_0000, 0x1_8000_0000, 0x40_0000), None);
+    }
+
+    #[test]
+    fn invalid_alignments_are_replaced() {
+        let mut kernel = kernel(0x020f, XLF_KERNEL_64 | XLF_CAN_BE_LOADED_ABOVE_4G);
+        kernel[KERNEL_ALIGNMENT..KERNEL_ALIGNMENT + 4].copy_from_slice(&0x30_0000u32.to_le_bytes());
+
+        let placement = SetupHeader::parse(&kernel).unwrap().placement();
+
+        assert_eq!(placement.alignment, DEFAULT_ALIGNMENT);
+        assert_eq!(placement.max_address, u64::MAX);
+    }
+
+    #[test]
+    fn boot_params_copy_the_setup_header() {
+        let kernel = kernel(0x020f, XLF_KERNEL_64);
+        let header = SetupHeader::parse(&kernel).unwrap();
+
+        let mut data = vec![0xaa; BOOT_PARAMS_SIZE];
+        let mut params = BootParams::new(&mut data, &kernel, &header);
+        params.set_command_line(0x1_2345_6000);
+
+        assert_eq!(data[0], 0);
+        assert_eq!(read_u32(&data, HEADER), HEADER_MAGIC);
+        assert_eq!(data[TYPE_OF_LOADER], LOADER_TYPE_UNDEFINED);
+        assert_eq!(read_u32(&data, CMD_LINE_PTR), 0x2345_6000);
+        assert_eq!(read_u32(&data, EXT_CMD_LINE_PTR), 1);
+        assert_eq!(data[header.header_end], 0);
+    }
+
+    #[test]
+    fn e820_table_is_limited() {
+        let kernel = kernel(0x020f, XLF_KERNEL_64);
+        let header = SetupHeader::parse(&kernel).unwrap();
+
+        let mut data = vec![0; BOOT_PARAMS_SIZE];
+        let mut params = BootParams::new(&mut data, &kernel, &header);
+
+        let entry = E820Entry {
+            address: 0x10_0000,
+            size: 0x1000,
+            kind: E820_RAM,
+        };
+
+        for _ in 0..E820_MAX_ENTRIES {
+            assert!(params.push_e820(entry));
+        }
+
+        assert!(!params.push_e820(entry));
+        assert_eq!(params.e820_entries(), E820_MAX_ENTRIES);
+        assert_eq!(read_u64(&data, E820_TABLE), 0x10_0000);
+    }
+
+    #[test]
+    fn screen_info_describes_the_color_components() {
+        let kernel = kernel(0x020f, XLF_KERNEL_64);
+        let header = SetupHeader::parse(&kernel).unwrap();
+
+        let mut data = vec![0; BOOT_PARAMS_SIZE];
+        let mut params = BootParams::new(&mut data, &kernel, &header);
+
+        params.set_screen_info(&ScreenInfo {
+            address: 0x8000_0000,
+            size: 0x30_0000,
+            width: 1024,
+            height: 768,
+            bits_per_pixel: 32,
+            pitch: 4096,
+            masks: (0xff0000, 0x00ff00, 0x0000ff),
+        });
+
+        assert_eq!(data[0x0f], VIDEO_TYPE_EFI);
+        assert_eq!(&data[0x26..0x2e], &[8, 16, 8, 8, 8, 0, 8, 24]);
+        assert_eq!(read_u32(&data, 0x18), 0x8000_0000);
+    }
+
+    #[test]
+    fn setup_data_is_chained() {
+        let kernel = kernel(0x020f, XLF_KERNEL_64);
+        let header = SetupHeader::parse(&kernel).unwrap();
+
+        let mut data = vec![0; BOOT_PARAMS_SIZE];
+        let mut params = BootParams::new(&mut data, &kernel, &header);
+
+        let mut first = [0; SETUP_DATA_HEADER_SIZE + 4];
+        let mut second = [0; SETUP_DATA_HEADER_SIZE + 32];
+        params.link_setup_data(&mut first, 0x1000, SETUP_E820_EXT);
+        params.link_setup_data(&mut second, 0x2000, SETUP_RNG_SEED);
+
+        assert_eq!(read_u64(&data, SETUP_DATA), 0x2000);
+        assert_eq!(read_u64(&second, 0), 0x1000);
+        assert_eq!(read_u32(&second, 8), SETUP_RNG_SEED);
+        assert_eq!(read_u32(&second, 12), 32);
+        assert_eq!(read_u64(&first, 0), 0);
+    }
+}
@@ -0,0 +1,244 @@
+/// The size of a page. Usable memory regions are shrunk to page boundaries.
+pub const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub enum MemoryRegionType {
+    /// Unused conventional memory, can be used by the kernel.
+    Usable,
+    /// Memory used by Ion or the firmware during boot (e.g. page tables and the boot
+    /// information), can be reclaimed by the kernel once it no longer needs the boot
+    /// information.
+    Bootloader,
+    /// Memory containing the ACPI tables, can be reclaimed by the kernel once it has parsed
+    /// the tables.
+    AcpiReclaimable,
+    /// Memory reserved by the firmware for ACPI, has to be preserved by the kernel.
+    AcpiNvs,
+    /// Memory mapped I/O regions.
+    Mmio,
+    /// The framebuffer that was set up by Ion.
+    Framebuffer,
+    /// Memory containing the kernel image and its modules.
+    Kernel,
+    /// Memory in which errors have been detected.
+    BadMemory,
+    /// Any other UEFI memory type, should be treated as reserved by the kernel.
+    UnknownUefi(u32),
+}
+
+impl MemoryRegionType {
+    /// Returns the priority of the type when resolving overlapping memory regions. The type
+    /// with the higher priority is kept for the overlapping part, so that memory is never
+    /// reported as more usable than it is.
+    fn priority(&self) -> u8 {
+        match self {
+            Self::Usable => 0,
+            Self::Bootloader => 1,
+            Self::AcpiReclaimable => 2,
+            Self::Kernel => 3,
+            Self::Framebuffer => 4,
+            Self::Mmio => 5,
+            Self::UnknownUefi(_) => 6,
+            Self::AcpiNvs => 7,
+            Self::BadMemory => 8,
+        }
+    }
+}
+
+/// Represent a physical memory region.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// The physical start address of the region.
+    pub start: u64,
+    /// The physical end address (exclusive) of the region.
+    pub end: u64,
+    /// The memory type of the memory region.
+    pub kind: MemoryRegionType,
+}
+
+#[inline]
+fn align_down(addr: u64, align: u64) -> u64 {
+    addr & !(align - 1)
+}
+
+#[inline]
+fn align_up(addr: u64, align: u64) -> u64 {
+    align_down(addr + align - 1, align)
+}
+
+/// Sanitizes the first `len` regions of the provided memory map and returns the new number
+/// of regions. The regions are sorted by their start address, overlapping regions are
+/// resolved in favour of the more restrictive type, usable regions are shrunk to page
+/// boundaries and adjacent regions of the same type are merged.
+///
+/// Resolving overlaps might split a region, so the slice should have room for additional
+/// regions; this function panics if it runs out of room.
+pub fn sanitize_memory_map(regions: &mut [MemoryRegion], mut len: usize) -> usize {
+    fn insert(regions: &mut [MemoryRegion], len: &mut usize, region: MemoryRegion) {
+        assert!(*len < regions.len(), "mmap: memory map is too small");
+
+        let index = regions[..*len]
+            .iter()
+            .position(|r| r.start > region.start)
+            .unwrap_or(*len);
+
+        regions.copy_within(index..*len, index + 1);
+        regions[index] = region;
+        *len += 1;
+    }
+
+    fn remove(regions: &mut [MemoryRegion], len: &mut usize, index: usize) {
+        regions.copy_within(index + 1..*len, index);
+        *len -= 1;
+    }
+
+    regions[..len].sort_unstable_by_key(|region| region.start);
+
+    // Resolve the overlapping regions. As the regions are sorted, only a region and its
+    // successor have to be compared; any change is checked again in the next iteration.
+    let mut i = 0;
+
+    while i + 1 < len {
+        let (a, b) = (regions[i], regions[i + 1]);
+
+        if a.end <= a.start {
+            remove(regions, &mut len, i);
+        } else if b.start >= a.end {
+            i += 1;
+        } else if a.kind.priority() >= b.kind.priority() {
+            // Keep the first region and move the start of the second one behind it.
+            remove(regions, &mut len, i + 1);
+
+            if b.end > a.end {
+                insert(regions, &mut len, MemoryRegion { start: a.end, ..b });
+            }
+        } else {
+            // Keep the second region, cutting it out of the first one.
+            regions[i].end = b.start;
+
+            if a.end > b.end {
+                insert(regions, &mut len, MemoryRegion { start: b.end, ..a });
+            }
+        }
+    }
+
+    // Shrink the usable regions to page boundaries and drop the empty regions.
+    let mut i = 0;
+
+    while i < len {
+        let region = &mut regions[i];
+
+        if region.kind == MemoryRegionType::Usable {
+            region.start = align_up(region.start, PAGE_SIZE);
+            region.end = align_down(region.end, PAGE_SIZE);
+        }
+
+        if region.end <= region.start {
+            remove(regions, &mut len, i);
+        } else {
+            i += 1;
+        }
+    }
+
+    // Merge the adjacent regions of the same type.
+    let mut i = 0;
+
+    while i + 1 < len {
+        if regions[i].kind == regions[i + 1].kind && regions[i].end == regions[i + 1].start {
+            regions[i].end = regions[i + 1].end;
+            remove(regions, &mut len, i + 1);
+        } else {
+            i += 1;
+        }
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(start: u64, end: u64, kind: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion { start, end, kind }
+    }
+
+    fn sanitize(regions: &[MemoryRegion]) -> Vec<MemoryRegion> {
+        let mut storage = regions.to_vec();
+        storage.resize(
+            regions.len() * 2 + 1,
+            region(0, 0, MemoryRegionType::Usable),
+        );
+
+        let len = sanitize_memory_map(&mut storage, regions.len());
+        storage.truncate(len);
+        storage
+    }
+
+    #[test]
+    fn sorts_and_merges_adjacent_regions() {
+        let regions = sanitize(&[
+            region(0x3000, 0x4000, MemoryRegionType::Usable),
+            region(0x1000, 0x2000, MemoryRegionType::Usable),
+            region(0x2000, 0x3000, MemoryRegionType::Usable),
+            region(0x4000, 0x5000, MemoryRegionType::Kernel),
+        ]);
+
+        assert_eq!(
+            regions,
+            [
+                region(0x1000, 0x4000, MemoryRegionType::Usable),
+                region(0x4000, 0x5000, MemoryRegionType::Kernel),
+            ]
+        );
+    }
+
+    #[test]
+    fn overlaps_keep_the_more_restrictive_type() {
+        let regions = sanitize(&[
+            region(0x0000, 0x8000, MemoryRegionType::Usable),
+            region(0x2000, 0x3000, MemoryRegionType::AcpiNvs),
+        ]);
+
+        assert_eq!(
+            regions,
+            [
+                region(0x0000, 0x2000, MemoryRegionType::Usable),
+                region(0x2000, 0x3000, MemoryRegionType::AcpiNvs),
+                region(0x3000, 0x8000, MemoryRegionType::Usable),
+            ]
+        );
+
+        let regions = sanitize(&[
+            region(0x0000, 0x4000, MemoryRegionType::BadMemory),
+            region(0x2000, 0x6000, MemoryRegionType::Bootloader),
+        ]);
+
+        assert_eq!(
+            regions,
+            [
+                region(0x0000, 0x4000, MemoryRegionType::BadMemory),
+                region(0x4000, 0x6000, MemoryRegionType::Bootloader),
+            ]
+        );
+    }
+
+    #[test]
+    fn usable_regions_are_page_aligned() {
+        let regions = sanitize(&[
+            region(0x0800, 0x2800, MemoryRegionType::Usable),
+            region(0x3000, 0x3800, MemoryRegionType::Usable),
+            region(0x4800, 0x5000, MemoryRegionType::Mmio),
+        ]);
+
+        assert_eq!(
+            regions,
+            [
+                region(0x1000, 0x2000, MemoryRegionType::Usable),
+                region(0x4800, 0x5000, MemoryRegionType::Mmio),
+            ]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+/// The magic value at the start of the Multiboot header.
+pub const HEADER_MAGIC: u32 = 0x1badb002;
+
+/// The magic value that is passed to the kernel in EAX.
+pub const BOOTLOADER_MAGIC: u32 = 0x2badb002;
+
+/// The header has to be contained within the first 8 KiB of the kernel image.
+pub const HEADER_SEARCH_LIMIT: usize = 8192;
+
+/// The header has to be 32-bit aligned.
+const HEADER_ALIGN: usize = 4;
+
+/// The size of the header including the address fields, which are only valid if
+/// [`HEADER_AOUT_KLUDGE`] is set.
+const HEADER_SIZE: usize = 32;
+
+/// The header flag requesting the modules to be aligned on page boundaries.
+pub const HEADER_PAGE_ALIGN: u32 = 1 << 0;
+/// The header flag requesting the memory information fields.
+pub const HEADER_MEMORY_INFO: u32 = 1 << 1;
+/// The header flag requesting the video mode information fields.
+pub const HEADER_VIDEO_MODE: u32 = 1 << 2;
+/// The header flag telling that the kernel has to be loaded using the address fields of the
+/// header (the "a.out kludge") instead of its ELF program headers.
+pub const HEADER_AOUT_KLUDGE: u32 = 1 << 16;
+
+/// The lower 16 bits of the header flags are requirements, so the bootloader has to refuse
+/// kernels that set any of them that it does not understand.
+const HEADER_REQUIRED_FLAGS: u32 = 0xffff;
+const HEADER_SUPPORTED_FLAGS: u32 = HEADER_PAGE_ALIGN | HEADER_MEMORY_INFO | HEADER_VIDEO_MODE;
+
+/// The flags of the boot information telling which of its fields are valid.
+pub const INFO_MEMORY: u32 = 1 << 0;
+pub const INFO_CMDLINE: u32 = 1 << 2;
+pub const INFO_MODULES: u32 = 1 << 3;
+pub const INFO_ELF_SECTIONS: u32 = 1 << 5;
+pub const INFO_MEMORY_MAP: u32 = 1 << 6;
+pub const INFO_BOOTLOADER_NAME: u32 = 1 << 9;
+pub const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+/// The memory map entry types, which are the same for Multiboot and Multiboot2.
+pub const MEMORY_AVAILABLE: u32 = 1;
+pub const MEMORY_RESERVED: u32 = 2;
+pub const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+pub const MEMORY_NVS: u32 = 4;
+pub const MEMORY_BADRAM: u32 = 5;
+
+/// The framebuffer type of direct RGB color framebuffers.
+pub const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// The start of upper memory, which is reported by the basic memory information.
+const UPPER_MEMORY_START: u64 = 0x100000;
+
+/// The end of lower memory, as the memory from 640 KiB to 1 MiB is never usable.
+const LOWER_MEMORY_END: u64 = 0xa0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The header requires a feature that is not supported.
+    UnsupportedFlags,
+    /// The address fields of the header do not describe a part of the kernel image.
+    InvalidAddresses,
+}
+
+impl HeaderError {
+    /// Returns a description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnsupportedFlags => "multiboot header requires an unsupported feature",
+            Self::InvalidAddresses => "multiboot header address fields are inconsistent",
+        }
+    }
+}
+
+/// Reads the little endian 32-bit value at the provided offset, if it is in bounds.
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+
+    let mut value = [0; 4];
+    value.copy_from_slice(bytes);
+
+    Some(u32::from_le_bytes(value))
+}
+
+/// Describes where the kernel image is loaded if the a.out kludge is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// The offset of the loaded part in the kernel image.
+    pub file_offset: usize,
+    /// The physical address at which the loaded part is placed.
+    pub load_addr: u32,
+    /// The size of the loaded part.
+    pub file_size: usize,
+    /// The size of the zeroed memory following the loaded part.
+    pub bss_size: usize,
+    pub entry_addr: u32,
+}
+
+/// The Multiboot header of a kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    /// The offset of the header in the kernel image.
+    pub offset: usize,
+    pub flags: u32,
+    header_addr: u32,
+    load_addr: u32,
+    load_end_addr: u32,
+    bss_end_addr: u32,
+    entry_addr: u32,
+}
+
+impl Header {
+    /// Searches the first 8 KiB of the kernel image for a header with a valid checksum.
+    pub fn find(kernel: &[u8]) -> Option<Self> {
+        let limit = kernel.len().min(HEADER_SEARCH_LIMIT);
+
+        (0..limit)
+            .step_by(HEADER_ALIGN)
+            .find_map(|offset| Self::parse(kernel, offset))
+    }
+
+    fn parse(kernel: &[u8], offset: usize) -> Option<Self> {
+        if read_u32(kernel, offset)? != HEADER_MAGIC {
+            return None;
+        }
+
+        let flags = read_u32(kernel, offset + 4)?;
+        let checksum = read_u32(kernel, offset + 8)?;
+
+        if HEADER_MAGIC.wrapping_add(flags).wrapping_add(checksum) != 0 {
+            return None;
+        }
+
+        // The address fields are only present if the kernel uses the a.out kludge.
+        let field = |index: usize| {
+            if flags & HEADER_AOUT_KLUDGE != 0 && offset + HEADER_SIZE <= kernel.len() {
+                read_u32(kernel, offset + 12 + index * 4)
+            } else {
+                Some(0)
+            }
+        };
+
+        Some(Self {
+            offset,
+            flags,
+            header_addr: field(0)?,
+            load_addr: field(1)?,
+            load_end_addr: field(2)?,
+            bss_end_addr: field(3)?,
+            entry_addr: field(4)?,
+        })
+    }
+
+    /// Checks that Ion understands all of the features that the kernel requires.
+    pub fn check(&self) -> Result<(), HeaderError> {
+        if self.flags & HEADER_REQUIRED_FLAGS & !HEADER_SUPPORTED_FLAGS != 0 {
+            return Err(HeaderError::UnsupportedFlags);
+        }
+
+        Ok(())
+    }
+
+    /// Returns where the kernel image has to be loaded if it uses the a.out kludge, or
+    /// [`None`] if it has to be loaded using its ELF program headers.
+    pub fn aout_layout(&self, kernel_len: usize) -> Result<Option<Layout>, HeaderError> {
+        if self.flags & HEADER_AOUT_KLUDGE == 0 {
+            return Ok(None);
+        }
+
+        if self.offset + HEADER_SIZE > kernel_len {
+            return Err(HeaderError::InvalidAddresses);
+        }
+
+        // The header address is the address at which the header itself is loaded, which
+        // tells how far into the image the loaded part starts.
+        let header_offset = self
+            .header_addr
+            .checked_sub(self.load_addr)
+            .ok_or(HeaderError::InvalidAddresses)? as usize;
+
+        let file_offset = self
+            .offset
+            .checked_sub(header_offset)
+            .ok_or(HeaderError::InvalidAddresses)?;
+
+        let file_size = if self.load_end_addr == 0 {
+            kernel_len - file_offset
+        } else {
+            self.load_end_addr
+                .checked_sub(self.load_addr)
+                .ok_or(HeaderError::InvalidAddresses)? as usize
+        };
+
+        if file_offset + file_size > kernel_len {
+            return Err(HeaderError::InvalidAddresses);
+        }
+
+        let load_end = self.load_addr as u64 + file_size as u64;
+
+        let bss_size = if self.bss_end_addr == 0 {
+            0
+        } else {
+            (self.bss_end_addr as u64)
+                .checked_sub(load_end)
+                .ok_or(HeaderError::InvalidAddresses)? as usize
+        };
+
+        Ok(Some(Layout {
+            file_offset,
+            load_addr: self.load_addr,
+            file_size,
+            bss_size,
+            entry_addr: self.entry_addr,
+        }))
+    }
+}
+
+/// Returns the amount of lower and upper memory in KiB for the basic memory information
+/// tag. Lower memory starts at address 0 and upper memory at 1 MiB, both end at the first
+/// hole. The available regions have to be sorted by their start address.
+pub fn basic_memory_info(available: impl IntoIterator<Item = (u64, u64)>) -> (u32, u32) {
+    let mut lower_end = 0;
+    let mut upper_end = UPPER_MEMORY_START;
+
+    for (start, end) in available {
+        if start <= lower_end && end > lower_end {
+            lower_end = end;
+        }
+
+        if start <= upper_end && end > upper_end {
+            upper_end = end;
+        }
+    }
+
+    let lower = lower_end.min(LOWER_MEMORY_END) / 1024;
+    let upper = ((upper_end - UPPER_MEMORY_START) / 1024).min(u32::MAX as u64);
+
+    (lower as u32, upper as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a kernel image with a header with the provided flags and address fields at
+    /// the provided offset.
+    fn kernel(offset: usize, flags: u32, addresses: [u32; 5]) -> Vec<u8> {
+        let mut kernel = vec![0; offset];
+        kernel.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+        kernel.extend_from_slice(&flags.to_le_bytes());
+        kernel.extend_from_slice(
+            &0u32
+                .wrapping_sub(HEADER_MAGIC)
+                .wrapping_sub(flags)
+                .to_le_bytes(),
+        );
+
+        for address in addresses {
+            kernel.extend_from_slice(&address.to_le_bytes());
+        }
+
+        kernel.resize(0x2000, 0);
+        kernel
+    }
+
+    #[test]
+    fn header_flags_are_checked() {
+        let flags = HEADER_PAGE_ALIGN | HEADER_MEMORY_INFO;
+        let header = Header::find(&kernel(0x40, flags, [0; 5])).unwrap();
+
+        assert_eq!(header.offset, 0x40);
+        assert_eq!(header.check(), Ok(()));
+        assert_eq!(header.aout_layout(0x2000), Ok(None));
+
+        // Bit 3 is a requirement that Ion does not understand, while the upper bits are
+        // optional.
+        let header = Header::find(&kernel(0, 1 << 3, [0; 5])).unwrap();
+        assert_eq!(header.check(), Err(HeaderError::UnsupportedFlags));
+
+        let header = Header::find(&kernel(0, 1 << 20, [0; 5])).unwrap();
+        assert_eq!(header.check(), Ok(()));
+    }
+
+    #[test]
+    fn invalid_headers_are_skipped() {
+        let mut corrupted = kernel(0, 0, [0; 5]);
+        corrupted[8] ^= 1;
+        assert!(Header::find(&corrupted).is_none());
+
+        assert!(Header::find(&kernel(2, 0, [0; 5])).is_none());
+    }
+
+    #[test]
+    fn aout_kludge_layout() {
+        // The header is at offset 0x40 of the image, which is loaded at 1 MiB.
+        let addresses = [0x100040, 0x100000, 0x101000, 0x104000, 0x10000c];
+        let header = Header::find(&kernel(0x40, HEADER_AOUT_KLUDGE, addresses)).unwrap();
+
+        assert_eq!(
+            header.aout_layout(0x2000),
+            Ok(Some(Layout {
+                file_offset: 0,
+                load_addr: 0x100000,
+                file_size: 0x1000,
+                bss_size: 0x3000,
+                entry_addr: 0x10000c,
+            }))
+        );
+
+        // A load end address of zero loads the rest of the image.
+        let addresses = [0x100040, 0x100000, 0, 0, 0x10000c];
+        let header = Header::find(&kernel(0x40, HEADER_AOUT_KLUDGE, addresses)).unwrap();
+        assert_eq!(
+            header.aout_layout(0x2000).unwrap().unwrap().file_size,
+            0x2000
+        );
+
+        // The header cannot be loaded before the start of the image.
+        let addresses = [0x100000, 0x100040, 0, 0, 0x10000c];
+        let header = Header::find(&kernel(0x40, HEADER_AOUT_KLUDGE, addresses)).unwrap();
+        assert_eq!(
+            header.aout_layout(0x2000),
+            Err(HeaderError::InvalidAddresses)
+        );
+    }
+
+    #[test]
+    fn basic_memory_info_stops_at_holes() {
+        let available = [
+            (0x1000, 0x9f000),
+            (0x100000, 0x200000),
+            (0x200000, 0x300000),
+            (0x400000, 0x800000),
+        ];
+
+        // The zero page is not reported as available, so lower memory is empty.
+        assert_eq!(basic_memory_info(available.iter().copied()), (0, 2048));
+        assert_eq!(basic_memory_info([(0, 0xa0000)]), (640, 0));
+    }
+}
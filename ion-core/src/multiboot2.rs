@@ -0,0 +1,399 @@
+use crate::multiboot::read_u32;
+
+/// The magic value at the start of the Multiboot2 header.
+pub const HEADER_MAGIC: u32 = 0xe85250d6;
+
+/// The magic value that is passed to the kernel in EAX.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d76289;
+
+/// The header has to be contained within the first 32 KiB of the kernel image.
+pub const HEADER_SEARCH_LIMIT: usize = 32768;
+
+/// The header has to be 64-bit aligned.
+const HEADER_ALIGN: usize = 8;
+
+/// The size of the fixed part of the header (the magic value, the architecture, the header
+/// length and the checksum).
+const HEADER_SIZE: usize = 16;
+
+/// The architecture field of the header for 32-bit protected mode i386.
+pub const ARCHITECTURE_I386: u32 = 0;
+
+/// The header tag types defined by the Multiboot2 specification.
+pub const HEADER_TAG_END: u16 = 0;
+pub const HEADER_TAG_INFORMATION_REQUEST: u16 = 1;
+pub const HEADER_TAG_CONSOLE_FLAGS: u16 = 4;
+pub const HEADER_TAG_FRAMEBUFFER: u16 = 5;
+pub const HEADER_TAG_MODULE_ALIGN: u16 = 6;
+
+/// The flag of a header tag that allows the bootloader to ignore it.
+const HEADER_TAG_OPTIONAL: u16 = 1 << 0;
+
+/// The boot information tag types defined by the Multiboot2 specification.
+pub const TAG_END: u32 = 0;
+pub const TAG_CMDLINE: u32 = 1;
+pub const TAG_BOOTLOADER_NAME: u32 = 2;
+pub const TAG_MODULE: u32 = 3;
+pub const TAG_BASIC_MEMINFO: u32 = 4;
+pub const TAG_MEMORY_MAP: u32 = 6;
+pub const TAG_FRAMEBUFFER: u32 = 8;
+pub const TAG_EFI64: u32 = 12;
+pub const TAG_ACPI_OLD: u32 = 14;
+pub const TAG_ACPI_NEW: u32 = 15;
+pub const TAG_EFI_MEMORY_MAP: u32 = 17;
+pub const TAG_EFI64_IMAGE_HANDLE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The header is not for the i386 architecture.
+    UnsupportedArchitecture,
+    /// The header contains a tag that has to be honored but is not supported.
+    UnsupportedTag,
+    /// The kernel requires an information tag that cannot be provided.
+    UnsupportedRequest,
+}
+
+impl HeaderError {
+    /// Returns a description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnsupportedArchitecture => "multiboot2 header is not for the i386 architecture",
+            Self::UnsupportedTag => "multiboot2 header requires an unsupported tag",
+            Self::UnsupportedRequest => "multiboot2 kernel requires an unsupported information tag",
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// A tag of the Multiboot2 header.
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderTag<'a> {
+    pub kind: u16,
+    /// Whether the bootloader is allowed to ignore the tag.
+    pub optional: bool,
+    /// The contents of the tag following the type, the flags and the size.
+    pub data: &'a [u8],
+}
+
+/// The Multiboot2 header of a kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Header<'a> {
+    /// The offset of the header in the kernel image.
+    pub offset: usize,
+    pub architecture: u32,
+    /// The tags following the fixed part of the header.
+    tags: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    /// Searches the first 32 KiB of the kernel image for a header with a valid checksum.
+    pub fn find(kernel: &'a [u8]) -> Option<Self> {
+        let limit = kernel.len().min(HEADER_SEARCH_LIMIT);
+
+        (0..limit)
+            .step_by(HEADER_ALIGN)
+            .find_map(|offset| Self::parse(kernel, offset))
+    }
+
+    fn parse(kernel: &'a [u8], offset: usize) -> Option<Self> {
+        if read_u32(kernel, offset)? != HEADER_MAGIC {
+            return None;
+        }
+
+        let architecture = read_u32(kernel, offset + 4)?;
+        let length = read_u32(kernel, offset + 8)?;
+        let checksum = read_u32(kernel, offset + 12)?;
+
+        let sum = HEADER_MAGIC
+            .wrapping_add(architecture)
+            .wrapping_add(length)
+            .wrapping_add(checksum);
+
+        if sum != 0 || (length as usize) < HEADER_SIZE {
+            return None;
+        }
+
+        let tags = kernel.get(offset + HEADER_SIZE..offset.checked_add(length as usize)?)?;
+
+        Some(Self {
+            offset,
+            architecture,
+            tags,
+        })
+    }
+
+    /// Returns the tags of the header up to the end tag. A malformed tag ends the list.
+    pub fn tags(&self) -> impl Iterator<Item = HeaderTag<'a>> {
+        let tags = self.tags;
+        let mut offset = 0;
+
+        core::iter::from_fn(move || {
+            let kind = read_u16(tags, offset)?;
+            let flags = read_u16(tags, offset + 2)?;
+            let size = read_u32(tags, offset + 4)? as usize;
+
+            if kind == HEADER_TAG_END || size < 8 {
+                return None;
+            }
+
+            let data = tags.get(offset + 8..offset.checked_add(size)?)?;
+
+            // The tags are padded to 8 bytes.
+            offset += (size + 7) & !7;
+
+            Some(HeaderTag {
+                kind,
+                optional: flags & HEADER_TAG_OPTIONAL != 0,
+                data,
+            })
+        })
+    }
+
+    /// Returns the information tags requested by the kernel, along with true if the kernel
+    /// is able to boot without them.
+    pub fn requested_tags(&self) -> impl Iterator<Item = (u32, bool)> + 'a {
+        self.tags()
+            .filter(|tag| tag.kind == HEADER_TAG_INFORMATION_REQUEST)
+            .flat_map(|tag| {
+                tag.data
+                    .chunks_exact(4)
+                    .map(move |kind| (read_u32(kind, 0).unwrap(), tag.optional))
+            })
+    }
+
+    /// Checks that the kernel can be booted by a bootloader that provides the information
+    /// tags for which `provided` returns true. Tags that are not known to Ion have to be
+    /// marked as optional.
+    pub fn check(&self, provided: impl Fn(u32) -> bool) -> Result<(), HeaderError> {
+        if self.architecture != ARCHITECTURE_I386 {
+            return Err(HeaderError::UnsupportedArchitecture);
+        }
+
+        let unsupported = self.tags().any(|tag| match tag.kind {
+            HEADER_TAG_INFORMATION_REQUEST
+            | HEADER_TAG_CONSOLE_FLAGS
+            | HEADER_TAG_FRAMEBUFFER
+            | HEADER_TAG_MODULE_ALIGN => false,
+            _ => !tag.optional,
+        });
+
+        if unsupported {
+            return Err(HeaderError::UnsupportedTag);
+        }
+
+        if self
+            .requested_tags()
+            .any(|(kind, optional)| !optional && !provided(kind))
+        {
+            return Err(HeaderError::UnsupportedRequest);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the boot information that is passed to the kernel into a buffer. The buffer
+/// starts with the total size and a reserved field, followed by the tags, which are each
+/// padded to 8 bytes.
+pub struct InfoWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    /// The offset of the tag that is being written.
+    tag_start: usize,
+}
+
+impl<'a> InfoWriter<'a> {
+    /// Creates a writer for the provided buffer, which has to be 8-byte aligned.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        assert!(
+            buffer.len() >= 8,
+            "multiboot2: boot information is too small"
+        );
+
+        buffer[..8].fill(0);
+
+        Self {
+            buffer,
+            len: 8,
+            tag_start: 8,
+        }
+    }
+
+    /// Appends the provided bytes to the tag that is being written.
+    pub fn write(&mut self, bytes: &[u8]) {
+        let end = self.len + bytes.len();
+
+        assert!(
+            end <= self.buffer.len(),
+            "multiboot2: boot information is too small"
+        );
+
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+
+    /// Starts a tag of the provided type. Its contents are appended with [`Self::write`].
+    pub fn start_tag(&mut self, kind: u32) {
+        self.tag_start = self.len;
+
+        self.write(&kind.to_le_bytes());
+        self.write(&0u32.to_le_bytes());
+    }
+
+    /// Fills in the size of the tag that is being written and pads it to 8 bytes.
+    pub fn end_tag(&mut self) {
+        let size = (self.len - self.tag_start) as u32;
+        self.buffer[self.tag_start + 4..self.tag_start + 8].copy_from_slice(&size.to_le_bytes());
+
+        let padded = (self.len + 7) & !7;
+        self.write(&[0; 7][..padded - self.len]);
+    }
+
+    /// Appends a tag whose contents are the concatenation of the provided parts.
+    pub fn tag(&mut self, kind: u32, parts: &[&[u8]]) {
+        self.start_tag(kind);
+
+        for part in parts {
+            self.write(part);
+        }
+
+        self.end_tag();
+    }
+
+    /// Appends the end tag and fills in the total size, which is returned.
+    pub fn finish(mut self) -> usize {
+        self.tag(TAG_END, &[]);
+
+        let total_size = self.len as u32;
+        self.buffer[..4].copy_from_slice(&total_size.to_le_bytes());
+
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a kernel image with a header containing the provided tags at the provided
+    /// offset.
+    fn kernel(offset: usize, architecture: u32, tags: &[&[u8]]) -> Vec<u8> {
+        let mut tag_bytes = tags.concat();
+        tag_bytes.extend_from_slice(&[0, 0, 0, 0, 8, 0, 0, 0]);
+
+        let length = (HEADER_SIZE + tag_bytes.len()) as u32;
+        let checksum = 0u32
+            .wrapping_sub(HEADER_MAGIC)
+            .wrapping_sub(architecture)
+            .wrapping_sub(length);
+
+        let mut kernel = vec![0; offset];
+        kernel.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+        kernel.extend_from_slice(&architecture.to_le_bytes());
+        kernel.extend_from_slice(&length.to_le_bytes());
+        kernel.extend_from_slice(&checksum.to_le_bytes());
+        kernel.extend_from_slice(&tag_bytes);
+        kernel
+    }
+
+    fn information_request(optional: bool, kinds: &[u32]) -> Vec<u8> {
+        let mut tag = vec![1, 0, optional as u8, 0];
+        tag.extend_from_slice(&((8 + kinds.len() * 4) as u32).to_le_bytes());
+
+        for kind in kinds {
+            tag.extend_from_slice(&kind.to_le_bytes());
+        }
+
+        tag.resize((tag.len() + 7) & !7, 0);
+        tag
+    }
+
+    #[test]
+    fn header_is_found() {
+        let kernel = kernel(0x1000, ARCHITECTURE_I386, &[]);
+        let header = Header::find(&kernel).unwrap();
+
+        assert_eq!(header.offset, 0x1000);
+        assert_eq!(header.tags().count(), 0);
+        assert_eq!(header.check(|_| false), Ok(()));
+    }
+
+    #[test]
+    fn invalid_headers_are_skipped() {
+        let mut corrupted = kernel(0, ARCHITECTURE_I386, &[]);
+        corrupted[12] ^= 1;
+        assert!(Header::find(&corrupted).is_none());
+
+        // The header has to be 64-bit aligned and within the first 32 KiB.
+        assert!(Header::find(&kernel(4, ARCHITECTURE_I386, &[])).is_none());
+        assert!(Header::find(&kernel(HEADER_SEARCH_LIMIT, ARCHITECTURE_I386, &[])).is_none());
+    }
+
+    #[test]
+    fn requested_tags_are_checked() {
+        let required = information_request(false, &[TAG_CMDLINE, TAG_FRAMEBUFFER]);
+        let kernel = kernel(0, ARCHITECTURE_I386, &[&required]);
+        let header = Header::find(&kernel).unwrap();
+
+        assert_eq!(
+            header.requested_tags().collect::<Vec<_>>(),
+            [(TAG_CMDLINE, false), (TAG_FRAMEBUFFER, false)]
+        );
+        assert_eq!(
+            header.check(|kind| kind == TAG_CMDLINE || kind == TAG_FRAMEBUFFER),
+            Ok(())
+        );
+        assert_eq!(
+            header.check(|kind| kind == TAG_CMDLINE),
+            Err(HeaderError::UnsupportedRequest)
+        );
+
+        let optional = information_request(true, &[TAG_FRAMEBUFFER]);
+        let kernel = self::kernel(0, ARCHITECTURE_I386, &[&optional]);
+        assert_eq!(Header::find(&kernel).unwrap().check(|_| false), Ok(()));
+    }
+
+    #[test]
+    fn unsupported_tags_are_rejected() {
+        // The EFI boot services tag asks the bootloader not to exit the boot services.
+        let required = [7, 0, 0, 0, 8, 0, 0, 0];
+        let optional = [7, 0, 1, 0, 8, 0, 0, 0];
+
+        let kernel = kernel(0, ARCHITECTURE_I386, &[&required]);
+        assert_eq!(
+            Header::find(&kernel).unwrap().check(|_| true),
+            Err(HeaderError::UnsupportedTag)
+        );
+
+        let kernel = self::kernel(0, ARCHITECTURE_I386, &[&optional]);
+        assert_eq!(Header::find(&kernel).unwrap().check(|_| true), Ok(()));
+
+        let kernel = self::kernel(0, 4, &[]);
+        assert_eq!(
+            Header::find(&kernel).unwrap().check(|_| true),
+            Err(HeaderError::UnsupportedArchitecture)
+        );
+    }
+
+    #[test]
+    fn info_writer_pads_tags() {
+        let mut buffer = [0xff; 64];
+        let mut writer = InfoWriter::new(&mut buffer);
+
+        writer.tag(TAG_BOOTLOADER_NAME, &[b"Ion\0"]);
+        let len = writer.finish();
+
+        assert_eq!(len, 32);
+        assert_eq!(read_u32(&buffer, 0), Some(32));
+        assert_eq!(read_u32(&buffer, 8), Some(TAG_BOOTLOADER_NAME));
+        assert_eq!(read_u32(&buffer, 12), Some(12));
+        assert_eq!(&buffer[16..20], b"Ion\0");
+        assert_eq!(&buffer[20..24], [0; 4]);
+        assert_eq!(read_u32(&buffer, 24), Some(TAG_END));
+        assert_eq!(read_u32(&buffer, 28), Some(8));
+    }
+}
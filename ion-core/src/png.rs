@@ -0,0 +1,267 @@
+// A decoder for PNG images, e.g. the icons of the menu entries. Only images with 8 bits per
+// sample that are not interlaced are supported, which is what image editors produce by
+// default.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use crate::inflate;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+const FILTER_NONE: u8 = 0;
+const FILTER_SUB: u8 = 1;
+const FILTER_UP: u8 = 2;
+const FILTER_AVERAGE: u8 = 3;
+const FILTER_PAETH: u8 = 4;
+
+/// The maximum width and height of an image in pixels, which keeps malformed images from
+/// exhausting the memory.
+const MAX_DIMENSION: usize = 4096;
+
+/// A decoded PNG image.
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// The pixels as `0xAARRGGBB`, row by row.
+    pixels: Vec<u32>,
+}
+
+impl Image {
+    /// Decodes the provided PNG file. Returns [`None`] if the file is not a valid PNG image
+    /// or uses an unsupported format.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if !data.starts_with(&SIGNATURE) {
+            return None;
+        }
+
+        let mut remaining = &data[SIGNATURE.len()..];
+
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut transparency: &[u8] = &[];
+        let mut compressed = Vec::new();
+
+        // Each chunk consists of its length, its type, its data and a CRC.
+        while remaining.len() >= 12 {
+            let len = u32::from_be_bytes(remaining[..4].try_into().ok()?) as usize;
+            let kind = &remaining[4..8];
+            let chunk = remaining.get(8..8 + len)?;
+
+            match kind {
+                b"IHDR" => header = Some(Header::parse(chunk)?),
+                b"PLTE" => palette = chunk,
+                b"tRNS" => transparency = chunk,
+                b"IDAT" => compressed.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => (),
+            }
+
+            remaining = remaining.get(12 + len..)?;
+        }
+
+        let header = header?;
+        let data = inflate::zlib_decompress(&compressed)?;
+
+        let channels = header.channels();
+        let stride = header.width * channels;
+
+        if data.len() < (stride + 1) * header.height {
+            return None;
+        }
+
+        let mut samples = Vec::with_capacity(stride * header.height);
+
+        for (y, row) in data
+            .chunks_exact(stride + 1)
+            .take(header.height)
+            .enumerate()
+        {
+            let start = samples.len();
+            samples.extend_from_slice(&row[1..]);
+
+            let (previous, current) = samples.split_at_mut(start);
+            let above = if y == 0 {
+                None
+            } else {
+                Some(&previous[start - stride..])
+            };
+
+            unfilter(row[0], current, above, channels)?;
+        }
+
+        let pixels = samples
+            .chunks_exact(channels)
+            .map(|sample| header.pixel(sample, palette, transparency))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            width: header.width,
+            height: header.height,
+            pixels,
+        })
+    }
+
+    /// Returns the width of the image in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the image in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the color of the pixel at the provided position as `0xAARRGGBB`.
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// The contents of the `IHDR` chunk that Ion uses.
+struct Header {
+    width: usize,
+    height: usize,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(chunk: &[u8]) -> Option<Self> {
+        if chunk.len() < 13 {
+            return None;
+        }
+
+        let width = u32::from_be_bytes(chunk[0..4].try_into().ok()?) as usize;
+        let height = u32::from_be_bytes(chunk[4..8].try_into().ok()?) as usize;
+        let (bit_depth, color_type, interlace) = (chunk[8], chunk[9], chunk[12]);
+
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return None;
+        }
+
+        // Both the compression and the filter method only have one defined value.
+        if bit_depth != 8 || chunk[10] != 0 || chunk[11] != 0 || interlace != 0 {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            color_type,
+        })
+    }
+
+    /// Returns the amount of samples of each pixel.
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGB => 3,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Returns the color of the pixel with the provided samples as `0xAARRGGBB`.
+    fn pixel(&self, sample: &[u8], palette: &[u8], transparency: &[u8]) -> Option<u32> {
+        let (red, green, blue, alpha) = match self.color_type {
+            COLOR_GRAY => (sample[0], sample[0], sample[0], 0xff),
+            COLOR_GRAY_ALPHA => (sample[0], sample[0], sample[0], sample[1]),
+            COLOR_RGB => (sample[0], sample[1], sample[2], 0xff),
+            COLOR_RGBA => (sample[0], sample[1], sample[2], sample[3]),
+
+            COLOR_PALETTE => {
+                let index = sample[0] as usize;
+                let color = palette.get(index * 3..index * 3 + 3)?;
+                let alpha = transparency.get(index).copied().unwrap_or(0xff);
+
+                (color[0], color[1], color[2], alpha)
+            }
+
+            _ => return None,
+        };
+
+        Some(u32::from_be_bytes([alpha, red, green, blue]))
+    }
+}
+
+/// Returns the sample that the Paeth filter predicts from the samples to the left, above
+/// and to the upper left.
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - upper_left as i16;
+
+    let distance_left = (estimate - left as i16).abs();
+    let distance_above = (estimate - above as i16).abs();
+    let distance_upper_left = (estimate - upper_left as i16).abs();
+
+    if distance_left <= distance_above && distance_left <= distance_upper_left {
+        left
+    } else if distance_above <= distance_upper_left {
+        above
+    } else {
+        upper_left
+    }
+}
+
+/// Reverses the filter of a row in place. `above` is the previous row, which has already
+/// been unfiltered.
+fn unfilter(filter: u8, row: &mut [u8], above: Option<&[u8]>, channels: usize) -> Option<()> {
+    let above_at = |i: usize| above.map_or(0, |above| above[i]);
+
+    for i in 0..row.len() {
+        let left = if i >= channels { row[i - channels] } else { 0 };
+        let upper_left = if i >= channels {
+            above_at(i - channels)
+        } else {
+            0
+        };
+
+        let prediction = match filter {
+            FILTER_NONE => 0,
+            FILTER_SUB => left,
+            FILTER_UP => above_at(i),
+            FILTER_AVERAGE => ((left as u16 + above_at(i) as u16) / 2) as u8,
+            FILTER_PAETH => paeth(left, above_at(i), upper_left),
+            _ => return None,
+        };
+
+        row[i] = row[i].wrapping_add(prediction);
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding() {
+        // A 2x2 RGBA image with a red, a green, a blue and a transparent pixel, whose rows
+        // use the Sub and the Paeth filter.
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00,
+            0x00, 0x72, 0xb6, 0x0d, 0x24, 0x00, 0x00, 0x00, 0x17, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0x9c, 0x63, 0xfc, 0xcf, 0xc0, 0xf0, 0x9f, 0x11, 0x48, 0xb0, 0x30, 0x32, 0x00, 0x49,
+            0x46, 0x46, 0x46, 0x00, 0x31, 0x32, 0x04, 0x07, 0x6e, 0xe4, 0x7a, 0x4d, 0x00, 0x00,
+            0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+
+        let image = Image::decode(&data).unwrap();
+
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.pixel(0, 0), 0xffff0000);
+        assert_eq!(image.pixel(1, 0), 0xff00ff00);
+        assert_eq!(image.pixel(0, 1), 0xff0000ff);
+        assert_eq!(image.pixel(1, 1), 0x00000000);
+
+        assert!(Image::decode(&data[..data.len() / 2]).is_none());
+        assert!(Image::decode(b"BM").is_none());
+    }
+}
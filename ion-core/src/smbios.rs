@@ -0,0 +1,245 @@
+// Parsing of the SMBIOS tables, which describe the machine and its firmware. Ion only uses
+// the BIOS information (type 0) and the system information (type 1) structures.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const ENTRY_POINT_SIGNATURE: &[u8] = b"_SM_";
+const ENTRY_POINT_3_SIGNATURE: &[u8] = b"_SM3_";
+
+const TYPE_BIOS_INFORMATION: u8 = 0;
+const TYPE_SYSTEM_INFORMATION: u8 = 1;
+const TYPE_END_OF_TABLE: u8 = 127;
+
+/// The size of the header of every structure: its type, its length and its handle.
+const STRUCTURE_HEADER_SIZE: usize = 4;
+
+/// The location of the structure table, as described by an entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub table_address: u64,
+    /// The size of the table in bytes. The 64-bit entry point only provides the maximum
+    /// size, so the table might end earlier.
+    pub table_len: usize,
+}
+
+impl EntryPoint {
+    /// Parses the 32-bit (`_SM_`) or the 64-bit (`_SM3_`) entry point.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.starts_with(ENTRY_POINT_3_SIGNATURE) {
+            let mut table_len = [0; 4];
+            table_len.copy_from_slice(data.get(12..16)?);
+
+            let mut address = [0; 8];
+            address.copy_from_slice(data.get(16..24)?);
+
+            Some(Self {
+                table_address: u64::from_le_bytes(address),
+                table_len: u32::from_le_bytes(table_len) as usize,
+            })
+        } else if data.starts_with(ENTRY_POINT_SIGNATURE) {
+            let table_len = u16::from_le_bytes([*data.get(0x16)?, *data.get(0x17)?]);
+            let mut address = [0; 4];
+            address.copy_from_slice(data.get(0x18..0x1c)?);
+
+            Some(Self {
+                table_address: u32::from_le_bytes(address) as u64,
+                table_len: table_len as usize,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the size of the entry point that starts with the provided signature, which
+    /// has to be read before it can be parsed.
+    pub fn size(data: &[u8]) -> Option<usize> {
+        if data.starts_with(ENTRY_POINT_3_SIGNATURE) {
+            Some(24)
+        } else if data.starts_with(ENTRY_POINT_SIGNATURE) {
+            Some(0x1f)
+        } else {
+            None
+        }
+    }
+}
+
+/// A structure of the table along with the strings that follow it.
+struct Structure<'a> {
+    ty: u8,
+    formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    /// Returns the string referenced by the byte at the provided offset of the formatted
+    /// area. The strings are numbered from 1 and 0 means that there is no string.
+    fn string(&self, offset: usize) -> Option<&'a str> {
+        let index = *self.formatted.get(offset)? as usize;
+
+        if index == 0 {
+            return None;
+        }
+
+        let string = self.strings.split(|&byte| byte == 0).nth(index - 1)?;
+        let string = core::str::from_utf8(string).ok()?.trim();
+
+        if string.is_empty() {
+            None
+        } else {
+            Some(string)
+        }
+    }
+}
+
+/// Returns an iterator over the structures of the provided table.
+fn structures(mut table: &[u8]) -> impl Iterator<Item = Structure<'_>> {
+    core::iter::from_fn(move || {
+        let len = *table.get(1)? as usize;
+
+        if len < STRUCTURE_HEADER_SIZE || table[0] == TYPE_END_OF_TABLE {
+            return None;
+        }
+
+        let formatted = table.get(..len)?;
+
+        // The strings are terminated by a double NUL, which is also present if the
+        // structure does not have any strings.
+        let remaining = &table[len..];
+        let end = remaining.windows(2).position(|pair| pair == [0, 0])?;
+
+        let structure = Structure {
+            ty: formatted[0],
+            formatted,
+            strings: &remaining[..end],
+        };
+
+        table = &remaining[end + 2..];
+        Some(structure)
+    })
+}
+
+/// The identification of the machine and its firmware.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary<'a> {
+    pub bios_vendor: Option<&'a str>,
+    pub bios_version: Option<&'a str>,
+    pub bios_date: Option<&'a str>,
+    pub manufacturer: Option<&'a str>,
+    pub product: Option<&'a str>,
+    pub version: Option<&'a str>,
+}
+
+impl<'a> Summary<'a> {
+    /// Collects the BIOS and the system information from the provided structure table.
+    pub fn parse(table: &'a [u8]) -> Self {
+        let mut summary = Self::default();
+
+        for structure in structures(table) {
+            match structure.ty {
+                TYPE_BIOS_INFORMATION => {
+                    summary.bios_vendor = structure.string(4);
+                    summary.bios_version = structure.string(5);
+                    summary.bios_date = structure.string(8);
+                }
+
+                TYPE_SYSTEM_INFORMATION => {
+                    summary.manufacturer = structure.string(4);
+                    summary.product = structure.string(5);
+                    summary.version = structure.string(6);
+                }
+
+                _ => (),
+            }
+        }
+
+        summary
+    }
+
+    /// Returns a single line describing the machine and its firmware (e.g. `LENOVO
+    /// 20KH006MGE, firmware N23ET75W (1.50) 10/13/2020`). Returns [`None`] if the table does
+    /// not identify the machine.
+    pub fn line(&self) -> Option<String> {
+        let join = |parts: &[Option<&str>]| -> String {
+            let parts = parts.iter().flatten().copied().collect::<Vec<_>>();
+            parts.join(" ")
+        };
+
+        let machine = join(&[self.manufacturer, self.product, self.version]);
+        let firmware = join(&[self.bios_vendor, self.bios_version, self.bios_date]);
+
+        match (machine.is_empty(), firmware.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(machine),
+            (true, false) => Some(alloc::format!("firmware {}", firmware)),
+            (false, false) => Some(alloc::format!("{}, firmware {}", machine, firmware)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_points() {
+        let mut entry_point = [0u8; 0x1f];
+        entry_point[..4].copy_from_slice(b"_SM_");
+        entry_point[0x16..0x18].copy_from_slice(&0x1234u16.to_le_bytes());
+        entry_point[0x18..0x1c].copy_from_slice(&0xf0000u32.to_le_bytes());
+
+        assert_eq!(EntryPoint::size(&entry_point), Some(0x1f));
+        assert_eq!(
+            EntryPoint::parse(&entry_point),
+            Some(EntryPoint {
+                table_address: 0xf0000,
+                table_len: 0x1234,
+            })
+        );
+
+        let mut entry_point = [0u8; 24];
+        entry_point[..5].copy_from_slice(b"_SM3_");
+        entry_point[12..16].copy_from_slice(&0x2000u32.to_le_bytes());
+        entry_point[16..24].copy_from_slice(&0x7f00_0000u64.to_le_bytes());
+
+        assert_eq!(
+            EntryPoint::parse(&entry_point),
+            Some(EntryPoint {
+                table_address: 0x7f00_0000,
+                table_len: 0x2000,
+            })
+        );
+
+        assert_eq!(EntryPoint::parse(b"RSD PTR "), None);
+    }
+
+    #[test]
+    fn summary() {
+        let mut table = Vec::new();
+
+        // BIOS information with the vendor, the version and the release date.
+        table.extend_from_slice(&[0, 0x12, 0, 0, 1, 2, 0, 0, 3]);
+        table.extend_from_slice(&[0; 0x12 - 9]);
+        table.extend_from_slice(b"LENOVO\0N23ET75W (1.50)\x0010/13/2020\0\0");
+
+        // A structure without strings.
+        table.extend_from_slice(&[32, 0x0b, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // System information without a version.
+        table.extend_from_slice(&[1, 0x08, 3, 0, 1, 2, 0, 0]);
+        table.extend_from_slice(b"LENOVO\x0020KH006MGE\0\0");
+
+        table.extend_from_slice(&[127, 4, 4, 0, 0, 0]);
+
+        let summary = Summary::parse(&table);
+
+        assert_eq!(summary.product, Some("20KH006MGE"));
+        assert_eq!(summary.version, None);
+        assert_eq!(
+            summary.line().as_deref(),
+            Some("LENOVO 20KH006MGE, firmware LENOVO N23ET75W (1.50) 10/13/2020")
+        );
+
+        assert_eq!(Summary::parse(&[]).line(), None);
+    }
+}
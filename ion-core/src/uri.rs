@@ -0,0 +1,320 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::devpath;
+
+pub struct Uri {
+    resource: String,
+    partition: Option<usize>,
+    device_path: Option<String>,
+    label: Option<String>,
+    path: String,
+}
+
+impl Uri {
+    /// Returns the resource component of the URI (e.g. `boot`).
+    pub fn resource(&self) -> &str {
+        &self.resource
+    }
+
+    /// Returns the partition number of the URI, if specified.
+    pub fn partition(&self) -> Option<usize> {
+        self.partition
+    }
+
+    /// Returns the text representation of the device path of `devpath://` URIs.
+    pub fn device_path(&self) -> Option<&str> {
+        self.device_path.as_deref()
+    }
+
+    /// Returns the volume label or the name of the marker file of `search://` URIs.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Returns the path component of the URI.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UriParseError {
+    /// Missing the resource part of the URI. (e.g. `:///root/path`)
+    MissingResource,
+    /// Invalid syntax for the URI. (e.g. `://`, `:^)` or invalid partition number.)
+    InvalidSyntax,
+    /// Invalid partition number or the provided partition number is out of bounds
+    /// `0..256`.
+    InvalidPartition,
+}
+
+/// Resolves the `.` and `..` components of the provided path. Returns [`None`] if the path
+/// leaves the root directory of the volume.
+fn normalize<'a>(components: impl Iterator<Item = &'a str>) -> Option<Vec<&'a str>> {
+    let mut result = Vec::new();
+
+    for component in components {
+        match component {
+            "." => (),
+            ".." => {
+                result.pop()?;
+            }
+            component => result.push(component),
+        }
+    }
+
+    Some(result)
+}
+
+/// Parses the provided URI like [`parse_uri`], but also accepts paths without a resource.
+/// These are resolved on the boot volume relative to `base`, which is the directory that
+/// the config file has been loaded from (e.g. `boot`), unless they start with a slash.
+pub fn parse_uri_relative(uri: &str, base: &str) -> Result<Uri, UriParseError> {
+    if uri.contains(':') {
+        return parse_uri(uri);
+    }
+
+    let components = match uri.strip_prefix('/') {
+        Some(absolute) => normalize(absolute.split('/')),
+        None => normalize(base.split('/').chain(uri.split('/'))),
+    };
+
+    let components = components.ok_or(UriParseError::InvalidSyntax)?;
+
+    if components.iter().all(|component| component.is_empty()) {
+        return Err(UriParseError::InvalidSyntax);
+    }
+
+    let path = components
+        .into_iter()
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("\\");
+
+    Ok(Uri {
+        resource: String::from("boot"),
+        partition: None,
+        device_path: None,
+        label: None,
+        path,
+    })
+}
+
+/// Helper function to parse the path URI. A URI takes the form of:
+/// `resource:///root/path`. This function will return an error if the URI is
+/// not valid.
+///
+/// The volume of `devpath://` URIs is described by the text representation of its device
+/// path instead of a partition number, e.g.
+/// `devpath://PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/HD(1,...)/boot/kernel.elf`.
+/// The volume of `search://` URIs is the one with the provided label, or the one that
+/// contains a marker file with that name, e.g. `search://LIVE_LABEL/boot/vmlinuz`.
+pub fn parse_uri(uri: &str) -> Result<Uri, UriParseError> {
+    // 1. Seperate the domain from the URI.
+    let mut parts = uri.splitn(2, ':');
+
+    let resource = parts.next().unwrap_or("");
+    let root = parts.next().ok_or(UriParseError::InvalidSyntax)?;
+
+    // ERROR: missing the resource
+    if resource.is_empty() {
+        return Err(UriParseError::MissingResource);
+    }
+
+    // ERROR: missing the double backslashes after the resource. The prefix is stripped
+    // instead of sliced off, as the URI might not have a character boundary there.
+    let root = match root.strip_prefix("//") {
+        Some(root) if !root.is_empty() => root,
+        _ => return Err(UriParseError::InvalidSyntax),
+    };
+
+    let mut root = root.split('/').collect::<Vec<_>>();
+
+    // The device path is made up of the leading components that are device path nodes.
+    let device_path = if resource == "devpath" {
+        let nodes = root
+            .iter()
+            .take_while(|component| devpath::is_text_node(component))
+            .count();
+
+        if nodes == 0 {
+            return Err(UriParseError::InvalidSyntax);
+        }
+
+        let device_path = root[..nodes].join("/");
+
+        // The device path takes the place of the partition number.
+        root.drain(..nodes - 1);
+        root[0] = "";
+
+        Some(device_path)
+    } else {
+        None
+    };
+
+    // The label takes the place of the partition number.
+    let label = if resource == "search" {
+        match root[0] {
+            "" => return Err(UriParseError::InvalidSyntax),
+            label => {
+                let label = String::from(label);
+                root[0] = "";
+
+                Some(label)
+            }
+        }
+    } else {
+        None
+    };
+
+    // ERROR: Missing the root partition number (or a backslash indicating
+    // that we have to use the boot partition) and the root directory itself and
+    // the path.
+    if root.len() < 3 {
+        return Err(UriParseError::InvalidSyntax);
+    }
+
+    let partition = match root[0] {
+        "" => None,
+        n => match n.parse::<usize>() {
+            Ok(n) if n < 256 => Some(n),
+            _ => return Err(UriParseError::InvalidPartition),
+        },
+    };
+
+    // 2. Convert the provided path to a UEFI path. Since UEFI paths use
+    // windows type of forward slashes as the path seperator and the URI
+    // uses backslashes instead.
+    let path = normalize(root[1..].iter().copied())
+        .ok_or(UriParseError::InvalidSyntax)?
+        .join("\\");
+
+    Ok(Uri {
+        resource: String::from(resource),
+        partition,
+        device_path,
+        label,
+        path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_partition() {
+        let uri = parse_uri("boot:///boot/kernel.elf").unwrap();
+
+        assert_eq!(uri.resource(), "boot");
+        assert_eq!(uri.partition(), None);
+        assert_eq!(uri.path(), "boot\\kernel.elf");
+    }
+
+    #[test]
+    fn explicit_partition() {
+        let uri = parse_uri("hdd://2/efi/ion/kernel.elf").unwrap();
+
+        assert_eq!(uri.resource(), "hdd");
+        assert_eq!(uri.partition(), Some(2));
+        assert_eq!(uri.path(), "efi\\ion\\kernel.elf");
+    }
+
+    #[test]
+    fn device_path() {
+        let uri = parse_uri(
+            "devpath://PciRoot(0x0)/Pci(0x1,0x1)/Ata(Primary,Master,0x0)/boot/kernel.elf",
+        )
+        .unwrap();
+
+        assert_eq!(uri.resource(), "devpath");
+        assert_eq!(uri.partition(), None);
+        assert_eq!(
+            uri.device_path(),
+            Some("PciRoot(0x0)/Pci(0x1,0x1)/Ata(Primary,Master,0x0)")
+        );
+        assert_eq!(uri.path(), "boot\\kernel.elf");
+
+        assert_eq!(
+            parse_uri("devpath://boot/kernel.elf").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn search() {
+        let uri = parse_uri("search://LIVE_LABEL/boot/vmlinuz").unwrap();
+
+        assert_eq!(uri.resource(), "search");
+        assert_eq!(uri.partition(), None);
+        assert_eq!(uri.label(), Some("LIVE_LABEL"));
+        assert_eq!(uri.path(), "boot\\vmlinuz");
+
+        assert_eq!(
+            parse_uri("search:///boot/vmlinuz").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        let uri = parse_uri_relative("kernel.elf", "boot").unwrap();
+        assert_eq!(uri.resource(), "boot");
+        assert_eq!(uri.path(), "boot\\kernel.elf");
+
+        let uri = parse_uri_relative("../efi/./ion/kernel.elf", "boot").unwrap();
+        assert_eq!(uri.path(), "efi\\ion\\kernel.elf");
+
+        let uri = parse_uri_relative("/kernel.elf", "boot").unwrap();
+        assert_eq!(uri.path(), "kernel.elf");
+
+        let uri = parse_uri_relative("boot:///boot/../kernel.elf", "boot").unwrap();
+        assert_eq!(uri.path(), "kernel.elf");
+
+        assert_eq!(
+            parse_uri_relative("../../kernel.elf", "boot").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+        assert_eq!(
+            parse_uri_relative("", "").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn invalid_uris() {
+        assert_eq!(
+            parse_uri(":///boot/kernel.elf").err(),
+            Some(UriParseError::MissingResource)
+        );
+        assert_eq!(
+            parse_uri("kernel.elf").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+        assert_eq!(
+            parse_uri("boot://").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+        assert_eq!(
+            parse_uri("boot:///kernel.elf").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+        assert_eq!(
+            parse_uri("boot:///../kernel.elf").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+        assert_eq!(
+            parse_uri("boot://x/boot/kernel.elf").err(),
+            Some(UriParseError::InvalidPartition)
+        );
+        assert_eq!(
+            parse_uri("boot://256/boot/kernel.elf").err(),
+            Some(UriParseError::InvalidPartition)
+        );
+        assert_eq!(
+            parse_uri("boot:é/boot/kernel.elf").err(),
+            Some(UriParseError::InvalidSyntax)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The directory that efivarfs is mounted at.
+const EFIVARS: &str = "/sys/firmware/efi/efivars";
+
+/// The vendor GUID of the global EFI variables (`Boot####`, `BootOrder` and `BootNext`).
+const GLOBAL_VARIABLE: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// The attributes of the boot variables: non-volatile, boot service access and runtime
+/// access.
+const ATTRIBUTES: u32 = 0x7;
+
+/// The attribute of a load option that makes it bootable.
+const LOAD_OPTION_ACTIVE: u32 = 1;
+
+fn variable_path(name: &str) -> PathBuf {
+    PathBuf::from(EFIVARS).join(format!("{}-{}", name, GLOBAL_VARIABLE))
+}
+
+/// Returns the name of the boot option variable with the provided number.
+pub fn boot_option_name(number: u16) -> String {
+    format!("Boot{:04X}", number)
+}
+
+/// Reads the global EFI variable with the provided name, without its attributes. Returns
+/// [`None`] if the variable does not exist.
+pub fn read(name: &str) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(variable_path(name)) {
+        Ok(data) if data.len() >= 4 => Ok(Some(data[4..].to_vec())),
+        Ok(_) => Ok(None),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Creates or replaces the global EFI variable with the provided name.
+pub fn write(name: &str, data: &[u8]) -> io::Result<()> {
+    let path = variable_path(name);
+
+    // The kernel marks existing variables as immutable, so that they are not deleted by
+    // accident. The ioctl that clears the flag is not in the standard library.
+    if path.exists() {
+        let status = Command::new("chattr").arg("-i").arg(&path).status()?;
+
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "ion-install: failed to make {} mutable",
+                path.display()
+            )));
+        }
+    }
+
+    // efivarfs requires the attributes and the data to be written at once.
+    let mut variable = ATTRIBUTES.to_le_bytes().to_vec();
+    variable.extend_from_slice(data);
+
+    fs::write(&path, variable)
+}
+
+/// Returns the numbers of the existing boot options.
+pub fn boot_options() -> io::Result<Vec<u16>> {
+    let mut options = Vec::new();
+
+    for entry in fs::read_dir(EFIVARS)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+
+        let number = name
+            .strip_suffix(GLOBAL_VARIABLE)
+            .and_then(|name| name.strip_suffix('-'))
+            .and_then(|name| name.strip_prefix("Boot"))
+            .filter(|number| number.len() == 4)
+            .and_then(|number| u16::from_str_radix(number, 16).ok());
+
+        if let Some(number) = number {
+            options.push(number);
+        }
+    }
+
+    options.sort_unstable();
+    Ok(options)
+}
+
+/// Parses a list of boot option numbers, which is the format of `BootOrder`.
+pub fn parse_boot_order(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|number| u16::from_le_bytes([number[0], number[1]]))
+        .collect()
+}
+
+pub fn encode_boot_order(order: &[u16]) -> Vec<u8> {
+    order
+        .iter()
+        .flat_map(|number| number.to_le_bytes())
+        .collect()
+}
+
+/// Encodes a string as a NUL-terminated UTF-16 string.
+fn utf16(string: &str) -> Vec<u8> {
+    string
+        .encode_utf16()
+        .chain(core::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+/// Parses a GUID in its textual form (e.g. `c12a7328-f81f-11d2-ba4b-00a0c93ec93b`) into its
+/// mixed-endian binary encoding.
+pub fn parse_guid(guid: &str) -> Option<[u8; 16]> {
+    let fields = guid.split('-').map(str::len).collect::<Vec<_>>();
+
+    if fields != [8, 4, 4, 4, 12] {
+        return None;
+    }
+
+    let hex = guid.replace('-', "");
+
+    let mut bytes = [0; 16];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    // The first three fields are stored in little endian.
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+
+    Some(bytes)
+}
+
+/// The GPT partition that the bootloader is installed on.
+#[derive(Debug, Clone, Copy)]
+pub struct Partition {
+    pub number: u32,
+    /// The first sector of the partition.
+    pub start: u64,
+    /// The size of the partition in sectors.
+    pub size: u64,
+    /// The unique partition GUID, in its binary encoding.
+    pub guid: [u8; 16],
+}
+
+/// Encodes an `EFI_LOAD_OPTION` booting the file at the provided path (using `\` as the
+/// separator) of the provided partition.
+pub fn encode_load_option(description: &str, partition: &Partition, path: &str) -> Vec<u8> {
+    let mut device_path = Vec::new();
+
+    // Hard drive media device path.
+    device_path.extend_from_slice(&[0x04, 0x01]);
+    device_path.extend_from_slice(&42u16.to_le_bytes());
+    device_path.extend_from_slice(&partition.number.to_le_bytes());
+    device_path.extend_from_slice(&partition.start.to_le_bytes());
+    device_path.extend_from_slice(&partition.size.to_le_bytes());
+    device_path.extend_from_slice(&partition.guid);
+    device_path.push(0x02); // GPT
+    device_path.push(0x02); // GUID signature
+
+    // File path media device path.
+    let path = utf16(path);
+
+    device_path.extend_from_slice(&[0x04, 0x04]);
+    device_path.extend_from_slice(&(4 + path.len() as u16).to_le_bytes());
+    device_path.extend_from_slice(&path);
+
+    // End of the device path.
+    device_path.extend_from_slice(&[0x7f, 0xff, 0x04, 0x00]);
+
+    let mut option = Vec::new();
+
+    option.extend_from_slice(&LOAD_OPTION_ACTIVE.to_le_bytes());
+    option.extend_from_slice(&(device_path.len() as u16).to_le_bytes());
+    option.extend_from_slice(&utf16(description));
+    option.extend_from_slice(&device_path);
+    option
+}
+
+/// Returns the description of the provided `EFI_LOAD_OPTION`.
+pub fn load_option_description(option: &[u8]) -> Option<String> {
+    let units = option
+        .get(6..)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect::<Vec<_>>();
+
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guids() {
+        assert_eq!(
+            parse_guid("c12a7328-f81f-11d2-ba4b-00a0c93ec93b"),
+            Some([
+                0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e,
+                0xc9, 0x3b
+            ])
+        );
+
+        assert_eq!(parse_guid("c12a7328f81f11d2ba4b00a0c93ec93b"), None);
+        assert_eq!(parse_guid("c12a7328-f81f-11d2-ba4b-00a0c93ec93x"), None);
+    }
+
+    #[test]
+    fn load_options() {
+        let partition = Partition {
+            number: 1,
+            start: 2048,
+            size: 0x1000,
+            guid: [0xaa; 16],
+        };
+
+        let option = encode_load_option("Ion", &partition, "\\EFI\\ion\\ion.efi");
+        let description_len = "Ion\0".len() * 2;
+        let path_len = "\\EFI\\ion\\ion.efi\0".len() * 2;
+
+        assert_eq!(option[0..4], LOAD_OPTION_ACTIVE.to_le_bytes());
+        assert_eq!(
+            u16::from_le_bytes([option[4], option[5]]) as usize,
+            42 + 4 + path_len + 4
+        );
+        assert_eq!(option.len(), 6 + description_len + 42 + 4 + path_len + 4);
+        assert_eq!(load_option_description(&option).as_deref(), Some("Ion"));
+
+        // The device path is terminated by an end node.
+        assert_eq!(option[option.len() - 4..], [0x7f, 0xff, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn boot_order() {
+        let order = [0x0003, 0x0001, 0x1000];
+
+        assert_eq!(encode_boot_order(&order), [3, 0, 1, 0, 0, 0x10]);
+        assert_eq!(parse_boot_order(&encode_boot_order(&order)), order);
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::efivars::{self, Partition};
+
+fn error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
+
+/// Returns the device number (`major:minor`) of the file system mounted at the provided
+/// path, using `/proc/self/mountinfo`.
+fn device_number(mount_point: &Path) -> io::Result<String> {
+    let mount_point = fs::canonicalize(mount_point)?;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    // The fields are the mount ID, the parent ID, the device number, the root and the mount
+    // point. The last matching mount is the one that is visible, so the lines are searched
+    // backwards.
+    mountinfo
+        .lines()
+        .rev()
+        .find_map(|line| {
+            let mut fields = line.split(' ');
+            let device = fields.nth(2)?;
+            let path = fields.nth(1)?;
+
+            // Spaces in the mount point are escaped as octal.
+            let path = path.replace("\\040", " ");
+            (Path::new(&path) == mount_point).then(|| String::from(device))
+        })
+        .ok_or_else(|| {
+            error(format!(
+                "ion-install: {} is not a mount point",
+                mount_point.display()
+            ))
+        })
+}
+
+fn read_sysfs(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_owned())
+}
+
+fn parse_sysfs(path: &Path) -> io::Result<u64> {
+    read_sysfs(path)?
+        .parse()
+        .map_err(|_| error(format!("ion-install: invalid value in {}", path.display())))
+}
+
+/// Returns the unique partition GUID of the provided block device, using the symbolic links
+/// created by udev.
+fn partition_guid(device: &Path) -> io::Result<String> {
+    for link in fs::read_dir("/dev/disk/by-partuuid")? {
+        let link = link?;
+
+        if fs::canonicalize(link.path())? == device {
+            return Ok(link.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    Err(error(format!(
+        "ion-install: {} is not a GPT partition",
+        device.display()
+    )))
+}
+
+/// Returns the GPT partition that is mounted at the provided path.
+pub fn partition(mount_point: &Path) -> io::Result<Partition> {
+    let sysfs = PathBuf::from("/sys/dev/block").join(device_number(mount_point)?);
+
+    if !sysfs.join("partition").exists() {
+        return Err(error(format!(
+            "ion-install: {} is not mounted from a partition",
+            mount_point.display()
+        )));
+    }
+
+    let uevent = read_sysfs(&sysfs.join("uevent"))?;
+    let name = uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .ok_or_else(|| {
+            error(format!(
+                "ion-install: no device name in {}",
+                sysfs.display()
+            ))
+        })?;
+
+    let device = PathBuf::from("/dev").join(name);
+    let guid = partition_guid(&device)?;
+
+    Ok(Partition {
+        number: parse_sysfs(&sysfs.join("partition"))? as u32,
+        // sysfs always reports the start and the size in 512-byte sectors.
+        start: parse_sysfs(&sysfs.join("start"))?,
+        size: parse_sysfs(&sysfs.join("size"))?,
+        guid: efivars::parse_guid(&guid)
+            .ok_or_else(|| error(format!("ion-install: invalid partition GUID {}", guid)))?,
+    })
+}
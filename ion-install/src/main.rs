@@ -0,0 +1,163 @@
+// Installs Ion onto the EFI system partition of a Linux host and registers it in the UEFI
+// boot order using efivarfs. With `--boot-next` the boot order is left untouched and Ion is
+// only booted once on the next boot, which is useful for trying out a new build.
+
+mod efivars;
+mod esp;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+struct Options {
+    ion: PathBuf,
+    config: Option<PathBuf>,
+    /// The mount point of the EFI system partition.
+    esp: PathBuf,
+    /// The directory on the EFI system partition that Ion is copied into.
+    directory: String,
+    label: String,
+    boot_next: bool,
+    /// Only copy the files, without touching the EFI variables.
+    no_register: bool,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: ion-install --ion <ion.efi> [--config <ion.cfg>] [--esp <mount point>]\n\
+         \x20      [--directory <path>] [--label <description>] [--boot-next] [--no-register]"
+    );
+
+    process::exit(2)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut ion = None;
+
+    let mut options = Options {
+        ion: PathBuf::new(),
+        config: None,
+        esp: PathBuf::from("/boot/efi"),
+        directory: String::from("EFI/ion"),
+        label: String::from("Ion"),
+        boot_next: false,
+        no_register: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+
+        match arg.as_str() {
+            "--ion" => ion = Some(PathBuf::from(value())),
+            "--config" => options.config = Some(value().into()),
+            "--esp" => options.esp = value().into(),
+            "--directory" => options.directory = value().trim_matches('/').into(),
+            "--label" => options.label = value(),
+            "--boot-next" => options.boot_next = true,
+            "--no-register" => options.no_register = true,
+            _ => usage(),
+        }
+    }
+
+    options.ion = ion.unwrap_or_else(|| usage());
+    options
+}
+
+/// Copies Ion and its config onto the EFI system partition. The config is placed next to
+/// Ion in the `boot` directory, which is where Ion looks for it first.
+fn copy_files(options: &Options) -> Result<()> {
+    let directory = options.esp.join(&options.directory);
+    fs::create_dir_all(&directory)?;
+
+    fs::copy(&options.ion, directory.join("ion.efi"))?;
+    println!("ion-install: copied Ion to {}", directory.display());
+
+    if let Some(config) = &options.config {
+        let boot = options.esp.join("boot");
+        fs::create_dir_all(&boot)?;
+
+        fs::copy(config, boot.join("ion.cfg"))?;
+        println!("ion-install: copied the config to {}", boot.display());
+    }
+
+    Ok(())
+}
+
+/// Returns the number of the existing boot option with the provided description, or the
+/// lowest unused number.
+fn boot_option_number(label: &str) -> Result<u16> {
+    let options = efivars::boot_options()?;
+
+    for &number in &options {
+        let option = efivars::read(&efivars::boot_option_name(number))?;
+
+        if option
+            .and_then(|option| efivars::load_option_description(&option))
+            .as_deref()
+            == Some(label)
+        {
+            return Ok(number);
+        }
+    }
+
+    (0..=u16::MAX)
+        .find(|number| !options.contains(number))
+        .ok_or_else(|| "ion-install: all boot option numbers are in use".into())
+}
+
+fn register(options: &Options) -> Result<()> {
+    let partition = esp::partition(&options.esp)?;
+
+    let path = format!("\\{}\\ion.efi", options.directory.replace('/', "\\"));
+    let number = boot_option_number(&options.label)?;
+    let name = efivars::boot_option_name(number);
+
+    efivars::write(
+        &name,
+        &efivars::encode_load_option(&options.label, &partition, &path),
+    )?;
+
+    println!("ion-install: registered {} as {}", path, name);
+
+    if options.boot_next {
+        efivars::write("BootNext", &number.to_le_bytes())?;
+        println!("ion-install: {} will be booted once on the next boot", name);
+
+        return Ok(());
+    }
+
+    let mut order = efivars::read("BootOrder")?
+        .map(|order| efivars::parse_boot_order(&order))
+        .unwrap_or_default();
+
+    if order.first() != Some(&number) {
+        order.retain(|&entry| entry != number);
+        order.insert(0, number);
+
+        efivars::write("BootOrder", &efivars::encode_boot_order(&order))?;
+        println!("ion-install: moved {} to the front of the boot order", name);
+    }
+
+    Ok(())
+}
+
+fn install(options: Options) -> Result<()> {
+    copy_files(&options)?;
+
+    if !options.no_register {
+        register(&options)?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = install(parse_options(env::args().skip(1))) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
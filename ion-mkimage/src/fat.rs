@@ -0,0 +1,44 @@
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+/// Formats the provided volume using FAT and copies the provided files into it. Each file is
+/// described by its path in the volume (using `/` as the separator) and the path of the file
+/// on the host. The FAT type is picked based on the size of the volume.
+pub fn write<T>(mut volume: T, label: [u8; 11], files: &[(&str, &Path)]) -> io::Result<()>
+where
+    T: Read + Write + Seek,
+{
+    fatfs::format_volume(
+        &mut volume,
+        fatfs::FormatVolumeOptions::new().volume_label(label),
+    )?;
+
+    let filesystem = fatfs::FileSystem::new(&mut volume, fatfs::FsOptions::new())?;
+    let root = filesystem.root_dir();
+
+    for (destination, source) in files {
+        let mut components = destination.split('/').collect::<Vec<_>>();
+        let name = components
+            .pop()
+            .expect("ion-mkimage: empty destination path");
+
+        // Create the parent directories of the file.
+        let mut directory = root.clone();
+
+        for component in components {
+            directory = match directory.open_dir(component) {
+                Ok(directory) => directory,
+                Err(_) => directory.create_dir(component)?,
+            };
+        }
+
+        let contents = fs::read(source)?;
+
+        let mut file = directory.create_file(name)?;
+        file.truncate()?;
+        file.write_all(&contents)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const SECTOR_SIZE: u64 = 512;
+
+/// The first sector of the EFI system partition, which aligns it to 1 MiB.
+pub const PARTITION_START: u64 = 2048;
+
+/// The amount of partition entries in the partition table and the size of each of them.
+const ENTRY_COUNT: u64 = 128;
+const ENTRY_SIZE: u64 = 128;
+
+/// The amount of sectors occupied by the partition entries.
+const ENTRY_SECTORS: u64 = ENTRY_COUNT * ENTRY_SIZE / SECTOR_SIZE;
+
+const HEADER_SIZE: u32 = 92;
+
+/// The partition type GUID of the EFI system partition
+/// (C12A7328-F81F-11D2-BA4B-00A0C93EC93B) in its on-disk mixed-endian encoding.
+const EFI_SYSTEM_PARTITION: [u8; 16] = [
+    0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b,
+];
+
+/// Computes the CRC32 (IEEE 802.3) checksum used by the GPT headers.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// Returns a random version 4 GUID.
+fn random_guid() -> [u8; 16] {
+    let mut guid = [0; 16];
+
+    // The hasher is seeded with random keys, so hashing nothing returns a random value.
+    for chunk in guid.chunks_mut(8) {
+        chunk.copy_from_slice(&RandomState::new().build_hasher().finish().to_le_bytes());
+    }
+
+    guid[7] = (guid[7] & 0x0f) | 0x40;
+    guid[8] = (guid[8] & 0x3f) | 0x80;
+    guid
+}
+
+/// Returns the sector range of the EFI system partition of a disk with the provided amount
+/// of sectors. The last sector is inclusive.
+pub fn partition_range(sectors: u64) -> (u64, u64) {
+    (PARTITION_START, sectors - ENTRY_SECTORS - 2)
+}
+
+fn header(
+    sectors: u64,
+    current: u64,
+    backup: u64,
+    entries_lba: u64,
+    disk_guid: &[u8; 16],
+    entries_crc: u32,
+) -> [u8; SECTOR_SIZE as usize] {
+    let mut header = [0; SECTOR_SIZE as usize];
+
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&HEADER_SIZE.to_le_bytes());
+    header[24..32].copy_from_slice(&current.to_le_bytes());
+    header[32..40].copy_from_slice(&backup.to_le_bytes());
+    header[40..48].copy_from_slice(&(ENTRY_SECTORS + 2).to_le_bytes());
+    header[48..56].copy_from_slice(&(sectors - ENTRY_SECTORS - 2).to_le_bytes());
+    header[56..72].copy_from_slice(disk_guid);
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+
+    let crc = crc32(&header[..HEADER_SIZE as usize]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// Writes a protective MBR and the primary and backup GPT describing a single EFI system
+/// partition that spans the whole disk (see [`partition_range`]).
+pub fn write<T: Write + Seek>(disk: &mut T, size: u64) -> io::Result<()> {
+    let sectors = size / SECTOR_SIZE;
+
+    if sectors <= PARTITION_START + ENTRY_SECTORS + 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "ion-mkimage: the disk is too small for a partition table",
+        ));
+    }
+
+    // The protective MBR, which covers the whole disk with a partition of type 0xee.
+    let mut mbr = [0; SECTOR_SIZE as usize];
+    let mbr_sectors = (sectors - 1).min(u32::MAX as u64) as u32;
+
+    mbr[446 + 1..446 + 4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    mbr[446 + 4] = 0xee;
+    mbr[446 + 5..446 + 8].copy_from_slice(&[0xff, 0xff, 0xff]);
+    mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&mbr_sectors.to_le_bytes());
+    mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    let (first, last) = partition_range(sectors);
+    let mut entries = vec![0; (ENTRY_COUNT * ENTRY_SIZE) as usize];
+
+    entries[0..16].copy_from_slice(&EFI_SYSTEM_PARTITION);
+    entries[16..32].copy_from_slice(&random_guid());
+    entries[32..40].copy_from_slice(&first.to_le_bytes());
+    entries[40..48].copy_from_slice(&last.to_le_bytes());
+
+    for (i, unit) in "EFI System Partition".encode_utf16().enumerate() {
+        entries[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+
+    let entries_crc = crc32(&entries);
+    let disk_guid = random_guid();
+
+    let backup_entries = sectors - ENTRY_SECTORS - 1;
+
+    let primary = header(sectors, 1, sectors - 1, 2, &disk_guid, entries_crc);
+    let backup = header(
+        sectors,
+        sectors - 1,
+        1,
+        backup_entries,
+        &disk_guid,
+        entries_crc,
+    );
+
+    disk.seek(SeekFrom::Start(0))?;
+    disk.write_all(&mbr)?;
+    disk.write_all(&primary)?;
+    disk.write_all(&entries)?;
+
+    disk.seek(SeekFrom::Start(backup_entries * SECTOR_SIZE))?;
+    disk.write_all(&entries)?;
+    disk.write_all(&backup)?;
+
+    Ok(())
+}
+
+/// A window into a disk, so that a file system can be written into a partition.
+pub struct Partition<T> {
+    disk: T,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<T: Seek> Partition<T> {
+    /// Creates a window of `len` bytes starting at the byte offset `start` of the disk.
+    pub fn new(mut disk: T, start: u64, len: u64) -> io::Result<Self> {
+        disk.seek(SeekFrom::Start(start))?;
+
+        Ok(Self {
+            disk,
+            start,
+            len,
+            position: 0,
+        })
+    }
+
+    /// Returns the amount of bytes that can be accessed from the current position.
+    fn remaining(&self, len: usize) -> usize {
+        (self.len.saturating_sub(self.position)).min(len as u64) as usize
+    }
+}
+
+impl<T: Read + Seek> Read for Partition<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+        let read = self.disk.read(&mut buf[..len])?;
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: Write + Seek> Write for Partition<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.remaining(buf.len());
+
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "ion-mkimage: write past the end of the partition",
+            ));
+        }
+
+        let written = self.disk.write(&buf[..len])?;
+
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.disk.flush()
+    }
+}
+
+impl<T: Seek> Seek for Partition<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.len as i64).checked_add(offset).map(|p| p as u64),
+            SeekFrom::Current(offset) => {
+                (self.position as i64).checked_add(offset).map(|p| p as u64)
+            }
+        };
+
+        match position {
+            Some(position) if position <= self.len => {
+                self.disk.seek(SeekFrom::Start(self.start + position))?;
+                self.position = position;
+
+                Ok(position)
+            }
+
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ion-mkimage: seek outside of the partition",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const DISK_SIZE: u64 = 4 * 1024 * 1024;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn partition_table() {
+        let mut disk = Cursor::new(vec![0; DISK_SIZE as usize]);
+        write(&mut disk, DISK_SIZE).unwrap();
+
+        let disk = disk.into_inner();
+        let sectors = DISK_SIZE / SECTOR_SIZE;
+
+        assert_eq!(disk[450], 0xee);
+        assert_eq!(&disk[510..512], &[0x55, 0xaa]);
+
+        for (lba, entries_lba) in [(1, 2), (sectors - 1, sectors - ENTRY_SECTORS - 1)] {
+            let header = &disk[(lba * SECTOR_SIZE) as usize..][..SECTOR_SIZE as usize];
+            let entries = &disk[(entries_lba * SECTOR_SIZE) as usize..]
+                [..(ENTRY_COUNT * ENTRY_SIZE) as usize];
+
+            assert_eq!(&header[0..8], b"EFI PART");
+            assert_eq!(header[24..32], lba.to_le_bytes());
+            assert_eq!(header[72..80], entries_lba.to_le_bytes());
+            assert_eq!(header[88..92], crc32(entries).to_le_bytes());
+
+            let mut zeroed = header[..HEADER_SIZE as usize].to_vec();
+            zeroed[16..20].fill(0);
+            assert_eq!(header[16..20], crc32(&zeroed).to_le_bytes());
+
+            assert_eq!(entries[0..16], EFI_SYSTEM_PARTITION);
+            assert_eq!(entries[32..40], PARTITION_START.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn partition_window() {
+        let mut disk = Cursor::new(vec![0; 64]);
+
+        {
+            let mut partition = Partition::new(&mut disk, 16, 16).unwrap();
+
+            assert_eq!(partition.write(&[1; 32]).unwrap(), 16);
+            assert!(partition.write(&[1]).is_err());
+            assert!(partition.seek(SeekFrom::Start(17)).is_err());
+
+            partition.seek(SeekFrom::End(-1)).unwrap();
+            partition.write_all(&[2]).unwrap();
+        }
+
+        let disk = disk.into_inner();
+
+        assert_eq!(disk[15], 0);
+        assert_eq!(disk[16], 1);
+        assert_eq!(disk[31], 2);
+        assert_eq!(disk[32], 0);
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Creates an ISO image that boots the provided FAT image using the El Torito EFI boot
+/// entry. The ISO 9660 file system is written by `xorriso`, which has to be installed.
+pub fn create(path: &Path, esp: &Path) -> io::Result<()> {
+    let root = path.with_extension("iso.d");
+
+    // xorriso builds the file system from a directory, which only contains the FAT image.
+    fs::create_dir_all(&root)?;
+    fs::copy(esp, root.join("efi.img"))?;
+
+    let status = Command::new("xorriso")
+        .arg("-as")
+        .arg("mkisofs")
+        .arg("-quiet")
+        .arg("-e")
+        .arg("efi.img")
+        .arg("-no-emul-boot")
+        .arg("-o")
+        .arg(path)
+        .arg(&root)
+        .status();
+
+    fs::remove_dir_all(&root)?;
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(io::Error::other(format!(
+            "ion-mkimage: xorriso failed with {}",
+            status
+        ))),
+        Err(error) => Err(io::Error::new(
+            error.kind(),
+            format!("ion-mkimage: failed to run xorriso ({})", error),
+        )),
+    }
+}
@@ -0,0 +1,103 @@
+// Builds bootable disk images with Ion installed, which is used by the `ion-mkimage` tool
+// and the integration tests.
+
+pub mod fat;
+pub mod gpt;
+pub mod iso;
+
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+
+/// The volume label of the EFI system partition.
+pub const VOLUME_LABEL: [u8; 11] = *b"ION        ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// A GPT partitioned disk containing a single EFI system partition.
+    Gpt,
+    /// A FAT file system without a partition table, which is also used as the El Torito
+    /// boot image of ISO images.
+    Fat,
+}
+
+/// Creates a disk image of the provided size containing the provided files. Each file is
+/// described by its path in the EFI system partition (using `/` as the separator) and the
+/// path of the file on the host.
+pub fn create(path: &Path, size: u64, layout: Layout, files: &[(&str, &Path)]) -> io::Result<()> {
+    let mut image = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+
+    image.set_len(size)?;
+
+    match layout {
+        Layout::Gpt => {
+            gpt::write(&mut image, size)?;
+
+            let (first, last) = gpt::partition_range(size / gpt::SECTOR_SIZE);
+            let partition = gpt::Partition::new(
+                &mut image,
+                first * gpt::SECTOR_SIZE,
+                (last - first + 1) * gpt::SECTOR_SIZE,
+            )?;
+
+            fat::write(partition, VOLUME_LABEL, files)
+        }
+
+        Layout::Fat => fat::write(&mut image, VOLUME_LABEL, files),
+    }
+}
+
+/// Returns the path at which the firmware looks for the bootloader of the provided
+/// architecture on removable media.
+pub fn efi_boot_path(arch: &str) -> Option<&'static str> {
+    match arch {
+        "x86_64" => Some("EFI/BOOT/BOOTX64.EFI"),
+        "ia32" => Some("EFI/BOOT/BOOTIA32.EFI"),
+        "aarch64" => Some("EFI/BOOT/BOOTAA64.EFI"),
+        "riscv64" => Some("EFI/BOOT/BOOTRISCV64.EFI"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    #[test]
+    fn files_are_written_into_the_partition() {
+        let directory = std::env::temp_dir().join(format!("ion-mkimage-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let kernel = directory.join("kernel.elf");
+        fs::write(&kernel, b"kernel").unwrap();
+
+        let image = directory.join("disk.img");
+        let size = 34 * 1024 * 1024;
+        create(&image, size, Layout::Gpt, &[("boot/kernel.elf", &kernel)]).unwrap();
+
+        let (first, last) = gpt::partition_range(size / gpt::SECTOR_SIZE);
+        let partition = gpt::Partition::new(
+            File::open(&image).unwrap(),
+            first * gpt::SECTOR_SIZE,
+            (last - first + 1) * gpt::SECTOR_SIZE,
+        )
+        .unwrap();
+
+        let mut contents = Vec::new();
+        {
+            let filesystem = fatfs::FileSystem::new(partition, fatfs::FsOptions::new()).unwrap();
+            let mut file = filesystem.root_dir().open_file("boot/kernel.elf").unwrap();
+            file.read_to_end(&mut contents).unwrap();
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(contents, b"kernel");
+    }
+}
@@ -0,0 +1,159 @@
+// Creates a bootable disk image with Ion installed, containing a config, a kernel and its
+// modules. The kernel and the modules are copied into the `boot` directory of the EFI
+// system partition, so they can be referred to as `boot:///boot/<name>` in the config.
+
+use std::env;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use ion_mkimage::Layout;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// The default size of the disk image in MiB.
+const DEFAULT_SIZE_MIB: u64 = 64;
+
+struct Options {
+    ion: PathBuf,
+    arch: String,
+    config: PathBuf,
+    kernel: PathBuf,
+    modules: Vec<PathBuf>,
+    /// Additional files, described by their path in the image and on the host.
+    files: Vec<(String, PathBuf)>,
+    size: u64,
+    layout: Layout,
+    iso: Option<PathBuf>,
+    output: PathBuf,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: ion-mkimage --ion <ion.efi> --config <ion.cfg> --kernel <kernel> -o <image>\n\
+         \x20      [--module <module>]... [--file <destination>=<source>]...\n\
+         \x20      [--arch x86_64|ia32|aarch64|riscv64] [--size <MiB>] [--no-gpt] [--iso <image>]"
+    );
+
+    process::exit(2)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut ion = None;
+    let mut config = None;
+    let mut kernel = None;
+    let mut output = None;
+
+    let mut options = Options {
+        ion: PathBuf::new(),
+        arch: String::from("x86_64"),
+        config: PathBuf::new(),
+        kernel: PathBuf::new(),
+        modules: Vec::new(),
+        files: Vec::new(),
+        size: DEFAULT_SIZE_MIB * 1024 * 1024,
+        layout: Layout::Gpt,
+        iso: None,
+        output: PathBuf::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+
+        match arg.as_str() {
+            "--ion" => ion = Some(PathBuf::from(value())),
+            "--config" => config = Some(PathBuf::from(value())),
+            "--kernel" => kernel = Some(PathBuf::from(value())),
+            "--module" => options.modules.push(value().into()),
+            "--arch" => options.arch = value(),
+            "--no-gpt" => options.layout = Layout::Fat,
+            "--iso" => options.iso = Some(value().into()),
+            "-o" | "--output" => output = Some(PathBuf::from(value())),
+
+            "--file" => {
+                let file = value();
+                let (destination, source) = file.split_once('=').unwrap_or_else(|| usage());
+
+                options
+                    .files
+                    .push((destination.trim_matches('/').into(), source.into()));
+            }
+
+            "--size" => {
+                let size: u64 = value().parse().unwrap_or_else(|_| usage());
+                options.size = size * 1024 * 1024;
+            }
+
+            _ => usage(),
+        }
+    }
+
+    match (ion, config, kernel, output) {
+        (Some(ion), Some(config), Some(kernel), Some(output)) => {
+            options.ion = ion;
+            options.config = config;
+            options.kernel = kernel;
+            options.output = output;
+        }
+
+        _ => usage(),
+    }
+
+    options
+}
+
+/// Returns the path of the provided kernel or module in the `boot` directory.
+fn boot_path(path: &Path) -> Result<String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("ion-mkimage: invalid file name {}", path.display()))?;
+
+    Ok(format!("boot/{}", name))
+}
+
+fn mkimage(options: Options) -> Result<()> {
+    let efi_path = ion_mkimage::efi_boot_path(&options.arch)
+        .ok_or_else(|| format!("ion-mkimage: unsupported architecture {}", options.arch))?;
+
+    let mut files = vec![
+        (String::from(efi_path), options.ion.clone()),
+        (String::from("boot/ion.cfg"), options.config.clone()),
+        (boot_path(&options.kernel)?, options.kernel.clone()),
+    ];
+
+    for module in &options.modules {
+        files.push((boot_path(module)?, module.clone()));
+    }
+
+    files.extend(options.files.iter().cloned());
+
+    let files = files
+        .iter()
+        .map(|(destination, source)| (destination.as_str(), source.as_path()))
+        .collect::<Vec<_>>();
+
+    ion_mkimage::create(&options.output, options.size, options.layout, &files)?;
+    println!("ion-mkimage: created {}", options.output.display());
+
+    if let Some(iso) = &options.iso {
+        // The El Torito boot image has to be a bare FAT file system.
+        let esp = iso.with_extension("esp");
+
+        ion_mkimage::create(&esp, options.size, Layout::Fat, &files)?;
+        let result = ion_mkimage::iso::create(iso, &esp);
+        std::fs::remove_file(&esp)?;
+
+        result?;
+        println!("ion-mkimage: created {}", iso.display());
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = mkimage(parse_options(env::args().skip(1))) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,580 @@
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use uefi::prelude::*;
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
+
+/// The physical address of the RSDP, or 0 if the firmware does not provide ACPI tables.
+static RSDP: AtomicU64 = AtomicU64::new(0);
+
+/// The root system description pointer. The extended fields are only valid if the
+/// revision is 2 or higher.
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The header shared by all of the system description tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// The largest table length that is accepted. The largest tables (e.g. the DSDT) are a few
+/// hundred KiB, so longer tables are corrupted and would reach past the mapped memory.
+const MAX_TABLE_LENGTH: usize = 16 * 1024 * 1024;
+
+impl SdtHeader {
+    /// Returns true if the length of the table covers at least the header and is within
+    /// [`MAX_TABLE_LENGTH`]. The other methods may only be called on tables with a valid
+    /// length.
+    fn length_valid(&self) -> bool {
+        let length = self.length as usize;
+        (mem::size_of::<SdtHeader>()..=MAX_TABLE_LENGTH).contains(&length)
+    }
+
+    /// Returns the bytes of the whole table, including the header.
+    fn bytes(&self) -> &'static [u8] {
+        // SAFETY: The tables are identity-mapped and the length covers the whole table.
+        unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, self.length as usize)
+        }
+    }
+
+    /// Returns the bytes of the table following the header.
+    fn data(&self) -> &'static [u8] {
+        &self.bytes()[mem::size_of::<SdtHeader>()..]
+    }
+}
+
+/// Returns true if the bytes add up to zero, as required for all of the ACPI structures.
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+/// This function is responsible for locating the RSDP in the UEFI configuration table.
+/// The ACPI 2.0 RSDP is preferred, as it provides the XSDT with 64-bit table addresses.
+pub fn init(system_table: &SystemTable<Boot>) {
+    let config_table = system_table.config_table();
+
+    let rsdp = config_table
+        .iter()
+        .find(|entry| entry.guid == ACPI2_GUID)
+        .or_else(|| config_table.iter().find(|entry| entry.guid == ACPI_GUID));
+
+    match rsdp {
+        Some(entry) => RSDP.store(entry.address as u64, Ordering::Relaxed),
+        None => log::warn!("acpi: the firmware does not provide an RSDP"),
+    }
+}
+
+/// Returns the physical address of the RSDP, if the firmware provides ACPI tables.
+pub fn rsdp_address() -> Option<u64> {
+    match RSDP.load(Ordering::Relaxed) {
+        0 => None,
+        address => Some(address),
+    }
+}
+
+/// Returns an iterator over the physical addresses of all of the tables referenced by the
+/// XSDT, or by the RSDT on ACPI 1.0 systems.
+fn table_addresses() -> impl Iterator<Item = u64> {
+    let (root, entry_size) = match rsdp_address() {
+        Some(address) => {
+            // SAFETY: The RSDP provided by the firmware is identity-mapped.
+            let rsdp = unsafe { &*(address as *const Rsdp) };
+
+            if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+                (rsdp.xsdt_address, 8)
+            } else {
+                (rsdp.rsdt_address as u64, 4)
+            }
+        }
+
+        None => (0, 4),
+    };
+
+    // SAFETY: The root table provided by the firmware is identity-mapped.
+    let data: &[u8] = match unsafe { (root as *const SdtHeader).as_ref() } {
+        Some(table) if table.length_valid() => table.data(),
+
+        Some(_) => {
+            log::warn!("acpi: ignoring the root table with an invalid length");
+            &[]
+        }
+
+        None => &[],
+    };
+
+    data.chunks_exact(entry_size)
+        .map(move |entry| match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        })
+}
+
+/// Returns the first table with the provided signature with a valid checksum.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    table_addresses()
+        .filter(|address| *address != 0)
+        // SAFETY: The tables provided by the firmware are identity-mapped.
+        .map(|address| unsafe { &*(address as *const SdtHeader) })
+        .filter(|table| &table.signature == signature)
+        .find(|table| {
+            let name = core::str::from_utf8(signature).unwrap_or("????");
+
+            if !table.length_valid() {
+                log::warn!("acpi: ignoring {} table with an invalid length", name);
+                return false;
+            }
+
+            let valid = checksum_valid(table.bytes());
+
+            if !valid {
+                log::warn!("acpi: ignoring {} table with an invalid checksum", name);
+            }
+
+            valid
+        })
+}
+
+/// An entry of the MADT.
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    /// The GIC CPU interface of a processor on aarch64 systems.
+    Gicc {
+        cpu_interface_number: u32,
+        processor_uid: u32,
+        flags: u32,
+        mpidr: u64,
+    },
+    /// The local interrupt controller of a hart on riscv64 systems.
+    Rintc {
+        flags: u32,
+        hart_id: u64,
+        processor_uid: u32,
+    },
+    /// An entry type that Ion does not parse.
+    Unknown(u8),
+}
+
+/// The flag of the local APIC entries marking the processor as enabled.
+pub const MADT_LAPIC_ENABLED: u32 = 1 << 0;
+/// The flag of the local APIC entries marking the processor as able to be enabled at
+/// runtime.
+pub const MADT_LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// Typed view of the multiple APIC description table, describing the interrupt
+/// controllers and the processors of the system.
+pub struct Madt {
+    header: &'static SdtHeader,
+}
+
+impl Madt {
+    /// Returns the MADT, if the firmware provides it.
+    pub fn get() -> Option<Self> {
+        let header = find_table(b"APIC")?;
+
+        if (header.length as usize) < mem::size_of::<SdtHeader>() + 8 {
+            return None;
+        }
+
+        Some(Self { header })
+    }
+
+    /// Returns the physical address of the local APIC. This might be overridden by a
+    /// [`MadtEntry::LocalApicAddressOverride`] entry.
+    pub fn local_apic_address(&self) -> u64 {
+        let overridden = self.entries().find_map(|entry| match entry {
+            MadtEntry::LocalApicAddressOverride { address } => Some(address),
+            _ => None,
+        });
+
+        overridden.unwrap_or_else(|| read_u32(self.header.data(), 0) as u64)
+    }
+
+    /// Returns true if the system also has dual 8259 PICs, which have to be masked
+    /// before using the APIC.
+    pub fn has_8259_pics(&self) -> bool {
+        read_u32(self.header.data(), 4) & 1 != 0
+    }
+
+    /// Returns an iterator over the entries of the MADT.
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> {
+        let data = &self.header.data()[8..];
+        let mut offset = 0;
+
+        core::iter::from_fn(move || {
+            let entry_type = *data.get(offset)?;
+            let length = *data.get(offset + 1)? as usize;
+
+            // Stop at malformed entries, as the following entries cannot be located.
+            if length < 2 || offset + length > data.len() {
+                return None;
+            }
+
+            let entry = &data[offset..offset + length];
+            offset += length;
+
+            let parsed = match (entry_type, length) {
+                (0, 8..=usize::MAX) => MadtEntry::LocalApic {
+                    processor_id: entry[2],
+                    apic_id: entry[3],
+                    flags: read_u32(entry, 4),
+                },
+
+                (1, 12..=usize::MAX) => MadtEntry::IoApic {
+                    id: entry[2],
+                    address: read_u32(entry, 4),
+                    gsi_base: read_u32(entry, 8),
+                },
+
+                (2, 10..=usize::MAX) => MadtEntry::InterruptSourceOverride {
+                    bus: entry[2],
+                    source: entry[3],
+                    gsi: read_u32(entry, 4),
+                    flags: read_u16(entry, 8),
+                },
+
+                (4, 6..=usize::MAX) => MadtEntry::LocalApicNmi {
+                    processor_id: entry[2],
+                    flags: read_u16(entry, 3),
+                    lint: entry[5],
+                },
+
+                (5, 12..=usize::MAX) => MadtEntry::LocalApicAddressOverride {
+                    address: read_u64(entry, 4),
+                },
+
+                (9, 16..=usize::MAX) => MadtEntry::LocalX2Apic {
+                    x2apic_id: read_u32(entry, 4),
+                    flags: read_u32(entry, 8),
+                    processor_uid: read_u32(entry, 12),
+                },
+
+                (11, 76..=usize::MAX) => MadtEntry::Gicc {
+                    cpu_interface_number: read_u32(entry, 4),
+                    processor_uid: read_u32(entry, 8),
+                    flags: read_u32(entry, 12),
+                    mpidr: read_u64(entry, 68),
+                },
+
+                (0x18, 20..=usize::MAX) => MadtEntry::Rintc {
+                    flags: read_u32(entry, 4),
+                    hart_id: read_u64(entry, 8),
+                    processor_uid: read_u32(entry, 16),
+                },
+
+                (entry_type, _) => MadtEntry::Unknown(entry_type),
+            };
+
+            Some(parsed)
+        })
+    }
+
+    /// Returns the amount of processors that are enabled or can be enabled.
+    pub fn processor_count(&self) -> usize {
+        let usable = MADT_LAPIC_ENABLED | MADT_LAPIC_ONLINE_CAPABLE;
+
+        self.entries()
+            .filter(|entry| match entry {
+                MadtEntry::LocalApic { flags, .. }
+                | MadtEntry::LocalX2Apic { flags, .. }
+                | MadtEntry::Gicc { flags, .. }
+                | MadtEntry::Rintc { flags, .. } => flags & usable != 0,
+                _ => false,
+            })
+            .count()
+    }
+}
+
+/// The flag of the ARM boot architecture flags of the FADT marking the platform as PSCI
+/// compliant.
+pub const FADT_ARM_PSCI_COMPLIANT: u16 = 1 << 0;
+/// The flag of the ARM boot architecture flags of the FADT requesting PSCI calls to use
+/// HVC instead of SMC.
+pub const FADT_ARM_PSCI_USE_HVC: u16 = 1 << 1;
+
+/// Typed view of the fixed ACPI description table. Only the fields that Ion uses are
+/// exposed.
+pub struct Fadt {
+    header: &'static SdtHeader,
+}
+
+impl Fadt {
+    /// Returns the FADT, if the firmware provides it.
+    pub fn get() -> Option<Self> {
+        let header = find_table(b"FACP")?;
+        Some(Self { header })
+    }
+
+    /// Returns the ARM boot architecture flags, which are 0 if the table predates them.
+    pub fn arm_boot_flags(&self) -> u16 {
+        let data = self.header.data();
+
+        if data.len() < 95 {
+            return 0;
+        }
+
+        read_u16(data, 93)
+    }
+}
+
+/// An ACPI generic address structure.
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    /// The address space of the register, 0 for system memory and 1 for system I/O.
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// Typed view of the high precision event timer description table.
+pub struct Hpet {
+    header: &'static SdtHeader,
+}
+
+impl Hpet {
+    /// Returns the HPET table, if the firmware provides it.
+    pub fn get() -> Option<Self> {
+        let header = find_table(b"HPET")?;
+
+        if (header.length as usize) < mem::size_of::<SdtHeader>() + 20 {
+            return None;
+        }
+
+        Some(Self { header })
+    }
+
+    /// Returns the hardware ID of the event timer block, which includes the amount of
+    /// comparators and the vendor ID.
+    pub fn event_timer_block_id(&self) -> u32 {
+        read_u32(self.header.data(), 0)
+    }
+
+    /// Returns the address of the HPET registers.
+    pub fn base_address(&self) -> GenericAddress {
+        let data = self.header.data();
+
+        GenericAddress {
+            address_space: data[4],
+            bit_width: data[5],
+            bit_offset: data[6],
+            access_size: data[7],
+            address: read_u64(data, 8),
+        }
+    }
+
+    /// Returns the sequence number of the HPET block.
+    pub fn hpet_number(&self) -> u8 {
+        self.header.data()[16]
+    }
+
+    /// Returns the minimum amount of ticks that can be used in periodic mode without
+    /// losing interrupts.
+    pub fn minimum_tick(&self) -> u16 {
+        read_u16(self.header.data(), 17)
+    }
+}
+
+/// An entry of the MCFG describing the ECAM region of a PCI segment group.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    /// The physical address of the ECAM region.
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Typed view of the PCI Express memory mapped configuration space table.
+pub struct Mcfg {
+    header: &'static SdtHeader,
+}
+
+impl Mcfg {
+    /// The size of each configuration space entry in bytes.
+    const ENTRY_SIZE: usize = 16;
+
+    /// Returns the MCFG, if the firmware provides it.
+    pub fn get() -> Option<Self> {
+        let header = find_table(b"MCFG")?;
+
+        if (header.length as usize) < mem::size_of::<SdtHeader>() + 8 {
+            return None;
+        }
+
+        Some(Self { header })
+    }
+
+    /// Returns an iterator over the ECAM regions of all of the PCI segment groups.
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> {
+        // The entries follow 8 reserved bytes.
+        self.header.data()[8..]
+            .chunks_exact(Self::ENTRY_SIZE)
+            .map(|entry| McfgEntry {
+                base_address: read_u64(entry, 0),
+                segment_group: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+    }
+}
+
+/// The flag of the BGRT status marking the image as currently displayed on the screen.
+pub const BGRT_DISPLAYED: u8 = 1 << 0;
+/// The image type of the BGRT for bitmap images, which is the only defined type.
+const BGRT_IMAGE_BITMAP: u8 = 0;
+
+/// Typed view of the boot graphics resource table, describing the logo that the firmware
+/// has drawn on the screen while booting.
+pub struct Bgrt {
+    header: &'static SdtHeader,
+}
+
+impl Bgrt {
+    /// Returns the BGRT, if the firmware provides it.
+    pub fn get() -> Option<Self> {
+        let header = find_table(b"BGRT")?;
+
+        if (header.length as usize) < mem::size_of::<SdtHeader>() + 20 {
+            return None;
+        }
+
+        Some(Self { header })
+    }
+
+    /// Returns true if the logo is still displayed on the screen without being rotated.
+    /// The orientation is stored in bits 1 and 2 of the status since ACPI 6.2.
+    pub fn is_displayed(&self) -> bool {
+        self.header.data()[2] & 0b111 == BGRT_DISPLAYED
+    }
+
+    /// Returns the bitmap file of the logo, if it is a bitmap.
+    pub fn image(&self) -> Option<&'static [u8]> {
+        let data = self.header.data();
+        let address = read_u64(data, 4);
+
+        if data[3] != BGRT_IMAGE_BITMAP || address == 0 {
+            return None;
+        }
+
+        // SAFETY: The firmware keeps the image in memory until the boot services are
+        // exited. The size of the file is stored at offset 2 of the bitmap file header.
+        unsafe {
+            let size = core::ptr::read_unaligned((address + 2) as *const u32);
+            Some(core::slice::from_raw_parts(
+                address as *const u8,
+                size as usize,
+            ))
+        }
+    }
+
+    /// Returns the position of the top left corner of the logo in pixels.
+    pub fn offset(&self) -> (usize, usize) {
+        let data = self.header.data();
+        (read_u32(data, 12) as usize, read_u32(data, 16) as usize)
+    }
+}
+
+/// Logs a summary of the ACPI tables that Ion uses.
+pub fn log_summary() {
+    if let Some(madt) = Madt::get() {
+        log::debug!(
+            "acpi: {} processors, local APIC at {:#x}",
+            madt.processor_count(),
+            madt.local_apic_address()
+        );
+    }
+
+    if let Some(hpet) = Hpet::get() {
+        log::debug!("acpi: HPET at {:#x}", hpet.base_address().address);
+    }
+
+    if let Some(bgrt) = Bgrt::get() {
+        let (x, y) = bgrt.offset();
+        log::debug!(
+            "acpi: boot logo at {},{} (displayed: {})",
+            x,
+            y,
+            bgrt.is_displayed()
+        );
+    }
+
+    if let Some(mcfg) = Mcfg::get() {
+        for entry in mcfg.entries() {
+            log::debug!(
+                "acpi: PCI segment {} (buses {}-{}) at {:#x}",
+                entry.segment_group,
+                entry.start_bus,
+                entry.end_bus,
+                entry.base_address
+            );
+        }
+    }
+}
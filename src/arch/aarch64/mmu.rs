@@ -0,0 +1,259 @@
+use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+
+/// The size of a page with the 4 KiB translation granule.
+pub const PAGE_SIZE: u64 = 0x1000;
+/// The size of a level 2 block.
+const BLOCK_SIZE: u64 = 0x200000;
+
+/// The MAIR_EL1 value used for the kernel address space. Attribute 0 is normal write-back
+/// memory, attribute 1 is device nGnRnE memory and attribute 2 is normal non-cacheable
+/// memory, which is used for write-combining mappings.
+pub const MAIR: u64 = 0x44_00_ff;
+
+/// The SCTLR_EL1 value used for the kernel: the reserved-one bits, the MMU and both the
+/// data and the instruction caches are enabled, and the kernel is entered little endian.
+pub const SCTLR: u64 = 0x30d0_0800 | (1 << 0) | (1 << 2) | (1 << 12);
+
+const DESC_VALID: u64 = 1 << 0;
+/// Marks the descriptor as a table descriptor, or as a page descriptor on level 3.
+const DESC_TABLE: u64 = 1 << 1;
+const DESC_ATTR_INDEX_SHIFT: u64 = 2;
+const DESC_READ_ONLY: u64 = 1 << 7;
+const DESC_INNER_SHAREABLE: u64 = 0b11 << 8;
+const DESC_ACCESSED: u64 = 1 << 10;
+const DESC_PRIVILEGED_EXECUTE_NEVER: u64 = 1 << 53;
+const DESC_UNPRIVILEGED_EXECUTE_NEVER: u64 = 1 << 54;
+const DESC_ADDRESS_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// The memory type of a mapping, selecting an attribute of [`MAIR`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Normal,
+    Device,
+    WriteCombining,
+}
+
+/// The attributes of a mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes {
+    pub kind: MemoryKind,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl Attributes {
+    pub const DATA: Self = Self {
+        kind: MemoryKind::Normal,
+        writable: true,
+        executable: false,
+    };
+
+    pub const DEVICE: Self = Self {
+        kind: MemoryKind::Device,
+        writable: true,
+        executable: false,
+    };
+
+    fn bits(&self) -> u64 {
+        let index = match self.kind {
+            MemoryKind::Normal => 0,
+            MemoryKind::Device => 1,
+            MemoryKind::WriteCombining => 2,
+        };
+
+        let mut bits = DESC_VALID
+            | DESC_ACCESSED
+            | DESC_INNER_SHAREABLE
+            | DESC_UNPRIVILEGED_EXECUTE_NEVER
+            | (index << DESC_ATTR_INDEX_SHIFT);
+
+        if !self.writable {
+            bits |= DESC_READ_ONLY;
+        }
+
+        if !self.executable {
+            bits |= DESC_PRIVILEGED_EXECUTE_NEVER;
+        }
+
+        bits
+    }
+}
+
+/// Allocates a zeroed translation table and returns its physical address.
+fn allocate_table<I>(frame_allocator: &mut BootFrameAllocator<I>) -> u64
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let address = frame_allocator
+        .allocate_frame()
+        .expect("mmu: failed to allocate a translation table")
+        .start_address()
+        .as_u64();
+
+    // SAFETY: UEFI identity-maps all memory and the frame is not used by anything else.
+    unsafe { core::ptr::write_bytes(address as *mut u64, 0, 512) };
+
+    address
+}
+
+/// A four-level translation table with the 4 KiB granule, covering 48 bits of virtual
+/// address space. The kernel address space uses one for each half (TTBR0_EL1 and
+/// TTBR1_EL1).
+pub struct TranslationTable {
+    root: u64,
+}
+
+impl TranslationTable {
+    pub fn new<I>(frame_allocator: &mut BootFrameAllocator<I>) -> Self
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        Self {
+            root: allocate_table(frame_allocator),
+        }
+    }
+
+    /// Returns the physical address of the level 0 table.
+    #[inline]
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// Returns the descriptor for the provided virtual address at the provided level,
+    /// creating the intermediate tables if required.
+    fn descriptor<I>(
+        &mut self,
+        virt: u64,
+        level: usize,
+        frame_allocator: &mut BootFrameAllocator<I>,
+    ) -> &mut u64
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let mut table = self.root;
+
+        for current in 0..=level {
+            let index = ((virt >> (39 - 9 * current)) & 0x1ff) as usize;
+
+            // SAFETY: The tables are identity-mapped and only accessed through the mutable
+            // reference to the translation table.
+            let descriptor = unsafe { &mut *(table as *mut u64).add(index) };
+
+            if current == level {
+                return descriptor;
+            }
+
+            if *descriptor & DESC_VALID == 0 {
+                *descriptor = allocate_table(frame_allocator) | DESC_VALID | DESC_TABLE;
+            }
+
+            assert!(
+                *descriptor & DESC_TABLE != 0,
+                "mmu: {:#x} is already mapped by a block",
+                virt
+            );
+
+            table = *descriptor & DESC_ADDRESS_MASK;
+        }
+
+        unreachable!()
+    }
+
+    /// Maps `size` bytes of physical memory starting at `phys` to `virt` with the provided
+    /// attributes. The parts of the range that are 2 MiB aligned both physically and
+    /// virtually are mapped using level 2 blocks.
+    pub fn map<I>(
+        &mut self,
+        virt: u64,
+        phys: u64,
+        size: u64,
+        attributes: Attributes,
+        frame_allocator: &mut BootFrameAllocator<I>,
+    ) where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let misalignment = virt & (PAGE_SIZE - 1);
+        assert_eq!(
+            misalignment,
+            phys & (PAGE_SIZE - 1),
+            "mmu: the addresses have different page offsets"
+        );
+
+        let virt = virt - misalignment;
+        let phys = phys - misalignment;
+        let size = (size + misalignment + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let bits = attributes.bits();
+
+        let mut offset = 0;
+
+        while offset < size {
+            let (virt, phys) = (virt + offset, phys + offset);
+
+            if (virt | phys) & (BLOCK_SIZE - 1) == 0 && size - offset >= BLOCK_SIZE {
+                let descriptor = self.descriptor(virt, 2, frame_allocator);
+
+                if *descriptor & DESC_VALID == 0 {
+                    *descriptor = phys | bits;
+                    offset += BLOCK_SIZE;
+
+                    continue;
+                }
+            }
+
+            let descriptor = self.descriptor(virt, 3, frame_allocator);
+            assert!(
+                *descriptor & DESC_VALID == 0,
+                "mmu: {:#x} is already mapped",
+                virt
+            );
+
+            *descriptor = phys | bits | DESC_TABLE;
+            offset += PAGE_SIZE;
+        }
+    }
+}
+
+/// Returns the TCR_EL1 value used for the kernel address space: 48-bit virtual addresses
+/// and the 4 KiB granule in both halves, with write-back cacheable and inner shareable
+/// table walks. The physical address size is the one supported by the processor.
+pub fn tcr() -> u64 {
+    let mmfr0: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0, options(nomem, nostack));
+    }
+
+    // Physical address sizes above 48 bits require the 52-bit descriptor format.
+    let physical_address_size = (mmfr0 & 0xf).min(0b101);
+
+    let half = 16 | (0b01 << 8) | (0b01 << 10) | (0b11 << 12);
+    let granule_4kib_upper = 0b10 << 30;
+
+    half | (half << 16) | granule_4kib_upper | (physical_address_size << 32)
+}
+
+/// Cleans the data cache lines covering the provided range to the point of coherency, so
+/// that a processor with its MMU disabled sees the data.
+pub fn clean_data_cache(address: u64, size: usize) {
+    let ctr: u64;
+
+    unsafe {
+        asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack));
+    }
+
+    let line_size = 4 << ((ctr >> 16) & 0xf);
+    let mut line = address & !(line_size - 1);
+
+    while line < address + size as u64 {
+        unsafe { asm!("dc cvac, {}", in(reg) line, options(nostack)) };
+        line += line_size;
+    }
+
+    unsafe { asm!("dsb sy", options(nostack)) };
+}
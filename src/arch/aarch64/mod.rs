@@ -0,0 +1,182 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+use crate::prelude::*;
+
+pub mod mmu;
+pub mod psci;
+pub mod smp;
+
+/// The translation tables of the kernel address space. The firmware keeps using its own
+/// translation tables until the kernel is entered, as UEFI identity-maps all memory.
+pub struct BootPageTables {
+    /// The translation table of the lower half (TTBR0_EL1), which identity-maps the
+    /// physical memory.
+    pub lower: mmu::TranslationTable,
+    /// The translation table of the higher half (TTBR1_EL1), which contains the kernel and
+    /// the direct map.
+    pub upper: mmu::TranslationTable,
+}
+
+/// Helper function to create the translation tables for the kernel itself.
+pub fn setup_boot_paging<I>(frame_allocator: &mut BootFrameAllocator<I>) -> BootPageTables
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    BootPageTables {
+        lower: mmu::TranslationTable::new(frame_allocator),
+        upper: mmu::TranslationTable::new(frame_allocator),
+    }
+}
+
+/// Masks all exceptions and halts the processor forever.
+pub fn halt() -> ! {
+    unsafe {
+        asm!("msr daifset, #0xf");
+
+        loop {
+            asm!("wfi");
+        }
+    }
+}
+
+/// The offset of the frame record (the frame pointer of the previous frame followed by the
+/// return address) below the frame pointer.
+pub const FRAME_RECORD_OFFSET: u64 = 0;
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let x29: u64;
+
+    unsafe {
+        asm!("mov {}, x29", out(reg) x29, options(nomem, nostack));
+    }
+
+    x29
+}
+
+/// Returns the current value of the virtual counter of the generic timer.
+#[inline]
+pub fn read_counter() -> u64 {
+    let value: u64;
+
+    // SAFETY: The virtual counter is always accessible at EL1 and EL2. The ISB prevents the
+    // counter from being read speculatively.
+    unsafe {
+        asm!("isb; mrs {}, cntvct_el0", out(reg) value, options(nomem, nostack));
+    }
+
+    value
+}
+
+/// Returns the frequency of the counter in Hz, if it can be discovered. The firmware is
+/// required to program CNTFRQ_EL0, although some firmware leaves it zero.
+pub fn counter_frequency() -> Option<u64> {
+    let frequency: u64;
+
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) frequency, options(nomem, nostack));
+    }
+
+    Some(frequency).filter(|&frequency| frequency != 0)
+}
+
+/// Returns true if the counter runs at a constant rate, regardless of the power state of
+/// the processor. This is always the case for the generic timer.
+pub fn counter_invariant() -> bool {
+    true
+}
+
+/// Reads a 64-bit value using RNDR, which fails if the entropy source is not able to
+/// provide a value in a reasonable amount of time.
+fn rndr() -> Option<u64> {
+    let (value, success): (u64, u64);
+
+    unsafe {
+        asm!(
+            "mrs {}, s3_3_c2_c4_0",
+            "cset {}, ne",
+            out(reg) value,
+            out(reg) success,
+            options(nomem, nostack),
+        );
+    }
+
+    if success != 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Calls the provided function with up to `count` values of the hardware random number
+/// generator of the processor (RNDR), if it implements FEAT_RNG.
+pub fn hardware_random<F>(count: usize, f: F)
+where
+    F: FnMut(u64),
+{
+    let isar0: u64;
+
+    unsafe {
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack));
+    }
+
+    if (isar0 >> 60) & 0xf != 0 {
+        (0..count).filter_map(|_| rndr()).for_each(f);
+    }
+}
+
+/// Returns the current exception level.
+pub fn current_el() -> u64 {
+    let current_el: u64;
+
+    unsafe {
+        asm!("mrs {}, CurrentEL", out(reg) current_el, options(nomem, nostack));
+    }
+
+    (current_el >> 2) & 0b11
+}
+
+/// Returns the features of the processor that entries can require with `REQUIRES=`. The
+/// features are only reported on x86, so entries that require any of them cannot run here.
+pub fn cpu_features() -> ion_core::cpu::Features {
+    ion_core::cpu::Features::empty()
+}
+
+/// Returns a summary of the processor. Only the exception level is reported, as the
+/// identification registers differ between the implementations.
+pub fn cpu_summary() -> Vec<String> {
+    vec![format!("Exception level: EL{}", current_el())]
+}
+
+/// Prints the program counter, the stack pointer and the system registers of the MMU.
+pub fn print_registers() {
+    let (pc, sp): (u64, u64);
+
+    unsafe {
+        asm!("adr {}, .", out(reg) pc, options(nomem, nostack));
+        asm!("mov {}, sp", out(reg) sp, options(nomem, nostack));
+    }
+
+    let (sctlr, ttbr0, ttbr1, tcr, mair): (u64, u64, u64, u64, u64);
+
+    // NOTE: The firmware runs at either EL1 or EL2. The EL1 registers are only meaningful
+    // in the former case.
+    unsafe {
+        asm!("mrs {}, sctlr_el1", out(reg) sctlr, options(nomem, nostack));
+        asm!("mrs {}, ttbr0_el1", out(reg) ttbr0, options(nomem, nostack));
+        asm!("mrs {}, ttbr1_el1", out(reg) ttbr1, options(nomem, nostack));
+        asm!("mrs {}, tcr_el1", out(reg) tcr, options(nomem, nostack));
+        asm!("mrs {}, mair_el1", out(reg) mair, options(nomem, nostack));
+    }
+
+    println!("PC:    {:#018x}    SP:    {:#018x}", pc, sp);
+    println!("EL:    {:<18}    SCTLR: {:#018x}", current_el(), sctlr);
+    println!("TTBR0: {:#018x}    TTBR1: {:#018x}", ttbr0, ttbr1);
+    println!("TCR:   {:#018x}    MAIR:  {:#018x}", tcr, mair);
+}
@@ -0,0 +1,95 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::acpi;
+
+const PSCI_VERSION: u32 = 0x8400_0000;
+const PSCI_CPU_ON_64: u32 = 0xc400_0003;
+
+/// Set if the PSCI calls have to use HVC instead of SMC.
+static USE_HVC: AtomicBool = AtomicBool::new(false);
+
+/// An error returned by the PSCI firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    Unknown(i64),
+}
+
+impl Error {
+    fn from_code(code: i64) -> Self {
+        match code {
+            -1 => Self::NotSupported,
+            -2 => Self::InvalidParameters,
+            -3 => Self::Denied,
+            -4 => Self::AlreadyOn,
+            -5 => Self::OnPending,
+            -6 => Self::InternalFailure,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Issues a PSCI call using the provided instruction. The SMC calling convention allows
+/// the firmware to clobber X4 to X17.
+macro_rules! psci_call {
+    ($instruction:literal, $function:expr, $arg0:expr, $arg1:expr, $arg2:expr) => {{
+        let result: u64;
+
+        asm!(
+            $instruction,
+            inout("x0") $function as u64 => result,
+            inout("x1") $arg0 => _,
+            inout("x2") $arg1 => _,
+            inout("x3") $arg2 => _,
+            out("x4") _, out("x5") _, out("x6") _, out("x7") _,
+            out("x8") _, out("x9") _, out("x10") _, out("x11") _,
+            out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+            out("x16") _, out("x17") _,
+            options(nostack),
+        );
+
+        result as i64
+    }};
+}
+
+/// Calls the PSCI function with the provided arguments through the configured conduit.
+fn call(function: u32, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    // SAFETY: The PSCI functions that Ion uses do not affect the current processor.
+    unsafe {
+        if USE_HVC.load(Ordering::Relaxed) {
+            psci_call!("hvc #0", function, arg0, arg1, arg2)
+        } else {
+            psci_call!("smc #0", function, arg0, arg1, arg2)
+        }
+    }
+}
+
+/// This function is responsible for selecting the PSCI conduit using the ARM boot
+/// architecture flags of the FADT. Without ACPI, SMC is used. Must be called after
+/// [`acpi::init`].
+pub fn init() {
+    let flags = acpi::Fadt::get().map_or(0, |fadt| fadt.arm_boot_flags());
+
+    if flags & acpi::FADT_ARM_PSCI_COMPLIANT == 0 {
+        log::warn!("psci: the firmware does not report PSCI support");
+    }
+
+    USE_HVC.store(flags & acpi::FADT_ARM_PSCI_USE_HVC != 0, Ordering::Relaxed);
+
+    let version = call(PSCI_VERSION, 0, 0, 0);
+    log::debug!("psci: version {}.{}", version >> 16, version & 0xffff);
+}
+
+/// Starts the processor with the provided MPIDR at the provided physical entry point. The
+/// processor enters with its MMU disabled and the provided context ID in X0.
+pub fn cpu_on(mpidr: u64, entry_point: u64, context_id: u64) -> Result<(), Error> {
+    match call(PSCI_CPU_ON_64, mpidr, entry_point, context_id) {
+        0 => Ok(()),
+        code => Err(Error::from_code(code)),
+    }
+}
@@ -0,0 +1,206 @@
+use core::sync::atomic::{self, Ordering};
+
+use super::{mmu, psci, BootPageTables};
+use crate::acpi::{self, MadtEntry};
+use crate::time;
+
+/// The flag of the GICC entries marking the processor as enabled.
+const GICC_ENABLED: u32 = 1 << 0;
+
+/// The bits of MPIDR_EL1 holding the affinity fields.
+const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// The amount of milliseconds that a processor is given to reach the kernel address space.
+const STARTUP_TIMEOUT_MS: u64 = 1000;
+
+/// The per-CPU information passed to the kernel (`struct stivale2_smp_info`). The
+/// application processors spin until the kernel writes `goto_address`, at which point
+/// they load `target_stack` and jump to it with the address of their information in X0.
+#[repr(C)]
+pub struct SmpInfo {
+    pub processor_id: u32,
+    pub gic_iface_no: u32,
+    pub mpidr: u64,
+    pub target_stack: u64,
+    pub goto_address: u64,
+    pub extra_argument: u64,
+}
+
+/// Returns the ACPI processor UID, the GIC CPU interface number and the MPIDR of every
+/// enabled processor.
+pub fn processors() -> impl Iterator<Item = (u32, u32, u64)> {
+    acpi::Madt::get()
+        .into_iter()
+        .flat_map(|madt| madt.entries())
+        .filter_map(|entry| match entry {
+            MadtEntry::Gicc {
+                cpu_interface_number,
+                processor_uid,
+                flags,
+                mpidr,
+            } if flags & GICC_ENABLED != 0 => Some((
+                processor_uid,
+                cpu_interface_number,
+                mpidr & MPIDR_AFFINITY_MASK,
+            )),
+
+            _ => None,
+        })
+}
+
+/// Returns the affinity fields of the MPIDR of the current processor.
+pub fn current_mpidr() -> u64 {
+    let mpidr: u64;
+
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack));
+    }
+
+    mpidr & MPIDR_AFFINITY_MASK
+}
+
+// The entry point of the application processors, which PSCI enters with the MMU disabled
+// and the address of the startup data of the processor in X0. Processors that are entered
+// at EL2 drop to EL1 first. The processor then loads the translation regime of the kernel
+// from the startup data, reports that it has booted and waits until the bootstrap
+// processor provides its information. Afterwards it spins until the kernel writes its
+// goto address. The offsets have to match `StartupData` and `SmpInfo`.
+global_asm!(
+    r#"
+.global SMP_AP_ENTRY
+
+SMP_AP_ENTRY:
+    mrs x1, CurrentEL
+    lsr x1, x1, #2
+    cmp x1, #2
+    b.ne 1f
+
+    // Run EL1 in AArch64 state, give it access to the timer and enter it with all of the
+    // exceptions masked.
+    mov x1, #(1 << 31)
+    msr hcr_el2, x1
+    mov x1, #3
+    msr cnthctl_el2, x1
+    msr cntvoff_el2, xzr
+    mov x1, #0x3c5
+    msr spsr_el2, x1
+    adr x1, 1f
+    msr elr_el2, x1
+    eret
+
+1:
+    ldr x1, [x0, #0]
+    msr mair_el1, x1
+    ldr x1, [x0, #8]
+    msr tcr_el1, x1
+    ldr x1, [x0, #16]
+    msr ttbr0_el1, x1
+    ldr x1, [x0, #24]
+    msr ttbr1_el1, x1
+    isb
+    tlbi vmalle1
+    dsb nsh
+    isb
+
+    // Do not trap the floating point and SIMD instructions.
+    mov x1, #(3 << 20)
+    msr cpacr_el1, x1
+
+    ldr x1, [x0, #32]
+    msr sctlr_el1, x1
+    isb
+
+    mov x1, #1
+    str x1, [x0, #48]
+
+    // Wait until the bootstrap processor provides the information of this processor.
+2:
+    yield
+    ldr x1, [x0, #40]
+    cbz x1, 2b
+
+    // Wait until the kernel writes the goto address of this processor.
+3:
+    yield
+    ldr x2, [x1, #24]
+    cbz x2, 3b
+
+    ldr x3, [x1, #16]
+    mov sp, x3
+    mov x0, x1
+    mov x29, xzr
+    mov x30, xzr
+    br x2
+"#
+);
+
+extern "C" {
+    static SMP_AP_ENTRY: u8;
+}
+
+/// The data that an application processor is started with. The layout has to match the
+/// offsets used in the `global_asm!` block above. It is read with the MMU disabled, so it
+/// has to be cleaned to the point of coherency before the processor is started.
+#[repr(C)]
+pub struct StartupData {
+    mair: u64,
+    tcr: u64,
+    ttbr0: u64,
+    ttbr1: u64,
+    sctlr: u64,
+    info: u64,
+    booted: u64,
+}
+
+impl StartupData {
+    /// Creates the startup data for entering the kernel address space.
+    pub fn new(page_tables: &BootPageTables) -> Self {
+        Self {
+            mair: mmu::MAIR,
+            tcr: mmu::tcr(),
+            ttbr0: page_tables.lower.root(),
+            ttbr1: page_tables.upper.root(),
+            sctlr: mmu::SCTLR,
+            info: 0,
+            booted: 0,
+        }
+    }
+}
+
+/// Starts the processor with the provided MPIDR, which parks itself in the kernel address
+/// space until the kernel writes the goto address of the provided information (mapped at
+/// the same address in both address spaces). The startup data must be identity-mapped and
+/// not used for any other processor. Returns false if the processor did not start, in
+/// which case it never receives the information.
+pub fn start(mpidr: u64, data: &mut StartupData, info: &SmpInfo) -> bool {
+    let address = data as *mut StartupData as u64;
+    mmu::clean_data_cache(address, core::mem::size_of::<StartupData>());
+
+    // SAFETY: Only the address of the entry point is taken.
+    let entry = unsafe { &SMP_AP_ENTRY } as *const u8 as u64;
+
+    if let Err(error) = psci::cpu_on(mpidr, entry, address) {
+        log::warn!("smp: failed to start MPIDR {:#x} ({:?})", mpidr, error);
+        return false;
+    }
+
+    let start = time::timestamp_ms();
+    let booted = core::ptr::addr_of!(data.booted);
+
+    while time::elapsed_ms(start) < STARTUP_TIMEOUT_MS {
+        // SAFETY: The processor writes the field with its caches enabled.
+        if unsafe { booted.read_volatile() } != 0 {
+            // The information has to be visible before its address.
+            atomic::fence(Ordering::Release);
+
+            let info_field = core::ptr::addr_of_mut!(data.info);
+            unsafe { info_field.write_volatile(info as *const SmpInfo as u64) };
+
+            return true;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    false
+}
@@ -0,0 +1,194 @@
+use core::arch::x86::_rdtsc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use raw_cpuid::CpuId;
+use x86_64::structures::paging::*;
+use x86_64::VirtAddr;
+
+use crate::prelude::*;
+
+/// The amount of times RDSEED is retried, as it fails if its entropy pool is exhausted.
+const RDSEED_RETRIES: usize = 16;
+
+/// The page tables of the kernel address space. IA-32 firmware runs with paging disabled
+/// or identity-mapped 32-bit paging, so Ion keeps using the firmware's address space until
+/// it enters long mode right before jumping to the kernel. All of the page tables are
+/// allocated below 4 GiB, so that they are accessible with 32-bit addresses.
+pub struct BootPageTables {
+    /// Provides access to the page tables of the kernel address space (not active).
+    pub kernel: OffsetPageTable<'static>,
+    /// The physical frame where the level 4 page table of the kernel address space is stored.
+    pub kernel_level_4_frame: PhysFrame,
+}
+
+/// Helper function to create a new page table for the kernel itself.
+pub fn setup_boot_paging(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> BootPageTables {
+    // NOTE: UEFI identity-maps all memory, so the offset between physical
+    // and virtual addresses is 0.
+    let off = VirtAddr::zero();
+
+    let frame: PhysFrame = frame_allocator
+        .allocate_frame()
+        .expect("mm: no unused frames");
+
+    let ptr: *mut PageTable = (off + frame.start_address().as_u64()).as_mut_ptr();
+
+    let level_4_table = unsafe {
+        ptr.write(PageTable::new());
+        &mut *ptr
+    };
+
+    BootPageTables {
+        kernel: unsafe { OffsetPageTable::new(level_4_table, off) },
+        kernel_level_4_frame: frame,
+    }
+}
+
+/// Disables the interrupts and halts the processor forever.
+pub fn halt() -> ! {
+    unsafe {
+        asm!("cli");
+
+        loop {
+            asm!("hlt");
+        }
+    }
+}
+
+/// The offset of the frame record (the frame pointer of the previous frame followed by the
+/// return address) below the frame pointer.
+pub const FRAME_RECORD_OFFSET: u64 = 0;
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let ebp: u32;
+
+    unsafe {
+        asm!("mov {}, ebp", out(reg) ebp, options(nomem, nostack));
+    }
+
+    ebp as u64
+}
+
+/// Returns the current value of the time stamp counter.
+#[inline]
+pub fn read_counter() -> u64 {
+    // SAFETY: Every processor that is able to run UEFI firmware has a TSC.
+    unsafe { _rdtsc() }
+}
+
+/// Returns the frequency of the counter in Hz, if it can be discovered. The frequency of
+/// the TSC is not reported reliably, so it has to be calibrated.
+pub fn counter_frequency() -> Option<u64> {
+    None
+}
+
+/// Returns true if the counter runs at a constant rate, regardless of the power state of
+/// the processor.
+pub fn counter_invariant() -> bool {
+    CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .map_or(false, |info| info.has_invariant_tsc())
+}
+
+/// Reads a 32-bit value using RDSEED, retrying if its entropy pool is exhausted.
+fn rdseed() -> Option<u32> {
+    for _ in 0..RDSEED_RETRIES {
+        let (value, success): (u32, u8);
+
+        unsafe {
+            asm!(
+                "rdseed {}; setc {}",
+                out(reg) value,
+                out(reg_byte) success,
+                options(nomem, nostack),
+            );
+        }
+
+        if success != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Reads a 32-bit value using RDRAND.
+fn rdrand() -> Option<u32> {
+    let (value, success): (u32, u8);
+
+    unsafe {
+        asm!(
+            "rdrand {}; setc {}",
+            out(reg) value,
+            out(reg_byte) success,
+            options(nomem, nostack),
+        );
+    }
+
+    if success != 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Calls the provided function with up to `count` values of each hardware random number
+/// generator of the processor (RDSEED and RDRAND). The instructions only provide 32 bits
+/// in protected mode, so two of them are combined into each value.
+pub fn hardware_random<F>(count: usize, mut f: F)
+where
+    F: FnMut(u64),
+{
+    let combine =
+        |generator: fn() -> Option<u32>| Some(((generator()? as u64) << 32) | generator()? as u64);
+
+    let cpuid = CpuId::new();
+
+    let rdseed_supported = cpuid
+        .get_extended_feature_info()
+        .map_or(false, |info| info.has_rdseed());
+
+    if rdseed_supported {
+        (0..count).filter_map(|_| combine(rdseed)).for_each(&mut f);
+    }
+
+    let rdrand_supported = cpuid
+        .get_feature_info()
+        .map_or(false, |info| info.has_rdrand());
+
+    if rdrand_supported {
+        (0..count).filter_map(|_| combine(rdrand)).for_each(&mut f);
+    }
+}
+
+/// Returns the features of the processor that entries can require with `REQUIRES=`.
+pub fn cpu_features() -> ion_core::cpu::Features {
+    crate::cpuid::features()
+}
+
+/// Returns a summary of the identification and the features of the processor.
+pub fn cpu_summary() -> Vec<String> {
+    crate::cpuid::summary()
+}
+
+/// Prints the instruction pointer, the stack pointer and the control registers.
+pub fn print_registers() {
+    let (eip, esp, cr0, cr2, cr3, cr4): (u32, u32, u32, u32, u32, u32);
+
+    unsafe {
+        asm!("call 2f", "2:", "pop {}", out(reg) eip);
+        asm!("mov {}, esp", out(reg) esp, options(nomem, nostack));
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack));
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack));
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack));
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack));
+    }
+
+    println!("EIP:  {:#010x}    ESP:  {:#010x}", eip, esp);
+    println!("CR0:  {:#010x}    CR2:  {:#010x}", cr0, cr2);
+    println!("CR3:  {:#010x}    CR4:  {:#010x}", cr3, cr4);
+}
@@ -0,0 +1,21 @@
+// Every architecture module provides the same set of functions, which are re-exported
+// here so that the rest of Ion does not have to care which architecture it has been built
+// for.
+
+#[cfg(target_arch = "aarch64")]
+pub mod aarch64;
+#[cfg(target_arch = "x86")]
+pub mod ia32;
+#[cfg(target_arch = "riscv64")]
+pub mod riscv64;
+#[cfg(target_arch = "x86_64")]
+pub mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+pub use self::aarch64::*;
+#[cfg(target_arch = "x86")]
+pub use self::ia32::*;
+#[cfg(target_arch = "riscv64")]
+pub use self::riscv64::*;
+#[cfg(target_arch = "x86_64")]
+pub use self::x86_64::*;
@@ -0,0 +1,279 @@
+use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+
+/// The size of a page.
+pub const PAGE_SIZE: u64 = 0x1000;
+/// The size of a level 1 leaf (megapage).
+const MEGAPAGE_SIZE: u64 = 0x200000;
+
+const PTE_VALID: u64 = 1 << 0;
+const PTE_READ: u64 = 1 << 1;
+const PTE_WRITE: u64 = 1 << 2;
+const PTE_EXECUTE: u64 = 1 << 3;
+const PTE_ACCESSED: u64 = 1 << 6;
+const PTE_DIRTY: u64 = 1 << 7;
+const PTE_PPN_SHIFT: u64 = 10;
+const PTE_PPN_MASK: u64 = 0x003f_ffff_ffff_fc00;
+
+const SATP_MODE_SHIFT: u64 = 60;
+
+/// The translation mode of the kernel address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Three-level page tables covering 39 bits of virtual address space.
+    Sv39,
+    /// Four-level page tables covering 48 bits of virtual address space.
+    Sv48,
+}
+
+impl Mode {
+    /// Returns the amount of page table levels.
+    pub fn levels(&self) -> usize {
+        match self {
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+        }
+    }
+
+    /// Returns the value of the MODE field of `satp`.
+    fn satp_mode(&self) -> u64 {
+        match self {
+            Self::Sv39 => 8,
+            Self::Sv48 => 9,
+        }
+    }
+
+    /// Returns the start of the higher half of the address space.
+    pub fn higher_half_start(&self) -> u64 {
+        match self {
+            Self::Sv39 => 0xffff_ffc0_0000_0000,
+            Self::Sv48 => 0xffff_8000_0000_0000,
+        }
+    }
+}
+
+/// The attributes of a mapping. The memory type is selected by the physical memory
+/// attributes of the platform, as Ion does not rely on the Svpbmt extension.
+#[derive(Debug, Clone, Copy)]
+pub struct Attributes {
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl Attributes {
+    fn bits(&self) -> u64 {
+        // The accessed and dirty bits are set upfront, as the processor is allowed to
+        // raise a page fault instead of updating them.
+        let mut bits = PTE_VALID | PTE_READ | PTE_ACCESSED | PTE_DIRTY;
+
+        if self.writable {
+            bits |= PTE_WRITE;
+        }
+
+        if self.executable {
+            bits |= PTE_EXECUTE;
+        }
+
+        bits
+    }
+}
+
+/// Allocates a zeroed page table and returns its physical address.
+fn allocate_table<I>(frame_allocator: &mut BootFrameAllocator<I>) -> u64
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let address = frame_allocator
+        .allocate_frame()
+        .expect("mmu: failed to allocate a page table")
+        .start_address()
+        .as_u64();
+
+    // SAFETY: UEFI identity-maps all memory and the frame is not used by anything else.
+    unsafe { core::ptr::write_bytes(address as *mut u64, 0, 512) };
+
+    address
+}
+
+/// Returns true if the processor supports the provided translation mode. The mode is
+/// probed by installing a page table that identity-maps the lower half with 512 GiB
+/// leaves, as UEFI identity-maps all memory, and reading `satp` back, which keeps its
+/// previous value if the mode is not supported.
+fn mode_supported<I>(mode: Mode, frame_allocator: &mut BootFrameAllocator<I>) -> bool
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let root = allocate_table(frame_allocator);
+    let bits = Attributes {
+        writable: true,
+        executable: true,
+    }
+    .bits();
+
+    for index in 0..256 {
+        // SAFETY: The table has just been allocated.
+        unsafe { *(root as *mut u64).add(index) = ((index as u64) << 37) | bits };
+    }
+
+    let probe = (mode.satp_mode() << SATP_MODE_SHIFT) | (root >> 12);
+    let current: u64;
+
+    unsafe {
+        asm!(
+            "csrr {previous}, satp",
+            "sfence.vma",
+            "csrw satp, {probe}",
+            "sfence.vma",
+            "csrr {current}, satp",
+            "csrw satp, {previous}",
+            "sfence.vma",
+            previous = out(reg) _,
+            current = out(reg) current,
+            probe = in(reg) probe,
+            options(nostack),
+        );
+    }
+
+    current == probe
+}
+
+/// Returns the translation mode with the most levels that is supported by the processor.
+pub fn detect_mode<I>(frame_allocator: &mut BootFrameAllocator<I>) -> Mode
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    if mode_supported(Mode::Sv48, frame_allocator) {
+        Mode::Sv48
+    } else {
+        Mode::Sv39
+    }
+}
+
+/// A Sv39 or Sv48 page table, which covers both halves of the address space.
+pub struct PageTable {
+    root: u64,
+    mode: Mode,
+}
+
+impl PageTable {
+    pub fn new<I>(mode: Mode, frame_allocator: &mut BootFrameAllocator<I>) -> Self
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        Self {
+            root: allocate_table(frame_allocator),
+            mode,
+        }
+    }
+
+    /// Returns the translation mode of the page table.
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the `satp` value that activates the page table.
+    pub fn satp(&self) -> u64 {
+        (self.mode.satp_mode() << SATP_MODE_SHIFT) | (self.root >> 12)
+    }
+
+    /// Returns the entry for the provided virtual address at the provided level (0 being
+    /// the level of 4 KiB pages), creating the intermediate tables if required.
+    fn entry<I>(
+        &mut self,
+        virt: u64,
+        level: usize,
+        frame_allocator: &mut BootFrameAllocator<I>,
+    ) -> &mut u64
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let mut table = self.root;
+
+        for current in (level..self.mode.levels()).rev() {
+            let index = ((virt >> (12 + 9 * current)) & 0x1ff) as usize;
+
+            // SAFETY: The tables are identity-mapped and only accessed through the mutable
+            // reference to the page table.
+            let entry = unsafe { &mut *(table as *mut u64).add(index) };
+
+            if current == level {
+                return entry;
+            }
+
+            if *entry & PTE_VALID == 0 {
+                *entry = (allocate_table(frame_allocator) >> 12 << PTE_PPN_SHIFT) | PTE_VALID;
+            }
+
+            assert!(
+                *entry & (PTE_READ | PTE_WRITE | PTE_EXECUTE) == 0,
+                "mmu: {:#x} is already mapped by a megapage",
+                virt
+            );
+
+            table = (*entry & PTE_PPN_MASK) >> PTE_PPN_SHIFT << 12;
+        }
+
+        unreachable!()
+    }
+
+    /// Maps `size` bytes of physical memory starting at `phys` to `virt` with the provided
+    /// attributes. The parts of the range that are 2 MiB aligned both physically and
+    /// virtually are mapped using megapages.
+    pub fn map<I>(
+        &mut self,
+        virt: u64,
+        phys: u64,
+        size: u64,
+        attributes: Attributes,
+        frame_allocator: &mut BootFrameAllocator<I>,
+    ) where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let misalignment = virt & (PAGE_SIZE - 1);
+        assert_eq!(
+            misalignment,
+            phys & (PAGE_SIZE - 1),
+            "mmu: the addresses have different page offsets"
+        );
+
+        let virt = virt - misalignment;
+        let phys = phys - misalignment;
+        let size = (size + misalignment + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let bits = attributes.bits();
+
+        let mut offset = 0;
+
+        while offset < size {
+            let (virt, phys) = (virt + offset, phys + offset);
+
+            if (virt | phys) & (MEGAPAGE_SIZE - 1) == 0 && size - offset >= MEGAPAGE_SIZE {
+                let entry = self.entry(virt, 1, frame_allocator);
+
+                if *entry & PTE_VALID == 0 {
+                    *entry = (phys >> 12 << PTE_PPN_SHIFT) | bits;
+                    offset += MEGAPAGE_SIZE;
+
+                    continue;
+                }
+            }
+
+            let entry = self.entry(virt, 0, frame_allocator);
+            assert!(
+                *entry & PTE_VALID == 0,
+                "mmu: {:#x} is already mapped",
+                virt
+            );
+
+            *entry = (phys >> 12 << PTE_PPN_SHIFT) | bits;
+            offset += PAGE_SIZE;
+        }
+    }
+}
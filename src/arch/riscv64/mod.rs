@@ -0,0 +1,125 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+use crate::prelude::*;
+
+pub mod mmu;
+pub mod sbi;
+pub mod smp;
+
+/// The page table of the kernel address space. The firmware keeps using its own page
+/// table until the kernel is entered, as UEFI identity-maps all memory.
+pub struct BootPageTables {
+    /// The page table covering both halves of the kernel address space. The lower half
+    /// identity-maps the physical memory and the higher half contains the kernel and the
+    /// direct map.
+    pub root: mmu::PageTable,
+}
+
+/// Helper function to create the page table for the kernel itself, using Sv48 if the
+/// processor supports it and Sv39 otherwise.
+pub fn setup_boot_paging<I>(frame_allocator: &mut BootFrameAllocator<I>) -> BootPageTables
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let mode = mmu::detect_mode(frame_allocator);
+    log::debug!("mmu: using {:?} for the kernel address space", mode);
+
+    BootPageTables {
+        root: mmu::PageTable::new(mode, frame_allocator),
+    }
+}
+
+/// Disables the interrupts and halts the hart forever.
+pub fn halt() -> ! {
+    unsafe {
+        asm!("csrci sstatus, 2");
+
+        loop {
+            asm!("wfi");
+        }
+    }
+}
+
+/// The offset of the frame record (the frame pointer of the previous frame followed by the
+/// return address) below the frame pointer.
+pub const FRAME_RECORD_OFFSET: u64 = 16;
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let s0: u64;
+
+    unsafe {
+        asm!("mv {}, s0", out(reg) s0, options(nomem, nostack));
+    }
+
+    s0
+}
+
+/// Returns the current value of the `time` counter.
+#[inline]
+pub fn read_counter() -> u64 {
+    let value: u64;
+
+    // SAFETY: The firmware is required to give the supervisor access to the counter.
+    unsafe {
+        asm!("csrr {}, time", out(reg) value, options(nomem, nostack));
+    }
+
+    value
+}
+
+/// Returns the frequency of the counter in Hz, if it can be discovered. The frequency is
+/// only described by the device tree, so it has to be calibrated.
+pub fn counter_frequency() -> Option<u64> {
+    None
+}
+
+/// Returns true if the counter runs at a constant rate, regardless of the power state of
+/// the hart. This is always the case for the `time` counter.
+pub fn counter_invariant() -> bool {
+    true
+}
+
+/// Calls the provided function with up to `count` values of the hardware random number
+/// generator of the hart. The `seed` CSR of the Zkr extension is usually not accessible in
+/// supervisor mode and there is no way to check, so no values are provided.
+pub fn hardware_random<F>(_count: usize, _f: F)
+where
+    F: FnMut(u64),
+{
+}
+
+/// Returns the features of the processor that entries can require with `REQUIRES=`. The
+/// features are only reported on x86, so entries that require any of them cannot run here.
+pub fn cpu_features() -> ion_core::cpu::Features {
+    ion_core::cpu::Features::empty()
+}
+
+/// Returns a summary of the processor, which is empty as the identification CSRs are only
+/// accessible in machine mode.
+pub fn cpu_summary() -> Vec<String> {
+    Vec::new()
+}
+
+/// Prints the program counter, the stack pointer and the supervisor CSRs.
+pub fn print_registers() {
+    let (pc, sp): (u64, u64);
+    let (sstatus, satp, scause, stval): (u64, u64, u64, u64);
+
+    unsafe {
+        asm!("auipc {}, 0", out(reg) pc, options(nomem, nostack));
+        asm!("mv {}, sp", out(reg) sp, options(nomem, nostack));
+        asm!("csrr {}, sstatus", out(reg) sstatus, options(nomem, nostack));
+        asm!("csrr {}, satp", out(reg) satp, options(nomem, nostack));
+        asm!("csrr {}, scause", out(reg) scause, options(nomem, nostack));
+        asm!("csrr {}, stval", out(reg) stval, options(nomem, nostack));
+    }
+
+    println!("PC:      {:#018x}    SP:     {:#018x}", pc, sp);
+    println!("SSTATUS: {:#018x}    SATP:   {:#018x}", sstatus, satp);
+    println!("SCAUSE:  {:#018x}    STVAL:  {:#018x}", scause, stval);
+}
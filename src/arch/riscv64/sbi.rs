@@ -0,0 +1,95 @@
+const SBI_BASE_EXTENSION: u64 = 0x10;
+const SBI_BASE_GET_SPEC_VERSION: u64 = 0;
+const SBI_BASE_PROBE_EXTENSION: u64 = 3;
+
+/// The Hart State Management extension ("HSM").
+const SBI_HSM_EXTENSION: u64 = 0x48534d;
+const SBI_HSM_HART_START: u64 = 0;
+
+/// An error returned by the SBI implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    Unknown(i64),
+}
+
+impl Error {
+    fn from_code(code: i64) -> Self {
+        match code {
+            -1 => Self::Failed,
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+/// Calls the provided SBI function and returns its value.
+fn call(extension: u64, function: u64, arg0: u64, arg1: u64, arg2: u64) -> Result<u64, Error> {
+    let (error, value): (i64, u64);
+
+    // SAFETY: The SBI functions that Ion uses do not affect the current hart.
+    unsafe {
+        asm!(
+            "ecall",
+            inout("a0") arg0 => error,
+            inout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") function,
+            in("a7") extension,
+            options(nostack),
+        );
+    }
+
+    match error {
+        0 => Ok(value),
+        code => Err(Error::from_code(code)),
+    }
+}
+
+/// Returns true if the SBI implementation provides the hart state management extension.
+pub fn hsm_supported() -> bool {
+    // Version 0.1 of the specification does not have a base extension.
+    match call(SBI_BASE_EXTENSION, SBI_BASE_GET_SPEC_VERSION, 0, 0, 0) {
+        Ok(version) => {
+            log::debug!(
+                "sbi: version {}.{}",
+                version >> 24 & 0x7f,
+                version & 0xff_ffff
+            );
+
+            call(
+                SBI_BASE_EXTENSION,
+                SBI_BASE_PROBE_EXTENSION,
+                SBI_HSM_EXTENSION,
+                0,
+                0,
+            )
+            .map_or(false, |available| available != 0)
+        }
+
+        Err(_) => false,
+    }
+}
+
+/// Starts the hart with the provided ID at the provided physical address. The hart enters
+/// in supervisor mode with its MMU disabled, its hart ID in A0 and the provided opaque
+/// value in A1.
+pub fn hart_start(hart_id: u64, start_address: u64, opaque: u64) -> Result<(), Error> {
+    call(
+        SBI_HSM_EXTENSION,
+        SBI_HSM_HART_START,
+        hart_id,
+        start_address,
+        opaque,
+    )
+    .map(|_| ())
+}
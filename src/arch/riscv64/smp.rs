@@ -0,0 +1,189 @@
+use core::sync::atomic::{self, AtomicU64, Ordering};
+
+use uefi::prelude::*;
+use uefi::{unsafe_guid, Protocol};
+
+use super::{sbi, BootPageTables};
+use crate::acpi::{self, MadtEntry};
+use crate::time;
+
+/// The flag of the RINTC entries marking the hart as enabled.
+const RINTC_ENABLED: u32 = 1 << 0;
+
+/// The amount of milliseconds that a hart is given to reach the kernel address space.
+const STARTUP_TIMEOUT_MS: u64 = 1000;
+
+/// The `RISCV_EFI_BOOT_PROTOCOL`, which reports the ID of the boot hart.
+#[repr(C)]
+#[unsafe_guid("ccd15fec-6f73-4eec-8395-3e69e4b940bf")]
+#[derive(Protocol)]
+struct RiscVBoot {
+    revision: u64,
+    get_boot_hart_id: unsafe extern "efiapi" fn(this: &RiscVBoot, hart_id: *mut usize) -> Status,
+}
+
+/// The ID of the boot hart, or `u64::MAX` if it is unknown.
+static BOOT_HART_ID: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// This function is responsible for querying the ID of the boot hart from the firmware.
+/// Must be called before exiting the boot services.
+pub fn init(system_table: &SystemTable<Boot>) {
+    let boot = match system_table.boot_services().locate_protocol::<RiscVBoot>() {
+        Ok(boot) => boot,
+        Err(_) => {
+            log::warn!("smp: the firmware does not provide the RISC-V boot protocol");
+            return;
+        }
+    };
+
+    // SAFETY: The protocol pointer is valid as long as the boot services are active.
+    let boot = unsafe { &*boot.unwrap().get() };
+    let mut hart_id = 0;
+
+    if unsafe { (boot.get_boot_hart_id)(boot, &mut hart_id) } == Status::SUCCESS {
+        BOOT_HART_ID.store(hart_id as u64, Ordering::Relaxed);
+        log::debug!("smp: booting on hart {}", hart_id);
+    }
+}
+
+/// Returns the ID of the boot hart, if the firmware has reported it.
+pub fn boot_hart_id() -> Option<u64> {
+    Some(BOOT_HART_ID.load(Ordering::Relaxed)).filter(|&hart_id| hart_id != u64::MAX)
+}
+
+/// The per-CPU information passed to the kernel. The layout matches the aarch64 variant of
+/// `struct stivale2_smp_info`, with the hart ID in place of the MPIDR. The harts spin until
+/// the kernel writes `goto_address`, at which point they load `target_stack` and jump to
+/// it with the address of their information in A0.
+#[repr(C)]
+pub struct SmpInfo {
+    pub processor_id: u32,
+    pub unused: u32,
+    pub hart_id: u64,
+    pub target_stack: u64,
+    pub goto_address: u64,
+    pub extra_argument: u64,
+}
+
+/// Returns the ACPI processor UID and the hart ID of every enabled hart.
+pub fn processors() -> impl Iterator<Item = (u32, u64)> {
+    acpi::Madt::get()
+        .into_iter()
+        .flat_map(|madt| madt.entries())
+        .filter_map(|entry| match entry {
+            MadtEntry::Rintc {
+                flags,
+                hart_id,
+                processor_uid,
+            } if flags & RINTC_ENABLED != 0 => Some((processor_uid, hart_id)),
+
+            _ => None,
+        })
+}
+
+// The entry point of the harts, which the SBI implementation enters in supervisor mode
+// with the MMU disabled, the hart ID in A0 and the address of the startup data of the hart
+// in A1. The hart switches to the kernel address space (in which the entry point is
+// identity-mapped), reports that it has booted and waits until the boot hart provides its
+// information. Afterwards it spins until the kernel writes its goto address. The offsets
+// have to match `StartupData` and `SmpInfo`.
+global_asm!(
+    r#"
+.global SMP_HART_ENTRY
+
+SMP_HART_ENTRY:
+    csrw sie, zero
+    csrci sstatus, 2
+
+    // Enable the floating point unit.
+    li t0, 1 << 13
+    csrs sstatus, t0
+
+    ld t0, 0(a1)
+    sfence.vma
+    csrw satp, t0
+    sfence.vma
+
+    li t0, 1
+    fence rw, rw
+    sd t0, 16(a1)
+
+    // Wait until the boot hart provides the information of this hart.
+1:
+    ld t1, 8(a1)
+    beqz t1, 1b
+    fence r, rw
+
+    // Wait until the kernel writes the goto address of this hart.
+2:
+    ld t2, 24(t1)
+    beqz t2, 2b
+    fence r, rw
+
+    ld sp, 16(t1)
+    mv a0, t1
+    li ra, 0
+    li s0, 0
+    jr t2
+"#
+);
+
+extern "C" {
+    static SMP_HART_ENTRY: u8;
+}
+
+/// The data that a hart is started with. The layout has to match the offsets used in the
+/// `global_asm!` block above.
+#[repr(C)]
+pub struct StartupData {
+    satp: u64,
+    info: u64,
+    booted: u64,
+}
+
+impl StartupData {
+    /// Creates the startup data for entering the kernel address space.
+    pub fn new(page_tables: &BootPageTables) -> Self {
+        Self {
+            satp: page_tables.root.satp(),
+            info: 0,
+            booted: 0,
+        }
+    }
+}
+
+/// Starts the hart with the provided ID, which parks itself in the kernel address space
+/// until the kernel writes the goto address of the provided information (mapped at the
+/// same address in both address spaces). The startup data must be identity-mapped and not
+/// used for any other hart. Returns false if the hart did not start, in which case it
+/// never receives the information.
+pub fn start(hart_id: u64, data: &mut StartupData, info: &SmpInfo) -> bool {
+    let address = data as *mut StartupData as u64;
+
+    // SAFETY: Only the address of the entry point is taken.
+    let entry = unsafe { &SMP_HART_ENTRY } as *const u8 as u64;
+
+    if let Err(error) = sbi::hart_start(hart_id, entry, address) {
+        log::warn!("smp: failed to start hart {} ({:?})", hart_id, error);
+        return false;
+    }
+
+    let start = time::timestamp_ms();
+    let booted = core::ptr::addr_of!(data.booted);
+
+    while time::elapsed_ms(start) < STARTUP_TIMEOUT_MS {
+        if unsafe { booted.read_volatile() } != 0 {
+            // The information has to be visible before its address.
+            atomic::fence(Ordering::Release);
+
+            let info_field = core::ptr::addr_of_mut!(data.info);
+            unsafe { info_field.write_volatile(info as *const SmpInfo as u64) };
+
+            return true;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    false
+}
@@ -0,0 +1,116 @@
+use core::arch::x86_64::__cpuid_count;
+
+use raw_cpuid::CpuId;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+use crate::config::ConfigurationEntry;
+
+/// The AVX-512 state components of XCR0, which have to be enabled together.
+const XCR0_AVX512: XCr0Flags = XCr0Flags::from_bits_truncate(
+    XCr0Flags::OPMASK.bits() | XCr0Flags::ZMM_HI256.bits() | XCr0Flags::HI16_ZMM.bits(),
+);
+
+pub fn enable_nxe_bit() {
+    unsafe { Efer::update(|efer| *efer |= EferFlags::NO_EXECUTE_ENABLE) }
+}
+
+pub fn enable_write_protect_bit() {
+    unsafe { Cr0::update(|cr0| *cr0 |= Cr0Flags::WRITE_PROTECT) };
+}
+
+/// Returns the state components that may be enabled in XCR0, as reported by the processor
+/// extended state enumeration leaf.
+fn supported_xcr0() -> XCr0Flags {
+    // SAFETY: The leaf is only queried if XSAVE is supported, which implies its presence.
+    let leaf = unsafe { __cpuid_count(0xd, 0) };
+    XCr0Flags::from_bits_truncate(((leaf.edx as u64) << 32) | leaf.eax as u64)
+}
+
+/// This function is responsible for enabling SSE and, if XSAVE is supported, the AVX and
+/// AVX-512 state, so that kernels can use them without having to set up the FPU first.
+/// XCR0 only enables the x87, SSE, AVX and AVX-512 state components.
+pub fn init_fpu() {
+    let cpuid = CpuId::new();
+    let features = cpuid.get_feature_info();
+
+    // The FPU has to be enabled natively and not emulated, with MP set so that WAIT
+    // respects the TS flag.
+    unsafe {
+        Cr0::update(|cr0| {
+            cr0.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            cr0.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+
+        Cr4::update(|cr4| *cr4 |= Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+    }
+
+    if !features.as_ref().map_or(false, |info| info.has_xsave()) {
+        log::debug!("cpu: XSAVE not supported, only enabling SSE");
+        return;
+    }
+
+    unsafe { Cr4::update(|cr4| *cr4 |= Cr4Flags::OSXSAVE) };
+
+    let supported = supported_xcr0();
+    let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+
+    if supported.contains(XCr0Flags::YMM) {
+        xcr0 |= XCr0Flags::YMM;
+
+        if supported.contains(XCR0_AVX512) {
+            xcr0 |= XCR0_AVX512;
+        }
+    }
+
+    // SAFETY: Only state components that are supported by the processor are enabled and
+    // the dependencies between them are respected.
+    unsafe { XCr0::write(xcr0) };
+
+    log::debug!("cpu: enabled the XSAVE state components {:?}", xcr0);
+}
+
+/// Returns the CR4 value with the supervisor mode protections requested by the provided
+/// entry (SMEP, SMAP and UMIP). Protections that are not supported by the processor are
+/// skipped with a warning. The value is only loaded by the context switch, as the firmware
+/// page tables might map Ion's own pages as user accessible.
+pub fn kernel_cr4(entry: &ConfigurationEntry) -> Cr4Flags {
+    let features = CpuId::new().get_extended_feature_info();
+    let mut cr4 = Cr4::read();
+
+    let requested = [
+        (
+            entry.smep(),
+            features.as_ref().map_or(false, |info| info.has_smep()),
+            Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+            "SMEP",
+        ),
+        (
+            entry.smap(),
+            features.as_ref().map_or(false, |info| info.has_smap()),
+            Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION,
+            "SMAP",
+        ),
+        (
+            entry.umip(),
+            features.as_ref().map_or(false, |info| info.has_umip()),
+            Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION,
+            "UMIP",
+        ),
+    ];
+
+    for (enabled, supported, flag, name) in requested.iter() {
+        if !*enabled {
+            continue;
+        }
+
+        if *supported {
+            cr4 |= *flag;
+        } else {
+            log::warn!("cpu: {} is not supported by the CPU", name);
+        }
+    }
+
+    cr4
+}
@@ -0,0 +1,74 @@
+use x86_64::VirtAddr;
+
+/// The selector of the 64-bit code segment in Ion's GDT.
+pub const CODE_64_SELECTOR: u16 = 0x28;
+/// The selector of the 64-bit data segment in Ion's GDT.
+pub const DATA_64_SELECTOR: u16 = 0x30;
+
+/// The descriptors of Ion's GDT. The layout matches the GDT documented by the stivale2
+/// specification, so that kernels can rely on the selectors being valid until they load
+/// their own GDT. All of the segments are flat, covering the whole address space.
+pub const ENTRIES: [u64; 7] = [
+    0,
+    0x0000_9a00_0000_ffff, // 0x08: 16-bit code
+    0x0000_9200_0000_ffff, // 0x10: 16-bit data
+    0x00cf_9a00_0000_ffff, // 0x18: 32-bit code
+    0x00cf_9200_0000_ffff, // 0x20: 32-bit data
+    0x00af_9a00_0000_ffff, // 0x28: 64-bit code
+    0x00cf_9200_0000_ffff, // 0x30: 64-bit data
+];
+
+/// The size of the GDT in bytes.
+pub const SIZE: usize = core::mem::size_of::<[u64; 7]>();
+
+/// The operand of the LGDT instruction.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Pointer {
+    pub limit: u16,
+    pub base: u64,
+}
+
+impl Pointer {
+    /// Returns the pointer to a GDT with the layout of [`ENTRIES`] at the provided address.
+    pub fn new(address: VirtAddr) -> Self {
+        Self {
+            limit: (SIZE - 1) as u16,
+            base: address.as_u64(),
+        }
+    }
+}
+
+/// Writes the GDT to the provided address, which has to point to at least [`SIZE`]
+/// writable bytes.
+pub unsafe fn write(address: VirtAddr) {
+    (address.as_mut_ptr() as *mut [u64; 7]).write(ENTRIES);
+}
+
+/// Loads the GDT at the provided address, which has to be written using [`write`] first,
+/// and reloads all of the segment registers with the 64-bit segments.
+pub unsafe fn load(address: VirtAddr) {
+    let pointer = Pointer::new(address);
+
+    asm!(
+        "
+        lgdt [{pointer}]
+
+        push {code}
+        lea {tmp}, [rip + 2f]
+        push {tmp}
+        retfq
+
+        2:
+        mov ds, {data:x}
+        mov es, {data:x}
+        mov fs, {data:x}
+        mov gs, {data:x}
+        mov ss, {data:x}
+        ",
+        pointer = in(reg) &pointer,
+        code = in(reg) CODE_64_SELECTOR as u64,
+        data = in(reg) DATA_64_SELECTOR as u64,
+        tmp = out(reg) _,
+    );
+}
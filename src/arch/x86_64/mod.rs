@@ -0,0 +1,145 @@
+use core::arch::x86_64::_rdtsc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use raw_cpuid::CpuId;
+use x86_64::instructions::random::RdRand;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+
+use crate::prelude::*;
+
+pub mod cpu;
+pub mod gdt;
+mod paging;
+pub mod smp;
+
+pub use self::paging::{setup_boot_paging, BootPageTables};
+
+/// The amount of times RDSEED is retried, as it fails if its entropy pool is exhausted.
+const RDSEED_RETRIES: usize = 16;
+
+/// Disables the interrupts and halts the processor forever.
+pub fn halt() -> ! {
+    unsafe {
+        asm!("cli");
+
+        loop {
+            asm!("hlt");
+        }
+    }
+}
+
+/// The offset of the frame record (the frame pointer of the previous frame followed by the
+/// return address) below the frame pointer.
+pub const FRAME_RECORD_OFFSET: u64 = 0;
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+    }
+
+    rbp
+}
+
+/// Returns the current value of the time stamp counter.
+#[inline]
+pub fn read_counter() -> u64 {
+    // SAFETY: The TSC is always available on x86_64.
+    unsafe { _rdtsc() }
+}
+
+/// Returns the frequency of the counter in Hz, if it can be discovered. The frequency of
+/// the TSC is not reported reliably, so it has to be calibrated.
+pub fn counter_frequency() -> Option<u64> {
+    None
+}
+
+/// Returns true if the counter runs at a constant rate, regardless of the power state of
+/// the processor.
+pub fn counter_invariant() -> bool {
+    CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .map_or(false, |info| info.has_invariant_tsc())
+}
+
+/// Reads a 64-bit value using RDSEED, if it is supported.
+fn rdseed() -> Option<u64> {
+    for _ in 0..RDSEED_RETRIES {
+        let (value, success): (u64, u8);
+
+        unsafe {
+            asm!(
+                "rdseed {}; setc {}",
+                out(reg) value,
+                out(reg_byte) success,
+                options(nomem, nostack),
+            );
+        }
+
+        if success != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Calls the provided function with up to `count` values of each hardware random number
+/// generator of the processor (RDSEED and RDRAND).
+pub fn hardware_random<F>(count: usize, mut f: F)
+where
+    F: FnMut(u64),
+{
+    let rdseed_supported = CpuId::new()
+        .get_extended_feature_info()
+        .map_or(false, |info| info.has_rdseed());
+
+    if rdseed_supported {
+        (0..count).filter_map(|_| rdseed()).for_each(&mut f);
+    }
+
+    if let Some(rdrand) = RdRand::new() {
+        (0..count).filter_map(|_| rdrand.get_u64()).for_each(&mut f);
+    }
+}
+
+/// Returns the features of the processor that entries can require with `REQUIRES=`.
+pub fn cpu_features() -> ion_core::cpu::Features {
+    crate::cpuid::features()
+}
+
+/// Returns a summary of the identification and the features of the processor.
+pub fn cpu_summary() -> Vec<String> {
+    crate::cpuid::summary()
+}
+
+/// Prints the instruction pointer, the stack pointer and the control registers.
+pub fn print_registers() {
+    let (rip, rsp): (u64, u64);
+
+    unsafe {
+        asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack));
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+    }
+
+    let (cr3, _) = Cr3::read_raw();
+
+    println!("RIP:  {:#018x}    RSP:  {:#018x}", rip, rsp);
+    println!(
+        "CR0:  {:#018x}    CR2:  {:#018x}",
+        Cr0::read_raw(),
+        Cr2::read().as_u64()
+    );
+    println!(
+        "CR3:  {:#018x}    CR4:  {:#018x}",
+        cr3.start_address().as_u64(),
+        Cr4::read_raw()
+    );
+    println!("EFER: {:#018x}", Efer::read_raw());
+}
@@ -0,0 +1,122 @@
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::*;
+use x86_64::VirtAddr;
+
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+
+pub struct BootPageTables {
+    /// Provides access to the page tables of the bootloader address space.
+    pub bootloader: OffsetPageTable<'static>,
+    /// Provides access to the page tables of the kernel address space (not active).
+    pub kernel: OffsetPageTable<'static>,
+    /// The physical frame where the level 4 page table of the kernel address space is stored.
+    pub kernel_level_4_frame: PhysFrame,
+    /// The physical frame where the level 5 page table of the kernel address space is stored,
+    /// if five-level paging has been enabled using [`BootPageTables::enable_level_5_paging`].
+    pub kernel_level_5_frame: Option<PhysFrame>,
+}
+
+impl BootPageTables {
+    /// Builds a level 5 page table on top of the level 4 page table of the kernel address
+    /// space. Both the first and the last level 5 entry reference the level 4 table, so that
+    /// all of the existing lower and higher half mappings keep their addresses. The frame
+    /// is allocated below 4 GiB, as CR3 has to be loaded in 32-bit mode to enable LA57.
+    pub fn enable_level_5_paging<I>(&mut self, frame_allocator: &mut BootFrameAllocator<I>)
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let frame = frame_allocator
+            .allocate_frames(1, 1 << 32, Size4KiB::SIZE)
+            .expect("mm: failed to allocate frame for the level 5 kernel table")
+            .start;
+
+        let table: &mut PageTable =
+            unsafe { &mut *(frame.start_address().as_u64() as *mut PageTable) };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        *table = PageTable::new();
+        table[0].set_frame(self.kernel_level_4_frame, flags);
+        table[511].set_frame(self.kernel_level_4_frame, flags);
+
+        self.kernel_level_5_frame = Some(frame);
+    }
+
+    /// Returns the level 5 page table of the kernel address space, if five-level paging
+    /// has been enabled.
+    pub fn kernel_level_5_table(&mut self) -> Option<&mut PageTable> {
+        // SAFETY: UEFI identity-maps all memory and the table is only accessed through
+        // the mutable reference to the page tables.
+        let frame = self.kernel_level_5_frame?;
+        Some(unsafe { &mut *(frame.start_address().as_u64() as *mut PageTable) })
+    }
+}
+
+/// Helper function to create and load the bootloader's page table and
+/// create a new page table for the kernel itself.
+pub fn setup_boot_paging(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> BootPageTables {
+    // NOTE: UEFI identity-maps all memory, so the offset between physical
+    // and virtual addresses is 0.
+    let off = VirtAddr::zero();
+
+    let boot_page_table = {
+        let old_table = {
+            let (frame, _) = Cr3::read();
+
+            let ptr: *const PageTable = (off + frame.start_address().as_u64()).as_ptr();
+
+            unsafe { &*ptr }
+        };
+
+        let new_frame = frame_allocator
+            .allocate_frame()
+            .expect("mm: failed to allocate frame for new level 4 boot table");
+
+        let new_table: &mut PageTable = {
+            let ptr: *mut PageTable = (off + new_frame.start_address().as_u64()).as_mut_ptr();
+
+            unsafe {
+                // Create a new empty, fresh page table.
+                ptr.write(PageTable::new());
+                &mut *ptr
+            }
+        };
+
+        // Copy the first entry (we don't need to access more than 512 GiB; also, some UEFI
+        // implementations seem to create an level 4 table entry 0 in all slots)
+        new_table[0] = old_table[0].clone();
+
+        // The first level 4 table entry is now identical, so we can just load the new one.
+        unsafe {
+            Cr3::write(new_frame, Cr3Flags::empty());
+            OffsetPageTable::new(&mut *new_table, off)
+        }
+    };
+
+    // Now we will create a page table for the kernel itself.
+    let (kernel_page_table, kernel_level_4_frame) = {
+        // get an unused frame for new level 4 page table
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .expect("mm: no unused frames");
+
+        log::info!("new page table at: {:#?}", &frame);
+
+        // 1. Get the corresponding virtual address.
+        let addr = off + frame.start_address().as_u64();
+
+        // 2. Initialize a new page table.
+        let ptr = addr.as_mut_ptr();
+        unsafe { *ptr = PageTable::new() };
+        let level_4_table = unsafe { &mut *ptr };
+
+        (unsafe { OffsetPageTable::new(level_4_table, off) }, frame)
+    };
+
+    BootPageTables {
+        bootloader: boot_page_table,
+        kernel: kernel_page_table,
+        kernel_level_4_frame,
+        kernel_level_5_frame: None,
+    }
+}
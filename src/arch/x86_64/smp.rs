@@ -0,0 +1,574 @@
+use raw_cpuid::CpuId;
+use x86_64::registers::control::Cr4Flags;
+use x86_64::registers::model_specific::Msr;
+use x86_64::registers::xcontrol::XCr0;
+use x86_64::structures::paging::*;
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::gdt;
+use super::BootPageTables;
+use crate::acpi::{self, MadtEntry};
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion};
+use crate::time;
+
+/// The model specific register containing the physical address of the local APIC.
+const IA32_APIC_BASE: u32 = 0x1b;
+
+/// The bit of `IA32_APIC_BASE` that enables the local APIC.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// The bit of `IA32_APIC_BASE` that switches the local APIC into x2APIC mode.
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+/// The model specific register of the first x2APIC register. The x2APIC registers are
+/// at the MMIO offset of the xAPIC register divided by 16.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_ICR_LOW: u64 = 0x300;
+const LAPIC_ICR_HIGH: u64 = 0x310;
+
+/// The interrupt command that resets a processor into the wait-for-SIPI state.
+const ICR_INIT: u32 = 0x4500;
+/// The interrupt command that starts a processor at the page selected by the vector.
+const ICR_STARTUP: u32 = 0x4600;
+/// The bit of the interrupt command register that is set while the IPI is sent.
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+/// The amount of milliseconds that the processors are given to reach the trampoline after
+/// the first and the second startup IPI.
+const STARTUP_TIMEOUTS_MS: [u64; 2] = [10, 1000];
+
+/// The per-CPU information passed to the kernel (`struct stivale2_smp_info`). The
+/// application processors spin until the kernel writes `goto_address`, at which point
+/// they load `target_stack` and jump to it with the address of their information in RDI.
+#[repr(C)]
+pub struct SmpInfo {
+    pub processor_id: u32,
+    pub lapic_id: u32,
+    pub target_stack: u64,
+    pub goto_address: u64,
+    pub extra_argument: u64,
+}
+
+/// Returns the ACPI processor ID and the local APIC ID of every enabled processor. The
+/// processors with a local APIC ID above 254 are described by x2APIC entries.
+pub fn processors() -> impl Iterator<Item = (u32, u32)> {
+    acpi::Madt::get()
+        .into_iter()
+        .flat_map(|madt| madt.entries())
+        .filter_map(|entry| match entry {
+            MadtEntry::LocalApic {
+                processor_id,
+                apic_id,
+                flags,
+            } if flags & acpi::MADT_LAPIC_ENABLED != 0 => {
+                Some((processor_id as u32, apic_id as u32))
+            }
+
+            MadtEntry::LocalX2Apic {
+                x2apic_id,
+                flags,
+                processor_uid,
+            } if flags & acpi::MADT_LAPIC_ENABLED != 0 => Some((processor_uid, x2apic_id)),
+
+            _ => None,
+        })
+}
+
+/// Returns true if the processor supports x2APIC mode.
+pub fn x2apic_supported() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_x2apic())
+}
+
+/// The local APIC of the bootstrap processor.
+pub enum LocalApic {
+    /// The local APIC is accessed through its memory mapped registers.
+    XApic { base: u64 },
+    /// The local APIC is accessed through model specific registers.
+    X2Apic,
+}
+
+impl LocalApic {
+    /// Returns the local APIC of the current processor, switching it into x2APIC mode if
+    /// requested and supported. If the firmware has already enabled x2APIC mode, it is
+    /// kept, as the local APIC cannot go back to xAPIC mode without being disabled.
+    pub fn new(x2apic: bool) -> Self {
+        let mut msr = Msr::new(IA32_APIC_BASE);
+
+        // SAFETY: The MSR is architectural on every processor with a local APIC.
+        let value = unsafe { msr.read() };
+
+        if value & APIC_BASE_X2APIC != 0 {
+            return Self::X2Apic;
+        }
+
+        if x2apic && x2apic_supported() {
+            unsafe { msr.write(value | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+            return Self::X2Apic;
+        }
+
+        Self::XApic {
+            base: value & 0xf_ffff_f000,
+        }
+    }
+
+    /// Returns true if the local APIC is in x2APIC mode.
+    #[inline]
+    pub fn is_x2apic(&self) -> bool {
+        matches!(self, Self::X2Apic)
+    }
+
+    fn read(&self, register: u64) -> u32 {
+        match self {
+            // SAFETY: The local APIC registers are identity mapped in the bootloader
+            // address space.
+            Self::XApic { base } => unsafe { ((base + register) as *const u32).read_volatile() },
+            Self::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (register >> 4) as u32).read() as u32
+            },
+        }
+    }
+
+    /// Returns the local APIC ID of the current processor.
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::XApic { .. } => self.read(LAPIC_ID) >> 24,
+            Self::X2Apic => self.read(LAPIC_ID),
+        }
+    }
+
+    /// Sends the provided interrupt command to the processor with the provided local APIC
+    /// ID and waits until it has been delivered.
+    fn send_ipi(&self, apic_id: u32, command: u32) {
+        match self {
+            Self::XApic { base } => {
+                // SAFETY: The local APIC registers are identity mapped in the bootloader
+                // address space.
+                unsafe {
+                    ((base + LAPIC_ICR_HIGH) as *mut u32).write_volatile(apic_id << 24);
+                    ((base + LAPIC_ICR_LOW) as *mut u32).write_volatile(command);
+                }
+
+                while self.read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+
+            // The interrupt command register is a single 64-bit register in x2APIC mode,
+            // which does not have a delivery status.
+            Self::X2Apic => unsafe {
+                let mut icr = Msr::new(X2APIC_MSR_BASE + (LAPIC_ICR_LOW >> 4) as u32);
+                icr.write(((apic_id as u64) << 32) | command as u64);
+            },
+        }
+    }
+}
+
+// The trampoline that the application processors are started at. It is copied to a frame
+// below 1 MiB, as the processors start in real mode at the page selected by the startup
+// IPI. The trampoline enters long mode using temporary page tables that identity-map the
+// first 2 MiB, switches to the kernel address space (in which the trampoline is
+// identity-mapped too), loads Ion's GDT and the register state of the bootstrap processor
+// and spins until the kernel writes the goto address of the processor. The processors are
+// started one after another, so they share the trampoline data and the stack at the end of
+// the trampoline frame, which is only used for the switch to long mode. If the bootstrap
+// processor is in x2APIC mode, the application processors switch into it too.
+global_asm!(
+    r#"
+.global SMP_TRAMPOLINE_START
+.global SMP_TRAMPOLINE_PROTECTED_MODE
+.global SMP_TRAMPOLINE_DATA
+.global SMP_TRAMPOLINE_BOOTED
+.global SMP_TRAMPOLINE_END
+
+.code16
+SMP_TRAMPOLINE_START:
+    cli
+    cld
+
+    // The data is addressed relative to the code segment, whose base is the trampoline.
+    mov ax, cs
+    mov ds, ax
+
+    xor ebx, ebx
+    mov bx, cs
+    shl ebx, 4
+
+    lgdt [smp_gdtr - SMP_TRAMPOLINE_START]
+
+    mov eax, cr0
+    bts eax, 0
+    mov cr0, eax
+
+    // jmp far dword ptr [smp_far_jump]
+    .byte 0x66, 0xff, 0x2e
+    .word smp_far_jump - SMP_TRAMPOLINE_START
+
+.code32
+SMP_TRAMPOLINE_PROTECTED_MODE:
+    mov ax, 0x20
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    lea esp, [ebx + 0x1000]
+
+    // Enable PAE (and LA57 if the kernel uses five-level paging).
+    mov eax, [ebx + (smp_initial_cr4 - SMP_TRAMPOLINE_START)]
+    mov cr4, eax
+
+    mov eax, [ebx + (smp_temporary_table - SMP_TRAMPOLINE_START)]
+    mov cr3, eax
+
+    // Enable long mode and the no-execute bit.
+    mov ecx, 0xc0000080
+    rdmsr
+    bts eax, 8
+    bts eax, 11
+    wrmsr
+
+    cmp byte ptr [ebx + (smp_x2apic - SMP_TRAMPOLINE_START)], 0
+    je 1f
+
+    mov ecx, 0x1b
+    rdmsr
+    or eax, 0xc00
+    wrmsr
+
+1:
+
+    // Enable paging and write protection, which enters long mode.
+    mov eax, cr0
+    bts eax, 16
+    bts eax, 31
+    mov cr0, eax
+
+    push 0x28
+    lea eax, [ebx + (smp_long_mode - SMP_TRAMPOLINE_START)]
+    push eax
+    retf
+
+.code64
+smp_long_mode:
+    // The upper half of RBX is undefined after the switch to long mode.
+    mov ebx, ebx
+
+    mov rax, [rbx + (smp_kernel_table - SMP_TRAMPOLINE_START)]
+    mov cr3, rax
+
+    // The selectors are the same in both copies of the GDT.
+    lgdt [rbx + (smp_kernel_gdtr - SMP_TRAMPOLINE_START)]
+
+    mov ax, 0x30
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    // Enable the FPU, see `cpu::init_fpu`.
+    mov rax, cr0
+    btr rax, 2
+    btr rax, 3
+    bts rax, 1
+    bts rax, 5
+    mov cr0, rax
+
+    mov rax, [rbx + (smp_cr4 - SMP_TRAMPOLINE_START)]
+    mov cr4, rax
+
+    mov rax, [rbx + (smp_xcr0 - SMP_TRAMPOLINE_START)]
+    test rax, rax
+    jz 2f
+
+    mov rdx, rax
+    shr rdx, 32
+    xor ecx, ecx
+    xsetbv
+
+2:
+    mov rdi, [rbx + (smp_info - SMP_TRAMPOLINE_START)]
+    mov byte ptr [rbx + (SMP_TRAMPOLINE_BOOTED - SMP_TRAMPOLINE_START)], 1
+
+    // Wait until the kernel writes the goto address of this processor.
+3:
+    pause
+    mov rax, [rdi + 16]
+    test rax, rax
+    jz 3b
+
+    mov rsp, [rdi + 8]
+    push 0
+    push rax
+
+    // Zero the general purpose registers, see `context_switch`.
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+
+    cld
+    ret
+
+.align 8
+SMP_TRAMPOLINE_DATA:
+smp_gdt:
+    .fill 7, 8, 0
+smp_gdtr:
+    .word 0
+    .quad 0
+smp_far_jump:
+    .long 0
+    .word 0
+smp_temporary_table:
+    .long 0
+smp_initial_cr4:
+    .long 0
+smp_kernel_table:
+    .quad 0
+smp_kernel_gdtr:
+    .word 0
+    .quad 0
+smp_cr4:
+    .quad 0
+smp_xcr0:
+    .quad 0
+smp_info:
+    .quad 0
+smp_x2apic:
+    .byte 0
+SMP_TRAMPOLINE_BOOTED:
+    .byte 0
+SMP_TRAMPOLINE_END:
+"#
+);
+
+extern "C" {
+    static SMP_TRAMPOLINE_START: u8;
+    static SMP_TRAMPOLINE_PROTECTED_MODE: u8;
+    static SMP_TRAMPOLINE_DATA: u8;
+    static SMP_TRAMPOLINE_BOOTED: u8;
+    static SMP_TRAMPOLINE_END: u8;
+}
+
+/// The data at the end of the trampoline. The layout has to match the data in the
+/// `global_asm!` block above.
+#[repr(C, packed)]
+struct TrampolineData {
+    gdt: [u64; 7],
+    gdtr: gdt::Pointer,
+    far_jump_offset: u32,
+    far_jump_selector: u16,
+    temporary_table: u32,
+    initial_cr4: u32,
+    kernel_table: u64,
+    kernel_gdtr: gdt::Pointer,
+    cr4: u64,
+    xcr0: u64,
+    info: u64,
+    x2apic: u8,
+    booted: u8,
+}
+
+/// Returns the offset of the provided trampoline symbol from the start of the trampoline.
+fn trampoline_offset(symbol: &u8) -> u64 {
+    // SAFETY: Only the addresses of the symbols are taken.
+    let start = unsafe { &SMP_TRAMPOLINE_START } as *const u8 as u64;
+    symbol as *const u8 as u64 - start
+}
+
+/// The trampoline that the application processors are started at.
+pub struct Trampoline {
+    address: PhysAddr,
+}
+
+impl Trampoline {
+    /// Copies the trampoline to a frame below 1 MiB, identity-maps it in the kernel
+    /// address space and builds the temporary page tables. The processors enter the kernel
+    /// address space with the provided GDT and CR4 (five-level paging is enabled if the
+    /// kernel address space uses it), and XCR0 if XSAVE is enabled. Their local APICs are
+    /// switched into the mode of the provided local APIC.
+    pub fn new<I>(
+        page_tables: &mut BootPageTables,
+        frame_allocator: &mut BootFrameAllocator<I>,
+        apic: &LocalApic,
+        gdt: VirtAddr,
+        cr4: Cr4Flags,
+    ) -> Self
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let frame = frame_allocator
+            .allocate_frames(1, 0x100000, Size4KiB::SIZE)
+            .expect("smp: failed to allocate the trampoline")
+            .start;
+
+        let address = frame.start_address();
+        let size = trampoline_offset(unsafe { &SMP_TRAMPOLINE_END }) as usize;
+
+        // SAFETY: The frame is identity-mapped and the trampoline is much smaller than a
+        // page.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                &SMP_TRAMPOLINE_START as *const u8,
+                address.as_u64() as *mut u8,
+                size,
+            );
+        }
+
+        unsafe {
+            page_tables.kernel.identity_map(
+                frame,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                frame_allocator,
+            )
+        }
+        .expect("smp: failed to map the trampoline")
+        .ignore();
+
+        let level_5_frame = page_tables.kernel_level_5_frame;
+        let kernel_table = level_5_frame.unwrap_or(page_tables.kernel_level_4_frame);
+
+        let mut initial_cr4 = Cr4Flags::PHYSICAL_ADDRESS_EXTENSION;
+        let mut cr4 = cr4;
+
+        if level_5_frame.is_some() {
+            initial_cr4 |= Cr4Flags::L5_PAGING;
+            cr4 |= Cr4Flags::L5_PAGING;
+        }
+
+        let xcr0 = if cr4.contains(Cr4Flags::OSXSAVE) {
+            XCr0::read_raw()
+        } else {
+            0
+        };
+
+        let data_offset = trampoline_offset(unsafe { &SMP_TRAMPOLINE_DATA });
+        let protected_mode_offset = trampoline_offset(unsafe { &SMP_TRAMPOLINE_PROTECTED_MODE });
+
+        let data = TrampolineData {
+            gdt: gdt::ENTRIES,
+            gdtr: gdt::Pointer::new(VirtAddr::new(address.as_u64() + data_offset)),
+            far_jump_offset: (address.as_u64() + protected_mode_offset) as u32,
+            // The 32-bit code segment of Ion's GDT.
+            far_jump_selector: 0x18,
+            temporary_table: Self::temporary_tables(frame_allocator, level_5_frame.is_some()),
+            initial_cr4: initial_cr4.bits() as u32,
+            kernel_table: kernel_table.start_address().as_u64(),
+            kernel_gdtr: gdt::Pointer::new(gdt),
+            cr4: cr4.bits(),
+            xcr0,
+            info: 0,
+            x2apic: apic.is_x2apic() as u8,
+            booted: 0,
+        };
+
+        // SAFETY: The trampoline has been copied to the identity-mapped frame.
+        unsafe {
+            ((address.as_u64() + data_offset) as *mut TrampolineData).write_unaligned(data);
+        }
+
+        Self { address }
+    }
+
+    /// Builds the temporary page tables that identity-map the first 2 MiB, which are used
+    /// to enter long mode. Returns the physical address of the top level table, which is
+    /// allocated below 4 GiB as CR3 is loaded in 32-bit mode.
+    fn temporary_tables<I>(frame_allocator: &mut BootFrameAllocator<I>, level_5: bool) -> u32
+    where
+        I: ExactSizeIterator + Clone,
+        I::Item: BootMemoryRegion,
+    {
+        let frames = frame_allocator
+            .allocate_frames(4, 1 << 32, Size4KiB::SIZE)
+            .expect("smp: failed to allocate the temporary page tables");
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let mut tables = frames.map(|frame| {
+            // SAFETY: The frames are identity-mapped and not used by anything else.
+            let table = unsafe { &mut *(frame.start_address().as_u64() as *mut PageTable) };
+            *table = PageTable::new();
+
+            (frame, table)
+        });
+
+        let (level_5_frame, level_5_table) = tables.next().unwrap();
+        let (level_4_frame, level_4_table) = tables.next().unwrap();
+        let (level_3_frame, level_3_table) = tables.next().unwrap();
+        let (level_2_frame, level_2_table) = tables.next().unwrap();
+
+        level_2_table[0].set_addr(PhysAddr::new(0), flags | PageTableFlags::HUGE_PAGE);
+        level_3_table[0].set_frame(level_2_frame, flags);
+        level_4_table[0].set_frame(level_3_frame, flags);
+        level_5_table[0].set_frame(level_4_frame, flags);
+
+        let top = if level_5 {
+            level_5_frame
+        } else {
+            level_4_frame
+        };
+
+        top.start_address().as_u64() as u32
+    }
+
+    /// Returns true if the last processor that has been started has reached the kernel
+    /// address space.
+    fn booted(&self) -> bool {
+        let offset = trampoline_offset(unsafe { &SMP_TRAMPOLINE_BOOTED });
+
+        // SAFETY: The trampoline frame is identity-mapped.
+        unsafe { ((self.address.as_u64() + offset) as *const u8).read_volatile() != 0 }
+    }
+
+    /// Starts the processor with the provided local APIC ID, which parks itself in the
+    /// kernel address space until the kernel writes the goto address of the provided
+    /// information (mapped at the same address in both address spaces). Returns false if
+    /// the processor did not start, in which case it is reset again so that it cannot use
+    /// the information later on.
+    pub fn start(&self, apic: &LocalApic, apic_id: u32, info: &SmpInfo) -> bool {
+        let data = self.address.as_u64() + trampoline_offset(unsafe { &SMP_TRAMPOLINE_DATA });
+
+        // SAFETY: The trampoline frame is identity-mapped and no processor is executing the
+        // trampoline at the moment.
+        unsafe {
+            let data = data as *mut TrampolineData;
+
+            core::ptr::addr_of_mut!((*data).info).write_unaligned(info as *const SmpInfo as u64);
+            core::ptr::addr_of_mut!((*data).booted).write_volatile(0);
+        }
+
+        apic.send_ipi(apic_id, ICR_INIT);
+        time::sleep_ms(10);
+
+        let vector = (self.address.as_u64() >> 12) as u32;
+
+        for timeout in STARTUP_TIMEOUTS_MS.iter() {
+            apic.send_ipi(apic_id, ICR_STARTUP | vector);
+
+            let start = time::timestamp_ms();
+
+            while time::elapsed_ms(start) < *timeout {
+                if self.booted() {
+                    return true;
+                }
+
+                core::hint::spin_loop();
+            }
+        }
+
+        apic.send_ipi(apic_id, ICR_INIT);
+        false
+    }
+}
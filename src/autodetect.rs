@@ -0,0 +1,197 @@
+// Detection of the operating systems on the volumes that the firmware can read, so that a
+// bare Ion install still shows bootable entries. The detected entries are appended to the
+// entries of the config file if `AUTO_DETECT=yes`.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode};
+
+use ion_core::autodetect;
+
+use crate::config::{BootProtocol, ConfigurationEntry, IonConfig};
+use crate::devpath;
+
+/// The directories that are searched for Linux kernels.
+const KERNEL_DIRS: &[&str] = &["", "boot"];
+
+/// The buffer that directory entries are read into. File information has to be 8-byte
+/// aligned.
+#[repr(C, align(8))]
+struct InfoBuffer([u8; 0x200]);
+
+/// Returns the name of each entry of the provided directory, along with true if the entry
+/// is a directory itself.
+pub fn list(directory: &mut Directory) -> Vec<(String, bool)> {
+    let mut buffer = InfoBuffer([0; 0x200]);
+    let mut entries = Vec::new();
+
+    // Entries whose information does not fit into the buffer end the listing early.
+    while let Ok(completion) = directory.read_entry(&mut buffer.0) {
+        let info: &mut FileInfo = match completion.unwrap() {
+            Some(info) => info,
+            None => break,
+        };
+
+        let name = char::decode_utf16(info.file_name().to_u16_slice().iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+
+        if name != "." && name != ".." {
+            let is_directory = info.attribute().contains(FileAttribute::DIRECTORY);
+            entries.push((name, is_directory));
+        }
+    }
+
+    entries
+}
+
+/// Returns true if there is a file with the provided path, which is relative to
+/// `directory`.
+fn exists(directory: &mut Directory, path: &str) -> bool {
+    match directory.open(path, FileMode::Read, FileAttribute::empty()) {
+        Ok(handle) => {
+            handle.unwrap().close();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Opens the subdirectory with the provided path, which is relative to `directory`.
+pub fn open_dir(directory: &mut Directory, path: &str) -> Option<Directory> {
+    let handle = directory
+        .open(path, FileMode::Read, FileAttribute::empty())
+        .ok()?
+        .unwrap();
+
+    Some(unsafe { Directory::new(handle) })
+}
+
+/// Leaks the provided string, as the entries have to live as long as the config.
+pub fn leak(value: String) -> &'static str {
+    Box::leak(value.into_boxed_str())
+}
+
+fn detect_linux(root: &mut Directory, volume: &'static str, entries: &mut Vec<ConfigurationEntry>) {
+    for dir in KERNEL_DIRS {
+        let files = if dir.is_empty() {
+            list(root)
+        } else {
+            match open_dir(root, dir) {
+                Some(mut directory) => list(&mut directory),
+                None => continue,
+            }
+        };
+
+        let names = files
+            .iter()
+            .filter(|(_, is_directory)| !is_directory)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+
+        let prefix = if dir.is_empty() {
+            format!("devpath://{}", volume)
+        } else {
+            format!("devpath://{}/{}", volume, dir)
+        };
+
+        for kernel in names
+            .iter()
+            .filter(|name| autodetect::is_linux_kernel(name))
+        {
+            let initrd = autodetect::matching_initrd(kernel, &names)
+                .map(|initrd| leak(format!("{}/{}", prefix, initrd)));
+
+            entries.push(ConfigurationEntry::generated(
+                leak(format!("Linux ({})", kernel)),
+                BootProtocol::Linux,
+                leak(format!("{}/{}", prefix, kernel)),
+                initrd,
+                volume,
+            ));
+        }
+    }
+}
+
+fn detect_efi(root: &mut Directory, volume: &'static str, entries: &mut Vec<ConfigurationEntry>) {
+    if exists(root, &autodetect::WINDOWS_BOOT_MANAGER.replace('/', "\\")) {
+        entries.push(ConfigurationEntry::generated(
+            "Windows Boot Manager",
+            BootProtocol::Chainload,
+            leak(format!(
+                "devpath://{}/{}",
+                volume,
+                autodetect::WINDOWS_BOOT_MANAGER
+            )),
+            None,
+            volume,
+        ));
+    }
+
+    let mut efi_dir = match open_dir(root, "EFI") {
+        Some(efi_dir) => efi_dir,
+        None => return,
+    };
+
+    for (vendor, is_directory) in list(&mut efi_dir) {
+        if !is_directory || !autodetect::is_scanned_efi_dir(&vendor) {
+            continue;
+        }
+
+        let applications = match open_dir(&mut efi_dir, &vendor) {
+            Some(mut directory) => list(&mut directory),
+            None => continue,
+        };
+
+        for (application, _) in applications
+            .iter()
+            .filter(|(name, is_directory)| !is_directory && autodetect::is_efi_application(name))
+        {
+            entries.push(ConfigurationEntry::generated(
+                leak(format!("{} ({})", vendor, application)),
+                BootProtocol::Chainload,
+                leak(format!(
+                    "devpath://{}/EFI/{}/{}",
+                    volume, vendor, application
+                )),
+                None,
+                volume,
+            ));
+        }
+    }
+}
+
+/// Scans every volume with a file system that the firmware supports for Linux kernels,
+/// the Windows Boot Manager and other EFI applications, and appends an entry for each of
+/// them. Kernels that are already booted by an entry of the config file are skipped.
+pub fn detect(system_table: &SystemTable<Boot>, boot_config: &mut IonConfig) {
+    let mut detected = Vec::new();
+
+    for (handle, path) in devpath::volumes(system_table) {
+        let mut root = match devpath::open_filesystem(system_table, handle) {
+            Some(root) => root,
+            None => continue,
+        };
+
+        let volume = leak(path);
+
+        detect_linux(&mut root, volume, &mut detected);
+        detect_efi(&mut root, volume, &mut detected);
+
+        root.close();
+    }
+
+    detected.retain(|entry| {
+        !boot_config
+            .entries
+            .iter()
+            .any(|existing| existing.path() == entry.path())
+    });
+
+    log::info!("autodetect: found {} operating systems", detected.len());
+    boot_config.entries.extend(detected);
+}
@@ -0,0 +1,85 @@
+// Loading of the Boot Loader Specification entries in `\loader\entries` on the boot volume,
+// which distributions that use systemd-boot install along with their kernels. The entries
+// are appended to the entries of the config file.
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::media::file::Directory;
+use uefi::table::boot::MemoryType;
+
+use ion_core::bls::{self, Entry};
+
+use crate::autodetect;
+use crate::config::ConfigurationEntry;
+
+/// The architecture of the firmware as named by the `architecture` key of the entries.
+#[cfg(target_arch = "x86_64")]
+const ARCHITECTURE: &str = "x64";
+#[cfg(target_arch = "aarch64")]
+const ARCHITECTURE: &str = "aa64";
+#[cfg(target_arch = "riscv64")]
+const ARCHITECTURE: &str = "riscv64";
+
+/// Returns the entries in `\loader\entries` on the boot volume that can be booted on this
+/// firmware, in the order in which they are shown in the menu.
+pub fn load(system_table: &SystemTable<Boot>, root: &mut Directory) -> Vec<ConfigurationEntry> {
+    let files = match autodetect::open_dir(root, bls::ENTRIES_DIR) {
+        Some(mut directory) => autodetect::list(&mut directory),
+        None => return Vec::new(),
+    };
+
+    let mut entries = Vec::new();
+
+    for (file_name, is_directory) in files {
+        if is_directory || bls::entry_id(&file_name).is_none() {
+            continue;
+        }
+
+        let file_name = autodetect::leak(file_name);
+        let path = autodetect::leak(format!("/loader/entries/{}", file_name));
+
+        let contents = match crate::read_file(system_table, root, path, MemoryType::LOADER_DATA)
+            .ok()
+            .and_then(|contents| core::str::from_utf8(contents).ok())
+        {
+            Some(contents) => contents,
+            None => {
+                log::warn!("bls: failed to read the entry {}", file_name);
+                continue;
+            }
+        };
+
+        let entry = Entry::parse(file_name, contents);
+
+        if entry.supports(ARCHITECTURE) {
+            entries.push(entry);
+        }
+    }
+
+    entries.sort_by(bls::compare);
+
+    let entries = entries
+        .iter()
+        .filter_map(|entry| {
+            let generated = ConfigurationEntry::from_loader_entry(entry);
+
+            if generated.is_none() {
+                log::warn!(
+                    "bls: the entry {} has neither a kernel nor an EFI application",
+                    entry.id
+                );
+            }
+
+            generated
+        })
+        .collect::<Vec<_>>();
+
+    log::info!(
+        "bls: found {} entries in \\{}",
+        entries.len(),
+        bls::ENTRIES_DIR
+    );
+    entries
+}
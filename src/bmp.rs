@@ -0,0 +1,156 @@
+use alloc::vec::Vec;
+
+use crate::logger::Color;
+
+const BMP_MAGIC: [u8; 2] = *b"BM";
+
+/// The size of the file header and the `BITMAPINFOHEADER` in bytes.
+const HEADER_SIZE: usize = 14 + 40;
+
+/// Uncompressed pixel data.
+const BI_RGB: u32 = 0;
+/// Uncompressed pixel data with color masks. Ion only supports the default BGRA masks.
+const BI_BITFIELDS: u32 = 3;
+
+/// An uncompressed 24-bit or 32-bit Windows bitmap loaded from disk.
+pub struct Bitmap {
+    pixels: &'static [u8],
+
+    width: usize,
+    height: usize,
+
+    bytes_per_pixel: usize,
+    /// The size of each row in bytes, including the padding to a multiple of four bytes.
+    row_size: usize,
+    /// Whether the rows are stored from top to bottom. Bitmaps are usually stored
+    /// bottom-up.
+    top_down: bool,
+}
+
+impl Bitmap {
+    /// Parses the provided bitmap file. Returns [`None`] if the file is not a valid
+    /// bitmap or uses an unsupported pixel format.
+    pub fn parse(data: &'static [u8]) -> Option<Self> {
+        if !data.starts_with(&BMP_MAGIC) {
+            return None;
+        }
+
+        let read_u16 = |offset: usize| -> Option<u16> {
+            let bytes = data.get(offset..offset + 2)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+
+        let read_u32 = |offset: usize| -> Option<u32> {
+            let bytes = data.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        let pixels_offset = read_u32(10)? as usize;
+        let width = read_u32(18)? as i32;
+        let height = read_u32(22)? as i32;
+        let bits_per_pixel = read_u16(28)?;
+        let compression = read_u32(30)?;
+
+        if width <= 0 || height == 0 {
+            return None;
+        }
+
+        let bytes_per_pixel = match (bits_per_pixel, compression) {
+            (24, BI_RGB) => 3,
+            (32, BI_RGB) | (32, BI_BITFIELDS) => 4,
+            _ => return None,
+        };
+
+        let width = width as usize;
+        let row_size = (width * bytes_per_pixel + 3) & !3;
+
+        // A negative height means that the rows are stored from top to bottom.
+        let top_down = height < 0;
+        let height = height.unsigned_abs() as usize;
+
+        let pixels = data.get(pixels_offset..(pixels_offset + row_size * height))?;
+
+        Some(Self {
+            pixels,
+
+            width,
+            height,
+
+            bytes_per_pixel,
+            row_size,
+            top_down,
+        })
+    }
+
+    /// Returns the width of the bitmap in pixels.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the bitmap in pixels.
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the color of the pixel at the provided position.
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let row = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+
+        let offset = row * self.row_size + x * self.bytes_per_pixel;
+        let (blue, green, red) = (
+            self.pixels[offset] as u32,
+            self.pixels[offset + 1] as u32,
+            self.pixels[offset + 2] as u32,
+        );
+
+        Color::new((red << 16) | (green << 8) | blue)
+    }
+}
+
+/// Encodes an uncompressed 24-bit bitmap with the provided dimensions. The color of each
+/// pixel is provided by the `pixel` function.
+pub fn encode<F>(width: usize, height: usize, pixel: F) -> Vec<u8>
+where
+    F: Fn(usize, usize) -> Color,
+{
+    let row_size = (width * 3 + 3) & !3;
+    let file_size = HEADER_SIZE + row_size * height;
+
+    let mut data = Vec::with_capacity(file_size);
+
+    // The file header.
+    data.extend_from_slice(&BMP_MAGIC);
+    data.extend_from_slice(&(file_size as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+
+    // The BITMAPINFOHEADER.
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as u32).to_le_bytes());
+    data.extend_from_slice(&(height as u32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes()); // The amount of planes.
+    data.extend_from_slice(&24u16.to_le_bytes());
+    data.extend_from_slice(&BI_RGB.to_le_bytes());
+    data.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 16]); // The resolution and the palette size.
+
+    // The rows are stored from bottom to top.
+    for y in (0..height).rev() {
+        let row_start = data.len();
+
+        for x in 0..width {
+            let [blue, green, red, _] = pixel(x, y).as_u32().to_le_bytes();
+            data.extend_from_slice(&[blue, green, red]);
+        }
+
+        data.resize(row_start + row_size, 0);
+    }
+
+    data
+}
@@ -0,0 +1,87 @@
+use alloc::vec;
+
+use uefi::prelude::*;
+use uefi::table::runtime::ResetType;
+
+use ion_core::bootorder::{self, LoadOption};
+
+use crate::nvram;
+
+/// The maximum size of a boot option that is read to check whether it is active.
+const BOOT_OPTION_MAX_SIZE: usize = 4096;
+
+/// The maximum amount of boot options in the `BootOrder` variable.
+const BOOT_ORDER_MAX_LEN: usize = 256;
+
+/// Reads the boot option with the provided number.
+fn read_option(system_table: &SystemTable<Boot>, number: u16) -> Option<LoadOption> {
+    let mut buf = vec![0; BOOT_OPTION_MAX_SIZE];
+    let name = bootorder::boot_option_name(number);
+
+    let size = nvram::read(system_table, &nvram::GLOBAL_VENDOR, &name, &mut buf).ok()?;
+    LoadOption::parse(&buf[..size])
+}
+
+/// Restarts the machine into the boot option that the firmware would have tried next if
+/// Ion had failed to start, so that the machine can still reach another OS or a recovery
+/// loader when none of Ion's entries can be booted. The option is selected for the next
+/// boot using the `BootNext` variable. Returns if there is no such option or it could not
+/// be selected.
+pub fn boot_fallback(system_table: &SystemTable<Boot>) {
+    let mut buf = vec![0; BOOT_ORDER_MAX_LEN * 2];
+
+    let order = match nvram::read(system_table, &nvram::GLOBAL_VENDOR, "BootOrder", &mut buf) {
+        Ok(size) => bootorder::parse_boot_order(&buf[..size]),
+        Err(error) => {
+            log::warn!("bootorder: failed to read BootOrder ({:?})", error);
+            return;
+        }
+    };
+
+    let mut current = [0; 2];
+    let current = nvram::read(
+        system_table,
+        &nvram::GLOBAL_VENDOR,
+        "BootCurrent",
+        &mut current,
+    )
+    .ok()
+    .filter(|&size| size == 2)
+    .map(|_| u16::from_le_bytes(current));
+
+    let fallback = bootorder::fallback_option(&order, current, |number| {
+        read_option(system_table, number).map_or(false, |option| option.is_active())
+    });
+
+    let number = match fallback {
+        Some(number) => number,
+        None => {
+            log::warn!("bootorder: no boot option follows Ion in the boot order");
+            return;
+        }
+    };
+
+    let description = read_option(system_table, number)
+        .map(|option| option.description)
+        .unwrap_or_default();
+
+    if let Err(error) = nvram::write(
+        system_table,
+        &nvram::GLOBAL_VENDOR,
+        "BootNext",
+        &number.to_le_bytes(),
+    ) {
+        log::warn!("bootorder: failed to set BootNext ({:?})", error);
+        return;
+    }
+
+    log::info!(
+        "bootorder: restarting into {} ({})",
+        bootorder::boot_option_name(number),
+        description
+    );
+
+    system_table
+        .runtime_services()
+        .reset(ResetType::Cold, Status::SUCCESS, None)
+}
@@ -1,22 +1,56 @@
 use alloc::string::String;
-use alloc::vec::Vec;
+use log::LevelFilter;
+use spin::mutex::SpinMutex;
 use uefi::prelude::*;
-use uefi::proto::console::text::{Input, Key};
 use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile};
+use uefi::proto::media::fs::SimpleFileSystem;
 use uefi::table::boot::{AllocateType, MemoryType};
 
+use ion_core::config::{self, CountdownKeys, Display, IdleAction, Line, Preferences};
+use ion_core::cpu::Features;
+use ion_core::hibernation::ResumeAction;
+use ion_core::uri;
+
+use crate::bls;
+use crate::devpath;
+use crate::error::IonError;
+use crate::i18n::{self, Language};
+use crate::input;
+use crate::keymap::Keymap;
+use crate::nvram;
 use crate::prelude::*;
+use crate::serial;
 
-const CONFIG_PATHS: &[&str] = &["boot\\ion.cfg", "ion.cfg"];
+pub use ion_core::config::BootProtocol;
+pub use ion_core::uri::{Uri, UriParseError};
 
-#[derive(Debug, Clone, Copy)]
-pub enum BootProtocol {
-    Stivale2,
-    Stivale,
-    Multiboot,
-    Multiboot2,
-    Linux,
-}
+/// The paths at which the config file is searched for, along with the directory that
+/// contains it.
+const CONFIG_PATHS: &[(&str, &str)] = &[("boot\\ion.cfg", "boot"), ("ion.cfg", "")];
+
+/// The directory that the config file has been loaded from, which paths without a resource
+/// are relative to.
+static CONFIG_DIR: SpinMutex<&str> = SpinMutex::new("");
+
+/// The name of the variable in which the running OS requests an entry for the next boot.
+const BOOT_NEXT_VARIABLE: &str = "IonBootNext";
+
+/// The maximum size of the `IonBootNext` variable.
+const BOOT_NEXT_MAX_SIZE: usize = 1024;
+
+/// The name of the variable in which the preferences changed in the menu are stored.
+const PREFERENCES_VARIABLE: &str = "IonPreferences";
+
+/// The maximum size of the `IonPreferences` variable.
+const PREFERENCES_MAX_SIZE: usize = 1024;
+
+/// The time in seconds after which the idle action of the boot menu is taken, unless
+/// specified otherwise by `MENU_IDLE_TIMEOUT=`.
+const DEFAULT_IDLE_TIMEOUT: usize = 300;
+
+/// The size of the stack allocated for kernels that do not provide their own stack, unless
+/// specified otherwise by `STACK_SIZE=`.
+const DEFAULT_STACK_SIZE: usize = 64 * 1024;
 
 #[derive(Clone)]
 pub struct ConfigurationEntry {
@@ -24,9 +58,161 @@ pub struct ConfigurationEntry {
     path: &'static str,
     name: &'static str,
     command_line: &'static str,
+    comment: &'static str,
+    icon: Option<&'static str>,
+    stack_size: usize,
+    kaslr: bool,
+    smep: bool,
+    smap: bool,
+    umip: bool,
+    dtb_path: Option<&'static str>,
+    initrd_path: Option<&'static str>,
+    runtime_remap: bool,
+    pci_tag: bool,
+    resume_swap: Option<&'static str>,
+    hiberfil: Option<&'static str>,
+    prefix: Option<&'static str>,
+    requires: Features,
 }
 
 impl ConfigurationEntry {
+    /// Creates an entry with the provided name, which uses the default value of every key.
+    fn new(name: &'static str) -> Self {
+        Self {
+            // We use stivale 2 as the default boot protocol.
+            protocol: BootProtocol::Stivale2,
+            name,
+            // By default we will set the kernel command line to an empty string.
+            command_line: "",
+            // By default we will set the kernel path to an empty string.
+            path: "",
+            // By default the entry does not have a description.
+            comment: "",
+            icon: None,
+            stack_size: DEFAULT_STACK_SIZE,
+            // By default the kernel and the direct map are placed at fixed addresses, as
+            // randomizing them has to be requested with `KASLR=yes`.
+            kaslr: false,
+            // By default the kernel is responsible for enabling the supervisor mode
+            // protections itself.
+            smep: false,
+            smap: false,
+            umip: false,
+            dtb_path: None,
+            initrd_path: None,
+            // By default the runtime services are left identity mapped, so that the kernel
+            // can call SetVirtualAddressMap itself.
+            runtime_remap: false,
+            pci_tag: false,
+            resume_swap: None,
+            hiberfil: None,
+            prefix: None,
+            // By default the entry can run on any processor.
+            requires: Features::empty(),
+        }
+    }
+
+    /// Prepends the provided prefix to the relative paths of the kernel and its modules.
+    fn apply_prefix(&mut self, prefix: &'static str) {
+        let apply = |path: &'static str| -> &'static str {
+            match config::apply_prefix(prefix, path) {
+                Some(path) => alloc::boxed::Box::leak(path.into_boxed_str()),
+                None => path,
+            }
+        };
+
+        self.path = apply(self.path);
+        self.initrd_path = self.initrd_path.map(apply);
+        self.dtb_path = self.dtb_path.map(apply);
+    }
+
+    /// Expands the `${ROOT_UUID}` and `${PART_GUID:<n>}` macros of the command line from
+    /// the GPT partition that Ion has been loaded from, which has the provided device path.
+    pub fn expand_command_line(
+        &mut self,
+        system_table: &SystemTable<Boot>,
+        boot_partition: Option<&[u8]>,
+    ) -> Result<(), IonError> {
+        if !self.command_line.contains("${") {
+            return Ok(());
+        }
+
+        let command_line = config::expand_command_line(self.command_line, |name| {
+            let partition = boot_partition?;
+
+            let partition = match name {
+                config::CommandLineMacro::RootUuid => ion_core::devpath::gpt_partition(partition),
+                config::CommandLineMacro::PartGuid(number) => {
+                    devpath::sibling_partition(system_table, partition, number)
+                }
+            };
+
+            partition.map(|partition| partition.guid)
+        })
+        .map_err(|(name, error)| IonError::CommandLineMacro(String::from(name), error.as_str()))?;
+
+        log::debug!("config: expanded the command line to {}", command_line);
+        self.command_line = alloc::boxed::Box::leak(command_line.into_boxed_str());
+        Ok(())
+    }
+
+    /// Creates an entry that is not specified in the config file (e.g. a detected
+    /// operating system), which boots the kernel at the provided URI.
+    pub fn generated(
+        name: &'static str,
+        protocol: BootProtocol,
+        path: &'static str,
+        initrd_path: Option<&'static str>,
+        comment: &'static str,
+    ) -> Self {
+        Self {
+            protocol,
+            path,
+            initrd_path,
+            comment,
+            ..Self::new(name)
+        }
+    }
+
+    /// Creates an entry from the provided Boot Loader Specification entry, whose paths are
+    /// on the boot volume. Returns [`None`] if the entry has neither a Linux kernel nor an
+    /// EFI application.
+    pub fn from_loader_entry(entry: &ion_core::bls::Entry<'static>) -> Option<Self> {
+        let on_boot_volume = |path: &str| -> &'static str {
+            let absolute = alloc::format!("/{}", path.trim_start_matches('/'));
+            alloc::boxed::Box::leak(absolute.into_boxed_str())
+        };
+
+        let (protocol, path) = match (entry.linux, entry.efi) {
+            (Some(linux), _) => (BootProtocol::Linux, linux),
+            (None, Some(efi)) => (BootProtocol::Chainload, efi),
+            (None, None) => return None,
+        };
+
+        // Ion passes a single initrd to the kernel.
+        if entry.initrd.len() > 1 {
+            log::warn!(
+                "config: only the first initrd of the entry {} is loaded",
+                entry.id
+            );
+        }
+
+        let name = alloc::boxed::Box::leak(entry.name().into_boxed_str());
+        let command_line = alloc::boxed::Box::leak(entry.command_line().into_boxed_str());
+
+        Some(Self {
+            command_line,
+            dtb_path: entry.devicetree.map(on_boot_volume),
+            ..Self::generated(
+                name,
+                protocol,
+                on_boot_volume(path),
+                entry.initrd.first().map(|initrd| on_boot_volume(initrd)),
+                entry.id,
+            )
+        })
+    }
+
     /// Returns the path of the kernel in the config entry.
     #[inline]
     pub fn path(&self) -> &'static str {
@@ -44,11 +230,138 @@ impl ConfigurationEntry {
     pub fn name(&self) -> &'static str {
         self.name
     }
+
+    /// Returns the command line that is passed to the kernel.
+    #[inline]
+    pub fn command_line(&self) -> &'static str {
+        self.command_line
+    }
+
+    /// Returns the description of the config entry shown in the menu footer.
+    #[inline]
+    pub fn comment(&self) -> &'static str {
+        self.comment
+    }
+
+    /// Returns the URI of the icon shown next to the name of the config entry in the menu,
+    /// which is a bitmap or a PNG image.
+    #[inline]
+    pub fn icon(&self) -> Option<&'static str> {
+        self.icon
+    }
+
+    /// Returns the size in bytes of the stack that is allocated if the kernel does not
+    /// provide its own stack.
+    #[inline]
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    /// Returns true if the physical load address of the kernel and the virtual address of
+    /// the direct map of the physical memory should be randomized.
+    #[inline]
+    pub fn kaslr(&self) -> bool {
+        self.kaslr
+    }
+
+    /// Returns true if supervisor mode execution prevention should be enabled before
+    /// jumping to the kernel.
+    #[inline]
+    pub fn smep(&self) -> bool {
+        self.smep
+    }
+
+    /// Returns true if supervisor mode access prevention should be enabled before jumping
+    /// to the kernel.
+    #[inline]
+    pub fn smap(&self) -> bool {
+        self.smap
+    }
+
+    /// Returns true if user mode instruction prevention should be enabled before jumping
+    /// to the kernel.
+    #[inline]
+    pub fn umip(&self) -> bool {
+        self.umip
+    }
+
+    /// Returns the URI of the flattened device tree blob passed to the kernel (if any). If
+    /// it is not specified the device tree provided by the firmware is passed instead.
+    #[inline]
+    pub fn dtb_path(&self) -> Option<&'static str> {
+        self.dtb_path
+    }
+
+    /// Returns the URI of the initial ramdisk passed to Linux kernels (if any).
+    #[inline]
+    pub fn initrd_path(&self) -> Option<&'static str> {
+        self.initrd_path
+    }
+
+    /// Returns true if the EFI runtime services should be switched to a higher half mapping
+    /// with `SetVirtualAddressMap` before jumping to the kernel.
+    #[inline]
+    pub fn runtime_remap(&self) -> bool {
+        self.runtime_remap
+    }
+
+    /// Returns true if the PCI devices should be enumerated and passed to the kernel.
+    #[inline]
+    pub fn pci_tag(&self) -> bool {
+        self.pci_tag
+    }
+
+    /// Returns the device path of the swap partition that Linux hibernates to, if any. The
+    /// entry is considered hibernated if the swap partition contains a swsusp signature.
+    #[inline]
+    pub fn resume_swap(&self) -> Option<&'static str> {
+        self.resume_swap
+    }
+
+    /// Returns the URI of the Windows hibernation file (`hiberfil.sys`), if any. Reading it
+    /// requires a driver for the file system of the Windows volume (see `DRIVER=`).
+    #[inline]
+    pub fn hiberfil(&self) -> Option<&'static str> {
+        self.hiberfil
+    }
+
+    /// Returns the processor features that the kernel requires.
+    #[inline]
+    pub fn requires(&self) -> Features {
+        self.requires
+    }
+
+    /// Returns the processor features that the kernel requires but the processor does not
+    /// support. The entry cannot be booted unless the result is empty.
+    pub fn missing_features(&self) -> Features {
+        self.requires.missing_from(crate::arch::cpu_features())
+    }
 }
 
 #[derive(Debug)]
 struct BootConfigutation {
     timeout: usize,
+    countdown_keys: CountdownKeys,
+    keymap: Keymap,
+    serial: Option<u32>,
+    beep: bool,
+    language: Option<Language>,
+    log_level: LevelFilter,
+    font: Option<&'static str>,
+    font_scale: Option<usize>,
+    resolution: Option<(usize, usize)>,
+    display: Option<Display<'static>>,
+    splash: Option<&'static str>,
+    preserve_logo: bool,
+    debug_wait: bool,
+    drivers: alloc::vec::Vec<&'static str>,
+    resume_action: ResumeAction,
+    idle_action: Option<IdleAction>,
+    idle_timeout: usize,
+    auto_detect: bool,
+    prefix: Option<&'static str>,
+    /// The name of the entry that has been booted last (see [`Preferences`]).
+    last_entry: Option<&'static str>,
 }
 
 pub struct IonConfig {
@@ -61,136 +374,330 @@ impl IonConfig {
     pub fn timeout(&self) -> usize {
         self.boot.timeout
     }
+
+    /// Returns the keys that interrupt the countdown of the boot menu.
+    pub fn countdown_keys(&self) -> CountdownKeys {
+        self.boot.countdown_keys
+    }
+
+    /// Returns the keyboard layout used to translate keyboard input.
+    pub fn keymap(&self) -> Keymap {
+        self.boot.keymap
+    }
+
+    /// Returns the baud rate of the serial console if the boot menu and the log should
+    /// be mirrored over the serial console.
+    pub fn serial(&self) -> Option<u32> {
+        self.boot.serial
+    }
+
+    /// Returns true if the boot menu should give audible feedback using the PC speaker.
+    pub fn beep(&self) -> bool {
+        self.boot.beep
+    }
+
+    /// Returns the language of the boot menu, if it was specified in the config.
+    pub fn language(&self) -> Option<Language> {
+        self.boot.language
+    }
+
+    /// Returns the maximum level of the log records that are displayed.
+    pub fn log_level(&self) -> LevelFilter {
+        self.boot.log_level
+    }
+
+    /// Returns the URI of the PC Screen Font file used instead of the built-in font, if
+    /// specified.
+    pub fn font(&self) -> Option<&'static str> {
+        self.boot.font
+    }
+
+    /// Returns the integer factor by which each glyph is scaled up. If [`None`], the
+    /// scale is chosen automatically from the display resolution.
+    pub fn font_scale(&self) -> Option<usize> {
+        self.boot.font_scale
+    }
+
+    /// Returns the resolution that the display should be switched to, if specified.
+    /// Otherwise the preferred resolution of the display is used.
+    pub fn resolution(&self) -> Option<(usize, usize)> {
+        self.boot.resolution
+    }
+
+    /// Returns the graphics output that should be used, if specified. Otherwise the output
+    /// of the console is used.
+    pub fn display(&self) -> Option<Display<'static>> {
+        self.boot.display
+    }
+
+    /// Returns the URI of the logo shown by the splash screen if the splash screen is
+    /// enabled. The URI is empty if the splash screen should not show a logo.
+    pub fn splash(&self) -> Option<&'static str> {
+        self.boot.splash
+    }
+
+    /// Returns true if the splash screen should keep the logo that the firmware has drawn
+    /// on the screen (see the BGRT) instead of showing its own logo.
+    pub fn preserve_logo(&self) -> bool {
+        self.boot.preserve_logo
+    }
+
+    /// Returns true if Ion should print its load address and wait for a key press before
+    /// showing the boot menu, so that a debugger can be attached.
+    pub fn debug_wait(&self) -> bool {
+        self.boot.debug_wait
+    }
+
+    /// Returns the URIs of the UEFI drivers that are loaded before booting, in the order
+    /// in which they were specified.
+    pub fn drivers(&self) -> &[&'static str] {
+        &self.boot.drivers
+    }
+
+    /// Returns what the boot menu does if an entry has a pending hibernation image.
+    pub fn resume_action(&self) -> ResumeAction {
+        self.boot.resume_action
+    }
+
+    /// Returns what the boot menu does once it has been left idle, along with the timeout
+    /// in milliseconds, if anything.
+    pub fn idle_action(&self) -> Option<(IdleAction, u64)> {
+        Some((self.boot.idle_action?, self.boot.idle_timeout as u64 * 1000))
+    }
+
+    /// Returns true if the volumes should be scanned for operating systems, which are
+    /// appended to the entries of the config file.
+    pub fn auto_detect(&self) -> bool {
+        self.boot.auto_detect
+    }
+
+    /// Returns the index of the entry that is selected initially, which is the entry that
+    /// has been booted last if it still exists.
+    pub fn default_entry(&self) -> usize {
+        self.boot
+            .last_entry
+            .and_then(|name| self.entries.iter().position(|entry| entry.name() == name))
+            .unwrap_or(0)
+    }
 }
 
-/// This function is responsible for wating for a keystroke event and returns the respective
-/// key code for that keystroke.
-pub fn get_char(system_table: &SystemTable<Boot>) -> Key {
-    unsafe {
-        // Retrieve the input protocol from the boot services,
-        let input_protocol = system_table
-            .boot_services()
-            .locate_protocol::<Input>()
-            .expect_success("Failed to locate input protocol");
-
-        let key = &mut *input_protocol.get(); // Get the inner cell value
-        let wait_for_key_event = key.wait_for_key_event(); // Get a reference to the wait for key event
-
-        // Loop until there is a keyboard event
-        loop {
-            system_table
-                .boot_services()
-                .wait_for_event(&mut [wait_for_key_event])
-                .expect_success("Failed add event in wait queue");
-
-            // Try and read the next keystroke from the input device, if any.
-            let scancode = key.read_key().expect_success("Failed to read key");
-
-            if let Some(code) = scancode {
-                return code;
-            }
+/// Reads and deletes the `IonBootNext` variable and returns the entry that it requests, with
+/// the command line replaced if the request contains one. The variable is deleted before the
+/// entry is booted, so that an entry that fails to boot is only tried once.
+pub fn take_boot_next(
+    system_table: &SystemTable<Boot>,
+    boot_config: &IonConfig,
+) -> Option<ConfigurationEntry> {
+    // The command line has to outlive the config, so the buffer is never freed.
+    let buf = alloc::vec![0; BOOT_NEXT_MAX_SIZE].leak();
+
+    let value = match nvram::read_str(system_table, &nvram::ION_VENDOR, BOOT_NEXT_VARIABLE, buf) {
+        Ok(value) => value,
+        Err(nvram::Error::NotFound) => return None,
+        Err(error) => {
+            log::warn!(
+                "config: failed to read {} ({:?})",
+                BOOT_NEXT_VARIABLE,
+                error
+            );
+            return None;
+        }
+    };
+
+    if let Err(error) = nvram::delete(system_table, &nvram::ION_VENDOR, BOOT_NEXT_VARIABLE) {
+        // Booting the entry again on every boot is worse than ignoring the request.
+        log::warn!(
+            "config: failed to delete {} ({:?})",
+            BOOT_NEXT_VARIABLE,
+            error
+        );
+        return None;
+    }
+
+    let request = parse_value(BOOT_NEXT_VARIABLE, value, config::parse_boot_next(value))?;
+
+    let mut entry = match boot_config
+        .entries
+        .iter()
+        .find(|entry| entry.name() == request.entry)
+    {
+        Some(entry) => entry.clone(),
+        None => {
+            log::warn!(
+                "config: {} requests the unknown entry {}",
+                BOOT_NEXT_VARIABLE,
+                request.entry
+            );
+            return None;
         }
+    };
+
+    if let Some(command_line) = request.command_line {
+        entry.command_line = command_line;
     }
+
+    log::info!(
+        "config: booting {} as requested by {}",
+        entry.name(),
+        BOOT_NEXT_VARIABLE
+    );
+    Some(entry)
 }
 
-pub struct Uri {
-    resource: String,
-    partition: Option<usize>,
-    path: String,
+/// Reads the preferences from the `IonPreferences` variable. Returns the default
+/// preferences if the variable does not exist or could not be read.
+fn read_preferences(system_table: &SystemTable<Boot>, buf: &mut [u8]) -> Preferences<'_> {
+    match nvram::read_str(system_table, &nvram::ION_VENDOR, PREFERENCES_VARIABLE, buf) {
+        Ok(value) => Preferences::parse(value),
+        Err(nvram::Error::NotFound) => Preferences::default(),
+        Err(error) => {
+            log::warn!(
+                "config: failed to read {} ({:?})",
+                PREFERENCES_VARIABLE,
+                error
+            );
+            Preferences::default()
+        }
+    }
 }
 
-impl Uri {
-    /// Returns the path component of the URI.
-    pub fn path(&self) -> &str {
-        &self.path
+/// Stores the provided value of the preference with the provided key (e.g. `LOG_LEVEL`) in
+/// the `IonPreferences` variable, so that it is used on the next boot instead of the value
+/// in the config file. The variable is only written if the value has changed, as the flash
+/// memory that holds the variables wears out.
+pub fn save_preference(system_table: &SystemTable<Boot>, key: &str, value: &str) {
+    let mut buf = alloc::vec![0; PREFERENCES_MAX_SIZE];
+    let mut preferences = read_preferences(system_table, &mut buf);
+    let previous = preferences;
+
+    if !preferences.set(key, value) || preferences == previous {
+        return;
+    }
+
+    let encoded = preferences.encode();
+
+    if let Err(error) = nvram::write_str(
+        system_table,
+        &nvram::ION_VENDOR,
+        PREFERENCES_VARIABLE,
+        &encoded,
+    ) {
+        log::warn!(
+            "config: failed to write {} ({:?})",
+            PREFERENCES_VARIABLE,
+            error
+        );
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum UriParseError {
-    /// Missing the resource part of the URI. (e.g. `:///root/path`)
-    MissingResource,
-    /// Invalid syntax for the URI. (e.g. `://`, `:^)` or invalid partition number.)
-    InvalidSyntax,
-    /// Invalid partition number or the provided partition number is out of bounds
-    /// `0..256`.
-    InvalidPartition,
+/// Parses the provided URI. Paths without a resource (e.g. `kernel.elf` or `../kernel.elf`)
+/// are resolved relative to the directory that the config file has been loaded from.
+pub fn parse_uri(uri: &str) -> Result<Uri, UriParseError> {
+    uri::parse_uri_relative(uri, *CONFIG_DIR.lock())
 }
 
-pub fn handle_uri_redirect<'a>(parsed_uri: &Uri, root: &'a mut Directory) -> &'a mut Directory {
-    match parsed_uri.resource.as_ref() {
+/// Returns the root directory of the volume of the provided URI. The `root` directory is
+/// the root of the volume that the config file has been loaded from.
+pub fn handle_uri_redirect<'a>(
+    system_table: &SystemTable<Boot>,
+    parsed_uri: &Uri,
+    root: &'a mut Directory,
+) -> Result<&'a mut Directory, IonError> {
+    match parsed_uri.resource() {
         "boot" => {
-            if parsed_uri.partition.is_some() {
-                unimplemented!()
+            if parsed_uri.partition().is_some() {
+                Err(IonError::UnsupportedResource(String::from(
+                    "boot:// with a partition",
+                )))
             } else {
-                // The user has not provided a partition number, so we will
-                // use the root directory of the boot partition instead.
-                root
+                // The user has not provided a partition number, so we will use the root
+                // directory of the volume that the config file has been loaded from.
+                Ok(root)
             }
         }
 
-        "hdd" => unimplemented!(),
-        "odd" => unimplemented!(),
-        "guid" => unimplemented!(),
-        "uuid" => unimplemented!(),
+        "devpath" => {
+            // The device path is always present in `devpath://` URIs.
+            let device_path = parsed_uri.device_path().unwrap();
 
-        "bios" => {
-            panic!(
-                "bios:// resource is no longer supported. Checkout CONFIG.md for hdd:// and odd://"
-            )
+            let volume = devpath::open_volume(system_table, device_path)
+                .ok_or_else(|| IonError::VolumeNotFound(String::from(device_path)))?;
+
+            // The volume stays open until the kernel is booted.
+            Ok(alloc::boxed::Box::leak(alloc::boxed::Box::new(volume)))
+        }
+
+        "search" => {
+            // The label is always present in `search://` URIs.
+            let marker = parsed_uri.label().unwrap();
+
+            // The boot volume is preferred, as it is usually the live media itself.
+            if devpath::volume_matches(root, marker) {
+                return Ok(root);
+            }
+
+            let volume = devpath::search_volume(system_table, marker)
+                .ok_or_else(|| IonError::MarkerNotFound(String::from(marker)))?;
+
+            // The volume stays open until the kernel is booted.
+            Ok(alloc::boxed::Box::leak(alloc::boxed::Box::new(volume)))
         }
 
-        resource => panic!("unsupported resource type: {}", resource),
+        "bios" => Err(IonError::UnsupportedResource(String::from(
+            "bios:// is no longer supported. Checkout CONFIG.md for hdd:// and odd://",
+        ))),
+
+        // TODO: Support the hdd://, odd://, guid:// and uuid:// resources.
+        resource => Err(IonError::UnsupportedResource(String::from(resource))),
     }
 }
 
-/// Helper function to parse the path URI. A URI takes the form of:
-/// `resource:///root/path`. This function will return false if the URI is
-/// not valid.
-pub fn parse_uri(uri: &'static str) -> Result<Uri, UriParseError> {
-    // 1. Seperate the domain from the URI.
-    let uri = uri.split(':').collect::<Vec<_>>();
+/// Returns the handle of the volume of the provided URI, which is needed to describe the
+/// files on it with a device path. The `root` directory is the root of the volume that the
+/// config file has been loaded from, whose handle is `boot_volume`.
+pub fn volume_handle(
+    system_table: &SystemTable<Boot>,
+    parsed_uri: &Uri,
+    root: &mut Directory,
+    boot_volume: Handle,
+) -> Result<Handle, IonError> {
+    match parsed_uri.resource() {
+        "devpath" => {
+            // The device path is always present in `devpath://` URIs.
+            let device_path = parsed_uri.device_path().unwrap();
+
+            devpath::find_handle::<SimpleFileSystem>(system_table, device_path)
+                .ok_or_else(|| IonError::VolumeNotFound(String::from(device_path)))
+        }
 
-    // ERROR: missing the resource
-    if uri.len() == 0 {
-        return Err(UriParseError::MissingResource);
-    }
+        "search" => {
+            // The label is always present in `search://` URIs.
+            let marker = parsed_uri.label().unwrap();
 
-    let resource = uri[0];
-    let root = uri[1];
+            if devpath::volume_matches(root, marker) {
+                return Ok(boot_volume);
+            }
 
-    // ERROR: missing the double backslashes after the resource.
-    if root.len() < 3 || &root[0..2] != "//" {
-        return Err(UriParseError::InvalidSyntax);
-    }
+            let (handle, volume) = devpath::search_handle(system_table, marker)
+                .ok_or_else(|| IonError::MarkerNotFound(String::from(marker)))?;
 
-    let root = root[2..].split("/").collect::<Vec<_>>();
+            volume.close();
+            Ok(handle)
+        }
 
-    // ERROR: Missing the root partition number (or a backslash indicating
-    // that we have to use the boot partition) and the root directory itself and
-    // the path.
-    if root.len() < 3 {
-        return Err(UriParseError::InvalidSyntax);
+        // The other resources either refer to the boot volume or are not supported.
+        _ => handle_uri_redirect(system_table, parsed_uri, root).map(|_| boot_volume),
     }
+}
 
-    let partition = match root[0] {
-        "" => None,
-        n => Some(
-            n.parse::<usize>()
-                .or(Err(UriParseError::InvalidPartition))?,
-        ),
-    };
-
-    // 2. Convert the provided path to a UEFI path. Since UEFI paths use
-    // windows type of forward slashes as the path seperator and the URI
-    // uses backslashes instead.
-    let path = root[1..].join("\\");
+/// Helper function to report the value of the provided config key if it could not be
+/// parsed. Invalid values are ignored, so that a typo does not prevent booting.
+fn parse_value<T>(key: &'static str, value: &'static str, parsed: Option<T>) -> Option<T> {
+    if parsed.is_none() {
+        log::warn!("config: {}", IonError::InvalidValue(key, value));
+    }
 
-    Ok(Uri {
-        resource: String::from(resource),
-        partition,
-        path,
-    })
+    parsed
 }
 
 /// This function is responsible for loading and parsing the config file for Ion.
@@ -199,12 +706,13 @@ pub fn load(system_table: &SystemTable<Boot>, root: &mut Directory) -> IonConfig
 
     // Go through each possible config path and initialize the configuration_file
     // variable if file exists.
-    for filename in CONFIG_PATHS {
+    for (filename, directory) in CONFIG_PATHS {
         let file_completion = root.open(filename, FileMode::Read, FileAttribute::empty());
 
         // Check if the file read operation completed with success.
         if let Ok(handle) = file_completion {
             configuration_file = Some(handle.expect("file read exited with warnings"));
+            *CONFIG_DIR.lock() = directory;
             break; // Avoid to re-assign the file handle again.
         }
     }
@@ -212,13 +720,12 @@ pub fn load(system_table: &SystemTable<Boot>, root: &mut Directory) -> IonConfig
     let configuration_file = if let Some(config) = configuration_file {
         config
     } else {
-        println!("Configuration file not found.\n");
-
-        println!("For information on the format of Ion config entries, consult CONFIG.md in");
-        println!("the root of the Ion source repository.\n");
+        let strings = i18n::strings();
 
-        println!("Press a key to enter an editor session and manually define a config entry...");
-        let _ = get_char(system_table);
+        println!("{}\n", strings.config_not_found);
+        println!("{}\n", strings.config_consult);
+        println!("{}", strings.config_editor);
+        let _ = input::wait(system_table);
 
         // TODO: Print a friendly message that the configuration file does not exist and add a built-in
         // terminal way to create the config file on the fly.
@@ -247,87 +754,239 @@ pub fn load(system_table: &SystemTable<Boot>, root: &mut Directory) -> IonConfig
     let mut boot_config = BootConfigutation {
         // We set the default time out to 5 seconds.
         timeout: 5,
+        countdown_keys: CountdownKeys::Any,
+        // The firmware reports keys using the US layout so use that by default.
+        keymap: Keymap::Qwerty,
+        serial: None,
+        beep: false,
+        // By default we use the language specified by the firmware.
+        language: None,
+        log_level: LevelFilter::Info,
+        font: None,
+        font_scale: None,
+        resolution: None,
+        display: None,
+        splash: None,
+        preserve_logo: false,
+        debug_wait: false,
+        drivers: alloc::vec::Vec::new(),
+        // By default the hibernated entry is booted, so that it resumes.
+        resume_action: ResumeAction::Select,
+        idle_action: None,
+        idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        auto_detect: false,
+        prefix: None,
+        last_entry: None,
     };
 
     let mut entries = alloc::vec::Vec::new();
 
     // Create the menu tree.
-    for line in configuration_str.split("\n") {
-        let mut line_chars = line.chars();
-
-        if let Some(':') = line_chars.nth(0) {
-            // In this case we got a new entry.
-            let config = ConfigurationEntry {
-                // We use stivale 2 as the default boot protocol.
-                protocol: BootProtocol::Stivale2,
-                // We have already skipped the colon using line_chars.nth(0) above so the rest
-                // of the line will be the kernel's name.
-                name: line_chars.as_str(),
-                // By default we will set the kernel command line to an empty string.
-                command_line: "",
-                // By default we will set the kernel path to an empty string.
-                path: "",
-            };
+    for line in config::lines(configuration_str) {
+        match line {
+            Line::Entry(name) => {
+                // In this case we got a new entry.
+                entries.push(ConfigurationEntry::new(name));
+            }
 
-            entries.push(config);
-        } else if let Some(current_entry) = entries.last_mut() {
             // Else in this case we are defining the local keys.
-            if let Some(key_idx) = line.find("=") {
-                let mut local_chars = line.chars();
-                local_chars.nth(key_idx); // Skip the key
+            Line::Option(key, value) if !entries.is_empty() => {
+                let current_entry = entries.last_mut().unwrap();
+
+                match key {
+                    "PROTOCOL" | "KERNEL_PROTOCOL" | "PROTO" => {
+                        if let Some(protocol) =
+                            parse_value("PROTOCOL", value, BootProtocol::parse(value))
+                        {
+                            current_entry.protocol = protocol;
+                        }
+                    }
+
+                    "CMDLINE" | "KERNEL_CMDLINE" => current_entry.command_line = value,
+                    "COMMENT" => current_entry.comment = value,
+                    "ICON" => current_entry.icon = Some(value),
+                    "KASLR" => current_entry.kaslr = config::parse_bool(value),
+                    "SMEP" => current_entry.smep = config::parse_bool(value),
+                    "SMAP" => current_entry.smap = config::parse_bool(value),
+                    "UMIP" => current_entry.umip = config::parse_bool(value),
+                    "DTB_PATH" => current_entry.dtb_path = Some(value),
+                    "INITRD_PATH" => current_entry.initrd_path = Some(value),
+                    "RUNTIME_REMAP" => current_entry.runtime_remap = config::parse_bool(value),
+                    "PCI_TAG" => current_entry.pci_tag = config::parse_bool(value),
+                    "RESUME_SWAP" => current_entry.resume_swap = Some(value),
+                    "HIBERFIL" => current_entry.hiberfil = Some(value),
+                    "PREFIX" => current_entry.prefix = Some(value),
+
+                    "REQUIRES" => {
+                        if let Some(features) = parse_value(key, value, Features::parse(value)) {
+                            current_entry.requires = features;
+                        }
+                    }
+
+                    "STACK_SIZE" => {
+                        if let Some(stack_size) = parse_value(key, value, value.parse().ok()) {
+                            current_entry.stack_size = stack_size;
+                        }
+                    }
+
+                    // TODO: Do not just expect the user to give the correct kernel path and
+                    // verify and parse the URI specified by the user. We will leave it as it
+                    // is right now.
+                    "PATH" | "KERNEL_PATH" => current_entry.path = value,
+
+                    _ => (),
+                }
+            }
+
+            // In this case we got a global key.
+            Line::Option(key, value) => match key {
+                "TIMEOUT" => boot_config.timeout = config::parse_timeout(value),
+
+                "COUNTDOWN_KEYS" => {
+                    if let Some(keys) = parse_value(key, value, CountdownKeys::parse(value)) {
+                        boot_config.countdown_keys = keys;
+                    }
+                }
+
+                "KEYMAP" => {
+                    if let Some(keymap) = parse_value(key, value, value.parse().ok()) {
+                        boot_config.keymap = keymap;
+                    }
+                }
+
+                "SERIAL" => {
+                    // The value takes the form of `yes[,baud]`.
+                    let mut parts = value.split(',');
+
+                    if parts.next() == Some("yes") {
+                        let baud_rate = parts
+                            .next()
+                            .and_then(|baud| parse_value(key, value, baud.parse::<u32>().ok()))
+                            .unwrap_or(serial::DEFAULT_BAUD_RATE);
 
-                let value = local_chars.as_str(); // Left with the value
+                        boot_config.serial = Some(baud_rate);
+                    } else {
+                        boot_config.serial = None;
+                    }
+                }
 
-                if line.starts_with("PROTOCOL=")
-                    || line.starts_with("KERNEL_PROTOCOL=")
-                    || line.starts_with("PROTO=")
-                {
-                    let protocol = match value {
-                        "stivale2" => BootProtocol::Stivale2,
-                        "stivale1" => BootProtocol::Stivale,
-                        "stivale" => BootProtocol::Stivale,
+                "BEEP" => boot_config.beep = config::parse_bool(value),
 
-                        "multiboot" => BootProtocol::Multiboot,
-                        "multiboot1" => BootProtocol::Multiboot,
-                        "multiboot2" => BootProtocol::Multiboot2,
+                "LANGUAGE" => {
+                    if let Some(language) = parse_value(key, value, value.parse().ok()) {
+                        boot_config.language = Some(language);
+                    }
+                }
 
-                        "linux" => BootProtocol::Linux,
+                "FONT" => boot_config.font = Some(value),
 
-                        _ => panic!("Invalid boot protocol"),
+                "FONT_SCALE" => {
+                    boot_config.font_scale = match value {
+                        "auto" => None,
+                        scale => parse_value(key, value, scale.parse::<usize>().ok()),
                     };
+                }
 
-                    current_entry.protocol = protocol;
-                } else if line.starts_with("CMDLINE=") || line.starts_with("KERNEL_CMDLINE=") {
-                    current_entry.command_line = value;
-                } else if line.starts_with("PATH=") || line.starts_with("KERNEL_PATH=") {
-                    current_entry.path = value;
+                "RESOLUTION" => {
+                    boot_config.resolution =
+                        parse_value(key, value, config::parse_resolution(value));
+                }
+
+                "DISPLAY" => boot_config.display = parse_value(key, value, Display::parse(value)),
+
+                "SPLASH" => boot_config.splash = config::parse_splash(value),
+                "PRESERVE_LOGO" => boot_config.preserve_logo = config::parse_bool(value),
+                "DEBUG_WAIT" => boot_config.debug_wait = config::parse_bool(value),
+                "DRIVER" => boot_config.drivers.push(value),
+                "AUTO_DETECT" => boot_config.auto_detect = config::parse_bool(value),
+                "PREFIX" => boot_config.prefix = Some(value),
 
-                    // TODO: Do not just expect the user to give the correct kernel path and verify
-                    // and parse the URI specified by the user. We will leave it as it is right now.
+                "MENU_IDLE_ACTION" => {
+                    if let Some(action) = parse_value(key, value, IdleAction::parse(value)) {
+                        boot_config.idle_action = action;
+                    }
                 }
-            }
-        } else {
-            // In this case we got a global key.
-            if let Some(key_idx) = line.find("=") {
-                let mut local_chars = line.chars();
-                local_chars.nth(key_idx); // Skip the key
 
-                let value = local_chars.as_str(); // Left with the value
+                "MENU_IDLE_TIMEOUT" => {
+                    if let Some(timeout) = parse_value(key, value, value.parse().ok()) {
+                        boot_config.idle_timeout = timeout;
+                    }
+                }
 
-                if line.starts_with("TIMEOUT") {
-                    let timeout =
-                        value
-                            .parse::<usize>()
-                            .unwrap_or_else(|_| if value.eq("no") { 0 } else { 5 });
+                "RESUME_ACTION" => {
+                    if let Some(action) = parse_value(key, value, ResumeAction::parse(value)) {
+                        boot_config.resume_action = action;
+                    }
+                }
 
-                    boot_config.timeout = timeout;
+                "LOG_LEVEL" => {
+                    if let Some(level) = parse_value(key, value, value.parse().ok()) {
+                        boot_config.log_level = level;
+                    }
                 }
-            }
+
+                "VERBOSE" => {
+                    // Verbose boots display everything, quiet boots only display warnings
+                    // and errors.
+                    boot_config.log_level = if config::parse_bool(value) {
+                        LevelFilter::Trace
+                    } else {
+                        LevelFilter::Warn
+                    };
+                }
+
+                _ => (),
+            },
         }
     }
 
     cfg_file_handle.close();
 
+    // The preferences that have been changed in the menu take precedence over the config
+    // file. The buffer is never freed, as the name of the last entry has to outlive the
+    // config.
+    let preferences = read_preferences(system_table, alloc::vec![0; PREFERENCES_MAX_SIZE].leak());
+
+    if let Some(value) = preferences.resolution {
+        if let Some(resolution) = parse_value("RESOLUTION", value, config::parse_resolution(value))
+        {
+            boot_config.resolution = Some(resolution);
+        }
+    }
+
+    if let Some(value) = preferences.log_level {
+        if let Some(level) = parse_value("LOG_LEVEL", value, value.parse().ok()) {
+            boot_config.log_level = level;
+        }
+    }
+
+    if let Some(value) = preferences.keymap {
+        if let Some(keymap) = parse_value("KEYMAP", value, value.parse().ok()) {
+            boot_config.keymap = keymap;
+        }
+    }
+
+    boot_config.last_entry = preferences.last_entry;
+
+    // The keys of an entry can be specified in any order, so the prefix is only applied
+    // once the whole config has been parsed.
+    for entry in entries.iter_mut() {
+        if let Some(prefix) = entry.prefix.or(boot_config.prefix) {
+            entry.apply_prefix(prefix);
+        }
+    }
+
+    // Distributions that use systemd-boot install an entry for each of their kernels, which
+    // are shown after the entries of the config file.
+    for entry in bls::load(system_table, root) {
+        if !entries
+            .iter()
+            .any(|existing| existing.path() == entry.path())
+        {
+            entries.push(entry);
+        }
+    }
+
     IonConfig {
         boot: boot_config,
         entries,
@@ -0,0 +1,278 @@
+use core::fmt;
+use core::fmt::Write;
+
+use spin::mutex::SpinMutex;
+
+use crate::logger::{self, Color};
+use crate::profile;
+use crate::time;
+
+/// An output target of the console, such as the screen, the serial console or the
+/// in-memory boot log. All of the console output and the log records are written to
+/// every registered sink.
+pub trait ConsoleSink: Sync {
+    /// Writes the provided console output (e.g. the boot menu) to the sink.
+    fn write_str(&self, s: &str);
+
+    /// Writes the provided log record to the sink. By default log records are treated
+    /// like any other console output.
+    fn write_record(&self, record: &str) {
+        self.write_str(record)
+    }
+
+    /// Returns the maximum level of the log records that are written to the sink. By
+    /// default all of the log records are written.
+    fn level(&self) -> log::LevelFilter {
+        log::LevelFilter::Trace
+    }
+
+    /// Clears the sink and moves the cursor to the top left corner.
+    fn clear(&self) {}
+
+    /// Moves the cursor to the provided (zero based) character cell.
+    fn set_cursor_pos(&self, _column: usize, _row: usize) {}
+
+    /// Clears the provided (zero based) text row and moves the cursor to the start of it.
+    fn clear_line(&self, _row: usize) {}
+
+    /// Replaces the contents of the provided (zero based) text row of the status region
+    /// with the provided text.
+    fn write_status(&self, row: usize, text: &str) {
+        self.clear_line(row);
+        self.write_str(text);
+    }
+
+    /// Sets the foreground color of the subsequent output.
+    fn set_fg(&self, _color: Color) {}
+
+    /// Makes sure that all of the output written so far is visible.
+    fn flush(&self) {}
+}
+
+/// The maximum amount of sinks that can be registered.
+const MAX_SINKS: usize = 8;
+
+/// The registered console sinks.
+static SINKS: SpinMutex<[Option<&'static dyn ConsoleSink>; MAX_SINKS]> =
+    SpinMutex::new([None; MAX_SINKS]);
+
+/// Adapter that formats the output for a single sink.
+struct SinkWriter {
+    sink: &'static dyn ConsoleSink,
+    record: bool,
+}
+
+impl fmt::Write for SinkWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.record {
+            self.sink.write_record(s);
+        } else {
+            self.sink.write_str(s);
+        }
+
+        Ok(())
+    }
+}
+
+/// The size of the buffer that keeps the log records written before the first sink has
+/// been registered.
+const EARLY_LOG_SIZE: usize = 4096;
+
+/// Keeps the log records that are written before any sink that displays them has been
+/// registered (e.g. while the display mode is set), so that they can be replayed into the
+/// sinks once they come up. Once the buffer is full the oldest lines are discarded.
+struct EarlyLog {
+    buffer: [u8; EARLY_LOG_SIZE],
+    len: usize,
+    /// Whether the first sink has been registered, after which nothing is recorded.
+    closed: bool,
+}
+
+impl EarlyLog {
+    fn push(&mut self, record: &str) {
+        let record = record.as_bytes();
+
+        if self.closed || record.len() > EARLY_LOG_SIZE {
+            return;
+        }
+
+        // Discard whole lines, so that the contents remain valid UTF-8.
+        while self.len + record.len() > EARLY_LOG_SIZE {
+            let first_line = self.buffer[..self.len]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.len, |newline| newline + 1);
+
+            self.buffer.copy_within(first_line..self.len, 0);
+            self.len -= first_line;
+        }
+
+        self.buffer[self.len..self.len + record.len()].copy_from_slice(record);
+        self.len += record.len();
+    }
+}
+
+impl ConsoleSink for SpinMutex<EarlyLog> {
+    fn write_str(&self, _s: &str) {}
+
+    fn write_record(&self, record: &str) {
+        self.lock().push(record);
+    }
+}
+
+static EARLY_LOG: SpinMutex<EarlyLog> = SpinMutex::new(EarlyLog {
+    buffer: [0; EARLY_LOG_SIZE],
+    len: 0,
+    closed: false,
+});
+
+/// Adds the provided sink to the registered sinks.
+fn add(sink: &'static dyn ConsoleSink) {
+    let mut sinks = SINKS.lock();
+
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("console: too many sinks registered");
+
+    *slot = Some(sink);
+}
+
+/// Registers the provided sink, so that all of the subsequent console output and log
+/// records are written to it. The log records that have been written before the first
+/// sink was registered are replayed into the sink first.
+pub fn register(sink: &'static dyn ConsoleSink) {
+    {
+        let mut early_log = EARLY_LOG.lock();
+        early_log.closed = true;
+
+        // SAFETY: Only whole strings and lines are stored in the buffer.
+        let records = unsafe { core::str::from_utf8_unchecked(&early_log.buffer[..early_log.len]) };
+
+        if !records.is_empty() {
+            sink.write_record(records);
+        }
+    }
+
+    add(sink);
+}
+
+/// Calls the provided function for each of the registered sinks. The sinks are copied out
+/// of the lock first, so that a sink that panics does not leave the registry locked.
+fn for_each_sink<F>(mut f: F)
+where
+    F: FnMut(&'static dyn ConsoleSink),
+{
+    let sinks = *SINKS.lock();
+
+    for sink in sinks.iter().flatten() {
+        f(*sink);
+    }
+}
+
+/// The global logger instance used for the `log` crate. It formats the log records and
+/// writes them to all of the registered sinks.
+struct ConsoleLogger;
+
+impl log::Log for ConsoleLogger {
+    #[inline]
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    #[inline]
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Prefix the records with the time since Ion has been entered and the phase of the
+        // boot process, so that slow firmware calls stand out.
+        let timestamp = time::timestamp_ms();
+        let phase = profile::current();
+
+        for_each_sink(|sink| {
+            if record.level() > sink.level() {
+                return;
+            }
+
+            let mut writer = SinkWriter { sink, record: true };
+
+            let _ = match phase {
+                Some(phase) => write!(writer, "[{:>6}ms {}] ", timestamp, phase.name()),
+                None => write!(writer, "[{:>6}ms] ", timestamp),
+            };
+
+            let _ = writeln!(writer, "{}:    {}", record.level(), record.args());
+        });
+    }
+
+    #[inline]
+    fn flush(&self) {}
+}
+
+static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
+
+/// This function is responsible for initializing the `log` crate and registering the
+/// in-memory boot log, so that all of the log records are kept from the very start.
+pub fn init() {
+    log::set_logger(&CONSOLE_LOGGER).expect("Logger already set");
+    log::set_max_level(log::LevelFilter::Trace);
+
+    add(&logger::BOOT_LOG);
+    add(&EARLY_LOG);
+}
+
+/// Clears all of the sinks.
+pub fn clear() {
+    for_each_sink(|sink| sink.clear());
+}
+
+/// Moves the cursor of all of the sinks to the provided (zero based) character cell.
+pub fn set_cursor_pos(column: usize, row: usize) {
+    for_each_sink(|sink| sink.set_cursor_pos(column, row));
+}
+
+/// Clears the provided (zero based) text row of all of the sinks and moves their cursor to
+/// the start of it.
+pub fn clear_line(row: usize) {
+    for_each_sink(|sink| sink.clear_line(row));
+}
+
+/// Replaces the contents of the provided (zero based) text row of the status region of all
+/// of the sinks with the provided text.
+pub fn write_status(row: usize, text: &str) {
+    for_each_sink(|sink| sink.write_status(row, text));
+}
+
+/// Sets the foreground color of the subsequent output of all of the sinks.
+pub fn set_fg(color: Color) {
+    for_each_sink(|sink| sink.set_fg(color));
+}
+
+/// Makes sure that all of the output written so far is visible on all of the sinks.
+pub fn flush() {
+    for_each_sink(|sink| sink.flush());
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::prelude::print!("\n"));
+    ($($arg:tt)*) => ($crate::prelude::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    for_each_sink(|sink| {
+        let _ = SinkWriter {
+            sink,
+            record: false,
+        }
+        .write_fmt(args);
+    });
+}
@@ -0,0 +1,105 @@
+// The processor features reported by CPUID, which are shared between the x86_64 and the
+// IA-32 builds of Ion.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use raw_cpuid::CpuId;
+
+use ion_core::cpu::{Feature, Features};
+
+/// Returns the features of the processor that entries can require with `REQUIRES=`.
+pub fn features() -> Features {
+    let cpuid = CpuId::new();
+    let mut features = Features::empty();
+
+    if let Some(info) = cpuid.get_feature_info() {
+        features.set(Feature::X2Apic, info.has_x2apic());
+        features.set(Feature::Sse3, info.has_sse3());
+        features.set(Feature::Ssse3, info.has_ssse3());
+        features.set(Feature::Sse41, info.has_sse41());
+        features.set(Feature::Sse42, info.has_sse42());
+        features.set(Feature::Popcnt, info.has_popcnt());
+        features.set(Feature::Avx, info.has_avx());
+        features.set(Feature::RdRand, info.has_rdrand());
+        features.set(Feature::Xsave, info.has_xsave());
+    }
+
+    if let Some(info) = cpuid.get_extended_feature_info() {
+        features.set(Feature::La57, info.has_la57());
+        features.set(Feature::Avx2, info.has_avx2());
+        features.set(Feature::Avx512F, info.has_avx512f());
+        features.set(Feature::Bmi1, info.has_bmi1());
+        features.set(Feature::Bmi2, info.has_bmi2());
+        features.set(Feature::Tsx, info.has_rtm());
+        features.set(Feature::RdSeed, info.has_rdseed());
+        features.set(Feature::Smep, info.has_smep());
+        features.set(Feature::Smap, info.has_smap());
+        features.set(Feature::Umip, info.has_umip());
+        features.set(Feature::FsGsBase, info.has_fsgsbase());
+    }
+
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        features.set(Feature::LongMode, info.has_64bit_mode());
+        features.set(Feature::NoExecute, info.has_execute_disable());
+        features.set(Feature::Pages1G, info.has_1gib_pages());
+    }
+
+    features
+}
+
+fn yes_no(supported: bool) -> &'static str {
+    if supported {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Returns a summary of the identification and the features of the processor, which helps
+/// to find out why a kernel does not boot on the machine.
+pub fn summary() -> Vec<String> {
+    let cpuid = CpuId::new();
+    let features = features();
+    let mut lines = Vec::new();
+
+    if let Some(vendor) = cpuid.get_vendor_info() {
+        lines.push(format!("Vendor:        {}", vendor.as_str()));
+    }
+
+    if let Some(brand) = cpuid.get_processor_brand_string() {
+        lines.push(format!("Brand:         {}", brand.as_str().trim()));
+    }
+
+    if let Some(info) = cpuid.get_feature_info() {
+        lines.push(format!(
+            "Family:        {:#x}, model {:#x}, stepping {}",
+            info.family_id(),
+            info.model_id(),
+            info.stepping_id()
+        ));
+    }
+
+    lines.push(String::new());
+
+    let highlights = [
+        ("Long mode:", Feature::LongMode),
+        ("1 GiB pages:", Feature::Pages1G),
+        ("x2APIC:", Feature::X2Apic),
+        ("LA57:", Feature::La57),
+        ("NX:", Feature::NoExecute),
+    ];
+
+    for (name, feature) in highlights.iter() {
+        lines.push(format!(
+            "{:<15}{}",
+            name,
+            yes_no(features.contains(*feature))
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push(format!("Features:      {}", features));
+    lines
+}
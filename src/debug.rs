@@ -0,0 +1,339 @@
+use core::mem;
+use core::ptr;
+
+use alloc::vec;
+
+use ion_core::command::{self, Command, CommandParseError};
+use uefi::prelude::*;
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::table::boot::MemoryDescriptor;
+
+use crate::arch;
+use crate::devpath;
+use crate::input;
+use crate::logger;
+use crate::menu;
+use crate::prelude::*;
+
+/// The offset of the `e_lfanew` field in the DOS header, which contains the offset of the
+/// PE header.
+const PE_HEADER_OFFSET: usize = 0x3c;
+
+const PE_SIGNATURE: [u8; 4] = *b"PE\0\0";
+
+/// The size of the PE signature and the COFF file header.
+const COFF_HEADER_SIZE: usize = 24;
+const SECTION_HEADER_SIZE: usize = 40;
+
+fn read_u16(image_base: usize, offset: usize) -> u16 {
+    unsafe { ptr::read_unaligned((image_base + offset) as *const u16) }
+}
+
+fn read_u32(image_base: usize, offset: usize) -> u32 {
+    unsafe { ptr::read_unaligned((image_base + offset) as *const u32) }
+}
+
+/// Prints the address of each section of Ion, which are read from the PE headers that the
+/// firmware has loaded at the image base.
+fn print_sections(image_base: usize, image_size: usize) {
+    let pe_header = read_u32(image_base, PE_HEADER_OFFSET) as usize;
+
+    let signature = unsafe { ptr::read_unaligned((image_base + pe_header) as *const [u8; 4]) };
+
+    if pe_header + COFF_HEADER_SIZE > image_size || signature != PE_SIGNATURE {
+        println!("debug: the PE headers are not mapped, unable to list the sections");
+        return;
+    }
+
+    let section_count = read_u16(image_base, pe_header + 6) as usize;
+    let optional_header_size = read_u16(image_base, pe_header + 20) as usize;
+    let sections = pe_header + COFF_HEADER_SIZE + optional_header_size;
+
+    for i in 0..section_count {
+        let header = sections + i * SECTION_HEADER_SIZE;
+
+        if header + SECTION_HEADER_SIZE > image_size {
+            break;
+        }
+
+        let name = unsafe { ptr::read_unaligned((image_base + header) as *const [u8; 8]) };
+        let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        let name = core::str::from_utf8(&name[..name_len]).unwrap_or("?");
+
+        let virtual_size = read_u32(image_base, header + 8) as usize;
+        let virtual_address = read_u32(image_base, header + 12) as usize;
+
+        println!(
+            "debug:   {:<8} {:#018x} ({:#x} bytes)",
+            name,
+            image_base + virtual_address,
+            virtual_size
+        );
+    }
+}
+
+/// Prints the address at which the firmware has loaded Ion and waits for a key press on
+/// the keyboard or the serial console. This gives the developer time to attach a debugger
+/// (e.g. QEMU's gdbstub) and load the symbols at the right offset.
+pub fn wait(system_table: &SystemTable<Boot>, image_base: usize, image_size: usize) {
+    println!(
+        "debug: Ion is loaded at {:#018x} ({:#x} bytes)",
+        image_base, image_size
+    );
+
+    print_sections(image_base, image_size);
+
+    println!("debug: attach the debugger and press any key to continue");
+    input::wait(system_table);
+}
+
+/// The maximum length of a command line of the debug console.
+const MAX_LINE_LEN: usize = 64;
+
+/// The amount of bytes shown on each line of a hex dump.
+const DUMP_BYTES_PER_LINE: u64 = 16;
+
+const HELP: &[(&str, &str)] = &[
+    ("memmap", "Print the firmware memory map"),
+    (
+        "volumes",
+        "Print the device paths of the volumes, for devpath:// URIs",
+    ),
+    ("regs", "Print the control registers"),
+    (
+        "cpu",
+        "Print the identification and the features of the processor",
+    ),
+    ("boot <n>", "Boot the entry with the provided index"),
+    (
+        "dump <addr> <len>",
+        "Print a hex dump of the provided memory range",
+    ),
+];
+
+/// Reads a command line using the provided function to wait for key presses. Returns
+/// [`None`] if ESC was pressed.
+fn read_line<'a>(
+    buffer: &'a mut [u8; MAX_LINE_LEN],
+    mut next_key: impl FnMut() -> Key,
+) -> Option<&'a str> {
+    let mut len = 0;
+
+    loop {
+        let (_, row) = logger::cursor_pos();
+        logger::clear_line(row);
+
+        // SAFETY: Only whole UTF-8 encoded characters are stored in the buffer.
+        print!("> {}", unsafe {
+            core::str::from_utf8_unchecked(&buffer[..len])
+        });
+        logger::flush();
+
+        match next_key() {
+            Key::Special(ScanCode::ESCAPE) => return None,
+            Key::Special(_) => (),
+
+            Key::Printable(c) => match char::from(c) {
+                '\r' => {
+                    println!();
+
+                    // SAFETY: Only whole UTF-8 encoded characters are stored in the buffer.
+                    return Some(unsafe { core::str::from_utf8_unchecked(&buffer[..len]) });
+                }
+
+                // Remove the last character, which can take up more than one byte.
+                '\u{8}' => {
+                    while len > 0 {
+                        len -= 1;
+
+                        if buffer[len] & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+
+                // Ctrl+U clears the line. Terminals send it as a control character, while
+                // the firmware might report the letter along with the modifier state.
+                '\u{15}' => len = 0,
+                'u' | 'U' if input::modifiers().ctrl => len = 0,
+
+                // The Latin-1 characters can be typed using the keymaps of other layouts.
+                c if (c.is_ascii_graphic() || c == ' ' || ('\u{a1}'..='\u{ff}').contains(&c))
+                    && len + c.len_utf8() <= MAX_LINE_LEN =>
+                {
+                    len += c.encode_utf8(&mut buffer[len..]).len();
+                }
+
+                _ => (),
+            },
+        }
+    }
+}
+
+fn print_memory_map(system_table: &SystemTable<Boot>) {
+    let boot_services = system_table.boot_services();
+
+    // The allocation of the storage buffer itself might split a region, so make some
+    // room for a few extra descriptors.
+    let mmap_size = boot_services.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
+    let mut mmap_storage = vec![0u8; mmap_size];
+
+    let (_, descriptors) = match boot_services.memory_map(&mut mmap_storage) {
+        Ok(completion) => completion.unwrap(),
+        Err(_) => {
+            println!("debug: failed to retrieve the memory map");
+            return;
+        }
+    };
+
+    for descriptor in descriptors {
+        println!(
+            "{:#018x}-{:#018x} {}",
+            descriptor.phys_start,
+            descriptor.phys_start + descriptor.page_count * 0x1000,
+            menu::memory_type_name(descriptor.ty)
+        );
+    }
+}
+
+/// Prints a hex dump of the provided memory range. Accessing memory that is not mapped
+/// faults, so this is only meant to be used by developers.
+fn dump(address: u64, len: u64) {
+    let mut line = address;
+    let end = address.saturating_add(len);
+
+    while line < end {
+        print!("{:#018x}:", line);
+
+        let count = (end - line).min(DUMP_BYTES_PER_LINE);
+        let mut bytes = [0u8; DUMP_BYTES_PER_LINE as usize];
+
+        for (i, byte) in bytes.iter_mut().take(count as usize).enumerate() {
+            *byte = unsafe { ptr::read_volatile((line + i as u64) as *const u8) };
+            print!(" {:02x}", byte);
+        }
+
+        // Align the ASCII column of a partial last line.
+        for _ in count..DUMP_BYTES_PER_LINE {
+            print!("   ");
+        }
+
+        print!("  |");
+
+        for &byte in &bytes[..count as usize] {
+            let c = if byte.is_ascii_graphic() {
+                byte as char
+            } else {
+                '.'
+            };
+            print!("{}", c);
+        }
+
+        println!("|");
+        line += count;
+    }
+}
+
+/// Executes the provided command line. The boot services are used to retrieve the memory
+/// map, if they are still available. Returns the index of the boot entry that should be
+/// booted, if any.
+fn execute(line: &str, system_table: Option<&SystemTable<Boot>>) -> Option<usize> {
+    match command::parse_command(line) {
+        Ok(Command::Help) => {
+            for (usage, description) in HELP {
+                println!("{:<20}{}", usage, description);
+            }
+        }
+
+        Ok(Command::Memmap) => match system_table {
+            Some(system_table) => print_memory_map(system_table),
+            None => {
+                println!("debug: the memory map is not available after exiting the boot services")
+            }
+        },
+
+        Ok(Command::Volumes) => match system_table {
+            Some(system_table) => {
+                for (_, path) in devpath::volumes(system_table) {
+                    println!("{}", path);
+                }
+            }
+            None => {
+                println!("debug: the volumes are not available after exiting the boot services")
+            }
+        },
+
+        Ok(Command::Regs) => arch::print_registers(),
+
+        Ok(Command::Cpu) => {
+            for line in arch::cpu_summary() {
+                println!("{}", line);
+            }
+        }
+
+        Ok(Command::Boot(index)) => {
+            if system_table.is_some() {
+                return Some(index);
+            }
+
+            println!("debug: no entry can be booted anymore");
+        }
+
+        Ok(Command::Dump(address, len)) => dump(address, len),
+
+        Err(CommandParseError::Empty) => (),
+        Err(CommandParseError::UnknownCommand) => {
+            println!("debug: unknown command, type 'help' for a list of commands")
+        }
+        Err(CommandParseError::InvalidArgument) => println!("debug: invalid argument"),
+    }
+
+    logger::flush();
+    None
+}
+
+/// Shows the debug console, which accepts commands from the keyboard and the serial
+/// console. Returns the index of the entry that should be booted, or [`None`] if ESC was
+/// pressed.
+pub fn console(system_table: &SystemTable<Boot>, entry_count: usize) -> Option<usize> {
+    logger::clear();
+
+    println!("Ion {} debug console", env!("CARGO_PKG_VERSION"));
+    println!("Type 'help' for a list of commands, press ESC to return...\n");
+
+    let mut buffer = [0; MAX_LINE_LEN];
+
+    loop {
+        let line = read_line(&mut buffer, || input::wait(system_table))?;
+
+        match execute(line, Some(system_table)) {
+            Some(index) if index < entry_count => return Some(index),
+            Some(_) => println!("debug: there are only {} entries", entry_count),
+            None => (),
+        }
+    }
+}
+
+/// Accepts commands over the serial console forever. This is used after a panic, so that
+/// failures of headless machines can be debugged remotely. The boot services might have
+/// been exited, so they are not used to wait for input. The keyboard is only read if they
+/// are still active.
+pub fn serial_console() -> ! {
+    println!("\nIon debug console, type 'help' for a list of commands");
+
+    let mut buffer = [0; MAX_LINE_LEN];
+
+    loop {
+        let next_key = || loop {
+            if let Some(key) = input::poll() {
+                break key;
+            }
+
+            core::hint::spin_loop();
+        };
+
+        if let Some(line) = read_line(&mut buffer, next_key) {
+            execute(line, None);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use core::ffi::c_void;
+use core::ptr;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileMode, FileSystemVolumeLabel};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::{unsafe_guid, Handle, Protocol};
+
+use ion_core::devpath;
+
+use crate::efi;
+
+/// The `EFI_LOCATE_SEARCH_TYPE` used to retrieve the handles that support a protocol.
+const BY_PROTOCOL: u32 = 2;
+
+/// The type and the subtype of the node that terminates a device path.
+const END_ENTIRE: [u8; 2] = [0x7f, 0xff];
+
+/// The UEFI device path protocol. The protocol interface is the first node of the device
+/// path, which is followed by the other nodes.
+#[repr(C)]
+#[unsafe_guid("09576e91-6d3f-11d2-8e39-00a0c969723b")]
+#[derive(Protocol)]
+struct DevicePath {
+    ty: u8,
+    subtype: u8,
+    length: [u8; 2],
+}
+
+/// Returns the device path installed on the provided handle, including the end node.
+pub fn of_handle(system_table: &SystemTable<Boot>, handle: Handle) -> Option<&'static [u8]> {
+    let device_path = system_table
+        .boot_services()
+        .handle_protocol::<DevicePath>(handle)
+        .ok()?
+        .unwrap();
+
+    // SAFETY: The protocol interface is the first node of the device path.
+    let device_path = unsafe { from_raw(device_path.get() as *const u8) };
+
+    if device_path.is_none() {
+        log::warn!("devpath: malformed device path on handle {:?}", handle);
+    }
+
+    device_path
+}
+
+/// Returns the device path starting at the provided pointer, including the end node.
+/// Returns [`None`] if the device path is malformed.
+///
+/// ## Safety
+/// The pointer has to point to a device path that is terminated by an end node and lives
+/// as long as the boot services.
+pub unsafe fn from_raw(start: *const u8) -> Option<&'static [u8]> {
+    let mut len = 0;
+
+    // The size of the device path is only known once the end node has been found.
+    loop {
+        // SAFETY: The firmware guarantees that the device path is terminated by an end
+        // node, so every node header up to it is readable.
+        let header = core::slice::from_raw_parts(start.add(len), 4);
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+        if length < header.len() {
+            return None;
+        }
+
+        len += length;
+
+        if header[..2] == END_ENTIRE {
+            break;
+        }
+    }
+
+    // SAFETY: All of the nodes have been checked above.
+    Some(core::slice::from_raw_parts(start, len))
+}
+
+/// Returns the handles of all devices that support the provided protocol.
+pub fn handles_by_protocol<P: Protocol>(system_table: &SystemTable<Boot>) -> Vec<Handle> {
+    let boot_services = system_table.boot_services();
+    let raw = efi::raw_boot_services(boot_services);
+
+    let mut handle_count = 0;
+    let mut handles = ptr::null_mut();
+
+    let status = unsafe {
+        (raw.locate_handle_buffer)(
+            BY_PROTOCOL,
+            &P::GUID as *const _ as *const c_void,
+            ptr::null(),
+            &mut handle_count,
+            &mut handles,
+        )
+    };
+
+    if status.is_error() {
+        return Vec::new();
+    }
+
+    // SAFETY: The firmware has allocated a buffer of `handle_count` handles.
+    let result = unsafe { core::slice::from_raw_parts(handles, handle_count) }.to_vec();
+
+    boot_services
+        .free_pool(handles as *mut u8)
+        .expect_success("devpath: failed to free the handle buffer");
+
+    result
+}
+
+/// Returns the GPT partition with the provided number on the same disk as the partition
+/// with the provided device path.
+pub fn sibling_partition(
+    system_table: &SystemTable<Boot>,
+    partition: &[u8],
+    number: u32,
+) -> Option<devpath::GptPartition> {
+    let disk = &partition[..devpath::gpt_partition(partition)?.offset];
+
+    handles_by_protocol::<DevicePath>(system_table)
+        .into_iter()
+        .filter_map(|handle| of_handle(system_table, handle))
+        .filter_map(|path| Some((path, devpath::gpt_partition(path)?)))
+        .find(|(path, sibling)| sibling.number == number && path[..sibling.offset] == *disk)
+        .map(|(_, sibling)| sibling)
+}
+
+/// Returns the text representation of the device path of every volume with a file system
+/// that the firmware supports, along with the handle of the volume.
+pub fn volumes(system_table: &SystemTable<Boot>) -> Vec<(Handle, String)> {
+    handles_by_protocol::<SimpleFileSystem>(system_table)
+        .into_iter()
+        .filter_map(|handle| {
+            let path = of_handle(system_table, handle)?;
+            Some((handle, devpath::format(path)))
+        })
+        .collect()
+}
+
+/// Returns the handle of the device that supports the provided protocol and has the
+/// provided device path, given in its text representation.
+pub fn find_handle<P: Protocol>(
+    system_table: &SystemTable<Boot>,
+    device_path: &str,
+) -> Option<Handle> {
+    handles_by_protocol::<P>(system_table)
+        .into_iter()
+        .find(|&handle| {
+            of_handle(system_table, handle).map_or(false, |path| {
+                devpath::text_matches(&devpath::format(path), device_path)
+            })
+        })
+}
+
+/// Opens the root directory of the volume with the provided device path, given in its
+/// text representation.
+pub fn open_volume(system_table: &SystemTable<Boot>, device_path: &str) -> Option<Directory> {
+    let handle = find_handle::<SimpleFileSystem>(system_table, device_path)?;
+    open_filesystem(system_table, handle)
+}
+
+/// Opens the root directory of the volume with the provided handle.
+pub fn open_filesystem(system_table: &SystemTable<Boot>, handle: Handle) -> Option<Directory> {
+    let filesystem = system_table
+        .boot_services()
+        .handle_protocol::<SimpleFileSystem>(handle)
+        .ok()?
+        .unwrap();
+
+    // SAFETY: The protocol pointer is valid as long as the boot services are active.
+    let filesystem = unsafe { &mut *filesystem.get() };
+    filesystem.open_volume().ok().map(|root| root.unwrap())
+}
+
+/// Returns true if the provided volume has the provided label or contains a marker file
+/// with that name in its root directory.
+pub fn volume_matches(root: &mut Directory, marker: &str) -> bool {
+    let mut info_buf = [0; 0x100];
+
+    if let Ok(info) = root.get_info::<FileSystemVolumeLabel>(&mut info_buf) {
+        let label = info.unwrap().volume_label().to_u16_slice();
+        let label = char::decode_utf16(label.iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+
+        if label.eq_ignore_ascii_case(marker) {
+            return true;
+        }
+    }
+
+    match root.open(marker, FileMode::Read, FileAttribute::empty()) {
+        Ok(handle) => {
+            handle.unwrap().close();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the handle and the root directory of the first volume that matches the provided
+/// label or marker file (see [`volume_matches`]). Removable media are searched first, as
+/// the marker files of live systems are usually found on optical discs and USB sticks.
+pub fn search_handle(
+    system_table: &SystemTable<Boot>,
+    marker: &str,
+) -> Option<(Handle, Directory)> {
+    let mut handles = handles_by_protocol::<SimpleFileSystem>(system_table)
+        .into_iter()
+        .map(|handle| {
+            let removable =
+                of_handle(system_table, handle).map_or(false, devpath::is_removable_media);
+            (handle, removable)
+        })
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so the firmware's order is kept otherwise.
+    handles.sort_by_key(|&(_, removable)| !removable);
+
+    handles.into_iter().find_map(|(handle, _)| {
+        let mut root = open_filesystem(system_table, handle)?;
+
+        if volume_matches(&mut root, marker) {
+            Some((handle, root))
+        } else {
+            root.close();
+            None
+        }
+    })
+}
+
+/// Opens the root directory of the first volume that matches the provided label or marker
+/// file (see [`search_handle`]).
+pub fn search_volume(system_table: &SystemTable<Boot>, marker: &str) -> Option<Directory> {
+    search_handle(system_table, marker).map(|(_, root)| root)
+}
@@ -0,0 +1,150 @@
+// Asynchronous file reads. File system drivers that sit on top of `EFI_DISK_IO2_PROTOCOL`
+// implement `EFI_FILE_PROTOCOL.ReadEx` without blocking, so the kernel and the other files
+// of an entry are read at once. This keeps fast devices (e.g. NVMe drives) busy instead of
+// leaving them idle between the reads of the individual files.
+
+use core::ffi::c_void;
+use core::ptr;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::media::file::{File, FileHandle, RegularFile};
+use uefi::table::boot::{Event, EventType, Tpl};
+use uefi::{unsafe_guid, Protocol};
+
+use crate::efi;
+
+/// The revision of `EFI_FILE_PROTOCOL` that introduced the asynchronous functions.
+const FILE_PROTOCOL_REVISION2: u64 = 0x0002_0000;
+
+/// The UEFI disk I/O 2 protocol. Ion never calls it directly, its presence only tells that
+/// the file system drivers are able to read asynchronously.
+#[repr(C)]
+#[unsafe_guid("151c8eae-7f2c-472c-9e54-9828194f6a88")]
+#[derive(Protocol)]
+struct DiskIo2 {
+    revision: u64,
+}
+
+/// The `EFI_FILE_IO_TOKEN` that describes an asynchronous read.
+#[repr(C)]
+struct FileIoToken {
+    event: Event,
+    status: Status,
+    buffer_size: usize,
+    buffer: *mut c_void,
+}
+
+/// Mirrors the layout of `EFI_FILE_PROTOCOL`. Only the members that Ion calls through it
+/// are typed.
+#[repr(C)]
+struct RawFile {
+    revision: u64,
+    open: usize,
+    close: usize,
+    delete: usize,
+    read: usize,
+    write: usize,
+    get_position: usize,
+    set_position: usize,
+    get_info: usize,
+    set_info: usize,
+    flush: usize,
+    open_ex: usize,
+    read_ex: unsafe extern "efiapi" fn(this: *mut RawFile, token: *mut FileIoToken) -> Status,
+    write_ex: usize,
+    flush_ex: usize,
+}
+
+/// Returns the `EFI_FILE_PROTOCOL` behind the provided file.
+fn raw_file(file: &mut RegularFile) -> *mut RawFile {
+    // SAFETY: `FileHandle` is a transparent wrapper around the protocol pointer.
+    unsafe { *(file.handle() as *mut FileHandle as *const *mut RawFile) }
+}
+
+/// Starts reading the provided file into the provided buffer. Returns [`None`] if the file
+/// cannot be read asynchronously.
+fn start_read(
+    system_table: &SystemTable<Boot>,
+    file: &mut RegularFile,
+    buffer: &mut [u8],
+) -> Option<Box<FileIoToken>> {
+    let raw = raw_file(file);
+
+    // SAFETY: The protocol stays valid as long as the file is open.
+    if unsafe { (*raw).revision } < FILE_PROTOCOL_REVISION2 {
+        return None;
+    }
+
+    let event = unsafe {
+        system_table
+            .boot_services()
+            .create_event(EventType::empty(), Tpl::CALLBACK, None)
+            .ok()?
+            .unwrap()
+    };
+
+    // The token is boxed, as the firmware writes the result to it once the read completes.
+    let mut token = Box::new(FileIoToken {
+        event,
+        status: Status::SUCCESS,
+        buffer_size: buffer.len(),
+        buffer: buffer.as_mut_ptr() as *mut c_void,
+    });
+
+    let status = unsafe { ((*raw).read_ex)(raw, &mut *token) };
+
+    if status.is_error() {
+        efi::close_event(system_table.boot_services(), event);
+        return None;
+    }
+
+    Some(token)
+}
+
+/// Reads each of the provided files into its buffer and returns the amount of bytes that
+/// have been read, or [`None`] if reading the file failed. The files are read at once if
+/// the firmware supports asynchronous reads and one after another otherwise.
+pub fn read_all(
+    system_table: &SystemTable<Boot>,
+    files: &mut [(RegularFile, &mut [u8])],
+) -> Vec<Option<usize>> {
+    let boot_services = system_table.boot_services();
+    let supported = files.len() > 1 && boot_services.locate_protocol::<DiskIo2>().is_ok();
+
+    let mut results = vec![None; files.len()];
+    let mut pending = Vec::new();
+
+    for (i, (file, buffer)) in files.iter_mut().enumerate() {
+        if supported {
+            if let Some(token) = start_read(system_table, file, buffer) {
+                pending.push((i, token));
+                continue;
+            }
+        }
+
+        results[i] = file.read(buffer).ok().map(|len| len.unwrap());
+    }
+
+    if !pending.is_empty() {
+        log::debug!("diskio: reading {} files asynchronously", pending.len());
+    }
+
+    for (i, token) in pending {
+        let completed = boot_services.wait_for_event(&mut [token.event]).is_ok();
+
+        efi::close_event(system_table.boot_services(), token.event);
+
+        // SAFETY: The firmware has written the result of the read to the token.
+        let status = unsafe { ptr::read_volatile(&token.status) };
+
+        if completed && status.is_success() {
+            results[i] = Some(unsafe { ptr::read_volatile(&token.buffer_size) });
+        }
+    }
+
+    results
+}
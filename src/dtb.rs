@@ -0,0 +1,64 @@
+use uefi::prelude::*;
+use uefi::Guid;
+
+/// The GUID of the UEFI configuration table that contains the flattened device tree
+/// provided by the firmware.
+const DTB_TABLE_GUID: Guid = Guid::from_values(
+    0xb1b621d5,
+    0xf19c,
+    0x41a5,
+    0x830b,
+    [0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0],
+);
+
+/// The magic value at the start of every flattened device tree header (big endian).
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
+/// The size of the fixed part of the flattened device tree header.
+const FDT_HEADER_SIZE: usize = 40;
+
+/// Reads the big endian 32-bit header field at the provided offset.
+fn read_be32(data: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&data[offset..offset + 4]);
+
+    u32::from_be_bytes(value)
+}
+
+/// Validates the flattened device tree header of the provided blob and returns the blob
+/// truncated to the total size in its header.
+pub fn validate(data: &'static [u8]) -> Option<&'static [u8]> {
+    if data.len() < FDT_HEADER_SIZE || read_be32(data, 0) != FDT_MAGIC {
+        return None;
+    }
+
+    let total_size = read_be32(data, 4) as usize;
+
+    if total_size < FDT_HEADER_SIZE || total_size > data.len() {
+        return None;
+    }
+
+    Some(&data[..total_size])
+}
+
+/// Returns the flattened device tree provided by the firmware through the UEFI
+/// configuration table, if there is any.
+pub fn from_config_table(system_table: &SystemTable<Boot>) -> Option<&'static [u8]> {
+    let address = system_table
+        .config_table()
+        .iter()
+        .find(|entry| entry.guid == DTB_TABLE_GUID)?
+        .address as *const u8;
+
+    // SAFETY: The firmware guarantees that the table starts with a device tree header, so
+    // the fixed part of the header can be read to find out the total size.
+    let header = unsafe { core::slice::from_raw_parts(address, FDT_HEADER_SIZE) };
+
+    if read_be32(header, 0) != FDT_MAGIC {
+        log::warn!("dtb: the firmware device tree has an invalid magic value");
+        return None;
+    }
+
+    let total_size = read_be32(header, 4) as usize;
+    validate(unsafe { core::slice::from_raw_parts(address, total_size) })
+}
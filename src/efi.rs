@@ -0,0 +1,407 @@
+use core::ffi::c_void;
+use core::{mem, ptr};
+
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::table::boot::{BootServices, Event, MemoryAttribute, MemoryDescriptor, MemoryType};
+use uefi::table::runtime::RuntimeServices;
+
+use crate::devpath;
+
+/// The `EFI_LOCATE_SEARCH_TYPE` used to retrieve every handle in the handle database.
+const ALL_HANDLES: u32 = 0;
+
+/// Mirrors the layout of the `EFI_BOOT_SERVICES` table, used to call the boot services
+/// that are not wrapped by the `uefi` crate. The function pointers that Ion does not call
+/// through this table are declared as opaque pointers.
+#[repr(C)]
+pub struct RawBootServices {
+    header: [u8; 24],
+
+    raise_tpl: usize,
+    restore_tpl: usize,
+
+    allocate_pages: usize,
+    free_pages: usize,
+    get_memory_map: usize,
+    allocate_pool: usize,
+    free_pool: usize,
+
+    create_event: usize,
+    set_timer: usize,
+    wait_for_event: usize,
+    signal_event: usize,
+    pub close_event: unsafe extern "efiapi" fn(event: Event) -> Status,
+    check_event: usize,
+
+    install_protocol_interface: usize,
+    reinstall_protocol_interface: usize,
+    uninstall_protocol_interface: usize,
+    handle_protocol: usize,
+    reserved: usize,
+    register_protocol_notify: usize,
+    locate_handle: usize,
+    locate_device_path: usize,
+    install_configuration_table: usize,
+
+    pub load_image: unsafe extern "efiapi" fn(
+        boot_policy: bool,
+        parent_image_handle: Handle,
+        device_path: *const c_void,
+        source_buffer: *const u8,
+        source_size: usize,
+        image_handle: *mut Handle,
+    ) -> Status,
+    pub start_image: unsafe extern "efiapi" fn(
+        image_handle: Handle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> Status,
+    exit: usize,
+    unload_image: usize,
+    exit_boot_services: usize,
+
+    get_next_monotonic_count: usize,
+    stall: usize,
+    set_watchdog_timer: usize,
+
+    pub connect_controller: unsafe extern "efiapi" fn(
+        controller: Handle,
+        driver_image: *const Handle,
+        remaining_device_path: *const c_void,
+        recursive: bool,
+    ) -> Status,
+    disconnect_controller: usize,
+
+    open_protocol: usize,
+    close_protocol: usize,
+    open_protocol_information: usize,
+
+    protocols_per_handle: usize,
+    pub locate_handle_buffer: unsafe extern "efiapi" fn(
+        search_type: u32,
+        protocol: *const c_void,
+        search_key: *const c_void,
+        handle_count: &mut usize,
+        buffer: &mut *mut Handle,
+    ) -> Status,
+    locate_protocol: usize,
+    install_multiple_protocol_interfaces: usize,
+    uninstall_multiple_protocol_interfaces: usize,
+
+    calculate_crc32: usize,
+    copy_mem: usize,
+    set_mem: usize,
+    create_event_ex: usize,
+}
+
+/// Mirrors the layout of the `EFI_RUNTIME_SERVICES` table, used to call the runtime
+/// services that are not wrapped by the `uefi` crate. Only the function pointers up to the
+/// last one that Ion calls are declared.
+#[repr(C)]
+struct RawRuntimeServices {
+    header: [u8; 24],
+
+    get_time: usize,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+
+    set_virtual_address_map: unsafe extern "efiapi" fn(
+        map_size: usize,
+        descriptor_size: usize,
+        descriptor_version: u32,
+        virtual_map: *mut MemoryDescriptor,
+    ) -> Status,
+}
+
+/// Mirrors the layout of the `EFI_LOADED_IMAGE_PROTOCOL`, used to pass load options to the
+/// images that Ion starts and to find the file that Ion has been loaded from. Only the
+/// members up to the load options are declared.
+#[repr(C)]
+struct RawLoadedImage {
+    revision: u32,
+    parent_handle: Handle,
+    system_table: usize,
+
+    device_handle: Handle,
+    file_path: *const u8,
+    reserved: usize,
+
+    load_options_size: u32,
+    load_options: *const u16,
+}
+
+/// The version of the memory descriptors passed to `SetVirtualAddressMap`.
+pub const MEMORY_DESCRIPTOR_VERSION: u32 = 1;
+
+/// The runtime regions of the UEFI memory map, which can be assigned virtual addresses
+/// in the higher half before jumping to the kernel.
+pub struct RuntimeMap {
+    runtime_services: &'static RawRuntimeServices,
+    storage: &'static mut [MemoryDescriptor],
+    len: usize,
+}
+
+impl RuntimeMap {
+    /// Allocates the storage for the runtime regions. Must be called before exiting the
+    /// boot services, as the memory map can only be collected afterwards.
+    pub fn new(system_table: &SystemTable<Boot>) -> Self {
+        let boot_services = system_table.boot_services();
+
+        // The allocations that follow might split a few regions, so make some room for
+        // extra descriptors.
+        let capacity = boot_services.memory_map_size() / mem::size_of::<MemoryDescriptor>() + 8;
+
+        let ptr = boot_services
+            .allocate_pool(
+                MemoryType::LOADER_DATA,
+                capacity * mem::size_of::<MemoryDescriptor>(),
+            )
+            .expect_success("efi: failed to allocate the runtime map");
+
+        // SAFETY: The runtime services table is provided by the firmware and lives in
+        // runtime services memory, which is never reclaimed.
+        let runtime_services = unsafe {
+            &*(system_table.runtime_services() as *const RuntimeServices
+                as *const RawRuntimeServices)
+        };
+
+        Self {
+            runtime_services,
+            // SAFETY: The pool is suitably aligned for the descriptors and is never freed.
+            storage: unsafe {
+                core::slice::from_raw_parts_mut(ptr as *mut MemoryDescriptor, capacity)
+            },
+            len: 0,
+        }
+    }
+
+    /// Copies the runtime regions of the provided memory map, which has to be the final
+    /// memory map returned when exiting the boot services.
+    pub fn collect<'a>(&mut self, memory_map: impl Iterator<Item = &'a MemoryDescriptor>) {
+        let runtime =
+            memory_map.filter(|descriptor| descriptor.att.contains(MemoryAttribute::RUNTIME));
+
+        for descriptor in runtime {
+            assert!(
+                self.len < self.storage.len(),
+                "efi: runtime map is too small"
+            );
+
+            self.storage[self.len] = *descriptor;
+            self.len += 1;
+        }
+    }
+
+    /// Returns the runtime regions.
+    pub fn descriptors(&self) -> &[MemoryDescriptor] {
+        &self.storage[..self.len]
+    }
+
+    /// Returns the physical address of the runtime services table.
+    pub fn runtime_services_address(&self) -> u64 {
+        self.runtime_services as *const RawRuntimeServices as u64
+    }
+
+    /// Maps each runtime region at its physical address plus the provided offset and
+    /// switches the firmware to the new mapping with `SetVirtualAddressMap`. Afterwards
+    /// the runtime services can only be called through the new mapping.
+    ///
+    /// ## Safety
+    /// The boot services have to be exited and the regions have to be mapped at the
+    /// provided offset in the address space of the kernel. This function can only be
+    /// called once.
+    pub unsafe fn remap(&mut self, offset: u64) -> Result<(), Status> {
+        for descriptor in self.storage[..self.len].iter_mut() {
+            descriptor.virt_start = descriptor.phys_start + offset;
+        }
+
+        let status = (self.runtime_services.set_virtual_address_map)(
+            self.len * mem::size_of::<MemoryDescriptor>(),
+            mem::size_of::<MemoryDescriptor>(),
+            MEMORY_DESCRIPTOR_VERSION,
+            self.storage.as_mut_ptr(),
+        );
+
+        if status.is_error() {
+            Err(status)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the raw boot services table.
+pub fn raw_boot_services(boot_services: &BootServices) -> &RawBootServices {
+    // SAFETY: `BootServices` is a transparent view of the `EFI_BOOT_SERVICES` table
+    // provided by the firmware.
+    unsafe { &*(boot_services as *const BootServices as *const RawBootServices) }
+}
+
+/// Closes the provided event. Errors are ignored, as there is nothing the caller could do
+/// about them.
+pub fn close_event(boot_services: &BootServices, event: Event) {
+    // SAFETY: The event has been created by the caller and is not used afterwards.
+    let _ = unsafe { (raw_boot_services(boot_services).close_event)(event) };
+}
+
+/// Returns the device path of the file that the provided image has been loaded from,
+/// relative to the device of the image. Returns [`None`] if the image has not been loaded
+/// from a file, e.g. when it has been loaded from a PCI option ROM.
+pub fn image_file_path(loaded_image: &LoadedImage) -> Option<&'static [u8]> {
+    // SAFETY: `LoadedImage` is a view of the `EFI_LOADED_IMAGE_PROTOCOL` installed by the
+    // firmware.
+    let raw = unsafe { &*(loaded_image as *const LoadedImage as *const RawLoadedImage) };
+
+    if raw.file_path.is_null() {
+        return None;
+    }
+
+    // SAFETY: The firmware provides a device path that lives as long as the image.
+    unsafe { devpath::from_raw(raw.file_path) }
+}
+
+/// Loads and starts the UEFI driver contained in the provided buffer. The driver installs
+/// its protocols when it is started, but it is only bound to the controllers the next time
+/// that they are connected (see [`connect_all_controllers`]).
+pub fn load_driver(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    image: &[u8],
+) -> Result<(), Status> {
+    let raw = raw_boot_services(system_table.boot_services());
+    let mut driver = mem::MaybeUninit::<Handle>::uninit();
+
+    let status = unsafe {
+        (raw.load_image)(
+            false,
+            image_handle,
+            ptr::null(),
+            image.as_ptr(),
+            image.len(),
+            driver.as_mut_ptr(),
+        )
+    };
+
+    if status.is_error() {
+        return Err(status);
+    }
+
+    // SAFETY: The firmware has returned the handle of the loaded image.
+    let driver = unsafe { driver.assume_init() };
+    let status = unsafe { (raw.start_image)(driver, ptr::null_mut(), ptr::null_mut()) };
+
+    if status.is_error() {
+        return Err(status);
+    }
+
+    Ok(())
+}
+
+/// Loads the EFI application contained in the provided buffer as a child of Ion and starts
+/// it with the provided load options. The device path of the application's file is passed
+/// to the firmware, which uses it to check the image against the Secure Boot databases and
+/// to fill in the device handle and the file path of its loaded image, so that it finds the
+/// files next to it (e.g. shim and its second stage). Returns once the application exits.
+pub fn start_application(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    device_path: Option<&[u8]>,
+    image: &[u8],
+    load_options: &str,
+) -> Result<(), Status> {
+    let boot_services = system_table.boot_services();
+    let raw = raw_boot_services(boot_services);
+    let mut application = mem::MaybeUninit::<Handle>::uninit();
+
+    let status = unsafe {
+        (raw.load_image)(
+            false,
+            image_handle,
+            device_path.map_or(ptr::null(), |path| path.as_ptr() as *const c_void),
+            image.as_ptr(),
+            image.len(),
+            application.as_mut_ptr(),
+        )
+    };
+
+    if status.is_error() {
+        return Err(status);
+    }
+
+    // SAFETY: The firmware has returned the handle of the loaded image.
+    let application = unsafe { application.assume_init() };
+
+    // The load options are a NUL-terminated UCS-2 string, which has to stay alive until
+    // the application exits.
+    let load_options = load_options
+        .encode_utf16()
+        .chain(core::iter::once(0))
+        .collect::<Vec<_>>();
+
+    if load_options.len() > 1 {
+        let loaded_image = boot_services
+            .handle_protocol::<LoadedImage>(application)
+            .expect_success("efi: failed to retrieve the loaded image of the application");
+
+        // SAFETY: `LoadedImage` is a view of the `EFI_LOADED_IMAGE_PROTOCOL` installed by
+        // the firmware, which the application does not access before it is started.
+        unsafe {
+            let loaded_image = &mut *(loaded_image.get() as *mut RawLoadedImage);
+
+            loaded_image.load_options_size = (load_options.len() * mem::size_of::<u16>()) as u32;
+            loaded_image.load_options = load_options.as_ptr();
+        }
+    }
+
+    let status = unsafe { (raw.start_image)(application, ptr::null_mut(), ptr::null_mut()) };
+
+    if status.is_error() {
+        return Err(status);
+    }
+
+    Ok(())
+}
+
+/// This function is responsible for recursively connecting all of the drivers to every
+/// controller in the handle database. Some firmware does not bind the drivers for USB
+/// keyboards (or any device that is not needed to start the boot option) before starting
+/// Ion, so we have to do it ourselves.
+pub fn connect_all_controllers(system_table: &SystemTable<Boot>) {
+    let boot_services = system_table.boot_services();
+    let raw = raw_boot_services(boot_services);
+
+    let mut handle_count = 0;
+    let mut handles = ptr::null_mut();
+
+    let status = unsafe {
+        (raw.locate_handle_buffer)(
+            ALL_HANDLES,
+            ptr::null(),
+            ptr::null(),
+            &mut handle_count,
+            &mut handles,
+        )
+    };
+
+    if status.is_error() {
+        log::warn!("efi: failed to retrieve the handle database ({:?})", status);
+        return;
+    }
+
+    // SAFETY: The firmware has allocated a buffer of `handle_count` handles.
+    let handle_slice = unsafe { core::slice::from_raw_parts(handles, handle_count) };
+
+    for handle in handle_slice {
+        // Most of the handles are not controllers, so the failures are expected and
+        // can be safely ignored.
+        let _ = unsafe { (raw.connect_controller)(*handle, ptr::null(), ptr::null(), true) };
+    }
+
+    boot_services
+        .free_pool(handles as *mut u8)
+        .expect_success("efi: failed to free the handle buffer");
+}
@@ -0,0 +1,175 @@
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+use uefi::{unsafe_guid, Guid, Protocol};
+
+use crate::arch;
+
+/// The amount of bytes requested from the UEFI RNG protocol.
+const UEFI_SEED_SIZE: usize = 32;
+
+/// The amount of 64-bit values read from each hardware random number generator of the
+/// processor.
+const HARDWARE_SEED_WORDS: usize = 4;
+
+/// The UEFI RNG protocol, provided by firmware with access to a hardware random number
+/// generator (e.g. a TPM).
+#[repr(C)]
+#[unsafe_guid("3152bca5-eade-433d-862e-c01cdc291f44")]
+#[derive(Protocol)]
+struct UefiRng {
+    get_info: usize,
+    get_rng: unsafe extern "efiapi" fn(
+        this: &UefiRng,
+        algorithm: *const Guid,
+        len: usize,
+        value: *mut u8,
+    ) -> Status,
+}
+
+/// A ChaCha20 based random number generator. After each block the key is replaced by
+/// the first half of the block, so that previous outputs cannot be reconstructed.
+struct ChaCha20Rng {
+    key: [u32; 8],
+    counter: u64,
+}
+
+impl ChaCha20Rng {
+    fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        state[a] = state[a].wrapping_add(state[b]);
+        state[d] = (state[d] ^ state[a]).rotate_left(16);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_left(12);
+        state[a] = state[a].wrapping_add(state[b]);
+        state[d] = (state[d] ^ state[a]).rotate_left(8);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_left(7);
+    }
+
+    /// Returns the next ChaCha20 block and replaces the key.
+    fn next_block(&mut self) -> [u32; 8] {
+        let mut state = [0; 16];
+
+        // "expand 32-byte k"
+        state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+        state[4..12].copy_from_slice(&self.key);
+        state[12] = self.counter as u32;
+        state[13] = (self.counter >> 32) as u32;
+
+        let initial = state;
+
+        for _ in 0..10 {
+            Self::quarter_round(&mut state, 0, 4, 8, 12);
+            Self::quarter_round(&mut state, 1, 5, 9, 13);
+            Self::quarter_round(&mut state, 2, 6, 10, 14);
+            Self::quarter_round(&mut state, 3, 7, 11, 15);
+
+            Self::quarter_round(&mut state, 0, 5, 10, 15);
+            Self::quarter_round(&mut state, 1, 6, 11, 12);
+            Self::quarter_round(&mut state, 2, 7, 8, 13);
+            Self::quarter_round(&mut state, 3, 4, 9, 14);
+        }
+
+        for (word, initial) in state.iter_mut().zip(initial.iter()) {
+            *word = word.wrapping_add(*initial);
+        }
+
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&state[..8]);
+
+        let mut output = [0; 8];
+        output.copy_from_slice(&state[8..]);
+        output
+    }
+
+    /// Mixes the provided value into the key.
+    fn mix(&mut self, value: u64) {
+        self.key[0] ^= value as u32;
+        self.key[1] ^= (value >> 32) as u32;
+
+        // Diffuse the value into the whole key.
+        self.next_block();
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        // Mix in the counter, so that the output also depends on when it is requested.
+        self.mix(arch::read_counter());
+
+        for chunk in buffer.chunks_mut(32) {
+            let block = self.next_block();
+
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> ((i % 4) * 8)) as u8;
+            }
+        }
+    }
+}
+
+static RNG: SpinMutex<ChaCha20Rng> = SpinMutex::new(ChaCha20Rng {
+    key: [0; 8],
+    counter: 0,
+});
+
+/// Reads random bytes from the UEFI RNG protocol, if the firmware provides it.
+fn uefi_rng(system_table: &SystemTable<Boot>) -> Option<[u8; UEFI_SEED_SIZE]> {
+    let rng = system_table
+        .boot_services()
+        .locate_protocol::<UefiRng>()
+        .ok()?;
+
+    // SAFETY: The protocol pointer is valid as long as the boot services are active.
+    let rng = unsafe { &*rng.unwrap().get() };
+    let mut seed = [0; UEFI_SEED_SIZE];
+
+    // A null algorithm selects the default algorithm of the firmware.
+    let status = unsafe { (rng.get_rng)(rng, core::ptr::null(), seed.len(), seed.as_mut_ptr()) };
+
+    if status == Status::SUCCESS {
+        Some(seed)
+    } else {
+        None
+    }
+}
+
+/// This function is responsible for seeding the random number generator from all of the
+/// available entropy sources: the UEFI RNG protocol, the hardware random number
+/// generators of the processor (e.g. RDSEED and RDRAND) and the counter. Must be called
+/// before exiting the boot services, as the UEFI RNG protocol is not available afterwards.
+pub fn init(system_table: &SystemTable<Boot>) {
+    let mut rng = RNG.lock();
+    let mut hardware = false;
+
+    if let Some(seed) = uefi_rng(system_table) {
+        for chunk in seed.chunks(8) {
+            let mut value = [0; 8];
+            value.copy_from_slice(chunk);
+
+            rng.mix(u64::from_le_bytes(value));
+        }
+
+        hardware = true;
+    }
+
+    arch::hardware_random(HARDWARE_SEED_WORDS, |value| {
+        rng.mix(value);
+        hardware = true;
+    });
+
+    rng.mix(arch::read_counter());
+
+    if !hardware {
+        log::warn!("entropy: no hardware random number generator found, using the timer");
+    }
+}
+
+/// Fills the provided buffer with random bytes.
+pub fn fill(buffer: &mut [u8]) {
+    RNG.lock().fill(buffer);
+}
+
+/// Returns a random 64-bit number.
+pub fn random_u64() -> u64 {
+    let mut value = [0; 8];
+    fill(&mut value);
+
+    u64::from_le_bytes(value)
+}
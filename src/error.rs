@@ -0,0 +1,99 @@
+use core::fmt;
+
+use alloc::string::String;
+
+use uefi::Status;
+
+use ion_core::cpu::Features;
+
+use crate::config::{BootProtocol, UriParseError};
+
+/// Errors that make Ion unable to boot the selected entry. These errors are recoverable, so
+/// they are reported to the user who is then returned to the boot menu.
+#[derive(Debug)]
+pub enum IonError {
+    /// The entry does not specify the path of the kernel.
+    MissingKernelPath,
+    /// The provided URI could not be parsed.
+    InvalidUri(&'static str, UriParseError),
+    /// The resource type of a URI is not supported (e.g. `bios://`).
+    UnsupportedResource(String),
+    /// There is no volume with the provided device path.
+    VolumeNotFound(String),
+    /// There is no volume with the provided label or marker file.
+    MarkerNotFound(String),
+    /// The file at the provided URI could not be opened or read.
+    FileNotFound(&'static str),
+    /// The kernel is not a valid ELF file.
+    InvalidElf(&'static str),
+    /// The kernel has been built for an architecture that Ion cannot boot.
+    UnsupportedArchitecture(String),
+    /// The kernel does not contain the header of the boot protocol.
+    MissingHeader(&'static str),
+    /// The kernel image does not contain the header of the boot protocol, which is searched
+    /// for at the start of the image.
+    HeaderNotFound(&'static str),
+    /// The header of the boot protocol is malformed or requests something unsupported.
+    InvalidHeader(&'static str),
+    /// The kernel has to be loaded at a physical address that is already in use.
+    AddressInUse(u64),
+    /// The boot protocol of the entry is not implemented.
+    UnsupportedProtocol(BootProtocol),
+    /// The value of a config key could not be parsed.
+    InvalidValue(&'static str, &'static str),
+    /// A macro of the command line could not be expanded.
+    CommandLineMacro(String, &'static str),
+    /// The EFI application could not be started or has exited with an error.
+    ApplicationFailed(&'static str, Status),
+    /// The processor does not support the features that the entry requires.
+    MissingCpuFeatures(Features),
+    /// The config does not contain any entries, so there is nothing to boot.
+    NoEntries,
+}
+
+impl fmt::Display for IonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingKernelPath => write!(f, "KERNEL_PATH not specified"),
+            Self::InvalidUri(uri, error) => write!(f, "invalid URI {} ({:?})", uri, error),
+            Self::UnsupportedResource(resource) => {
+                write!(f, "unsupported resource type: {}", resource)
+            }
+            Self::VolumeNotFound(device_path) => {
+                write!(f, "no volume with the device path {}", device_path)
+            }
+            Self::MarkerNotFound(marker) => {
+                write!(f, "no volume with the label or marker file {}", marker)
+            }
+            Self::FileNotFound(uri) => write!(f, "failed to open {}. Is its path correct?", uri),
+            Self::InvalidElf(error) => write!(f, "invalid ELF file ({})", error),
+            Self::UnsupportedArchitecture(machine) => {
+                write!(f, "unsupported architecture {}", machine)
+            }
+            Self::MissingHeader(section) => write!(f, "section {} not found", section),
+            Self::HeaderNotFound(protocol) => write!(f, "no {} header found", protocol),
+            Self::InvalidHeader(error) => write!(f, "invalid header ({})", error),
+            Self::AddressInUse(address) => {
+                write!(f, "the memory at {:#x} is already in use", address)
+            }
+            Self::UnsupportedProtocol(protocol) => {
+                write!(f, "the {:?} boot protocol is not supported", protocol)
+            }
+            Self::InvalidValue(key, value) => write!(f, "invalid value for {}: {}", key, value),
+            Self::CommandLineMacro(name, error) => {
+                write!(
+                    f,
+                    "unable to expand {} in the command line ({})",
+                    name, error
+                )
+            }
+            Self::ApplicationFailed(path, status) => {
+                write!(f, "the EFI application {} has failed ({:?})", path, status)
+            }
+            Self::MissingCpuFeatures(features) => {
+                write!(f, "the processor does not support {}", features)
+            }
+            Self::NoEntries => write!(f, "the config does not contain any entries"),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::collections::BTreeMap;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TAB: u8 = 0x02;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+/// The largest glyph dimensions in pixels that are accepted. Larger glyphs would not leave
+/// room for more than a few lines of text, and a glyph taller than the screen breaks the
+/// scrolling of the logger.
+const MAX_GLYPH_SIZE: usize = 64;
+
+/// A PC Screen Font (version 1 or 2) loaded from disk.
+pub struct PsfFont {
+    width: usize,
+    height: usize,
+
+    glyph_size: usize,
+    glyph_count: usize,
+    glyphs: &'static [u8],
+
+    /// Maps unicode characters to glyph indices. If the font does not contain a unicode
+    /// table, the character code is used as the glyph index instead.
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl PsfFont {
+    /// Parses the provided PSF1 or PSF2 font file. Returns [`None`] if the file is not a
+    /// valid PC Screen Font.
+    pub fn parse(data: &'static [u8]) -> Option<Self> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            None
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Option<Self> {
+        let mode = *data.get(2)?;
+        let height = *data.get(3)? as usize;
+
+        if height == 0 || height > MAX_GLYPH_SIZE {
+            return None;
+        }
+
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = 4 + glyph_count * height;
+        let glyphs = data.get(4..glyphs_end)?;
+
+        let unicode = if mode & PSF1_MODE_HAS_TAB != 0 {
+            let mut unicode = BTreeMap::new();
+            let mut glyph = 0;
+            let mut in_sequence = false;
+
+            // The unicode table consists of little endian UCS-2 characters for each
+            // glyph terminated by a separator.
+            for entry in data[glyphs_end..].chunks_exact(2) {
+                match u16::from_le_bytes([entry[0], entry[1]]) {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+
+                    // We only render single characters, so skip multi-character sequences.
+                    PSF1_START_SEQUENCE => in_sequence = true,
+
+                    c if !in_sequence => {
+                        if let Some(c) = char::from_u32(c as u32) {
+                            unicode.entry(c).or_insert(glyph);
+                        }
+                    }
+
+                    _ => {}
+                }
+            }
+
+            Some(unicode)
+        } else {
+            None
+        };
+
+        Some(Self {
+            width: 8,
+            height,
+
+            glyph_size: height,
+            glyph_count,
+            glyphs,
+
+            unicode,
+        })
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Option<Self> {
+        let read_u32 = |offset: usize| -> Option<usize> {
+            let bytes = data.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+
+        let header_size = read_u32(8)?;
+        let flags = read_u32(12)? as u32;
+        let glyph_count = read_u32(16)?;
+        let glyph_size = read_u32(20)?;
+        let height = read_u32(24)?;
+        let width = read_u32(28)?;
+
+        if width == 0 || height == 0 || width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+            return None;
+        }
+
+        // Make sure that the glyph size is consistent with the glyph dimensions.
+        if glyph_size < height * ((width + 7) / 8) {
+            return None;
+        }
+
+        let glyphs_end = glyph_count
+            .checked_mul(glyph_size)?
+            .checked_add(header_size)?;
+        let glyphs = data.get(header_size..glyphs_end)?;
+
+        let unicode = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let mut unicode = BTreeMap::new();
+
+            // The unicode table consists of UTF-8 encoded characters for each glyph
+            // terminated by a separator.
+            for (glyph, entry) in data[glyphs_end..]
+                .split(|b| *b == PSF2_SEPARATOR)
+                .enumerate()
+            {
+                // We only render single characters, so skip multi-character sequences.
+                let entry = entry
+                    .split(|b| *b == PSF2_START_SEQUENCE)
+                    .next()
+                    .unwrap_or(&[]);
+
+                if let Ok(chars) = core::str::from_utf8(entry) {
+                    for c in chars.chars() {
+                        unicode.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+
+            Some(unicode)
+        } else {
+            None
+        };
+
+        Some(Self {
+            width,
+            height,
+
+            glyph_size,
+            glyph_count,
+            glyphs,
+
+            unicode,
+        })
+    }
+
+    /// Returns the width of a glyph in pixels.
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of a glyph in pixels.
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the bitmap of the glyph for the provided character, if the font covers
+    /// it. Each row of the bitmap is padded to a whole byte and the most significant bit
+    /// is the leftmost pixel.
+    pub fn glyph(&self, c: char) -> Option<&'static [u8]> {
+        let index = match &self.unicode {
+            Some(unicode) => *unicode.get(&c)?,
+            None => c as usize,
+        };
+
+        if index >= self.glyph_count {
+            return None;
+        }
+
+        let glyphs: &'static [u8] = self.glyphs;
+        Some(&glyphs[(index * self.glyph_size)..((index + 1) * self.glyph_size)])
+    }
+
+    /// Returns true if the pixel at the provided position of the glyph is set.
+    #[inline]
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let bytes_per_row = (self.width + 7) / 8;
+        glyph[y * bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
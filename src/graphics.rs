@@ -0,0 +1,382 @@
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+use uefi::proto::console::gop::{GraphicsOutput, Mode, PixelFormat};
+use uefi::{unsafe_guid, Protocol};
+
+use ion_core::config::Display;
+
+use crate::logger::{self, FrameBufferInfo};
+use crate::{devpath, nvram};
+
+/// The offset of the first detailed timing descriptor in an EDID block. The first
+/// detailed timing descriptor describes the preferred (native) mode of the display.
+const EDID_PREFERRED_TIMING: usize = 54;
+
+/// The resolution of the mode that the firmware has left active, which the position of
+/// the firmware's boot logo refers to.
+static FIRMWARE_RESOLUTION: SpinMutex<Option<(usize, usize)>> = SpinMutex::new(None);
+
+/// The maximum size of the `ConOut` variable.
+const CONSOLE_OUT_MAX_SIZE: usize = 4096;
+
+/// A graphics output along with the handle it is installed on.
+struct Output {
+    handle: Handle,
+    gop: *mut GraphicsOutput<'static>,
+}
+
+// SAFETY: Ion only runs on the bootstrap processor so the raw protocol pointers are never
+// shared between threads.
+unsafe impl Send for Output {}
+
+/// The graphics output that Ion draws to, chosen by [`select`].
+static OUTPUT: SpinMutex<Option<Output>> = SpinMutex::new(None);
+
+/// The framebuffers of the other graphics outputs, which are passed to kernels that accept
+/// several framebuffers.
+static SECONDARY_FRAMEBUFFERS: SpinMutex<Vec<(u64, FrameBufferInfo)>> = SpinMutex::new(Vec::new());
+
+/// The UEFI EDID Active protocol, describing the display that is currently attached to
+/// the graphics output.
+#[repr(C)]
+#[unsafe_guid("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+#[derive(Protocol)]
+struct EdidActive {
+    size: u32,
+    edid: *const u8,
+}
+
+/// The UEFI EDID Discovered protocol, containing the unmodified EDID read from the display.
+#[repr(C)]
+#[unsafe_guid("1c0c34f6-d380-41fa-a049-8ad06c1a66aa")]
+#[derive(Protocol)]
+struct EdidDiscovered {
+    size: u32,
+    edid: *const u8,
+}
+
+/// Returns the preferred resolution of the display attached to the graphics output on the
+/// provided handle from its EDID, if the firmware provides it. Outputs that are not backed
+/// by a single device (e.g. the console splitter) use the EDID of any display.
+fn preferred_resolution(
+    system_table: &SystemTable<Boot>,
+    handle: Handle,
+) -> Option<(usize, usize)> {
+    let boot_services = system_table.boot_services();
+
+    let edid = if let Ok(active) = boot_services
+        .handle_protocol::<EdidActive>(handle)
+        .or_else(|_| boot_services.locate_protocol::<EdidActive>())
+    {
+        // SAFETY: The protocol pointer is valid as long as the boot services are active.
+        let active = unsafe { &*active.unwrap().get() };
+        (active.edid, active.size)
+    } else if let Ok(discovered) = boot_services
+        .handle_protocol::<EdidDiscovered>(handle)
+        .or_else(|_| boot_services.locate_protocol::<EdidDiscovered>())
+    {
+        // SAFETY: The protocol pointer is valid as long as the boot services are active.
+        let discovered = unsafe { &*discovered.unwrap().get() };
+        (discovered.edid, discovered.size)
+    } else {
+        return None;
+    };
+
+    if edid.0.is_null() || (edid.1 as usize) < EDID_PREFERRED_TIMING + 18 {
+        return None;
+    }
+
+    // SAFETY: The EDID buffer is at least as large as the size reported by the protocol.
+    let edid = unsafe { core::slice::from_raw_parts(edid.0, edid.1 as usize) };
+    let timing = &edid[EDID_PREFERRED_TIMING..(EDID_PREFERRED_TIMING + 18)];
+
+    // A pixel clock of zero means that the descriptor is not a detailed timing
+    // descriptor.
+    if timing[0] == 0 && timing[1] == 0 {
+        return None;
+    }
+
+    let width = timing[2] as usize | ((timing[4] as usize & 0xf0) << 4);
+    let height = timing[5] as usize | ((timing[7] as usize & 0xf0) << 4);
+
+    Some((width, height))
+}
+
+/// Returns true if the device path of a graphics output is one of the console output
+/// devices in the `ConOut` variable, or a parent or a child of one.
+fn is_console(console: &[u8], device: &[u8]) -> bool {
+    ion_core::devpath::instances(console).any(|instance| {
+        ion_core::devpath::starts_with(device, instance)
+            || ion_core::devpath::starts_with(instance, device)
+    })
+}
+
+/// This function is responsible for choosing the graphics output that Ion draws to. The
+/// firmware might provide a graphics output for every display, so the output selected with
+/// `DISPLAY=` is preferred, followed by the output that the firmware uses as its console.
+/// The framebuffers of the other outputs are remembered for the kernel.
+pub fn select(system_table: &SystemTable<Boot>, display: Option<Display>) {
+    let boot_services = system_table.boot_services();
+
+    let outputs = devpath::handles_by_protocol::<GraphicsOutput>(system_table)
+        .into_iter()
+        .filter_map(|handle| {
+            let gop = boot_services
+                .handle_protocol::<GraphicsOutput>(handle)
+                .ok()?;
+            let path = devpath::of_handle(system_table, handle);
+
+            Some((handle, gop.unwrap().get(), path))
+        })
+        .collect::<Vec<_>>();
+
+    let mut console = alloc::vec![0; CONSOLE_OUT_MAX_SIZE];
+    let console = nvram::read(system_table, &nvram::GLOBAL_VENDOR, "ConOut", &mut console)
+        .map(|size| &console[..size])
+        .unwrap_or_default();
+
+    for (i, (_, gop, path)) in outputs.iter().enumerate() {
+        // SAFETY: The protocol pointer is valid as long as the boot services are active.
+        let (width, height) = unsafe { (**gop).current_mode_info().resolution() };
+
+        log::debug!(
+            "graphics: output {}: {} ({}x{}{})",
+            i,
+            path.map(ion_core::devpath::format).unwrap_or_default(),
+            width,
+            height,
+            if path.map_or(false, |path| is_console(console, path)) {
+                ", console"
+            } else {
+                ""
+            }
+        );
+    }
+
+    let selected = match display {
+        Some(Display::Index(index)) if index < outputs.len() => Some(index),
+        Some(Display::DevicePath(text)) => outputs.iter().position(|(_, _, path)| {
+            path.map_or(false, |path| {
+                ion_core::devpath::text_starts_with(&ion_core::devpath::format(path), text)
+            })
+        }),
+        _ => None,
+    };
+
+    if let (Some(display), None) = (display, selected) {
+        log::warn!("graphics: the display {:?} does not exist", display);
+    }
+
+    let selected = selected
+        .or_else(|| {
+            outputs
+                .iter()
+                .position(|(_, _, path)| path.map_or(false, |path| is_console(console, path)))
+        })
+        .unwrap_or(0);
+
+    let (handle, gop, _) = match outputs.get(selected) {
+        Some(&output) => output,
+        None => return,
+    };
+
+    *OUTPUT.lock() = Some(Output {
+        handle,
+        gop: gop as *mut GraphicsOutput<'static>,
+    });
+
+    let mut addresses = Vec::new();
+    let mut secondary = Vec::new();
+
+    // The selected output comes first, so that the outputs that mirror it are skipped.
+    let outputs = core::iter::once(&outputs[selected]).chain(&outputs);
+
+    for (i, &(_, gop, _)) in outputs.enumerate() {
+        // SAFETY: The protocol pointer is valid as long as the boot services are active.
+        let gop = unsafe { &mut *gop };
+
+        if let PixelFormat::BltOnly = gop.current_mode_info().pixel_format() {
+            continue;
+        }
+
+        let address = gop.frame_buffer().as_mut_ptr() as u64;
+
+        if address == 0 || addresses.contains(&address) {
+            continue;
+        }
+
+        addresses.push(address);
+
+        if i != 0 {
+            secondary.push((address, framebuffer_info(gop)));
+        }
+    }
+
+    *SECONDARY_FRAMEBUFFERS.lock() = secondary;
+}
+
+/// Returns the graphics output that Ion draws to, if the firmware provides one.
+pub fn output() -> Option<&'static mut GraphicsOutput<'static>> {
+    // SAFETY: The protocol pointer is valid as long as the boot services are active.
+    OUTPUT
+        .lock()
+        .as_ref()
+        .map(|output| unsafe { &mut *output.gop })
+}
+
+/// Returns the framebuffers of the graphics outputs other than the one that Ion draws to,
+/// along with their physical addresses. Outputs whose framebuffer is not accessible are
+/// not included.
+pub fn secondary_framebuffers() -> Vec<(u64, FrameBufferInfo)> {
+    SECONDARY_FRAMEBUFFERS.lock().clone()
+}
+
+/// Describes the layout of the framebuffer of the provided graphics output in its current
+/// mode. If the framebuffer is not accessible, this describes a backbuffer of BltPixels
+/// that is copied to the screen using the Blt function of the GOP.
+pub fn framebuffer_info(gop: &GraphicsOutput) -> FrameBufferInfo {
+    let mode_info = gop.current_mode_info();
+    let (horizontal_resolution, vertical_resolution) = mode_info.resolution();
+
+    let (pixel_format, bits_per_pixel) = match mode_info.pixel_format() {
+        PixelFormat::Rgb => (logger::PixelFormat::RGB, 32),
+        PixelFormat::Bgr => (logger::PixelFormat::BGR, 32),
+        PixelFormat::Bitmask => {
+            let mask = mode_info
+                .pixel_bitmask()
+                .expect("gop: bitmask pixel format without a pixel bitmask");
+
+            let pixel_format = logger::PixelFormat::Bitmask {
+                red: mask.red,
+                green: mask.green,
+                blue: mask.blue,
+            };
+
+            // The pixel is as wide as the highest bit that is covered by any of the masks.
+            let used_bits = mask.red | mask.green | mask.blue | mask.reserved;
+            let bits_per_pixel = 32 - used_bits.leading_zeros() as usize;
+
+            (pixel_format, bits_per_pixel)
+        }
+        PixelFormat::BltOnly => (logger::PixelFormat::BGR, 32),
+    };
+
+    let bytes_per_pixel = (bits_per_pixel + 7) / 8;
+    let stride = match mode_info.pixel_format() {
+        PixelFormat::BltOnly => horizontal_resolution,
+        _ => mode_info.stride(),
+    };
+
+    FrameBufferInfo {
+        horizontal_resolution,
+        vertical_resolution,
+        pixel_format,
+        bits_per_pixel,
+        bytes_per_pixel,
+        stride,
+        pitch: stride * bytes_per_pixel,
+    }
+}
+
+/// The reason why the display mode could not be switched to the requested resolution.
+#[derive(Debug, Clone, Copy)]
+pub enum ModeError {
+    /// The display does not provide a mode with the resolution.
+    Unsupported(usize, usize),
+    /// The firmware failed to switch to the mode with the resolution.
+    Failed(usize, usize),
+}
+
+impl fmt::Display for ModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(width, height) => write!(
+                f,
+                "the resolution {}x{} is not supported by the display",
+                width, height
+            ),
+            Self::Failed(width, height) => {
+                write!(f, "failed to set the mode to {}x{}", width, height)
+            }
+        }
+    }
+}
+
+/// This function is responsible for switching the selected GOP to the mode with the
+/// provided resolution or, if none is provided, to the preferred mode of the display. The
+/// mode that the firmware has left active is kept if no matching mode is found.
+///
+/// The error is returned instead of being logged, as the caller usually replaces the
+/// framebuffer of the logger afterwards, which clears the screen.
+pub fn set_mode(
+    system_table: &SystemTable<Boot>,
+    resolution: Option<(usize, usize)>,
+) -> Result<(), ModeError> {
+    let (handle, gop) = match OUTPUT.lock().as_ref() {
+        // SAFETY: The protocol pointer is valid as long as the boot services are active.
+        Some(output) => (output.handle, unsafe { &mut *output.gop }),
+        None => return Ok(()),
+    };
+
+    FIRMWARE_RESOLUTION
+        .lock()
+        .get_or_insert(gop.current_mode_info().resolution());
+
+    let target = match resolution.or_else(|| preferred_resolution(system_table, handle)) {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    if gop.current_mode_info().resolution() == target {
+        return Ok(());
+    }
+
+    let mode: Option<Mode> = gop
+        .modes()
+        .map(|mode| mode.unwrap())
+        .find(|mode| mode.info().resolution() == target);
+
+    match mode {
+        Some(mode) => gop
+            .set_mode(&mode)
+            .map(|_| ())
+            .map_err(|_| ModeError::Failed(target.0, target.1)),
+
+        None => Err(ModeError::Unsupported(target.0, target.1)),
+    }
+}
+
+/// Returns the resolution of the mode that follows the current mode of the selected GOP,
+/// wrapping around to the first mode. Modes with the current resolution are skipped.
+/// Returns [`None`] if there is no graphics output or it only supports one resolution.
+pub fn next_resolution() -> Option<(usize, usize)> {
+    let gop = output()?;
+    let current = gop.current_mode_info().resolution();
+
+    let resolutions: Vec<(usize, usize)> = gop
+        .modes()
+        .map(|mode| mode.unwrap().info().resolution())
+        .collect();
+
+    let position = resolutions
+        .iter()
+        .position(|resolution| *resolution == current)?;
+
+    resolutions
+        .iter()
+        .cycle()
+        .skip(position + 1)
+        .take(resolutions.len())
+        .find(|resolution| **resolution != current)
+        .copied()
+}
+
+/// Returns the resolution of the mode that the firmware has left active, before Ion has
+/// switched the mode.
+pub fn firmware_resolution() -> Option<(usize, usize)> {
+    *FIRMWARE_RESOLUTION.lock()
+}
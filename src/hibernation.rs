@@ -0,0 +1,191 @@
+// Detection of hibernated operating systems. Cold booting over a pending hibernation image
+// corrupts the file systems that the hibernated system still has mounted, so the entry of
+// the hibernated system is either selected or the user is warned before the menu is shown.
+
+use core::ffi::c_void;
+
+use uefi::prelude::*;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileMode, RegularFile};
+use uefi::table::boot::{AllocateType, MemoryType};
+use uefi::{unsafe_guid, Protocol};
+
+use ion_core::hibernation::{self, SWAP_HEADER_SIZE};
+
+use crate::config::{self, ConfigurationEntry, IonConfig};
+use crate::devpath;
+use crate::i18n;
+use crate::input;
+use crate::logger;
+use crate::prelude::*;
+
+/// The UEFI block I/O protocol, which is installed by the firmware for every disk and
+/// partition. Only the members that Ion uses are typed.
+#[repr(C)]
+#[unsafe_guid("964e5b21-6459-11d2-8e39-00a0c969723b")]
+#[derive(Protocol)]
+struct BlockIo {
+    revision: u64,
+    media: *const BlockIoMedia,
+    reset: usize,
+    read_blocks: unsafe extern "efiapi" fn(
+        this: &BlockIo,
+        media_id: u32,
+        lba: u64,
+        buffer_size: usize,
+        buffer: *mut c_void,
+    ) -> Status,
+    write_blocks: usize,
+    flush_blocks: usize,
+}
+
+/// The `EFI_BLOCK_IO_MEDIA` structure describing the medium of a block device.
+#[repr(C)]
+struct BlockIoMedia {
+    media_id: u32,
+    removable_media: u8,
+    media_present: u8,
+    logical_partition: u8,
+    read_only: u8,
+    write_caching: u8,
+    block_size: u32,
+    io_align: u32,
+    last_block: u64,
+}
+
+/// Reads the swap header of the partition with the provided device path and returns true
+/// if it contains a Linux hibernation image.
+fn swap_hibernated(system_table: &SystemTable<Boot>, device_path: &str) -> Option<bool> {
+    let boot_services = system_table.boot_services();
+
+    let handle = devpath::find_handle::<BlockIo>(system_table, device_path)?;
+    let block_io = boot_services
+        .handle_protocol::<BlockIo>(handle)
+        .ok()?
+        .unwrap();
+
+    // SAFETY: The protocol and its media are valid as long as the boot services are active.
+    let block_io = unsafe { &*block_io.get() };
+    let media = unsafe { &*block_io.media };
+    let block_size = media.block_size as usize;
+
+    if media.media_present == 0 || block_size == 0 {
+        return None;
+    }
+
+    // Only whole blocks can be read. The buffer is page aligned, which satisfies the
+    // alignment that block devices require in practice.
+    let size = (SWAP_HEADER_SIZE + block_size - 1) / block_size * block_size;
+    let pages = (size + 0xfff) / 0x1000;
+
+    let buffer = boot_services
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages)
+        .ok()?
+        .unwrap();
+
+    let status =
+        unsafe { (block_io.read_blocks)(block_io, media.media_id, 0, size, buffer as *mut c_void) };
+
+    // SAFETY: The buffer has been filled by the block device above.
+    let header = unsafe { core::slice::from_raw_parts(buffer as *const u8, size) };
+    let hibernated = status.is_success() && hibernation::swap_hibernated(header);
+
+    boot_services
+        .free_pages(buffer, pages)
+        .expect_success("hibernation: failed to free the swap header buffer");
+
+    Some(hibernated)
+}
+
+/// Reads the start of the Windows hibernation file at the provided URI and returns true if
+/// it contains an image that has not been resumed yet.
+fn hiberfil_pending(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    uri: &'static str,
+) -> Option<bool> {
+    let parsed_uri = match config::parse_uri(uri) {
+        Ok(parsed_uri) => parsed_uri,
+        Err(error) => {
+            log::warn!("hibernation: invalid URI {} ({:?})", uri, error);
+            return None;
+        }
+    };
+
+    let directory = match config::handle_uri_redirect(system_table, &parsed_uri, root) {
+        Ok(directory) => directory,
+        Err(error) => {
+            log::warn!("hibernation: {}", error);
+            return None;
+        }
+    };
+
+    // Windows deletes the hibernation file if hibernation is disabled.
+    let handle = directory
+        .open(parsed_uri.path(), FileMode::Read, FileAttribute::empty())
+        .ok()?
+        .unwrap();
+
+    let mut file = unsafe { RegularFile::new(handle) };
+    let mut header = [0; 4];
+    let len = file.read(&mut header).ok().map_or(0, |len| len.unwrap());
+
+    file.close();
+    Some(hibernation::hiberfil_pending(&header[..len]))
+}
+
+fn is_hibernated(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    entry: &ConfigurationEntry,
+) -> bool {
+    if let Some(device_path) = entry.resume_swap() {
+        match swap_hibernated(system_table, device_path) {
+            Some(true) => return true,
+            Some(false) => (),
+            None => log::warn!(
+                "hibernation: unable to read the swap partition {}",
+                device_path
+            ),
+        }
+    }
+
+    if let Some(uri) = entry.hiberfil() {
+        if hiberfil_pending(system_table, root, uri) == Some(true) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Returns the index of the first entry that has a pending hibernation image, if any.
+pub fn detect(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    boot_config: &IonConfig,
+) -> Option<usize> {
+    let index = boot_config
+        .entries
+        .iter()
+        .position(|entry| is_hibernated(system_table, root, entry))?;
+
+    log::warn!(
+        "hibernation: {} has a pending hibernation image",
+        boot_config.entries[index].name()
+    );
+
+    Some(index)
+}
+
+/// Warns the user that the provided entry has been hibernated and waits for a key press.
+pub fn warn(system_table: &SystemTable<Boot>, entry: &ConfigurationEntry) {
+    logger::clear();
+
+    println!(
+        "{}",
+        i18n::format(i18n::strings().hibernated, &[&entry.name()])
+    );
+    logger::flush();
+
+    let _ = input::wait(system_table);
+}
@@ -0,0 +1,318 @@
+use core::fmt::{Display, Write};
+use core::str::FromStr;
+
+use alloc::string::String;
+
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+
+use crate::nvram;
+
+/// Languages that the boot menu has been translated to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+impl FromStr for Language {
+    type Err = ();
+
+    /// Parses the value of the `LANGUAGE=` config key or of the `PlatformLang` UEFI
+    /// variable. Both the two letter language codes (e.g. `de`) and RFC 4646 language
+    /// tags (e.g. `de-DE`) are accepted.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split('-').next() {
+            Some("en") => Ok(Self::English),
+            Some("de") => Ok(Self::German),
+            Some("fr") => Ok(Self::French),
+            Some("es") => Ok(Self::Spanish),
+
+            _ => Err(()),
+        }
+    }
+}
+
+impl Language {
+    fn strings(&self) -> &'static Strings {
+        match self {
+            Self::English => &ENGLISH,
+            Self::German => &GERMAN,
+            Self::French => &FRENCH,
+            Self::Spanish => &SPANISH,
+        }
+    }
+}
+
+/// All of the user facing strings of the menu. The `{}` placeholders are substituted
+/// using [`format`].
+pub struct Strings {
+    pub select_entry: &'static str,
+    pub autoboot: &'static str,
+    pub memory_map_hint: &'static str,
+    pub help_hint: &'static str,
+    pub log_level: &'static str,
+
+    pub keybindings: &'static str,
+    pub help_return: &'static str,
+    /// The menu keybindings listed by the help screen, along with their descriptions.
+    pub help: &'static [(&'static str, &'static str)],
+
+    pub memory_map_header: &'static str,
+    pub memory_map_footer: &'static str,
+    pub memory_map_totals: &'static str,
+
+    pub pci_header: &'static str,
+    pub cpu_header: &'static str,
+
+    pub memtest_header: &'static str,
+    pub memtest_summary: &'static str,
+    pub memtest_aborted: &'static str,
+    pub memtest_return: &'static str,
+
+    pub config_not_found: &'static str,
+    pub config_consult: &'static str,
+    pub config_editor: &'static str,
+
+    pub boot_failed: &'static str,
+    pub firmware_fallback: &'static str,
+    pub hibernated: &'static str,
+}
+
+const ENGLISH: Strings = Strings {
+    select_entry: "Select entry:",
+    autoboot: "Booting automatically in {}, press any key to stop the countdown...",
+    memory_map_hint: "Press 'm' (or click here) to view the memory map.",
+    help_hint: "Press F1 for help.",
+    log_level: "Log level: {} (press 'v' to change)",
+
+    keybindings: "Keybindings:",
+    help_return: "Press F1 or ESC to return...",
+    help: &[
+        ("Up/Down", "Move the selection"),
+        ("Enter", "Boot the selected entry"),
+        ("Click", "Select an entry, click again to boot it"),
+        ("m", "View the memory map"),
+        ("t", "Run the memory test"),
+        ("p", "View the PCI devices"),
+        ("c", "View the processor features"),
+        ("v", "Change the log level"),
+        ("k", "Change the keyboard layout"),
+        ("r", "Change the screen resolution"),
+        (":", "Open the debug console"),
+        ("F1", "Show or hide this help screen"),
+        ("F12", "Save a screenshot to the boot partition"),
+    ],
+
+    memory_map_header: "Memory map: {} entries, {} total, {} usable",
+    memory_map_footer: "Page {}/{}. Use the arrow keys to scroll, press ESC to return...",
+    memory_map_totals: "Totals:",
+
+    pci_header: "PCI devices: {} functions",
+    cpu_header: "Processor:",
+
+    memtest_header: "Testing the usable memory, press ESC to abort. Patterns:",
+    memtest_summary: "Tested {} MiB, {} failing words.",
+    memtest_aborted: "The memory test has been aborted.",
+    memtest_return: "Press ESC to return...",
+
+    config_not_found: "Configuration file not found.",
+    config_consult: "For information on the format of Ion config entries, consult CONFIG.md in\nthe root of the Ion source repository.",
+    config_editor: "Press a key to enter an editor session and manually define a config entry...",
+
+    boot_failed: "Failed to boot the selected entry. Press any key to return to the menu...",
+    firmware_fallback: "None of the entries could be booted. Press 'f' to start the next boot option of the firmware.",
+    hibernated: "{} has been hibernated. Booting another entry might corrupt its file systems.\nPress any key to continue...",
+};
+
+const GERMAN: Strings = Strings {
+    select_entry: "Eintrag auswählen:",
+    autoboot: "Automatischer Start in {}, beliebige Taste drücken, um den Countdown anzuhalten...",
+    memory_map_hint: "'m' drücken (oder hier klicken), um die Speicherbelegung anzuzeigen.",
+    help_hint: "F1 drücken für Hilfe.",
+    log_level: "Log-Level: {} ('v' drücken zum Ändern)",
+
+    keybindings: "Tastenbelegung:",
+    help_return: "F1 oder ESC drücken, um zurückzukehren...",
+    help: &[
+        ("Up/Down", "Auswahl bewegen"),
+        ("Enter", "Ausgewählten Eintrag starten"),
+        ("Click", "Eintrag auswählen, erneut klicken, um ihn zu starten"),
+        ("m", "Speicherbelegung anzeigen"),
+        ("t", "Speichertest ausführen"),
+        ("p", "PCI-Geräte anzeigen"),
+        ("c", "Prozessorfunktionen anzeigen"),
+        ("v", "Log-Level ändern"),
+        ("k", "Tastaturbelegung ändern"),
+        ("r", "Bildschirmauflösung ändern"),
+        (":", "Debug-Konsole öffnen"),
+        ("F1", "Diese Hilfe ein- oder ausblenden"),
+        ("F12", "Bildschirmfoto auf der Boot-Partition speichern"),
+    ],
+
+    memory_map_header: "Speicherbelegung: {} Einträge, {} gesamt, {} nutzbar",
+    memory_map_footer: "Seite {}/{}. Mit den Pfeiltasten blättern, ESC drücken, um zurückzukehren...",
+    memory_map_totals: "Summen:",
+
+    pci_header: "PCI-Geräte: {} Funktionen",
+    cpu_header: "Prozessor:",
+
+    memtest_header: "Der nutzbare Speicher wird getestet, ESC drücken zum Abbrechen. Muster:",
+    memtest_summary: "{} MiB getestet, {} fehlerhafte Wörter.",
+    memtest_aborted: "Der Speichertest wurde abgebrochen.",
+    memtest_return: "ESC drücken, um zurückzukehren...",
+
+    config_not_found: "Konfigurationsdatei nicht gefunden.",
+    config_consult: "Informationen zum Format der Ion-Konfiguration finden Sie in CONFIG.md im\nHauptverzeichnis des Ion-Quellcodes.",
+    config_editor: "Beliebige Taste drücken, um einen Konfigurationseintrag manuell anzulegen...",
+
+    boot_failed: "Der Eintrag konnte nicht gestartet werden. Beliebige Taste drücken, um zum Menü zurückzukehren...",
+    firmware_fallback: "Keiner der Einträge konnte gestartet werden. 'f' drücken, um die nächste Bootoption der Firmware zu starten.",
+    hibernated: "{} befindet sich im Ruhezustand. Das Starten eines anderen Eintrags kann seine Dateisysteme beschädigen.\nBeliebige Taste drücken, um fortzufahren...",
+};
+
+const FRENCH: Strings = Strings {
+    select_entry: "Sélectionnez une entrée :",
+    autoboot: "Démarrage automatique dans {}, appuyez sur une touche pour arrêter le compte à rebours...",
+    memory_map_hint: "Appuyez sur 'm' (ou cliquez ici) pour afficher la carte mémoire.",
+    help_hint: "Appuyez sur F1 pour l'aide.",
+    log_level: "Niveau de journalisation : {} (appuyez sur 'v' pour changer)",
+
+    keybindings: "Raccourcis clavier :",
+    help_return: "Appuyez sur F1 ou ÉCHAP pour revenir...",
+    help: &[
+        ("Up/Down", "Déplacer la sélection"),
+        ("Enter", "Démarrer l'entrée sélectionnée"),
+        ("Click", "Sélectionner une entrée, cliquer à nouveau pour la démarrer"),
+        ("m", "Afficher la carte mémoire"),
+        ("t", "Lancer le test de la mémoire"),
+        ("p", "Afficher les périphériques PCI"),
+        ("c", "Afficher les fonctionnalités du processeur"),
+        ("v", "Changer le niveau de journalisation"),
+        ("k", "Changer la disposition du clavier"),
+        ("r", "Changer la résolution de l'écran"),
+        (":", "Ouvrir la console de débogage"),
+        ("F1", "Afficher ou masquer cette aide"),
+        ("F12", "Enregistrer une capture d'écran sur la partition de démarrage"),
+    ],
+
+    memory_map_header: "Carte mémoire : {} entrées, {} au total, {} utilisables",
+    memory_map_footer: "Page {}/{}. Utilisez les flèches pour défiler, ÉCHAP pour revenir...",
+    memory_map_totals: "Totaux :",
+
+    pci_header: "Périphériques PCI : {} fonctions",
+    cpu_header: "Processeur :",
+
+    memtest_header: "Test de la mémoire utilisable, appuyez sur ÉCHAP pour interrompre. Motifs :",
+    memtest_summary: "{} Mio testés, {} mots défaillants.",
+    memtest_aborted: "Le test de la mémoire a été interrompu.",
+    memtest_return: "Appuyez sur ÉCHAP pour revenir...",
+
+    config_not_found: "Fichier de configuration introuvable.",
+    config_consult: "Pour le format des entrées de configuration d'Ion, consultez CONFIG.md à\nla racine du dépôt des sources d'Ion.",
+    config_editor: "Appuyez sur une touche pour définir manuellement une entrée de configuration...",
+
+    boot_failed: "Impossible de démarrer l'entrée. Appuyez sur une touche pour revenir au menu...",
+    firmware_fallback: "Aucune entrée n'a pu être démarrée. Appuyez sur 'f' pour lancer l'option de démarrage suivante du firmware.",
+    hibernated: "{} est en veille prolongée. Démarrer une autre entrée peut corrompre ses systèmes de fichiers.\nAppuyez sur une touche pour continuer...",
+};
+
+const SPANISH: Strings = Strings {
+    select_entry: "Seleccione una entrada:",
+    autoboot: "Arrancando automáticamente en {}, pulse cualquier tecla para detener la cuenta atrás...",
+    memory_map_hint: "Pulse 'm' (o haga clic aquí) para ver el mapa de memoria.",
+    help_hint: "Pulse F1 para obtener ayuda.",
+    log_level: "Nivel de registro: {} (pulse 'v' para cambiar)",
+
+    keybindings: "Atajos de teclado:",
+    help_return: "Pulse F1 o ESC para volver...",
+    help: &[
+        ("Up/Down", "Mover la selección"),
+        ("Enter", "Arrancar la entrada seleccionada"),
+        ("Click", "Seleccionar una entrada, clic de nuevo para arrancarla"),
+        ("m", "Ver el mapa de memoria"),
+        ("t", "Ejecutar la prueba de memoria"),
+        ("p", "Ver los dispositivos PCI"),
+        ("c", "Ver las características del procesador"),
+        ("v", "Cambiar el nivel de registro"),
+        ("k", "Cambiar la distribución del teclado"),
+        ("r", "Cambiar la resolución de la pantalla"),
+        (":", "Abrir la consola de depuración"),
+        ("F1", "Mostrar u ocultar esta ayuda"),
+        ("F12", "Guardar una captura de pantalla en la partición de arranque"),
+    ],
+
+    memory_map_header: "Mapa de memoria: {} entradas, {} en total, {} utilizables",
+    memory_map_footer: "Página {}/{}. Use las flechas para desplazarse, ESC para volver...",
+    memory_map_totals: "Totales:",
+
+    pci_header: "Dispositivos PCI: {} funciones",
+    cpu_header: "Procesador:",
+
+    memtest_header: "Probando la memoria utilizable, pulse ESC para cancelar. Patrones:",
+    memtest_summary: "{} MiB probados, {} palabras defectuosas.",
+    memtest_aborted: "La prueba de memoria ha sido cancelada.",
+    memtest_return: "Pulse ESC para volver...",
+
+    config_not_found: "No se encontró el archivo de configuración.",
+    config_consult: "Para información sobre el formato de las entradas de Ion, consulte CONFIG.md\nen la raíz del repositorio de código fuente de Ion.",
+    config_editor: "Pulse una tecla para definir manualmente una entrada de configuración...",
+
+    boot_failed: "No se pudo arrancar la entrada. Pulse una tecla para volver al menú...",
+    firmware_fallback: "No se pudo arrancar ninguna entrada. Pulse 'f' para iniciar la siguiente opción de arranque del firmware.",
+    hibernated: "{} está hibernado. Arrancar otra entrada puede dañar sus sistemas de archivos.\nPulse una tecla para continuar...",
+};
+
+/// The language that is currently used for all of the menu strings.
+static ACTIVE_LANGUAGE: SpinMutex<Language> = SpinMutex::new(Language::English);
+
+/// Sets the language that is used for all of the subsequent menu strings.
+pub fn set(language: Language) {
+    *ACTIVE_LANGUAGE.lock() = language;
+}
+
+/// Returns the strings of the active language.
+pub fn strings() -> &'static Strings {
+    ACTIVE_LANGUAGE.lock().strings()
+}
+
+/// Substitutes each `{}` placeholder in the provided `template` with the respective
+/// argument.
+pub fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+
+    if let Some(first) = parts.next() {
+        result.push_str(first);
+    }
+
+    for part in parts {
+        if let Some(arg) = args.next() {
+            let _ = write!(result, "{}", arg);
+        }
+
+        result.push_str(part);
+    }
+
+    result
+}
+
+/// Returns the language specified by the firmware's `PlatformLang` variable, if it
+/// is set to a language that Ion has been translated to.
+pub fn platform_language(system_table: &SystemTable<Boot>) -> Option<Language> {
+    let mut value = [0u8; 32];
+
+    // The variable is a null terminated ASCII string.
+    let value = nvram::read_str(
+        system_table,
+        &nvram::GLOBAL_VENDOR,
+        "PlatformLang",
+        &mut value,
+    )
+    .ok()?;
+
+    value.parse().ok()
+}
@@ -0,0 +1,320 @@
+// The keyboard input of the boot menu and the consoles. The Simple Text Input Ex protocol
+// is used if the firmware provides it, as only it reports the state of the modifier keys.
+// Held keys are repeated at a consistent rate, independent of the firmware. Keys are also
+// read from the serial console, which keeps working after the boot services have been
+// exited.
+
+use core::convert::TryFrom;
+
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+use uefi::proto::console::text::{Input, Key, ScanCode};
+use uefi::table::boot::{Event, EventType, TimerTrigger, Tpl};
+use uefi::{unsafe_guid, Char16, Protocol};
+
+use ion_core::input::{KeyRepeat, Modifiers};
+
+use crate::efi;
+use crate::keymap;
+use crate::serial;
+use crate::time;
+
+/// The state of the modifier and toggle keys reported along with a key.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct KeyState {
+    shift_state: u32,
+    toggle_state: u8,
+}
+
+/// A key reported by the Simple Text Input Ex protocol.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct KeyData {
+    scan_code: u16,
+    unicode_char: u16,
+    key_state: KeyState,
+}
+
+impl KeyData {
+    /// Returns the ID of the key, which tells repeats of the key apart from other keys.
+    fn id(&self) -> u32 {
+        (self.scan_code as u32) << 16 | self.unicode_char as u32
+    }
+
+    /// Returns the key in the selected keymap. Returns [`None`] for the reports of the
+    /// modifier keys themselves.
+    fn key(&self) -> Option<Key> {
+        if self.scan_code != 0 {
+            return Some(Key::Special(ScanCode(self.scan_code)));
+        }
+
+        if self.unicode_char == 0 {
+            return None;
+        }
+
+        let c = Char16::try_from(self.unicode_char).ok()?;
+        Some(keymap::translate(Key::Printable(c)))
+    }
+}
+
+/// The UEFI Simple Text Input Ex protocol. Only the members that Ion calls are typed.
+#[repr(C)]
+#[unsafe_guid("dd9e7534-7762-4698-8c14-f58517a625aa")]
+#[derive(Protocol)]
+struct TextInputEx {
+    reset: usize,
+    read_key_stroke_ex:
+        extern "efiapi" fn(this: &mut TextInputEx, key_data: *mut KeyData) -> Status,
+    wait_for_key_ex: Event,
+    set_state: usize,
+    register_key_notify: usize,
+    unregister_key_notify: usize,
+}
+
+enum Device {
+    Extended(*mut TextInputEx),
+    Simple(*mut Input),
+}
+
+/// A key press read from the keyboard.
+#[derive(Debug)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub modifiers: Modifiers,
+    /// Whether the key is held down, which makes this press one of its repeats.
+    pub repeat: bool,
+}
+
+struct Keyboard {
+    device: Device,
+    repeat: KeyRepeat,
+    /// The key whose synthesized repeats have not been read yet, along with the amount of
+    /// remaining repeats.
+    pending: Option<(KeyData, usize)>,
+    /// The modifiers that were held down during the last key press.
+    modifiers: Modifiers,
+}
+
+// SAFETY: Ion only runs on the bootstrap processor so the raw protocol pointers are never
+// shared between threads.
+unsafe impl Send for Keyboard {}
+
+static KEYBOARD: SpinMutex<Option<Keyboard>> = SpinMutex::new(None);
+
+impl Keyboard {
+    /// Reads the next key from the firmware. Returns [`None`] if no key has been pressed.
+    fn read_firmware_key(&mut self) -> Option<KeyData> {
+        // SAFETY: The protocol pointers are valid as long as the boot services are active.
+        unsafe {
+            match self.device {
+                Device::Extended(extended) => {
+                    let extended = &mut *extended;
+                    let mut data = KeyData::default();
+
+                    if (extended.read_key_stroke_ex)(extended, &mut data).is_success() {
+                        Some(data)
+                    } else {
+                        None
+                    }
+                }
+
+                Device::Simple(simple) => {
+                    let key = (*simple).read_key().ok()?.unwrap()?;
+
+                    let (scan_code, unicode_char) = match key {
+                        Key::Special(scan_code) => (scan_code.0, 0),
+                        Key::Printable(c) => (0, u16::from(c)),
+                    };
+
+                    Some(KeyData {
+                        scan_code,
+                        unicode_char,
+                        key_state: KeyState::default(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn read_key(&mut self) -> Option<KeyEvent> {
+        let (data, repeat) = match self.pending.take() {
+            Some((data, remaining)) => {
+                if remaining > 1 {
+                    self.pending = Some((data, remaining - 1));
+                }
+
+                (data, true)
+            }
+
+            None => loop {
+                let data = self.read_firmware_key()?;
+
+                // Skip the reports of the modifier keys themselves.
+                if data.key().is_none() {
+                    continue;
+                }
+
+                let (presses, held) = self.repeat.report(data.id(), time::timestamp_ms());
+
+                if presses > 1 {
+                    self.pending = Some((data, presses - 1));
+                }
+
+                break (data, held);
+            },
+        };
+
+        self.modifiers = Modifiers::from_shift_state(data.key_state.shift_state);
+
+        Some(KeyEvent {
+            key: data.key()?,
+            modifiers: self.modifiers,
+            repeat,
+        })
+    }
+}
+
+/// This function is responsible for locating the keyboard. The Simple Text Input Ex
+/// protocol is preferred over the Simple Text Input protocol, which does not report the
+/// state of the modifier keys.
+pub fn init(system_table: &SystemTable<Boot>) {
+    let boot_services = system_table.boot_services();
+
+    let device = if let Ok(extended) = boot_services.locate_protocol::<TextInputEx>() {
+        Device::Extended(extended.unwrap().get())
+    } else if let Ok(simple) = boot_services.locate_protocol::<Input>() {
+        Device::Simple(simple.unwrap().get())
+    } else {
+        log::warn!("input: no keyboard found");
+        return;
+    };
+
+    *KEYBOARD.lock() = Some(Keyboard {
+        device,
+        repeat: KeyRepeat::new(),
+        pending: None,
+        modifiers: Modifiers::default(),
+    });
+}
+
+/// Returns the event that is signaled when a key is pressed, or [`None`] if there is no
+/// keyboard.
+pub fn wait_for_key_event() -> Option<Event> {
+    let keyboard = KEYBOARD.lock();
+
+    // SAFETY: The protocol pointers are valid as long as the boot services are active.
+    keyboard.as_ref().map(|keyboard| unsafe {
+        match keyboard.device {
+            Device::Extended(extended) => (*extended).wait_for_key_ex,
+            Device::Simple(simple) => (*simple).wait_for_key_event(),
+        }
+    })
+}
+
+/// Reads the next key press from the keyboard, including the synthesized repeats of held
+/// keys. Returns [`None`] if no key has been pressed.
+pub fn read_key() -> Option<KeyEvent> {
+    // The debug console might read keys after a panic while reading the keyboard.
+    KEYBOARD.try_lock()?.as_mut()?.read_key()
+}
+
+/// Returns the modifiers that were held down during the last key press.
+pub fn modifiers() -> Modifiers {
+    KEYBOARD
+        .lock()
+        .as_ref()
+        .map_or(Modifiers::default(), |keyboard| keyboard.modifiers)
+}
+
+/// Stops reading the keyboard, as the protocols of the firmware are not available after we
+/// have exited the boot services. Must be called before exiting the boot services.
+pub fn exit_boot_services() {
+    *KEYBOARD.lock() = None;
+}
+
+/// Input received by [`wait_timeout`].
+pub enum InputEvent {
+    /// A key was pressed on the keyboard or the serial console.
+    Key(Key),
+    /// The additional event passed to [`wait_timeout`] was signaled.
+    Other,
+    /// The timeout passed to [`wait_timeout`] expired without any input.
+    Timeout,
+}
+
+/// Returns the next key pressed on the serial console or the keyboard, if any. This
+/// function does not block. After the boot services have been exited only the serial
+/// console is read.
+pub fn poll() -> Option<Key> {
+    // Serial terminals send the characters of the user's own keyboard layout, so the key
+    // does not need to be translated.
+    serial::read_key().or_else(|| read_key().map(|event| event.key))
+}
+
+/// This function is responsible for waiting for a key press or for the provided additional
+/// event (e.g. pointer input) to be signaled, whichever comes first. Waiting is given up
+/// once the provided timeout in milliseconds (if any) expires. The `idle` function is
+/// called every time the function wakes up without any input.
+pub fn wait_timeout(
+    system_table: &SystemTable<Boot>,
+    other: Option<Event>,
+    timeout: Option<u64>,
+    mut idle: impl FnMut(),
+) -> InputEvent {
+    let boot_services = system_table.boot_services();
+    let start = time::timestamp_ms();
+
+    // The serial console does not signal an event when input is available and the elapsed
+    // time is measured with the counter of the processor, so we periodically wake up every
+    // 10 milliseconds to poll both.
+    //
+    // SAFETY: The event does not have a notification function.
+    let poll_event = unsafe { boot_services.create_event(EventType::TIMER, Tpl::CALLBACK, None) }
+        .expect_success("input: failed to create the poll event");
+
+    boot_services
+        .set_timer(poll_event, TimerTrigger::Periodic(100000))
+        .expect_success("input: failed to start the poll timer");
+
+    // Without a keyboard we only wait for the serial console.
+    let wait_for_key_event = wait_for_key_event().unwrap_or(poll_event);
+    let mut events = [poll_event, wait_for_key_event, other.unwrap_or(poll_event)];
+    let count = if other.is_some() { 3 } else { 2 };
+
+    let input = loop {
+        // Keys that are already waiting (e.g. synthesized repeats) are returned right away.
+        if let Some(key) = poll() {
+            break InputEvent::Key(key);
+        }
+
+        if timeout.map_or(false, |timeout| time::elapsed_ms(start) >= timeout) {
+            break InputEvent::Timeout;
+        }
+
+        let index = boot_services
+            .wait_for_event(&mut events[..count])
+            .expect_success("input: failed to wait for input");
+
+        if index == 2 {
+            break InputEvent::Other;
+        }
+
+        idle();
+    };
+
+    // Closing the event also cancels the timer. The menu calls this function in a loop, so
+    // the event must not be leaked.
+    efi::close_event(boot_services, poll_event);
+
+    input
+}
+
+/// This function is responsible for waiting for a key press and returns the pressed key.
+pub fn wait(system_table: &SystemTable<Boot>) -> Key {
+    loop {
+        if let InputEvent::Key(key) = wait_timeout(system_table, None, None, || ()) {
+            return key;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use core::convert::TryFrom;
+use core::str::FromStr;
+
+use spin::mutex::SpinMutex;
+use uefi::proto::console::text::Key;
+use uefi::Char16;
+
+/// Keyboard layouts that Ion can translate the firmware input into.
+///
+/// UEFI firmware reports printable keys as if a US QWERTY keyboard was attached, so
+/// we translate the reported character into the character that is printed on the same
+/// physical key in the selected layout. Only mappings that result in a character that
+/// is covered by the built-in font are translated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keymap {
+    Qwerty,
+    Qwertz,
+    Azerty,
+    Dvorak,
+}
+
+impl FromStr for Keymap {
+    type Err = ();
+
+    /// Parses the value of the `KEYMAP=` config key.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "us" | "qwerty" => Ok(Self::Qwerty),
+            "de" | "qwertz" => Ok(Self::Qwertz),
+            "fr" | "azerty" => Ok(Self::Azerty),
+            "dvorak" => Ok(Self::Dvorak),
+
+            _ => Err(()),
+        }
+    }
+}
+
+impl Keymap {
+    /// Returns the translation table for the keymap. Each pair maps the character
+    /// reported by the firmware to the character of the keymap.
+    fn table(&self) -> &'static [(char, char)] {
+        match self {
+            Self::Qwerty => &[],
+            Self::Qwertz => QWERTZ,
+            Self::Azerty => AZERTY,
+            Self::Dvorak => DVORAK,
+        }
+    }
+
+    /// Returns the value of the `KEYMAP=` config key that selects the keymap.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Qwerty => "qwerty",
+            Self::Qwertz => "qwertz",
+            Self::Azerty => "azerty",
+            Self::Dvorak => "dvorak",
+        }
+    }
+
+    /// Returns the next keymap in the cycle toggled by the `k` key of the menu.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Qwerty => Self::Qwertz,
+            Self::Qwertz => Self::Azerty,
+            Self::Azerty => Self::Dvorak,
+            Self::Dvorak => Self::Qwerty,
+        }
+    }
+
+    /// Translates the provided US QWERTY character into the character of the keymap.
+    pub fn translate(&self, c: char) -> char {
+        self.table()
+            .iter()
+            .find(|(from, _)| *from == c)
+            .map_or(c, |(_, to)| *to)
+    }
+}
+
+#[rustfmt::skip]
+const QWERTZ: &[(char, char)] = &[
+    ('y', 'z'), ('z', 'y'), ('Y', 'Z'), ('Z', 'Y'),
+    ('@', '"'), ('^', '&'), ('&', '/'), ('*', '('), ('(', ')'), (')', '='),
+    ('_', '?'), ('+', '`'), ('`', '^'), (']', '+'), ('}', '*'),
+    ('\\', '#'), ('|', '\''), ('<', ';'), ('>', ':'), ('/', '-'), ('?', '_'),
+    ('[', 'ü'), ('{', 'Ü'), (';', 'ö'), (':', 'Ö'), ('\'', 'ä'), ('"', 'Ä'),
+    ('-', 'ß'), ('=', '´'), ('#', '§'), ('~', '°'),
+];
+
+#[rustfmt::skip]
+const AZERTY: &[(char, char)] = &[
+    ('a', 'q'), ('q', 'a'), ('A', 'Q'), ('Q', 'A'),
+    ('z', 'w'), ('w', 'z'), ('Z', 'W'), ('W', 'Z'),
+    (';', 'm'), (':', 'M'), ('m', ','), ('M', '?'),
+    ('1', '&'), ('2', 'é'), ('3', '"'), ('4', '\''), ('5', '('), ('6', '-'),
+    ('7', 'è'), ('8', '_'), ('9', 'ç'), ('0', 'à'),
+    ('!', '1'), ('@', '2'), ('#', '3'), ('$', '4'), ('%', '5'),
+    ('^', '6'), ('&', '7'), ('*', '8'), ('(', '9'), (')', '0'),
+    ('-', ')'), (',', ';'), ('<', '.'), ('.', ':'), ('>', '/'), ('/', '!'),
+    ('\'', 'ù'), ('"', '%'), ('[', '^'), ('{', '¨'), (']', '$'), ('}', '£'),
+    ('\\', '*'), ('|', 'µ'), ('`', '²'),
+];
+
+#[rustfmt::skip]
+const DVORAK: &[(char, char)] = &[
+    ('q', '\''), ('w', ','), ('e', '.'), ('r', 'p'), ('t', 'y'), ('y', 'f'),
+    ('u', 'g'), ('i', 'c'), ('o', 'r'), ('p', 'l'), ('[', '/'), (']', '='),
+    ('s', 'o'), ('d', 'e'), ('f', 'u'), ('g', 'i'), ('h', 'd'), ('j', 'h'),
+    ('k', 't'), ('l', 'n'), (';', 's'), ('\'', '-'),
+    ('z', ';'), ('x', 'q'), ('c', 'j'), ('v', 'k'), ('b', 'x'), ('n', 'b'),
+    (',', 'w'), ('.', 'v'), ('/', 'z'), ('-', '['), ('=', ']'),
+    ('Q', '"'), ('W', '<'), ('E', '>'), ('R', 'P'), ('T', 'Y'), ('Y', 'F'),
+    ('U', 'G'), ('I', 'C'), ('O', 'R'), ('P', 'L'), ('{', '?'), ('}', '+'),
+    ('S', 'O'), ('D', 'E'), ('F', 'U'), ('G', 'I'), ('H', 'D'), ('J', 'H'),
+    ('K', 'T'), ('L', 'N'), (':', 'S'), ('"', '_'),
+    ('Z', ':'), ('X', 'Q'), ('C', 'J'), ('V', 'K'), ('B', 'X'), ('N', 'B'),
+    ('<', 'W'), ('>', 'V'), ('?', 'Z'), ('_', '{'), ('+', '}'),
+];
+
+/// The keymap that is currently used to translate keyboard input.
+static ACTIVE_KEYMAP: SpinMutex<Keymap> = SpinMutex::new(Keymap::Qwerty);
+
+/// Sets the keymap that is used to translate all of the subsequent keyboard input.
+pub fn set(keymap: Keymap) {
+    *ACTIVE_KEYMAP.lock() = keymap;
+}
+
+/// Returns the keymap that is currently used to translate keyboard input.
+pub fn active() -> Keymap {
+    *ACTIVE_KEYMAP.lock()
+}
+
+/// Translates the provided key using the active keymap. Special keys are returned
+/// as is.
+pub fn translate(key: Key) -> Key {
+    match key {
+        Key::Printable(c) => {
+            let translated = ACTIVE_KEYMAP.lock().translate(c.into());
+
+            // All of the translation tables only contain characters that fit into UCS-2.
+            Key::Printable(Char16::try_from(translated).unwrap_or(c))
+        }
+
+        special => special,
+    }
+}
@@ -1,6 +1,9 @@
 use core::fmt;
 use core::fmt::Write;
 
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use font8x8::UnicodeFonts;
 
 use spin::mutex::SpinMutex;
@@ -8,6 +11,14 @@ use spin::Once;
 
 use bit_field::BitField;
 
+use uefi::proto::console::gop::{BltOp, BltPixel, BltRegion, GraphicsOutput};
+use uefi::proto::console::text;
+use uefi::proto::media::file::Directory;
+
+use crate::bmp;
+use crate::console::{self, ConsoleSink};
+use crate::font::PsfFont;
+
 /// Describes the layout and pixel format of a framebuffer.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -18,14 +29,32 @@ pub struct FrameBufferInfo {
     pub vertical_resolution: usize,
     /// The color format of each pixel.
     pub pixel_format: PixelFormat,
-    /// The number of bits per pixel.
+    /// The number of bits per pixel that are used to store the color components.
     pub bits_per_pixel: usize,
+    /// The number of bytes per pixel in the framebuffer.
+    pub bytes_per_pixel: usize,
     /// Number of pixels between the start of a line and the start of the next.
     ///
     /// Some framebuffers use additional padding at the end of a line, so this
     /// value might be larger than `horizontal_resolution`. It is
     /// therefore recommended to use this field for calculating the start address of a line.
     pub stride: usize,
+    /// Number of bytes between the start of a line and the start of the next.
+    pub pitch: usize,
+}
+
+impl FrameBufferInfo {
+    /// Returns the size of the framebuffer in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.pitch * self.vertical_resolution
+    }
+
+    /// Returns the byte offset of the provided pixel in the framebuffer.
+    #[inline]
+    fn byte_offset(&self, x: usize, y: usize) -> usize {
+        y * self.pitch + x * self.bytes_per_pixel
+    }
 }
 
 /// Color format of pixels in the framebuffer.
@@ -43,34 +72,142 @@ pub enum PixelFormat {
     /// Length might be larger than 3, check [`bytes_per_pixel`][FrameBufferInfo::bytes_per_pixel]
     /// for this.
     BGR,
+    /// The position of each color component is described by a bitmask.
+    Bitmask { red: u32, green: u32, blue: u32 },
+}
+
+impl PixelFormat {
+    /// Returns the masks of the red, green and blue color components of a pixel.
+    pub fn masks(&self) -> (u32, u32, u32) {
+        match *self {
+            Self::RGB => (0x0000ff, 0x00ff00, 0xff0000),
+            Self::BGR => (0xff0000, 0x00ff00, 0x0000ff),
+            Self::Bitmask { red, green, blue } => (red, green, blue),
+        }
+    }
+
+    /// Converts the provided color to the raw value of a pixel in this format.
+    fn pixel_value(&self, color: Color) -> u32 {
+        let red = color.0.get_bits(16..24);
+        let green = color.0.get_bits(8..16);
+        let blue = color.0.get_bits(0..8);
+
+        match *self {
+            Self::RGB => red | (green << 8) | (blue << 16),
+            Self::BGR => blue | (green << 8) | (red << 16),
+            Self::Bitmask {
+                red: red_mask,
+                green: green_mask,
+                blue: blue_mask,
+            } => {
+                apply_mask(red, red_mask)
+                    | apply_mask(green, green_mask)
+                    | apply_mask(blue, blue_mask)
+            }
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Converts the provided raw value of a pixel in this format back to a color.
+    fn color(&self, value: u32) -> Color {
+        let (red, green, blue) = self.masks();
+
+        Color::new(
+            (extract_mask(value, red) << 16)
+                | (extract_mask(value, green) << 8)
+                | extract_mask(value, blue),
+        )
+    }
+}
+
+/// Extracts the color component described by the mask from the provided pixel value and
+/// scales it to 8 bits.
+fn extract_mask(value: u32, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let width = mask.count_ones();
+    let component = (value & mask) >> mask.trailing_zeros();
+
+    if width <= 8 {
+        component << (8 - width)
+    } else {
+        component >> (width - 8)
+    }
+}
+
+/// Scales the provided 8-bit color component to the width of the mask and moves it to
+/// the position of the mask.
+fn apply_mask(component: u32, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let width = mask.count_ones();
+    let scaled = if width <= 8 {
+        component >> (8 - width)
+    } else {
+        component << (width - 8)
+    };
+
+    (scaled << mask.trailing_zeros()) & mask
+}
+
+/// The destination that the backbuffer is copied to on flush.
+pub enum Output {
+    /// The framebuffer is directly accessible.
+    Linear(&'static mut [u8]),
+    /// The firmware does not expose the framebuffer (`PixelBltOnly`), so the backbuffer
+    /// is copied using the Blt function of the GOP. Only usable while the boot services
+    /// are active.
+    Blt(*mut GraphicsOutput<'static>),
+    /// There is no graphics output (e.g. on server boards without video), so the text is
+    /// written to the UEFI Simple Text Output console instead. The framebuffer info
+    /// describes a virtual screen of 8x16 character cells. Only usable while the boot
+    /// services are active.
+    Text(*mut text::Output<'static>),
+    /// Nothing is displayed on the screen.
+    None,
 }
 
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Color(u32);
 
 impl Color {
     #[inline]
-    pub fn new(hex: u32) -> Self {
+    pub const fn new(hex: u32) -> Self {
         Self(hex)
     }
+
+    /// Returns the color as `0xRRGGBB`.
+    #[inline]
+    pub const fn as_u32(&self) -> u32 {
+        self.0
+    }
 }
 
 /// The global logger instance used for the `log` crate.
 pub static LOGGER: Once<LockedLogger> = Once::new();
 
+/// The maximum level of the log records that are displayed on the screen. It is kept
+/// outside of the [`Logger`], so that it survives re-initializing the framebuffer.
+static LEVEL: SpinMutex<log::LevelFilter> = SpinMutex::new(log::LevelFilter::Trace);
+
+/// The font scale that has been configured, if any. Like [`LEVEL`], it is kept outside of
+/// the [`Logger`], so that it survives re-initializing the framebuffer.
+static FONT_SCALE: SpinMutex<Option<usize>> = SpinMutex::new(None);
+
 /// A [`Logger`] instance protected by a spinlock.
 pub struct LockedLogger(SpinMutex<Logger>);
 
 impl LockedLogger {
     /// Create a new instance that logs to the given framebuffer.
     #[inline]
-    pub fn new(
-        framebuffer: &'static mut [u8],
-        backbuffer: &'static mut [u8],
-        info: FrameBufferInfo,
-    ) -> Self {
-        Self(SpinMutex::new(Logger::new(framebuffer, backbuffer, info)))
+    pub fn new(output: Output, backbuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        Self(SpinMutex::new(Logger::new(output, backbuffer, info)))
     }
 
     /// Force-unlocks the logger to prevent a deadlock.
@@ -82,24 +219,137 @@ impl LockedLogger {
     }
 }
 
-impl log::Log for LockedLogger {
-    #[inline]
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+impl ConsoleSink for LockedLogger {
+    fn write_str(&self, s: &str) {
+        let _ = self.0.lock().write_str(s);
     }
 
-    #[inline]
-    fn log(&self, record: &log::Record) {
+    fn level(&self) -> log::LevelFilter {
+        level()
+    }
+
+    fn write_record(&self, record: &str) {
         let mut logger = self.0.lock();
-        writeln!(logger, "{}:    {}", record.level(), record.args()).unwrap();
+
+        if !logger.quiet {
+            let _ = logger.write_str(record);
+        }
     }
 
-    #[inline]
-    fn flush(&self) {}
+    fn clear(&self) {
+        self.0.lock().clear();
+    }
+
+    fn set_cursor_pos(&self, column: usize, row: usize) {
+        let mut logger = self.0.lock();
+        let (x, y) = (column * logger.char_width(), row * logger.line_height());
+
+        logger.set_cursor_pos(x, y);
+    }
+
+    fn clear_line(&self, row: usize) {
+        self.0.lock().clear_line(row);
+    }
+
+    fn write_status(&self, row: usize, text: &str) {
+        self.0.lock().write_status(row, text);
+    }
+
+    fn set_fg(&self, color: Color) {
+        self.0.lock().set_fg(color);
+    }
+
+    fn flush(&self) {
+        self.0.lock().flush();
+    }
+}
+
+/// The size of the in-memory boot log in bytes.
+const BOOT_LOG_SIZE: usize = 64 * 1024;
+
+/// The path of the boot log on the boot partition.
+const BOOT_LOG_PATH: &str = "ion-boot.log";
+
+/// An in-memory copy of all of the log records. The boot log is written to the boot
+/// partition before we exit the boot services, so that failed boots on headless machines
+/// still leave a trail behind, and is passed to the kernel.
+///
+/// The boot log is a ring buffer, so once it is full the oldest records are overwritten.
+/// Since the buffer is part of Ion's image it is physically contiguous.
+pub struct BootLog {
+    buffer: [u8; BOOT_LOG_SIZE],
+    /// The offset at which the next byte is written.
+    head: usize,
+    /// Whether the ring buffer has wrapped around at least once.
+    wrapped: bool,
+}
+
+impl BootLog {
+    /// Returns the contents of the boot log in chronological order, split in two parts
+    /// as the ring buffer might have wrapped around.
+    pub fn contents(&self) -> (&[u8], &[u8]) {
+        if self.wrapped {
+            (&self.buffer[self.head..], &self.buffer[..self.head])
+        } else {
+            (&self.buffer[..self.head], &[])
+        }
+    }
+
+    /// Returns the physical address of the ring buffer.
+    pub fn address(&self) -> u64 {
+        // NOTE: UEFI identity-maps all memory, so the virtual address is the physical one.
+        self.buffer.as_ptr() as u64
+    }
+
+    /// Returns the capacity of the ring buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        BOOT_LOG_SIZE
+    }
+
+    /// Returns the offset at which the next byte is written.
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// Returns true if the ring buffer has wrapped around at least once.
+    pub fn wrapped(&self) -> bool {
+        self.wrapped
+    }
+}
+
+impl fmt::Write for BootLog {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.buffer[self.head] = byte;
+            self.head = (self.head + 1) % BOOT_LOG_SIZE;
+
+            if self.head == 0 {
+                self.wrapped = true;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ConsoleSink for SpinMutex<BootLog> {
+    /// Only the log records are kept in the boot log.
+    fn write_str(&self, _s: &str) {}
+
+    fn write_record(&self, record: &str) {
+        let _ = fmt::Write::write_str(&mut *self.lock(), record);
+    }
 }
 
+/// The global boot log instance.
+pub static BOOT_LOG: SpinMutex<BootLog> = SpinMutex::new(BootLog {
+    buffer: [0; BOOT_LOG_SIZE],
+    head: 0,
+    wrapped: false,
+});
+
 struct Logger {
-    framebuffer: &'static mut [u8],
+    output: Output,
     backbuffer: &'static mut [u8],
 
     info: FrameBufferInfo,
@@ -107,21 +357,67 @@ struct Logger {
     x_pos: usize,
     y_pos: usize,
 
-    scroll_lock: bool,
-
     fg: Color,
     bg: Color,
+
+    /// The position of the mouse pointer, if it has been drawn.
+    pointer: Option<(usize, usize)>,
+
+    /// The region of the backbuffer that has been modified since the last flush.
+    dirty: Option<DirtyRect>,
+
+    /// The font loaded from disk. If not set, the built-in 8x8 font is used.
+    font: Option<PsfFont>,
+    /// The integer factor by which each glyph is scaled up.
+    scale: usize,
+
+    /// Whether the log records are hidden (e.g. while the splash screen is shown).
+    quiet: bool,
+
+    /// The amount of text rows at the bottom of the screen that are reserved for status
+    /// lines (e.g. the countdown of the menu), which the log does not scroll.
+    status_rows: usize,
 }
 
+// SAFETY: Ion only runs on the bootstrap processor so the raw GOP pointer is never
+// shared between threads.
+unsafe impl Send for Logger {}
+
+/// The amount of text lines that we aim for when choosing the font scale automatically.
+const TARGET_TEXT_LINES: usize = 50;
+
+/// The least amount of text columns and rows that the font scale has to leave room for, as
+/// the menu would not fit and the log could not be scrolled otherwise.
+const MIN_TEXT_COLUMNS: usize = 40;
+const MIN_TEXT_ROWS: usize = 12;
+
+/// A rectangular region of the screen in pixels. The end coordinates are exclusive.
+#[derive(Debug, Clone, Copy)]
+struct DirtyRect {
+    start_x: usize,
+    start_y: usize,
+    end_x: usize,
+    end_y: usize,
+}
+
+/// The bitmap of the mouse pointer. Each byte represents a row where the least
+/// significant bit is the leftmost pixel.
+const POINTER_GLYPH: [u8; 8] = [
+    0b0000_0001,
+    0b0000_0011,
+    0b0000_0111,
+    0b0000_1111,
+    0b0001_1111,
+    0b0000_0111,
+    0b0000_1101,
+    0b0001_1000,
+];
+
 impl Logger {
     #[inline]
-    fn new(
-        framebuffer: &'static mut [u8],
-        backbuffer: &'static mut [u8],
-        info: FrameBufferInfo,
-    ) -> Self {
-        Self {
-            framebuffer,
+    fn new(output: Output, backbuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
+        let mut logger = Self {
+            output,
             backbuffer,
 
             info,
@@ -129,63 +425,324 @@ impl Logger {
             x_pos: 0x00,
             y_pos: 0x00,
 
-            scroll_lock: false,
             fg: Color::new(u32::MAX),
             bg: Color::new(u32::MIN),
+
+            pointer: None,
+            dirty: None,
+
+            font: None,
+            scale: 1,
+
+            quiet: false,
+
+            status_rows: 0,
+        };
+
+        logger.update_scale();
+        logger
+    }
+
+    /// Applies the configured font scale or, if none has been configured, scales up the
+    /// glyphs on high resolution displays, so that the text remains readable. The scale is
+    /// lowered if the screen would not fit [`MIN_TEXT_COLUMNS`] and [`MIN_TEXT_ROWS`].
+    fn update_scale(&mut self) {
+        // The text console cannot scale the glyphs.
+        if let Output::Text(_) = self.output {
+            self.scale = 1;
+            return;
         }
+
+        let (width, height) = self
+            .font
+            .as_ref()
+            .map_or((8, 16), |font| (font.width(), font.height()));
+
+        let scale = FONT_SCALE
+            .lock()
+            .unwrap_or_else(|| self.height() / (16 * TARGET_TEXT_LINES));
+        let max_scale = (self.width() / (width * MIN_TEXT_COLUMNS))
+            .min(self.height() / (height * MIN_TEXT_ROWS));
+
+        self.scale = scale.min(max_scale).max(1);
     }
 
     fn write_char(&mut self, c: char) {
+        if let Output::Text(_) = self.output {
+            self.write_text_char(c);
+            return;
+        }
+
         match c {
             '\n' => self.new_line(),
             '\r' => self.carriage_return(),
             _ => {
-                if self.x_pos >= self.width() {
+                if self.x_pos + self.char_width() > self.width() {
                     self.new_line();
                 }
 
-                if self.y_pos >= (self.height() - 16) {
-                    self.clear();
+                while self.y_pos >= self.log_height().saturating_sub(self.line_height()) {
+                    self.scroll();
                 }
 
-                let rendered = font8x8::BASIC_FONTS
-                    .get(c)
-                    .expect("Character not found in basic font");
-
-                self.write_rendered_char(rendered);
+                self.draw_char(c);
             }
         }
     }
 
+    /// Draws the provided character at the cursor position and advances the cursor,
+    /// without wrapping or scrolling.
+    fn draw_char(&mut self, c: char) {
+        if let Output::Text(_) = self.output {
+            self.write_text_char(c);
+            return;
+        }
+
+        if self.font.is_some() {
+            self.write_font_char(c);
+            return;
+        }
+
+        // Fall back to the Latin-1 supplement glyphs for the translated strings and render a
+        // question mark for any character that is not covered at all.
+        let rendered = font8x8::BASIC_FONTS
+            .get(c)
+            .or_else(|| font8x8::LATIN_FONTS.get(c))
+            .or_else(|| font8x8::BASIC_FONTS.get('?'))
+            .unwrap();
+
+        self.write_rendered_char(rendered);
+    }
+
     fn write_rendered_char(&mut self, rendered: [u8; 8]) {
         for (y, byte) in rendered.iter().enumerate() {
             for (x, bit) in (0..8).enumerate() {
                 let draw = *byte & (1 << bit) == 0;
+                self.write_glyph_pixel(x, y, if draw { self.bg } else { self.fg });
+            }
+        }
+
+        self.mark_dirty(self.x_pos, self.y_pos, 8 * self.scale, 8 * self.scale);
+        self.x_pos += self.char_width();
+    }
+
+    /// Writes the provided character to the text console and keeps track of the cursor
+    /// position in pixels, so that the rest of the logger does not have to care about the
+    /// type of the output.
+    fn write_text_char(&mut self, c: char) {
+        let stdout = match self.output {
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            Output::Text(stdout) => unsafe { &mut *stdout },
+            _ => return,
+        };
+
+        match c {
+            '\n' => {
+                let _ = stdout.write_char('\n');
+                self.new_line();
+
+                // The firmware scrolls the console by itself.
+                self.y_pos = self
+                    .y_pos
+                    .min(self.height().saturating_sub(self.line_height()));
+            }
+
+            '\r' => {
+                let _ = stdout.write_char('\r');
+                self.carriage_return();
+            }
+
+            _ => {
+                // Characters that the console cannot display are replaced by a question
+                // mark.
+                if stdout.write_char(c).is_err() {
+                    let _ = stdout.write_char('?');
+                }
+
+                self.x_pos += self.char_width();
+            }
+        }
+    }
+
+    /// Writes the pixel at the provided position of the glyph that is rendered at the
+    /// current cursor position, scaled up by the font scale.
+    fn write_glyph_pixel(&mut self, x: usize, y: usize, color: Color) {
+        for scaled_y in 0..self.scale {
+            for scaled_x in 0..self.scale {
                 self.write_pixel(
-                    self.x_pos + x,
-                    self.y_pos + y,
-                    if draw { self.bg } else { self.fg },
+                    self.x_pos + x * self.scale + scaled_x,
+                    self.y_pos + y * self.scale + scaled_y,
+                    color,
                 );
             }
         }
+    }
+
+    /// Renders the provided character using the font loaded from disk.
+    fn write_font_char(&mut self, c: char) {
+        let font = self.font.as_ref().unwrap();
+        let (width, height) = (font.width(), font.height());
+
+        // Render a question mark for any character that is not covered by the font.
+        let glyph = match font.glyph(c).or_else(|| font.glyph('?')) {
+            Some(glyph) => glyph,
+            None => {
+                self.x_pos += self.char_width();
+                return;
+            }
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let draw = self.font.as_ref().unwrap().pixel(glyph, x, y);
+                self.write_glyph_pixel(x, y, if draw { self.fg } else { self.bg });
+            }
+        }
+
+        self.mark_dirty(
+            self.x_pos,
+            self.y_pos,
+            width * self.scale,
+            height * self.scale,
+        );
+
+        self.x_pos += self.char_width();
+    }
+
+    /// Returns the width of a character cell in pixels.
+    #[inline]
+    fn char_width(&self) -> usize {
+        if let Output::Text(_) = self.output {
+            return 8;
+        }
+
+        self.font.as_ref().map_or(8, |font| font.width()) * self.scale
+    }
+
+    /// Returns the height of a text line in pixels.
+    #[inline]
+    fn line_height(&self) -> usize {
+        if let Output::Text(_) = self.output {
+            return 16;
+        }
 
-        self.x_pos += 8;
+        self.font.as_ref().map_or(16, |font| font.height()) * self.scale
     }
 
     fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
-        let pixel_offset = y * self.info.stride + x;
-        let color = [
-            (color.0.get_bits(0..8) & 255) as u8,
-            (color.0.get_bits(8..16) & 255) as u8,
-            (color.0.get_bits(16..24) & 255) as u8,
-            (color.0.get_bits(24..32) & 255) as u8,
-        ];
+        let color = self.info.pixel_format.pixel_value(color).to_le_bytes();
+
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let byte_offset = self.info.byte_offset(x, y);
+
+        self.backbuffer[byte_offset..(byte_offset + bytes_per_pixel)]
+            .copy_from_slice(&color[..bytes_per_pixel]);
+    }
+
+    /// Draws the mouse pointer directly into the framebuffer, so that moving the pointer
+    /// around does not require redrawing the whole screen. The area below the pointer is
+    /// restored from the backbuffer.
+    fn draw_pointer(&mut self, x: usize, y: usize) {
+        // The text console cannot display the pointer.
+        if let Output::Text(_) = self.output {
+            return;
+        }
+
+        // Restore the area below the previous position of the pointer.
+        if let Some((old_x, old_y)) = self.pointer.take() {
+            let end_x = (old_x + 8).min(self.width());
+            let end_y = (old_y + 8).min(self.height());
+
+            self.copy_to_output(old_x, old_y, end_x, end_y);
+        }
+
+        let white = self.info.pixel_format.pixel_value(Color::new(u32::MAX));
+
+        for (row, byte) in POINTER_GLYPH.iter().enumerate() {
+            for column in 0..8 {
+                if *byte & (1 << column) == 0 || x + column >= self.width() {
+                    continue;
+                }
 
-        let bits_per_pixel = self.info.bits_per_pixel;
-        let byte_offset = pixel_offset * bits_per_pixel;
+                if y + row >= self.height() {
+                    break;
+                }
 
-        self.backbuffer[byte_offset..(byte_offset + bits_per_pixel)]
-            .copy_from_slice(&color[..bits_per_pixel]);
+                self.write_output_pixel(x + column, y + row, white);
+            }
+        }
+
+        self.pointer = Some((x, y));
+    }
+
+    /// Copies the provided region of the backbuffer to the output. The end coordinates
+    /// are exclusive.
+    fn copy_to_output(&mut self, start_x: usize, start_y: usize, end_x: usize, end_y: usize) {
+        if start_x >= end_x || start_y >= end_y {
+            return;
+        }
+
+        match &mut self.output {
+            Output::Linear(framebuffer) => {
+                for y in start_y..end_y {
+                    let start = self.info.byte_offset(start_x, y);
+                    let end = self.info.byte_offset(end_x, y);
+
+                    framebuffer[start..end].copy_from_slice(&self.backbuffer[start..end]);
+                }
+            }
+
+            Output::Blt(gop) => {
+                // SAFETY: The backbuffer of a Blt-only framebuffer consists of BltPixels
+                // and the allocation is suitably aligned.
+                let buffer = unsafe {
+                    core::slice::from_raw_parts(
+                        self.backbuffer.as_ptr() as *const BltPixel,
+                        self.backbuffer.len() / core::mem::size_of::<BltPixel>(),
+                    )
+                };
+
+                // SAFETY: The protocol pointer is valid as long as the boot services are active.
+                let _ = unsafe { &mut **gop }.blt(BltOp::BufferToVideo {
+                    buffer,
+                    src: BltRegion::SubRectangle {
+                        coords: (start_x, start_y),
+                        px_stride: self.info.stride,
+                    },
+                    dest: (start_x, start_y),
+                    dims: (end_x - start_x, end_y - start_y),
+                });
+            }
+
+            Output::Text(_) | Output::None => {}
+        }
+    }
+
+    /// Writes the provided raw pixel value directly to the output, bypassing the
+    /// backbuffer.
+    fn write_output_pixel(&mut self, x: usize, y: usize, value: u32) {
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+
+        match &mut self.output {
+            Output::Linear(framebuffer) => {
+                let byte_offset = self.info.byte_offset(x, y);
+                framebuffer[byte_offset..(byte_offset + bytes_per_pixel)]
+                    .copy_from_slice(&value.to_le_bytes()[..bytes_per_pixel]);
+            }
+
+            Output::Blt(gop) => {
+                let [blue, green, red, _] = value.to_le_bytes();
+
+                // SAFETY: The protocol pointer is valid as long as the boot services are active.
+                let _ = unsafe { &mut **gop }.blt(BltOp::VideoFill {
+                    color: BltPixel::new(red, green, blue),
+                    dest: (x, y),
+                    dims: (1, 1),
+                });
+            }
+
+            Output::Text(_) | Output::None => {}
+        }
     }
 
     #[inline]
@@ -193,7 +750,221 @@ impl Logger {
         self.x_pos = 0;
         self.y_pos = 0;
 
-        self.backbuffer.fill(0x00)
+        if let Output::Text(stdout) = self.output {
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            let _ = unsafe { &mut *stdout }.clear();
+            return;
+        }
+
+        self.backbuffer.fill(0x00);
+        self.mark_dirty(0, 0, self.width(), self.height());
+    }
+
+    /// Draws an image at the provided position in pixels. The color of each pixel is
+    /// provided by the `pixel` function, relative to the top left corner of the image.
+    fn draw_image<F>(&mut self, x: usize, y: usize, width: usize, height: usize, pixel: F)
+    where
+        F: Fn(usize, usize) -> Color,
+    {
+        // The text console cannot display images.
+        if let Output::Text(_) = self.output {
+            return;
+        }
+
+        let width = width.min(self.width().saturating_sub(x));
+        let height = height.min(self.height().saturating_sub(y));
+
+        for image_y in 0..height {
+            for image_x in 0..width {
+                self.write_pixel(x + image_x, y + image_y, pixel(image_x, image_y));
+            }
+        }
+
+        self.mark_dirty(x, y, width, height);
+    }
+
+    /// Reads the color of the pixel at the provided position from the backbuffer.
+    fn read_pixel(&self, x: usize, y: usize) -> Color {
+        let mut value = [0; 4];
+
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let byte_offset = self.info.byte_offset(x, y);
+
+        value[..bytes_per_pixel]
+            .copy_from_slice(&self.backbuffer[byte_offset..(byte_offset + bytes_per_pixel)]);
+
+        self.info.pixel_format.color(u32::from_le_bytes(value))
+    }
+
+    /// Moves the cursor to the provided position in pixels.
+    fn set_cursor_pos(&mut self, x: usize, y: usize) {
+        self.x_pos = x;
+        self.y_pos = y;
+
+        if let Output::Text(stdout) = self.output {
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            let stdout = unsafe { &mut *stdout };
+            let _ = stdout.set_cursor_position(x / self.char_width(), y / self.line_height());
+        }
+    }
+
+    /// Returns the position of the cursor in character cells.
+    fn cursor_pos(&self) -> (usize, usize) {
+        (
+            self.x_pos / self.char_width(),
+            self.y_pos / self.line_height(),
+        )
+    }
+
+    /// Returns the amount of text columns that fit on the screen.
+    #[inline]
+    fn columns(&self) -> usize {
+        self.width() / self.char_width()
+    }
+
+    /// Returns the amount of text rows that fit on the screen.
+    #[inline]
+    fn rows(&self) -> usize {
+        self.height() / self.line_height()
+    }
+
+    /// Returns the range of the backbuffer that covers the provided text rows.
+    fn row_range(&self, row: usize, count: usize) -> core::ops::Range<usize> {
+        let start_y = (row * self.line_height()).min(self.height());
+        let end_y = ((row + count) * self.line_height()).min(self.height());
+
+        self.info.byte_offset(0, start_y)..self.info.byte_offset(0, end_y)
+    }
+
+    /// Clears the provided text row and moves the cursor to the start of it.
+    fn clear_line(&mut self, row: usize) {
+        self.set_cursor_pos(0, row * self.line_height());
+
+        if let Output::Text(stdout) = self.output {
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            let stdout = unsafe { &mut *stdout };
+
+            // Leave the last column alone, as writing to it would make the console wrap.
+            for _ in 0..self.columns().saturating_sub(1) {
+                let _ = stdout.write_char(' ');
+            }
+
+            let _ = stdout.set_cursor_position(0, row);
+            return;
+        }
+
+        let range = self.row_range(row, 1);
+        self.backbuffer[range].fill(0x00);
+
+        self.mark_dirty(0, self.y_pos, self.width(), self.line_height());
+    }
+
+    /// Sets the foreground color. The text console cannot display arbitrary colors, so
+    /// any color other than the default one highlights the text using inverted colors.
+    fn set_fg(&mut self, color: Color) {
+        self.fg = color;
+
+        if let Output::Text(stdout) = self.output {
+            let (fg, bg) = if color.0 == u32::MAX {
+                (text::Color::LightGray, text::Color::Black)
+            } else {
+                (text::Color::Black, text::Color::LightGray)
+            };
+
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            let _ = unsafe { &mut *stdout }.set_color(fg, bg);
+        }
+    }
+
+    /// Marks the provided region of the backbuffer as modified, so that it is copied
+    /// to the framebuffer on the next flush.
+    fn mark_dirty(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let end_x = (x + width).min(self.width());
+        let end_y = (y + height).min(self.height());
+
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => DirtyRect {
+                start_x: dirty.start_x.min(x),
+                start_y: dirty.start_y.min(y),
+                end_x: dirty.end_x.max(end_x),
+                end_y: dirty.end_y.max(end_y),
+            },
+
+            None => DirtyRect {
+                start_x: x,
+                start_y: y,
+                end_x,
+                end_y,
+            },
+        });
+    }
+
+    /// Scrolls the contents of the log up by one text line, discarding the topmost line
+    /// and clearing the bottom line. The status rows are left alone.
+    fn scroll(&mut self) {
+        let line_size = self.line_height() * self.info.pitch;
+        let log_size = self.info.byte_offset(0, self.log_height());
+
+        self.backbuffer.copy_within(line_size..log_size, 0);
+        self.backbuffer[(log_size - line_size)..log_size].fill(0x00);
+
+        self.y_pos -= self.line_height();
+        self.mark_dirty(0, 0, self.width(), self.log_height());
+    }
+
+    /// Returns the height of the part of the screen that the log is written to in pixels,
+    /// which excludes the status rows.
+    #[inline]
+    fn log_height(&self) -> usize {
+        self.height()
+            .saturating_sub(self.status_rows * self.line_height())
+    }
+
+    /// Reserves the provided amount of text rows at the bottom of the screen for status
+    /// lines and clears them. The log is scrolled out of the reserved rows if necessary.
+    fn reserve_status_rows(&mut self, count: usize) {
+        // Always leave room for the log, as it could not be scrolled otherwise.
+        self.status_rows = count.min(self.rows().saturating_sub(2));
+
+        // The firmware scrolls the text console by itself, which we cannot prevent.
+        if let Output::Text(_) = self.output {
+            return;
+        }
+
+        while self.y_pos >= self.log_height() {
+            self.scroll();
+        }
+
+        let (x, y) = (self.x_pos, self.y_pos);
+
+        for row in self.rows().saturating_sub(self.status_rows)..self.rows() {
+            self.clear_line(row);
+        }
+
+        self.set_cursor_pos(x, y);
+    }
+
+    /// Replaces the contents of the provided status row with the provided text, which is
+    /// cut off at the end of the row. The cursor of the log is left where it was, so log
+    /// records written in between status updates cannot end up in the status rows.
+    fn write_status(&mut self, row: usize, text: &str) {
+        if row < self.rows().saturating_sub(self.status_rows) || row >= self.rows() {
+            return;
+        }
+
+        let (x, y) = (self.x_pos, self.y_pos);
+        self.clear_line(row);
+
+        // Leave the last column alone, as writing to it would make the text console wrap.
+        for c in text
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(self.columns().saturating_sub(1))
+        {
+            self.draw_char(c);
+        }
+
+        self.set_cursor_pos(x, y);
     }
 
     #[inline]
@@ -213,19 +984,22 @@ impl Logger {
 
     #[inline]
     fn new_line(&mut self) {
-        if !self.scroll_lock {
-            self.y_pos += 16;
-        }
-
+        self.y_pos += self.line_height();
         self.carriage_return();
     }
 
+    /// Copies the modified region of the backbuffer to the framebuffer.
     fn flush(&mut self) {
-        // SAFETY: life is ment to be unsafe
-        unsafe {
-            self.backbuffer
-                .as_ptr()
-                .copy_to_nonoverlapping(self.framebuffer.as_mut_ptr(), self.framebuffer.len());
+        let dirty = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+
+        self.copy_to_output(dirty.start_x, dirty.start_y, dirty.end_x, dirty.end_y);
+
+        // The flush has overwritten the pointer, so draw it again.
+        if let Some((x, y)) = self.pointer.take() {
+            self.draw_pointer(x, y);
         }
     }
 }
@@ -241,64 +1015,290 @@ impl fmt::Write for Logger {
 }
 
 /// This function is responsible for initializing the global logger
-/// instance.
-pub fn init(framebuffer: &'static mut [u8], backbuffer: &'static mut [u8], info: FrameBufferInfo) {
-    let logger = LOGGER.call_once(move || LockedLogger::new(framebuffer, backbuffer, info));
+/// instance and registering it as a console sink.
+pub fn init(output: Output, backbuffer: &'static mut [u8], info: FrameBufferInfo) {
+    let logger = LOGGER.call_once(move || LockedLogger::new(output, backbuffer, info));
+    console::register(logger);
+}
 
-    log::set_logger(logger).expect("Logger already set");
-    log::set_max_level(log::LevelFilter::Trace);
+/// Returns the physical address and the layout of the framebuffer, if it is directly
+/// accessible.
+pub fn framebuffer() -> Option<(u64, FrameBufferInfo)> {
+    let logger = LOGGER.get()?.0.lock();
+
+    match &logger.output {
+        // NOTE: UEFI identity-maps all memory, so the virtual address is the physical one.
+        Output::Linear(framebuffer) => Some((framebuffer.as_ptr() as u64, logger.info)),
+        _ => None,
+    }
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::logger::_print(format_args!($($arg)*)));
+/// Stops using the Blt function of the GOP or the text console to display the log, as
+/// they are not available after we have exited the boot services. Must be called before
+/// exiting the boot services.
+pub fn exit_boot_services() {
+    LOGGER.get().map(|l| {
+        let mut logger = l.0.lock();
+
+        if let Output::Blt(_) | Output::Text(_) = logger.output {
+            logger.output = Output::None;
+        }
+    });
 }
 
-#[macro_export]
-macro_rules! println {
-    () => ($crate::prelude::print!("\n"));
-    ($($arg:tt)*) => ($crate::prelude::print!("{}\n", format_args!($($arg)*)));
+/// Replaces the framebuffer of the global logger instance, e.g. after the display mode
+/// has been changed. The contents of the screen are discarded, while the font and the
+/// mouse pointer are kept. Returns the backbuffer of the previous framebuffer, which is
+/// not used anymore and can be freed by the caller.
+pub fn set_framebuffer(
+    output: Output,
+    backbuffer: &'static mut [u8],
+    info: FrameBufferInfo,
+) -> Option<&'static mut [u8]> {
+    LOGGER.get().map(|l| {
+        let mut logger = l.0.lock();
+        let previous = core::mem::replace(&mut *logger, Logger::new(output, backbuffer, info));
+
+        logger.font = previous.font;
+        logger.update_scale();
+
+        // The pointer is drawn again by the next flush, within the bounds of the new mode.
+        let (max_x, max_y) = (
+            logger.width().saturating_sub(1),
+            logger.height().saturating_sub(1),
+        );
+        logger.pointer = previous.pointer.map(|(x, y)| (x.min(max_x), y.min(max_y)));
+
+        logger.clear();
+        previous.backbuffer
+    })
+}
+
+/// This function is responsible for writing the boot log to the root directory of the
+/// boot partition, replacing the boot log of the previous boot.
+pub fn save_boot_log(root: &mut Directory) {
+    let written = {
+        let boot_log = BOOT_LOG.lock();
+        let (first, second) = boot_log.contents();
+
+        crate::write_file(root, BOOT_LOG_PATH, &[first, second])
+    };
+
+    if !written {
+        log::warn!("logger: failed to write the boot log");
+    }
 }
 
 /// This function is responsible for clearing the screen.
 pub fn clear() {
-    LOGGER.get().map(|l| l.0.lock().clear());
+    console::clear();
 }
 
-pub fn set_cursor_pos(x: usize, y: usize) {
+/// Moves the cursor to the provided (zero based) text column and row.
+pub fn set_cursor_pos(column: usize, row: usize) {
+    console::set_cursor_pos(column, row);
+}
+
+/// Returns the (zero based) text column and row of the cursor on the screen.
+pub fn cursor_pos() -> (usize, usize) {
+    LOGGER.get().map_or((0, 0), |l| l.0.lock().cursor_pos())
+}
+
+/// Returns the amount of text columns that fit on the screen.
+pub fn columns() -> usize {
+    LOGGER.get().map_or(80, |l| l.0.lock().columns())
+}
+
+/// Returns the amount of text rows that fit on the screen.
+pub fn rows() -> usize {
+    LOGGER.get().map_or(25, |l| l.0.lock().rows())
+}
+
+/// Clears the provided (zero based) text row and moves the cursor to the start of it, so
+/// that the row can be redrawn (e.g. the countdown) without redrawing the whole screen.
+pub fn clear_line(row: usize) {
+    console::clear_line(row);
+}
+
+/// Reserves the provided amount of text rows at the bottom of the screen for status lines
+/// (e.g. the countdown of the menu), which are written with [`set_status`] and are not
+/// scrolled by the log. A count of zero releases the rows again.
+pub fn reserve_status_rows(count: usize) {
+    LOGGER.get().map(|l| l.0.lock().reserve_status_rows(count));
+}
+
+/// Returns the amount of text rows reserved for status lines.
+pub fn status_rows() -> usize {
+    LOGGER.get().map_or(0, |l| l.0.lock().status_rows)
+}
+
+/// Replaces the contents of the provided (zero based) status row with the provided text.
+/// The rows are counted from the first row reserved by [`reserve_status_rows`].
+pub fn set_status(index: usize, text: &str) {
+    let row = rows().saturating_sub(status_rows()) + index;
+    console::write_status(row, text);
+}
+
+/// The contents of a range of text rows of the screen, saved by [`save_region`].
+pub struct SavedRegion {
+    row: usize,
+    count: usize,
+    pixels: Vec<u8>,
+}
+
+/// Saves the contents of the provided range of text rows of the screen, so that they can
+/// be restored after they have been drawn over (e.g. by a popup). Nothing is saved if the
+/// screen is a text console, as its contents cannot be read back.
+pub fn save_region(row: usize, count: usize) -> SavedRegion {
+    let pixels = LOGGER.get().map_or(Vec::new(), |l| {
+        let logger = l.0.lock();
+        let range = logger.row_range(row, count);
+
+        logger.backbuffer[range].to_vec()
+    });
+
+    SavedRegion { row, count, pixels }
+}
+
+/// Restores the contents of the text rows saved by [`save_region`].
+pub fn restore_region(region: &SavedRegion) {
     LOGGER.get().map(|l| {
-        l.0.lock().x_pos = x;
-        l.0.lock().y_pos = y;
+        let mut logger = l.0.lock();
+        let range = logger.row_range(region.row, region.count);
+
+        if range.len() != region.pixels.len() {
+            return;
+        }
+
+        logger.backbuffer[range].copy_from_slice(&region.pixels);
+
+        let (y, height) = (
+            region.row * logger.line_height(),
+            region.count * logger.line_height(),
+        );
+        let width = logger.width();
+
+        logger.mark_dirty(0, y, width, height);
+    });
+}
+
+/// Replaces the built-in font with the provided font loaded from disk.
+pub fn set_font(font: PsfFont) {
+    LOGGER.get().map(|l| {
+        let mut logger = l.0.lock();
+
+        // The glyphs of the font can be larger than the built-in ones.
+        logger.font = Some(font);
+        logger.update_scale();
     });
 }
 
+/// Sets the integer factor by which each glyph is scaled up. The scale is lowered if the
+/// screen would not fit enough text otherwise.
+pub fn set_font_scale(scale: usize) {
+    *FONT_SCALE.lock() = Some(scale);
+    LOGGER.get().map(|l| l.0.lock().update_scale());
+}
+
+/// Returns the width of a character cell in pixels.
+pub fn char_width() -> usize {
+    LOGGER.get().map_or(8, |l| l.0.lock().char_width())
+}
+
+/// Returns the height of a text line in pixels.
+pub fn line_height() -> usize {
+    LOGGER.get().map_or(16, |l| l.0.lock().line_height())
+}
+
 pub fn with_fg<F>(color: Color, f: F)
 where
     F: FnOnce(),
 {
-    LOGGER.get().map(|l| {
-        let mut lock = l.0.lock();
-        let old = lock.fg;
-        lock.fg = color;
-        core::mem::drop(lock);
-        f();
-        let mut lock = l.0.lock();
-        lock.fg = old;
-    });
+    let old = LOGGER.get().map_or(Color::new(u32::MAX), |l| l.0.lock().fg);
+
+    console::set_fg(color);
+    f();
+    console::set_fg(old);
+}
+
+/// Sets the maximum level of the log records that are displayed. The serial console and the
+/// boot log still receive all of the log records.
+pub fn set_level(level: log::LevelFilter) {
+    *LEVEL.lock() = level;
+}
+
+/// Returns the maximum level of the log records that are displayed.
+pub fn level() -> log::LevelFilter {
+    *LEVEL.lock()
 }
 
 pub fn flush() {
-    LOGGER.get().map(|l| l.0.lock().flush());
+    console::flush();
 }
 pub fn display_height() -> usize {
     LOGGER.get().map(|l| l.0.lock().height()).unwrap()
 }
 
-pub fn set_scroll_lock(lock: bool) {
-    LOGGER.get().map(|l| l.0.lock().scroll_lock = lock);
+pub fn display_width() -> usize {
+    LOGGER.get().map(|l| l.0.lock().width()).unwrap()
+}
+
+/// Draws an image at the provided position in pixels. The color of each pixel is provided
+/// by the `pixel` function, relative to the top left corner of the image.
+pub fn draw_image<F>(x: usize, y: usize, width: usize, height: usize, pixel: F)
+where
+    F: Fn(usize, usize) -> Color,
+{
+    LOGGER
+        .get()
+        .map(|l| l.0.lock().draw_image(x, y, width, height, pixel));
+}
+
+/// Returns true if images can be drawn on the screen, which is not the case if the log is
+/// displayed on the text console.
+pub fn can_draw_images() -> bool {
+    LOGGER.get().map_or(false, |l| {
+        !matches!(l.0.lock().output, Output::Text(_) | Output::None)
+    })
+}
+
+/// Hides or shows the log records on the screen. The log records are still written to
+/// all of the other sinks.
+pub fn set_quiet(quiet: bool) {
+    LOGGER.get().map(|l| l.0.lock().quiet = quiet);
+}
+
+/// Writes the contents of the boot log to the screen, e.g. to show the log records that
+/// have been hidden by [`set_quiet`].
+pub fn show_boot_log() {
+    let boot_log = BOOT_LOG.lock();
+    let (first, second) = boot_log.contents();
+
+    LOGGER.get().map(|l| {
+        let mut logger = l.0.lock();
+
+        for part in [first, second].iter() {
+            let _ = logger.write_str(&String::from_utf8_lossy(part));
+        }
+    });
+}
+
+/// Returns the contents of the screen encoded as a bitmap, or [`None`] if the screen is
+/// a text console.
+pub fn screenshot() -> Option<Vec<u8>> {
+    let logger = LOGGER.get()?.0.lock();
+
+    if let Output::Text(_) = logger.output {
+        return None;
+    }
+
+    Some(bmp::encode(logger.width(), logger.height(), |x, y| {
+        logger.read_pixel(x, y)
+    }))
 }
 
-#[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
-    LOGGER.get().map(|l| l.0.lock().write_fmt(args));
+/// Moves the mouse pointer to the provided position, drawing it if it has not been
+/// drawn yet.
+pub fn set_pointer_pos(x: usize, y: usize) {
+    LOGGER.get().map(|l| l.0.lock().draw_pointer(x, y));
 }
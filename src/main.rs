@@ -1,35 +1,74 @@
 #![feature(
     abi_efiapi,
-    custom_test_frameworks,
     asm,
     panic_info_message,
     alloc_error_handler,
+    global_asm,
     maybe_uninit_slice
 )]
-#![test_runner(crate::test_runner)]
 #![no_std]
 #![no_main]
 
 extern crate alloc;
 
 use uefi::prelude::*;
-use uefi::proto::console::gop::GraphicsOutput;
+use uefi::proto::console::gop::{self, GraphicsOutput};
+use uefi::proto::console::text;
 use uefi::proto::loaded_image::LoadedImage;
 use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile};
 use uefi::proto::media::fs::SimpleFileSystem;
 use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType};
-use x86_64::registers::control::{Cr3, Cr3Flags};
-use x86_64::structures::paging::*;
-use x86_64::VirtAddr;
 
+use alloc::vec;
+use alloc::vec::Vec;
 use core::mem;
 use core::panic::PanicInfo;
 
+use error::IonError;
+use ion_core::hibernation::ResumeAction;
+
+mod acpi;
+mod arch;
+mod autodetect;
+mod bls;
+mod bmp;
+mod bootorder;
 mod config;
+mod console;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod cpuid;
+mod debug;
+mod devpath;
+mod diskio;
+mod dtb;
+mod efi;
+mod entropy;
+mod error;
+mod font;
+mod graphics;
+mod hibernation;
+mod i18n;
+mod input;
+mod keymap;
 mod logger;
+mod mem;
+mod memtest;
 mod menu;
+mod nvram;
+mod panic;
+mod pci;
 mod pmm;
+mod pointer;
+mod preload;
+mod profile;
 mod protocols;
+mod selfcheck;
+mod serial;
+mod smbios;
+mod speaker;
+mod splash;
+mod symbols;
+mod time;
 mod prelude {
     pub use crate::{print, println};
 }
@@ -39,169 +78,541 @@ fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
     panic!("oom {:?}", layout)
 }
 
-pub struct BootPageTables {
-    /// Provides access to the page tables of the bootloader address space.
-    pub bootloader: OffsetPageTable<'static>,
-    /// Provides access to the page tables of the kernel address space (not active).
-    pub kernel: OffsetPageTable<'static>,
-    /// The physical frame where the level 4 page table of the kernel address space is stored.
-    pub kernel_level_4_frame: PhysFrame,
+/// This function is responsible for initializing the logger for Ion.
+fn init_logger(system_table: &SystemTable<Boot>) {
+    match framebuffer(system_table) {
+        Some((output, backbuffer, info)) => logger::init(output, backbuffer, info),
+        None => {
+            let (output, info) = text_console(system_table);
+            logger::init(output, &mut [], info);
+
+            log::warn!("ion: no graphics output found, falling back to the text console");
+        }
+    }
 }
 
-/// Helper function to create and load the bootloader's page table and
-/// create a new page table for the kernel itself.
-fn setup_boot_paging(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> BootPageTables {
-    // NOTE: UEFI identity-maps all memory, so the offset between physical
-    // and virtual addresses is 0.
-    let off = VirtAddr::zero();
+/// Helper function to query the current mode of the selected GOP and allocate a backbuffer
+/// for it. Returns [`None`] if the firmware does not provide a graphics output.
+fn framebuffer(
+    system_table: &SystemTable<Boot>,
+) -> Option<(logger::Output, &'static mut [u8], logger::FrameBufferInfo)> {
+    let gop = graphics::output()?;
+    let info = graphics::framebuffer_info(gop);
+
+    let output = match gop.current_mode_info().pixel_format() {
+        // The framebuffer is not accessible, so we render into a backbuffer of BltPixels
+        // and copy it to the screen using the Blt function of the GOP.
+        gop::PixelFormat::BltOnly => logger::Output::Blt(gop as *mut GraphicsOutput<'static>),
+        _ => framebuffer_output(gop),
+    };
 
-    let boot_page_table = {
-        let old_table = {
-            let (frame, _) = Cr3::read();
+    let backbuffer = unsafe {
+        let ptr = system_table
+            .boot_services()
+            .allocate_pool(MemoryType::LOADER_DATA, info.size())
+            .expect_success("could not allocate memory");
 
-            let ptr: *const PageTable = (off + frame.start_address().as_u64()).as_ptr();
+        // SAFETY: The provided pointer by allocate_pool is guaranteed to be
+        // valid.
+        core::slice::from_raw_parts_mut(ptr, info.size())
+    };
 
-            unsafe { &*ptr }
-        };
+    Some((output, backbuffer, info))
+}
 
-        let new_frame = frame_allocator
-            .allocate_frame()
-            .expect("mm: failed to allocate frame for new level 4 boot table");
+/// Helper function to switch the selected GOP to the mode with the provided resolution and
+/// to hand the framebuffer of the new mode to the logger.
+///
+/// Replacing the framebuffer clears the screen, so the error is returned for the caller
+/// to log afterwards.
+fn switch_mode(
+    system_table: &SystemTable<Boot>,
+    resolution: Option<(usize, usize)>,
+) -> Result<(), graphics::ModeError> {
+    let mode = graphics::set_mode(system_table, resolution);
+
+    if let Some((output, backbuffer, info)) = framebuffer(system_table) {
+        // The text console does not use a backbuffer, so there is nothing to free then.
+        match logger::set_framebuffer(output, backbuffer, info) {
+            Some(previous) if !previous.is_empty() => system_table
+                .boot_services()
+                .free_pool(previous.as_mut_ptr())
+                .expect_success("failed to free the backbuffer"),
+
+            _ => (),
+        }
+    }
 
-        let new_table: &mut PageTable = {
-            let ptr: *mut PageTable = (off + new_frame.start_address().as_u64()).as_mut_ptr();
+    mode
+}
 
-            unsafe {
-                // Create a new empty, fresh page table.
-                ptr.write(PageTable::new());
-                &mut *ptr
-            }
-        };
+/// Helper function to describe the UEFI Simple Text Output console as a virtual screen of
+/// 8x16 character cells, used if the firmware does not provide a graphics output.
+fn text_console(system_table: &SystemTable<Boot>) -> (logger::Output, logger::FrameBufferInfo) {
+    let stdout = system_table.stdout();
 
-        // Copy the first entry (we don't need to access more than 512 GiB; also, some UEFI
-        // implementations seem to create an level 4 table entry 0 in all slots)
-        new_table[0] = old_table[0].clone();
+    let (columns, rows) = stdout
+        .current_mode()
+        .ok()
+        .and_then(|mode| mode.unwrap())
+        .map_or((80, 25), |mode| (mode.columns(), mode.rows()));
 
-        // The first level 4 table entry is now identical, so we can just load the new one.
-        unsafe {
-            Cr3::write(new_frame, Cr3Flags::empty());
-            OffsetPageTable::new(&mut *new_table, off)
-        }
+    let info = logger::FrameBufferInfo {
+        horizontal_resolution: columns * 8,
+        vertical_resolution: rows * 16,
+        pixel_format: logger::PixelFormat::BGR,
+        bits_per_pixel: 0,
+        bytes_per_pixel: 0,
+        stride: columns * 8,
+        pitch: 0,
     };
 
-    // Now we will create a page table for the kernel itself.
-    let (kernel_page_table, kernel_level_4_frame) = {
-        // get an unused frame for new level 4 page table
-        let frame: PhysFrame = frame_allocator
-            .allocate_frame()
-            .expect("mm: no unused frames");
+    let stdout = stdout as *mut text::Output as *mut text::Output<'static>;
+    (logger::Output::Text(stdout), info)
+}
+
+/// Helper function to get direct access to the framebuffer of the provided GOP.
+fn framebuffer_output(gop: &mut GraphicsOutput) -> logger::Output {
+    let mut framebuffer = gop.frame_buffer();
+    let slice =
+        unsafe { core::slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), framebuffer.size()) };
+
+    logger::Output::Linear(slice)
+}
+
+/// Helper function to read the whole file at the provided URI into memory of the provided
+/// type. Returns an error if the URI is invalid or the file could not be opened.
+fn read_file(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    path: &'static str,
+    memory_type: MemoryType,
+) -> Result<&'static [u8], IonError> {
+    read_files(system_table, root, &[(path, memory_type)]).map(|files| files[0])
+}
+
+/// Helper function to read the whole files at the provided URIs into memory of the provided
+/// types. The files are read at once if the firmware supports asynchronous reads. Returns an
+/// error if any of the URIs is invalid or any of the files could not be read.
+fn read_files(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    files: &[(&'static str, MemoryType)],
+) -> Result<Vec<&'static [u8]>, IonError> {
+    let mut data: Vec<&'static [u8]> = vec![&[]; files.len()];
+    let mut pending = Vec::new();
+    let mut reads = Vec::new();
+
+    for (i, &(path, memory_type)) in files.iter().enumerate() {
+        // The file might have been read while the countdown of the menu was running.
+        if let Some(preloaded) = preload::take(path, memory_type) {
+            data[i] = preloaded;
+            continue;
+        }
+
+        let parsed_uri =
+            config::parse_uri(path).map_err(|error| IonError::InvalidUri(path, error))?;
+        let uri = config::handle_uri_redirect(system_table, &parsed_uri, root)?;
+
+        let file_completion = uri
+            .open(parsed_uri.path(), FileMode::Read, FileAttribute::empty())
+            .map_err(|_| IonError::FileNotFound(path))?
+            .unwrap();
+
+        let mut file_handle = unsafe { RegularFile::new(file_completion) };
+
+        let mut info_buf = [0; 0x100];
+        let file_info = file_handle
+            .get_info::<FileInfo>(&mut info_buf)
+            .unwrap_success();
 
-        log::info!("new page table at: {:#?}", &frame);
+        let pages = file_info.file_size() as usize / 0x1000 + 1;
+        let mem_start = system_table
+            .boot_services()
+            .allocate_pages(AllocateType::AnyPages, memory_type, pages)
+            .unwrap_success();
+
+        let buf = unsafe { core::slice::from_raw_parts_mut(mem_start as *mut u8, pages * 0x1000) };
+
+        pending.push(i);
+        reads.push((file_handle, buf));
+    }
 
-        // 1. Get the corresponding virtual address.
-        let addr = off + frame.start_address().as_u64();
+    let lengths = diskio::read_all(system_table, &mut reads);
 
-        // 2. Initialize a new page table.
-        let ptr = addr.as_mut_ptr();
-        unsafe { *ptr = PageTable::new() };
-        let level_4_table = unsafe { &mut *ptr };
+    for ((i, (file_handle, buf)), len) in pending.into_iter().zip(reads).zip(lengths) {
+        file_handle.close();
 
-        (unsafe { OffsetPageTable::new(level_4_table, off) }, frame)
+        let buf: &'static [u8] = buf;
+        data[i] = &buf[..len.ok_or(IonError::FileNotFound(files[i].0))?];
+    }
+
+    Ok(data)
+}
+
+/// Reads the file that Ion has been loaded from and checks it against the checksum that has
+/// been embedded after the build, if any.
+fn check_image(system_table: &SystemTable<Boot>, root: &mut Directory, loaded_image: &LoadedImage) {
+    let expected = match selfcheck::expected() {
+        Some(expected) => expected,
+        None => {
+            log::debug!("selfcheck: the image does not contain a checksum");
+            return;
+        }
+    };
+
+    let uri = match selfcheck::image_uri(loaded_image) {
+        Some(uri) => uri,
+        None => {
+            log::debug!("selfcheck: the image has not been loaded from a file");
+            return;
+        }
     };
 
-    BootPageTables {
-        bootloader: boot_page_table,
-        kernel: kernel_page_table,
-        kernel_level_4_frame,
+    match read_file(system_table, root, uri, MemoryType::BOOT_SERVICES_DATA) {
+        Ok(image) => selfcheck::verify(uri, image, expected),
+        Err(error) => log::warn!("selfcheck: failed to read {} ({})", uri, error),
     }
 }
 
-/// This function is responsible for initializing the logger for Ion and
-/// returns the physical address of the framebuffer.
-fn init_logger(system_table: &SystemTable<Boot>) {
-    let gop = system_table
-        .boot_services()
-        .locate_protocol::<GraphicsOutput>()
-        .expect_success("failed to locate GOP");
+/// Helper function to write the provided contents to the file at the provided path in the
+/// root directory of the boot partition, replacing the file if it already exists. Returns
+/// false if the file could not be written.
+fn write_file(root: &mut Directory, path: &str, contents: &[&[u8]]) -> bool {
+    // Delete the existing file, so that we do not leave stale data behind if the new
+    // contents are shorter.
+    if let Ok(handle) = root.open(path, FileMode::ReadWrite, FileAttribute::empty()) {
+        let _ = handle.unwrap().delete();
+    }
 
-    let gop = unsafe { &mut *gop.get() };
-    let mode_info = gop.current_mode_info();
-    let (horizontal_resolution, vertical_resolution) = mode_info.resolution();
+    let handle = match root.open(path, FileMode::CreateReadWrite, FileAttribute::empty()) {
+        Ok(handle) => handle.unwrap(),
+        Err(_) => return false,
+    };
 
-    let mut framebuffer = gop.frame_buffer();
+    let mut file = unsafe { RegularFile::new(handle) };
+    let written = contents.iter().all(|part| file.write(part).is_ok());
 
-    let backbuffer = unsafe {
-        let ptr = system_table
-            .boot_services()
-            .allocate_pool(MemoryType::LOADER_DATA, framebuffer.size())
-            .expect_success("could not allocate memory");
+    file.close();
+    written
+}
 
-        // SAFETY: The provided pointer by allocate_pool is guaranteed to be
-        // valid.
-        core::slice::from_raw_parts_mut(ptr, framebuffer.size())
+/// The files of an entry, which are read by [`prepare_kernel`].
+struct EntryFiles {
+    kernel: &'static [u8],
+    dtb: Option<&'static [u8]>,
+    /// The initrd, which is only read for the protocols that Ion passes it to.
+    initrd: Option<&'static [u8]>,
+}
+
+/// Returns true if the initrd of the entry is read by Ion and passed to the kernel.
+fn reads_initrd(protocol: config::BootProtocol) -> bool {
+    matches!(
+        protocol,
+        config::BootProtocol::Multiboot
+            | config::BootProtocol::Multiboot2
+            | config::BootProtocol::Linux
+    )
+}
+
+/// Helper function to check that the processor supports the features that the provided
+/// entry requires (see `REQUIRES=`), as the kernel would crash on it otherwise.
+fn check_requirements(entry: &config::ConfigurationEntry) -> Result<(), IonError> {
+    let missing = entry.missing_features();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(IonError::MissingCpuFeatures(missing))
+    }
+}
+
+/// Helper function to read the kernel, the device tree blob and the initrd of the provided
+/// entry into memory at once and check that the kernel can be booted using the boot
+/// protocol of the entry.
+fn prepare_kernel(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    entry: &config::ConfigurationEntry,
+) -> Result<EntryFiles, IonError> {
+    if entry.path().is_empty() {
+        return Err(IonError::MissingKernelPath);
+    }
+
+    let kernel_path = entry.path();
+    log::debug!("stivale2: loading kernel {}...\n", kernel_path);
+
+    let mut files = vec![(kernel_path, pmm::KERNEL_MEMORY_TYPE)];
+
+    if let Some(dtb_path) = entry.dtb_path() {
+        files.push((dtb_path, MemoryType::LOADER_DATA));
+    }
+
+    let initrd_path = entry
+        .initrd_path()
+        .filter(|_| reads_initrd(entry.protocol()));
+
+    if let Some(initrd_path) = initrd_path {
+        files.push((initrd_path, MemoryType::LOADER_DATA));
+    }
+
+    let data = read_files(system_table, root, &files)?;
+    let kernel = data[0];
+
+    match entry.protocol() {
+        config::BootProtocol::Stivale2 => protocols::stivale2::validate(kernel)?,
+        #[cfg(target_arch = "x86_64")]
+        config::BootProtocol::Multiboot => protocols::multiboot::v1::load(system_table, kernel)?,
+        #[cfg(target_arch = "x86_64")]
+        config::BootProtocol::Multiboot2 => protocols::multiboot::v2::load(system_table, kernel)?,
+        #[cfg(target_arch = "x86_64")]
+        config::BootProtocol::Linux => protocols::linux::load(system_table, kernel)?,
+        protocol => return Err(IonError::UnsupportedProtocol(protocol)),
+    }
+
+    let dtb = entry.dtb_path().and(data.get(1).copied());
+    let initrd = initrd_path.and(data.last().copied());
+
+    Ok(EntryFiles {
+        kernel,
+        dtb,
+        initrd,
+    })
+}
+
+/// Helper function to start the EFI application of the provided entry as a child of Ion,
+/// with the command line of the entry as its load options. The application is started
+/// like the firmware starts its boot options, so that boot managers which load further
+/// files from their own volume (e.g. shim or the Windows Boot Manager) behave the same.
+/// Returns once the application exits.
+fn chainload(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    root: &mut Directory,
+    boot_volume: Handle,
+    entry: &config::ConfigurationEntry,
+) -> Result<(), IonError> {
+    if entry.path().is_empty() {
+        return Err(IonError::MissingKernelPath);
+    }
+
+    let path = entry.path();
+    let image = read_file(system_table, root, path, MemoryType::BOOT_SERVICES_DATA)?;
+
+    let parsed_uri = config::parse_uri(path).map_err(|error| IonError::InvalidUri(path, error))?;
+    let volume = config::volume_handle(system_table, &parsed_uri, root, boot_volume)?;
+
+    // The device path of the file consists of the device path of its volume followed by
+    // the path of the file on the volume.
+    let device_path = devpath::of_handle(system_table, volume).map(|volume_path| {
+        let file_path = alloc::format!("\\{}", parsed_uri.path());
+        ion_core::devpath::append_file_path(volume_path, &file_path)
+    });
+
+    if device_path.is_none() {
+        log::warn!("chainload: the volume of {} has no device path", path);
+    }
+
+    config::save_preference(system_table, "LAST_ENTRY", entry.name());
+
+    if splash::is_active() {
+        splash::hide();
+    }
+
+    log::info!("chainload: starting {}", path);
+    logger::flush();
+
+    efi::start_application(
+        system_table,
+        image_handle,
+        device_path.as_deref(),
+        image,
+        entry.command_line(),
+    )
+    .map_err(|status| IonError::ApplicationFailed(path, status))?;
+
+    log::info!("chainload: {} has exited", path);
+    Ok(())
+}
+
+/// Helper function to load the font specified in the config (if any) and replace the
+/// built-in font of the logger with it.
+fn load_font(system_table: &SystemTable<Boot>, root: &mut Directory, config: &config::IonConfig) {
+    let path = match config.font() {
+        Some(path) => path,
+        None => return,
     };
 
-    let slice =
-        unsafe { core::slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), framebuffer.size()) };
+    let data = match read_file(system_table, root, path, MemoryType::LOADER_DATA) {
+        Ok(data) => data,
+        Err(error) => {
+            log::warn!("font: {}", error);
+            return;
+        }
+    };
 
-    let info = logger::FrameBufferInfo {
-        horizontal_resolution,
-        vertical_resolution,
-        pixel_format: match mode_info.pixel_format() {
-            uefi::proto::console::gop::PixelFormat::Rgb => logger::PixelFormat::RGB,
-            uefi::proto::console::gop::PixelFormat::Bgr => logger::PixelFormat::BGR,
-            _ => unimplemented!(),
-        },
-        bits_per_pixel: 4,
-        stride: mode_info.stride(),
+    match font::PsfFont::parse(data) {
+        Some(font) => logger::set_font(font),
+        None => log::warn!("font: {} is not a valid PC Screen Font file", path),
+    }
+}
+
+/// Loads and starts the UEFI drivers specified in the config and connects them to the
+/// controllers, so that they provide access to file systems that the firmware does not
+/// support (e.g. ext4 or NTFS).
+fn load_drivers(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    root: &mut Directory,
+    config: &config::IonConfig,
+) {
+    for &path in config.drivers() {
+        let image = match read_file(system_table, root, path, MemoryType::BOOT_SERVICES_DATA) {
+            Ok(image) => image,
+            Err(error) => {
+                log::warn!("driver: {}", error);
+                continue;
+            }
+        };
+
+        match efi::load_driver(system_table, image_handle, image) {
+            Ok(()) => log::info!("driver: started {}", path),
+            Err(status) => log::warn!("driver: failed to start {} ({:?})", path, status),
+        }
+    }
+
+    efi::connect_all_controllers(system_table);
+}
+
+/// Helper function to validate the device tree blob specified by the entry, which has been
+/// read by [`prepare_kernel`]. If the entry does not specify one, the device tree provided
+/// by the firmware (if any) is used instead.
+fn load_dtb(
+    system_table: &SystemTable<Boot>,
+    entry: &config::ConfigurationEntry,
+    data: Option<&'static [u8]>,
+) -> Option<&'static [u8]> {
+    let (path, data) = match (entry.dtb_path(), data) {
+        (Some(path), Some(data)) => (path, data),
+        _ => return dtb::from_config_table(system_table),
     };
 
-    logger::init(slice, backbuffer, info)
+    let dtb = dtb::validate(data);
+
+    if dtb.is_none() {
+        log::warn!("dtb: {} is not a valid flattened device tree", path);
+    }
+
+    dtb
 }
 
-fn prepare_kernel(
+/// Helper function to load the logo at the provided URI (if any) and show the splash
+/// screen. The logo of the firmware is kept on the screen instead if it should be
+/// preserved.
+fn show_splash(
     system_table: &SystemTable<Boot>,
     root: &mut Directory,
+    logo: &'static str,
+    preserve_logo: bool,
+) {
+    if preserve_logo {
+        match splash::firmware_logo() {
+            Some((bitmap, position)) => return splash::show(Some(bitmap), Some(position)),
+            None => log::debug!("splash: the firmware does not display a logo"),
+        }
+    }
+
+    let bitmap = if logo.is_empty() {
+        None
+    } else {
+        let bitmap = read_file(system_table, root, logo, MemoryType::LOADER_DATA)
+            .ok()
+            .and_then(bmp::Bitmap::parse);
+
+        if bitmap.is_none() {
+            log::warn!("splash: failed to load the logo {}", logo);
+        }
+
+        bitmap
+    };
+
+    splash::show(bitmap, None);
+}
+
+/// Helper function to record that the provided entry has failed to boot. Returns true if
+/// every entry of the config has failed to boot.
+fn record_failure(
+    failed: &mut Vec<(&'static str, &'static str)>,
+    config: &config::IonConfig,
     entry: &config::ConfigurationEntry,
-) -> &'static [u8] {
-    let parsed_uri = config::parse_uri(entry.path()).expect("stivale2: failed to parse the URI");
-    let uri = config::handle_uri_redirect(&parsed_uri, root);
+) -> bool {
+    let key = |entry: &config::ConfigurationEntry| (entry.name(), entry.path());
 
-    assert_ne!(entry.path().len(), 0, "stivale2: KERNEL_PATH not specified");
+    if !failed.contains(&key(entry)) {
+        failed.push(key(entry));
+    }
 
-    let kernel_path = entry.path();
-    let file_completion = uri
-        .open(parsed_uri.path(), FileMode::Read, FileAttribute::empty())
-        .expect_success("stivale2: failed to open kernel file. Is its path correct?");
+    config
+        .entries
+        .iter()
+        .all(|entry| failed.contains(&key(entry)))
+}
 
-    log::debug!("stivale2: loading kernel {}...\n", kernel_path);
+/// Helper function to report an error that prevented the selected entry from being booted.
+/// The function returns when a key is pressed or the idle timeout of the menu expires, after
+/// which the menu is shown again. If `offer_fallback` is true (i.e. none of the entries can
+/// be booted), pressing `f` restarts into the next boot option of the firmware instead.
+fn report_error(
+    system_table: &SystemTable<Boot>,
+    error: &IonError,
+    idle_timeout: Option<u64>,
+    offer_fallback: bool,
+) {
+    if splash::is_active() {
+        splash::hide();
+    }
 
-    let mut cfg_file_handle = unsafe { RegularFile::new(file_completion) };
+    log::error!("ion: {}", error);
 
-    let mut info_buf = [0; 0x100];
-    let cfg_info = cfg_file_handle
-        .get_info::<FileInfo>(&mut info_buf)
-        .unwrap_success();
+    let strings = i18n::strings();
 
-    let pages = cfg_info.file_size() as usize / 0x1000 + 1;
-    let mem_start = system_table
-        .boot_services()
-        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages)
-        .unwrap_success();
+    if offer_fallback {
+        println!("\n{}", strings.firmware_fallback);
+    }
 
-    let buf = unsafe { core::slice::from_raw_parts_mut(mem_start as *mut u8, pages * 0x1000) };
-    let len = cfg_file_handle.read(buf).unwrap_success();
+    println!("\n{}", strings.boot_failed);
+    logger::flush();
+
+    let input = input::wait_timeout(system_table, None, idle_timeout, || ());
 
-    buf[..len].as_ref()
+    if let input::InputEvent::Key(text::Key::Printable(c)) = input {
+        if offer_fallback && matches!(char::from(c), 'f' | 'F') {
+            bootorder::boot_fallback(system_table);
+        }
+    }
 }
 
 #[entry]
 fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
+    // The timestamps of the log records are relative to the entry of Ion.
+    time::mark_entry();
+
     system_table
         .stdout()
         .clear()
         .expect_success("failed to clear system stdout");
 
+    // The log records written before the screen is set up are kept and shown once it is.
+    console::init();
+
+    // Switch to the native resolution of the display before we start drawing to it.
+    graphics::select(&system_table, None);
+    // The log records written before the logger has been initialized are replayed, so
+    // the error is not lost.
+    if let Err(error) = graphics::set_mode(&system_table, None) {
+        log::warn!("graphics: {}", error);
+    }
+
     init_logger(&system_table);
 
     let boot_services = system_table.boot_services();
@@ -212,6 +623,35 @@ fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
         uefi::alloc::init(boot_services);
     }
 
+    if let Some(language) = i18n::platform_language(&system_table) {
+        i18n::set(language);
+    }
+
+    // Make sure that all of the input devices (e.g. USB keyboards) are bound to their
+    // drivers before we show the menu.
+    efi::connect_all_controllers(&system_table);
+    input::init(&system_table);
+
+    // The UEFI RNG protocol is only available while the boot services are active.
+    entropy::init(&system_table);
+
+    acpi::init(&system_table);
+    acpi::log_summary();
+
+    // The identification of the machine is shown in the header of the menu.
+    smbios::init(&system_table);
+
+    // The PSCI conduit is described by the FADT.
+    #[cfg(target_arch = "aarch64")]
+    arch::psci::init();
+
+    // The ID of the boot hart is only reported by the firmware.
+    #[cfg(target_arch = "riscv64")]
+    arch::smp::init(&system_table);
+
+    // The TSC is calibrated against the HPET from the ACPI tables, if there is one.
+    time::init(&system_table);
+
     // Query the handle for the loaded image protocol.
     let loaded_image = system_table
         .boot_services()
@@ -219,6 +659,13 @@ fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
         .expect_success("failed to retrieve loaded image protocokl");
     let loaded_image = unsafe { &*loaded_image.get() }; // Get the inner cell value
 
+    // The image base is required to symbolize the backtrace on panic.
+    symbols::set_image_base(loaded_image.info().0 as u64);
+
+    // The command line macros are expanded from the partition that Ion has been loaded
+    // from.
+    let boot_partition = devpath::of_handle(&system_table, loaded_image.device());
+
     // Query the handle for the simple file system protocol.
     let filesystem = system_table
         .boot_services()
@@ -231,14 +678,213 @@ fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
         .open_volume()
         .expect_success("failed to open volume");
 
-    let ion_config = config::load(&system_table, &mut root); // Load the config and store it in a local variable.
-    let selected_entry = menu::init(&system_table, ion_config);
+    let config_start = profile::start(profile::Phase::ConfigLoad);
+    let mut ion_config = config::load(&system_table, &mut root); // Load the config and store it in a local variable.
+    profile::finish(profile::Phase::ConfigLoad, config_start);
+
+    if let Some(display) = ion_config.display() {
+        graphics::select(&system_table, Some(display));
+    }
+
+    if ion_config.display().is_some() || ion_config.resolution().is_some() {
+        if let Err(error) = switch_mode(&system_table, ion_config.resolution()) {
+            log::warn!("graphics: {}", error);
+        }
+    }
 
-    // We have to load the kernel before we exit the boot services since we rely on the
-    // simple file system boot services protocol to read the kernel from the disk into
-    // memory.
-    let kernel = prepare_kernel(&system_table, &mut root, &selected_entry);
+    keymap::set(ion_config.keymap());
+    logger::set_level(ion_config.log_level());
+    load_font(&system_table, &mut root, &ion_config);
 
+    if let Some(scale) = ion_config.font_scale() {
+        logger::set_font_scale(scale);
+    }
+
+    if let Some(language) = ion_config.language() {
+        i18n::set(language);
+    }
+
+    if let Some(baud_rate) = ion_config.serial() {
+        serial::init(&system_table, baud_rate);
+    }
+
+    if !ion_config.drivers().is_empty() {
+        load_drivers(&system_table, image_handle, &mut root, &ion_config);
+    }
+
+    // The drivers might provide access to more volumes, so they are scanned afterwards.
+    if ion_config.auto_detect() {
+        autodetect::detect(&system_table, &mut ion_config);
+    }
+
+    check_image(&system_table, &mut root, loaded_image);
+
+    if ion_config.debug_wait() {
+        let (image_base, image_size) = loaded_image.info();
+        debug::wait(&system_table, image_base as usize, image_size as usize);
+    }
+
+    // Without any entries the menu has nothing to show, so the error is reported until the
+    // user starts the next boot option of the firmware.
+    if ion_config.entries.is_empty() {
+        loop {
+            report_error(&system_table, &IonError::NoEntries, None, true);
+        }
+    }
+
+    // Errors that prevent the selected entry from being booted are reported and the user
+    // is returned to the menu, so that they can pick another entry.
+    let mut countdown = true;
+    let mut default_entry = ion_config.default_entry();
+
+    // The entries that have failed to boot, identified by their name and path.
+    let mut failed = Vec::new();
+
+    // The running OS can request an entry for the next boot, which skips the menu once.
+    let mut boot_next = config::take_boot_next(&system_table, &ion_config);
+
+    // Booting over a hibernated system would corrupt its file systems, so either select
+    // its entry or make the user pick an entry themselves.
+    let hibernated = if boot_next.is_none() {
+        hibernation::detect(&system_table, &mut root, &ion_config)
+    } else {
+        None
+    };
+
+    if let Some(entry) = hibernated {
+        match ion_config.resume_action() {
+            ResumeAction::Select => default_entry = entry,
+            ResumeAction::Warn => {
+                hibernation::warn(&system_table, &ion_config.entries[entry]);
+                countdown = false;
+            }
+        }
+    }
+
+    let (selected_entry, kernel, dtb, initrd) = loop {
+        let menu_start = profile::start(profile::Phase::Menu);
+        let mut selected_entry = match boot_next.take() {
+            Some(entry) => entry,
+            None => menu::init(
+                &system_table,
+                &mut root,
+                &ion_config,
+                countdown,
+                default_entry,
+            ),
+        };
+        profile::finish(profile::Phase::Menu, menu_start);
+
+        if let Some(logo) = ion_config.splash() {
+            show_splash(&system_table, &mut root, logo, ion_config.preserve_logo());
+        }
+
+        // EFI applications are started right away and the menu is shown again once they
+        // exit.
+        if selected_entry.protocol() == config::BootProtocol::Chainload {
+            let started = check_requirements(&selected_entry)
+                .and_then(|_| selected_entry.expand_command_line(&system_table, boot_partition))
+                .and_then(|_| {
+                    chainload(
+                        &system_table,
+                        image_handle,
+                        &mut root,
+                        loaded_image.device(),
+                        &selected_entry,
+                    )
+                });
+
+            if let Err(error) = started {
+                let idle_timeout = ion_config.idle_action().map(|(_, timeout)| timeout);
+                let all_failed = record_failure(&mut failed, &ion_config, &selected_entry);
+
+                report_error(&system_table, &error, idle_timeout, all_failed);
+            }
+
+            countdown = false;
+            continue;
+        }
+
+        // We have to load the kernel before we exit the boot services since we rely on the
+        // simple file system boot services protocol to read the kernel from the disk into
+        // memory.
+        let kernel_read_start = profile::start(profile::Phase::KernelRead);
+        let loaded = check_requirements(&selected_entry)
+            .and_then(|_| selected_entry.expand_command_line(&system_table, boot_partition))
+            .and_then(|_| prepare_kernel(&system_table, &mut root, &selected_entry));
+        profile::finish(profile::Phase::KernelRead, kernel_read_start);
+
+        match loaded {
+            Ok(files) => {
+                config::save_preference(&system_table, "LAST_ENTRY", selected_entry.name());
+
+                let dtb = load_dtb(&system_table, &selected_entry, files.dtb);
+                break (selected_entry, files.kernel, dtb, files.initrd);
+            }
+            Err(error) => {
+                let idle_timeout = ion_config.idle_action().map(|(_, timeout)| timeout);
+                let all_failed = record_failure(&mut failed, &ion_config, &selected_entry);
+
+                report_error(&system_table, &error, idle_timeout, all_failed);
+                countdown = false;
+            }
+        }
+    };
+
+    // The files of the other entries are not needed anymore.
+    preload::discard(&system_table);
+
+    // The devices are enumerated while the firmware still owns the configuration space.
+    let pci_devices = if cfg!(feature = "pci-tag") && selected_entry.pci_tag() {
+        Some(&*pci::enumerate(&system_table).leak())
+    } else {
+        None
+    };
+
+    splash::advance(splash::Milestone::KernelRead);
+    splash::check_for_keypress();
+
+    // This is our last chance to access the boot partition, so save the boot log.
+    logger::save_boot_log(&mut root);
+
+    // The runtime regions can only be collected from the final memory map, but there is no
+    // way to allocate memory for them afterwards.
+    let mut runtime_map = if selected_entry.runtime_remap() {
+        Some(efi::RuntimeMap::new(&system_table))
+    } else {
+        None
+    };
+
+    // The boot information of Multiboot kernels is allocated from the firmware, so that it
+    // shows up in the memory map that is passed to the kernel.
+    #[cfg(target_arch = "x86_64")]
+    let multiboot = match selected_entry.protocol() {
+        config::BootProtocol::Multiboot | config::BootProtocol::Multiboot2 => {
+            Some(protocols::multiboot::prepare(
+                &system_table,
+                image_handle,
+                &selected_entry,
+                kernel,
+                initrd,
+            ))
+        }
+        _ => None,
+    };
+
+    // The same goes for the zero page of Linux kernels.
+    #[cfg(target_arch = "x86_64")]
+    let linux = match selected_entry.protocol() {
+        config::BootProtocol::Linux => Some(protocols::linux::prepare(
+            &system_table,
+            &selected_entry,
+            kernel,
+            initrd,
+        )),
+        _ => None,
+    };
+
+    // Every allocation can split a descriptor of the memory map, so the buffer for the
+    // final memory map is sized after all of the others have been made.
     let mmap_storage = {
         let max_mmap_size =
             system_table.boot_services().memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
@@ -252,26 +898,64 @@ fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
     };
 
     uefi::alloc::exit_boot_services();
+    serial::exit_boot_services();
+    logger::exit_boot_services();
+    input::exit_boot_services();
 
     let (_, mmap) = system_table
         .exit_boot_services(image_handle, mmap_storage)
         .expect_success("ion: failed to exit the boot services");
 
-    logger::clear();
-    logger::flush();
+    if let Some(runtime_map) = runtime_map.as_mut() {
+        runtime_map.collect(mmap.clone());
+    }
+
+    // Keep the splash screen on the screen until we hand off control to the kernel.
+    if !splash::is_active() {
+        logger::clear();
+        logger::flush();
+    }
+
+    let mut allocator = pmm::BootFrameAllocator::new(mmap.clone().copied());
+    let paging_start = profile::start(profile::Phase::PagingSetup);
+    let mut offset_tables = arch::setup_boot_paging(&mut allocator);
+    profile::finish(profile::Phase::PagingSetup, paging_start);
 
-    let mut allocator = pmm::BootFrameAllocator::new(mmap.copied());
-    let mut offset_tables = setup_boot_paging(&mut allocator);
+    splash::advance(splash::Milestone::PagingSetUp);
 
     match selected_entry.protocol() {
-        config::BootProtocol::Stivale2 => {
-            protocols::stivale2::boot(&mut offset_tables, &mut allocator, kernel)
+        config::BootProtocol::Stivale2 => protocols::stivale2::boot(
+            &mut offset_tables,
+            &mut allocator,
+            kernel,
+            dtb,
+            runtime_map,
+            protocols::tags::TagSources { pci_devices },
+            &selected_entry,
+        ),
+
+        #[cfg(target_arch = "x86_64")]
+        config::BootProtocol::Multiboot | config::BootProtocol::Multiboot2 => {
+            protocols::multiboot::boot(
+                multiboot.expect("ion: multiboot boot information not allocated"),
+                &allocator,
+                mmap,
+                kernel,
+                &selected_entry,
+            )
         }
 
-        config::BootProtocol::Stivale => todo!(),
-        config::BootProtocol::Multiboot => todo!(),
-        config::BootProtocol::Multiboot2 => todo!(),
-        config::BootProtocol::Linux => todo!(),
+        #[cfg(target_arch = "x86_64")]
+        config::BootProtocol::Linux => protocols::linux::boot(
+            linux.expect("ion: linux zero page not allocated"),
+            &allocator,
+            mmap,
+            kernel,
+            &selected_entry,
+        ),
+
+        // The other boot protocols are rejected by `prepare_kernel`.
+        protocol => unreachable!("ion: unsupported boot protocol {:?}", protocol),
     }
 
     loop {}
@@ -281,31 +965,15 @@ fn efi_main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
 extern "C" fn rust_begin_unwind(info: &PanicInfo) -> ! {
     unsafe {
         logger::LOGGER.get().map(|l| l.force_unlock());
+        logger::BOOT_LOG.force_unlock();
     }
 
-    let deafult_panic = &format_args!("");
-    let panic_message = info.message().unwrap_or(deafult_panic);
+    panic::show(info);
 
-    log::error!("cpu '0' panicked at '{}'", panic_message);
-
-    if let Some(panic_location) = info.location() {
-        log::error!("{}", panic_location);
+    // Headless machines can still be inspected over the serial console.
+    if serial::is_enabled() {
+        debug::serial_console();
     }
 
-    logger::flush();
-
-    unsafe {
-        asm!("cli");
-
-        loop {
-            asm!("hlt");
-        }
-    }
-}
-
-#[cfg(test)]
-fn test_runner(tests: &[&dyn Fn()]) {
-    for test in tests {
-        test();
-    }
+    arch::halt()
 }
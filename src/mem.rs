@@ -0,0 +1,95 @@
+// Memory copy and fill primitives used when loading kernels. The compiler provided
+// `memcpy` and `memset` copy a byte at a time in freestanding builds, which makes loading
+// large kernels noticeably slow. On x86 CPUs with enhanced REP MOVSB/STOSB (ERMS) the string
+// instructions are used instead, as they move whole cache lines at a time.
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+use spin::Once;
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::{__cpuid_count, __get_cpuid_max};
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::{__cpuid_count, __get_cpuid_max};
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+static ERMS: Once<bool> = Once::new();
+
+/// Returns true if the CPU supports enhanced REP MOVSB/STOSB.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn has_erms() -> bool {
+    *ERMS.call_once(|| {
+        // SAFETY: CPUID is available on every CPU that can run UEFI firmware and the
+        // extended feature leaf is only queried if it is supported.
+        unsafe {
+            if __get_cpuid_max(0).0 < 7 {
+                return false;
+            }
+
+            __cpuid_count(7, 0).ebx & (1 << 9) != 0
+        }
+    })
+}
+
+/// Copies `len` bytes from `src` to `dest`.
+///
+/// ## Safety
+/// Both ranges have to be valid and must not overlap.
+pub unsafe fn copy(dest: *mut u8, src: *const u8, len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if has_erms() {
+        asm!(
+            "rep movsb",
+            inout("rcx") len => _,
+            inout("rdi") dest => _,
+            inout("rsi") src => _,
+            options(nostack, preserves_flags)
+        );
+        return;
+    }
+
+    #[cfg(target_arch = "x86")]
+    if has_erms() {
+        asm!(
+            "rep movsb",
+            inout("ecx") len => _,
+            inout("edi") dest => _,
+            inout("esi") src => _,
+            options(nostack, preserves_flags)
+        );
+        return;
+    }
+
+    core::ptr::copy_nonoverlapping(src, dest, len);
+}
+
+/// Sets `len` bytes starting at `dest` to zero.
+///
+/// ## Safety
+/// The range has to be valid.
+pub unsafe fn zero(dest: *mut u8, len: usize) {
+    #[cfg(target_arch = "x86_64")]
+    if has_erms() {
+        asm!(
+            "rep stosb",
+            inout("rcx") len => _,
+            inout("rdi") dest => _,
+            in("al") 0u8,
+            options(nostack, preserves_flags)
+        );
+        return;
+    }
+
+    #[cfg(target_arch = "x86")]
+    if has_erms() {
+        asm!(
+            "rep stosb",
+            inout("ecx") len => _,
+            inout("edi") dest => _,
+            in("al") 0u8,
+            options(nostack, preserves_flags)
+        );
+        return;
+    }
+
+    core::ptr::write_bytes(dest, 0, len);
+}
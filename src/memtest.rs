@@ -0,0 +1,255 @@
+use core::mem;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType};
+
+use crate::i18n;
+use crate::input;
+use crate::logger::{self, Color};
+use crate::prelude::*;
+
+/// The maximum amount of failing addresses that are listed. Any further failures are only
+/// counted.
+const MAX_REPORTED_FAILURES: usize = 16;
+
+/// The amount of bytes that are tested before checking whether the test has been aborted.
+const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+const FAILURE_COLOR: Color = Color::new(0xFF5555);
+const SUCCESS_COLOR: Color = Color::new(0x55FF55);
+
+/// The patterns that are written to and read back from memory. Each pattern returns the
+/// value of the word at the provided address.
+#[derive(Debug, Clone, Copy)]
+enum Pattern {
+    /// A single set bit that moves one position further with each word.
+    WalkingOnes,
+    /// A single cleared bit that moves one position further with each word.
+    WalkingZeros,
+    /// Each word contains its own address, which detects faulty address lines.
+    AddressInAddress,
+    /// Each word contains the complement of its own address.
+    InverseAddress,
+}
+
+impl Pattern {
+    const ALL: [Pattern; 4] = [
+        Self::WalkingOnes,
+        Self::WalkingZeros,
+        Self::AddressInAddress,
+        Self::InverseAddress,
+    ];
+
+    #[inline]
+    fn value(&self, address: u64) -> u64 {
+        let bit = (address / 8) % 64;
+
+        match self {
+            Self::WalkingOnes => 1 << bit,
+            Self::WalkingZeros => !(1 << bit),
+            Self::AddressInAddress => address,
+            Self::InverseAddress => !address,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::WalkingOnes => "walking ones",
+            Self::WalkingZeros => "walking zeros",
+            Self::AddressInAddress => "address in address",
+            Self::InverseAddress => "inverse address",
+        }
+    }
+}
+
+/// The results of a memory test run.
+struct TestResults {
+    tested_bytes: u64,
+    failures: usize,
+    aborted: bool,
+}
+
+impl TestResults {
+    /// Reports a word at the provided address that did not contain the expected value.
+    fn report_failure(&mut self, address: u64, expected: u64, actual: u64) {
+        if self.failures < MAX_REPORTED_FAILURES {
+            logger::with_fg(FAILURE_COLOR, || {
+                println!(
+                    "  {:#018x}: expected {:#018x}, read {:#018x} (bits {:#018x})",
+                    address,
+                    expected,
+                    actual,
+                    expected ^ actual
+                );
+            });
+
+            logger::flush();
+        }
+
+        self.failures += 1;
+    }
+}
+
+/// Returns true if ESC has been pressed on the keyboard or the serial console. This
+/// function does not block.
+fn abort_requested() -> bool {
+    matches!(input::poll(), Some(Key::Special(ScanCode::ESCAPE)))
+}
+
+/// Writes the provided pattern to the memory from `start` to `end` and verifies it.
+fn test_range(start: u64, end: u64, pattern: Pattern, results: &mut TestResults) {
+    for address in (start..end).step_by(8) {
+        // SAFETY: The range has been allocated from the firmware, so nothing else uses it.
+        // Volatile accesses make sure that the memory is actually written and read back.
+        unsafe { (address as *mut u64).write_volatile(pattern.value(address)) };
+    }
+
+    for address in (start..end).step_by(8) {
+        let expected = pattern.value(address);
+        let actual = unsafe { (address as *const u64).read_volatile() };
+
+        if actual != expected {
+            results.report_failure(address, expected, actual);
+        }
+    }
+}
+
+/// Tests the provided memory region with all of the patterns, in chunks of
+/// [`CHUNK_SIZE`] bytes.
+fn test_region(start: u64, end: u64, results: &mut TestResults) {
+    for pattern in Pattern::ALL.iter() {
+        let mut chunk_start = start;
+
+        while chunk_start < end {
+            if abort_requested() {
+                results.aborted = true;
+                return;
+            }
+
+            let chunk_end = (chunk_start + CHUNK_SIZE).min(end);
+            test_range(chunk_start, chunk_end, *pattern, results);
+
+            chunk_start = chunk_end;
+        }
+    }
+
+    results.tested_bytes += end - start;
+}
+
+/// This function is responsible for testing all of the usable memory with pattern-based
+/// RAM tests (walking bits and address-in-address) and reporting any failing addresses.
+/// Each usable region is allocated from the firmware while it is tested, so that the
+/// memory used by Ion and the firmware itself is left untouched. The function returns
+/// when the user presses ESC after the test has finished.
+pub fn run(system_table: &SystemTable<Boot>) {
+    let boot_services = system_table.boot_services();
+    let strings = i18n::strings();
+
+    // The allocation of the storage buffer itself might split a region, so make some
+    // room for a few extra descriptors.
+    let mmap_size = boot_services.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
+    let mut mmap_storage = vec![0u8; mmap_size];
+
+    let (_, descriptors) = boot_services
+        .memory_map(&mut mmap_storage)
+        .expect_success("memtest: failed to retrieve the memory map");
+
+    // Copy the usable regions, as allocating the regions changes the memory map.
+    let regions = descriptors
+        .filter(|descriptor| descriptor.ty == MemoryType::CONVENTIONAL)
+        .map(|descriptor| (descriptor.phys_start, descriptor.page_count))
+        .collect::<Vec<_>>();
+
+    // Testing large amounts of memory takes a while, so make sure that the firmware does
+    // not reset the machine in the meantime.
+    let _ = boot_services.set_watchdog_timer(0, 0x10000, None);
+
+    logger::clear();
+
+    println!("Ion {} ", env!("CARGO_PKG_VERSION"));
+    println!("{}\n", strings.memtest_header);
+
+    for pattern in Pattern::ALL.iter() {
+        println!("  {}", pattern.name());
+    }
+
+    println!();
+    logger::flush();
+
+    let mut results = TestResults {
+        tested_bytes: 0,
+        failures: 0,
+        aborted: false,
+    };
+
+    for (phys_start, page_count) in regions {
+        // Regions that have been allocated since the memory map was retrieved (e.g. the
+        // map storage itself) cannot be tested.
+        let allocation = boot_services.allocate_pages(
+            AllocateType::Address(phys_start as usize),
+            MemoryType::LOADER_DATA,
+            page_count as usize,
+        );
+
+        if allocation.is_err() {
+            log::debug!("memtest: skipping region at {:#x}", phys_start);
+            continue;
+        }
+
+        let end = phys_start + page_count * 0x1000;
+
+        println!("{:#018x}-{:#018x}", phys_start, end);
+        logger::flush();
+
+        // Null pointers must not be dereferenced, so the first page is never tested.
+        test_region(phys_start.max(0x1000), end, &mut results);
+
+        boot_services
+            .free_pages(phys_start, page_count as usize)
+            .expect_success("memtest: failed to free the tested region");
+
+        if results.aborted {
+            break;
+        }
+    }
+
+    let summary = i18n::format(
+        strings.memtest_summary,
+        &[&(results.tested_bytes / (1024 * 1024)), &results.failures],
+    );
+
+    println!();
+
+    if results.aborted {
+        println!("{}", strings.memtest_aborted);
+    }
+
+    let color = if results.failures == 0 {
+        SUCCESS_COLOR
+    } else {
+        FAILURE_COLOR
+    };
+
+    logger::with_fg(color, || println!("{}", summary));
+    println!("\n{}", strings.memtest_return);
+
+    logger::flush();
+
+    if results.failures != 0 {
+        log::error!(
+            "memtest: {} failing words in {} MiB",
+            results.failures,
+            results.tested_bytes / (1024 * 1024)
+        );
+    }
+
+    loop {
+        if let Key::Special(ScanCode::ESCAPE) = input::wait(system_table) {
+            break;
+        }
+    }
+}
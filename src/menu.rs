@@ -1,135 +1,647 @@
+use core::fmt;
+use core::mem;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use log::LevelFilter;
 use uefi::prelude::*;
-use uefi::proto::console::text::{Input, Key, ScanCode};
-use uefi::table::boot::{EventType, TimerTrigger, Tpl};
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::proto::media::file::Directory;
+use uefi::table::boot::{MemoryDescriptor, MemoryType};
+use uefi::table::runtime::ResetType;
 
+use ion_core::config::{CountdownKeys, IdleAction};
+use ion_core::png;
+
+use crate::arch;
+use crate::bmp::Bitmap;
 use crate::config::{self, ConfigurationEntry};
+use crate::debug;
+use crate::graphics;
+use crate::i18n;
+use crate::input::{self, InputEvent};
+use crate::keymap;
 use crate::logger;
+use crate::memtest;
+use crate::pci;
+use crate::pointer::PointerDevice;
+use crate::preload;
+use crate::smbios;
 
 use crate::config::IonConfig;
 use crate::logger::Color;
 
 use crate::prelude::*;
+use crate::speaker;
+use crate::time;
 
-/// This function is responsible for sleeping the provided amount of `seconds` and if
-/// a special key is pressed in the duration specified, the function will return the keyboard
-/// scancode and quit the timer. Else the function will return [`None`].
-pub fn pit_sleep_and_quit_on_keypress(
-    system_table: &SystemTable<Boot>,
-    seconds: usize,
-) -> Option<ScanCode> {
-    unsafe {
-        // Create a new timer event with the TPL set to callback.
-        let event = system_table
-            .boot_services()
-            .create_event(EventType::TIMER, Tpl::CALLBACK, None)
-            .expect_success("Failed to create timer event");
-
-        // Retrieve the input protocol from the boot services,
-        let input_protocol = system_table
-            .boot_services()
-            .locate_protocol::<Input>()
-            .expect_success("Failed to locate input protocol");
-
-        let key = &mut *input_protocol.get(); // Get the inner cell value
-        let wait_for_key_event = key.wait_for_key_event(); // Get a reference to the wait for key event
-
-        // Initialize the timer event that we created before and set the amount of seconds requested.
-        system_table
-            .boot_services()
-            .set_timer(event, TimerTrigger::Relative(10000000 * seconds as u64))
-            .expect_success("Failed to create timer from event");
-
-        // Loop until the timer finishes or interrupted by a keyboard interrupt.
-        loop {
-            let result = system_table
-                .boot_services()
-                .wait_for_event(&mut [event, wait_for_key_event])
-                .expect_success("Failed add event in wait queue");
-
-            // If the result is equal to zero, that means our timer event has finished and return. Since
-            // we did not retrieve a scancode we return [`None`].
-            if result == 0 {
-                return None;
+/// The time in milliseconds after the start of the countdown during which key presses are
+/// ignored.
+const COUNTDOWN_DEBOUNCE_MS: u64 = 250;
+
+/// The amount of text rows at the bottom of the screen that are reserved for the status
+/// lines of the menu.
+const STATUS_ROWS: usize = 2;
+
+/// The status row that shows the description of the selected entry.
+const COMMENT_ROW: usize = 0;
+
+/// The status row that shows the countdown.
+const COUNTDOWN_ROW: usize = 1;
+
+/// Wrapper around a size in bytes that is displayed in the largest fitting binary unit.
+struct HumanSize(u64);
+
+impl fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let mut size = self.0;
+        let mut unit = 0;
+
+        // Only move to the next unit if the size is still exactly representable, so
+        // that we never display a rounded value.
+        while size >= 1024 && size % 1024 == 0 && unit < UNITS.len() - 1 {
+            size /= 1024;
+            unit += 1;
+        }
+
+        // Use pad so that the width and alignment flags of the formatter are respected.
+        f.pad(&format!("{} {}", size, UNITS[unit]))
+    }
+}
+
+/// Returns a short human readable name for the provided UEFI memory type.
+pub fn memory_type_name(ty: MemoryType) -> &'static str {
+    match ty {
+        MemoryType::RESERVED => "Reserved",
+        MemoryType::LOADER_CODE => "Loader code",
+        MemoryType::LOADER_DATA => "Loader data",
+        MemoryType::BOOT_SERVICES_CODE => "Boot services code",
+        MemoryType::BOOT_SERVICES_DATA => "Boot services data",
+        MemoryType::RUNTIME_SERVICES_CODE => "Runtime services code",
+        MemoryType::RUNTIME_SERVICES_DATA => "Runtime services data",
+        MemoryType::CONVENTIONAL => "Conventional",
+        MemoryType::UNUSABLE => "Unusable",
+        MemoryType::ACPI_RECLAIM => "ACPI reclaimable",
+        MemoryType::ACPI_NON_VOLATILE => "ACPI NVS",
+        MemoryType::MMIO => "MMIO",
+        MemoryType::MMIO_PORT_SPACE => "MMIO port space",
+        MemoryType::PAL_CODE => "PAL code",
+        MemoryType::PERSISTENT_MEMORY => "Persistent memory",
+        _ => "Unknown",
+    }
+}
+
+/// This function is responsible for pretty-printing the UEFI memory map along with the
+/// size of each region and the totals for each memory type. The function returns when
+/// the user presses the ESC key.
+pub fn show_memory_map(system_table: &SystemTable<Boot>) {
+    let boot_services = system_table.boot_services();
+
+    // The allocation of the storage buffer itself might split a region, so make some
+    // room for a few extra descriptors.
+    let mmap_size = boot_services.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
+    let mut mmap_storage = vec![0u8; mmap_size];
+
+    let (_, descriptors) = boot_services
+        .memory_map(&mut mmap_storage)
+        .expect_success("menu: failed to retrieve the memory map");
+
+    let mut descriptors = descriptors.copied().collect::<Vec<_>>();
+    descriptors.sort_unstable_by_key(|descriptor| descriptor.phys_start);
+
+    let mut totals: Vec<(MemoryType, u64)> = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+
+    for descriptor in descriptors.iter() {
+        let size = descriptor.page_count * 0x1000;
+
+        lines.push(format!(
+            "{:#018x}-{:#018x} {:>10} {}",
+            descriptor.phys_start,
+            descriptor.phys_start + size,
+            HumanSize(size),
+            memory_type_name(descriptor.ty)
+        ));
+
+        match totals.iter_mut().find(|(ty, _)| *ty == descriptor.ty) {
+            Some((_, total)) => *total += size,
+            None => totals.push((descriptor.ty, size)),
+        }
+    }
+
+    let total_size = totals.iter().map(|(_, size)| size).sum::<u64>();
+    let usable_size = totals
+        .iter()
+        .filter(|(ty, _)| *ty == MemoryType::CONVENTIONAL)
+        .map(|(_, size)| size)
+        .sum::<u64>();
+
+    let strings = i18n::strings();
+
+    // Append the per-type totals at the end of the listing.
+    lines.push(String::new());
+    lines.push(String::from(strings.memory_map_totals));
+
+    for (ty, size) in totals.iter() {
+        lines.push(format!(
+            "{:>24} {}",
+            memory_type_name(*ty),
+            HumanSize(*size)
+        ));
+    }
+
+    let header = i18n::format(
+        strings.memory_map_header,
+        &[
+            &descriptors.len(),
+            &HumanSize(total_size),
+            &HumanSize(usable_size),
+        ],
+    );
+
+    show_paged(system_table, &header, &lines);
+}
+
+/// Shows the provided lines one screen at a time below the provided header, until ESC is
+/// pressed. The pages can be scrolled with the arrow keys.
+fn show_paged(system_table: &SystemTable<Boot>, header: &str, lines: &[String]) {
+    let strings = i18n::strings();
+
+    // Reserve space for the header and the footer.
+    let rows_per_page = logger::rows().saturating_sub(6).max(1);
+    let page_count = ((lines.len() + rows_per_page - 1) / rows_per_page).max(1);
+    let mut page = 0;
+
+    loop {
+        logger::clear();
+
+        println!("{}\n", header);
+
+        for line in lines.iter().skip(page * rows_per_page).take(rows_per_page) {
+            println!("{}", line);
+        }
+
+        let footer = i18n::format(strings.memory_map_footer, &[&(page + 1), &page_count]);
+        println!("\n{}", footer);
+
+        logger::flush();
+
+        match input::wait(system_table) {
+            Key::Special(ScanCode::UP) | Key::Special(ScanCode::PAGE_UP) => {
+                page = page.saturating_sub(1);
             }
 
-            // Try and read the next keystroke from the input device, if any.
-            let scancode = key.read_key().expect_success("Failed to read key");
-
-            // Check if there is any keystore.
-            if let Some(code) = scancode {
-                // If the key stroke is classified as special we return the keyboard scancode
-                // and quit the timer.
-                if let Key::Special(special) = code {
-                    return Some(special);
-                } else {
-                    // Else if the key stroke is not classified as special, we will still quit the
-                    // timer as the user might want to access the boot menu. To overcome this issue
-                    // we will return a null scancode.
-                    return Some(ScanCode::NULL);
+            Key::Special(ScanCode::DOWN) | Key::Special(ScanCode::PAGE_DOWN) => {
+                if page + 1 < page_count {
+                    page += 1;
                 }
             }
+
+            Key::Special(ScanCode::ESCAPE) => return,
+            _ => (),
         }
     }
 }
 
-/// Helper function used to print the boot menu tree.
-fn print_tree(boot_config: &IonConfig, selected_entry: usize) {
+/// Enumerates the PCI devices and shows them, until ESC is pressed.
+fn show_pci_devices(system_table: &SystemTable<Boot>) {
+    let devices = pci::enumerate(system_table);
+    let mut lines = Vec::new();
+
+    for device in devices.iter() {
+        lines.push(format!(
+            "{:04x}:{:02x}:{:02x}.{} {:04x}:{:04x} {:02x}.{:02x}.{:02x} {}",
+            device.segment,
+            device.bus,
+            device.device,
+            device.function,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if,
+            device.class_name()
+        ));
+
+        for (i, bar) in device.bars.iter().enumerate().filter(|(_, bar)| **bar != 0) {
+            lines.push(format!("{:>16}BAR{} {:#018x}", "", i, bar));
+        }
+    }
+
+    let header = i18n::format(i18n::strings().pci_header, &[&devices.len()]);
+    show_paged(system_table, &header, &lines);
+}
+
+/// The path of the screenshot on the boot partition.
+const SCREENSHOT_PATH: &str = "ion-screenshot.bmp";
+
+/// Saves a screenshot of the screen to the root directory of the boot partition, so
+/// that users can attach the exact rendering of the menu to bug reports.
+fn save_screenshot(root: &mut Directory) {
+    let bitmap = match logger::screenshot() {
+        Some(bitmap) => bitmap,
+        None => {
+            log::warn!("menu: screenshots are not supported by the text console");
+            return;
+        }
+    };
+
+    if crate::write_file(root, SCREENSHOT_PATH, &[&bitmap]) {
+        log::info!("menu: saved the screenshot to {}", SCREENSHOT_PATH);
+    } else {
+        log::warn!("menu: failed to save the screenshot");
+    }
+}
+
+/// Returns the next log level in the cycle toggled by the `v` key.
+fn next_log_level(level: LevelFilter) -> LevelFilter {
+    match level {
+        LevelFilter::Off => LevelFilter::Error,
+        LevelFilter::Error => LevelFilter::Warn,
+        LevelFilter::Warn => LevelFilter::Info,
+        LevelFilter::Info => LevelFilter::Debug,
+        LevelFilter::Debug => LevelFilter::Trace,
+        LevelFilter::Trace => LevelFilter::Off,
+    }
+}
+
+/// This function is responsible for showing the help screen listing all of the boot
+/// menu keybindings. The function returns when the user presses F1 or ESC.
+pub fn show_help(system_table: &SystemTable<Boot>) {
+    // Save the menu, so that it can be restored when the help screen is closed.
+    let menu = logger::save_region(0, logger::rows());
+    logger::clear();
+
+    println!("Ion {} ", env!("CARGO_PKG_VERSION"));
+    let strings = i18n::strings();
+    println!("{}\n", strings.keybindings);
+
+    for (keys, description) in strings.help.iter() {
+        println!("{:>12}  {}", keys, description);
+    }
+
+    println!("\n{}", strings.help_return);
+    logger::flush();
+
+    loop {
+        match input::wait(system_table) {
+            Key::Special(ScanCode::FUNCTION_1) | Key::Special(ScanCode::ESCAPE) => break,
+            _ => (),
+        }
+    }
+
+    logger::restore_region(&menu);
+    logger::flush();
+}
+
+/// The text row at which the first entry of the boot menu tree is printed.
+const MENU_ENTRY_ROW: usize = 3;
+
+/// The icon of an entry, which is either a bitmap or a PNG image.
+enum Icon {
+    Bitmap(Bitmap),
+    Png(png::Image),
+}
+
+impl Icon {
+    fn parse(data: &'static [u8]) -> Option<Self> {
+        Bitmap::parse(data)
+            .map(Self::Bitmap)
+            .or_else(|| png::Image::decode(data).map(Self::Png))
+    }
+
+    /// Returns the width and the height of the icon in pixels.
+    fn size(&self) -> (usize, usize) {
+        match self {
+            Self::Bitmap(bitmap) => (bitmap.width(), bitmap.height()),
+            Self::Png(image) => (image.width(), image.height()),
+        }
+    }
+
+    /// Returns the color of the pixel at the provided position. Transparent pixels are
+    /// blended onto the black background of the menu.
+    fn pixel(&self, x: usize, y: usize) -> Color {
+        match self {
+            Self::Bitmap(bitmap) => bitmap.pixel(x, y),
+            Self::Png(image) => {
+                let pixel = image.pixel(x, y);
+                let alpha = pixel >> 24;
+                let blend = |shift: u32| (((pixel >> shift) & 0xff) * alpha / 0xff) << shift;
+
+                Color::new(blend(16) | blend(8) | blend(0))
+            }
+        }
+    }
+}
+
+/// Loads the icons of the entries, if the menu is displayed graphically. Icons that cannot
+/// be loaded are left out.
+fn load_icons(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    boot_config: &IonConfig,
+) -> Vec<Option<Icon>> {
+    if !logger::can_draw_images() {
+        return Vec::new();
+    }
+
+    let mut load = |path: &'static str| {
+        let icon = crate::read_file(system_table, root, path, MemoryType::LOADER_DATA)
+            .ok()
+            .and_then(Icon::parse);
+
+        if icon.is_none() {
+            log::warn!("menu: failed to load the icon {}", path);
+        }
+
+        icon
+    };
+
+    boot_config
+        .entries
+        .iter()
+        .map(|entry| entry.icon().and_then(&mut load))
+        .collect()
+}
+
+/// The color of the entries that cannot run on the processor.
+const UNSUPPORTED_ENTRY_COLOR: Color = Color::new(0x808080);
+
+/// Helper function used to print the boot menu tree. The icons of the entries are drawn in
+/// front of their names, which are indented if any entry has an icon.
+fn print_tree(boot_config: &IonConfig, selected_entry: usize, icons: &[Option<Icon>]) {
+    let size = logger::line_height();
+    let indent = if icons.iter().any(Option::is_some) {
+        size / logger::char_width() + 2
+    } else {
+        0
+    };
+
     for (i, entry) in boot_config.entries.iter().enumerate() {
+        let name = format!("{:indent$}{}", "", entry.name(), indent = indent);
+
         if i == selected_entry {
             logger::with_fg(Color::new(0xFFAAF), || {
-                println!("{}", entry.name());
+                println!("{}", name);
+            })
+        } else if !entry.missing_features().is_empty() {
+            // The processor does not support the features that the entry requires.
+            logger::with_fg(UNSUPPORTED_ENTRY_COLOR, || {
+                println!("{}", name);
             })
         } else {
-            println!("{}", entry.name());
+            println!("{}", name);
+        }
+
+        // The icons are scaled to the height of a line.
+        if let Some(icon) = icons.get(i).and_then(Option::as_ref) {
+            let (width, height) = icon.size();
+
+            logger::draw_image(0, (MENU_ENTRY_ROW + i) * size, size, size, |x, y| {
+                icon.pixel(x * width / size, y * height / size)
+            });
         }
     }
 
     logger::flush();
 }
 
+/// What a key press does while the countdown is running.
+enum CountdownAction {
+    /// Stop the countdown and wait for the user to pick an entry.
+    Interrupt,
+    /// Boot the default entry right away.
+    Boot,
+    Ignore,
+}
+
+/// Returns what the provided key does while the countdown is running.
+fn countdown_action(keys: CountdownKeys, key: Key) -> CountdownAction {
+    match (keys, key) {
+        (CountdownKeys::Any, _) => CountdownAction::Interrupt,
+
+        (_, Key::Special(ScanCode::ESCAPE))
+        | (_, Key::Special(ScanCode::UP))
+        | (_, Key::Special(ScanCode::DOWN))
+        | (_, Key::Special(ScanCode::LEFT))
+        | (_, Key::Special(ScanCode::RIGHT)) => CountdownAction::Interrupt,
+
+        (_, Key::Printable(c)) if char::from(c) == '\r' => CountdownAction::Boot,
+        _ => CountdownAction::Ignore,
+    }
+}
+
+/// Turns the machine off or restarts it once the boot menu has been left idle.
+fn reset(system_table: &SystemTable<Boot>, action: IdleAction) -> ! {
+    let reset_type = match action {
+        IdleAction::Shutdown => ResetType::Shutdown,
+        _ => ResetType::Cold,
+    };
+
+    log::info!("menu: idle timeout expired ({:?})", action);
+
+    system_table
+        .runtime_services()
+        .reset(reset_type, Status::SUCCESS, None)
+}
+
 /// This function is responsible for intializing the boot menu. This function returns the
-/// index of the selected boot entry.
-pub fn init(system_table: &SystemTable<Boot>, boot_config: IonConfig) -> ConfigurationEntry {
-    let mut selected_entry = 0;
-    let mut done_timeout = false;
+/// index of the selected boot entry. The countdown is skipped if `countdown` is false (e.g.
+/// when returning to the menu after booting an entry failed). The entry with the index
+/// `default_entry` is selected initially.
+pub fn init(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    boot_config: &IonConfig,
+    countdown: bool,
+    default_entry: usize,
+) -> ConfigurationEntry {
+    // Log records written while the menu is shown (e.g. by the preloader) must not scroll
+    // the countdown away.
+    logger::reserve_status_rows(STATUS_ROWS);
+    let entry = run(system_table, root, boot_config, countdown, default_entry);
+    logger::reserve_status_rows(0);
+
+    entry
+}
+
+fn run(
+    system_table: &SystemTable<Boot>,
+    root: &mut Directory,
+    boot_config: &IonConfig,
+    countdown: bool,
+    default_entry: usize,
+) -> ConfigurationEntry {
+    let mut selected_entry = default_entry;
+    let mut done_timeout = !countdown;
+
+    // Machines that are left at the menu (e.g. in kiosks) should not stay powered on
+    // indefinitely.
+    let idle_action = boot_config.idle_action();
+    let idle_timeout = idle_action.map(|(_, timeout)| timeout);
+
+    let icons = load_icons(system_table, root, boot_config);
+
+    let mut pointer = PointerDevice::locate(
+        system_table,
+        logger::display_width(),
+        logger::display_height(),
+    );
+
+    // Let visually impaired users know that the menu is ready for input.
+    if boot_config.beep() {
+        speaker::beep(system_table, 880, 150);
+    }
 
     loop {
         logger::clear();
 
-        println!("Ion {} ", env!("CARGO_PKG_VERSION"));
-        println!("Select entry:\n");
+        let strings = i18n::strings();
+
+        // Screenshots and photos of the menu tell which machine they have been taken on.
+        match smbios::machine() {
+            Some(machine) => println!("Ion {}  {}", env!("CARGO_PKG_VERSION"), machine),
+            None => println!("Ion {} ", env!("CARGO_PKG_VERSION")),
+        }
+
+        println!("{}\n", strings.select_entry);
+
+        print_tree(boot_config, selected_entry, &icons);
 
-        print_tree(&boot_config, selected_entry);
+        println!("\n{}", strings.memory_map_hint);
+        println!("{}", strings.help_hint);
+        println!("{}", i18n::format(strings.log_level, &[&logger::level()]));
+
+        // Show the description of the selected entry in the status line above the
+        // countdown.
+        logger::set_status(COMMENT_ROW, boot_config.entries[selected_entry].comment());
+
+        logger::flush();
 
         if !done_timeout {
-            for i in (0..boot_config.timeout()).rev() {
-                logger::set_cursor_pos(0, logger::display_height() - 24);
-                logger::set_scroll_lock(true);
+            let mut interrupted = false;
+
+            // Read the files of the default entry while waiting, so that it can be booted
+            // right away once the countdown runs out.
+            let mut preloader = preload::Preloader::new(&boot_config.entries[selected_entry]);
+            let mut idle = || preloader.step(system_table, root);
 
-                println!(
-                    "Booting automatically in {}, press any key to stop the countdown...",
-                    i
-                );
+            let countdown_start = time::timestamp_ms();
+
+            'countdown: for i in (0..boot_config.timeout()).rev() {
+                logger::set_status(COUNTDOWN_ROW, &i18n::format(strings.autoboot, &[&i]));
 
                 logger::flush();
-                logger::set_scroll_lock(false);
 
-                if pit_sleep_and_quit_on_keypress(system_table, 1).is_some() {
-                    break;
+                let second_start = time::timestamp_ms();
+
+                loop {
+                    let remaining = 1000u64.saturating_sub(time::elapsed_ms(second_start));
+
+                    let key =
+                        match input::wait_timeout(system_table, None, Some(remaining), &mut idle) {
+                            InputEvent::Key(key) => key,
+                            _ => break,
+                        };
+
+                    // Keys that are pressed right away are most likely left over from the
+                    // firmware (e.g. a bounced Enter from its boot menu).
+                    if time::elapsed_ms(countdown_start) < COUNTDOWN_DEBOUNCE_MS {
+                        continue;
+                    }
+
+                    match countdown_action(boot_config.countdown_keys(), key) {
+                        CountdownAction::Interrupt => {
+                            interrupted = true;
+                            break 'countdown;
+                        }
+
+                        CountdownAction::Boot => break 'countdown,
+                        CountdownAction::Ignore => (),
+                    }
                 }
             }
 
+            preloader.cancel(system_table);
+
+            // Boot the default entry once the countdown runs out. A timeout of 0 disables
+            // the countdown.
+            if !interrupted && boot_config.timeout() != 0 {
+                return boot_config.entries[selected_entry].clone();
+            }
+
             done_timeout = true;
             continue;
         }
 
         loop {
-            match config::get_char(system_table) {
+            let pointer_event = pointer.as_ref().map(|p| p.wait_for_input_event());
+
+            // Any input restarts the idle timeout, as every wait starts it anew.
+            let key = match input::wait_timeout(system_table, pointer_event, idle_timeout, || ()) {
+                InputEvent::Key(key) => key,
+                InputEvent::Timeout => match idle_action {
+                    Some((IdleAction::BootDefault, _)) => {
+                        log::info!("menu: idle timeout expired, booting the default entry");
+                        return boot_config.entries[default_entry].clone();
+                    }
+
+                    Some((action, _)) => reset(system_table, action),
+                    None => continue,
+                },
+                InputEvent::Other => {
+                    // The additional event is only passed if we have a pointer device.
+                    let pointer = pointer.as_mut().unwrap();
+                    let clicked = pointer.poll();
+                    let (x, y) = pointer.position();
+
+                    logger::set_pointer_pos(x, y);
+
+                    if !clicked {
+                        continue;
+                    }
+
+                    let row = y / logger::line_height();
+                    let memory_map_row = MENU_ENTRY_ROW + boot_config.entries.len() + 1;
+
+                    match row.checked_sub(MENU_ENTRY_ROW) {
+                        // Clicking on the selected entry boots it, clicking on any other
+                        // entry selects it.
+                        Some(entry) if entry == selected_entry => {
+                            return boot_config.entries[selected_entry].clone();
+                        }
+
+                        Some(entry) if entry < boot_config.entries.len() => {
+                            selected_entry = entry;
+                            break;
+                        }
+
+                        _ if row == memory_map_row => {
+                            show_memory_map(system_table);
+                            break;
+                        }
+
+                        _ => continue,
+                    }
+                }
+            };
+
+            match key {
                 Key::Special(code) => match code {
                     ScanCode::UP => {
-                        selected_entry = selected_entry
-                            .checked_sub(1)
-                            .unwrap_or(boot_config.entries.len() - 1);
+                        selected_entry = selected_entry.checked_sub(1).unwrap_or_else(|| {
+                            // Signal that the selection wrapped around to the last entry.
+                            if boot_config.beep() {
+                                speaker::beep(system_table, 440, 50);
+                            }
+
+                            boot_config.entries.len() - 1
+                        });
 
                         // Breaking out of this loop will cause the parent draw loop to
                         // continue.
@@ -141,6 +653,11 @@ pub fn init(system_table: &SystemTable<Boot>, boot_config: IonConfig) -> Configu
 
                         if selected_entry >= boot_config.entries.len() {
                             selected_entry = 0;
+
+                            // Signal that the selection wrapped around to the first entry.
+                            if boot_config.beep() {
+                                speaker::beep(system_table, 440, 50);
+                            }
                         }
 
                         // Breaking out of this loop will cause the parent draw loop to
@@ -148,6 +665,13 @@ pub fn init(system_table: &SystemTable<Boot>, boot_config: IonConfig) -> Configu
                         break;
                     }
 
+                    ScanCode::FUNCTION_1 => {
+                        show_help(system_table);
+                        break;
+                    }
+
+                    ScanCode::FUNCTION_12 => save_screenshot(root),
+
                     _ => (),
                 },
 
@@ -161,6 +685,83 @@ pub fn init(system_table: &SystemTable<Boot>, boot_config: IonConfig) -> Configu
                             return boot_config.entries[selected_entry].clone();
                         }
 
+                        'm' | 'M' => {
+                            show_memory_map(system_table);
+                            break;
+                        }
+
+                        't' | 'T' => {
+                            memtest::run(system_table);
+                            break;
+                        }
+
+                        'p' | 'P' => {
+                            show_pci_devices(system_table);
+                            break;
+                        }
+
+                        'c' | 'C' => {
+                            show_paged(
+                                system_table,
+                                i18n::strings().cpu_header,
+                                &arch::cpu_summary(),
+                            );
+                            break;
+                        }
+
+                        'v' | 'V' => {
+                            let level = next_log_level(logger::level());
+
+                            logger::set_level(level);
+                            config::save_preference(system_table, "LOG_LEVEL", level.as_str());
+                            break;
+                        }
+
+                        'k' | 'K' => {
+                            let keymap = keymap::active().next();
+
+                            keymap::set(keymap);
+                            config::save_preference(system_table, "KEYMAP", keymap.as_str());
+                            log::info!("menu: switched the keymap to {}", keymap.as_str());
+                            break;
+                        }
+
+                        'r' | 'R' => {
+                            let (width, height) = match graphics::next_resolution() {
+                                Some(resolution) => resolution,
+                                None => continue,
+                            };
+
+                            match crate::switch_mode(system_table, Some((width, height))) {
+                                Ok(()) => {
+                                    let resolution = format!("{}x{}", width, height);
+                                    config::save_preference(
+                                        system_table,
+                                        "RESOLUTION",
+                                        &resolution,
+                                    );
+                                }
+
+                                Err(error) => log::warn!("graphics: {}", error),
+                            }
+
+                            if let Some(pointer) = pointer.as_mut() {
+                                pointer.resize(logger::display_width(), logger::display_height());
+                            }
+
+                            break;
+                        }
+
+                        ':' => {
+                            let entry_count = boot_config.entries.len();
+
+                            if let Some(entry) = debug::console(system_table, entry_count) {
+                                return boot_config.entries[entry].clone();
+                            }
+
+                            break;
+                        }
+
                         _ => (),
                     }
                 }
@@ -0,0 +1,199 @@
+use uefi::prelude::*;
+use uefi::table::runtime::{VariableAttributes, VariableVendor};
+use uefi::{CStr16, Guid};
+
+/// The vendor GUID under which Ion stores its own variables.
+pub const ION_VENDOR: VariableVendor = VariableVendor(Guid::from_values(
+    0x4e2b64d1,
+    0x5c0e,
+    0x4a53,
+    0x9f27,
+    [0x6c, 0x1d, 0x0b, 0x8a, 0x9e, 0x33],
+));
+
+/// The vendor GUID of the variables defined by the UEFI specification.
+pub const GLOBAL_VENDOR: VariableVendor = VariableVendor::GLOBAL_VARIABLE;
+
+/// The maximum length of a variable name, including the null terminator.
+const MAX_NAME_LEN: usize = 64;
+
+/// The `OsIndications` bit that requests the firmware to stop in its setup UI on the next
+/// boot.
+pub const OS_INDICATION_BOOT_TO_FW_UI: u64 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The variable name is too long or cannot be represented in UCS-2.
+    InvalidName,
+    /// The variable does not exist.
+    NotFound,
+    /// The variable does not fit into the provided buffer or does not have the size of
+    /// the requested type. Contains the actual size of the variable, if it is known.
+    InvalidSize(Option<usize>),
+    /// The contents of the variable are not valid for the requested type.
+    InvalidValue,
+    /// The firmware returned an unexpected error.
+    Firmware(Status),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<uefi::Error> for Error {
+    fn from(error: uefi::Error) -> Self {
+        match error.status() {
+            Status::NOT_FOUND => Self::NotFound,
+            Status::BUFFER_TOO_SMALL => Self::InvalidSize(None),
+            status => Self::Firmware(status),
+        }
+    }
+}
+
+/// Encodes the provided variable name as a null terminated UCS-2 string into the
+/// provided buffer.
+fn encode_name<'a>(name: &str, buf: &'a mut [u16; MAX_NAME_LEN]) -> Result<&'a CStr16> {
+    let mut len = 0;
+
+    for c in name.encode_utf16() {
+        if len == MAX_NAME_LEN - 1 {
+            return Err(Error::InvalidName);
+        }
+
+        buf[len] = c;
+        len += 1;
+    }
+
+    buf[len] = 0;
+    CStr16::from_u16_with_nul(&buf[..=len]).map_err(|_| Error::InvalidName)
+}
+
+/// Reads the variable with the provided name into the provided buffer and returns its
+/// size.
+pub fn read(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+    buf: &mut [u8],
+) -> Result<usize> {
+    let mut name_buf = [0; MAX_NAME_LEN];
+    let name = encode_name(name, &mut name_buf)?;
+
+    let runtime_services = system_table.runtime_services();
+
+    match runtime_services.get_variable(name, vendor, buf) {
+        Ok(completion) => Ok(completion.unwrap().0),
+        Err(error) if error.status() == Status::BUFFER_TOO_SMALL => {
+            let size = runtime_services
+                .get_variable_size(name, vendor)
+                .ok()
+                .map(|completion| completion.unwrap());
+
+            Err(Error::InvalidSize(size))
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Writes the provided data to the variable with the provided name. Ion's variables are
+/// always non-volatile and accessible at runtime, so that the kernel can read them too.
+pub fn write(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut name_buf = [0; MAX_NAME_LEN];
+    let name = encode_name(name, &mut name_buf)?;
+
+    let attributes = VariableAttributes::NON_VOLATILE
+        | VariableAttributes::BOOTSERVICE_ACCESS
+        | VariableAttributes::RUNTIME_ACCESS;
+
+    system_table
+        .runtime_services()
+        .set_variable(name, vendor, attributes, data)
+        .map(|completion| completion.unwrap())
+        .map_err(Error::from)
+}
+
+/// Deletes the variable with the provided name. Deleting a variable that does not exist is
+/// not an error.
+pub fn delete(system_table: &SystemTable<Boot>, vendor: &VariableVendor, name: &str) -> Result<()> {
+    match write(system_table, vendor, name, &[]) {
+        Err(Error::NotFound) => Ok(()),
+        result => result,
+    }
+}
+
+/// Reads a little endian 64-bit integer from the variable with the provided name.
+pub fn read_u64(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+) -> Result<u64> {
+    let mut value = [0; 8];
+
+    match read(system_table, vendor, name, &mut value)? {
+        8 => Ok(u64::from_le_bytes(value)),
+        size => Err(Error::InvalidSize(Some(size))),
+    }
+}
+
+/// Writes a little endian 64-bit integer to the variable with the provided name.
+pub fn write_u64(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+    value: u64,
+) -> Result<()> {
+    write(system_table, vendor, name, &value.to_le_bytes())
+}
+
+/// Reads an ASCII or UTF-8 string from the variable with the provided name into the
+/// provided buffer. A null terminator is stripped.
+pub fn read_str<'a>(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+    buf: &'a mut [u8],
+) -> Result<&'a str> {
+    let size = read(system_table, vendor, name, buf)?;
+    let value = core::str::from_utf8(&buf[..size]).map_err(|_| Error::InvalidValue)?;
+
+    Ok(value.trim_end_matches('\0'))
+}
+
+/// Writes a string to the variable with the provided name, without a null terminator.
+pub fn write_str(
+    system_table: &SystemTable<Boot>,
+    vendor: &VariableVendor,
+    name: &str,
+    value: &str,
+) -> Result<()> {
+    write(system_table, vendor, name, value.as_bytes())
+}
+
+/// Returns the `OsIndications` bits that are supported by the firmware.
+pub fn os_indications_supported(system_table: &SystemTable<Boot>) -> u64 {
+    read_u64(system_table, &GLOBAL_VENDOR, "OsIndicationsSupported").unwrap_or(0)
+}
+
+/// Sets the provided bits in the `OsIndications` variable, which are processed by the
+/// firmware on the next boot. Fails if the firmware does not support any of the bits.
+pub fn set_os_indications(system_table: &SystemTable<Boot>, bits: u64) -> Result<()> {
+    if os_indications_supported(system_table) & bits != bits {
+        return Err(Error::InvalidValue);
+    }
+
+    let current = match read_u64(system_table, &GLOBAL_VENDOR, "OsIndications") {
+        Ok(value) => value,
+        Err(Error::NotFound) => 0,
+        Err(error) => return Err(error),
+    };
+
+    write_u64(
+        system_table,
+        &GLOBAL_VENDOR,
+        "OsIndications",
+        current | bits,
+    )
+}
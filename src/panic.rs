@@ -0,0 +1,90 @@
+use core::panic::PanicInfo;
+
+use crate::arch;
+use crate::logger::{self, Color};
+use crate::prelude::*;
+use crate::symbols;
+
+/// The amount of log lines preceding the panic that are shown on the panic screen.
+const LOG_CONTEXT_LINES: usize = 10;
+
+const PANIC_COLOR: Color = Color::new(0xFF5555);
+
+/// Prints the provided bytes of the boot log. Non-ASCII bytes are replaced by a question
+/// mark, as we cannot rely on the allocator to decode them while panicking.
+fn print_bytes<'a>(bytes: impl Iterator<Item = &'a u8>) {
+    for byte in bytes {
+        let c = if byte.is_ascii() { *byte as char } else { '?' };
+        print!("{}", c);
+    }
+}
+
+/// Prints the last [`LOG_CONTEXT_LINES`] lines of the boot log.
+fn print_log_context() {
+    let boot_log = logger::BOOT_LOG.lock();
+    let (first, second) = boot_log.contents();
+
+    let bytes = || first.iter().chain(second.iter());
+    let len = first.len() + second.len();
+
+    // Find the start of the oldest line that is shown. The boot log ends with a new line,
+    // so we have to look for one more line break than the amount of lines.
+    let start = bytes()
+        .rev()
+        .enumerate()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(LOG_CONTEXT_LINES)
+        .map_or(0, |(i, _)| len - i);
+
+    print_bytes(bytes().skip(start));
+}
+
+/// This function is responsible for showing the panic screen with the panic message, the
+/// source location, the control registers and the log records preceding the panic, so that
+/// bug reports contain actionable data.
+pub fn show(info: &PanicInfo) {
+    // Make sure that the panic screen is visible even if the log records are hidden by the
+    // splash screen.
+    logger::set_quiet(false);
+    logger::clear();
+
+    let default_panic = &format_args!("");
+    let panic_message = info.message().unwrap_or(default_panic);
+
+    logger::with_fg(PANIC_COLOR, || {
+        println!("Ion {} panicked on cpu '0'\n", env!("CARGO_PKG_VERSION"));
+    });
+
+    println!("{}", panic_message);
+
+    if let Some(location) = info.location() {
+        println!("at {}", location);
+    }
+
+    println!();
+    arch::print_registers();
+
+    logger::with_fg(PANIC_COLOR, || {
+        println!("\nBacktrace:");
+    });
+
+    let mut frame = 0;
+
+    symbols::backtrace(|address| {
+        match symbols::lookup(address) {
+            Some((name, offset)) => {
+                println!("{:>4}: {:#018x} {}+{:#x}", frame, address, name, offset)
+            }
+            None => println!("{:>4}: {:#018x} <unknown>", frame, address),
+        }
+
+        frame += 1;
+    });
+
+    logger::with_fg(PANIC_COLOR, || {
+        println!("\nLast log records:");
+    });
+
+    print_log_context();
+    logger::flush();
+}
@@ -0,0 +1,299 @@
+use core::ffi::c_void;
+use core::ptr;
+
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::{unsafe_guid, Handle, Identify, Protocol};
+
+use crate::acpi;
+use crate::efi;
+
+/// The `EFI_LOCATE_SEARCH_TYPE` used to retrieve the handles that support a protocol.
+const BY_PROTOCOL: u32 = 2;
+
+/// The `EFI_PCI_IO_PROTOCOL_WIDTH` of 32-bit accesses.
+const PCI_IO_WIDTH_UINT32: u32 = 2;
+
+const PCI_VENDOR_ID: u32 = 0x00;
+const PCI_CLASS: u32 = 0x08;
+const PCI_HEADER_TYPE: u32 = 0x0c;
+const PCI_BAR_0: u32 = 0x10;
+
+/// The bit of the header type that is set if the device has more than one function.
+const PCI_MULTIFUNCTION: u8 = 1 << 7;
+
+/// The UEFI PCI I/O protocol, which is installed by the firmware for every PCI function.
+/// Only the members that Ion uses are typed.
+#[repr(C)]
+#[unsafe_guid("4cf5b200-68b8-4ca5-9eec-b23e3f50029a")]
+#[derive(Protocol)]
+struct PciIo {
+    poll_mem: usize,
+    poll_io: usize,
+    mem_read: usize,
+    mem_write: usize,
+    io_read: usize,
+    io_write: usize,
+    pci_read: unsafe extern "efiapi" fn(
+        this: &PciIo,
+        width: u32,
+        offset: u32,
+        count: usize,
+        buffer: *mut c_void,
+    ) -> Status,
+    pci_write: usize,
+    copy_mem: usize,
+    map: usize,
+    unmap: usize,
+    allocate_buffer: usize,
+    free_buffer: usize,
+    flush: usize,
+    get_location: unsafe extern "efiapi" fn(
+        this: &PciIo,
+        segment: &mut usize,
+        bus: &mut usize,
+        device: &mut usize,
+        function: &mut usize,
+    ) -> Status,
+}
+
+/// A PCI function as it is reported to the kernel.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct PciDevice {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision: u8,
+    pub header_type: u8,
+    reserved: u16,
+    /// The raw values of the base address registers, including the type bits. The upper
+    /// half of a 64-bit memory BAR is folded into the BAR itself, in which case the next
+    /// BAR is 0. Only the first two BARs are used by bridges.
+    pub bars: [u64; 6],
+}
+
+impl PciDevice {
+    /// Returns a human readable name of the class of the device.
+    pub fn class_name(&self) -> &'static str {
+        match self.class {
+            0x00 => "Unclassified",
+            0x01 => "Mass storage controller",
+            0x02 => "Network controller",
+            0x03 => "Display controller",
+            0x04 => "Multimedia controller",
+            0x05 => "Memory controller",
+            0x06 => "Bridge",
+            0x07 => "Communication controller",
+            0x08 => "System peripheral",
+            0x09 => "Input device controller",
+            0x0c => "Serial bus controller",
+            0x0d => "Wireless controller",
+            _ => "Other",
+        }
+    }
+}
+
+/// Provides access to the configuration space of a single PCI function.
+enum ConfigSpace<'a> {
+    /// The address of the memory mapped configuration space of the function.
+    Ecam(u64),
+    PciIo(&'a PciIo),
+}
+
+impl ConfigSpace<'_> {
+    fn read_u32(&self, offset: u32) -> u32 {
+        match self {
+            // SAFETY: The ECAM regions are identity mapped by the firmware.
+            Self::Ecam(address) => unsafe {
+                ((address + offset as u64) as *const u32).read_volatile()
+            },
+
+            Self::PciIo(pci_io) => {
+                let mut value = u32::MAX;
+
+                let status = unsafe {
+                    (pci_io.pci_read)(
+                        pci_io,
+                        PCI_IO_WIDTH_UINT32,
+                        offset,
+                        1,
+                        &mut value as *mut u32 as *mut c_void,
+                    )
+                };
+
+                if status.is_error() {
+                    u32::MAX
+                } else {
+                    value
+                }
+            }
+        }
+    }
+
+    /// Reads the function at the provided location. Returns [`None`] if there is no
+    /// function.
+    fn read_device(&self, segment: u16, bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+        let id = self.read_u32(PCI_VENDOR_ID);
+
+        if id & 0xffff == 0xffff {
+            return None;
+        }
+
+        let class = self.read_u32(PCI_CLASS);
+        let header_type = (self.read_u32(PCI_HEADER_TYPE) >> 16) as u8;
+
+        let bar_count = match header_type & !PCI_MULTIFUNCTION {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        };
+
+        let mut bars = [0; 6];
+        let mut i = 0;
+
+        while i < bar_count {
+            let low = self.read_u32(PCI_BAR_0 + i as u32 * 4);
+
+            // A 64-bit memory BAR uses the following BAR for its upper half.
+            if low & 0b111 == 0b100 && i + 1 < bar_count {
+                let high = self.read_u32(PCI_BAR_0 + (i as u32 + 1) * 4);
+
+                bars[i] = ((high as u64) << 32) | low as u64;
+                i += 2;
+            } else {
+                bars[i] = low as u64;
+                i += 1;
+            }
+        }
+
+        Some(PciDevice {
+            segment,
+            bus,
+            device,
+            function,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            revision: class as u8,
+            header_type,
+            reserved: 0,
+            bars,
+        })
+    }
+}
+
+/// Enumerates the PCI functions in the ECAM regions described by the MCFG.
+fn enumerate_ecam(mcfg: &acpi::Mcfg, devices: &mut Vec<PciDevice>) {
+    for region in mcfg.entries() {
+        for bus in region.start_bus..=region.end_bus {
+            for device in 0..32 {
+                for function in 0..8 {
+                    let address = region.base_address
+                        + ((bus as u64) << 20)
+                        + ((device as u64) << 15)
+                        + ((function as u64) << 12);
+
+                    let config = ConfigSpace::Ecam(address);
+
+                    match config.read_device(region.segment_group, bus, device, function) {
+                        Some(found) => {
+                            devices.push(found);
+
+                            if function == 0 && found.header_type & PCI_MULTIFUNCTION == 0 {
+                                break;
+                            }
+                        }
+
+                        // Without function 0 there cannot be any other functions.
+                        None if function == 0 => break,
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Enumerates the PCI functions through the PCI I/O protocol instances of the firmware.
+fn enumerate_pci_io(system_table: &SystemTable<Boot>, devices: &mut Vec<PciDevice>) {
+    let boot_services = system_table.boot_services();
+    let raw = efi::raw_boot_services(boot_services);
+
+    let mut handle_count = 0;
+    let mut handles: *mut Handle = ptr::null_mut();
+
+    let status = unsafe {
+        (raw.locate_handle_buffer)(
+            BY_PROTOCOL,
+            &PciIo::GUID as *const _ as *const c_void,
+            ptr::null(),
+            &mut handle_count,
+            &mut handles,
+        )
+    };
+
+    if status.is_error() {
+        log::warn!("pci: no PCI I/O protocol instances found ({:?})", status);
+        return;
+    }
+
+    // SAFETY: The firmware has allocated a buffer of `handle_count` handles.
+    let handle_slice = unsafe { core::slice::from_raw_parts(handles, handle_count) };
+
+    for handle in handle_slice {
+        let pci_io = match boot_services.handle_protocol::<PciIo>(*handle) {
+            Ok(pci_io) => unsafe { &*pci_io.unwrap().get() },
+            Err(_) => continue,
+        };
+
+        let (mut segment, mut bus, mut device, mut function) = (0, 0, 0, 0);
+        let status = unsafe {
+            (pci_io.get_location)(pci_io, &mut segment, &mut bus, &mut device, &mut function)
+        };
+
+        if status.is_error() {
+            continue;
+        }
+
+        let config = ConfigSpace::PciIo(pci_io);
+
+        if let Some(found) =
+            config.read_device(segment as u16, bus as u8, device as u8, function as u8)
+        {
+            devices.push(found);
+        }
+    }
+
+    boot_services
+        .free_pool(handles as *mut u8)
+        .expect_success("pci: failed to free the handle buffer");
+
+    devices.sort_unstable_by_key(|device| {
+        (device.segment, device.bus, device.device, device.function)
+    });
+}
+
+/// This function is responsible for enumerating all of the PCI functions, through the
+/// ECAM regions described by the MCFG or, if there is no MCFG, through the PCI I/O
+/// protocol. Must be called before exiting the boot services.
+pub fn enumerate(system_table: &SystemTable<Boot>) -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+
+    match acpi::Mcfg::get() {
+        Some(mcfg) => enumerate_ecam(&mcfg, &mut devices),
+        None => enumerate_pci_io(system_table, &mut devices),
+    }
+
+    log::debug!("pci: found {} functions", devices.len());
+    devices
+}
@@ -1,29 +1,20 @@
 use uefi::table::boot::{MemoryDescriptor, MemoryType};
 
+pub use ion_core::mmap::{sanitize_memory_map, MemoryRegion, MemoryRegionType};
+
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::page_table::PageTableEntry;
 use x86_64::structures::paging::*;
-use x86_64::{PhysAddr, VirtAddr};
+use x86_64::{align_down, align_up, PhysAddr, VirtAddr};
 use xmas_elf::program::ProgramHeader;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-#[non_exhaustive]
-#[repr(C)]
-pub enum MemoryRegionType {
-    /// Unused conventional memory, can be used by the kernel.
-    Usable,
-    UnknownUefi(u32),
-}
+use crate::logger;
 
-/// Represent a physical memory region.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-#[repr(C)]
-pub struct MemoryRegion {
-    /// The physical start address of the region.
-    pub start: u64,
-    /// The physical end address (exclusive) of the region.
-    pub end: u64,
-    /// The memory type of the memory region.
-    pub kind: MemoryRegionType,
-}
+/// The UEFI memory type of the buffer that the kernel file is read into. The loaded kernel
+/// segments are mapped directly from this buffer, so it must not be reported as reclaimable
+/// like the rest of the memory allocated by Ion.
+pub const KERNEL_MEMORY_TYPE: MemoryType = MemoryType(0x8000_0000);
 
 pub trait BootMemoryRegion: Copy + core::fmt::Debug {
     /// Returns the physical start address of the region.
@@ -48,62 +39,400 @@ impl<'a> BootMemoryRegion for MemoryDescriptor {
     fn region_type(&self) -> MemoryRegionType {
         match self.ty {
             MemoryType::CONVENTIONAL => MemoryRegionType::Usable,
+            MemoryType::LOADER_CODE
+            | MemoryType::LOADER_DATA
+            | MemoryType::BOOT_SERVICES_CODE
+            | MemoryType::BOOT_SERVICES_DATA => MemoryRegionType::Bootloader,
+            MemoryType::ACPI_RECLAIM => MemoryRegionType::AcpiReclaimable,
+            MemoryType::ACPI_NON_VOLATILE => MemoryRegionType::AcpiNvs,
+            MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => MemoryRegionType::Mmio,
+            MemoryType::UNUSABLE => MemoryRegionType::BadMemory,
+            KERNEL_MEMORY_TYPE => MemoryRegionType::Kernel,
             other => MemoryRegionType::UnknownUefi(other.0),
         }
     }
 }
 
-pub struct BootFrameAllocator<I, D> {
-    #[allow(unused)]
+/// A range of physical memory. Both the start and the (exclusive) end are frame aligned.
+#[derive(Debug, Copy, Clone)]
+struct FrameRange {
+    start: u64,
+    end: u64,
+}
+
+/// A list of disjoint memory ranges sorted by their start address. Adjacent ranges are
+/// always merged.
+struct RangeList<const N: usize> {
+    ranges: [FrameRange; N],
+    len: usize,
+}
+
+impl<const N: usize> RangeList<N> {
+    const fn new() -> Self {
+        Self {
+            ranges: [FrameRange { start: 0, end: 0 }; N],
+            len: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[FrameRange] {
+        &self.ranges[..self.len]
+    }
+
+    fn insert(&mut self, index: usize, range: FrameRange) {
+        assert!(self.len < N, "pmm: too many memory ranges");
+
+        self.ranges.copy_within(index..self.len, index + 1);
+        self.ranges[index] = range;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.ranges.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+
+    /// Adds the provided memory range to the list, merging it with the neighbouring
+    /// ranges.
+    fn add(&mut self, mut start: u64, mut end: u64) {
+        if start >= end {
+            return;
+        }
+
+        let mut index = self
+            .as_slice()
+            .iter()
+            .position(|range| range.start > start)
+            .unwrap_or(self.len);
+
+        if index > 0 && self.ranges[index - 1].end >= start {
+            index -= 1;
+
+            start = self.ranges[index].start;
+            end = end.max(self.ranges[index].end);
+
+            self.remove(index);
+        }
+
+        while index < self.len && self.ranges[index].start <= end {
+            end = end.max(self.ranges[index].end);
+            self.remove(index);
+        }
+
+        self.insert(index, FrameRange { start, end });
+    }
+}
+
+/// Splits the provided memory range into the parts that are covered by the provided ranges
+/// and the parts that are not. The provided function is called for each part in order,
+/// along with whether the part is covered.
+fn split_range<F>(ranges: &[FrameRange], start: u64, end: u64, mut f: F)
+where
+    F: FnMut(u64, u64, bool),
+{
+    let mut pos = start;
+
+    for range in ranges {
+        let covered_start = range.start.max(start);
+        let covered_end = range.end.min(end);
+
+        if covered_start >= covered_end {
+            continue;
+        }
+
+        if pos < covered_start {
+            f(pos, covered_start, false);
+        }
+
+        f(covered_start, covered_end, true);
+        pos = covered_end;
+    }
+
+    if pos < end {
+        f(pos, end, false);
+    }
+}
+
+/// The maximum amount of disjoint free memory ranges tracked by [`BootFrameAllocator`].
+const MAX_FREE_RANGES: usize = 512;
+
+/// The maximum amount of disjoint memory ranges allocated for the kernel image.
+const MAX_KERNEL_RANGES: usize = 64;
+
+/// The end of the physical memory that Ion can access. IA-32 firmware runs with 32-bit
+/// addresses, so the frames above 4 GiB cannot be filled in by Ion.
+#[cfg(target_arch = "x86")]
+const ACCESSIBLE_MEMORY_END: u64 = 1 << 32;
+#[cfg(not(target_arch = "x86"))]
+const ACCESSIBLE_MEMORY_END: u64 = u64::MAX;
+
+/// The physical frame allocator used after exiting the boot services. It keeps a sorted list
+/// of the free memory ranges, so that frames can be freed again and multiple contiguous
+/// frames can be allocated at once.
+pub struct BootFrameAllocator<I> {
     original: I,
-    memory_map: I,
-    current_descriptor: Option<D>,
-    next_frame: PhysFrame,
+    free: RangeList<MAX_FREE_RANGES>,
+    /// The allocated memory ranges that are part of the kernel image, see
+    /// [`BootFrameAllocator::mark_kernel`].
+    kernel: RangeList<MAX_KERNEL_RANGES>,
 }
 
-impl<I, D> BootFrameAllocator<I, D>
+impl<I> BootFrameAllocator<I>
 where
-    I: ExactSizeIterator<Item = D> + Clone,
+    I: ExactSizeIterator + Clone,
     I::Item: BootMemoryRegion,
 {
     pub fn new(memory_map: I) -> Self {
-        let start_frame = PhysFrame::containing_address(PhysAddr::new(0x1000));
-
-        Self {
+        let mut allocator = Self {
             original: memory_map.clone(),
-            memory_map,
-            current_descriptor: None,
-            next_frame: start_frame,
+            free: RangeList::new(),
+            kernel: RangeList::new(),
+        };
+
+        for region in memory_map {
+            if region.region_type() != MemoryRegionType::Usable {
+                continue;
+            }
+
+            // Never hand out the zero frame, as a null pointer is never valid.
+            let start = region.start().as_u64().max(Size4KiB::SIZE);
+            let end = region.start().as_u64() + region.len();
+
+            allocator.free.add(
+                align_up(start, Size4KiB::SIZE),
+                align_down(end, Size4KiB::SIZE),
+            );
         }
+
+        allocator
     }
 
-    pub fn allocate_frame_from_descriptor(&mut self, descriptor: D) -> Option<PhysFrame> {
-        let start_addr = descriptor.start();
-        let start_frame = PhysFrame::containing_address(start_addr);
-        let end_addr = start_addr + descriptor.len();
-        let end_frame = PhysFrame::containing_address(end_addr - 1u64);
+    /// Allocates `count` physically contiguous frames that end at or below `max_addr` and
+    /// whose start address is aligned to `align` bytes. The alignment has to be a power of
+    /// two of at least the frame size.
+    ///
+    /// This is used to satisfy the placement requirements of the boot protocols, e.g. the
+    /// SMP trampoline has to be placed below 1 MiB. Pass [`u64::MAX`] as `max_addr` if the
+    /// frames can be placed anywhere.
+    pub fn allocate_frames(
+        &mut self,
+        count: u64,
+        max_addr: u64,
+        align: u64,
+    ) -> Option<PhysFrameRange> {
+        assert!(count > 0, "pmm: cannot allocate zero frames");
+        assert!(
+            align.is_power_of_two() && align >= Size4KiB::SIZE,
+            "pmm: invalid frame alignment {:#x}",
+            align
+        );
+
+        let size = count.checked_mul(Size4KiB::SIZE)?;
+        let max_addr = max_addr.min(ACCESSIBLE_MEMORY_END);
+
+        for index in 0..self.free.len {
+            let range = self.free.ranges[index];
+
+            let start = align_up(range.start, align);
+
+            // The free ranges are sorted, so none of the following ranges can satisfy the
+            // request either.
+            if start >= max_addr {
+                break;
+            }
+
+            let end = match start.checked_add(size) {
+                Some(end) if end <= range.end && end <= max_addr => end,
+                _ => continue,
+            };
+
+            return Some(self.take(index, start, end));
+        }
+
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames whose start address is aligned to
+    /// `align` bytes, like [`Self::allocate_frames`]. The frames are placed at one of all of
+    /// the possible positions, which is selected by the provided random number.
+    pub fn allocate_random_frames(
+        &mut self,
+        count: u64,
+        align: u64,
+        random: u64,
+    ) -> Option<PhysFrameRange> {
+        assert!(count > 0, "pmm: cannot allocate zero frames");
+        assert!(
+            align.is_power_of_two() && align >= Size4KiB::SIZE,
+            "pmm: invalid frame alignment {:#x}",
+            align
+        );
+
+        let size = count.checked_mul(Size4KiB::SIZE)?;
+
+        // Returns the number of aligned start addresses within the free range at which the
+        // frames can be placed.
+        let positions = |range: &FrameRange| {
+            let start = align_up(range.start, align);
 
-        // Set next_frame to start_frame if its smaller then next_frame.
-        if self.next_frame < start_frame {
-            self.next_frame = start_frame;
+            match start.checked_add(size) {
+                Some(end) if end <= range.end => (range.end - end) / align + 1,
+                _ => 0,
+            }
+        };
+
+        let total: u64 = self.free.as_slice().iter().map(positions).sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut position = random % total;
+
+        for index in 0..self.free.len {
+            let range = self.free.ranges[index];
+            let count = positions(&range);
+
+            if position < count {
+                let start = align_up(range.start, align) + position * align;
+                return Some(self.take(index, start, start + size));
+            }
+
+            position -= count;
         }
 
-        if self.next_frame < end_frame {
-            let frame = self.next_frame;
-            self.next_frame += 1;
+        unreachable!()
+    }
+
+    /// Removes the provided memory range from the free range at the provided index and
+    /// returns its frames.
+    fn take(&mut self, index: usize, start: u64, end: u64) -> PhysFrameRange {
+        let range = self.free.ranges[index];
 
-            Some(frame)
+        // Split the free range into the parts before and after the allocation.
+        if end < range.end {
+            self.free.ranges[index].start = end;
         } else {
-            None
+            self.free.remove(index);
         }
+
+        if range.start < start {
+            self.free.insert(
+                index,
+                FrameRange {
+                    start: range.start,
+                    end: start,
+                },
+            );
+        }
+
+        PhysFrame::range(
+            PhysFrame::containing_address(PhysAddr::new(start)),
+            PhysFrame::containing_address(PhysAddr::new(end)),
+        )
     }
 
-    /// Returns the number of memory regions in the underlying memory map.
+    /// Frees the provided frames, so that they can be allocated again.
     ///
-    /// The function always returns the same value, i.e. the length doesn't
-    /// change after calls to `allocate_frame`.
-    pub fn len(&self) -> usize {
-        self.original.len()
+    /// ## Safety
+    ///
+    /// The caller must ensure that the frames were allocated by this allocator and are no
+    /// longer used.
+    pub unsafe fn deallocate_frames(&mut self, frames: PhysFrameRange) {
+        self.free.add(
+            frames.start.start_address().as_u64(),
+            frames.end.start_address().as_u64(),
+        );
+    }
+
+    /// Marks the provided allocated frames as part of the kernel image, so that they are
+    /// reported as [`MemoryRegionType::Kernel`] instead of being reclaimable.
+    pub fn mark_kernel(&mut self, frames: PhysFrameRange) {
+        self.kernel.add(
+            frames.start.start_address().as_u64(),
+            frames.end.start_address().as_u64(),
+        );
+    }
+
+    /// Calls the provided function for each region of the memory map that is passed to the
+    /// kernel. The usable regions of the original memory map are split into the parts that
+    /// are still free and the parts that were allocated by Ion, which are reported as
+    /// [`MemoryRegionType::Bootloader`] or [`MemoryRegionType::Kernel`].
+    pub fn memory_map<F>(&self, mut f: F)
+    where
+        F: FnMut(MemoryRegion),
+    {
+        for region in self.original.clone() {
+            let start = region.start().as_u64();
+            let end = start + region.len();
+
+            if region.region_type() != MemoryRegionType::Usable {
+                f(MemoryRegion {
+                    start,
+                    end,
+                    kind: region.region_type(),
+                });
+
+                continue;
+            }
+
+            split_range(self.free.as_slice(), start, end, |start, end, free| {
+                if free {
+                    f(MemoryRegion {
+                        start,
+                        end,
+                        kind: MemoryRegionType::Usable,
+                    });
+
+                    return;
+                }
+
+                split_range(self.kernel.as_slice(), start, end, |start, end, kernel| {
+                    let kind = if kernel {
+                        MemoryRegionType::Kernel
+                    } else {
+                        MemoryRegionType::Bootloader
+                    };
+
+                    f(MemoryRegion { start, end, kind });
+                });
+            });
+        }
+    }
+
+    /// Returns the number of regions that [`Self::memory_map`] currently reports. Note that
+    /// allocating or freeing frames might change the number of regions.
+    pub fn memory_map_len(&self) -> usize {
+        let mut len = 0;
+        self.memory_map(|_| len += 1);
+        len
+    }
+
+    /// Collects the memory map including the framebuffer into the provided regions, sorts
+    /// and merges them and returns the number of regions. This is used by the boot
+    /// protocols that build their memory map after the heap is gone. No frames must be
+    /// allocated afterwards, as they would not be reflected in the memory map.
+    pub fn collect_memory_map(&self, regions: &mut [MemoryRegion]) -> usize {
+        let mut len = 0;
+
+        let mut push = |region| {
+            assert!(len < regions.len(), "pmm: memory map buffer is too small");
+
+            regions[len] = region;
+            len += 1;
+        };
+
+        self.memory_map(&mut push);
+
+        if let Some((address, info)) = logger::framebuffer() {
+            push(MemoryRegion {
+                start: address,
+                end: address + info.size() as u64,
+                kind: MemoryRegionType::Framebuffer,
+            });
+        }
+
+        sanitize_memory_map(regions, len)
     }
 
     /// Returns the largest detected physical memory address.
@@ -118,37 +447,206 @@ where
     }
 }
 
-unsafe impl<I, D> FrameAllocator<Size4KiB> for BootFrameAllocator<I, D>
+unsafe impl<I> FrameAllocator<Size4KiB> for BootFrameAllocator<I>
 where
-    I: ExactSizeIterator<Item = D> + Clone,
+    I: ExactSizeIterator + Clone,
     I::Item: BootMemoryRegion,
 {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        if let Some(current_descriptor) = self.current_descriptor {
-            match self.allocate_frame_from_descriptor(current_descriptor) {
-                Some(frame) => return Some(frame),
-                None => {
-                    self.current_descriptor = None;
-                }
-            }
+        self.allocate_frames(1, u64::MAX, Size4KiB::SIZE)
+            .map(|frames| frames.start)
+    }
+}
+
+impl<I> FrameDeallocator<Size4KiB> for BootFrameAllocator<I>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.deallocate_frames(PhysFrame::range(frame, frame + 1));
+    }
+}
+
+/// Returns the next level page table referenced by the provided entry, creating it if the
+/// entry is unused.
+fn next_table<'a>(
+    entry: &'a mut PageTableEntry,
+    phys_offset: VirtAddr,
+    parent_flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<&'a mut PageTable, MapToError<Size4KiB>> {
+    if entry.is_unused() {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let table: *mut PageTable = (phys_offset + frame.start_address().as_u64()).as_mut_ptr();
+
+        // SAFETY: The frame was just allocated, so it is not used for anything else.
+        unsafe { table.write(PageTable::new()) };
+        entry.set_frame(frame, parent_flags);
+    } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return Err(MapToError::ParentEntryHugePage);
+    } else {
+        entry.set_flags(entry.flags() | parent_flags);
+    }
+
+    let table: *mut PageTable = (phys_offset + entry.addr().as_u64()).as_mut_ptr();
+
+    // SAFETY: The entry references a valid page table, which is accessible at the
+    // physical memory offset.
+    Ok(unsafe { &mut *table })
+}
+
+/// Maps `len` bytes of physical memory starting at `phys` to `virt` with the provided
+/// flags. Both addresses have to be page aligned.
+///
+/// Unlike calling `map_to` for each 4 KiB page, the intermediate page tables are only
+/// walked once per 2 MiB, and the parts of the range that are 2 MiB aligned both
+/// physically and virtually are mapped using 2 MiB pages. If the flags contain the PAT bit
+/// (which shares its position with the huge page bit in 4 KiB entries), only 4 KiB pages
+/// are used.
+pub fn map_range(
+    page_table: &mut OffsetPageTable,
+    virt: VirtAddr,
+    phys: PhysAddr,
+    len: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(
+        virt.is_aligned(Size4KiB::SIZE) && phys.is_aligned(Size4KiB::SIZE),
+        "pmm: map_range requires page aligned addresses"
+    );
+
+    let phys_offset = page_table.phys_offset();
+    let level_4_table = page_table.level_4_table();
+
+    let parent_flags = flags
+        & (PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+    let huge_pages = !flags.contains(PageTableFlags::HUGE_PAGE);
+
+    let len = align_up(len, Size4KiB::SIZE);
+    let mut offset = 0;
+
+    while offset < len {
+        let page = Page::<Size4KiB>::containing_address(virt + offset);
+        let frame_addr = phys + offset;
+
+        let level_3_table = next_table(
+            &mut level_4_table[page.p4_index()],
+            phys_offset,
+            parent_flags,
+            frame_allocator,
+        )?;
+
+        let level_2_table = next_table(
+            &mut level_3_table[page.p3_index()],
+            phys_offset,
+            parent_flags,
+            frame_allocator,
+        )?;
+
+        let level_2_entry = &mut level_2_table[page.p2_index()];
+
+        if huge_pages
+            && level_2_entry.is_unused()
+            && page.start_address().is_aligned(Size2MiB::SIZE)
+            && frame_addr.is_aligned(Size2MiB::SIZE)
+            && len - offset >= Size2MiB::SIZE
+        {
+            level_2_entry.set_addr(frame_addr, flags | PageTableFlags::HUGE_PAGE);
+            offset += Size2MiB::SIZE;
+
+            continue;
         }
 
-        // Find next suitable descriptor
-        while let Some(descriptor) = self.memory_map.next() {
-            if descriptor.region_type() != MemoryRegionType::Usable {
-                continue;
+        let level_1_table = next_table(level_2_entry, phys_offset, parent_flags, frame_allocator)?;
+
+        // Fill the level 1 entries up to the end of the table or the range.
+        for level_1_entry in level_1_table.iter_mut().skip(usize::from(page.p1_index())) {
+            if offset >= len {
+                break;
             }
 
-            if let Some(frame) = self.allocate_frame_from_descriptor(descriptor) {
-                self.current_descriptor = Some(descriptor);
-                return Some(frame);
+            if !level_1_entry.is_unused() {
+                return Err(MapToError::PageAlreadyMapped(
+                    PhysFrame::containing_address(level_1_entry.addr()),
+                ));
             }
+
+            level_1_entry.set_addr(phys + offset, flags);
+            offset += Size4KiB::SIZE;
         }
+    }
 
-        None
+    Ok(())
+}
+
+/// The size of the virtual memory covered by a single level 5 page table entry.
+pub const LEVEL_5_ENTRY_SIZE: u64 = 1 << 48;
+
+/// Maps `len` bytes of physical memory starting at `phys` to the 57-bit virtual address
+/// `virt` in the five-level hierarchy referenced by the provided level 5 table, using
+/// [`map_range`] on the level 4 table of each level 5 entry.
+pub fn map_range_level_5(
+    level_5_table: &mut PageTable,
+    virt: u64,
+    phys: PhysAddr,
+    len: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    const HALF_SIZE: u64 = LEVEL_5_ENTRY_SIZE / 2;
+
+    let parent_flags = flags
+        & (PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+
+    let len = align_up(len, Size4KiB::SIZE);
+    let mut offset = 0;
+
+    while offset < len {
+        let address = virt + offset;
+        let index = ((address >> 48) & 0x1ff) as usize;
+
+        let level_4_table = next_table(
+            &mut level_5_table[index],
+            VirtAddr::zero(),
+            parent_flags,
+            frame_allocator,
+        )?;
+
+        // SAFETY: UEFI identity-maps all memory.
+        let mut mapper = unsafe { OffsetPageTable::new(level_4_table, VirtAddr::zero()) };
+
+        // The level 4 table is mapped through a four-level view, in which the upper half
+        // of the level 5 entry is sign extended. This results in the same table indices,
+        // but the range has to be split at the sign bit.
+        let lower = address & (LEVEL_5_ENTRY_SIZE - 1);
+        let size = (HALF_SIZE - (lower & (HALF_SIZE - 1))).min(len - offset);
+
+        map_range(
+            &mut mapper,
+            VirtAddr::new_truncate(lower),
+            phys + offset,
+            size,
+            flags,
+            frame_allocator,
+        )?;
+
+        offset += size;
     }
+
+    Ok(())
 }
 
+/// The size of the virtual memory covered by a single level 4 page table entry.
+pub const LEVEL_4_ENTRY_SIZE: u64 = 512 * Size1GiB::SIZE;
+
+/// The index of the first level 4 entry of the higher half of the address space.
+const HIGHER_HALF_START: usize = 256;
+
 /// Keeps track of used entries in a level 4 page table.
 ///
 /// Useful for determining a free virtual memory block, e.g. for mapping additional data.
@@ -165,44 +663,109 @@ impl UsedLevel4Entries {
             entry_state: [false; 512],
         };
 
-        used.entry_state[0] = true; // TODO: Can we do this dynamically?
-
         for segment in segments {
-            let start_page: Page = Page::containing_address(VirtAddr::new(segment.virtual_addr()));
-            let end_page: Page = Page::containing_address(VirtAddr::new(
-                segment.virtual_addr() + segment.mem_size(),
-            ));
-
-            for p4_index in u64::from(start_page.p4_index())..=u64::from(end_page.p4_index()) {
-                used.entry_state[p4_index as usize] = true;
-            }
+            used.mark_range_as_used(VirtAddr::new(segment.virtual_addr()), segment.mem_size());
         }
 
         used
     }
 
-    /// Returns a unused level 4 entry and marks it as used.
+    /// Marks all level 4 entries covering the provided virtual memory range as used.
+    pub fn mark_range_as_used(&mut self, start: VirtAddr, size: u64) {
+        if size == 0 {
+            return;
+        }
+
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(start + (size - 1));
+
+        for p4_index in u64::from(start_page.p4_index())..=u64::from(end_page.p4_index()) {
+            self.entry_state[p4_index as usize] = true;
+        }
+    }
+
+    /// Returns the indices of the first entries of all runs of `count` contiguous unused
+    /// level 4 entries within the provided half of the address space. A run never crosses
+    /// the boundary between the halves, as the addresses in between are not canonical.
+    fn free_runs(&self, count: usize, higher_half: bool) -> impl Iterator<Item = usize> + '_ {
+        let (start, end) = if higher_half {
+            (HIGHER_HALF_START, self.entry_state.len())
+        } else {
+            (0, HIGHER_HALF_START)
+        };
+
+        assert!(
+            count > 0 && count <= end - start,
+            "pmm: invalid amount of level 4 entries requested"
+        );
+
+        (start..=(end - count)).filter(move |&index| {
+            self.entry_state[index..index + count]
+                .iter()
+                .all(|used| !used)
+        })
+    }
+
+    /// Marks the `count` level 4 entries starting at the provided index as used and returns
+    /// the virtual start address of the first one.
+    fn take_entries(&mut self, index: usize, count: usize) -> VirtAddr {
+        for entry in &mut self.entry_state[index..index + count] {
+            *entry = true;
+        }
+
+        Page::from_page_table_indices_1gib(
+            PageTableIndex::new(index as u16),
+            PageTableIndex::new(0),
+        )
+        .start_address()
+    }
+
+    /// Returns the amount of level 4 entries that are required to map `size` bytes.
+    fn entries_for(size: u64) -> usize {
+        ((size + LEVEL_4_ENTRY_SIZE - 1) / LEVEL_4_ENTRY_SIZE).max(1) as usize
+    }
+
+    /// Returns the virtual start address of enough contiguous unused level 4 entries to map
+    /// `size` bytes and marks them as used. The lower half of the address space is
+    /// preferred.
     ///
-    /// Since this method marks each returned index as used, it can be used multiple times
+    /// Since this method marks the returned entries as used, it can be used multiple times
     /// to determine multiple unused virtual memory regions.
-    pub fn get_free_entry(&mut self) -> PageTableIndex {
-        let (idx, entry) = self
-            .entry_state
-            .iter_mut()
-            .enumerate()
-            .find(|(_, &mut entry)| entry == false)
+    pub fn get_free_address_range(&mut self, size: u64) -> VirtAddr {
+        let count = Self::entries_for(size);
+
+        let index = self
+            .free_runs(count, false)
+            .next()
+            .or_else(|| self.free_runs(count, true).next())
             .expect("no usable level 4 entries found");
 
-        *entry = true;
-        PageTableIndex::new(idx as u16)
+        self.take_entries(index, count)
+    }
+
+    /// Returns the virtual start address of enough contiguous unused level 4 entries in the
+    /// higher half of the address space to map `size` bytes and marks them as used. The
+    /// entries are selected at random among all of the candidates using the provided
+    /// random number.
+    pub fn get_random_higher_half_range(&mut self, size: u64, random: u64) -> VirtAddr {
+        let count = Self::entries_for(size);
+        let candidates = self.free_runs(count, true).count();
+
+        assert_ne!(candidates, 0, "no usable level 4 entries found");
+
+        let index = self
+            .free_runs(count, true)
+            .nth((random % candidates as u64) as usize)
+            .unwrap();
+
+        self.take_entries(index, count)
     }
 
     /// Returns the virtual start address of an unused level 4 entry and marks it as used.
     ///
-    /// This is a convenience method around [`get_free_entry`], so all of its docs applies here
-    /// too.
+    /// This is a convenience method around [`get_free_address_range`], so all of its docs
+    /// applies here too.
     pub fn get_free_address(&mut self) -> VirtAddr {
-        Page::from_page_table_indices_1gib(self.get_free_entry(), PageTableIndex::new(0))
-            .start_address()
+        self.get_free_address_range(LEVEL_4_ENTRY_SIZE)
     }
 }
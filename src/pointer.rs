@@ -0,0 +1,210 @@
+use uefi::prelude::*;
+use uefi::proto::console::pointer::Pointer;
+use uefi::table::boot::Event;
+use uefi::{unsafe_guid, Protocol};
+
+/// The current state of an absolute pointer device.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct AbsolutePointerState {
+    current_x: u64,
+    current_y: u64,
+    current_z: u64,
+    active_buttons: u32,
+}
+
+/// Describes the coordinate range of an absolute pointer device.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct AbsolutePointerMode {
+    absolute_min_x: u64,
+    absolute_min_y: u64,
+    absolute_min_z: u64,
+    absolute_max_x: u64,
+    absolute_max_y: u64,
+    absolute_max_z: u64,
+    attributes: u32,
+}
+
+/// The UEFI Absolute Pointer protocol, used by touch screens and tablets.
+#[repr(C)]
+#[unsafe_guid("8d59d32b-c655-4ae9-9b15-f25904992a43")]
+#[derive(Protocol)]
+struct AbsolutePointer {
+    reset: extern "efiapi" fn(this: &mut AbsolutePointer, extended_verification: bool) -> Status,
+    get_state:
+        extern "efiapi" fn(this: &mut AbsolutePointer, state: *mut AbsolutePointerState) -> Status,
+    wait_for_input: Event,
+    mode: *const AbsolutePointerMode,
+}
+
+/// The first button of an absolute pointer device. For touch screens this bit is set
+/// while the screen is being touched.
+const ABSOLUTE_POINTER_TOUCH_ACTIVE: u32 = 1 << 0;
+
+enum Device {
+    Simple(*mut Pointer<'static>),
+    Absolute(*mut AbsolutePointer),
+}
+
+/// A mouse, touch pad or touch screen used to control the boot menu.
+pub struct PointerDevice {
+    device: Device,
+
+    width: usize,
+    height: usize,
+
+    x: usize,
+    y: usize,
+
+    pressed: bool,
+}
+
+impl PointerDevice {
+    /// Locates the pointer device. Absolute pointer devices are preferred over simple
+    /// pointer devices as they do not require the user to move a cursor around. Returns
+    /// [`None`] if the firmware does not provide any pointer device.
+    pub fn locate(system_table: &SystemTable<Boot>, width: usize, height: usize) -> Option<Self> {
+        let boot_services = system_table.boot_services();
+
+        let device = if let Ok(absolute) = boot_services.locate_protocol::<AbsolutePointer>() {
+            let absolute = absolute.unwrap().get();
+
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            let protocol = unsafe { &mut *absolute };
+            if (protocol.reset)(protocol, false).is_error() {
+                return None;
+            }
+
+            Device::Absolute(absolute)
+        } else if let Ok(simple) = boot_services.locate_protocol::<Pointer>() {
+            let simple = simple.unwrap().get() as *mut Pointer<'static>;
+
+            // SAFETY: The protocol pointer is valid as long as the boot services are active.
+            unsafe { &mut *simple }.reset(false).ok()?;
+
+            Device::Simple(simple)
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            device,
+
+            width,
+            height,
+
+            // Start off with the cursor in the middle of the screen.
+            x: width / 2,
+            y: height / 2,
+
+            pressed: false,
+        })
+    }
+
+    /// Returns the event that is signaled when there is input available from the
+    /// pointer device.
+    pub fn wait_for_input_event(&self) -> Event {
+        // SAFETY: The protocol pointers are valid as long as the boot services are active.
+        unsafe {
+            match self.device {
+                Device::Simple(simple) => (*simple).wait_for_input_event(),
+                Device::Absolute(absolute) => (*absolute).wait_for_input,
+            }
+        }
+    }
+
+    /// Updates the size of the screen after the mode has been changed. The pointer is
+    /// kept inside of the new screen.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+
+        self.x = self.x.min(width.saturating_sub(1));
+        self.y = self.y.min(height.saturating_sub(1));
+    }
+
+    /// Returns the current position of the pointer in pixels.
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    /// Reads the state of the pointer device and updates the pointer position. Returns
+    /// true if the primary button was clicked (or the screen was touched) since the last
+    /// time the state was read.
+    pub fn poll(&mut self) -> bool {
+        // SAFETY: The protocol pointers are valid as long as the boot services are active.
+        let pressed = unsafe {
+            match self.device {
+                Device::Simple(simple) => {
+                    let simple = &mut *simple;
+                    let resolution = simple.mode().resolution;
+
+                    let state = match simple.read_state() {
+                        Ok(state) => match state.unwrap() {
+                            Some(state) => state,
+                            None => return false,
+                        },
+
+                        Err(_) => return false,
+                    };
+
+                    // The resolution is specified in counts per millimeter, move the cursor
+                    // by 4 pixels per millimeter of movement.
+                    let dx = state.relative_movement.0 as i64 * 4 / resolution.0.max(1) as i64;
+                    let dy = state.relative_movement.1 as i64 * 4 / resolution.1.max(1) as i64;
+
+                    self.x = (self.x as i64 + dx).clamp(0, self.width as i64 - 1) as usize;
+                    self.y = (self.y as i64 + dy).clamp(0, self.height as i64 - 1) as usize;
+
+                    state.button.0
+                }
+
+                Device::Absolute(absolute) => {
+                    let absolute = &mut *absolute;
+                    let mode = &*absolute.mode;
+                    let mut state = AbsolutePointerState::default();
+
+                    if (absolute.get_state)(absolute, &mut state).is_error() {
+                        return false;
+                    }
+
+                    // The firmware may report coordinates outside of the range of the
+                    // device, so they are clamped before being scaled.
+                    let range_x = mode
+                        .absolute_max_x
+                        .saturating_sub(mode.absolute_min_x)
+                        .max(1);
+                    let range_y = mode
+                        .absolute_max_y
+                        .saturating_sub(mode.absolute_min_y)
+                        .max(1);
+
+                    let x = state
+                        .current_x
+                        .saturating_sub(mode.absolute_min_x)
+                        .min(range_x);
+                    let y = state
+                        .current_y
+                        .saturating_sub(mode.absolute_min_y)
+                        .min(range_y);
+
+                    // Scale the device coordinates to the screen coordinates.
+                    let x = (x as u128 * self.width as u128 / range_x as u128) as usize;
+                    let y = (y as u128 * self.height as u128 / range_y as u128) as usize;
+
+                    self.x = x.min(self.width.saturating_sub(1));
+                    self.y = y.min(self.height.saturating_sub(1));
+
+                    state.active_buttons & ABSOLUTE_POINTER_TOUCH_ACTIVE != 0
+                }
+            }
+        };
+
+        // Only report a click on the transition from released to pressed.
+        let clicked = pressed && !self.pressed;
+        self.pressed = pressed;
+
+        clicked
+    }
+}
@@ -0,0 +1,198 @@
+// Preloading of the files of the default entry while the countdown of the boot menu runs.
+// UEFI does not provide threads, so the files are read in small chunks whenever the menu
+// wakes up to poll for input. Once the entry is booted its files are taken from the cache
+// instead of being read from the disk again.
+
+use alloc::vec::Vec;
+
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile};
+use uefi::table::boot::{AllocateType, MemoryType};
+
+use crate::config::{self, ConfigurationEntry};
+use crate::pmm;
+
+/// The amount of bytes read each time the menu wakes up, which keeps the menu responsive
+/// while still finishing typical kernels within a few seconds.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A file that has been read completely.
+struct CachedFile {
+    uri: &'static str,
+    memory_type: MemoryType,
+    data: &'static [u8],
+    pages: usize,
+}
+
+static CACHE: SpinMutex<Vec<CachedFile>> = SpinMutex::new(Vec::new());
+
+/// A file that is being read.
+struct PendingFile {
+    uri: &'static str,
+    memory_type: MemoryType,
+    file: RegularFile,
+    buffer: &'static mut [u8],
+    pages: usize,
+    len: usize,
+}
+
+/// Reads the files of an entry in chunks.
+pub struct Preloader {
+    /// The files that have not been opened yet, along with the memory type that they are
+    /// read into.
+    queue: Vec<(&'static str, MemoryType)>,
+    current: Option<PendingFile>,
+}
+
+impl Preloader {
+    /// Creates a preloader for the kernel, the initrd and the device tree blob of the
+    /// provided entry. The memory types match the ones that the files are read into when
+    /// the entry is booted.
+    pub fn new(entry: &ConfigurationEntry) -> Self {
+        let mut queue = Vec::new();
+
+        if !entry.path().is_empty() {
+            queue.push((entry.path(), pmm::KERNEL_MEMORY_TYPE));
+        }
+
+        for path in entry.initrd_path().iter().chain(entry.dtb_path().iter()) {
+            queue.push((*path, MemoryType::LOADER_DATA));
+        }
+
+        // Files that have been cached before (e.g. on a previous countdown) are skipped.
+        let cache = CACHE.lock();
+        queue.retain(|(uri, memory_type)| {
+            !cache
+                .iter()
+                .any(|cached| cached.uri == *uri && cached.memory_type == *memory_type)
+        });
+
+        Self {
+            queue,
+            current: None,
+        }
+    }
+
+    fn open(
+        system_table: &SystemTable<Boot>,
+        root: &mut Directory,
+        uri: &'static str,
+        memory_type: MemoryType,
+    ) -> Option<PendingFile> {
+        let parsed_uri = config::parse_uri(uri).ok()?;
+        let directory = config::handle_uri_redirect(system_table, &parsed_uri, root).ok()?;
+
+        let handle = directory
+            .open(parsed_uri.path(), FileMode::Read, FileAttribute::empty())
+            .ok()?
+            .unwrap();
+
+        let mut file = unsafe { RegularFile::new(handle) };
+
+        let mut info_buf = [0; 0x100];
+        let file_size = file
+            .get_info::<FileInfo>(&mut info_buf)
+            .ok()?
+            .unwrap()
+            .file_size();
+
+        let pages = file_size as usize / 0x1000 + 1;
+        let start = system_table
+            .boot_services()
+            .allocate_pages(AllocateType::AnyPages, memory_type, pages)
+            .ok()?
+            .unwrap();
+
+        Some(PendingFile {
+            uri,
+            memory_type,
+            file,
+            buffer: unsafe { core::slice::from_raw_parts_mut(start as *mut u8, pages * 0x1000) },
+            pages,
+            len: 0,
+        })
+    }
+
+    /// Reads the next chunk of the files. Files that cannot be opened are skipped, as
+    /// the error is reported once the entry is booted.
+    pub fn step(&mut self, system_table: &SystemTable<Boot>, root: &mut Directory) {
+        if self.current.is_none() {
+            if self.queue.is_empty() {
+                return;
+            }
+
+            let (uri, memory_type) = self.queue.remove(0);
+            self.current = Self::open(system_table, root, uri, memory_type);
+        }
+
+        let pending = match self.current.as_mut() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let requested = CHUNK_SIZE.min(pending.buffer.len() - pending.len);
+        let chunk = &mut pending.buffer[pending.len..pending.len + requested];
+
+        let read = match pending.file.read(chunk) {
+            Ok(read) => read.unwrap(),
+            Err(_) => {
+                let pending = self.current.take().unwrap();
+                pending.file.close();
+                free(system_table, pending.buffer.as_ptr() as u64, pending.pages);
+                return;
+            }
+        };
+
+        pending.len += read;
+
+        // The buffer is larger than the file, so a short read marks its end.
+        if read < requested {
+            let pending = self.current.take().unwrap();
+            pending.file.close();
+
+            log::debug!("preload: {} ({} bytes)", pending.uri, pending.len);
+
+            CACHE.lock().push(CachedFile {
+                uri: pending.uri,
+                memory_type: pending.memory_type,
+                data: &pending.buffer[..pending.len],
+                pages: pending.pages,
+            });
+        }
+    }
+
+    /// Stops preloading. The files that have already been read stay cached.
+    pub fn cancel(mut self, system_table: &SystemTable<Boot>) {
+        if let Some(pending) = self.current.take() {
+            pending.file.close();
+            free(system_table, pending.buffer.as_ptr() as u64, pending.pages);
+        }
+    }
+}
+
+fn free(system_table: &SystemTable<Boot>, address: u64, pages: usize) {
+    system_table
+        .boot_services()
+        .free_pages(address, pages)
+        .expect_success("preload: failed to free the file buffer");
+}
+
+/// Removes the file at the provided URI from the cache and returns its contents, if it has
+/// been read into memory of the provided type.
+pub fn take(uri: &str, memory_type: MemoryType) -> Option<&'static [u8]> {
+    let mut cache = CACHE.lock();
+    let index = cache
+        .iter()
+        .position(|cached| cached.uri == uri && cached.memory_type == memory_type)?;
+
+    Some(cache.remove(index).data)
+}
+
+/// Frees the files that have been preloaded but were not used, e.g. because another entry
+/// has been booted.
+pub fn discard(system_table: &SystemTable<Boot>) {
+    for cached in CACHE.lock().drain(..) {
+        free(system_table, cached.data.as_ptr() as u64, cached.pages);
+    }
+}
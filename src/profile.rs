@@ -0,0 +1,108 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::time;
+
+/// The phases of the boot process whose duration is measured.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    ConfigLoad,
+    /// The time spent in the boot menu, including the autoboot countdown.
+    Menu,
+    KernelRead,
+    PagingSetup,
+    /// Loading the kernel ELF file into its address space.
+    KernelLoad,
+    TagConstruction,
+}
+
+impl Phase {
+    pub const COUNT: usize = 6;
+
+    const ALL: [Phase; Self::COUNT] = [
+        Self::ConfigLoad,
+        Self::Menu,
+        Self::KernelRead,
+        Self::PagingSetup,
+        Self::KernelLoad,
+        Self::TagConstruction,
+    ];
+
+    /// Returns the name of the phase, which is also shown in front of the log records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ConfigLoad => "config load",
+            Self::Menu => "menu",
+            Self::KernelRead => "kernel read",
+            Self::PagingSetup => "paging setup",
+            Self::KernelLoad => "kernel load",
+            Self::TagConstruction => "tag construction",
+        }
+    }
+}
+
+/// The duration of each phase in microseconds, indexed by [`Phase`].
+static DURATIONS: [AtomicU64; Phase::COUNT] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// The index of the phase that has been started last, or [`NO_PHASE`] if no phase has been
+/// started yet.
+static CURRENT: AtomicUsize = AtomicUsize::new(NO_PHASE);
+
+const NO_PHASE: usize = usize::MAX;
+
+/// Marks the start of the provided phase. Returns the timestamp that is passed to
+/// [`finish`].
+#[inline]
+pub fn start(phase: Phase) -> u64 {
+    CURRENT.store(phase as usize, Ordering::Relaxed);
+    time::timestamp_us()
+}
+
+/// Returns the phase that has been started last, or [`None`] if Ion is still initializing.
+pub fn current() -> Option<Phase> {
+    Phase::ALL.get(CURRENT.load(Ordering::Relaxed)).copied()
+}
+
+/// Records that the provided phase, which started at the provided timestamp (see
+/// [`start`]), has finished. Phases that are entered more than once are accumulated.
+pub fn finish(phase: Phase, start: u64) {
+    let duration = time::timestamp_us().saturating_sub(start);
+    DURATIONS[phase as usize].fetch_add(duration, Ordering::Relaxed);
+}
+
+/// Returns the duration of each phase in microseconds, indexed by [`Phase`].
+pub fn durations() -> [u64; Phase::COUNT] {
+    let mut durations = [0; Phase::COUNT];
+
+    for (duration, recorded) in durations.iter_mut().zip(DURATIONS.iter()) {
+        *duration = recorded.load(Ordering::Relaxed);
+    }
+
+    durations
+}
+
+/// Logs the duration of each phase and the total time since Ion has been entered.
+pub fn log_summary() {
+    for (phase, duration) in Phase::ALL.iter().zip(durations().iter()) {
+        log::info!(
+            "profile: {:<16} {:>6}.{:03} ms",
+            phase.name(),
+            duration / 1000,
+            duration % 1000
+        );
+    }
+
+    let total = time::timestamp_us();
+    log::info!(
+        "profile: {:<16} {:>6}.{:03} ms",
+        "total",
+        total / 1000,
+        total % 1000
+    );
+}
@@ -0,0 +1,482 @@
+// The Linux x86 boot protocol. The protected-mode part of the bzImage is loaded while the
+// boot services are still active and entered through its 64-bit entry point after they
+// have been exited, with the zero page (`struct boot_params`) in RSI. The zero page is
+// filled in the same way as the EFI stub of the kernel would, so that the early console,
+// `efifb` and the EFI runtime services work and all of the memory is reported.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType};
+
+use ion_core::linux::{self, BootParams, E820Entry, Placement, ScreenInfo, SetupHeader};
+
+use crate::acpi;
+use crate::arch::gdt;
+use crate::config::ConfigurationEntry;
+use crate::efi;
+use crate::entropy;
+use crate::error::IonError;
+use crate::logger;
+use crate::mem;
+use crate::pmm::{BootFrameAllocator, BootMemoryRegion, MemoryRegion, MemoryRegionType};
+use crate::profile;
+use crate::splash;
+
+use x86_64::VirtAddr;
+
+/// The physical address that the protected-mode kernel has been loaded at by [`load`].
+static KERNEL_ADDRESS: AtomicU64 = AtomicU64::new(0);
+
+/// The highest address that is accessible through the 32-bit fields of the zero page.
+const MAX_ADDRESS: u64 = 0xffff_ffff;
+
+/// The amount of additional memory map entries that are reserved, as the allocations that
+/// follow and the frames allocated for Ion's page tables might split memory regions.
+const MEMORY_MAP_SLACK: usize = 16;
+
+/// The size of the random seed that is passed to the kernel.
+const RNG_SEED_SIZE: usize = 32;
+
+/// The GDT required by the 64-bit boot protocol, with a flat 64-bit code segment at 0x10
+/// (`__BOOT_CS`) and a flat data segment at 0x18 (`__BOOT_DS`).
+const GDT: [u64; 4] = [0, 0, 0x00af_9a00_0000_ffff, 0x00cf_9200_0000_ffff];
+
+/// Returns the memory map entry type of the provided region type. The memory used by Ion is
+/// reported as usable, as the kernel copies the zero page before it allocates memory.
+fn e820_type(kind: MemoryRegionType) -> u32 {
+    match kind {
+        MemoryRegionType::Usable | MemoryRegionType::Bootloader => linux::E820_RAM,
+        MemoryRegionType::AcpiReclaimable => linux::E820_ACPI,
+        MemoryRegionType::AcpiNvs => linux::E820_NVS,
+        MemoryRegionType::BadMemory => linux::E820_UNUSABLE,
+        MemoryRegionType::Kernel
+        | MemoryRegionType::Framebuffer
+        | MemoryRegionType::Mmio
+        | MemoryRegionType::UnknownUefi(_) => linux::E820_RESERVED,
+    }
+}
+
+/// Returns the bytes of the provided value.
+fn bytes_of<T>(value: &T) -> &[u8] {
+    // SAFETY: The types passed in are plain old data.
+    unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    }
+}
+
+/// Returns the setup header of the kernel, which has been checked by [`load`].
+fn setup_header(kernel: &[u8]) -> SetupHeader {
+    SetupHeader::parse(kernel).expect("linux: invalid setup header")
+}
+
+/// Allocates the memory for the protected-mode kernel. The kernel is placed at its
+/// preferred address if that memory is free, as that avoids relocating it. Relocatable
+/// kernels are placed in the lowest free memory with the required alignment otherwise, so
+/// the kernel is free to choose its final (randomized) location from the memory map.
+fn allocate_kernel(
+    system_table: &SystemTable<Boot>,
+    placement: &Placement,
+    pages: usize,
+) -> Result<u64, IonError> {
+    let boot_services = system_table.boot_services();
+
+    // The memory is reported as usable, as the kernel reserves its own image.
+    let allocate = |address: u64| {
+        boot_services
+            .allocate_pages(
+                AllocateType::Address(address as usize),
+                MemoryType::LOADER_DATA,
+                pages,
+            )
+            .is_ok()
+    };
+
+    if allocate(placement.preferred) {
+        return Ok(placement.preferred);
+    }
+
+    if !placement.relocatable {
+        return Err(IonError::AddressInUse(placement.preferred));
+    }
+
+    let mmap_size = boot_services.memory_map_size() + 8 * core::mem::size_of::<MemoryDescriptor>();
+    let mut mmap_storage = vec![0u8; mmap_size];
+
+    let (_, descriptors) = boot_services
+        .memory_map(&mut mmap_storage)
+        .expect_success("linux: failed to retrieve the memory map");
+
+    // Copy the free regions, as allocating the kernel changes the memory map.
+    let mut regions = descriptors
+        .filter(|descriptor| descriptor.ty == MemoryType::CONVENTIONAL)
+        .map(|descriptor| {
+            (
+                descriptor.phys_start,
+                descriptor.phys_start + descriptor.page_count * 0x1000,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    regions.sort_unstable();
+
+    let size = (pages * 0x1000) as u64;
+
+    regions
+        .into_iter()
+        .filter_map(|(start, end)| placement.fit(start, end, size))
+        .find(|&address| allocate(address))
+        .ok_or(IonError::AddressInUse(placement.preferred))
+}
+
+/// Checks that the kernel can be booted using the 64-bit boot protocol and loads its
+/// protected-mode part. This has to be done while the boot services are still active, so
+/// that the memory of the kernel is allocated from the firmware.
+pub fn load(system_table: &SystemTable<Boot>, kernel: &[u8]) -> Result<(), IonError> {
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+
+    let header =
+        SetupHeader::parse(kernel).map_err(|error| IonError::InvalidHeader(error.as_str()))?;
+
+    let payload = &kernel[header.setup_size..];
+
+    // The kernel decompresses itself in place, which needs `init_size` bytes.
+    let size = payload.len().max(header.init_size as usize);
+    let pages = (size + 0xfff) / 0x1000;
+
+    let placement = header.placement();
+    let address = allocate_kernel(system_table, &placement, pages)?;
+
+    // SAFETY: The memory has been allocated above.
+    unsafe {
+        mem::copy(address as *mut u8, payload.as_ptr(), payload.len());
+        mem::zero(
+            (address as *mut u8).add(payload.len()),
+            pages * 0x1000 - payload.len(),
+        );
+    }
+
+    KERNEL_ADDRESS.store(address, Ordering::Relaxed);
+
+    log::info!(
+        "linux: loaded boot protocol {}.{:02} kernel at {:#x} (preferred {:#x})",
+        header.version >> 8,
+        header.version & 0xff,
+        address,
+        placement.preferred
+    );
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+    Ok(())
+}
+
+/// The memory for the zero page and everything it points to, which is allocated before
+/// the boot services are exited, so that it shows up in the memory map.
+pub struct Prepared {
+    /// The address of the zero page, which is followed by the GDT, the command line, the
+    /// EFI memory map, the setup data nodes and the scratch buffer that the memory map is
+    /// sanitized in, as described by [`Layout`].
+    address: u64,
+    capacity: usize,
+    initrd: Option<(u64, u64)>,
+    system_table: u64,
+}
+
+/// Returns the address of the initrd, which is copied if the kernel cannot access it.
+fn place_initrd(system_table: &SystemTable<Boot>, header: &SetupHeader, data: &[u8]) -> u64 {
+    let start = data.as_ptr() as u64;
+
+    let max_address = if header.xloadflags & linux::XLF_CAN_BE_LOADED_ABOVE_4G != 0 {
+        u64::MAX
+    } else {
+        header.initrd_addr_max as u64
+    };
+
+    if start + data.len() as u64 <= max_address {
+        return start;
+    }
+
+    let copy = system_table
+        .boot_services()
+        .allocate_pages(
+            AllocateType::MaxAddress(max_address as usize),
+            MemoryType::LOADER_DATA,
+            (data.len() + 0xfff) / 0x1000,
+        )
+        .expect_success("linux: failed to allocate memory for the initrd");
+
+    // SAFETY: The memory has been allocated above.
+    unsafe { mem::copy(copy as *mut u8, data.as_ptr(), data.len()) };
+    copy
+}
+
+/// Returns the command line of the entry, truncated to the length that the kernel accepts.
+fn command_line<'a>(header: &SetupHeader, entry: &'a ConfigurationEntry) -> &'a [u8] {
+    let command_line = entry.command_line().as_bytes();
+    &command_line[..command_line.len().min(header.cmdline_size as usize)]
+}
+
+/// The layout of the memory allocated by [`prepare`].
+struct Layout {
+    gdt: usize,
+    command_line: usize,
+    efi_memory_map: usize,
+    e820_ext: usize,
+    rng_seed: usize,
+    scratch: usize,
+    size: usize,
+}
+
+impl Layout {
+    fn new(command_line_len: usize, capacity: usize) -> Self {
+        let align = |offset: usize| (offset + 15) & !15;
+
+        let gdt = linux::BOOT_PARAMS_SIZE;
+        let command_line = align(gdt + core::mem::size_of_val(&GDT));
+        let efi_memory_map = align(command_line + command_line_len + 1);
+        let e820_ext = align(efi_memory_map + capacity * core::mem::size_of::<MemoryDescriptor>());
+        let rng_seed =
+            align(e820_ext + linux::SETUP_DATA_HEADER_SIZE + capacity * linux::E820_ENTRY_SIZE);
+        let scratch = align(rng_seed + linux::SETUP_DATA_HEADER_SIZE + RNG_SEED_SIZE);
+        let size = scratch + capacity * core::mem::size_of::<MemoryRegion>();
+
+        Self {
+            gdt,
+            command_line,
+            efi_memory_map,
+            e820_ext,
+            rng_seed,
+            scratch,
+            size,
+        }
+    }
+}
+
+impl Prepared {
+    /// Returns the part of the allocated memory from `start` to `end`.
+    ///
+    /// ## Safety
+    /// The parts that are in use at the same time must not overlap.
+    unsafe fn part(&self, start: usize, end: usize) -> &'static mut [u8] {
+        core::slice::from_raw_parts_mut((self.address + start as u64) as *mut u8, end - start)
+    }
+}
+
+/// Allocates the zero page and places the initrd of the kernel, which has been loaded by
+/// [`load`]. Must be called right before exiting the boot services.
+pub fn prepare(
+    system_table: &SystemTable<Boot>,
+    entry: &ConfigurationEntry,
+    kernel: &'static [u8],
+    initrd: Option<&'static [u8]>,
+) -> Prepared {
+    let header = setup_header(kernel);
+
+    let initrd = initrd.map(|data| (place_initrd(system_table, &header, data), data.len() as u64));
+
+    let capacity = system_table.boot_services().memory_map_size()
+        / core::mem::size_of::<MemoryDescriptor>()
+        + MEMORY_MAP_SLACK;
+
+    let layout = Layout::new(command_line(&header, entry).len(), capacity);
+    let pages = (layout.size + 0xfff) / 0x1000;
+
+    let address = system_table
+        .boot_services()
+        .allocate_pages(
+            AllocateType::MaxAddress(MAX_ADDRESS as usize),
+            MemoryType::LOADER_DATA,
+            pages,
+        )
+        .expect_success("linux: failed to allocate the zero page");
+
+    Prepared {
+        address,
+        capacity,
+        initrd,
+        // SAFETY: The system table is a transparent wrapper around a pointer.
+        system_table: unsafe { *(system_table as *const SystemTable<Boot> as *const u64) },
+    }
+}
+
+/// Fills in the memory map of the zero page. The entries that do not fit are passed in the
+/// provided [`linux::SETUP_E820_EXT`] node, which is linked if it is needed.
+fn write_memory_map(params: &mut BootParams, regions: &[MemoryRegion], e820_ext: &mut [u8]) {
+    let mut extended = 0;
+
+    for region in regions {
+        let entry = E820Entry {
+            address: region.start,
+            size: region.end - region.start,
+            kind: e820_type(region.kind),
+        };
+
+        if !params.push_e820(entry) {
+            let offset = linux::SETUP_DATA_HEADER_SIZE + extended * linux::E820_ENTRY_SIZE;
+
+            e820_ext[offset..offset + linux::E820_ENTRY_SIZE].copy_from_slice(&entry.to_bytes());
+            extended += 1;
+        }
+    }
+
+    if extended > 0 {
+        let node =
+            &mut e820_ext[..linux::SETUP_DATA_HEADER_SIZE + extended * linux::E820_ENTRY_SIZE];
+        let address = node.as_ptr() as u64;
+
+        params.link_setup_data(node, address, linux::SETUP_E820_EXT);
+    }
+
+    log::debug!(
+        "linux: memory map has {} entries",
+        params.e820_entries() + extended
+    );
+}
+
+/// Fills in the zero page and jumps to the 64-bit entry point of the kernel. The boot
+/// services have to be exited and `efi_memory_map` has to be the memory map that was
+/// returned when exiting them.
+pub fn boot<'a, I>(
+    prepared: Prepared,
+    frame_allocator: &BootFrameAllocator<I>,
+    efi_memory_map: impl Iterator<Item = &'a MemoryDescriptor>,
+    kernel: &'static [u8],
+    entry: &ConfigurationEntry,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let params_start = profile::start(profile::Phase::TagConstruction);
+
+    let header = setup_header(kernel);
+    let cmdline = command_line(&header, entry);
+    let layout = Layout::new(cmdline.len(), prepared.capacity);
+
+    // SAFETY: The parts of the layout do not overlap.
+    let (params_memory, gdt_memory, command_line, efi_map, e820_ext, rng_seed, scratch) = unsafe {
+        (
+            prepared.part(0, layout.gdt),
+            prepared.part(layout.gdt, layout.command_line),
+            prepared.part(layout.command_line, layout.efi_memory_map),
+            prepared.part(layout.efi_memory_map, layout.e820_ext),
+            prepared.part(layout.e820_ext, layout.rng_seed),
+            prepared.part(layout.rng_seed, layout.scratch),
+            prepared.part(layout.scratch, layout.size),
+        )
+    };
+
+    let kernel_address = KERNEL_ADDRESS.load(Ordering::Relaxed);
+
+    let mut params = BootParams::new(params_memory, kernel, &header);
+    params.set_kernel_address(kernel_address);
+
+    command_line[..cmdline.len()].copy_from_slice(cmdline);
+    command_line[cmdline.len()] = 0;
+    params.set_command_line(command_line.as_ptr() as u64);
+
+    if let Some((address, size)) = prepared.initrd {
+        params.set_ramdisk(address, size);
+    }
+
+    if let Some(address) = acpi::rsdp_address() {
+        params.set_acpi_rsdp(address);
+    }
+
+    if let Some((address, info)) = logger::framebuffer() {
+        params.set_screen_info(&ScreenInfo {
+            address,
+            size: info.size() as u32,
+            width: info.horizontal_resolution as u16,
+            height: info.vertical_resolution as u16,
+            bits_per_pixel: (info.bytes_per_pixel * 8) as u16,
+            pitch: info.pitch as u16,
+            masks: info.pixel_format.masks(),
+        });
+    }
+
+    // The descriptors are passed as returned when exiting the boot services, so that the
+    // kernel can use the runtime services.
+    let descriptor_size = core::mem::size_of::<MemoryDescriptor>();
+    let mut efi_map_size = 0;
+
+    for descriptor in efi_memory_map.take(prepared.capacity) {
+        efi_map[efi_map_size..efi_map_size + descriptor_size].copy_from_slice(bytes_of(descriptor));
+        efi_map_size += descriptor_size;
+    }
+
+    params.set_efi_info(
+        prepared.system_table,
+        efi_map.as_ptr() as u64,
+        efi_map_size as u32,
+        descriptor_size as u32,
+        efi::MEMORY_DESCRIPTOR_VERSION,
+    );
+
+    let regions: &mut [MemoryRegion] = unsafe {
+        let ptr = scratch.as_mut_ptr() as *mut MemoryRegion;
+
+        // SAFETY: The scratch buffer is large enough for `capacity` regions and is 16-byte
+        // aligned. A zeroed memory region is valid.
+        core::ptr::write_bytes(ptr, 0, prepared.capacity);
+        core::slice::from_raw_parts_mut(ptr, prepared.capacity)
+    };
+
+    let count = frame_allocator.collect_memory_map(regions);
+    write_memory_map(&mut params, &regions[..count], e820_ext);
+
+    // The kernel seeds its random number generator with the seed before it can use any of
+    // the hardware sources.
+    entropy::fill(&mut rng_seed[linux::SETUP_DATA_HEADER_SIZE..]);
+    let rng_seed_address = rng_seed.as_ptr() as u64;
+    params.link_setup_data(rng_seed, rng_seed_address, linux::SETUP_RNG_SEED);
+
+    profile::finish(profile::Phase::TagConstruction, params_start);
+    profile::log_summary();
+
+    log::info!("linux: jumping to the kernel entry point");
+
+    // SAFETY: The GDT memory is part of the allocation and large enough for the entries.
+    unsafe { (gdt_memory.as_mut_ptr() as *mut [u64; 4]).write_unaligned(GDT) };
+
+    let gdtr = gdt::Pointer {
+        limit: (core::mem::size_of_val(&GDT) - 1) as u16,
+        base: VirtAddr::from_ptr(gdt_memory.as_ptr()).as_u64(),
+    };
+
+    let entry_point = kernel_address + linux::ENTRY_64_OFFSET;
+
+    splash::advance(splash::Milestone::Handoff);
+
+    // SAFETY: The kernel, the zero page and everything it points to are identity-mapped.
+    unsafe {
+        asm!(
+            "
+            cli
+            lgdt [rdi]
+
+            push 0x10
+            lea rax, [rip + 2f]
+            push rax
+            retfq
+
+            2:
+            mov eax, 0x18
+            mov ds, ax
+            mov es, ax
+            mov ss, ax
+
+            xor ebp, ebp
+            jmp rcx
+            ",
+            in("rdi") &gdtr,
+            in("rcx") entry_point,
+            in("rsi") prepared.address,
+            out("rax") _,
+        );
+    }
+
+    unreachable!()
+}
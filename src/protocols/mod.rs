@@ -1 +1,6 @@
+#[cfg(target_arch = "x86_64")]
+pub mod linux;
+#[cfg(target_arch = "x86_64")]
+pub mod multiboot;
 pub mod stivale2;
+pub mod tags;
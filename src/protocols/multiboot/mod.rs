@@ -0,0 +1,526 @@
+// The Multiboot and Multiboot2 boot protocols. The kernel is loaded at its physical
+// addresses while the boot services are still active, as there is no way to return to the
+// menu if they overlap memory that is in use. The kernel is entered in 32-bit protected
+// mode with paging disabled, as described by the i386 machine state of both
+// specifications. The initrd of the entry is passed to the kernel as its only module.
+
+pub mod v1;
+pub mod v2;
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType};
+
+use ion_core::multiboot;
+
+use crate::arch::gdt;
+use crate::config::{BootProtocol, ConfigurationEntry};
+use crate::error::IonError;
+use crate::graphics;
+use crate::logger;
+use crate::mem;
+use crate::pmm::{self, BootFrameAllocator, BootMemoryRegion, MemoryRegion, MemoryRegionType};
+use crate::profile;
+use crate::splash;
+
+use x86_64::VirtAddr;
+
+/// The name that is reported in the boot information.
+const BOOTLOADER_NAME: &[u8] = b"Ion\0";
+
+/// The highest address that the kernel can access with paging disabled.
+const MAX_ADDRESS: u64 = 0xffff_ffff;
+
+/// The size of the boot information other than the command line, the module string, the
+/// memory maps and the framebuffers of additional displays, rounded up generously.
+const FIXED_INFO_SIZE: usize = 0x1000;
+
+/// The amount of additional memory map entries that are reserved, as the allocations that
+/// follow and the frames allocated for Ion's page tables might split memory regions.
+const MEMORY_MAP_SLACK: usize = 16;
+
+/// The size of a memory map entry, which is the same for both protocols.
+const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+
+/// The size of a Multiboot2 framebuffer tag for a direct RGB color framebuffer, including
+/// its padding.
+const FRAMEBUFFER_TAG_SIZE: usize = 40;
+
+/// Returns the bytes of the provided value.
+fn bytes_of<T>(value: &T) -> &[u8] {
+    // SAFETY: The types passed in are plain old data without padding.
+    unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    }
+}
+
+/// Returns the memory map entry type of the provided region type. The memory used by Ion is
+/// reported as available, so the kernel has to copy the boot information before it
+/// allocates memory.
+fn memory_type(kind: MemoryRegionType) -> u32 {
+    match kind {
+        MemoryRegionType::Usable | MemoryRegionType::Bootloader => multiboot::MEMORY_AVAILABLE,
+        MemoryRegionType::AcpiReclaimable => multiboot::MEMORY_ACPI_RECLAIMABLE,
+        MemoryRegionType::AcpiNvs => multiboot::MEMORY_NVS,
+        MemoryRegionType::BadMemory => multiboot::MEMORY_BADRAM,
+        MemoryRegionType::Kernel
+        | MemoryRegionType::Framebuffer
+        | MemoryRegionType::Mmio
+        | MemoryRegionType::UnknownUefi(_) => multiboot::MEMORY_RESERVED,
+    }
+}
+
+/// Returns the position and the size of the red, green and blue components of the pixels
+/// of the provided framebuffer, in the order used by the boot information.
+fn color_info(info: &logger::FrameBufferInfo) -> [u8; 6] {
+    let (red, green, blue) = info.pixel_format.masks();
+
+    [
+        red.trailing_zeros() as u8,
+        red.count_ones() as u8,
+        green.trailing_zeros() as u8,
+        green.count_ones() as u8,
+        blue.trailing_zeros() as u8,
+        blue.count_ones() as u8,
+    ]
+}
+
+/// Allocates the provided page aligned physical memory ranges for the kernel. If any of
+/// them is already in use, the ranges that have been allocated are freed again, as the
+/// user returns to the menu.
+fn allocate_ranges(
+    system_table: &SystemTable<Boot>,
+    ranges: &[(u64, u64)],
+) -> Result<(), IonError> {
+    let boot_services = system_table.boot_services();
+
+    for (i, &(start, end)) in ranges.iter().enumerate() {
+        let allocated = boot_services.allocate_pages(
+            AllocateType::Address(start as usize),
+            pmm::KERNEL_MEMORY_TYPE,
+            ((end - start) / 0x1000) as usize,
+        );
+
+        if allocated.is_err() {
+            for &(start, end) in &ranges[..i] {
+                boot_services
+                    .free_pages(start, ((end - start) / 0x1000) as usize)
+                    .expect_success("multiboot: failed to free the kernel memory");
+            }
+
+            return Err(IonError::AddressInUse(start));
+        }
+    }
+
+    Ok(())
+}
+
+/// Allocates zeroed memory of the provided size below 4 GiB.
+fn allocate_low(
+    system_table: &SystemTable<Boot>,
+    size: usize,
+    memory_type: MemoryType,
+) -> &'static mut [u8] {
+    let pages = (size + 0xfff) / 0x1000;
+
+    let address = system_table
+        .boot_services()
+        .allocate_pages(
+            AllocateType::MaxAddress(MAX_ADDRESS as usize),
+            memory_type,
+            pages,
+        )
+        .expect_success("multiboot: failed to allocate memory below 4 GiB");
+
+    // SAFETY: The pages have been allocated above and are never freed.
+    unsafe {
+        mem::zero(address as *mut u8, pages * 0x1000);
+        core::slice::from_raw_parts_mut(address as *mut u8, pages * 0x1000)
+    }
+}
+
+/// Returns the page aligned physical memory ranges that are covered by the loadable
+/// segments of the kernel, sorted and with overlapping ranges merged.
+fn segment_ranges(elf: &xmas_elf::ElfFile) -> Vec<(u64, u64)> {
+    let mut ranges = elf
+        .program_iter()
+        .filter(|segment| segment.get_type() == Ok(xmas_elf::program::Type::Load))
+        .filter(|segment| segment.mem_size() > 0)
+        .map(|segment| {
+            let start = segment.physical_addr() & !0xfff;
+            let end = (segment.physical_addr() + segment.mem_size() + 0xfff) & !0xfff;
+
+            (start, end)
+        })
+        .collect::<Vec<_>>();
+
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+/// Loads the segments of the provided ELF kernel at their physical addresses.
+fn load_elf(system_table: &SystemTable<Boot>, kernel: &[u8]) -> Result<(), IonError> {
+    let elf = xmas_elf::ElfFile::new(kernel).map_err(IonError::InvalidElf)?;
+    xmas_elf::header::sanity_check(&elf).map_err(IonError::InvalidElf)?;
+
+    // The kernel is entered in 32-bit mode, but some kernels are linked as 64-bit ELF files
+    // with a 32-bit entry point.
+    let machine = elf.header.pt2.machine().as_machine();
+
+    if machine != xmas_elf::header::Machine::X86 && machine != xmas_elf::header::Machine::X86_64 {
+        return Err(IonError::UnsupportedArchitecture(format!("{:?}", machine)));
+    }
+
+    if elf.header.pt2.entry_point() > MAX_ADDRESS {
+        return Err(IonError::InvalidElf("entry point is above 4 GiB"));
+    }
+
+    for p_header in elf.program_iter() {
+        xmas_elf::program::sanity_check(p_header, &elf).map_err(IonError::InvalidElf)?;
+    }
+
+    let ranges = segment_ranges(&elf);
+
+    if ranges.iter().any(|&(_, end)| end > MAX_ADDRESS + 1) {
+        return Err(IonError::InvalidElf("segment is above 4 GiB"));
+    }
+
+    allocate_ranges(system_table, &ranges)?;
+
+    for segment in elf.program_iter() {
+        if segment.get_type() != Ok(xmas_elf::program::Type::Load) {
+            continue;
+        }
+
+        let address = segment.physical_addr() as *mut u8;
+        let file_size = segment.file_size() as usize;
+        let offset = segment.offset() as usize;
+
+        // SAFETY: The memory of the segment has been allocated above and the file contents
+        // are within the kernel, as checked by the sanity check of the program header.
+        unsafe {
+            mem::copy(address, kernel[offset..].as_ptr(), file_size);
+            mem::zero(
+                address.add(file_size),
+                segment.mem_size() as usize - file_size,
+            );
+        }
+    }
+
+    log::info!(
+        "multiboot: loaded {} segments, entry point at {:#x}",
+        ranges.len(),
+        elf.header.pt2.entry_point()
+    );
+
+    Ok(())
+}
+
+/// The module that is passed to the kernel. The initrd path of the entry is passed as its
+/// string.
+#[derive(Clone, Copy)]
+struct Module {
+    start: u32,
+    end: u32,
+}
+
+/// Returns the module for the provided initrd. Files are read into page aligned memory,
+/// which satisfies the alignment that kernels can request, so the initrd is only copied if
+/// it is not accessible with paging disabled.
+fn place_module(system_table: &SystemTable<Boot>, data: &'static [u8]) -> Module {
+    let mut start = data.as_ptr() as u64;
+
+    if start + data.len() as u64 > MAX_ADDRESS {
+        let copy = allocate_low(system_table, data.len(), MemoryType::LOADER_DATA);
+
+        // SAFETY: The copy is at least as large as the initrd.
+        unsafe { mem::copy(copy.as_mut_ptr(), data.as_ptr(), data.len()) };
+        start = copy.as_ptr() as u64;
+    }
+
+    Module {
+        start: start as u32,
+        end: (start + data.len() as u64) as u32,
+    }
+}
+
+/// The values that are passed to the kernel besides the memory maps.
+#[derive(Clone, Copy)]
+struct BootContext {
+    system_table: u64,
+    image_handle: u64,
+    module: Option<Module>,
+    /// The copy of the ELF section header table, which is only passed to Multiboot kernels.
+    sections: Option<v1::SectionTable>,
+}
+
+/// The memory for the boot information and the trampoline, which is allocated before the
+/// boot services are exited, so that it shows up in the memory map that is passed to the
+/// kernel.
+pub struct Prepared {
+    /// The memory for the boot information, followed by the scratch buffer that the
+    /// memory map is sanitized in.
+    info: &'static mut [u8],
+    info_size: usize,
+    capacity: usize,
+    trampoline: u64,
+    context: BootContext,
+}
+
+/// Allocates the boot information and places the module and the ELF sections of the
+/// kernel, which has been loaded by [`v1::load`] or [`v2::load`], below 4 GiB. Must be
+/// called right before exiting the boot services.
+pub fn prepare(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    entry: &ConfigurationEntry,
+    kernel: &'static [u8],
+    initrd: Option<&'static [u8]>,
+) -> Prepared {
+    let module = initrd.map(|data| place_module(system_table, data));
+
+    let sections = match entry.protocol() {
+        BootProtocol::Multiboot => v1::copy_sections(system_table, kernel),
+        _ => None,
+    };
+
+    let capacity = system_table.boot_services().memory_map_size()
+        / core::mem::size_of::<MemoryDescriptor>()
+        + MEMORY_MAP_SLACK;
+
+    let info_size = FIXED_INFO_SIZE
+        + entry.command_line().len()
+        + entry.initrd_path().map_or(0, str::len)
+        + capacity * MEMORY_MAP_ENTRY_SIZE
+        + capacity * core::mem::size_of::<MemoryDescriptor>()
+        + graphics::secondary_framebuffers().len() * FRAMEBUFFER_TAG_SIZE;
+    let info_size = (info_size + 7) & !7;
+
+    let size = info_size + capacity * core::mem::size_of::<MemoryRegion>();
+
+    Prepared {
+        info: allocate_low(system_table, size, MemoryType::LOADER_DATA),
+        info_size,
+        capacity,
+        trampoline: allocate_low(system_table, 0x1000, MemoryType::LOADER_CODE).as_ptr() as u64,
+        context: BootContext {
+            // SAFETY: Both types are transparent wrappers around a pointer.
+            system_table: unsafe { *(system_table as *const SystemTable<Boot> as *const u64) },
+            image_handle: unsafe { *(&image_handle as *const Handle as *const u64) },
+            module,
+            sections,
+        },
+    }
+}
+
+/// Returns the amount of lower and upper memory in KiB.
+fn basic_memory_info(regions: &[MemoryRegion]) -> (u32, u32) {
+    let available = regions
+        .iter()
+        .filter(|region| memory_type(region.kind) == multiboot::MEMORY_AVAILABLE)
+        .map(|region| (region.start, region.end));
+
+    multiboot::basic_memory_info(available)
+}
+
+/// Writes the boot information and jumps to the entry point of the kernel. The boot
+/// services have to be exited and `efi_memory_map` has to be the memory map that was
+/// returned when exiting them.
+pub fn boot<'a, I>(
+    prepared: Prepared,
+    frame_allocator: &BootFrameAllocator<I>,
+    efi_memory_map: impl Iterator<Item = &'a MemoryDescriptor>,
+    kernel: &'static [u8],
+    entry: &ConfigurationEntry,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let tags_start = profile::start(profile::Phase::TagConstruction);
+
+    let Prepared {
+        info,
+        info_size,
+        capacity,
+        trampoline,
+        context,
+    } = prepared;
+
+    let (info, scratch) = info.split_at_mut(info_size);
+
+    let regions: &mut [MemoryRegion] = unsafe {
+        let ptr = scratch.as_mut_ptr() as *mut MemoryRegion;
+
+        // SAFETY: The scratch buffer is large enough for `capacity` regions and follows the
+        // 8-byte aligned boot information. A zeroed memory region is valid.
+        core::ptr::write_bytes(ptr, 0, capacity);
+        core::slice::from_raw_parts_mut(ptr, capacity)
+    };
+
+    let count = frame_allocator.collect_memory_map(regions);
+    let regions = &regions[..count];
+
+    let (magic, entry_point, info_len) = match entry.protocol() {
+        BootProtocol::Multiboot => (
+            multiboot::BOOTLOADER_MAGIC,
+            v1::entry_point(kernel),
+            v1::write_boot_info(info, regions, &context, kernel, entry),
+        ),
+
+        _ => (
+            ion_core::multiboot2::BOOTLOADER_MAGIC,
+            v2::entry_point(kernel),
+            v2::write_boot_info(info, regions, efi_memory_map, &context, entry),
+        ),
+    };
+
+    log::debug!("multiboot: boot information is {} bytes", info_len);
+
+    profile::finish(profile::Phase::TagConstruction, tags_start);
+    profile::log_summary();
+
+    log::info!("multiboot: jumping to the kernel entry point");
+
+    let size = trampoline_offset(unsafe { &MULTIBOOT_TRAMPOLINE_END }) as usize;
+    let data_offset = trampoline_offset(unsafe { &MULTIBOOT_TRAMPOLINE_DATA });
+
+    let data = TrampolineData {
+        gdt: gdt::ENTRIES,
+        gdtr: gdt::Pointer::new(VirtAddr::new(trampoline + data_offset)),
+        magic,
+        entry_point,
+        info: info.as_ptr() as u32,
+    };
+
+    // SAFETY: The trampoline page has been allocated below 4 GiB, is identity-mapped and
+    // the trampoline is much smaller than a page.
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            &MULTIBOOT_TRAMPOLINE_START as *const u8,
+            trampoline as *mut u8,
+            size,
+        );
+
+        ((trampoline + data_offset) as *mut TrampolineData).write_unaligned(data);
+    }
+
+    splash::advance(splash::Milestone::Handoff);
+
+    unsafe {
+        asm!("cli; jmp rsi", in("rsi") trampoline);
+    }
+
+    unreachable!()
+}
+
+// The trampoline that leaves long mode and jumps to the kernel. Paging can only be disabled
+// from compatibility mode, so the trampoline switches to the 32-bit code segment of its
+// copy of Ion's GDT first. It is copied to an identity mapped page below 4 GiB, whose end
+// is used as the stack. As required by both specifications, EAX contains the magic value
+// of the protocol and EBX the physical address of the boot information. The trampoline
+// expects its own address in RSI and the data at its end to be filled in by `boot`.
+global_asm!(
+    r#"
+.global MULTIBOOT_TRAMPOLINE_START
+.global MULTIBOOT_TRAMPOLINE_DATA
+.global MULTIBOOT_TRAMPOLINE_END
+
+.code64
+MULTIBOOT_TRAMPOLINE_START:
+    cli
+    lea rsp, [rsi + 0x1000]
+    lgdt [rsi + (multiboot_gdtr - MULTIBOOT_TRAMPOLINE_START)]
+
+    push 0x18
+    lea rax, [rsi + (multiboot_protected_mode - MULTIBOOT_TRAMPOLINE_START)]
+    push rax
+    retfq
+
+.code32
+multiboot_protected_mode:
+    mov ax, 0x20
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    // Disable paging, which leaves long mode.
+    mov eax, cr0
+    btr eax, 31
+    mov cr0, eax
+
+    // Clear EFER.LME and CR4.PAE, so that the kernel can set up paging from scratch.
+    mov ecx, 0xc0000080
+    rdmsr
+    btr eax, 8
+    wrmsr
+
+    mov eax, cr4
+    btr eax, 5
+    mov cr4, eax
+
+    mov ebx, [esi + (multiboot_info - MULTIBOOT_TRAMPOLINE_START)]
+    mov ecx, [esi + (multiboot_entry_point - MULTIBOOT_TRAMPOLINE_START)]
+    mov eax, [esi + (multiboot_magic - MULTIBOOT_TRAMPOLINE_START)]
+
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+
+    cld
+    jmp ecx
+
+.align 8
+MULTIBOOT_TRAMPOLINE_DATA:
+multiboot_gdt:
+    .fill 7, 8, 0
+multiboot_gdtr:
+    .word 0
+    .quad 0
+multiboot_magic:
+    .long 0
+multiboot_entry_point:
+    .long 0
+multiboot_info:
+    .long 0
+MULTIBOOT_TRAMPOLINE_END:
+"#
+);
+
+extern "C" {
+    static MULTIBOOT_TRAMPOLINE_START: u8;
+    static MULTIBOOT_TRAMPOLINE_DATA: u8;
+    static MULTIBOOT_TRAMPOLINE_END: u8;
+}
+
+/// The data at the end of the trampoline. The layout has to match the data in the
+/// `global_asm!` block above.
+#[repr(C, packed)]
+struct TrampolineData {
+    gdt: [u64; 7],
+    gdtr: gdt::Pointer,
+    magic: u32,
+    entry_point: u32,
+    info: u32,
+}
+
+/// Returns the offset of the provided trampoline symbol from the start of the trampoline.
+fn trampoline_offset(symbol: &u8) -> u64 {
+    // SAFETY: Only the addresses of the symbols are taken.
+    let start = unsafe { &MULTIBOOT_TRAMPOLINE_START } as *const u8 as u64;
+    symbol as *const u8 as u64 - start
+}
@@ -0,0 +1,367 @@
+// The Multiboot boot protocol, which is still used by many older kernels. The boot
+// information is a fixed structure whose flags tell which of its fields are valid. Kernels
+// that are not ELF files describe where they have to be loaded using the address fields of
+// their header (the "a.out kludge").
+
+use uefi::prelude::*;
+use uefi::table::boot::MemoryType;
+
+use ion_core::multiboot::{self, Header, Layout};
+
+use super::BootContext;
+use crate::config::ConfigurationEntry;
+use crate::error::IonError;
+use crate::logger;
+use crate::mem;
+use crate::pmm::MemoryRegion;
+use crate::profile;
+
+/// The boot information structure. Only the fields whose flags are set are valid.
+#[repr(C, packed)]
+#[derive(Default)]
+struct Info {
+    flags: u32,
+    mem_lower: u32,
+    mem_upper: u32,
+    boot_device: u32,
+    cmdline: u32,
+    mods_count: u32,
+    mods_addr: u32,
+    /// The number, size, address and string table index of the ELF section headers.
+    syms: [u32; 4],
+    mmap_length: u32,
+    mmap_addr: u32,
+    drives_length: u32,
+    drives_addr: u32,
+    config_table: u32,
+    boot_loader_name: u32,
+    apm_table: u32,
+    vbe_control_info: u32,
+    vbe_mode_info: u32,
+    vbe_mode: u16,
+    vbe_interface_seg: u16,
+    vbe_interface_off: u16,
+    vbe_interface_len: u16,
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+    color_info: [u8; 6],
+}
+
+/// An entry of the memory map. The size field does not include itself.
+#[repr(C, packed)]
+struct MemoryMapEntry {
+    size: u32,
+    base: u64,
+    length: u64,
+    kind: u32,
+}
+
+impl MemoryMapEntry {
+    fn new(region: &MemoryRegion) -> Self {
+        Self {
+            size: (core::mem::size_of::<Self>() - 4) as u32,
+            base: region.start,
+            length: region.end - region.start,
+            kind: super::memory_type(region.kind),
+        }
+    }
+}
+
+/// An entry of the module list.
+#[repr(C)]
+struct ModuleEntry {
+    start: u32,
+    end: u32,
+    string: u32,
+    reserved: u32,
+}
+
+/// The copy of the ELF section header table that is passed to the kernel.
+#[derive(Clone, Copy)]
+pub(super) struct SectionTable {
+    count: u32,
+    entry_size: u32,
+    address: u32,
+    string_index: u32,
+}
+
+/// Writes the variable sized parts of the boot information, which follow the boot
+/// information structure, and returns their physical addresses.
+struct InfoWriter<'a> {
+    info: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> InfoWriter<'a> {
+    fn new(info: &'a mut [u8]) -> Self {
+        let len = (core::mem::size_of::<Info>() + 7) & !7;
+        Self { info, len }
+    }
+
+    /// Writes the provided parts one after another at the next 8-byte aligned offset.
+    fn write(&mut self, parts: &[&[u8]]) -> u32 {
+        let start = self.len;
+
+        for part in parts {
+            self.info[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+        }
+
+        self.len = (self.len + 7) & !7;
+        self.info.as_ptr() as u32 + start as u32
+    }
+
+    /// Writes the boot information structure at the start and returns the total size.
+    fn finish(self, info: &Info) -> usize {
+        self.info[..core::mem::size_of::<Info>()].copy_from_slice(super::bytes_of(info));
+        self.len
+    }
+}
+
+/// Returns the header of the kernel, which has been checked by [`load`].
+fn header(kernel: &[u8]) -> Header {
+    Header::find(kernel).expect("multiboot: kernel has no header")
+}
+
+/// Returns where the kernel is loaded if it uses the a.out kludge.
+fn aout_layout(kernel: &[u8]) -> Option<Layout> {
+    header(kernel)
+        .aout_layout(kernel.len())
+        .expect("multiboot: invalid header")
+}
+
+/// Loads the kernel at the addresses given by the a.out kludge of its header.
+fn load_aout(
+    system_table: &SystemTable<Boot>,
+    kernel: &[u8],
+    layout: Layout,
+) -> Result<(), IonError> {
+    let load_end = layout.load_addr as u64 + (layout.file_size + layout.bss_size) as u64;
+
+    if load_end > super::MAX_ADDRESS + 1 {
+        return Err(IonError::InvalidHeader(
+            "multiboot load address is above 4 GiB",
+        ));
+    }
+
+    let start = layout.load_addr as u64 & !0xfff;
+    let end = (load_end + 0xfff) & !0xfff;
+
+    super::allocate_ranges(system_table, &[(start, end)])?;
+
+    let address = layout.load_addr as *mut u8;
+
+    // SAFETY: The memory has been allocated above and the layout has been checked to be
+    // within the kernel image.
+    unsafe {
+        mem::copy(
+            address,
+            kernel[layout.file_offset..].as_ptr(),
+            layout.file_size,
+        );
+        mem::zero(address.add(layout.file_size), layout.bss_size);
+    }
+
+    log::info!(
+        "multiboot: loaded a.out kludge image at {:#x}, entry point at {:#x}",
+        layout.load_addr,
+        layout.entry_addr
+    );
+
+    Ok(())
+}
+
+/// Checks that the kernel can be booted using the Multiboot protocol and loads it at its
+/// physical addresses. This has to be done while the boot services are still active, so
+/// that the memory of the kernel is allocated from the firmware.
+pub fn load(system_table: &SystemTable<Boot>, kernel: &[u8]) -> Result<(), IonError> {
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+
+    let header = Header::find(kernel).ok_or(IonError::HeaderNotFound("multiboot"))?;
+    let layout = header
+        .check()
+        .and_then(|_| header.aout_layout(kernel.len()))
+        .map_err(|error| IonError::InvalidHeader(error.as_str()))?;
+
+    match layout {
+        Some(layout) => load_aout(system_table, kernel, layout)?,
+        None => super::load_elf(system_table, kernel)?,
+    }
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+    Ok(())
+}
+
+/// Returns the entry point of the kernel, which has been checked by [`load`].
+pub(super) fn entry_point(kernel: &[u8]) -> u32 {
+    match aout_layout(kernel) {
+        Some(layout) => layout.entry_addr,
+        None => {
+            let elf = xmas_elf::ElfFile::new(kernel).expect("multiboot: invalid ELF file");
+            elf.header.pt2.entry_point() as u32
+        }
+    }
+}
+
+/// Copies the ELF section header table of the kernel below 4 GiB, so that the kernel can
+/// find its symbols. The sections that are not part of a loaded segment are copied along
+/// and their addresses are updated, as the kernel image might not be accessible with
+/// paging disabled.
+pub(super) fn copy_sections(
+    system_table: &SystemTable<Boot>,
+    kernel: &[u8],
+) -> Option<SectionTable> {
+    if aout_layout(kernel).is_some() {
+        return None;
+    }
+
+    let elf = xmas_elf::ElfFile::new(kernel).ok()?;
+
+    let count = elf.header.pt2.sh_count() as usize;
+    let entry_size = elf.header.pt2.sh_entry_size() as usize;
+    let table_offset = elf.header.pt2.sh_offset() as usize;
+    let table_size = count * entry_size;
+
+    if count == 0 || kernel.len() < table_offset + table_size {
+        return None;
+    }
+
+    let is_copied = |section: &xmas_elf::sections::SectionHeader| {
+        section.address() == 0
+            && section.size() > 0
+            && !matches!(
+                section.get_type(),
+                Ok(xmas_elf::sections::ShType::Null) | Ok(xmas_elf::sections::ShType::NoBits)
+            )
+    };
+
+    let size = elf
+        .section_iter()
+        .filter(|section| is_copied(section))
+        .fold((table_size + 15) & !15, |size, section| {
+            size + ((section.size() as usize + 15) & !15)
+        });
+
+    let buffer = super::allocate_low(system_table, size, MemoryType::LOADER_DATA);
+    buffer[..table_size].copy_from_slice(&kernel[table_offset..table_offset + table_size]);
+
+    let is_32bit = elf.header.pt1.class() == xmas_elf::header::Class::ThirtyTwo;
+    let mut offset = (table_size + 15) & !15;
+
+    for (i, section) in elf.section_iter().enumerate() {
+        if !is_copied(&section) {
+            continue;
+        }
+
+        let start = section.offset() as usize;
+        let len = section.size() as usize;
+
+        if kernel.len() < start + len {
+            continue;
+        }
+
+        buffer[offset..offset + len].copy_from_slice(&kernel[start..start + len]);
+
+        let address = buffer.as_ptr() as u64 + offset as u64;
+        let entry = &mut buffer[i * entry_size..(i + 1) * entry_size];
+
+        // The address is the fourth field of the section header, which follows the 32-bit
+        // name and type and the flags, whose size depends on the class.
+        if is_32bit {
+            entry[12..16].copy_from_slice(&(address as u32).to_le_bytes());
+        } else {
+            entry[16..24].copy_from_slice(&address.to_le_bytes());
+        }
+
+        offset += (len + 15) & !15;
+    }
+
+    Some(SectionTable {
+        count: count as u32,
+        entry_size: entry_size as u32,
+        address: buffer.as_ptr() as u32,
+        string_index: elf.header.pt2.sh_str_index() as u32,
+    })
+}
+
+/// Writes the boot information for the provided entry and returns its size.
+pub(super) fn write_boot_info(
+    info: &mut [u8],
+    regions: &[MemoryRegion],
+    context: &BootContext,
+    kernel: &[u8],
+    entry: &ConfigurationEntry,
+) -> usize {
+    let header = header(kernel);
+    let mut writer = InfoWriter::new(info);
+
+    let (mem_lower, mem_upper) = super::basic_memory_info(regions);
+
+    let mut info = Info {
+        flags: multiboot::INFO_MEMORY
+            | multiboot::INFO_CMDLINE
+            | multiboot::INFO_BOOTLOADER_NAME
+            | multiboot::INFO_MEMORY_MAP,
+        mem_lower,
+        mem_upper,
+        cmdline: writer.write(&[entry.command_line().as_bytes(), &[0]]),
+        boot_loader_name: writer.write(&[super::BOOTLOADER_NAME]),
+        ..Default::default()
+    };
+
+    if let Some(module) = context.module {
+        let string = entry.initrd_path().unwrap_or_default();
+        let string = writer.write(&[string.as_bytes(), &[0]]);
+
+        let module = ModuleEntry {
+            start: module.start,
+            end: module.end,
+            string,
+            reserved: 0,
+        };
+
+        info.flags |= multiboot::INFO_MODULES;
+        info.mods_count = 1;
+        info.mods_addr = writer.write(&[super::bytes_of(&module)]);
+    }
+
+    if let Some(sections) = context.sections {
+        info.flags |= multiboot::INFO_ELF_SECTIONS;
+        info.syms = [
+            sections.count,
+            sections.entry_size,
+            sections.address,
+            sections.string_index,
+        ];
+    }
+
+    let mmap_start = writer.len;
+
+    for region in regions {
+        writer.write(&[super::bytes_of(&MemoryMapEntry::new(region))]);
+    }
+
+    info.mmap_addr = writer.info.as_ptr() as u32 + mmap_start as u32;
+    info.mmap_length = (writer.len - mmap_start) as u32;
+
+    // The framebuffer is only described if the kernel asks for the video mode, as older
+    // kernels expect the VGA text mode otherwise.
+    if header.flags & multiboot::HEADER_VIDEO_MODE != 0 {
+        if let Some((address, fb)) = logger::framebuffer() {
+            info.flags |= multiboot::INFO_FRAMEBUFFER;
+            info.framebuffer_addr = address;
+            info.framebuffer_pitch = fb.pitch as u32;
+            info.framebuffer_width = fb.horizontal_resolution as u32;
+            info.framebuffer_height = fb.vertical_resolution as u32;
+            info.framebuffer_bpp = fb.bits_per_pixel as u8;
+            info.framebuffer_type = multiboot::FRAMEBUFFER_TYPE_RGB;
+            info.color_info = super::color_info(&fb);
+        }
+    }
+
+    writer.finish(&info)
+}
@@ -0,0 +1,225 @@
+// The Multiboot2 boot protocol. The boot information consists of tags, which the kernel can
+// request in its header.
+
+use uefi::prelude::*;
+use uefi::table::boot::MemoryDescriptor;
+
+use ion_core::multiboot2::{self, Header, InfoWriter};
+
+use super::BootContext;
+use crate::acpi;
+use crate::config::ConfigurationEntry;
+use crate::efi;
+use crate::error::IonError;
+use crate::graphics;
+use crate::logger;
+use crate::pmm::MemoryRegion;
+use crate::profile;
+
+/// The information tags that Ion always provides. The framebuffer tag is only provided if
+/// the framebuffer is directly accessible.
+const PROVIDED_TAGS: &[u32] = &[
+    multiboot2::TAG_CMDLINE,
+    multiboot2::TAG_BOOTLOADER_NAME,
+    multiboot2::TAG_MODULE,
+    multiboot2::TAG_BASIC_MEMINFO,
+    multiboot2::TAG_MEMORY_MAP,
+    multiboot2::TAG_EFI64,
+    multiboot2::TAG_EFI_MEMORY_MAP,
+    multiboot2::TAG_EFI64_IMAGE_HANDLE,
+];
+
+/// An entry of the memory map tag.
+#[repr(C)]
+struct MemoryMapEntry {
+    base: u64,
+    length: u64,
+    kind: u32,
+    reserved: u32,
+}
+
+impl MemoryMapEntry {
+    fn new(region: &MemoryRegion) -> Self {
+        Self {
+            base: region.start,
+            length: region.end - region.start,
+            kind: super::memory_type(region.kind),
+            reserved: 0,
+        }
+    }
+}
+
+/// The contents of the framebuffer tag for direct RGB color framebuffers.
+#[repr(C, packed)]
+struct FramebufferInfo {
+    address: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bits_per_pixel: u8,
+    kind: u8,
+    reserved: u16,
+    color_info: [u8; 6],
+}
+
+impl FramebufferInfo {
+    fn new(address: u64, info: logger::FrameBufferInfo) -> Self {
+        Self {
+            address,
+            pitch: info.pitch as u32,
+            width: info.horizontal_resolution as u32,
+            height: info.vertical_resolution as u32,
+            bits_per_pixel: info.bits_per_pixel as u8,
+            kind: ion_core::multiboot::FRAMEBUFFER_TYPE_RGB,
+            reserved: 0,
+            color_info: super::color_info(&info),
+        }
+    }
+}
+
+/// Returns true if Ion can provide the information tag with the provided type.
+fn is_provided(kind: u32) -> bool {
+    PROVIDED_TAGS.contains(&kind)
+        || (kind == multiboot2::TAG_FRAMEBUFFER && logger::framebuffer().is_some())
+        || ((kind == multiboot2::TAG_ACPI_OLD || kind == multiboot2::TAG_ACPI_NEW)
+            && acpi::rsdp_address().is_some())
+}
+
+/// Checks that the kernel can be booted using the Multiboot2 protocol and loads its
+/// segments at their physical addresses. This has to be done while the boot services are
+/// still active, so that the memory of the kernel is allocated from the firmware.
+pub fn load(system_table: &SystemTable<Boot>, kernel: &[u8]) -> Result<(), IonError> {
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+
+    let header = Header::find(kernel).ok_or(IonError::HeaderNotFound("multiboot2"))?;
+    header
+        .check(is_provided)
+        .map_err(|error| IonError::InvalidHeader(error.as_str()))?;
+
+    super::load_elf(system_table, kernel)?;
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+    Ok(())
+}
+
+/// Returns the entry point of the kernel, which has been checked by [`load`].
+pub(super) fn entry_point(kernel: &[u8]) -> u32 {
+    let elf = xmas_elf::ElfFile::new(kernel).expect("multiboot2: invalid ELF file");
+    elf.header.pt2.entry_point() as u32
+}
+
+/// Writes the memory map tag and the basic memory information tag, which is derived from
+/// it.
+fn write_memory_map_tags(writer: &mut InfoWriter, regions: &[MemoryRegion]) {
+    let (lower, upper) = super::basic_memory_info(regions);
+    writer.tag(
+        multiboot2::TAG_BASIC_MEMINFO,
+        &[&lower.to_le_bytes(), &upper.to_le_bytes()],
+    );
+
+    let entry_size = core::mem::size_of::<MemoryMapEntry>() as u32;
+
+    writer.start_tag(multiboot2::TAG_MEMORY_MAP);
+    writer.write(&entry_size.to_le_bytes());
+    writer.write(&0u32.to_le_bytes());
+
+    for region in regions {
+        writer.write(super::bytes_of(&MemoryMapEntry::new(region)));
+    }
+
+    writer.end_tag();
+}
+
+/// Writes the tag containing a copy of the RSDP, if the firmware provides ACPI tables.
+fn write_acpi_tag(writer: &mut InfoWriter) {
+    let address = match acpi::rsdp_address() {
+        Some(address) => address,
+        None => return,
+    };
+
+    // SAFETY: The RSDP provided by the firmware is identity-mapped. Its revision is at
+    // offset 15 and revision 2 extended it from 20 to 36 bytes.
+    let revision = unsafe { *((address + 15) as *const u8) };
+
+    let (kind, size) = if revision >= 2 {
+        (multiboot2::TAG_ACPI_NEW, 36)
+    } else {
+        (multiboot2::TAG_ACPI_OLD, 20)
+    };
+
+    let rsdp = unsafe { core::slice::from_raw_parts(address as *const u8, size) };
+    writer.tag(kind, &[rsdp]);
+}
+
+/// Writes the boot information for the provided entry and returns its size.
+pub(super) fn write_boot_info<'a>(
+    info: &mut [u8],
+    regions: &[MemoryRegion],
+    efi_memory_map: impl Iterator<Item = &'a MemoryDescriptor>,
+    context: &BootContext,
+    entry: &ConfigurationEntry,
+) -> usize {
+    let mut writer = InfoWriter::new(info);
+
+    writer.tag(
+        multiboot2::TAG_CMDLINE,
+        &[entry.command_line().as_bytes(), &[0]],
+    );
+    writer.tag(multiboot2::TAG_BOOTLOADER_NAME, &[super::BOOTLOADER_NAME]);
+
+    if let Some(module) = context.module {
+        let string = entry.initrd_path().unwrap_or_default();
+
+        writer.tag(
+            multiboot2::TAG_MODULE,
+            &[
+                &module.start.to_le_bytes(),
+                &module.end.to_le_bytes(),
+                string.as_bytes(),
+                &[0],
+            ],
+        );
+    }
+
+    write_memory_map_tags(&mut writer, regions);
+
+    // Kernels that only support a single display use the first framebuffer tag, which
+    // describes the display that Ion has drawn to. The displays of the other graphics
+    // outputs follow it.
+    if let Some((address, info)) = logger::framebuffer() {
+        let secondary = graphics::secondary_framebuffers();
+
+        for (address, info) in core::iter::once((address, info)).chain(secondary) {
+            writer.tag(
+                multiboot2::TAG_FRAMEBUFFER,
+                &[super::bytes_of(&FramebufferInfo::new(address, info))],
+            );
+        }
+    }
+
+    writer.tag(
+        multiboot2::TAG_EFI64,
+        &[&context.system_table.to_le_bytes()],
+    );
+    writer.tag(
+        multiboot2::TAG_EFI64_IMAGE_HANDLE,
+        &[&context.image_handle.to_le_bytes()],
+    );
+
+    write_acpi_tag(&mut writer);
+
+    // The descriptors are passed as returned when exiting the boot services. Ion's own
+    // allocations are loader data, so the kernel can tell them apart.
+    let descriptor_size = core::mem::size_of::<MemoryDescriptor>() as u32;
+
+    writer.start_tag(multiboot2::TAG_EFI_MEMORY_MAP);
+    writer.write(&descriptor_size.to_le_bytes());
+    writer.write(&efi::MEMORY_DESCRIPTOR_VERSION.to_le_bytes());
+
+    for descriptor in efi_memory_map {
+        writer.write(super::bytes_of(descriptor));
+    }
+
+    writer.end_tag();
+    writer.finish()
+}
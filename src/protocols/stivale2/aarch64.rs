@@ -0,0 +1,438 @@
+use super::{
+    allocate_boot_info, allocate_boot_info_tag, allocate_stack, create_memory_map_tag,
+    find_header_tag, physical_memory_regions, DtbTag, FramebufferTag, HhdmTag, SmpTag, DTB_TAG_ID,
+    HHDM_TAG_ID, SMP_HEADER_TAG_ID,
+};
+use crate::arch::mmu::{self, Attributes, MemoryKind};
+use crate::arch::smp::{self, SmpInfo};
+use crate::arch::BootPageTables;
+use crate::config::ConfigurationEntry;
+use crate::efi;
+use crate::logger;
+use crate::mem;
+use crate::pmm::BootFrameAllocator;
+use crate::pmm::BootMemoryRegion;
+use crate::pmm::MemoryRegionType;
+use crate::profile;
+use crate::protocols::tags::{self, TagSources};
+use crate::splash;
+
+use stivale_boot::v2::*;
+
+use x86_64::align_up;
+use xmas_elf::program::ProgramHeader;
+
+/// The start of the higher half of the 48-bit address space, which is translated using
+/// TTBR1_EL1. The kernel has to be linked above it.
+const HIGHER_HALF_START: u64 = 0xffff_0000_0000_0000;
+
+/// The virtual address at which the physical memory is mapped.
+const HHDM_OFFSET: u64 = HIGHER_HALF_START;
+
+/// Copies the provided `PT_LOAD` segment into newly allocated frames and maps them into
+/// the higher half of the kernel address space. The part of the segment that is not backed
+/// by the file (`.bss`) is zeroed.
+fn handle_load_segment<I>(
+    segment: ProgramHeader,
+    kernel: &[u8],
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let virt_start = segment.virtual_addr();
+
+    if virt_start < HIGHER_HALF_START {
+        panic!("stivale2: the kernel has to be linked in the higher half");
+    }
+
+    let misalignment = virt_start & (mmu::PAGE_SIZE - 1);
+    let size = align_up(misalignment + segment.mem_size(), mmu::PAGE_SIZE);
+
+    let frames = frame_allocator
+        .allocate_frames(size / mmu::PAGE_SIZE, u64::MAX, mmu::PAGE_SIZE)
+        .expect("stivale2: failed to allocate the kernel segment");
+
+    frame_allocator.mark_kernel(frames);
+
+    let phys_start = frames.start.start_address().as_u64();
+    let file_start = segment.offset() as usize;
+    let file_end = file_start + segment.file_size() as usize;
+
+    // SAFETY: The frames have just been allocated and are identity-mapped.
+    unsafe {
+        let destination = phys_start as *mut u8;
+
+        mem::zero(destination, size as usize);
+        mem::copy(
+            destination.add(misalignment as usize),
+            kernel[file_start..file_end].as_ptr(),
+            segment.file_size() as usize,
+        );
+    }
+
+    let attributes = Attributes {
+        kind: MemoryKind::Normal,
+        writable: segment.flags().is_write(),
+        executable: segment.flags().is_execute(),
+    };
+
+    page_tables.upper.map(
+        virt_start,
+        phys_start + misalignment,
+        segment.mem_size(),
+        attributes,
+        frame_allocator,
+    );
+}
+
+/// Identity-maps the physical memory in the lower half and maps it at [`HHDM_OFFSET`] in
+/// the higher half. The memory mapped I/O regions are mapped as device memory and the
+/// framebuffer as write-combining memory. The identity mapping is executable, as both the
+/// handoff to the kernel and the application processors run from it.
+fn map_physical_memory<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let framebuffer =
+        logger::framebuffer().map(|(address, info)| (address, address + info.size() as u64));
+
+    for region in physical_memory_regions(frame_allocator).iter() {
+        let is_framebuffer = framebuffer.map_or(false, |(start, end)| {
+            region.start < end && region.end > start
+        });
+
+        let kind = match region.kind {
+            _ if is_framebuffer => MemoryKind::WriteCombining,
+            MemoryRegionType::Mmio => MemoryKind::Device,
+            _ => MemoryKind::Normal,
+        };
+
+        let size = region.end - region.start;
+
+        page_tables.lower.map(
+            region.start,
+            region.start,
+            size,
+            Attributes {
+                kind,
+                writable: true,
+                executable: kind == MemoryKind::Normal,
+            },
+            frame_allocator,
+        );
+
+        page_tables.upper.map(
+            HHDM_OFFSET + region.start,
+            region.start,
+            size,
+            Attributes {
+                kind,
+                writable: true,
+                executable: false,
+            },
+            frame_allocator,
+        );
+    }
+}
+
+/// Allocates the SMP tag and starts all of the enabled application processors using
+/// PSCI. Returns [`None`] if there is no MADT.
+fn create_smp_tag<I>(
+    page_tables: &BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    stack_size: usize,
+) -> Option<&'static mut SmpTag>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let processors = || {
+        smp::processors().map(|(processor_id, gic_iface_no, mpidr)| {
+            let info = SmpInfo {
+                processor_id,
+                gic_iface_no,
+                mpidr,
+                target_stack: 0,
+                goto_address: 0,
+                extra_argument: 0,
+            };
+
+            (mpidr, info)
+        })
+    };
+
+    super::create_smp_tag(
+        page_tables,
+        frame_allocator,
+        stack_size,
+        smp::current_mpidr(),
+        processors,
+    )
+}
+
+pub fn boot<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    kernel: &'static [u8],
+    dtb: Option<&'static [u8]>,
+    runtime_map: Option<efi::RuntimeMap>,
+    tag_sources: TagSources,
+    entry: &ConfigurationEntry,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+    let elf = xmas_elf::ElfFile::new(kernel).expect("stivale2: invalid ELF file");
+
+    let stivale2_hdr;
+    let smp_requested;
+
+    match elf.header.pt2.machine().as_machine() {
+        xmas_elf::header::Machine::AArch64 => {
+            xmas_elf::header::sanity_check(&elf).expect("stivale2: failed ELF sanity check");
+
+            // 1. Get the stivale2 header section.
+            let header = elf
+                .find_section_by_name(".stivale2hdr")
+                .expect("stivale2: section .stivale2hdr not found");
+
+            if header.size() != core::mem::size_of::<StivaleHeader>() as u64 {
+                panic!("stivale2: section .stivale2hdr does not match the size of the struct");
+            }
+
+            // SAFETY: The size of the section is checked above and the address provided is
+            // valid and mapped.
+            stivale2_hdr = unsafe { &*(header.raw_data(&elf).as_ptr() as *const StivaleHeader) };
+
+            log::info!("stivale2: 64-bit kernel detected");
+
+            smp_requested =
+                find_header_tag(&elf, header.raw_data(&elf), SMP_HEADER_TAG_ID).is_some();
+
+            if entry.kaslr() {
+                log::warn!("stivale2: KASLR is not supported on aarch64");
+            }
+
+            // 2. Load the kernel.
+            for p_header in elf.program_iter() {
+                xmas_elf::program::sanity_check(p_header, &elf)
+                    .expect("stivale2: failed ELF program header sanity check");
+
+                if let Ok(xmas_elf::program::Type::Load) = p_header.get_type() {
+                    handle_load_segment(p_header, kernel, page_tables, frame_allocator);
+                }
+            }
+        }
+
+        machine => panic!("stivale2: unsupported architecture {:?}", machine),
+    }
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+
+    // The stivale2 specs says the stack has to be 16-byte aligned.
+    if (stivale2_hdr.get_stack() as u64 & (16 - 1)) != 0 {
+        panic!("stivale2: requested stack is not 16-byte aligned");
+    }
+
+    map_physical_memory(page_tables, frame_allocator);
+    logger::flush();
+
+    // A null stack pointer means that the kernel expects us to provide a stack.
+    let stack_top = if stivale2_hdr.get_stack() as u64 == 0 {
+        let stack_top = allocate_stack(frame_allocator, entry.stack_size());
+
+        log::debug!(
+            "stivale2: allocated a {} byte stack at {:#x}",
+            entry.stack_size(),
+            stack_top
+        );
+
+        stack_top
+    } else {
+        stivale2_hdr.get_stack() as u64
+    };
+
+    if runtime_map.is_some() {
+        log::warn!("stivale2: remapping the EFI runtime services is not supported on aarch64");
+    }
+
+    let tags_start = profile::start(profile::Phase::TagConstruction);
+
+    // Now we have to prepare the stivale struct that we will pass as an argument
+    // in X0 to the kernel's entry point function.
+    let stivale_struct = allocate_boot_info_tag(frame_allocator, StivaleStruct::new());
+
+    stivale_struct.set_bootloader_brand("Ion");
+    stivale_struct.set_bootloader_version(env!("CARGO_PKG_VERSION"));
+
+    let hhdm_tag = allocate_boot_info_tag(
+        frame_allocator,
+        HhdmTag {
+            header: StivaleTagHeader {
+                identifier: HHDM_TAG_ID,
+                next: 0,
+            },
+            address: HHDM_OFFSET,
+        },
+    );
+
+    stivale_struct.add_tag(&mut hhdm_tag.header);
+
+    // The framebuffer is only passed to the kernel if it is directly accessible. It is
+    // identity-mapped as write-combining memory.
+    if let Some((address, info)) = logger::framebuffer() {
+        let framebuffer_tag =
+            allocate_boot_info_tag(frame_allocator, FramebufferTag::new(address, info));
+
+        stivale_struct.add_tag(&mut framebuffer_tag.header);
+    }
+
+    if let Some(dtb) = dtb {
+        let dtb_tag = allocate_boot_info_tag(
+            frame_allocator,
+            DtbTag {
+                header: StivaleTagHeader {
+                    identifier: DTB_TAG_ID,
+                    next: 0,
+                },
+                address: dtb.as_ptr() as u64,
+                size: dtb.len() as u64,
+            },
+        );
+
+        stivale_struct.add_tag(&mut dtb_tag.header);
+    }
+
+    // The application processors are started after all of the other kernel mappings have
+    // been created, as they switch to the kernel address space right away.
+    if smp_requested {
+        if let Some(smp_tag) = create_smp_tag(page_tables, frame_allocator, entry.stack_size()) {
+            stivale_struct.add_tag(&mut smp_tag.header);
+        }
+    }
+
+    profile::finish(profile::Phase::TagConstruction, tags_start);
+    profile::log_summary();
+
+    log::info!("stivale2: jumping to the kernel entry point");
+
+    tags::attach(
+        &tag_sources,
+        |size| allocate_boot_info(frame_allocator, size) as *mut u8,
+        |tag| stivale_struct.add_tag(tag),
+    );
+
+    // NOTE: The memory map tag has to be created after all other allocations, as any
+    // frame that is allocated afterwards would be reported as usable to the kernel.
+    let memory_map_tag = create_memory_map_tag(frame_allocator);
+    stivale_struct.add_tag(&mut memory_map_tag.header);
+
+    let switch_context = SwitchContext {
+        ttbr0: page_tables.lower.root(),
+        ttbr1: page_tables.upper.root(),
+        tcr: mmu::tcr(),
+        stack_top,
+        entry_point: elf.header.pt2.entry_point(),
+        stivale_struct,
+    };
+
+    splash::advance(splash::Milestone::Handoff);
+
+    // SAFTEY: The stack and the kernel entry point are checked above.
+    unsafe { context_switch(switch_context) }
+}
+
+struct SwitchContext {
+    ttbr0: u64,
+    ttbr1: u64,
+    tcr: u64,
+    stack_top: u64,
+    entry_point: u64,
+    stivale_struct: &'static StivaleStruct,
+}
+
+/// Switches to the kernel address space and jumps to the kernel entry point at EL1 with
+/// the address of the stivale2 struct in X0. If the firmware runs at EL2, the kernel is
+/// entered by returning to EL1. Otherwise the MMU is disabled while the translation
+/// registers are replaced, which is safe as the handoff runs from the identity mapping.
+unsafe fn context_switch(context: SwitchContext) -> ! {
+    asm!(
+        "msr daifset, #0xf",
+        "mrs x8, CurrentEL",
+        "cmp x8, #(2 << 2)",
+        "b.eq 1f",
+
+        "mrs x8, sctlr_el1",
+        "bic x8, x8, #1",
+        "msr sctlr_el1, x8",
+        "isb",
+
+        "msr mair_el1, x3",
+        "msr tcr_el1, x4",
+        "msr ttbr0_el1, x5",
+        "msr ttbr1_el1, x6",
+        "isb",
+        "tlbi vmalle1",
+        "dsb nsh",
+        "isb",
+
+        // Do not trap the floating point and SIMD instructions.
+        "mov x8, #(3 << 20)",
+        "msr cpacr_el1, x8",
+        "msr sctlr_el1, x7",
+        "isb",
+
+        "mov sp, x2",
+        "mov x29, xzr",
+        "mov x30, xzr",
+        "br x1",
+
+        // Run EL1 in AArch64 state (which also disables the EL2 host extensions before the
+        // EL1 registers are written) and give it access to the timer.
+        "1:",
+        "mov x8, #(1 << 31)",
+        "msr hcr_el2, x8",
+        "mov x8, #3",
+        "msr cnthctl_el2, x8",
+        "msr cntvoff_el2, xzr",
+        "isb",
+
+        "msr mair_el1, x3",
+        "msr tcr_el1, x4",
+        "msr ttbr0_el1, x5",
+        "msr ttbr1_el1, x6",
+        "isb",
+        "tlbi vmalle1",
+        "dsb nsh",
+        "isb",
+
+        "mov x8, #(3 << 20)",
+        "msr cpacr_el1, x8",
+        "msr sctlr_el1, x7",
+        "msr sp_el1, x2",
+
+        // Enter EL1h with all of the exceptions masked.
+        "mov x8, #0x3c5",
+        "msr spsr_el2, x8",
+        "msr elr_el2, x1",
+        "mov x29, xzr",
+        "mov x30, xzr",
+        "eret",
+
+        in("x0") context.stivale_struct as *const StivaleStruct as u64,
+        in("x1") context.entry_point,
+        in("x2") context.stack_top,
+        in("x3") mmu::MAIR,
+        in("x4") context.tcr,
+        in("x5") context.ttbr0,
+        in("x6") context.ttbr1,
+        in("x7") mmu::SCTLR,
+        options(noreturn)
+    );
+}
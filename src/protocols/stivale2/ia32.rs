@@ -0,0 +1,412 @@
+use super::{
+    allocate_boot_info, allocate_boot_info_tag, allocate_stack, create_memory_map_tag,
+    find_header_tag, DtbTag, FramebufferTag, HhdmTag, DTB_TAG_ID, HHDM_TAG_ID, SMP_HEADER_TAG_ID,
+};
+use crate::arch::BootPageTables;
+use crate::config::ConfigurationEntry;
+use crate::efi;
+use crate::logger;
+use crate::mem;
+use crate::pmm;
+use crate::pmm::BootFrameAllocator;
+use crate::pmm::BootMemoryRegion;
+use crate::profile;
+use crate::protocols::tags::{self, TagSources};
+use crate::splash;
+
+use raw_cpuid::CpuId;
+use stivale_boot::v2::*;
+
+use x86_64::align_up;
+use x86_64::structures::paging::*;
+use x86_64::{PhysAddr, VirtAddr};
+use xmas_elf::program::ProgramHeader;
+
+/// The virtual address at which the physical memory is mapped.
+const HHDM_OFFSET: u64 = 0xffff_8000_0000_0000;
+
+/// The size of the identity mapping at the start of the address space, which includes
+/// Ion itself, as all of the memory that IA-32 firmware uses is below 4 GiB.
+const IDENTITY_MAP_SIZE: u64 = 1 << 32;
+
+/// The descriptors of the GDT that is loaded when the kernel is entered. The layout
+/// matches the GDT documented by the stivale2 specification.
+const GDT_ENTRIES: [u64; 7] = [
+    0,
+    0x0000_9a00_0000_ffff, // 0x08: 16-bit code
+    0x0000_9200_0000_ffff, // 0x10: 16-bit data
+    0x00cf_9a00_0000_ffff, // 0x18: 32-bit code
+    0x00cf_9200_0000_ffff, // 0x20: 32-bit data
+    0x00af_9a00_0000_ffff, // 0x28: 64-bit code
+    0x00cf_9200_0000_ffff, // 0x30: 64-bit data
+];
+
+/// Copies the provided `PT_LOAD` segment into newly allocated frames and maps them into
+/// the kernel address space. The part of the segment that is not backed by the file
+/// (`.bss`) is zeroed.
+fn handle_load_segment<I>(
+    segment: ProgramHeader,
+    kernel: &[u8],
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let virt_start = VirtAddr::new(segment.virtual_addr());
+
+    if virt_start.as_u64() < IDENTITY_MAP_SIZE {
+        panic!("stivale2: the kernel overlaps the identity mapping");
+    }
+
+    let misalignment = virt_start.as_u64() & (Size4KiB::SIZE - 1);
+    let size = align_up(misalignment + segment.mem_size(), Size4KiB::SIZE);
+
+    let frames = frame_allocator
+        .allocate_frames(size / Size4KiB::SIZE, u64::MAX, Size4KiB::SIZE)
+        .expect("stivale2: failed to allocate the kernel segment");
+
+    frame_allocator.mark_kernel(frames);
+
+    let phys_start = frames.start.start_address();
+    let file_start = segment.offset() as usize;
+    let file_end = file_start + segment.file_size() as usize;
+
+    // SAFETY: The frames have just been allocated below 4 GiB and are identity-mapped.
+    unsafe {
+        let destination = phys_start.as_u64() as usize as *mut u8;
+
+        mem::zero(destination, size as usize);
+        mem::copy(
+            destination.add(misalignment as usize),
+            kernel[file_start..file_end].as_ptr(),
+            segment.file_size() as usize,
+        );
+    }
+
+    let mut flags = PageTableFlags::PRESENT;
+
+    if !segment.flags().is_execute() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    if segment.flags().is_write() {
+        flags |= PageTableFlags::WRITABLE;
+    }
+
+    pmm::map_range(
+        &mut page_tables.kernel,
+        virt_start.align_down(Size4KiB::SIZE),
+        phys_start,
+        size,
+        flags,
+        frame_allocator,
+    )
+    .expect("stivale2: failed to map the kernel segment");
+}
+
+pub fn boot<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    kernel: &'static [u8],
+    dtb: Option<&'static [u8]>,
+    runtime_map: Option<efi::RuntimeMap>,
+    tag_sources: TagSources,
+    entry: &ConfigurationEntry,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+    let elf = xmas_elf::ElfFile::new(kernel).expect("stivale2: invalid ELF file");
+
+    let stivale2_hdr;
+
+    match elf.header.pt2.machine().as_machine() {
+        xmas_elf::header::Machine::X86_64 => {
+            // 1. Check if the CPU actually supports long mode.
+            let long_mode_supported = CpuId::new()
+                .get_extended_processor_and_feature_identifiers()
+                .map_or(false, |info| info.has_64bit_mode());
+
+            if !long_mode_supported {
+                panic!("stivale2: CPU does not support 64-bit mode.")
+            }
+
+            xmas_elf::header::sanity_check(&elf).expect("stivale2: failed ELF sanity check");
+
+            // 2. Get the stivale2 header section.
+            let header = elf
+                .find_section_by_name(".stivale2hdr")
+                .expect("stivale2: section .stivale2hdr not found");
+
+            if header.size() != core::mem::size_of::<StivaleHeader>() as u64 {
+                panic!("stivale2: section .stivale2hdr does not match the size of the struct");
+            }
+
+            // SAFETY: The size of the section is checked above and the address provided is
+            // valid and mapped.
+            stivale2_hdr = unsafe { &*(header.raw_data(&elf).as_ptr() as *const StivaleHeader) };
+
+            log::info!("stivale2: 64-bit kernel detected, entering long mode from IA-32 firmware");
+
+            if find_header_tag(&elf, header.raw_data(&elf), SMP_HEADER_TAG_ID).is_some() {
+                log::warn!("stivale2: SMP is not supported on IA-32 firmware");
+            }
+
+            if entry.kaslr() {
+                log::warn!("stivale2: KASLR is not supported on IA-32 firmware");
+            }
+
+            // 3. Load the kernel.
+            for p_header in elf.program_iter() {
+                xmas_elf::program::sanity_check(p_header, &elf)
+                    .expect("stivale2: failed ELF program header sanity check");
+
+                if let Ok(xmas_elf::program::Type::Load) = p_header.get_type() {
+                    handle_load_segment(p_header, kernel, page_tables, frame_allocator);
+                }
+            }
+        }
+
+        machine => panic!("stivale2: unsupported architecture {:?}", machine),
+    }
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+
+    // The stivale2 specs says the stack has to be 16-byte aligned.
+    if (stivale2_hdr.get_stack() as u64 & (16 - 1)) != 0 {
+        panic!("stivale2: requested stack is not 16-byte aligned");
+    }
+
+    // The identity mapping includes Ion itself, so that it keeps running after paging has
+    // been enabled.
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    pmm::map_range(
+        &mut page_tables.kernel,
+        VirtAddr::zero(),
+        PhysAddr::new(0),
+        IDENTITY_MAP_SIZE,
+        flags,
+        frame_allocator,
+    )
+    .expect("stivale2: failed to identity-map the first 4 GiB");
+
+    let direct_map_size = align_up(frame_allocator.max_phys_addr().as_u64(), Size2MiB::SIZE);
+
+    pmm::map_range(
+        &mut page_tables.kernel,
+        VirtAddr::new(HHDM_OFFSET),
+        PhysAddr::new(0),
+        direct_map_size,
+        flags | PageTableFlags::NO_EXECUTE,
+        frame_allocator,
+    )
+    .expect("stivale2: failed to map the physical memory");
+
+    logger::flush();
+
+    // A null stack pointer means that the kernel expects us to provide a stack.
+    let stack_top = if stivale2_hdr.get_stack() as u64 == 0 {
+        let stack_top = allocate_stack(frame_allocator, entry.stack_size());
+
+        log::debug!(
+            "stivale2: allocated a {} byte stack at {:#x}",
+            entry.stack_size(),
+            stack_top
+        );
+
+        stack_top
+    } else {
+        stivale2_hdr.get_stack() as u64
+    };
+
+    if runtime_map.is_some() {
+        log::warn!(
+            "stivale2: remapping the EFI runtime services is not supported on IA-32 firmware"
+        );
+    }
+
+    let tags_start = profile::start(profile::Phase::TagConstruction);
+
+    // Now we have to prepare the stivale struct that we will pass as an argument
+    // in RDI to the kernel's entry point function.
+    let stivale_struct = allocate_boot_info_tag(frame_allocator, StivaleStruct::new());
+
+    stivale_struct.set_bootloader_brand("Ion");
+    stivale_struct.set_bootloader_version(env!("CARGO_PKG_VERSION"));
+
+    let hhdm_tag = allocate_boot_info_tag(
+        frame_allocator,
+        HhdmTag {
+            header: StivaleTagHeader {
+                identifier: HHDM_TAG_ID,
+                next: 0,
+            },
+            address: HHDM_OFFSET,
+        },
+    );
+
+    stivale_struct.add_tag(&mut hhdm_tag.header);
+
+    // The framebuffer is only passed to the kernel if it is directly accessible.
+    if let Some((address, info)) = logger::framebuffer() {
+        let framebuffer_tag =
+            allocate_boot_info_tag(frame_allocator, FramebufferTag::new(address, info));
+
+        stivale_struct.add_tag(&mut framebuffer_tag.header);
+    }
+
+    if let Some(dtb) = dtb {
+        let dtb_tag = allocate_boot_info_tag(
+            frame_allocator,
+            DtbTag {
+                header: StivaleTagHeader {
+                    identifier: DTB_TAG_ID,
+                    next: 0,
+                },
+                address: dtb.as_ptr() as u64,
+                size: dtb.len() as u64,
+            },
+        );
+
+        stivale_struct.add_tag(&mut dtb_tag.header);
+    }
+
+    profile::finish(profile::Phase::TagConstruction, tags_start);
+    profile::log_summary();
+
+    log::info!("stivale2: jumping to the kernel entry point");
+
+    tags::attach(
+        &tag_sources,
+        |size| allocate_boot_info(frame_allocator, size) as usize as *mut u8,
+        |tag| stivale_struct.add_tag(tag),
+    );
+
+    // The switch context includes the GDT that is loaded for the kernel, so it has to be
+    // allocated before the memory map tag is created.
+    let switch_context = allocate_boot_info_tag(
+        frame_allocator,
+        SwitchContext {
+            gdt: GDT_ENTRIES,
+            gdtr_limit: (core::mem::size_of::<[u64; 7]>() - 1) as u16,
+            gdtr_base: 0,
+            level_4_table: page_tables.kernel_level_4_frame.start_address().as_u64() as u32,
+            entry_point: elf.header.pt2.entry_point(),
+            stack_top,
+            stivale_struct: stivale_struct as *const StivaleStruct as u64,
+        },
+    );
+
+    switch_context.gdtr_base = switch_context.gdt.as_ptr() as u32;
+
+    // NOTE: The memory map tag has to be created after all other allocations, as any
+    // frame that is allocated afterwards would be reported as usable to the kernel.
+    let memory_map_tag = create_memory_map_tag(frame_allocator);
+    stivale_struct.add_tag(&mut memory_map_tag.header);
+
+    splash::advance(splash::Milestone::Handoff);
+
+    // SAFTEY: The stack and the kernel entry point are checked above.
+    unsafe { context_switch(switch_context) }
+}
+
+/// The state that is loaded when entering long mode. The layout has to match the offsets
+/// used in [`context_switch`].
+#[repr(C, packed)]
+struct SwitchContext {
+    gdt: [u64; 7],
+    gdtr_limit: u16,
+    gdtr_base: u32,
+    /// The physical address of the level 4 page table, which is below 4 GiB.
+    level_4_table: u32,
+    entry_point: u64,
+    stack_top: u64,
+    stivale_struct: u64,
+}
+
+/// Enters long mode and jumps to the kernel entry point with the address of the stivale2
+/// struct in RDI. Paging is disabled first in case the firmware uses 32-bit paging, as
+/// CR4.PAE and EFER.LME can only be changed while paging is disabled. Enabling paging
+/// enters compatibility mode, from which a far return through the 64-bit code segment of
+/// the new GDT enters 64-bit mode. The code runs from the identity mapping.
+unsafe fn context_switch(context: &'static SwitchContext) -> ! {
+    asm!(
+        "cli",
+        "cld",
+
+        "mov eax, cr0",
+        "btr eax, 31",
+        "mov cr0, eax",
+
+        "lgdt [esi + 56]",
+
+        // Enable PAE.
+        "mov eax, cr4",
+        "bts eax, 5",
+        "mov cr4, eax",
+
+        "mov eax, [esi + 62]",
+        "mov cr3, eax",
+
+        // Enable long mode and the no-execute bit.
+        "mov ecx, 0xc0000080",
+        "rdmsr",
+        "bts eax, 8",
+        "bts eax, 11",
+        "wrmsr",
+
+        // Enable paging and write protection, which enters compatibility mode.
+        "mov eax, cr0",
+        "bts eax, 16",
+        "bts eax, 31",
+        "mov cr0, eax",
+
+        "push 0x28",
+        "call 2f",
+        "2:",
+        "pop eax",
+        "add eax, 3f - 2b",
+        "push eax",
+        "retf",
+
+        ".code64",
+        "3:",
+        // The upper half of RSI is undefined after the switch to 64-bit mode.
+        "mov esi, esi",
+
+        "mov ax, 0x30",
+        "mov ds, ax",
+        "mov es, ax",
+        "mov fs, ax",
+        "mov gs, ax",
+        "mov ss, ax",
+
+        "mov rsp, [rsi + 74]",
+        "mov rdi, [rsi + 82]",
+        "push 0",
+        "push qword ptr [rsi + 66]",
+
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "ret",
+        ".code32",
+
+        in("esi") context as *const SwitchContext,
+        options(noreturn)
+    );
+}
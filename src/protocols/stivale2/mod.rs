@@ -0,0 +1,591 @@
+use core::mem::MaybeUninit;
+
+use alloc::format;
+
+use ion_core::elf::LoadSegment;
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+use crate::arch::smp::{self, SmpInfo, StartupData};
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+use crate::arch::BootPageTables;
+use crate::error::IonError;
+use crate::logger;
+use crate::pmm;
+use crate::pmm::BootFrameAllocator;
+use crate::pmm::BootMemoryRegion;
+use crate::pmm::MemoryRegion;
+use crate::pmm::MemoryRegionType;
+
+use stivale_boot::v2::*;
+
+#[cfg(not(target_arch = "x86_64"))]
+use x86_64::align_up;
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64;
+#[cfg(target_arch = "x86")]
+mod ia32;
+#[cfg(target_arch = "riscv64")]
+mod riscv64;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "aarch64")]
+pub use self::aarch64::boot;
+#[cfg(target_arch = "x86")]
+pub use self::ia32::boot;
+#[cfg(target_arch = "riscv64")]
+pub use self::riscv64::boot;
+#[cfg(target_arch = "x86_64")]
+pub use self::x86_64::boot;
+
+/// The machine that the kernel has to be built for. IA-32 firmware boots 64-bit kernels.
+#[cfg(target_arch = "aarch64")]
+const KERNEL_MACHINE: xmas_elf::header::Machine = xmas_elf::header::Machine::AArch64;
+#[cfg(target_arch = "riscv64")]
+const KERNEL_MACHINE: xmas_elf::header::Machine = xmas_elf::header::Machine::RISC_V;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const KERNEL_MACHINE: xmas_elf::header::Machine = xmas_elf::header::Machine::X86_64;
+
+/// Checks that the provided kernel can be booted using the stivale2 protocol. This has to
+/// be done while the boot services are still active, as there is no way to return to the
+/// menu afterwards.
+pub fn validate(kernel: &[u8]) -> Result<(), IonError> {
+    let elf = xmas_elf::ElfFile::new(kernel).map_err(IonError::InvalidElf)?;
+    xmas_elf::header::sanity_check(&elf).map_err(IonError::InvalidElf)?;
+
+    let machine = elf.header.pt2.machine().as_machine();
+
+    if machine != KERNEL_MACHINE {
+        return Err(IonError::UnsupportedArchitecture(format!("{:?}", machine)));
+    }
+
+    for p_header in elf.program_iter() {
+        xmas_elf::program::sanity_check(p_header, &elf).map_err(IonError::InvalidElf)?;
+    }
+
+    let header = elf
+        .find_section_by_name(".stivale2hdr")
+        .ok_or(IonError::MissingHeader(".stivale2hdr"))?;
+
+    ion_core::elf::check_stivale2_header(header.raw_data(&elf))
+        .map_err(|error| IonError::InvalidHeader(error.as_str()))
+}
+
+/// The identifier of the stivale2 higher half direct map tag.
+const HHDM_TAG_ID: u64 = 0xb0ed257db18cb58f;
+
+/// The stivale2 tag describing the virtual address at which all of the physical memory is
+/// mapped.
+#[repr(C)]
+struct HhdmTag {
+    header: StivaleTagHeader,
+    address: u64,
+}
+
+/// The identifier of the stivale2 command line tag.
+const CMDLINE_TAG_ID: u64 = 0xe5e76a1b4597a781;
+
+/// The stivale2 tag describing the command line of the config entry.
+#[repr(C)]
+struct CmdlineTag {
+    header: StivaleTagHeader,
+    /// The address of the NUL-terminated command line.
+    address: u64,
+}
+
+/// The identifier of the stivale2 device tree blob tag.
+const DTB_TAG_ID: u64 = 0xabb29bd49a2833fa;
+
+/// The stivale2 tag describing the flattened device tree blob passed to the kernel.
+#[repr(C)]
+struct DtbTag {
+    header: StivaleTagHeader,
+    /// The physical address of the device tree blob.
+    address: u64,
+    /// The size of the device tree blob in bytes.
+    size: u64,
+}
+
+/// The identifier of the Ion vendor tag describing the EFI runtime mapping ("ionrtmap").
+const ION_EFI_RUNTIME_MAP_TAG_ID: u64 = 0x696f6e72746d6170;
+
+/// Ion vendor tag that describes the virtual addresses that the EFI runtime services have
+/// been switched to with `SetVirtualAddressMap`. The tag is followed by `entries` EFI
+/// memory descriptors of `descriptor_size` bytes each, with the virtual start of every
+/// runtime region filled in.
+#[repr(C)]
+struct EfiRuntimeMapTag {
+    header: StivaleTagHeader,
+    /// The virtual address of the runtime services table.
+    runtime_services: u64,
+    /// The size of each memory descriptor in bytes.
+    descriptor_size: u64,
+    /// The version of the memory descriptors.
+    descriptor_version: u64,
+    /// The amount of memory descriptors following the tag.
+    entries: u64,
+}
+
+/// The identifier of the stivale2 header tag requesting the application processors to be
+/// started.
+const SMP_HEADER_TAG_ID: u64 = 0x1ab015085f3273df;
+
+/// The identifier of the stivale2 SMP tag.
+const SMP_TAG_ID: u64 = 0x34d1d96339647025;
+
+/// The identifier of the stivale2 framebuffer tag.
+const FRAMEBUFFER_TAG_ID: u64 = 0x506461d2950408fa;
+
+/// The memory model of the framebuffer in the framebuffer tag. stivale2 only defines RGB.
+const FRAMEBUFFER_MEMORY_MODEL_RGB: u8 = 1;
+
+/// The stivale2 framebuffer tag describing the framebuffer that was used by Ion. The
+/// fields are naturally aligned, so no padding is inserted.
+#[repr(C)]
+struct FramebufferTag {
+    header: StivaleTagHeader,
+    /// The address of the framebuffer in the kernel address space. On x86_64 this is a
+    /// write-combining mapping, or the physical address if the PAT is not supported.
+    address: u64,
+    width: u16,
+    height: u16,
+    /// The number of bytes between the start of a line and the start of the next.
+    pitch: u16,
+    bits_per_pixel: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+    unused: u8,
+}
+
+impl FramebufferTag {
+    fn new(address: u64, info: logger::FrameBufferInfo) -> Self {
+        let (red, green, blue) = info.pixel_format.masks();
+
+        // Returns the size and the shift of the provided color component mask.
+        let mask = |mask: u32| (mask.count_ones() as u8, mask.trailing_zeros() as u8);
+
+        let (red_mask_size, red_mask_shift) = mask(red);
+        let (green_mask_size, green_mask_shift) = mask(green);
+        let (blue_mask_size, blue_mask_shift) = mask(blue);
+
+        Self {
+            header: StivaleTagHeader {
+                identifier: FRAMEBUFFER_TAG_ID,
+                next: 0,
+            },
+            address,
+            width: info.horizontal_resolution as u16,
+            height: info.vertical_resolution as u16,
+            pitch: info.pitch as u16,
+            bits_per_pixel: info.bits_per_pixel as u16,
+            memory_model: FRAMEBUFFER_MEMORY_MODEL_RGB,
+            red_mask_size,
+            red_mask_shift,
+            green_mask_size,
+            green_mask_shift,
+            blue_mask_size,
+            blue_mask_shift,
+            unused: 0,
+        }
+    }
+}
+
+/// The identifier of the stivale2 memory map tag.
+const MEMORY_MAP_TAG_ID: u64 = 0x2187f79e8612de07;
+
+/// The amount of additional entries that are reserved in the memory map tag, as allocating
+/// the tag itself and resolving overlapping regions might split memory regions.
+const MEMORY_MAP_SLACK: usize = 16;
+
+/// The stivale2 memory map tag. The tag is followed by `entries` memory map entries.
+#[repr(C)]
+struct MemoryMapTag {
+    header: StivaleTagHeader,
+    entries: u64,
+}
+
+/// The memory map entry types defined by the stivale2 specification.
+const MEMORY_MAP_USABLE: u32 = 1;
+const MEMORY_MAP_RESERVED: u32 = 2;
+const MEMORY_MAP_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_MAP_ACPI_NVS: u32 = 4;
+const MEMORY_MAP_BAD_MEMORY: u32 = 5;
+const MEMORY_MAP_BOOTLOADER_RECLAIMABLE: u32 = 0x1000;
+const MEMORY_MAP_KERNEL_AND_MODULES: u32 = 0x1001;
+const MEMORY_MAP_FRAMEBUFFER: u32 = 0x1002;
+
+#[repr(C)]
+struct MemoryMapEntry {
+    base: u64,
+    length: u64,
+    kind: u32,
+    unused: u32,
+}
+
+impl MemoryMapEntry {
+    fn new(region: MemoryRegion) -> Self {
+        let kind = match region.kind {
+            MemoryRegionType::Usable => MEMORY_MAP_USABLE,
+            MemoryRegionType::Bootloader => MEMORY_MAP_BOOTLOADER_RECLAIMABLE,
+            MemoryRegionType::AcpiReclaimable => MEMORY_MAP_ACPI_RECLAIMABLE,
+            MemoryRegionType::AcpiNvs => MEMORY_MAP_ACPI_NVS,
+            MemoryRegionType::Framebuffer => MEMORY_MAP_FRAMEBUFFER,
+            MemoryRegionType::Kernel => MEMORY_MAP_KERNEL_AND_MODULES,
+            MemoryRegionType::BadMemory => MEMORY_MAP_BAD_MEMORY,
+            MemoryRegionType::Mmio | MemoryRegionType::UnknownUefi(_) => MEMORY_MAP_RESERVED,
+        };
+
+        Self {
+            base: region.start,
+            length: region.end - region.start,
+            kind,
+            unused: 0,
+        }
+    }
+}
+
+/// Returns the header tag with the provided identifier from the stivale2 header of the
+/// kernel, starting at the tag header, if there is any.
+fn find_header_tag<'a>(
+    elf: &xmas_elf::ElfFile<'a>,
+    header: &[u8],
+    identifier: u64,
+) -> Option<&'a [u8]> {
+    let segments = elf
+        .program_iter()
+        .filter(|segment| segment.get_type() == Ok(xmas_elf::program::Type::Load))
+        .map(|segment| LoadSegment {
+            virtual_addr: segment.virtual_addr(),
+            offset: segment.offset(),
+            file_size: segment.file_size(),
+        });
+
+    ion_core::elf::find_header_tag(elf.input, segments, header, identifier)
+}
+
+/// Returns the amount of entries that the memory map tag can hold and its size in bytes,
+/// including the scratch buffer used by [`write_memory_map_tag`].
+fn memory_map_tag_size<I>(frame_allocator: &BootFrameAllocator<I>) -> (usize, usize)
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    // The framebuffer is not part of the UEFI memory map, so it is added separately.
+    let capacity = frame_allocator.memory_map_len()
+        + logger::framebuffer().is_some() as usize
+        + MEMORY_MAP_SLACK;
+
+    // The regions are collected and sanitized in a scratch buffer behind the entries, as
+    // the heap is no longer available at this point.
+    let size = core::mem::size_of::<MemoryMapTag>()
+        + capacity * core::mem::size_of::<MemoryMapEntry>()
+        + capacity * core::mem::size_of::<MemoryRegion>();
+
+    (capacity, size)
+}
+
+/// Fills in the memory map tag at the provided address with the memory map of the frame
+/// allocator. No frames must be allocated afterwards, as they would not be reflected in
+/// the memory map.
+///
+/// ## Safety
+/// The provided address must point to writable boot information of the size returned by
+/// [`memory_map_tag_size`] for the provided capacity.
+unsafe fn write_memory_map_tag<I>(
+    frame_allocator: &BootFrameAllocator<I>,
+    addr: *mut u8,
+    capacity: usize,
+) -> &'static mut MemoryMapTag
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let entries_size = capacity * core::mem::size_of::<MemoryMapEntry>();
+    let entries = addr.add(core::mem::size_of::<MemoryMapTag>()) as *mut MemoryMapEntry;
+
+    let regions: &mut [MemoryRegion] = {
+        let ptr = addr.add(core::mem::size_of::<MemoryMapTag>() + entries_size) as *mut _;
+
+        // SAFETY: The scratch buffer is within the boot information and a zeroed memory
+        // region is valid.
+        core::ptr::write_bytes(ptr, 0, capacity);
+        core::slice::from_raw_parts_mut(ptr, capacity)
+    };
+
+    let mut len = 0;
+
+    let mut push = |region| {
+        assert!(len < capacity, "stivale2: memory map tag is too small");
+
+        regions[len] = region;
+        len += 1;
+    };
+
+    frame_allocator.memory_map(&mut push);
+
+    if let Some((address, info)) = logger::framebuffer() {
+        push(MemoryRegion {
+            start: address,
+            end: address + info.size() as u64,
+            kind: MemoryRegionType::Framebuffer,
+        });
+    }
+
+    let count = pmm::sanitize_memory_map(regions, len);
+
+    for (i, region) in regions[..count].iter().enumerate() {
+        // SAFETY: The entry is within the boot information, as there are at most
+        // `capacity` regions.
+        entries.add(i).write(MemoryMapEntry::new(*region));
+    }
+
+    let tag: &'static mut MaybeUninit<MemoryMapTag> = &mut *(addr as *mut _);
+
+    tag.write(MemoryMapTag {
+        header: StivaleTagHeader {
+            identifier: MEMORY_MAP_TAG_ID,
+            next: 0,
+        },
+        entries: count as u64,
+    })
+}
+
+/// The size of the pages that the boot information is allocated in on the architectures
+/// that pass it to the kernel using its identity-mapped physical address.
+#[cfg(not(target_arch = "x86_64"))]
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Allocates physically contiguous boot information of the provided size and returns its
+/// physical address. The boot information is passed to the kernel using its physical
+/// address, as the physical memory is identity-mapped.
+#[cfg(not(target_arch = "x86_64"))]
+fn allocate_boot_info<I>(frame_allocator: &mut BootFrameAllocator<I>, size: usize) -> u64
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let page_count = align_up(size as u64, PAGE_SIZE) / PAGE_SIZE;
+
+    frame_allocator
+        .allocate_frames(page_count, u64::MAX, PAGE_SIZE)
+        .expect("frame allocation for boot info failed")
+        .start
+        .start_address()
+        .as_u64()
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn allocate_boot_info_tag<T, I>(
+    frame_allocator: &mut BootFrameAllocator<I>,
+    value: T,
+) -> &'static mut T
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let addr = allocate_boot_info(frame_allocator, core::mem::size_of::<T>());
+
+    let boot_info: &'static mut MaybeUninit<T> = unsafe { &mut *(addr as usize as *mut _) };
+    boot_info.write(value)
+}
+
+/// Allocates a stack of the provided size and returns its top. The stack is only
+/// identity-mapped.
+#[cfg(not(target_arch = "x86_64"))]
+fn allocate_stack<I>(frame_allocator: &mut BootFrameAllocator<I>, size: usize) -> u64
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    assert!(size > 0, "stivale2: the stack size cannot be 0");
+
+    let size = align_up(size as u64, PAGE_SIZE);
+    let frames = frame_allocator
+        .allocate_frames(size / PAGE_SIZE, u64::MAX, PAGE_SIZE)
+        .expect("stivale2: failed to allocate the kernel stack");
+
+    // The kernel might keep using the stack, so it must not be reported as reclaimable.
+    frame_allocator.mark_kernel(frames);
+
+    frames.start.start_address().as_u64() + size
+}
+
+/// Allocates the memory map tag and fills it in with the memory map of the frame allocator.
+/// No frames must be allocated afterwards, as they would not be reflected in the memory map.
+#[cfg(not(target_arch = "x86_64"))]
+fn create_memory_map_tag<I>(
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> &'static mut MemoryMapTag
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let (capacity, size) = memory_map_tag_size(frame_allocator);
+    let addr = allocate_boot_info(frame_allocator, size);
+
+    // NOTE: The memory map is constructed after the tag has been allocated, so that it
+    // includes the frames of the tag itself.
+    unsafe { write_memory_map_tag(frame_allocator, addr as usize as *mut u8, capacity) }
+}
+
+/// Returns the regions of the physical memory that have to be mapped for the kernel,
+/// including the framebuffer, which is usually not part of the UEFI memory map. The
+/// architecture specific code maps them using its own page table type.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+fn physical_memory_regions<I>(
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> &'static mut [MemoryRegion]
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    // The regions are collected in a scratch buffer, as the heap is no longer available
+    // and the frame allocator cannot be used while its memory map is iterated.
+    let capacity = frame_allocator.memory_map_len() + 1 + MEMORY_MAP_SLACK;
+    let scratch = allocate_boot_info(
+        frame_allocator,
+        capacity * core::mem::size_of::<MemoryRegion>(),
+    );
+
+    // SAFETY: The scratch buffer has just been allocated and a zeroed memory region is
+    // valid.
+    let regions: &'static mut [MemoryRegion] = unsafe {
+        core::ptr::write_bytes(scratch as *mut MemoryRegion, 0, capacity);
+        core::slice::from_raw_parts_mut(scratch as *mut _, capacity)
+    };
+
+    let mut len = 0;
+
+    frame_allocator.memory_map(|region| {
+        assert!(len < capacity, "stivale2: too many memory regions");
+
+        regions[len] = region;
+        len += 1;
+    });
+
+    if let Some((address, info)) = logger::framebuffer() {
+        let end = address + info.size() as u64;
+        let covered = regions[..len]
+            .iter()
+            .any(|region| region.start <= address && region.end >= end);
+
+        if !covered && len < capacity {
+            regions[len] = MemoryRegion {
+                start: address,
+                end,
+                kind: MemoryRegionType::Framebuffer,
+            };
+
+            len += 1;
+        }
+    }
+
+    &mut regions[..len]
+}
+
+/// The stivale2 tag describing the processors of the system. The tag is followed by
+/// `cpu_count` per-CPU information entries, including the bootstrap processor. The
+/// application processors spin in Ion's image, which is reported as bootloader reclaimable
+/// memory, so it must not be reclaimed before all of them have been started.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+#[repr(C)]
+struct SmpTag {
+    header: StivaleTagHeader,
+    flags: u64,
+    /// The MPIDR of the bootstrap processor on aarch64 and its hart ID on riscv64.
+    bsp_id: u64,
+    unused: u64,
+    cpu_count: u64,
+}
+
+/// Allocates the SMP tag and starts all of the application processors returned by
+/// `processors`, which is called twice. Each processor is returned along with the ID that
+/// it is started with, and the processor whose ID is `bsp_id` is only listed. Returns
+/// [`None`] if there are no processors.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+fn create_smp_tag<I, F, P>(
+    page_tables: &BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    stack_size: usize,
+    bsp_id: u64,
+    processors: F,
+) -> Option<&'static mut SmpTag>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+    F: Fn() -> P,
+    P: Iterator<Item = (u64, SmpInfo)>,
+{
+    let capacity = processors().count();
+
+    if capacity == 0 {
+        log::warn!("smp: no processors found in the MADT");
+        return None;
+    }
+
+    let size = core::mem::size_of::<SmpTag>() + capacity * core::mem::size_of::<SmpInfo>();
+    let addr = allocate_boot_info(frame_allocator, size);
+    let entries = (addr + core::mem::size_of::<SmpTag>() as u64) as *mut SmpInfo;
+
+    let startup_data = allocate_boot_info(
+        frame_allocator,
+        capacity * core::mem::size_of::<StartupData>(),
+    ) as *mut StartupData;
+
+    let mut cpu_count = 0;
+
+    for (index, (id, info)) in processors().enumerate() {
+        // SAFETY: The entry is within the allocated boot information, as there are at most
+        // `capacity` processors.
+        let info = unsafe {
+            let entry = entries.add(cpu_count);
+            entry.write(info);
+
+            &mut *entry
+        };
+
+        if id == bsp_id {
+            cpu_count += 1;
+            continue;
+        }
+
+        info.target_stack = allocate_stack(frame_allocator, stack_size);
+
+        // SAFETY: Every processor has its own startup data within the allocated boot
+        // information.
+        let data = unsafe {
+            let data = startup_data.add(index);
+            data.write(StartupData::new(page_tables));
+
+            &mut *data
+        };
+
+        // The entry of a processor that did not start is reused for the next one.
+        if smp::start(id, data, info) {
+            cpu_count += 1;
+        } else {
+            log::warn!("smp: processor {:#x} did not start", id);
+        }
+    }
+
+    log::info!("smp: started {} of {} processors", cpu_count, capacity);
+
+    let tag: &'static mut MaybeUninit<SmpTag> = unsafe { &mut *(addr as *mut _) };
+
+    Some(tag.write(SmpTag {
+        header: StivaleTagHeader {
+            identifier: SMP_TAG_ID,
+            next: 0,
+        },
+        flags: 0,
+        bsp_id,
+        unused: 0,
+        cpu_count: cpu_count as u64,
+    }))
+}
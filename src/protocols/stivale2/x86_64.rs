@@ -0,0 +1,1623 @@
+use core::mem::MaybeUninit;
+
+use super::{
+    find_header_tag, memory_map_tag_size, write_memory_map_tag, CmdlineTag, DtbTag,
+    EfiRuntimeMapTag, FramebufferTag, HhdmTag, MemoryMapTag, CMDLINE_TAG_ID, DTB_TAG_ID,
+    HHDM_TAG_ID, ION_EFI_RUNTIME_MAP_TAG_ID, SMP_HEADER_TAG_ID, SMP_TAG_ID,
+};
+use crate::arch::smp::{self, SmpInfo};
+use crate::arch::{cpu, gdt, BootPageTables};
+use crate::config::ConfigurationEntry;
+use crate::efi;
+use crate::entropy;
+use crate::logger;
+use crate::mem;
+use crate::pmm;
+use crate::pmm::BootFrameAllocator;
+use crate::pmm::BootMemoryRegion;
+use crate::pmm::UsedLevel4Entries;
+use crate::profile;
+use crate::protocols::tags::{self, TagSources};
+use crate::splash;
+
+use ion_core::elf::read_u64;
+use raw_cpuid::CpuId;
+use stivale_boot::v2::*;
+use uefi::table::boot::MemoryDescriptor;
+
+use x86_64::align_up;
+use x86_64::registers::control::Cr4Flags;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::*;
+use x86_64::PhysAddr;
+use x86_64::VirtAddr;
+
+use x86_64::structures::paging::mapper::MapToError;
+use xmas_elf::program::ProgramHeader;
+
+/// The identifier of the Ion vendor tag describing Ion's GDT ("iongdtbl").
+const ION_GDT_TAG_ID: u64 = 0x696f6e676474626c;
+
+/// Ion vendor tag that describes the GDT that is loaded when the kernel is entered, so that
+/// kernels can keep using it or copy it.
+#[repr(C)]
+struct GdtTag {
+    header: StivaleTagHeader,
+    /// The virtual address of the GDT in the kernel address space.
+    address: u64,
+    /// The size of the GDT in bytes.
+    size: u64,
+}
+
+/// The flag of the SMP header tag requesting x2APIC mode, and of the SMP tag reporting
+/// that x2APIC mode has been enabled.
+const SMP_X2APIC: u64 = 1 << 0;
+
+/// The stivale2 tag describing the processors of the system. The tag is followed by
+/// `cpu_count` per-CPU information entries, including the bootstrap processor. The
+/// trampoline that the application processors spin in is reported as bootloader
+/// reclaimable memory, so it must not be reclaimed before all of them have been started.
+#[repr(C)]
+struct SmpTag {
+    header: StivaleTagHeader,
+    flags: u64,
+    bsp_lapic_id: u32,
+    unused: u32,
+    cpu_count: u64,
+}
+
+/// The identifier of the stivale2 header tag requesting five-level paging.
+const LEVEL_5_PAGING_HEADER_TAG_ID: u64 = 0x932f477032007e8f;
+
+/// The virtual address at which the physical memory is mapped if five-level paging is
+/// enabled (the start of the higher half of the 57-bit address space).
+const LEVEL_5_HHDM_OFFSET: u64 = 0xff00_0000_0000_0000;
+
+/// The IA32_PAT MSR.
+const PAT_MSR: u32 = 0x277;
+
+/// The page attribute table programmed by Ion: PA0 write-back, PA1 write-through, PA2
+/// uncached-minus, PA3 uncached, PA4 write-protected, PA5 write-combining, PA6
+/// uncached-minus and PA7 uncached. PA0 to PA3 match the power-on defaults.
+const PAT_VALUE: u64 = 0x0007_0105_0007_0406;
+
+/// The PAT bit of a 4 KiB page table entry. It is at the same position as the huge page
+/// bit of the higher level entries, which is why the x86_64 crate only names the latter.
+const PAT_4KIB: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+/// The page table flags that select the write-combining entry (PA5) of the page attribute
+/// table. Only valid in 4 KiB page table entries.
+const PAT_WRITE_COMBINING: PageTableFlags =
+    PageTableFlags::from_bits_truncate(PAT_4KIB.bits() | PageTableFlags::WRITE_THROUGH.bits());
+
+/// Returns true if the CPU supports five-level paging.
+fn level_5_paging_supported() -> bool {
+    if CpuId::new().get_extended_feature_info().is_none() {
+        return false;
+    }
+
+    // SAFETY: The extended feature leaf is supported, as checked above.
+    let features = unsafe { core::arch::x86_64::__cpuid_count(7, 0) };
+    features.ecx & (1 << 16) != 0
+}
+
+fn handle_bss_segment<I>(
+    segment: &ProgramHeader,
+    segment_flags: PageTableFlags,
+    kernel_offset: PhysAddr,
+    page_table: &mut OffsetPageTable,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let virt_start_addr = VirtAddr::new(segment.virtual_addr());
+    let phys_start_addr = kernel_offset + segment.offset();
+    let mem_size = segment.mem_size();
+    let file_size = segment.file_size();
+
+    // Calculate virual memory region that must be zeroed
+    let zero_start = virt_start_addr + file_size;
+    let zero_end = virt_start_addr + mem_size;
+
+    // In some cases, `zero_start` might not be page-aligned. This requires some
+    // special treatment because we can't safely zero a frame of the original file.
+    let data_bytes_before_zero = zero_start.as_u64() & 0xfff;
+    if data_bytes_before_zero != 0 {
+        /*
+         * The last non-bss frame of the segment consists partly of data and partly of bss
+         * memory, which must be zeroed. Unfortunately, the file representation might have
+         * reused the part of the frame that should be zeroed to store the next segment. This
+         * means that we can't simply overwrite that part with zeroes, as we might overwrite
+         * other data this way.
+         *
+         * Example:
+         *
+         *   XXXXXXXXXXXXXXX000000YYYYYYY000ZZZZZZZZZZZ     virtual memory (XYZ are data)
+         *   |·············|     /·····/   /·········/
+         *   |·············| ___/·····/   /·········/
+         *   |·············|/·····/‾‾‾   /·········/
+         *   |·············||·····|/·̅·̅·̅·̅·̅·····/‾‾‾‾
+         *   XXXXXXXXXXXXXXXYYYYYYYZZZZZZZZZZZ              file memory (zeros are not saved)
+         *   '       '       '       '        '
+         *   The areas filled with dots (`·`) indicate a mapping between virtual and file
+         *   memory. We see that the data regions `X`, `Y`, `Z` have a valid mapping, while
+         *   the regions that are initialized with 0 have not.
+         *
+         *   The ticks (`'`) below the file memory line indicate the start of a new frame. We
+         *   see that the last frames of the `X` and `Y` regions in the file are followed
+         *   by the bytes of the next region. So we can't zero these parts of the frame
+         *   because they are needed by other memory regions.
+         *
+         * To solve this problem, we need to allocate a new frame for the last segment page
+         * and copy all data content of the original frame over. Afterwards, we can zero
+         * the remaining part of the frame since the frame is no longer shared with other
+         * segments now.
+         */
+
+        // Calculate the frame where the last segment page is mapped
+        let orig_frame: PhysFrame =
+            PhysFrame::containing_address(phys_start_addr + file_size - 1u64);
+
+        // Allocate a new frame to replace `orig_frame`
+        let new_frame = frame_allocator.allocate_frame().unwrap();
+        frame_allocator.mark_kernel(PhysFrame::range(new_frame, new_frame + 1));
+
+        // Zero the new frame and copy the data bytes from `orig_frame` over, utilizing
+        // that both frames are identity-mapped.
+        unsafe {
+            let orig_bytes_ptr = orig_frame.start_address().as_u64() as *const u8;
+            let new_bytes_ptr = new_frame.start_address().as_u64() as *mut u8;
+
+            mem::zero(new_bytes_ptr, Size4KiB::SIZE as usize);
+            mem::copy(
+                new_bytes_ptr,
+                orig_bytes_ptr,
+                data_bytes_before_zero as usize,
+            );
+        }
+
+        // Map the last page to `new_frame`. `handle_load_segment` leaves it unmapped.
+        let last_page = Page::containing_address(virt_start_addr + file_size - 1u64);
+
+        let flusher =
+            unsafe { page_table.map_to(last_page, new_frame, segment_flags, frame_allocator) }?;
+
+        // SAFETY: We operate on an inactive page table, so we don't need to flush our changes
+        flusher.ignore();
+    }
+
+    // Map additional frames for `.bss` memory that is not present in source file
+    let start_page: Page =
+        Page::containing_address(VirtAddr::new(align_up(zero_start.as_u64(), Size4KiB::SIZE)));
+    let end_page = Page::containing_address(zero_end);
+
+    let pages = Page::range_inclusive(start_page, end_page);
+    let page_count = pages.count() as u64;
+
+    if page_count == 0 {
+        return Ok(());
+    }
+
+    // Allocate all of the frames at once, so that they form a single kernel region in the
+    // memory map.
+    let frames = frame_allocator
+        .allocate_frames(page_count, u64::MAX, Size4KiB::SIZE)
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    frame_allocator.mark_kernel(frames);
+
+    // Zero the frames, utilizing that they are identity-mapped.
+    unsafe {
+        let frames_ptr = frames.start.start_address().as_u64() as *mut u8;
+        mem::zero(frames_ptr, (page_count * Size4KiB::SIZE) as usize);
+    }
+
+    pmm::map_range(
+        page_table,
+        start_page.start_address(),
+        frames.start.start_address(),
+        page_count * Size4KiB::SIZE,
+        segment_flags,
+        frame_allocator,
+    )
+}
+
+/// The identifier of the stivale2 kernel base address tag.
+const KERNEL_BASE_ADDRESS_TAG_ID: u64 = 0x060d78874a2a8af0;
+
+/// The stivale2 tag describing the physical and virtual address at which the kernel has been
+/// loaded. Kernels need it to find their physical frames if the load address is randomized.
+#[repr(C)]
+struct KernelBaseAddressTag {
+    header: StivaleTagHeader,
+    physical_base_address: u64,
+    virtual_base_address: u64,
+}
+
+/// The start of the kernel image in physical and virtual memory.
+#[derive(Clone, Copy)]
+struct KernelBase {
+    phys: PhysAddr,
+    virt: VirtAddr,
+}
+
+/// Returns true if the physical load address of the kernel can be randomized. Only
+/// position independent kernels (`ET_DYN`) are relocated, as other kernels may rely on
+/// being loaded at the physical address they have been linked at.
+fn is_relocatable(elf: &xmas_elf::ElfFile) -> bool {
+    matches!(
+        elf.header.pt2.type_().as_type(),
+        xmas_elf::header::Type::SharedObject
+    )
+}
+
+/// Returns the virtual address range covered by the loadable segments of the kernel, with
+/// the start rounded down and the end rounded up to a page.
+fn load_segments_range(elf: &xmas_elf::ElfFile) -> Option<(VirtAddr, VirtAddr)> {
+    let mut range: Option<(u64, u64)> = None;
+
+    for p_header in elf.program_iter() {
+        if !matches!(p_header.get_type(), Ok(xmas_elf::program::Type::Load))
+            || p_header.mem_size() == 0
+        {
+            continue;
+        }
+
+        let start = p_header.virtual_addr();
+        let end = start + p_header.mem_size();
+
+        range = Some(range.map_or((start, end), |(min, max)| (min.min(start), max.max(end))));
+    }
+
+    range.map(|(start, end)| {
+        (
+            VirtAddr::new(start).align_down(Size4KiB::SIZE),
+            VirtAddr::new(end).align_up(Size4KiB::SIZE),
+        )
+    })
+}
+
+/// Returns the address at which a kernel that is not relocated is loaded, which is where the
+/// lowest loadable segment is mapped from the kernel file.
+fn fixed_kernel_base(elf: &xmas_elf::ElfFile, kernel_offset: PhysAddr) -> KernelBase {
+    let lowest = elf
+        .program_iter()
+        .filter(|p_header| matches!(p_header.get_type(), Ok(xmas_elf::program::Type::Load)))
+        .min_by_key(|p_header| p_header.virtual_addr())
+        .expect("stivale2: the kernel does not have any loadable segments");
+
+    KernelBase {
+        phys: (kernel_offset + lowest.offset()).align_down(Size4KiB::SIZE),
+        virt: VirtAddr::new(lowest.virtual_addr()).align_down(Size4KiB::SIZE),
+    }
+}
+
+/// Allocates zeroed physically contiguous frames at a random address for all loadable
+/// segments of a relocatable kernel, so that the kernel keeps its layout and can find its
+/// physical frames through the kernel base address tag.
+fn allocate_relocated_kernel<I>(
+    elf: &xmas_elf::ElfFile,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> KernelBase
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let (start, end) =
+        load_segments_range(elf).expect("stivale2: the kernel does not have any loadable segments");
+
+    let page_count = (end - start) / Size4KiB::SIZE;
+    let frames = frame_allocator
+        .allocate_random_frames(page_count, Size4KiB::SIZE, entropy::random_u64())
+        .expect("stivale2: failed to allocate the frames of the relocated kernel");
+
+    frame_allocator.mark_kernel(frames);
+
+    // SAFETY: The frames were just allocated and are identity-mapped.
+    unsafe {
+        mem::zero(
+            frames.start.start_address().as_u64() as *mut u8,
+            (page_count * Size4KiB::SIZE) as usize,
+        );
+    }
+
+    KernelBase {
+        phys: frames.start.start_address(),
+        virt: start,
+    }
+}
+
+/// Copies a loadable segment of a relocatable kernel into the frames allocated by
+/// [`allocate_relocated_kernel`] and maps it at its virtual address. The `.bss` part is
+/// already zeroed.
+fn handle_relocated_load_segment<I>(
+    segment: ProgramHeader,
+    kernel_offset: PhysAddr,
+    kernel_base: KernelBase,
+    page_table: &mut OffsetPageTable,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let virt_start_addr = VirtAddr::new(segment.virtual_addr());
+    let start_page: Page = Page::containing_address(virt_start_addr);
+
+    let page_offset = virt_start_addr.as_u64() & (Size4KiB::SIZE - 1);
+    let page_count = align_up(page_offset + segment.mem_size(), Size4KiB::SIZE) / Size4KiB::SIZE;
+
+    if page_count == 0 {
+        return Ok(());
+    }
+
+    let phys_start_addr = kernel_base.phys + (start_page.start_address() - kernel_base.virt);
+
+    // Copy the segment, utilizing that the frames are identity-mapped.
+    unsafe {
+        let dest = phys_start_addr.as_u64() as *mut u8;
+        let src = (kernel_offset + segment.offset()).as_u64() as *const u8;
+
+        mem::copy(
+            dest.add(page_offset as usize),
+            src,
+            segment.file_size() as usize,
+        );
+    }
+
+    let mut segment_flags = PageTableFlags::PRESENT;
+
+    if !segment.flags().is_execute() {
+        segment_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    if segment.flags().is_write() {
+        segment_flags |= PageTableFlags::WRITABLE;
+    }
+
+    pmm::map_range(
+        page_table,
+        start_page.start_address(),
+        phys_start_addr,
+        page_count * Size4KiB::SIZE,
+        segment_flags,
+        frame_allocator,
+    )
+}
+
+fn handle_load_segment<I>(
+    segment: ProgramHeader,
+    kernel_offset: PhysAddr,
+    page_table: &mut OffsetPageTable,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let phys_start_addr = kernel_offset + segment.offset();
+    let start_frame: PhysFrame = PhysFrame::containing_address(phys_start_addr);
+    let end_frame: PhysFrame =
+        PhysFrame::containing_address(phys_start_addr + segment.file_size() - 1u64);
+
+    let virt_start_addr = VirtAddr::new(segment.virtual_addr());
+    let start_page: Page = Page::containing_address(virt_start_addr);
+
+    let mut segment_flags = PageTableFlags::PRESENT;
+
+    if !segment.flags().is_execute() {
+        segment_flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    if segment.flags().is_write() {
+        segment_flags |= PageTableFlags::WRITABLE;
+    }
+
+    // If the `.bss` part of the segment starts in the middle of the last frame, that frame
+    // is replaced by a copy in `handle_bss_segment`, so it must not be mapped here.
+    let zero_start = virt_start_addr + segment.file_size();
+    let frame_count =
+        if segment.mem_size() > segment.file_size() && zero_start.as_u64() & 0xfff != 0 {
+            end_frame - start_frame
+        } else {
+            end_frame - start_frame + 1
+        };
+
+    // Map all frames of the segment at the desired virtual address.
+    pmm::map_range(
+        page_table,
+        start_page.start_address(),
+        start_frame.start_address(),
+        frame_count * Size4KiB::SIZE,
+        segment_flags,
+        frame_allocator,
+    )?;
+
+    if segment.mem_size() > segment.file_size() {
+        handle_bss_segment(
+            &segment,
+            segment_flags,
+            kernel_offset,
+            page_table,
+            frame_allocator,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Allocates and maps `size` bytes of boot information in both the kernel and the
+/// bootloader address space and returns their virtual address.
+fn allocate_boot_info<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    size: usize,
+) -> VirtAddr
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let addr = useable_entries.get_free_address();
+
+    let start_page = Page::containing_address(addr);
+    let end_page = Page::containing_address(addr + size - 1u64);
+    for page in Page::range_inclusive(start_page, end_page) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("frame allocation for boot info failed");
+
+        unsafe {
+            page_tables
+                .kernel
+                .map_to(page, frame, flags, frame_allocator)
+        }
+        .unwrap()
+        .flush();
+
+        // We need to be able to access it too.
+        unsafe {
+            page_tables
+                .bootloader
+                .map_to(page, frame, flags, frame_allocator)
+        }
+        .unwrap()
+        .flush();
+    }
+
+    addr
+}
+
+fn allocate_boot_info_tag<T, I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    value: T,
+) -> &'static mut T
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let addr = allocate_boot_info(
+        page_tables,
+        frame_allocator,
+        useable_entries,
+        core::mem::size_of::<T>(),
+    );
+
+    let boot_info: &'static mut MaybeUninit<T> = unsafe { &mut *addr.as_mut_ptr() };
+    boot_info.write(value)
+}
+
+/// Allocates the EFI runtime map tag and fills it in with the runtime regions, which have
+/// to be remapped at the provided offset already.
+fn create_efi_runtime_map_tag<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    runtime_map: &efi::RuntimeMap,
+    offset: u64,
+) -> &'static mut EfiRuntimeMapTag
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let descriptors = runtime_map.descriptors();
+    let size = core::mem::size_of::<EfiRuntimeMapTag>()
+        + descriptors.len() * core::mem::size_of::<MemoryDescriptor>();
+
+    let addr = allocate_boot_info(page_tables, frame_allocator, useable_entries, size);
+
+    let entries: *mut MemoryDescriptor =
+        (addr + core::mem::size_of::<EfiRuntimeMapTag>()).as_mut_ptr();
+
+    // SAFETY: The descriptors are within the allocated boot information.
+    unsafe { core::ptr::copy_nonoverlapping(descriptors.as_ptr(), entries, descriptors.len()) };
+
+    let tag: &'static mut MaybeUninit<EfiRuntimeMapTag> = unsafe { &mut *addr.as_mut_ptr() };
+
+    tag.write(EfiRuntimeMapTag {
+        header: StivaleTagHeader {
+            identifier: ION_EFI_RUNTIME_MAP_TAG_ID,
+            next: 0,
+        },
+        runtime_services: runtime_map.runtime_services_address() + offset,
+        descriptor_size: core::mem::size_of::<MemoryDescriptor>() as u64,
+        descriptor_version: efi::MEMORY_DESCRIPTOR_VERSION as u64,
+        entries: descriptors.len() as u64,
+    })
+}
+
+/// Allocates the command line tag, followed by a NUL-terminated copy of the provided command
+/// line.
+fn create_cmdline_tag<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    command_line: &str,
+) -> &'static mut CmdlineTag
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let size = core::mem::size_of::<CmdlineTag>() + command_line.len() + 1;
+
+    let addr = allocate_boot_info(page_tables, frame_allocator, useable_entries, size);
+    let string = addr + core::mem::size_of::<CmdlineTag>();
+
+    // SAFETY: The command line and its terminator are within the allocated boot information.
+    unsafe {
+        let ptr: *mut u8 = string.as_mut_ptr();
+
+        core::ptr::copy_nonoverlapping(command_line.as_ptr(), ptr, command_line.len());
+        ptr.add(command_line.len()).write(0);
+    }
+
+    let tag: &'static mut MaybeUninit<CmdlineTag> = unsafe { &mut *addr.as_mut_ptr() };
+
+    tag.write(CmdlineTag {
+        header: StivaleTagHeader {
+            identifier: CMDLINE_TAG_ID,
+            next: 0,
+        },
+        address: string.as_u64(),
+    })
+}
+
+/// Starts the application processors and allocates the SMP tag describing them. Each
+/// application processor gets its own stack of the provided size. The processors are
+/// switched into x2APIC mode if the provided header tag flags request it and the CPU
+/// supports it. Returns [`None`] if the processors cannot be enumerated.
+fn create_smp_tag<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    gdt: VirtAddr,
+    cr4: Cr4Flags,
+    stack_size: usize,
+    header_flags: u64,
+) -> Option<&'static mut SmpTag>
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let capacity = smp::processors().count();
+
+    if capacity == 0 {
+        log::warn!("smp: no processors found in the MADT");
+        return None;
+    }
+
+    let size = core::mem::size_of::<SmpTag>() + capacity * core::mem::size_of::<SmpInfo>();
+    let addr = allocate_boot_info(page_tables, frame_allocator, useable_entries, size);
+    let entries: *mut SmpInfo = (addr + core::mem::size_of::<SmpTag>()).as_mut_ptr();
+
+    let x2apic_requested = header_flags & SMP_X2APIC != 0;
+
+    if x2apic_requested && !smp::x2apic_supported() {
+        log::warn!("smp: x2APIC mode is not supported by the CPU, using xAPIC mode");
+    }
+
+    let apic = smp::LocalApic::new(x2apic_requested);
+    let bsp_lapic_id = apic.id();
+    let trampoline = smp::Trampoline::new(page_tables, frame_allocator, &apic, gdt, cr4);
+
+    // All of the AP stacks share one virtual range, in which each stack is preceded by an
+    // unmapped guard page.
+    let stack_pages = align_up(stack_size as u64, Size4KiB::SIZE) / Size4KiB::SIZE;
+    let slot_size = (stack_pages + 1) * Size4KiB::SIZE;
+    let stacks_start = useable_entries.get_free_address_range(capacity as u64 * slot_size);
+
+    let mut cpu_count = 0;
+    let mut stack_count = 0;
+
+    for (processor_id, lapic_id) in smp::processors() {
+        // SAFETY: The entry is within the allocated boot information, as there are at most
+        // `capacity` processors.
+        let info = unsafe {
+            let info = entries.add(cpu_count);
+
+            info.write(SmpInfo {
+                processor_id,
+                lapic_id,
+                target_stack: 0,
+                goto_address: 0,
+                extra_argument: 0,
+            });
+
+            &mut *info
+        };
+
+        if lapic_id == bsp_lapic_id {
+            cpu_count += 1;
+            continue;
+        }
+
+        // Processors with an ID of 255 or above can only be addressed in x2APIC mode.
+        if !apic.is_x2apic() && lapic_id >= 0xff {
+            log::warn!(
+                "smp: processor {} (local APIC ID {}) requires x2APIC mode",
+                processor_id,
+                lapic_id
+            );
+
+            continue;
+        }
+
+        let stack_start = Page::containing_address(stacks_start + stack_count * slot_size) + 1;
+        let stack = allocate_ap_stack(page_tables, frame_allocator, stack_start, stack_pages);
+
+        info.target_stack = (stack_start + stack_pages).start_address().as_u64();
+
+        // The entry and the stack slot of a processor that did not start are reused for the
+        // next one.
+        if trampoline.start(&apic, lapic_id, info) {
+            // The kernel might keep using the stack, so it must not be reported as
+            // reclaimable.
+            frame_allocator.mark_kernel(stack);
+
+            cpu_count += 1;
+            stack_count += 1;
+        } else {
+            log::warn!(
+                "smp: processor {} (local APIC ID {}) did not start",
+                processor_id,
+                lapic_id
+            );
+
+            free_ap_stack(page_tables, frame_allocator, stack_start, stack);
+        }
+    }
+
+    log::info!("smp: started {} of {} processors", cpu_count, capacity);
+
+    let tag: &'static mut MaybeUninit<SmpTag> = unsafe { &mut *addr.as_mut_ptr() };
+
+    Some(tag.write(SmpTag {
+        header: StivaleTagHeader {
+            identifier: SMP_TAG_ID,
+            next: 0,
+        },
+        flags: if apic.is_x2apic() { SMP_X2APIC } else { 0 },
+        bsp_lapic_id,
+        unused: 0,
+        cpu_count: cpu_count as u64,
+    }))
+}
+
+/// Allocates the stack of an application processor and maps it at the provided page using
+/// 4 KiB pages, so that it can be unmapped again by [`free_ap_stack`] if the processor does
+/// not start. Returns the frames of the stack.
+fn allocate_ap_stack<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    start_page: Page,
+    page_count: u64,
+) -> PhysFrameRange
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let frames = frame_allocator
+        .allocate_frames(page_count, u64::MAX, Size4KiB::SIZE)
+        .expect("smp: failed to allocate the stack of a processor");
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    for (page, frame) in Page::range(start_page, start_page + page_count).zip(frames) {
+        // SAFETY: The frame has just been allocated and the page is in the range reserved for
+        // the AP stacks.
+        unsafe {
+            page_tables
+                .kernel
+                .map_to(page, frame, flags, frame_allocator)
+        }
+        .expect("smp: failed to map the stack of a processor")
+        .ignore();
+    }
+
+    frames
+}
+
+/// Unmaps and frees the stack of an application processor that did not start.
+fn free_ap_stack<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    start_page: Page,
+    frames: PhysFrameRange,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let page_count = frames.end - frames.start;
+
+    for page in Page::range(start_page, start_page + page_count) {
+        page_tables
+            .kernel
+            .unmap(page)
+            .expect("smp: failed to unmap the stack of a processor")
+            .1
+            .ignore();
+    }
+
+    // SAFETY: The frames have been allocated by `allocate_ap_stack` and are no longer
+    // mapped.
+    unsafe { frame_allocator.deallocate_frames(frames) };
+}
+
+/// Allocates the memory map tag and fills it in with the memory map of the frame allocator.
+/// No frames must be allocated afterwards, as they would not be reflected in the memory map.
+fn create_memory_map_tag<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+) -> &'static mut MemoryMapTag
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let (capacity, size) = memory_map_tag_size(frame_allocator);
+    let addr = allocate_boot_info(page_tables, frame_allocator, useable_entries, size);
+
+    // NOTE: The memory map is constructed after the tag has been allocated, so that it
+    // includes the frames of the tag itself.
+    unsafe { write_memory_map_tag(frame_allocator, addr.as_mut_ptr(), capacity) }
+}
+
+/// Allocates a stack of the provided size for kernels that do not provide their own stack
+/// and maps it into the kernel address space. Returns the top of the stack.
+fn allocate_stack<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    size: usize,
+) -> VirtAddr
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    assert!(size > 0, "stivale2: the stack size cannot be 0");
+
+    let page_count = align_up(size as u64, Size4KiB::SIZE) / Size4KiB::SIZE;
+    let frames = frame_allocator
+        .allocate_frames(page_count, u64::MAX, Size4KiB::SIZE)
+        .expect("stivale2: failed to allocate the kernel stack");
+
+    // The kernel might keep using the stack, so it must not be reported as reclaimable.
+    frame_allocator.mark_kernel(frames);
+
+    // Leave the first page unmapped as a guard page, so that a stack overflow results in
+    // a page fault instead of silently corrupting memory.
+    let start_page = Page::containing_address(
+        useable_entries.get_free_address_range((page_count + 1) * Size4KiB::SIZE),
+    ) + 1;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    pmm::map_range(
+        &mut page_tables.kernel,
+        start_page.start_address(),
+        frames.start.start_address(),
+        page_count * Size4KiB::SIZE,
+        flags,
+        frame_allocator,
+    )
+    .expect("stivale2: failed to map the kernel stack");
+
+    (start_page + page_count).start_address()
+}
+
+/// Maps the framebuffer at the provided physical address into the kernel address space with
+/// the write-combining memory type and returns its virtual address. This makes rendering an
+/// early kernel console a lot faster. The PAT must have been programmed by [`setup_pat`].
+fn map_framebuffer<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    useable_entries: &mut UsedLevel4Entries,
+    address: u64,
+    size: usize,
+) -> VirtAddr
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let start_frame = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(address));
+    let end_frame = PhysFrame::containing_address(PhysAddr::new(address + size as u64 - 1));
+
+    let start_page = Page::containing_address(
+        useable_entries.get_free_address_range((end_frame - start_frame + 1) * Size4KiB::SIZE),
+    );
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PAT_WRITE_COMBINING;
+
+    // `map_to` refuses entries with the PAT bit set, as it cannot tell it apart from the
+    // huge page bit, so the entries are written by `map_range` instead.
+    pmm::map_range(
+        &mut page_tables.kernel,
+        start_page.start_address(),
+        start_frame.start_address(),
+        (end_frame - start_frame + 1) * Size4KiB::SIZE,
+        flags,
+        frame_allocator,
+    )
+    .expect("stivale2: failed to map the framebuffer");
+
+    start_page.start_address() + (address & (Size4KiB::SIZE - 1))
+}
+
+pub fn boot<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+    kernel: &'static [u8],
+    dtb: Option<&'static [u8]>,
+    runtime_map: Option<efi::RuntimeMap>,
+    tag_sources: TagSources,
+    entry: &ConfigurationEntry,
+) where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let kernel_load_start = profile::start(profile::Phase::KernelLoad);
+    let kernel_offset = unsafe { PhysAddr::new_unsafe(&kernel[0] as *const u8 as u64) };
+    assert!(
+        kernel_offset.is_aligned(Size4KiB::SIZE),
+        "stivale2: loaded kernel ELF file is not sufficiently aligned"
+    );
+
+    let elf = xmas_elf::ElfFile::new(kernel).expect("stivale2: invalid ELF file");
+
+    let stivale2_hdr;
+    let level_5_paging;
+    let smp_header_flags;
+    let kernel_base;
+    let is_32_bit = false;
+
+    cpu::enable_nxe_bit();
+    cpu::enable_write_protect_bit();
+    cpu::init_fpu();
+
+    let write_combining = setup_pat();
+
+    match elf.header.pt2.machine().as_machine() {
+        xmas_elf::header::Machine::X86_64 => {
+            // 1. Check if the CPU actually supports long mode.
+            let long_mode_supported = CpuId::new()
+                .get_extended_processor_and_feature_identifiers()
+                .map_or(false, |info| info.has_64bit_mode());
+
+            if !long_mode_supported {
+                panic!("stivale2: CPU does not support 64-bit mode.")
+            }
+
+            xmas_elf::header::sanity_check(&elf).expect("stivale2: failed ELF sanity check");
+
+            // 2. Get the stivale2 header section.
+            let header = elf
+                .find_section_by_name(".stivale2hdr")
+                .expect("stivale2: section .stivale2hdr not found");
+
+            if header.size() < core::mem::size_of::<StivaleHeader>() as u64 {
+                panic!("stivale2: section .stivale2hdr is smaller than size of the struct.");
+            } else if header.size() > core::mem::size_of::<StivaleHeader>() as u64 {
+                panic!("stivale2: section .stivale2hdr is larger than size of the struct.");
+            }
+
+            // SAFETY: The size of the section is checked above and the address provided is valid and
+            // mapped.
+            stivale2_hdr = unsafe { &*(header.raw_data(&elf).as_ptr() as *const StivaleHeader) };
+
+            log::info!("stivale2: 64-bit kernel detected");
+
+            // Five-level paging is only enabled if both the kernel and the CPU support it.
+            let level_5_requested =
+                find_header_tag(&elf, header.raw_data(&elf), LEVEL_5_PAGING_HEADER_TAG_ID)
+                    .is_some();
+            level_5_paging = level_5_requested && level_5_paging_supported();
+
+            if level_5_requested && !level_5_paging {
+                log::warn!("stivale2: five-level paging is not supported by the CPU");
+            }
+
+            // The flags of the SMP header tag follow the identifier and the next pointer.
+            smp_header_flags = find_header_tag(&elf, header.raw_data(&elf), SMP_HEADER_TAG_ID)
+                .map(|tag| read_u64(tag, 16).unwrap_or(0));
+
+            // 3. Load the kernel.
+            let relocate = entry.kaslr() && is_relocatable(&elf);
+
+            if entry.kaslr() && !relocate {
+                log::warn!(
+                    "stivale2: the physical load address of a non-PIE kernel is not randomized"
+                );
+            }
+
+            kernel_base = if relocate {
+                allocate_relocated_kernel(&elf, frame_allocator)
+            } else {
+                fixed_kernel_base(&elf, kernel_offset)
+            };
+
+            for p_header in elf.program_iter() {
+                xmas_elf::program::sanity_check(p_header, &elf)
+                    .expect("stivale2: failed ELF program header sanity check");
+
+                match p_header
+                    .get_type()
+                    .expect("stivale2: failed to get ELF program heade type")
+                {
+                    xmas_elf::program::Type::Load if relocate => handle_relocated_load_segment(
+                        p_header,
+                        kernel_offset,
+                        kernel_base,
+                        &mut page_tables.kernel,
+                        frame_allocator,
+                    )
+                    .unwrap(),
+
+                    xmas_elf::program::Type::Load => handle_load_segment(
+                        p_header,
+                        kernel_offset,
+                        &mut page_tables.kernel,
+                        frame_allocator,
+                    )
+                    .unwrap(),
+                    _ => {}
+                }
+            }
+        }
+
+        machine => panic!("stivale2: unsupported architecture {:?}", machine),
+    };
+
+    profile::finish(profile::Phase::KernelLoad, kernel_load_start);
+
+    if level_5_paging {
+        page_tables.enable_level_5_paging(frame_allocator);
+        log::info!("stivale2: five-level paging enabled");
+    }
+
+    if (stivale2_hdr.get_flags() & (1 << 1)) == 1 && is_32_bit {
+        panic!("stivale2: higher half header flag not supported in 32-bit mode");
+    }
+
+    // The stivale2 specs says the stack has to be 16-byte aligned.
+    if (stivale2_hdr.get_stack() as u64 & (16 - 1)) != 0 {
+        panic!("stivale2: requested stack is not 16-byte aligned");
+    }
+
+    // It also says the stack cannot be NULL for 32-bit kernels
+    if is_32_bit && stivale2_hdr.get_stack() as u64 == 0 {
+        panic!("stivale2: the stack cannot be 0 for 32-bit kernels");
+    }
+
+    // Identity-map context switch function, so that we don't get an immediate pagefault
+    // after switching the active page table.
+    let context_switch_function = PhysAddr::new(context_switch as *const () as u64);
+    let context_switch_function_start_frame: PhysFrame =
+        PhysFrame::containing_address(context_switch_function);
+
+    for frame in PhysFrame::range_inclusive(
+        context_switch_function_start_frame,
+        context_switch_function_start_frame + 1,
+    ) {
+        unsafe {
+            page_tables
+                .kernel
+                .identity_map(frame, PageTableFlags::PRESENT, frame_allocator)
+        }
+        .unwrap()
+        .flush();
+    }
+
+    logger::flush();
+
+    let mut useable_entries = UsedLevel4Entries::new(elf.program_iter());
+
+    // The bootloader address space identity-maps the first 512 GiB using the first level 4
+    // entry of the firmware page tables (see `setup_boot_paging`), so the boot information
+    // cannot be mapped there. The context switch function is identity-mapped too.
+    useable_entries.mark_range_as_used(VirtAddr::zero(), pmm::LEVEL_4_ENTRY_SIZE);
+    useable_entries.mark_range_as_used(
+        VirtAddr::new(context_switch_function.as_u64()),
+        2 * Size4KiB::SIZE,
+    );
+
+    // A null stack pointer means that the kernel expects us to provide a stack.
+    let stack_top = if stivale2_hdr.get_stack() as u64 == 0 {
+        let stack_top = allocate_stack(
+            page_tables,
+            frame_allocator,
+            &mut useable_entries,
+            entry.stack_size(),
+        );
+
+        log::debug!(
+            "stivale2: allocated a {} byte stack at {:#x}",
+            entry.stack_size(),
+            stack_top.as_u64()
+        );
+
+        stack_top
+    } else {
+        VirtAddr::new(stivale2_hdr.get_stack() as u64)
+    };
+
+    let max_phys = frame_allocator.max_phys_addr();
+
+    let direct_map_size = align_up(max_phys.as_u64(), Size2MiB::SIZE);
+    let direct_map_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let offset = if level_5_paging {
+        // With five-level paging, the physical memory is mapped in its own level 5 entries,
+        // so that more than the 128 TiB of the four-level higher half can be mapped.
+        pmm::map_range_level_5(
+            page_tables.kernel_level_5_table().unwrap(),
+            LEVEL_5_HHDM_OFFSET,
+            PhysAddr::new(0),
+            direct_map_size,
+            direct_map_flags,
+            frame_allocator,
+        )
+        .expect("stivale2: failed to map the physical memory");
+
+        LEVEL_5_HHDM_OFFSET
+    } else {
+        // The physical memory is mapped at a random offset in the higher half if KASLR is
+        // enabled, so that the kernel can access it without knowing its physical layout.
+        let offset = if entry.kaslr() {
+            useable_entries.get_random_higher_half_range(max_phys.as_u64(), entropy::random_u64())
+        } else {
+            useable_entries.get_free_address_range(max_phys.as_u64())
+        };
+
+        pmm::map_range(
+            &mut page_tables.kernel,
+            offset,
+            PhysAddr::new(0),
+            direct_map_size,
+            direct_map_flags,
+            frame_allocator,
+        )
+        .expect("stivale2: failed to map the physical memory");
+
+        offset.as_u64()
+    };
+
+    let level_5_trampoline = if level_5_paging {
+        Some(allocate_level_5_trampoline(page_tables, frame_allocator))
+    } else {
+        None
+    };
+
+    let tags_start = profile::start(profile::Phase::TagConstruction);
+
+    // Now we have to prepare the stivale struct that we will pass as an argument
+    // in RDI to the kernel's entry point function.
+    let stivale_struct = allocate_boot_info_tag(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        StivaleStruct::new(),
+    );
+
+    stivale_struct.set_bootloader_brand("Ion");
+    stivale_struct.set_bootloader_version(env!("CARGO_PKG_VERSION"));
+
+    let hhdm_tag = allocate_boot_info_tag(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        HhdmTag {
+            header: StivaleTagHeader {
+                identifier: HHDM_TAG_ID,
+                next: 0,
+            },
+            address: offset,
+        },
+    );
+
+    stivale_struct.add_tag(&mut hhdm_tag.header);
+
+    let kernel_base_tag = allocate_boot_info_tag(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        KernelBaseAddressTag {
+            header: StivaleTagHeader {
+                identifier: KERNEL_BASE_ADDRESS_TAG_ID,
+                next: 0,
+            },
+            physical_base_address: kernel_base.phys.as_u64(),
+            virtual_base_address: kernel_base.virt.as_u64(),
+        },
+    );
+
+    stivale_struct.add_tag(&mut kernel_base_tag.header);
+
+    let cmdline_tag = create_cmdline_tag(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        entry.command_line(),
+    );
+
+    stivale_struct.add_tag(&mut cmdline_tag.header);
+
+    // The framebuffer is only passed to the kernel if it is directly accessible.
+    if let Some((address, info)) = logger::framebuffer() {
+        let address = if write_combining {
+            map_framebuffer(
+                page_tables,
+                frame_allocator,
+                &mut useable_entries,
+                address,
+                info.size(),
+            )
+            .as_u64()
+        } else {
+            address
+        };
+
+        let framebuffer_tag = allocate_boot_info_tag(
+            page_tables,
+            frame_allocator,
+            &mut useable_entries,
+            FramebufferTag::new(address, info),
+        );
+
+        stivale_struct.add_tag(&mut framebuffer_tag.header);
+    }
+
+    if let Some(dtb) = dtb {
+        let dtb_tag = allocate_boot_info_tag(
+            page_tables,
+            frame_allocator,
+            &mut useable_entries,
+            DtbTag {
+                header: StivaleTagHeader {
+                    identifier: DTB_TAG_ID,
+                    next: 0,
+                },
+                address: dtb.as_ptr() as u64,
+                size: dtb.len() as u64,
+            },
+        );
+
+        stivale_struct.add_tag(&mut dtb_tag.header);
+    }
+
+    if let Some(mut runtime_map) = runtime_map {
+        // The runtime regions are reachable through the direct map, so they are placed at
+        // the same offset. The call has to be made while the firmware is still identity
+        // mapped, which is the case in the bootloader address space.
+        match unsafe { runtime_map.remap(offset) } {
+            Ok(()) => {
+                let runtime_map_tag = create_efi_runtime_map_tag(
+                    page_tables,
+                    frame_allocator,
+                    &mut useable_entries,
+                    &runtime_map,
+                    offset,
+                );
+
+                stivale_struct.add_tag(&mut runtime_map_tag.header);
+
+                log::debug!(
+                    "stivale2: remapped the EFI runtime services to {:#x}",
+                    offset
+                );
+            }
+
+            Err(status) => log::error!(
+                "stivale2: failed to remap the EFI runtime services ({:?})",
+                status
+            ),
+        }
+    }
+
+    // Kernels cannot rely on the GDT installed by the firmware, so Ion loads its own GDT
+    // before jumping to the kernel. It is mapped at the same address in both address
+    // spaces.
+    let gdt_address = allocate_boot_info(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        gdt::SIZE,
+    );
+
+    unsafe { gdt::write(gdt_address) };
+
+    let gdt_tag = allocate_boot_info_tag(
+        page_tables,
+        frame_allocator,
+        &mut useable_entries,
+        GdtTag {
+            header: StivaleTagHeader {
+                identifier: ION_GDT_TAG_ID,
+                next: 0,
+            },
+            address: gdt_address.as_u64(),
+            size: gdt::SIZE as u64,
+        },
+    );
+
+    stivale_struct.add_tag(&mut gdt_tag.header);
+
+    let cr4 = cpu::kernel_cr4(entry);
+
+    // The application processors are started after all of the other kernel mappings have
+    // been created, as they switch to the kernel address space right away.
+    if let Some(smp_header_flags) = smp_header_flags {
+        let smp_tag = create_smp_tag(
+            page_tables,
+            frame_allocator,
+            &mut useable_entries,
+            gdt_address,
+            cr4,
+            entry.stack_size(),
+            smp_header_flags,
+        );
+
+        if let Some(smp_tag) = smp_tag {
+            stivale_struct.add_tag(&mut smp_tag.header);
+        }
+    }
+
+    profile::finish(profile::Phase::TagConstruction, tags_start);
+    profile::log_summary();
+
+    log::info!("stivale2: jumping to the kernel entry point");
+
+    tags::attach(
+        &tag_sources,
+        |size| {
+            allocate_boot_info(page_tables, frame_allocator, &mut useable_entries, size)
+                .as_mut_ptr()
+        },
+        |tag| stivale_struct.add_tag(tag),
+    );
+
+    // NOTE: The memory map tag has to be created after all other allocations, as any
+    // frame that is allocated afterwards would be reported as usable to the kernel.
+    let memory_map_tag = create_memory_map_tag(page_tables, frame_allocator, &mut useable_entries);
+
+    stivale_struct.add_tag(&mut memory_map_tag.header);
+
+    let switch_context = SwitchContext {
+        page_table: page_tables.kernel_level_4_frame,
+        stack_top,
+        entry_point: VirtAddr::new(elf.header.pt2.entry_point()),
+        stivale_struct,
+        gdt: gdt_address,
+        cr4,
+        level_5_trampoline,
+    };
+
+    if let Some(trampoline) = level_5_trampoline {
+        write_level_5_trampoline_data(trampoline, page_tables, &switch_context);
+    }
+
+    splash::advance(splash::Milestone::Handoff);
+
+    // SAFTEY: The stack and the kernel entry point are checked above.
+    unsafe {
+        gdt::load(gdt_address);
+        context_switch(switch_context);
+    }
+}
+
+struct SwitchContext {
+    page_table: PhysFrame,
+    stack_top: VirtAddr,
+    entry_point: VirtAddr,
+    stivale_struct: &'static StivaleStruct,
+    /// The address of Ion's GDT, which is mapped in both address spaces.
+    gdt: VirtAddr,
+    /// The CR4 value that is loaded after switching to the kernel address space.
+    cr4: Cr4Flags,
+    /// The physical address of the trampoline that enables five-level paging, if any.
+    level_5_trampoline: Option<PhysAddr>,
+}
+
+// The trampoline that enables five-level paging before jumping to the kernel. CR4.LA57 can
+// only be changed while paging is disabled, so the trampoline temporarily leaves long mode
+// through the 32-bit code segment of Ion's GDT. As it runs in 32-bit mode, it is copied to
+// an identity mapped frame below 4 GiB (including a copy of the GDT) whose end is used as
+// the stack. Afterwards the GDT in the kernel address space is loaded again. The
+// trampoline expects its own address in RSI and the data at its end to be filled in by
+// `write_level_5_trampoline_data`.
+global_asm!(
+    r#"
+.global LA57_TRAMPOLINE_START
+.global LA57_TRAMPOLINE_DATA
+.global LA57_TRAMPOLINE_END
+
+.code64
+LA57_TRAMPOLINE_START:
+    cli
+    lea rsp, [rsi + 0x1000]
+    lgdt [rsi + (la57_gdtr - LA57_TRAMPOLINE_START)]
+
+    push 0x18
+    lea rax, [rsi + (la57_compat_mode - LA57_TRAMPOLINE_START)]
+    push rax
+    retfq
+
+.code32
+la57_compat_mode:
+    mov ax, 0x20
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    // Disable paging, which leaves long mode.
+    mov eax, cr0
+    btr eax, 31
+    mov cr0, eax
+
+    mov eax, cr4
+    bts eax, 12
+    mov cr4, eax
+
+    mov eax, [esi + (la57_level_5_table - LA57_TRAMPOLINE_START)]
+    mov cr3, eax
+
+    // Enable paging, which enters long mode again, now using five-level paging.
+    mov eax, cr0
+    bts eax, 31
+    mov cr0, eax
+
+    push 0x28
+    lea eax, [esi + (la57_long_mode - LA57_TRAMPOLINE_START)]
+    push eax
+    retf
+
+.code64
+la57_long_mode:
+    // The selectors are the same in both copies of the GDT.
+    lgdt [rip + la57_kernel_gdtr]
+
+    mov ax, 0x30
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov rsp, [rip + la57_stack_top]
+    mov rdi, [rip + la57_stivale_struct]
+    push 0
+    push qword ptr [rip + la57_entry_point]
+
+    // Zero the general purpose registers, see `context_switch`.
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor ebp, ebp
+    xor r8d, r8d
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+
+    cld
+    ret
+
+.align 8
+LA57_TRAMPOLINE_DATA:
+la57_gdt:
+    .fill 7, 8, 0
+la57_gdtr:
+    .word 0
+    .quad 0
+la57_kernel_gdtr:
+    .word 0
+    .quad 0
+la57_level_5_table:
+    .long 0
+la57_stack_top:
+    .quad 0
+la57_entry_point:
+    .quad 0
+la57_stivale_struct:
+    .quad 0
+LA57_TRAMPOLINE_END:
+"#
+);
+
+extern "C" {
+    static LA57_TRAMPOLINE_START: u8;
+    static LA57_TRAMPOLINE_DATA: u8;
+    static LA57_TRAMPOLINE_END: u8;
+}
+
+/// The data at the end of the five-level paging trampoline. The layout has to match the
+/// data in the `global_asm!` block above.
+#[repr(C, packed)]
+struct Level5TrampolineData {
+    gdt: [u64; 7],
+    gdtr: gdt::Pointer,
+    kernel_gdtr: gdt::Pointer,
+    level_5_table: u32,
+    stack_top: u64,
+    entry_point: u64,
+    stivale_struct: u64,
+}
+
+/// Returns the offset of the provided trampoline symbol from the start of the trampoline.
+fn trampoline_offset(symbol: &u8) -> u64 {
+    // SAFETY: Only the addresses of the symbols are taken.
+    let start = unsafe { &LA57_TRAMPOLINE_START } as *const u8 as u64;
+    symbol as *const u8 as u64 - start
+}
+
+/// Copies the five-level paging trampoline to a frame below 4 GiB and identity-maps it in
+/// the kernel address space. Returns the physical address of the trampoline.
+fn allocate_level_5_trampoline<I>(
+    page_tables: &mut BootPageTables,
+    frame_allocator: &mut BootFrameAllocator<I>,
+) -> PhysAddr
+where
+    I: ExactSizeIterator + Clone,
+    I::Item: BootMemoryRegion,
+{
+    let frame = frame_allocator
+        .allocate_frames(1, 1 << 32, Size4KiB::SIZE)
+        .expect("stivale2: failed to allocate the five-level paging trampoline")
+        .start;
+
+    let size = trampoline_offset(unsafe { &LA57_TRAMPOLINE_END }) as usize;
+
+    // SAFETY: The frame is identity-mapped and the trampoline is much smaller than a page.
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            &LA57_TRAMPOLINE_START as *const u8,
+            frame.start_address().as_u64() as *mut u8,
+            size,
+        );
+    }
+
+    unsafe {
+        page_tables.kernel.identity_map(
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            frame_allocator,
+        )
+    }
+    .expect("stivale2: failed to map the five-level paging trampoline")
+    .ignore();
+
+    frame.start_address()
+}
+
+/// Fills in the data of the five-level paging trampoline at the provided address.
+fn write_level_5_trampoline_data(
+    trampoline: PhysAddr,
+    page_tables: &BootPageTables,
+    context: &SwitchContext,
+) {
+    let level_5_table = page_tables
+        .kernel_level_5_frame
+        .expect("stivale2: five-level paging is not enabled");
+
+    let data_offset = trampoline_offset(unsafe { &LA57_TRAMPOLINE_DATA });
+
+    let data = Level5TrampolineData {
+        gdt: gdt::ENTRIES,
+        gdtr: gdt::Pointer::new(VirtAddr::new(trampoline.as_u64() + data_offset)),
+        kernel_gdtr: gdt::Pointer::new(context.gdt),
+        level_5_table: level_5_table.start_address().as_u64() as u32,
+        stack_top: context.stack_top.as_u64(),
+        entry_point: context.entry_point.as_u64(),
+        stivale_struct: context.stivale_struct as *const _ as u64,
+    };
+
+    // SAFETY: The trampoline has been copied to the identity-mapped frame.
+    unsafe {
+        ((trampoline.as_u64() + data_offset) as *mut Level5TrampolineData).write_unaligned(data);
+    }
+}
+
+/// Switches to the kernel address space and jumps to the kernel entry point. As required
+/// by the stivale2 specification, RDI contains the address of the stivale2 struct, the
+/// return address on the stack is 0, interrupts are disabled and the direction flag is
+/// cleared. All of the other general purpose registers are zeroed, so that the kernel does
+/// not depend on any leftover state of Ion.
+unsafe fn context_switch(context: SwitchContext) -> ! {
+    if let Some(trampoline) = context.level_5_trampoline {
+        // The trampoline is identity-mapped in the kernel address space and sets up the
+        // same register state before jumping to the kernel.
+        asm!(
+            "cli; mov cr3, {}; mov cr4, {}; jmp rsi",
+            in(reg) context.page_table.start_address().as_u64(),
+            in(reg) context.cr4.bits(),
+            in("rsi") trampoline.as_u64(),
+        );
+
+        unreachable!()
+    }
+
+    asm!(
+        "
+        cli
+        mov cr3, {}
+        mov cr4, {}
+        mov rsp, {}
+        push 0
+        push {}
+
+        xor eax, eax
+        xor ebx, ebx
+        xor ecx, ecx
+        xor edx, edx
+        xor esi, esi
+        xor ebp, ebp
+        xor r8d, r8d
+        xor r9d, r9d
+        xor r10d, r10d
+        xor r11d, r11d
+        xor r12d, r12d
+        xor r13d, r13d
+        xor r14d, r14d
+        xor r15d, r15d
+
+        cld
+        ret
+        ",
+        in(reg) context.page_table.start_address().as_u64(),
+        in(reg) context.cr4.bits(),
+        in(reg) context.stack_top.as_u64(),
+        in(reg) context.entry_point.as_u64(),
+        in("rdi") context.stivale_struct as *const _ as usize,
+    );
+
+    unreachable!()
+}
+
+/// Programs the page attribute table, so that the framebuffer can be mapped with the
+/// write-combining memory type. Returns false if the CPU does not support the PAT.
+fn setup_pat() -> bool {
+    let pat_supported = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_pat());
+
+    if pat_supported {
+        // SAFETY: Only entries that are not used by the firmware mappings are changed.
+        unsafe { Msr::new(PAT_MSR).write(PAT_VALUE) };
+    } else {
+        log::warn!("stivale2: PAT not supported, the framebuffer is not write-combining");
+    }
+
+    pat_supported
+}
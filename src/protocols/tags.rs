@@ -0,0 +1,211 @@
+// Ion vendor tags, which are attached to the boot information of the kernel in addition to
+// the tags defined by the boot protocol. Each tag can be left out of the build by disabling
+// its Cargo feature.
+
+use core::mem::{self, MaybeUninit};
+
+use stivale_boot::v2::StivaleTagHeader;
+
+#[cfg(feature = "random-seed-tag")]
+use crate::entropy;
+#[cfg(feature = "boot-log-tag")]
+use crate::logger;
+use crate::pci::PciDevice;
+#[cfg(feature = "profile-tag")]
+use crate::{profile, time};
+
+/// The identifier of the Ion vendor tag describing the boot log ring buffer ("ionbtlog").
+#[cfg(feature = "boot-log-tag")]
+const ION_BOOT_LOG_TAG_ID: u64 = 0x696f6e62746c6f67;
+
+/// Ion vendor tag that describes the in-memory boot log, so that kernels can fold Ion's
+/// log records into their own log.
+#[cfg(feature = "boot-log-tag")]
+#[repr(C)]
+struct BootLogTag {
+    header: StivaleTagHeader,
+    /// The physical address of the ring buffer.
+    address: u64,
+    /// The capacity of the ring buffer in bytes.
+    size: u64,
+    /// The offset at which the next byte would have been written. If the ring buffer has
+    /// wrapped around this is also the offset of the oldest byte.
+    head: u64,
+    /// Set to 1 if the ring buffer has wrapped around at least once.
+    wrapped: u64,
+}
+
+/// The identifier of the Ion vendor tag describing the boot time profile ("ionprofl").
+#[cfg(feature = "profile-tag")]
+const ION_PROFILE_TAG_ID: u64 = 0x696f6e70726f666c;
+
+/// Ion vendor tag that describes how long the phases of the boot process took, so that
+/// regressions in boot latency can be measured by the kernel.
+#[cfg(feature = "profile-tag")]
+#[repr(C)]
+struct ProfileTag {
+    header: StivaleTagHeader,
+    /// The amount of microseconds between the calibration of the timer and the creation of
+    /// the tag.
+    timestamp: u64,
+    /// The amount of entries in `durations`.
+    phase_count: u64,
+    /// The duration of each phase in microseconds, in the following order: config load,
+    /// menu, kernel read, paging setup, kernel load and tag construction.
+    durations: [u64; profile::Phase::COUNT],
+}
+
+/// The identifier of the Ion vendor tag listing the PCI devices ("ionpcidv").
+#[cfg(feature = "pci-tag")]
+const ION_PCI_TAG_ID: u64 = 0x696f6e7063696476;
+
+/// Ion vendor tag that lists the PCI functions found by Ion, to help with early driver
+/// bring-up. The tag is followed by `entries` PCI device entries.
+#[cfg(feature = "pci-tag")]
+#[repr(C)]
+struct PciTag {
+    header: StivaleTagHeader,
+    /// The amount of PCI device entries following the tag.
+    entries: u64,
+}
+
+/// The identifier of the Ion vendor tag containing the random seed ("ionrseed").
+#[cfg(feature = "random-seed-tag")]
+const ION_RANDOM_SEED_TAG_ID: u64 = 0x696f6e7273656564;
+
+/// The amount of random bytes passed in the random seed tag.
+#[cfg(feature = "random-seed-tag")]
+const RANDOM_SEED_SIZE: usize = 64;
+
+/// Ion vendor tag containing random bytes from Ion's entropy pool, so that kernels can
+/// seed their random number generator before their own entropy sources are available.
+#[cfg(feature = "random-seed-tag")]
+#[repr(C)]
+struct RandomSeedTag {
+    header: StivaleTagHeader,
+    /// The amount of bytes in `seed`.
+    size: u64,
+    seed: [u8; RANDOM_SEED_SIZE],
+}
+
+/// The information that the vendor tags are created from, which is collected before the
+/// boot services are exited.
+pub struct TagSources {
+    /// The PCI functions found by Ion, if the entry requested the PCI tag.
+    pub pci_devices: Option<&'static [PciDevice]>,
+}
+
+fn header(identifier: u64) -> StivaleTagHeader {
+    StivaleTagHeader {
+        identifier,
+        next: 0,
+    }
+}
+
+/// Writes the provided tag to newly allocated boot information, followed by `extra` bytes
+/// that are left for the caller to fill in.
+fn write_tag<T>(
+    allocate: &mut impl FnMut(usize) -> *mut u8,
+    value: T,
+    extra: usize,
+) -> &'static mut T {
+    let addr = allocate(mem::size_of::<T>() + extra);
+
+    // SAFETY: The allocator returns writable boot information of the requested size.
+    let tag: &'static mut MaybeUninit<T> = unsafe { &mut *(addr as *mut _) };
+    tag.write(value)
+}
+
+#[cfg(feature = "pci-tag")]
+fn pci_tag(
+    allocate: &mut impl FnMut(usize) -> *mut u8,
+    devices: &[PciDevice],
+) -> &'static mut StivaleTagHeader {
+    let extra = devices.len() * mem::size_of::<PciDevice>();
+    let tag = write_tag(
+        allocate,
+        PciTag {
+            header: header(ION_PCI_TAG_ID),
+            entries: devices.len() as u64,
+        },
+        extra,
+    );
+
+    // SAFETY: The devices are within the allocated boot information.
+    unsafe {
+        let entries = (tag as *mut PciTag).add(1) as *mut PciDevice;
+        core::ptr::copy_nonoverlapping(devices.as_ptr(), entries, devices.len());
+    }
+
+    &mut tag.header
+}
+
+#[cfg(feature = "random-seed-tag")]
+fn random_seed_tag(allocate: &mut impl FnMut(usize) -> *mut u8) -> &'static mut StivaleTagHeader {
+    let mut tag = RandomSeedTag {
+        header: header(ION_RANDOM_SEED_TAG_ID),
+        size: RANDOM_SEED_SIZE as u64,
+        seed: [0; RANDOM_SEED_SIZE],
+    };
+
+    entropy::fill(&mut tag.seed);
+    &mut write_tag(allocate, tag, 0).header
+}
+
+#[cfg(feature = "profile-tag")]
+fn profile_tag(allocate: &mut impl FnMut(usize) -> *mut u8) -> &'static mut StivaleTagHeader {
+    let tag = ProfileTag {
+        header: header(ION_PROFILE_TAG_ID),
+        timestamp: time::timestamp_us(),
+        phase_count: profile::Phase::COUNT as u64,
+        durations: profile::durations(),
+    };
+
+    &mut write_tag(allocate, tag, 0).header
+}
+
+#[cfg(feature = "boot-log-tag")]
+fn boot_log_tag(allocate: &mut impl FnMut(usize) -> *mut u8) -> &'static mut StivaleTagHeader {
+    let boot_log_tag = {
+        let boot_log = logger::BOOT_LOG.lock();
+
+        BootLogTag {
+            header: header(ION_BOOT_LOG_TAG_ID),
+            address: boot_log.address(),
+            size: boot_log.capacity() as u64,
+            head: boot_log.head() as u64,
+            wrapped: boot_log.wrapped() as u64,
+        }
+    };
+
+    &mut write_tag(allocate, boot_log_tag, 0).header
+}
+
+/// Creates the Ion vendor tags that are enabled and passes each of them to `add_tag`. The
+/// boot information is allocated using `allocate`, which has to return memory of the
+/// requested size that is accessible at the same address by both Ion and the kernel.
+///
+/// NOTE: Anything that is logged after this function returns is not reflected in the boot
+/// log tag, so it has to be called right before the memory map tag is created.
+#[allow(unused_variables, unused_mut)]
+pub fn attach(
+    sources: &TagSources,
+    mut allocate: impl FnMut(usize) -> *mut u8,
+    mut add_tag: impl FnMut(&'static mut StivaleTagHeader),
+) {
+    #[cfg(feature = "pci-tag")]
+    if let Some(devices) = sources.pci_devices {
+        add_tag(pci_tag(&mut allocate, devices));
+    }
+
+    #[cfg(feature = "random-seed-tag")]
+    add_tag(random_seed_tag(&mut allocate));
+
+    #[cfg(feature = "profile-tag")]
+    add_tag(profile_tag(&mut allocate));
+
+    // The boot log tag has to be created last, as anything that is logged after it is
+    // created would not be reflected in the head offset passed to the kernel.
+    #[cfg(feature = "boot-log-tag")]
+    add_tag(boot_log_tag(&mut allocate));
+}
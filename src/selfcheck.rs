@@ -0,0 +1,60 @@
+use alloc::boxed::Box;
+use alloc::format;
+
+use uefi::proto::loaded_image::LoadedImage;
+
+use ion_core::checksum::{self, Mismatch, Record};
+
+use crate::efi;
+
+/// The checksum record of Ion, filled in after the build by `tools/embed_checksum.py`. See
+/// [`ion_core::checksum`] for its layout.
+#[used]
+#[link_section = ".ioncrc"]
+static RECORD: [u8; checksum::RECORD_SIZE] = checksum::empty_record();
+
+/// Returns the checksum that has been embedded into Ion, if any. The record is read through
+/// a volatile read for the same reason as the symbol table, as it is patched after the
+/// build.
+pub fn expected() -> Option<Record> {
+    let record = &RECORD as *const [u8; checksum::RECORD_SIZE];
+
+    // SAFETY: The pointer is valid as it is derived from a reference to a static.
+    Record::parse(unsafe { &*core::ptr::read_volatile(&record) })
+}
+
+/// Returns the URI of the file that Ion has been loaded from (e.g.
+/// `boot:///EFI/BOOT/BOOTX64.EFI`), which is on the boot partition.
+pub fn image_uri(loaded_image: &LoadedImage) -> Option<&'static str> {
+    let path = ion_core::devpath::file_path(efi::image_file_path(loaded_image)?)?;
+    let path = path.trim_start_matches('\\').replace('\\', "/");
+
+    Some(Box::leak(format!("boot:///{}", path).into_boxed_str()))
+}
+
+/// Checks the copy of Ion at the provided URI against the embedded checksum and warns if
+/// it does not match. A corrupted copy on the EFI system partition otherwise shows up as
+/// hangs or crashes that are hard to track down.
+pub fn verify(uri: &str, image: &[u8], expected: Record) {
+    match checksum::verify(image, expected) {
+        Ok(()) => log::debug!("selfcheck: {} matches its checksum", uri),
+
+        Err(Mismatch::MissingRecord) => {
+            log::warn!("selfcheck: {} is not the image of Ion that is running", uri)
+        }
+
+        Err(Mismatch::Size { expected, actual }) => log::warn!(
+            "selfcheck: {} is corrupted (expected {} bytes, found {} bytes)",
+            uri,
+            expected,
+            actual
+        ),
+
+        Err(Mismatch::Crc { expected, actual }) => log::warn!(
+            "selfcheck: {} is corrupted (expected CRC32 {:#010x}, found {:#010x})",
+            uri,
+            expected,
+            actual
+        ),
+    }
+}
@@ -0,0 +1,339 @@
+use core::convert::TryFrom;
+use core::fmt;
+use core::fmt::Write;
+
+use spin::mutex::SpinMutex;
+use spin::Once;
+
+#[cfg(target_arch = "x86_64")]
+use uart_16550::SerialPort;
+use uefi::prelude::*;
+use uefi::proto::console::serial::{ControlBits, Serial};
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::Char16;
+
+#[cfg(target_arch = "x86_64")]
+use x86_64::instructions::port::{Port, PortReadOnly};
+
+use crate::console::{self, ConsoleSink};
+use crate::logger::Color;
+
+/// The I/O port base of the first serial port (COM1).
+#[cfg(target_arch = "x86_64")]
+const COM1: u16 = 0x3F8;
+
+/// The frequency of the 16550 UART input clock divided by 16.
+#[cfg(target_arch = "x86_64")]
+const UART_BASE_BAUD: u32 = 115200;
+
+/// The baud rate used by the serial console if none is specified in the config.
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+
+/// The amount of times we poll for the rest of an escape sequence before treating
+/// the escape byte as a lone ESC key press.
+const ESCAPE_SEQUENCE_SPINS: usize = 100000;
+
+enum Backend {
+    /// The firmware's Serial I/O protocol. Only usable while the boot services are
+    /// active.
+    Uefi(*mut Serial<'static>),
+    /// Ion's own 16550 UART driver used if the firmware does not provide the Serial
+    /// I/O protocol or after we have exited the boot services.
+    #[cfg(target_arch = "x86_64")]
+    Uart(SerialPort),
+    /// There is no UART at a well known address on other architectures, so the serial
+    /// console stops working once the boot services have been exited.
+    #[cfg(not(target_arch = "x86_64"))]
+    Disabled,
+}
+
+pub struct SerialConsole {
+    backend: Backend,
+    baud_rate: u32,
+    /// A byte that followed an ESC without starting an escape sequence. It is returned
+    /// by the next read, as the key would be lost otherwise.
+    pending: Option<u8>,
+}
+
+// SAFETY: Ion only runs on the bootstrap processor so the raw protocol pointer is
+// never shared between threads.
+unsafe impl Send for SerialConsole {}
+
+impl SerialConsole {
+    #[cfg(target_arch = "x86_64")]
+    fn uart(baud_rate: u32) -> Self {
+        // SAFETY: COM1 is a standard I/O port base on PC compatible machines.
+        let mut port = unsafe { SerialPort::new(COM1) };
+        port.init();
+
+        // The UART driver always initializes the port with a fixed baud rate, so
+        // reprogram the divisor latch with the requested baud rate.
+        let divisor = (UART_BASE_BAUD / baud_rate.max(1)).max(1) as u16;
+
+        let mut data = Port::<u8>::new(COM1);
+        let mut interrupt_enable = Port::<u8>::new(COM1 + 1);
+        let mut line_control = Port::<u8>::new(COM1 + 3);
+
+        unsafe {
+            let control = line_control.read();
+
+            line_control.write(control | 0x80); // Enable DLAB.
+            data.write(divisor as u8);
+            interrupt_enable.write((divisor >> 8) as u8);
+            line_control.write(control & !0x80); // Disable DLAB.
+        }
+
+        Self {
+            backend: Backend::Uart(port),
+            baud_rate,
+            pending: None,
+        }
+    }
+
+    /// Returns the serial console that is used without the firmware's Serial I/O protocol.
+    fn fallback(baud_rate: u32) -> Self {
+        #[cfg(target_arch = "x86_64")]
+        return Self::uart(baud_rate);
+
+        #[cfg(not(target_arch = "x86_64"))]
+        Self {
+            backend: Backend::Disabled,
+            baud_rate,
+            pending: None,
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match &mut self.backend {
+            Backend::Uefi(serial) => {
+                // SAFETY: The protocol pointer is valid as long as the boot services are active.
+                let _ = unsafe { &mut **serial }.write(&[byte]);
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            Backend::Uart(port) => port.send(byte),
+
+            #[cfg(not(target_arch = "x86_64"))]
+            Backend::Disabled => {}
+        }
+    }
+
+    /// Reads a byte from the serial console, if any is available. This function
+    /// does not block.
+    fn read_byte(&mut self) -> Option<u8> {
+        if let Some(byte) = self.pending.take() {
+            return Some(byte);
+        }
+
+        match &mut self.backend {
+            Backend::Uefi(serial) => {
+                // SAFETY: The protocol pointer is valid as long as the boot services are active.
+                let serial = unsafe { &mut **serial };
+                let control = serial.get_control_bits().ok()?.unwrap();
+
+                if control.contains(ControlBits::INPUT_BUFFER_EMPTY) {
+                    return None;
+                }
+
+                let mut byte = [0; 1];
+                serial.read(&mut byte).ok()?;
+
+                Some(byte[0])
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            Backend::Uart(port) => {
+                // Check the data ready bit of the line status register before reading
+                // from the port as the receive function would block otherwise.
+                let mut line_status = PortReadOnly::<u8>::new(COM1 + 5);
+
+                if unsafe { line_status.read() } & 1 == 0 {
+                    return None;
+                }
+
+                Some(port.receive())
+            }
+
+            #[cfg(not(target_arch = "x86_64"))]
+            Backend::Disabled => None,
+        }
+    }
+
+    /// Polls for the next byte of an escape sequence.
+    fn read_sequence_byte(&mut self) -> Option<u8> {
+        for _ in 0..ESCAPE_SEQUENCE_SPINS {
+            if let Some(byte) = self.read_byte() {
+                return Some(byte);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        None
+    }
+
+    /// Reads a key from the serial console and translates VT100 escape sequences
+    /// into the respective UEFI scancodes.
+    fn read_key(&mut self) -> Option<Key> {
+        let byte = self.read_byte()?;
+
+        let escape = Key::Special(ScanCode::ESCAPE);
+
+        // An escape that is followed by an incomplete or unknown sequence is reported as a
+        // plain ESC key, as the menu would never see the key otherwise.
+        let key = match byte {
+            0x1b => match self.read_sequence_byte() {
+                Some(b'[') => match self.read_sequence_byte() {
+                    Some(b'A') => Key::Special(ScanCode::UP),
+                    Some(b'B') => Key::Special(ScanCode::DOWN),
+                    Some(b'C') => Key::Special(ScanCode::RIGHT),
+                    Some(b'D') => Key::Special(ScanCode::LEFT),
+                    Some(b'H') => Key::Special(ScanCode::HOME),
+                    Some(b'F') => Key::Special(ScanCode::END),
+
+                    // The page up and page down keys are followed by a tilde.
+                    Some(b'5') => self
+                        .read_sequence_byte()
+                        .map_or(escape, |_| Key::Special(ScanCode::PAGE_UP)),
+                    Some(b'6') => self
+                        .read_sequence_byte()
+                        .map_or(escape, |_| Key::Special(ScanCode::PAGE_DOWN)),
+
+                    _ => escape,
+                },
+
+                Some(b'O') => match self.read_sequence_byte() {
+                    Some(b'P') => Key::Special(ScanCode::FUNCTION_1),
+                    Some(b'Q') => Key::Special(ScanCode::FUNCTION_2),
+                    Some(b'R') => Key::Special(ScanCode::FUNCTION_3),
+                    Some(b'S') => Key::Special(ScanCode::FUNCTION_4),
+
+                    _ => escape,
+                },
+
+                // The byte does not start an escape sequence (for example an Alt+key
+                // combination), so keep it for the next read.
+                Some(byte) => {
+                    self.pending = Some(byte);
+                    escape
+                }
+
+                None => escape,
+            },
+
+            // Terminals send either a carriage return or a line feed for the ENTER key,
+            // UEFI reports a carriage return.
+            b'\r' | b'\n' => Key::Printable(Char16::try_from('\r').unwrap()),
+            0x7f => Key::Printable(Char16::try_from('\u{8}').unwrap()),
+
+            byte => Key::Printable(Char16::try_from(byte as char).ok()?),
+        };
+
+        Some(key)
+    }
+}
+
+impl fmt::Write for SerialConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect a carriage return before every line feed.
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+impl ConsoleSink for SpinMutex<SerialConsole> {
+    fn write_str(&self, s: &str) {
+        let _ = fmt::Write::write_str(&mut *self.lock(), s);
+    }
+
+    fn clear(&self) {
+        self.write_str("\x1b[2J\x1b[H");
+    }
+
+    fn set_cursor_pos(&self, column: usize, row: usize) {
+        let _ = write!(self.lock(), "\x1b[{};{}H", row + 1, column + 1);
+    }
+
+    fn clear_line(&self, row: usize) {
+        let _ = write!(self.lock(), "\x1b[{};1H\x1b[2K", row + 1);
+    }
+
+    /// The serial console cannot display arbitrary colors, so any color other than the
+    /// default one highlights the text using reverse video instead.
+    fn set_fg(&self, color: Color) {
+        if color == Color::new(u32::MAX) {
+            self.write_str("\x1b[0m");
+        } else {
+            self.write_str("\x1b[7m");
+        }
+    }
+}
+
+/// The global serial console instance. Only initialized if the serial console has been
+/// enabled in the config.
+static SERIAL: Once<SpinMutex<SerialConsole>> = Once::new();
+
+/// This function is responsible for initializing the serial console with the provided
+/// baud rate. The firmware's Serial I/O protocol is preferred over Ion's own UART driver
+/// while the boot services are active.
+pub fn init(system_table: &SystemTable<Boot>, baud_rate: u32) {
+    if SERIAL.get().is_some() {
+        return;
+    }
+
+    let serial = SERIAL.call_once(|| {
+        let console = match system_table.boot_services().locate_protocol::<Serial>() {
+            Ok(serial) => {
+                let serial = serial.unwrap().get() as *mut Serial<'static>;
+
+                // SAFETY: The protocol pointer is valid as long as the boot services are active.
+                let protocol = unsafe { &mut *serial };
+                let mut io_mode = *protocol.io_mode();
+                io_mode.baud_rate = baud_rate as u64;
+
+                if protocol.set_attributes(&io_mode).is_err() {
+                    log::warn!("serial: failed to set the baud rate to {}", baud_rate);
+                }
+
+                SerialConsole {
+                    backend: Backend::Uefi(serial),
+                    baud_rate,
+                    pending: None,
+                }
+            }
+
+            Err(_) => SerialConsole::fallback(baud_rate),
+        };
+
+        SpinMutex::new(console)
+    });
+
+    console::register(serial);
+}
+
+/// Switches the serial console over to Ion's own UART driver. Must be called before
+/// exiting the boot services.
+pub fn exit_boot_services() {
+    if let Some(serial) = SERIAL.get() {
+        let mut serial = serial.lock();
+        *serial = SerialConsole::fallback(serial.baud_rate);
+    }
+}
+
+/// Returns true if the serial console has been initialized.
+pub fn is_enabled() -> bool {
+    SERIAL.get().is_some()
+}
+
+/// Reads a key from the serial console, if any is available. This function does not
+/// block.
+pub fn read_key() -> Option<Key> {
+    SERIAL.get().and_then(|s| s.lock().read_key())
+}
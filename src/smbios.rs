@@ -0,0 +1,63 @@
+use spin::mutex::SpinMutex;
+use uefi::prelude::*;
+use uefi::table::cfg::{SMBIOS3_GUID, SMBIOS_GUID};
+
+use ion_core::smbios::{EntryPoint, Summary};
+
+/// The line describing the machine and its firmware, shown in the header of the menu.
+static MACHINE: SpinMutex<Option<&'static str>> = SpinMutex::new(None);
+
+/// This function is responsible for locating the SMBIOS tables in the UEFI configuration
+/// table and reading the identification of the machine from them. The 64-bit entry point
+/// is preferred, as the 32-bit one cannot describe tables above 4 GiB.
+pub fn init(system_table: &SystemTable<Boot>) {
+    let config_table = system_table.config_table();
+
+    let entry_point = config_table
+        .iter()
+        .find(|entry| entry.guid == SMBIOS3_GUID)
+        .or_else(|| config_table.iter().find(|entry| entry.guid == SMBIOS_GUID));
+
+    let address = match entry_point {
+        Some(entry) => entry.address as *const u8,
+        None => {
+            log::debug!("smbios: the firmware does not provide SMBIOS tables");
+            return;
+        }
+    };
+
+    // SAFETY: The firmware guarantees that the table starts with an entry point, whose
+    // signature tells its size.
+    let entry_point = unsafe {
+        let signature = core::slice::from_raw_parts(address, 5);
+        EntryPoint::size(signature)
+            .and_then(|size| EntryPoint::parse(core::slice::from_raw_parts(address, size)))
+    };
+
+    let entry_point = match entry_point {
+        Some(entry_point) => entry_point,
+        None => {
+            log::warn!("smbios: the entry point has an invalid signature");
+            return;
+        }
+    };
+
+    // SAFETY: The structure table provided by the firmware is identity-mapped.
+    let table = unsafe {
+        core::slice::from_raw_parts(
+            entry_point.table_address as *const u8,
+            entry_point.table_len,
+        )
+    };
+
+    if let Some(line) = Summary::parse(table).line() {
+        log::info!("smbios: {}", line);
+        *MACHINE.lock() = Some(alloc::boxed::Box::leak(line.into_boxed_str()));
+    }
+}
+
+/// Returns the line describing the machine and its firmware (e.g. `LENOVO 20KH006MGE,
+/// firmware LENOVO N23ET75W (1.50) 10/13/2020`), if the firmware provides SMBIOS tables.
+pub fn machine() -> Option<&'static str> {
+    *MACHINE.lock()
+}
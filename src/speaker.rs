@@ -0,0 +1,45 @@
+use uefi::prelude::*;
+#[cfg(target_arch = "x86_64")]
+use x86_64::instructions::port::Port;
+
+/// The frequency of the PIT input clock in Hz.
+const PIT_FREQUENCY: u32 = 1193182;
+
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const SPEAKER_CONTROL: u16 = 0x61;
+
+/// This function is responsible for playing a tone with the provided `frequency` (in Hz)
+/// for the provided amount of milliseconds on the PC speaker.
+#[cfg(target_arch = "x86_64")]
+pub fn beep(system_table: &SystemTable<Boot>, frequency: u32, duration_ms: usize) {
+    let divisor = PIT_FREQUENCY / frequency;
+
+    let mut command = Port::<u8>::new(PIT_COMMAND);
+    let mut channel_2 = Port::<u8>::new(PIT_CHANNEL_2);
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+
+    unsafe {
+        // Program PIT channel 2 as a square wave generator (mode 3) with the
+        // requested frequency.
+        command.write(0b1011_0110);
+        channel_2.write(divisor as u8);
+        channel_2.write((divisor >> 8) as u8);
+
+        // Connect the speaker to PIT channel 2 by setting the gate and data bits.
+        let value = control.read();
+        control.write(value | 0b11);
+    }
+
+    system_table.boot_services().stall(duration_ms * 1000);
+
+    unsafe {
+        // Disconnect the speaker again.
+        let value = control.read();
+        control.write(value & !0b11);
+    }
+}
+
+/// There is no PC speaker on other architectures, so the menu stays silent.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn beep(_system_table: &SystemTable<Boot>, _frequency: u32, _duration_ms: usize) {}
@@ -0,0 +1,164 @@
+use spin::mutex::SpinMutex;
+
+use crate::acpi;
+use crate::bmp::Bitmap;
+use crate::graphics;
+use crate::input;
+use crate::logger::{self, Color};
+
+/// The height of the progress bar in pixels.
+const PROGRESS_BAR_HEIGHT: usize = 8;
+/// The space between the logo and the progress bar in pixels.
+const PROGRESS_BAR_MARGIN: usize = 32;
+
+const PROGRESS_BAR_BACKGROUND: Color = Color::new(0x404040);
+const PROGRESS_BAR_FOREGROUND: Color = Color::new(0xFFFFFF);
+
+/// The milestones of the boot process at which the progress bar is advanced.
+#[derive(Debug, Clone, Copy)]
+pub enum Milestone {
+    ConfigLoaded,
+    KernelRead,
+    PagingSetUp,
+    Handoff,
+}
+
+impl Milestone {
+    /// Returns the progress in percent that is displayed once the milestone is reached.
+    fn progress(&self) -> usize {
+        match self {
+            Self::ConfigLoaded => 25,
+            Self::KernelRead => 50,
+            Self::PagingSetUp => 75,
+            Self::Handoff => 100,
+        }
+    }
+}
+
+struct Splash {
+    /// The position of the progress bar in pixels.
+    bar_x: usize,
+    bar_y: usize,
+    bar_width: usize,
+}
+
+/// The active splash screen, if any.
+static SPLASH: SpinMutex<Option<Splash>> = SpinMutex::new(None);
+
+/// Returns the logo that the firmware has drawn on the screen (as described by the BGRT)
+/// along with its position, so that the splash screen seamlessly continues the boot
+/// screen of the firmware.
+pub fn firmware_logo() -> Option<(Bitmap, (usize, usize))> {
+    let bgrt = acpi::Bgrt::get().filter(|bgrt| bgrt.is_displayed())?;
+    let logo = Bitmap::parse(bgrt.image()?)?;
+
+    let (width, height) = (logger::display_width(), logger::display_height());
+    let (x, y) = bgrt.offset();
+
+    // The position refers to the mode of the firmware. If Ion has switched the mode, the
+    // logo is centered horizontally and its center is kept at the same relative height.
+    let position = match graphics::firmware_resolution() {
+        Some((firmware_width, firmware_height))
+            if (firmware_width, firmware_height) != (width, height) =>
+        {
+            let center = (y + logo.height() / 2) * height / firmware_height.max(1);
+
+            (
+                width.saturating_sub(logo.width()) / 2,
+                center.saturating_sub(logo.height() / 2),
+            )
+        }
+
+        _ => (x, y),
+    };
+
+    if position.0 + logo.width() > width || position.1 + logo.height() > height {
+        return None;
+    }
+
+    Some((logo, position))
+}
+
+/// This function is responsible for showing the splash screen with the provided logo at
+/// the provided position, or centered on the screen if there is none. The log records are
+/// hidden until a key is pressed.
+pub fn show(logo: Option<Bitmap>, position: Option<(usize, usize)>) {
+    logger::set_quiet(true);
+    logger::clear();
+
+    let (width, height) = (logger::display_width(), logger::display_height());
+
+    let logo_bottom = match &logo {
+        Some(logo) => {
+            let (x, y) = position.unwrap_or((
+                width.saturating_sub(logo.width()) / 2,
+                height.saturating_sub(logo.height()) / 2,
+            ));
+
+            logger::draw_image(x, y, logo.width(), logo.height(), |x, y| logo.pixel(x, y));
+            y + logo.height()
+        }
+
+        None => height / 2,
+    };
+
+    let bar_width = width / 3;
+
+    *SPLASH.lock() = Some(Splash {
+        bar_x: (width - bar_width) / 2,
+        bar_y: logo_bottom + PROGRESS_BAR_MARGIN,
+        bar_width,
+    });
+
+    advance(Milestone::ConfigLoaded);
+}
+
+/// Advances the progress bar to the provided milestone, if the splash screen is shown.
+pub fn advance(milestone: Milestone) {
+    if let Some(splash) = SPLASH.lock().as_ref() {
+        let filled = splash.bar_width * milestone.progress() / 100;
+
+        logger::draw_image(
+            splash.bar_x,
+            splash.bar_y,
+            splash.bar_width,
+            PROGRESS_BAR_HEIGHT,
+            |x, _| {
+                if x < filled {
+                    PROGRESS_BAR_FOREGROUND
+                } else {
+                    PROGRESS_BAR_BACKGROUND
+                }
+            },
+        );
+
+        logger::flush();
+    }
+}
+
+/// Returns true if the splash screen is shown.
+pub fn is_active() -> bool {
+    SPLASH.lock().is_some()
+}
+
+/// Hides the splash screen and shows the log records if a key has been pressed on the
+/// keyboard or the serial console. This function does not block.
+pub fn check_for_keypress() {
+    if !is_active() {
+        return;
+    }
+
+    if input::poll().is_some() {
+        hide();
+    }
+}
+
+/// Hides the splash screen and shows all of the log records that have been hidden so far.
+pub fn hide() {
+    *SPLASH.lock() = None;
+
+    logger::set_quiet(false);
+    logger::clear();
+    logger::show_boot_log();
+    logger::flush();
+}
@@ -0,0 +1,143 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch;
+
+/// The size of the embedded symbol table in bytes.
+const SYMBOL_TABLE_SIZE: usize = 256 * 1024;
+
+/// The magic bytes at the start of the embedded symbol table. `tools/embed_symbols.py`
+/// looks for them to find the symbol table in the built image.
+const SYMBOL_TABLE_MAGIC: [u8; 8] = *b"IONSYMS\0";
+
+/// The maximum amount of frames that are walked by [`backtrace`].
+const MAX_FRAMES: usize = 32;
+
+const fn empty_symbol_table() -> [u8; SYMBOL_TABLE_SIZE] {
+    let mut table = [0; SYMBOL_TABLE_SIZE];
+    let mut i = 0;
+
+    while i < SYMBOL_TABLE_MAGIC.len() {
+        table[i] = SYMBOL_TABLE_MAGIC[i];
+        i += 1;
+    }
+
+    table
+}
+
+/// The symbol table of Ion, filled in after the build by `tools/embed_symbols.py`. The
+/// table consists of the magic bytes, the amount of symbols as a little endian u32, the
+/// symbols sorted by address as pairs of little endian u32s (the address relative to the
+/// image base and the offset of the name) and the null terminated names.
+#[used]
+#[link_section = ".ionsyms"]
+static SYMBOL_TABLE: [u8; SYMBOL_TABLE_SIZE] = empty_symbol_table();
+
+/// The address at which the firmware has loaded Ion.
+static IMAGE_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the address at which the firmware has loaded Ion, used to translate addresses into
+/// symbol table entries.
+pub fn set_image_base(image_base: u64) {
+    IMAGE_BASE.store(image_base, Ordering::Relaxed);
+}
+
+/// Returns the symbol table. The pointer to the table is laundered through a volatile read,
+/// as the table is patched after the build and the compiler must not constant fold reads
+/// of its initial contents.
+fn symbol_table() -> &'static [u8; SYMBOL_TABLE_SIZE] {
+    let table = &SYMBOL_TABLE as *const [u8; SYMBOL_TABLE_SIZE];
+
+    // SAFETY: The pointer is valid as it is derived from a reference to a static.
+    unsafe { &*core::ptr::read_volatile(&table) }
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        table[offset],
+        table[offset + 1],
+        table[offset + 2],
+        table[offset + 3],
+    ])
+}
+
+/// Returns the name of the function containing the provided address and the offset of the
+/// address into the function, if the symbol table has been embedded.
+pub fn lookup(address: u64) -> Option<(&'static str, u64)> {
+    let image_base = IMAGE_BASE.load(Ordering::Relaxed);
+    let relative = address.checked_sub(image_base)?;
+
+    let table = symbol_table();
+    let count = read_u32(table, SYMBOL_TABLE_MAGIC.len()) as usize;
+    let entries = SYMBOL_TABLE_MAGIC.len() + 4;
+
+    // Make sure that a corrupted symbol table does not make us read out of bounds.
+    if entries + count * 8 > SYMBOL_TABLE_SIZE {
+        return None;
+    }
+
+    let entry = |i: usize| {
+        (
+            read_u32(table, entries + i * 8),
+            read_u32(table, entries + i * 8 + 4),
+        )
+    };
+
+    // Find the last symbol that starts at or below the address.
+    let (mut low, mut high) = (0, count);
+
+    while low < high {
+        let mid = (low + high) / 2;
+
+        if entry(mid).0 as u64 <= relative {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let (symbol_address, name_offset) = entry(low.checked_sub(1)?);
+
+    let name = table.get(name_offset as usize..)?;
+    let len = name.iter().position(|c| *c == 0)?;
+    let name = core::str::from_utf8(&name[..len]).ok()?;
+
+    Some((name, relative - symbol_address as u64))
+}
+
+/// Walks the frame pointers starting at the frame of the caller and calls the provided
+/// function with the return address of each frame. Ion is built with frame pointers, see
+/// `.cargo/config.toml`.
+pub fn backtrace<F>(mut f: F)
+where
+    F: FnMut(u64),
+{
+    let mut frame_pointer = arch::frame_pointer();
+
+    for _ in 0..MAX_FRAMES {
+        if frame_pointer <= arch::FRAME_RECORD_OFFSET
+            || frame_pointer % core::mem::size_of::<usize>() as u64 != 0
+        {
+            break;
+        }
+
+        // SAFETY: Each frame starts with the frame pointer of the previous frame followed
+        // by the return address, both of which are pointer sized.
+        let (next, return_address) = unsafe {
+            let frame = (frame_pointer - arch::FRAME_RECORD_OFFSET) as *const usize;
+            (*frame as u64, *frame.add(1) as u64)
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        f(return_address);
+
+        // The stack grows down, so the previous frames are always at higher addresses.
+        if next <= frame_pointer {
+            break;
+        }
+
+        frame_pointer = next;
+    }
+}
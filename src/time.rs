@@ -0,0 +1,186 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use uefi::prelude::*;
+
+use crate::acpi;
+use crate::arch;
+
+/// The duration of the counter calibration in milliseconds.
+const CALIBRATION_MS: u64 = 10;
+
+/// The offset of the general capabilities and ID register of the HPET.
+const HPET_CAPABILITIES: u64 = 0x00;
+/// The offset of the general configuration register of the HPET.
+const HPET_CONFIGURATION: u64 = 0x10;
+/// The offset of the main counter value register of the HPET.
+const HPET_MAIN_COUNTER: u64 = 0xf0;
+
+/// The bit of the general configuration register that starts the main counter.
+const HPET_ENABLE: u64 = 1 << 0;
+
+/// The amount of counter ticks per millisecond, or 0 if the counter has not been
+/// calibrated yet.
+static COUNTER_TICKS_PER_MS: AtomicU64 = AtomicU64::new(0);
+/// The value of the counter when Ion has been entered (see [`mark_entry`]), or when the
+/// timer was calibrated otherwise.
+static COUNTER_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// The memory mapped registers of the high precision event timer.
+struct Hpet {
+    base: u64,
+}
+
+impl Hpet {
+    /// Returns the HPET described by the ACPI tables, if there is any and its registers
+    /// are memory mapped.
+    fn get() -> Option<Self> {
+        let address = acpi::Hpet::get()?.base_address();
+
+        // The registers are always memory mapped in practice, the system I/O address space
+        // is not supported.
+        if address.address_space != 0 || address.address == 0 {
+            return None;
+        }
+
+        Some(Self {
+            base: address.address,
+        })
+    }
+
+    #[inline]
+    fn read(&self, offset: u64) -> u64 {
+        // SAFETY: The HPET registers are identity mapped, both by the firmware and in the
+        // bootloader address space.
+        unsafe { ((self.base + offset) as *const u64).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, offset: u64, value: u64) {
+        unsafe { ((self.base + offset) as *mut u64).write_volatile(value) }
+    }
+
+    /// Returns the period of the main counter in femtoseconds.
+    fn period_fs(&self) -> u64 {
+        self.read(HPET_CAPABILITIES) >> 32
+    }
+
+    /// Busy waits for the provided amount of milliseconds using the main counter, which is
+    /// started if the firmware has not done so.
+    fn wait_ms(&self, milliseconds: u64) {
+        let configuration = self.read(HPET_CONFIGURATION);
+
+        if configuration & HPET_ENABLE == 0 {
+            self.write(HPET_CONFIGURATION, configuration | HPET_ENABLE);
+        }
+
+        let ticks = milliseconds * 1_000_000_000_000 / self.period_fs();
+        let start = self.read(HPET_MAIN_COUNTER);
+
+        while self.read(HPET_MAIN_COUNTER).wrapping_sub(start) < ticks {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Calibrates the counter against the HPET or, if there is no HPET, against the stall
+/// boot service. Returns the amount of ticks per millisecond and the name of the reference.
+fn calibrate(system_table: &SystemTable<Boot>) -> (u64, &'static str) {
+    let hpet = Hpet::get().filter(|hpet| {
+        let period = hpet.period_fs();
+
+        // The specification limits the period to 100 nanoseconds.
+        period != 0 && period <= 100_000_000
+    });
+
+    let start = arch::read_counter();
+
+    let source = match hpet {
+        Some(hpet) => {
+            hpet.wait_ms(CALIBRATION_MS);
+            "HPET"
+        }
+
+        None => {
+            system_table
+                .boot_services()
+                .stall(CALIBRATION_MS as usize * 1000);
+            "UEFI stall"
+        }
+    };
+
+    let ticks_per_ms = ((arch::read_counter() - start) / CALIBRATION_MS).max(1);
+    (ticks_per_ms, source)
+}
+
+/// Records the value of the counter when Ion has been entered, so that the timestamps
+/// include the time spent before the timer was calibrated. Must be called before [`init`].
+pub fn mark_entry() {
+    COUNTER_BASE.store(arch::read_counter(), Ordering::SeqCst);
+}
+
+/// This function is responsible for setting up the timer. The counter of the processor
+/// (e.g. the TSC) is calibrated, unless its frequency is reported by the processor. Must be
+/// called after [`acpi::init`] and before exiting the boot services. Afterwards the
+/// functions of this module keep working after the boot services have been exited.
+pub fn init(system_table: &SystemTable<Boot>) {
+    if !arch::counter_invariant() {
+        log::warn!("time: the counter is not invariant, timings might be inaccurate");
+    }
+
+    let (ticks_per_ms, source) = match arch::counter_frequency() {
+        Some(frequency) => ((frequency / 1000).max(1), "the processor"),
+        None => calibrate(system_table),
+    };
+
+    COUNTER_TICKS_PER_MS.store(ticks_per_ms, Ordering::SeqCst);
+    let _ =
+        COUNTER_BASE.compare_exchange(0, arch::read_counter(), Ordering::SeqCst, Ordering::SeqCst);
+
+    log::debug!(
+        "time: counter frequency is {} MHz (reported by {})",
+        ticks_per_ms / 1000,
+        source
+    );
+}
+
+/// Returns the amount of milliseconds since Ion has been entered. The timestamps are
+/// monotonic and 0 if [`init`] has not been called yet.
+pub fn timestamp_ms() -> u64 {
+    let ticks_per_ms = COUNTER_TICKS_PER_MS.load(Ordering::Relaxed);
+
+    if ticks_per_ms == 0 {
+        return 0;
+    }
+
+    arch::read_counter().saturating_sub(COUNTER_BASE.load(Ordering::Relaxed)) / ticks_per_ms
+}
+
+/// Returns the amount of microseconds since Ion has been entered. The timestamps are
+/// monotonic and 0 if [`init`] has not been called yet.
+pub fn timestamp_us() -> u64 {
+    let ticks_per_ms = COUNTER_TICKS_PER_MS.load(Ordering::Relaxed);
+
+    if ticks_per_ms == 0 {
+        return 0;
+    }
+
+    let ticks = arch::read_counter().saturating_sub(COUNTER_BASE.load(Ordering::Relaxed));
+    (ticks as u128 * 1000 / ticks_per_ms as u128) as u64
+}
+
+/// Busy waits for the provided amount of milliseconds. Returns immediately if [`init`] has
+/// not been called yet.
+pub fn sleep_ms(milliseconds: u64) {
+    let ticks = milliseconds * COUNTER_TICKS_PER_MS.load(Ordering::Relaxed);
+    let start = arch::read_counter();
+
+    while arch::read_counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the amount of milliseconds since the provided timestamp.
+#[inline]
+pub fn elapsed_ms(timestamp: u64) -> u64 {
+    timestamp_ms().saturating_sub(timestamp)
+}
@@ -0,0 +1,280 @@
+# Minimal Linux-like test payload. It carries a real mode setup header like a bzImage, so
+# that it is loaded by the Linux boot protocol, but only implements the 64-bit entry point.
+# It checks the boot parameters and reports the results over the serial port in the format
+# that is expected by the integration test harness (see `xtask`).
+#
+# The payload is position independent, as the bootloader is free to relocate it.
+
+.intel_syntax noprefix
+
+.set SETUP_SECTS, 1
+.set KERNEL_START, (SETUP_SECTS + 1) * 512
+
+.set LOADED_HIGH, 1 << 0
+.set XLF_KERNEL_64, 1 << 0
+.set XLF_CAN_BE_LOADED_ABOVE_4G, 1 << 1
+
+# Offsets into the boot parameters ("zero page").
+.set BP_ORIG_VIDEO_IS_VGA, 0x0f
+.set BP_LFB_BASE, 0x18
+.set BP_EXT_LFB_BASE, 0x3a
+.set BP_EXT_CMD_LINE_PTR, 0xc8
+.set BP_EFI_LOADER_SIGNATURE, 0x1c0
+.set BP_E820_ENTRIES, 0x1e8
+.set BP_BOOT_FLAG, 0x1fe
+.set BP_HEADER, 0x202
+.set BP_TYPE_OF_LOADER, 0x210
+.set BP_CMD_LINE_PTR, 0x228
+.set BP_E820_TABLE, 0x2d0
+
+.set E820_ENTRY_SIZE, 20
+.set E820_RAM, 1
+.set VIDEO_TYPE_EFI, 0x70
+
+.set COM1, 0x3f8
+.set DEBUG_EXIT, 0xf4
+
+.section .text
+
+# The real mode setup code is never run, only the setup header is read by the bootloader.
+.code16
+setup_start:
+.org 0x1f1
+    .byte SETUP_SECTS                       # setup_sects
+    .short 0                                # root_flags
+    .long (payload_end - kernel_start) / 16 # syssize
+    .short 0                                # ram_size
+    .short 0xffff                           # vid_mode
+    .short 0                                # root_dev
+    .short 0xaa55                           # boot_flag
+    jmp setup_code                          # jump
+.org 0x202
+    .ascii "HdrS"                           # header
+    .short 0x020f                           # version
+    .long 0                                 # realmode_swtch
+    .short 0                                # start_sys_seg
+    .short 0                                # kernel_version
+    .byte 0                                 # type_of_loader
+    .byte LOADED_HIGH                       # loadflags
+    .short 0                                # setup_move_size
+    .long 0x100000                          # code32_start
+    .long 0                                 # ramdisk_image
+    .long 0                                 # ramdisk_size
+    .long 0                                 # bootsect_kludge
+    .short 0                                # heap_end_ptr
+    .byte 0                                 # ext_loader_ver
+    .byte 0                                 # ext_loader_type
+    .long 0                                 # cmd_line_ptr
+    .long 0x7fffffff                        # initrd_addr_max
+    .long 0x200000                          # kernel_alignment
+    .byte 1                                 # relocatable_kernel
+    .byte 21                                # min_alignment
+    .short XLF_KERNEL_64 | XLF_CAN_BE_LOADED_ABOVE_4G # xloadflags
+    .long 255                               # cmdline_size
+    .long 0                                 # hardware_subarch
+    .quad 0                                 # hardware_subarch_data
+    .long 0                                 # payload_offset
+    .long 0                                 # payload_length
+    .quad 0                                 # setup_data
+    .quad 0x1000000                         # pref_address
+    .long init_end - kernel_start           # init_size
+    .long 0                                 # handover_offset
+    .long 0                                 # kernel_info_offset
+
+setup_code:
+    hlt
+    jmp setup_code
+
+# The protected mode kernel, with the 64-bit entry point at offset 0x200.
+.org KERNEL_START
+kernel_start:
+.code32
+    hlt
+    jmp kernel_start
+
+.org KERNEL_START + 0x200
+.code64
+startup_64:
+    cli
+    cld
+    lea rsp, [rip + stack_top]
+
+    # Keep the address of the boot parameters around.
+    mov rbp, rsi
+
+    call header
+    lea rsi, [rip + check_header]
+    call report
+
+    call loader
+    lea rsi, [rip + check_loader]
+    call report
+
+    call cmdline
+    lea rsi, [rip + check_cmdline]
+    call report
+
+    call e820
+    lea rsi, [rip + check_e820]
+    call report
+
+    call framebuffer
+    lea rsi, [rip + check_framebuffer]
+    call report
+
+    call efi
+    lea rsi, [rip + check_efi]
+    call report
+
+    lea rsi, [rip + done_marker]
+    call puts
+
+    mov al, 0x10
+    out DEBUG_EXIT, al
+
+1:
+    hlt
+    jmp 1b
+
+# Writes the NUL-terminated string at RSI to the serial port.
+puts:
+    push rax
+    push rdx
+
+1:
+    lodsb
+    test al, al
+    jz 3f
+
+    mov ah, al
+    mov dx, COM1 + 5
+
+    # Wait for the transmit holding register to be empty.
+2:
+    in al, dx
+    test al, 0x20
+    jz 2b
+
+    mov al, ah
+    mov dx, COM1
+    out dx, al
+    jmp 1b
+
+3:
+    pop rdx
+    pop rax
+    ret
+
+# Reports the check named by the string at RSI as passed if RAX is zero and as failed
+# otherwise.
+report:
+    push rsi
+    lea rsi, [rip + pass_prefix]
+    test rax, rax
+    jz 1f
+    lea rsi, [rip + fail_prefix]
+1:
+    call puts
+    pop rsi
+    call puts
+    lea rsi, [rip + newline]
+    jmp puts
+
+# The checks return zero in RAX if they passed.
+failed:
+    mov eax, 1
+    ret
+
+passed:
+    xor eax, eax
+    ret
+
+# Checks that the setup header has been copied into the boot parameters.
+header:
+    cmp word ptr [rbp + BP_BOOT_FLAG], 0xaa55
+    jne failed
+    cmp dword ptr [rbp + BP_HEADER], 0x53726448 # "HdrS"
+    jne failed
+    jmp passed
+
+# Checks that the bootloader identified itself.
+loader:
+    cmp byte ptr [rbp + BP_TYPE_OF_LOADER], 0
+    je failed
+    jmp passed
+
+cmdline:
+    mov esi, [rbp + BP_CMD_LINE_PTR]
+    mov eax, [rbp + BP_EXT_CMD_LINE_PTR]
+    shl rax, 32
+    or rsi, rax
+    jz failed
+
+    lea rdi, [rip + expected_cmdline]
+    mov ecx, expected_cmdline_end - expected_cmdline
+    xor eax, eax
+    repe cmpsb
+    setne al
+    ret
+
+# Checks that there is at least one usable memory region.
+e820:
+    movzx ecx, byte ptr [rbp + BP_E820_ENTRIES]
+    lea rdx, [rbp + BP_E820_TABLE]
+
+1:
+    test ecx, ecx
+    jz failed
+    cmp dword ptr [rdx + 16], E820_RAM
+    je passed
+    add rdx, E820_ENTRY_SIZE
+    dec ecx
+    jmp 1b
+
+# Checks that the EFI framebuffer is described and that the first pixel can be written.
+framebuffer:
+    cmp byte ptr [rbp + BP_ORIG_VIDEO_IS_VGA], VIDEO_TYPE_EFI
+    jne failed
+
+    mov edx, [rbp + BP_LFB_BASE]
+    mov eax, [rbp + BP_EXT_LFB_BASE]
+    shl rax, 32
+    or rdx, rax
+    jz failed
+
+    mov dword ptr [rdx], 0x00ffffff
+    cmp dword ptr [rdx], 0x00ffffff
+    jne failed
+    jmp passed
+
+# Checks that the bootloader passed the EFI system table of a 64-bit firmware.
+efi:
+    cmp dword ptr [rbp + BP_EFI_LOADER_SIGNATURE], 0x34364c45 # "EL64"
+    jne failed
+    jmp passed
+
+check_header: .asciz "header"
+check_loader: .asciz "type_of_loader"
+check_cmdline: .asciz "cmdline"
+check_e820: .asciz "e820"
+check_framebuffer: .asciz "framebuffer"
+check_efi: .asciz "efi"
+
+pass_prefix: .asciz "PASS: "
+fail_prefix: .asciz "FAIL: "
+done_marker: .asciz "DONE\n"
+newline: .asciz "\n"
+
+# The command line passed by the integration test config.
+expected_cmdline: .asciz "ion conformance test"
+expected_cmdline_end:
+
+.balign 16
+payload_end:
+
+# The stack is part of the memory reserved by init_size, but not of the image.
+.section .bss
+.balign 16
+    .skip 16384
+stack_top:
+init_end:
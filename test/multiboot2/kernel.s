@@ -0,0 +1,261 @@
+# Multiboot2 test kernel. It is entered in 32-bit protected mode with paging disabled,
+# checks the boot information and reports the results over the serial port in the format
+# that is expected by the integration test harness (see `xtask`).
+
+.intel_syntax noprefix
+
+.set MULTIBOOT2_MAGIC, 0xe85250d6
+.set MULTIBOOT2_BOOTLOADER_MAGIC, 0x36d76289
+.set MULTIBOOT2_HEADER_LENGTH, header_end - header_start
+
+.set TAG_END, 0
+.set TAG_CMDLINE, 1
+.set TAG_MEMORY_MAP, 6
+.set TAG_FRAMEBUFFER, 8
+
+.set MEMORY_MAP_AVAILABLE, 1
+.set FRAMEBUFFER_TYPE_RGB, 1
+
+.set COM1, 0x3f8
+.set DEBUG_EXIT, 0xf4
+
+.section .multiboot2, "a"
+.align 8
+header_start:
+    .long MULTIBOOT2_MAGIC
+    .long 0                                 # i386
+    .long MULTIBOOT2_HEADER_LENGTH
+    .long 0x100000000 - (MULTIBOOT2_MAGIC + MULTIBOOT2_HEADER_LENGTH)
+
+    # The information request tag, which makes the tags that are checked mandatory.
+.align 8
+information_request_start:
+    .short 1
+    .short 0
+    .long information_request_end - information_request_start
+    .long TAG_CMDLINE
+    .long TAG_MEMORY_MAP
+    .long TAG_FRAMEBUFFER
+information_request_end:
+
+    # The framebuffer tag, requesting the preferred resolution of the bootloader.
+.align 8
+    .short 5
+    .short 0
+    .long 20
+    .long 0
+    .long 0
+    .long 32
+
+.align 8
+    .short TAG_END
+    .short 0
+    .long 8
+header_end:
+
+.section .rodata
+check_magic: .asciz "magic"
+check_cmdline: .asciz "cmdline"
+check_memory_map: .asciz "memmap"
+check_framebuffer: .asciz "framebuffer"
+
+pass_prefix: .asciz "PASS: "
+fail_prefix: .asciz "FAIL: "
+done_marker: .asciz "DONE\n"
+newline: .asciz "\n"
+
+# The command line passed by the integration test config.
+expected_cmdline: .asciz "ion conformance test"
+expected_cmdline_end:
+
+.section .bss
+.align 16
+stack_bottom:
+    .skip 16384
+stack_top:
+
+.section .text
+.code32
+
+.global _start
+_start:
+    cli
+    cld
+    mov esp, offset stack_top
+
+    # Keep the address of the boot information around.
+    mov ebp, ebx
+
+    xor ecx, ecx
+    cmp eax, MULTIBOOT2_BOOTLOADER_MAGIC
+    setne cl
+    mov eax, ecx
+    mov esi, offset check_magic
+    call report
+
+    # Without the right magic value EBX is not a valid boot information pointer.
+    test eax, eax
+    jnz done
+
+    call cmdline
+    mov esi, offset check_cmdline
+    call report
+
+    call memory_map
+    mov esi, offset check_memory_map
+    call report
+
+    call framebuffer
+    mov esi, offset check_framebuffer
+    call report
+
+done:
+    mov esi, offset done_marker
+    call puts
+
+    mov al, 0x10
+    out DEBUG_EXIT, al
+
+1:
+    hlt
+    jmp 1b
+
+# Writes the NUL-terminated string at ESI to the serial port.
+puts:
+    push eax
+    push edx
+
+1:
+    lodsb
+    test al, al
+    jz 3f
+
+    mov ah, al
+    mov dx, COM1 + 5
+
+    # Wait for the transmit holding register to be empty.
+2:
+    in al, dx
+    test al, 0x20
+    jz 2b
+
+    mov al, ah
+    mov dx, COM1
+    out dx, al
+    jmp 1b
+
+3:
+    pop edx
+    pop eax
+    ret
+
+# Reports the check named by the string at ESI as passed if EAX is zero and as failed
+# otherwise.
+report:
+    push esi
+    mov esi, offset pass_prefix
+    test eax, eax
+    jz 1f
+    mov esi, offset fail_prefix
+1:
+    call puts
+    pop esi
+    call puts
+    mov esi, offset newline
+    jmp puts
+
+# Returns the address of the boot information tag with the type in ECX in EAX, or zero if
+# there is no such tag.
+find_tag:
+    lea eax, [ebp + 8]
+
+1:
+    mov edx, [eax]
+    cmp edx, TAG_END
+    je 2f
+    cmp edx, ecx
+    je 3f
+
+    # The tags are padded to 8 bytes.
+    mov edx, [eax + 4]
+    add edx, 7
+    and edx, ~7
+    add eax, edx
+    jmp 1b
+
+2:
+    xor eax, eax
+3:
+    ret
+
+# The checks return zero in EAX if they passed.
+failed:
+    mov eax, 1
+    ret
+
+cmdline:
+    mov ecx, TAG_CMDLINE
+    call find_tag
+    test eax, eax
+    jz failed
+
+    lea esi, [eax + 8]
+    mov edi, offset expected_cmdline
+    mov ecx, expected_cmdline_end - expected_cmdline
+    xor eax, eax
+    repe cmpsb
+    setne al
+    ret
+
+# Checks that there is at least one available memory region.
+memory_map:
+    mov ecx, TAG_MEMORY_MAP
+    call find_tag
+    test eax, eax
+    jz failed
+
+    mov ebx, [eax + 8]                      # entry size
+    test ebx, ebx
+    jz failed
+
+    mov ecx, eax
+    add ecx, [eax + 4]                      # end of the tag
+    lea edx, [eax + 16]                     # first entry
+
+1:
+    cmp edx, ecx
+    jae failed
+    cmp dword ptr [edx + 16], MEMORY_MAP_AVAILABLE
+    je 2f
+    add edx, ebx
+    jmp 1b
+
+2:
+    xor eax, eax
+    ret
+
+# Checks the framebuffer description and that the first pixel can be written.
+framebuffer:
+    mov ecx, TAG_FRAMEBUFFER
+    call find_tag
+    test eax, eax
+    jz failed
+
+    # The framebuffer is not reachable without paging if it is above 4 GiB.
+    cmp dword ptr [eax + 12], 0
+    jne failed
+    cmp byte ptr [eax + 28], 32             # bits per pixel
+    jne failed
+    cmp byte ptr [eax + 29], FRAMEBUFFER_TYPE_RGB
+    jne failed
+
+    mov edx, [eax + 8]
+    test edx, edx
+    jz failed
+
+    mov dword ptr [edx], 0x00ffffff
+    cmp dword ptr [edx], 0x00ffffff
+    jne failed
+
+    xor eax, eax
+    ret
@@ -1,7 +1,10 @@
 #![no_std]
 #![no_main]
 
+use core::arch::asm;
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use stivale_boot::v2::*;
 
@@ -14,6 +17,52 @@ const STACK_SIZE: usize = 4096 * 16;
 /// We are going to allocate our stack as an uninitialised array in .bss.
 static STACK: P2Align12<[u8; STACK_SIZE]> = P2Align12([0; STACK_SIZE]);
 
+/// A stivale2 header tag. The header tags are linked using their addresses, so they have to
+/// be statics.
+#[repr(C)]
+struct HeaderTag<T> {
+    identifier: u64,
+    next: *const (),
+    body: T,
+}
+
+// SAFETY: The header tags are only read by the bootloader.
+unsafe impl<T> Sync for HeaderTag<T> {}
+
+#[repr(C)]
+struct FramebufferHeader {
+    width: u16,
+    height: u16,
+    bits_per_pixel: u16,
+    unused: u16,
+}
+
+/// Requests a framebuffer with the preferred resolution of the bootloader.
+static FRAMEBUFFER_HEADER_TAG: HeaderTag<FramebufferHeader> = HeaderTag {
+    identifier: 0x3ecc1bc43d0f7971,
+    next: &SMP_HEADER_TAG as *const _ as *const (),
+    body: FramebufferHeader {
+        width: 0,
+        height: 0,
+        bits_per_pixel: 0,
+        unused: 0,
+    },
+};
+
+/// Requests the application processors to be started, in xAPIC mode.
+static SMP_HEADER_TAG: HeaderTag<u64> = HeaderTag {
+    identifier: 0x1ab015085f3273df,
+    next: &LEVEL_5_PAGING_HEADER_TAG as *const _ as *const (),
+    body: 0,
+};
+
+/// Requests five-level paging, which is only enabled if the CPU supports it.
+static LEVEL_5_PAGING_HEADER_TAG: HeaderTag<()> = HeaderTag {
+    identifier: 0x932f477032007e8f,
+    next: core::ptr::null(),
+    body: (),
+};
+
 /// The stivale2 specification says we need to define a "header structure".
 /// This structure needs to reside in the .stivale2hdr ELF section in order
 /// for the bootloader to find it. We use the #[linker_section] and #[used] macros to
@@ -23,14 +72,531 @@ static STACK: P2Align12<[u8; STACK_SIZE]> = P2Align12([0; STACK_SIZE]);
 #[used]
 static STIVALE_HDR: StivaleHeader = StivaleHeader::new()
     .stack(&STACK.0[STACK_SIZE - 4096] as *const u8)
-    .tags(0x00 as *const ());
+    .tags(&FRAMEBUFFER_HEADER_TAG as *const _ as *const ());
+
+/// The command line passed by the integration test config (see `xtask`).
+const EXPECTED_CMDLINE: &str = "ion conformance test";
+
+/// The amount of processors that QEMU is started with by the integration test harness.
+const EXPECTED_CPU_COUNT: u64 = 2;
+
+const CMDLINE_TAG_ID: u64 = 0xe5e76a1b4597a781;
+const MEMORY_MAP_TAG_ID: u64 = 0x2187f79e8612de07;
+const FRAMEBUFFER_TAG_ID: u64 = 0x506461d2950408fa;
+const HHDM_TAG_ID: u64 = 0xb0ed257db18cb58f;
+const SMP_TAG_ID: u64 = 0x34d1d96339647025;
+const ION_GDT_TAG_ID: u64 = 0x696f6e676474626c;
+const ION_PROFILE_TAG_ID: u64 = 0x696f6e70726f666c;
+const ION_BOOT_LOG_TAG_ID: u64 = 0x696f6e62746c6f67;
+const ION_RANDOM_SEED_TAG_ID: u64 = 0x696f6e7273656564;
+
+const MEMORY_MAP_USABLE: u32 = 1;
+const MEMORY_MAP_KERNEL_AND_MODULES: u32 = 0x1001;
+
+/// The boot information passed to the kernel entry point (`struct stivale2_struct`).
+#[repr(C)]
+struct BootInfo {
+    bootloader_brand: [u8; 64],
+    bootloader_version: [u8; 64],
+    tags: u64,
+}
+
+#[repr(C)]
+struct TagHeader {
+    identifier: u64,
+    next: u64,
+}
+
+#[repr(C)]
+struct CmdlineTag {
+    header: TagHeader,
+    address: u64,
+}
+
+#[repr(C)]
+struct MemoryMapTag {
+    header: TagHeader,
+    entries: u64,
+}
+
+#[repr(C)]
+struct MemoryMapEntry {
+    base: u64,
+    length: u64,
+    kind: u32,
+    unused: u32,
+}
+
+#[repr(C)]
+struct FramebufferTag {
+    header: TagHeader,
+    address: u64,
+    width: u16,
+    height: u16,
+    pitch: u16,
+    bits_per_pixel: u16,
+    memory_model: u8,
+    red_mask_size: u8,
+    red_mask_shift: u8,
+    green_mask_size: u8,
+    green_mask_shift: u8,
+    blue_mask_size: u8,
+    blue_mask_shift: u8,
+    unused: u8,
+}
+
+#[repr(C)]
+struct HhdmTag {
+    header: TagHeader,
+    address: u64,
+}
+
+#[repr(C)]
+struct SmpTag {
+    header: TagHeader,
+    flags: u64,
+    bsp_lapic_id: u32,
+    unused: u32,
+    cpu_count: u64,
+}
+
+#[repr(C)]
+struct SmpInfo {
+    processor_id: u32,
+    lapic_id: u32,
+    target_stack: u64,
+    goto_address: u64,
+    extra_argument: u64,
+}
+
+#[repr(C)]
+struct GdtTag {
+    header: TagHeader,
+    address: u64,
+    size: u64,
+}
+
+#[repr(C)]
+struct ProfileTag {
+    header: TagHeader,
+    timestamp: u64,
+    phase_count: u64,
+}
+
+#[repr(C)]
+struct RandomSeedTag {
+    header: TagHeader,
+    size: u64,
+    seed: [u8; 64],
+}
+
+#[repr(C)]
+struct BootLogTag {
+    header: TagHeader,
+    address: u64,
+    size: u64,
+    head: u64,
+    wrapped: u64,
+}
+
+impl BootInfo {
+    /// Returns the struct tag with the provided identifier, if the bootloader passed it.
+    fn tag<T>(&self, identifier: u64) -> Option<&T> {
+        let mut tag = self.tags as *const TagHeader;
+
+        // SAFETY: The bootloader guarantees that the tags are mapped.
+        while let Some(header) = unsafe { tag.as_ref() } {
+            if header.identifier == identifier {
+                return Some(unsafe { &*(tag as *const T) });
+            }
+
+            tag = header.next as *const TagHeader;
+        }
+
+        None
+    }
+}
+
+/// The COM1 serial port, which has already been initialized by the bootloader. The results
+/// of the checks are reported over it to the integration test harness (see `xtask`).
+const COM1: u16 = 0x3f8;
+
+/// The I/O port of the QEMU `isa-debug-exit` device.
+const DEBUG_EXIT: u16 = 0xf4;
+
+unsafe fn outb(port: u16, value: u8) {
+    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack));
+}
+
+unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack));
+    value
+}
+
+struct Serial;
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            unsafe {
+                // Wait for the transmit holding register to be empty.
+                while inb(COM1 + 5) & 0x20 == 0 {}
+                outb(COM1, byte);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+macro_rules! serial_println {
+    ($($arg:tt)*) => {
+        let _ = writeln!(Serial, $($arg)*);
+    };
+}
+
+/// Reports the result of a check to the integration test harness.
+fn report(check: &str, result: Result<(), &str>) {
+    match result {
+        Ok(()) => {
+            serial_println!("PASS: {}", check);
+        }
+
+        Err(reason) => {
+            serial_println!("FAIL: {} ({})", check, reason);
+        }
+    }
+}
+
+/// Returns the string up to the first NUL byte.
+fn c_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("<invalid UTF-8>")
+}
+
+fn check_brand(info: &BootInfo) -> Result<(), &'static str> {
+    if c_str(&info.bootloader_brand) != "Ion" {
+        return Err("unexpected bootloader brand");
+    }
+
+    if c_str(&info.bootloader_version).is_empty() {
+        return Err("empty bootloader version");
+    }
+
+    Ok(())
+}
+
+fn check_cmdline(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<CmdlineTag>(CMDLINE_TAG_ID)
+        .ok_or("tag not found")?;
+
+    // SAFETY: The command line is a NUL-terminated string in the boot information.
+    let cmdline = unsafe {
+        let ptr = tag.address as *const u8;
+        let mut len = 0;
+
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+
+        core::slice::from_raw_parts(ptr, len)
+    };
+
+    let cmdline = core::str::from_utf8(cmdline).map_err(|_| "invalid UTF-8")?;
+    serial_println!("cmdline: {}", cmdline);
+
+    if cmdline != EXPECTED_CMDLINE {
+        return Err("command line does not match the config");
+    }
+
+    Ok(())
+}
+
+fn check_memory_map(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<MemoryMapTag>(MEMORY_MAP_TAG_ID)
+        .ok_or("tag not found")?;
+
+    // SAFETY: The entries follow the tag.
+    let entries = unsafe {
+        let ptr = (tag as *const MemoryMapTag).add(1) as *const MemoryMapEntry;
+        core::slice::from_raw_parts(ptr, tag.entries as usize)
+    };
+
+    if entries.is_empty() {
+        return Err("memory map is empty");
+    }
+
+    let mut usable = 0;
+    let mut kernel = false;
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.length == 0 {
+            return Err("empty entry");
+        }
+
+        if let Some(next) = entries.get(i + 1) {
+            if entry.base + entry.length > next.base {
+                return Err("entries are unsorted or overlap");
+            }
+        }
+
+        match entry.kind {
+            MEMORY_MAP_USABLE => {
+                if entry.base % 4096 != 0 || entry.length % 4096 != 0 {
+                    return Err("usable entry is not page aligned");
+                }
+
+                usable += entry.length;
+            }
+
+            MEMORY_MAP_KERNEL_AND_MODULES => kernel = true,
+            _ => {}
+        }
+    }
+
+    serial_println!(
+        "memmap: {} entries, {} KiB usable",
+        entries.len(),
+        usable / 1024
+    );
+
+    if usable == 0 {
+        return Err("no usable memory");
+    }
+
+    if !kernel {
+        return Err("the kernel is not reported");
+    }
+
+    Ok(())
+}
+
+fn check_framebuffer(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<FramebufferTag>(FRAMEBUFFER_TAG_ID)
+        .ok_or("tag not found")?;
+
+    serial_println!(
+        "framebuffer: {}x{}, {} bpp, pitch {}",
+        tag.width,
+        tag.height,
+        tag.bits_per_pixel,
+        tag.pitch
+    );
+
+    if tag.address == 0 || tag.width == 0 || tag.height == 0 {
+        return Err("invalid framebuffer");
+    }
+
+    if tag.bits_per_pixel != 32 {
+        return Err("unsupported pixel size");
+    }
+
+    if (tag.pitch as u64) < tag.width as u64 * 4 {
+        return Err("pitch is smaller than a line");
+    }
+
+    let mask = |size: u8, shift: u8| ((1u64 << size) - 1) << shift;
+    let red = mask(tag.red_mask_size, tag.red_mask_shift);
+    let green = mask(tag.green_mask_size, tag.green_mask_shift);
+    let blue = mask(tag.blue_mask_size, tag.blue_mask_shift);
+
+    if red & green != 0
+        || red & blue != 0
+        || green & blue != 0
+        || (red | green | blue) > u32::MAX as u64
+    {
+        return Err("invalid color masks");
+    }
+
+    // Write to the first and the last pixel and read them back, which faults if the
+    // framebuffer is not mapped.
+    let first = tag.address as *mut u32;
+    let last = (tag.address
+        + (tag.height as u64 - 1) * tag.pitch as u64
+        + (tag.width as u64 - 1) * 4) as *mut u32;
+
+    for pixel in [first, last] {
+        // SAFETY: The pixels are within the framebuffer.
+        unsafe {
+            pixel.write_volatile(green as u32);
+
+            if pixel.read_volatile() != green as u32 {
+                return Err("pixel did not read back");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_hhdm(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info.tag::<HhdmTag>(HHDM_TAG_ID).ok_or("tag not found")?;
+
+    if tag.address < 0xffff_8000_0000_0000 || tag.address % 0x20_0000 != 0 {
+        return Err("invalid direct map address");
+    }
+
+    Ok(())
+}
+
+/// The amount of application processors that have reached [`ap_entry`].
+static AP_CHECKED_IN: AtomicUsize = AtomicUsize::new(0);
+
+const AP_STACK_SIZE: usize = 4096 * 4;
+const MAX_APS: usize = 8;
+
+static mut AP_STACKS: P2Align12<[[u8; AP_STACK_SIZE]; MAX_APS]> =
+    P2Align12([[0; AP_STACK_SIZE]; MAX_APS]);
+
+extern "C" fn ap_entry(_info: &'static SmpInfo) -> ! {
+    AP_CHECKED_IN.fetch_add(1, Ordering::SeqCst);
+
+    loop {
+        unsafe { asm!("cli; hlt") }
+    }
+}
+
+fn check_smp(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info.tag::<SmpTag>(SMP_TAG_ID).ok_or("tag not found")?;
+
+    // SAFETY: The per-CPU information follows the tag.
+    let cpus = unsafe {
+        let ptr = (tag as *const SmpTag).add(1) as *mut SmpInfo;
+        core::slice::from_raw_parts_mut(ptr, tag.cpu_count as usize)
+    };
+
+    serial_println!("smp: {} processors", cpus.len());
+
+    if tag.cpu_count != EXPECTED_CPU_COUNT {
+        return Err("unexpected processor count");
+    }
+
+    if !cpus.iter().any(|cpu| cpu.lapic_id == tag.bsp_lapic_id) {
+        return Err("the bootstrap processor is not listed");
+    }
+
+    let mut started = 0;
+
+    for cpu in cpus.iter_mut() {
+        if cpu.lapic_id == tag.bsp_lapic_id || started == MAX_APS {
+            continue;
+        }
+
+        // SAFETY: Every application processor gets its own stack.
+        let stack = unsafe { AP_STACKS.0[started].as_ptr() as u64 + AP_STACK_SIZE as u64 };
+
+        cpu.target_stack = stack;
+
+        // The application processor starts as soon as the address is written, so it has to
+        // be written last.
+        unsafe {
+            core::ptr::write_volatile(&mut cpu.goto_address, ap_entry as usize as u64);
+        }
+
+        started += 1;
+    }
+
+    for _ in 0..100_000_000 {
+        if AP_CHECKED_IN.load(Ordering::SeqCst) == started {
+            return Ok(());
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Err("not all application processors checked in")
+}
+
+fn check_gdt(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info.tag::<GdtTag>(ION_GDT_TAG_ID).ok_or("tag not found")?;
+
+    if tag.address == 0 || tag.size == 0 || tag.size % 8 != 0 {
+        return Err("invalid GDT");
+    }
+
+    Ok(())
+}
+
+fn check_profile(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<ProfileTag>(ION_PROFILE_TAG_ID)
+        .ok_or("tag not found")?;
+
+    if tag.phase_count == 0 {
+        return Err("no phases");
+    }
+
+    Ok(())
+}
+
+fn check_random_seed(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<RandomSeedTag>(ION_RANDOM_SEED_TAG_ID)
+        .ok_or("tag not found")?;
+
+    if tag.size != tag.seed.len() as u64 {
+        return Err("unexpected seed size");
+    }
+
+    // The chance of 64 random bytes all being zero is negligible.
+    if tag.seed.iter().all(|&byte| byte == 0) {
+        return Err("seed is zero");
+    }
+
+    Ok(())
+}
+
+fn check_boot_log(info: &BootInfo) -> Result<(), &'static str> {
+    let tag = info
+        .tag::<BootLogTag>(ION_BOOT_LOG_TAG_ID)
+        .ok_or("tag not found")?;
+
+    if tag.size == 0 || tag.head >= tag.size {
+        return Err("invalid ring buffer");
+    }
+
+    if tag.head == 0 && tag.wrapped == 0 {
+        return Err("boot log is empty");
+    }
+
+    Ok(())
+}
+
+/// Reports that all of the checks have been run and exits QEMU.
+fn done() -> ! {
+    serial_println!("DONE");
+
+    unsafe {
+        outb(DEBUG_EXIT, 0x10);
+    }
 
-#[no_mangle]
-extern "C" fn _start() -> ! {
     loop {}
 }
 
+#[no_mangle]
+extern "C" fn _start(info: &'static BootInfo) -> ! {
+    serial_println!("PASS: entry");
+
+    report("brand", check_brand(info));
+    report("cmdline", check_cmdline(info));
+    report("memmap", check_memory_map(info));
+    report("framebuffer", check_framebuffer(info));
+    report("hhdm", check_hhdm(info));
+    report("smp", check_smp(info));
+    report("gdt", check_gdt(info));
+    report("profile", check_profile(info));
+    report("random seed", check_random_seed(info));
+    report("boot log", check_boot_log(info));
+
+    done()
+}
+
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("FAIL: panic ({})", info);
+    done()
 }
@@ -0,0 +1,66 @@
+#!/usr/bin/env python3
+"""Embeds the size and the CRC32 of the built image of Ion into its `.ioncrc` section, so
+that Ion can detect a corrupted copy of itself on the EFI system partition.
+
+The CRC32 covers the whole image, with the size and the CRC32 treated as zeros. This has
+to be the last step that modifies the image (i.e. after embed_symbols.py).
+
+Usage: embed_checksum.py <ion.efi>
+"""
+
+import struct
+import sys
+import zlib
+
+MAGIC = b"IONCRC32"
+SECTION_NAME = b".ioncrc"
+
+
+def record_offset(image):
+    """Returns the file offset of the checksum record, which is the start of .ioncrc."""
+    pe = struct.unpack_from("<I", image, 0x3C)[0]
+
+    if image[pe : pe + 4] != b"PE\0\0":
+        sys.exit("embed_checksum: not a PE image")
+
+    section_count = struct.unpack_from("<H", image, pe + 6)[0]
+    optional_header_size = struct.unpack_from("<H", image, pe + 20)[0]
+    sections = pe + 24 + optional_header_size
+
+    for i in range(section_count):
+        header = sections + i * 40
+
+        if image[header : header + 8].rstrip(b"\0") == SECTION_NAME:
+            return struct.unpack_from("<I", image, header + 20)[0]
+
+    sys.exit("embed_checksum: section .ioncrc not found")
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+
+    image_path = sys.argv[1]
+
+    with open(image_path, "rb") as image_file:
+        image = bytearray(image_file.read())
+
+    offset = record_offset(image)
+
+    if image[offset : offset + len(MAGIC)] != MAGIC:
+        sys.exit("embed_checksum: .ioncrc does not contain the checksum record")
+
+    fields = offset + len(MAGIC)
+    image[fields : fields + 8] = bytes(8)
+
+    crc = zlib.crc32(image) & 0xFFFFFFFF
+    image[fields : fields + 8] = struct.pack("<II", len(image), crc)
+
+    with open(image_path, "wb") as image_file:
+        image_file.write(image)
+
+    print("embed_checksum: embedded CRC32 {:#010x}".format(crc))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,158 @@
+#!/usr/bin/env python3
+"""Embeds the symbol table of Ion into the `.ionsyms` section of the built image, so
+that the panic screen can print a symbolized backtrace.
+
+The symbols are read from the map file written by the linker (see `.cargo/config.toml`).
+
+Usage: embed_symbols.py <ion.efi> <ion.map>
+"""
+
+import re
+import struct
+import sys
+
+MAGIC = b"IONSYMS\0"
+TABLE_SIZE = 256 * 1024
+SECTION_NAME = b".ionsyms"
+
+# Symbol lines of the lld-link map file consist of the address, the size, the alignment
+# and the name of the symbol.
+SYMBOL_LINE = re.compile(r"^\s*([0-9a-fA-F]+)\s+([0-9a-fA-F]+)\s+(\d+)\s+(\S+)$")
+
+
+def pe_info(image):
+    """Returns the image base and the file offset of the symbol table section."""
+    pe = struct.unpack_from("<I", image, 0x3C)[0]
+
+    if image[pe : pe + 4] != b"PE\0\0":
+        sys.exit("embed_symbols: not a PE image")
+
+    section_count = struct.unpack_from("<H", image, pe + 6)[0]
+    optional_header_size = struct.unpack_from("<H", image, pe + 20)[0]
+    optional_header = pe + 24
+
+    # The image base is a 64-bit value in PE32+ images.
+    image_base = struct.unpack_from("<Q", image, optional_header + 24)[0]
+    sections = optional_header + optional_header_size
+
+    for i in range(section_count):
+        header = sections + i * 40
+
+        if image[header : header + 8].rstrip(b"\0") == SECTION_NAME:
+            return image_base, struct.unpack_from("<I", image, header + 20)[0]
+
+    sys.exit("embed_symbols: section .ionsyms not found")
+
+
+def demangle(name):
+    """Demangles legacy Rust symbol names, dropping the trailing hash."""
+    match = re.match(r"^_?_ZN(.*)E$", name)
+
+    if not match:
+        return name
+
+    rest = match.group(1)
+    parts = []
+
+    while rest:
+        length = re.match(r"^(\d+)", rest)
+
+        if not length:
+            return name
+
+        start = len(length.group(1))
+        end = start + int(length.group(1))
+
+        part = rest[start:end]
+
+        # Components starting with an escape sequence are prefixed with an underscore.
+        parts.append(part[1:] if part.startswith("_$") else part)
+        rest = rest[end:]
+
+    if parts and re.match(r"^h[0-9a-f]{16}$", parts[-1]):
+        parts.pop()
+
+    demangled = "::".join(parts)
+
+    for escape, replacement in (
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("..", "::"),
+    ):
+        demangled = demangled.replace(escape, replacement)
+
+    return demangled
+
+
+def read_symbols(map_path, image_base):
+    symbols = {}
+
+    with open(map_path) as map_file:
+        for line in map_file:
+            match = SYMBOL_LINE.match(line)
+
+            if not match:
+                continue
+
+            address, _, _, name = match.groups()
+
+            # Skip the section and input file lines.
+            if name.startswith(".") or ":(" in name:
+                continue
+
+            relative = int(address, 16) - image_base
+
+            if 0 <= relative < 2**32:
+                symbols.setdefault(relative, demangle(name))
+
+    return sorted(symbols.items())
+
+
+def build_table(symbols):
+    names = bytearray()
+    entries = bytearray()
+    names_offset = len(MAGIC) + 4 + len(symbols) * 8
+
+    for address, name in symbols:
+        entries += struct.pack("<II", address, names_offset + len(names))
+        names += name.encode() + b"\0"
+
+    table = MAGIC + struct.pack("<I", len(symbols)) + entries + names
+
+    if len(table) > TABLE_SIZE:
+        sys.exit("embed_symbols: the symbol table does not fit into .ionsyms")
+
+    return table
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+
+    image_path, map_path = sys.argv[1:]
+
+    with open(image_path, "rb") as image_file:
+        image = bytearray(image_file.read())
+
+    image_base, offset = pe_info(image)
+
+    if image[offset : offset + len(MAGIC)] != MAGIC:
+        sys.exit("embed_symbols: .ionsyms does not contain the symbol table")
+
+    table = build_table(read_symbols(map_path, image_base))
+    image[offset : offset + len(table)] = table
+
+    with open(image_path, "wb") as image_file:
+        image_file.write(image)
+
+    print("embed_symbols: embedded {} symbols".format(struct.unpack_from("<I", table, 8)[0]))
+
+
+if __name__ == "__main__":
+    main()
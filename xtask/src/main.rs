@@ -0,0 +1,308 @@
+// Builds Ion and the test kernels and boots each of them in QEMU using OVMF, asserting on
+// the results that the test kernels report over the serial port.
+//
+// The test kernels print a `PASS: <check>` or `FAIL: <check>` line for each of their checks
+// followed by a `DONE` line once they are finished.
+
+mod qemu;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::Duration;
+
+use ion_mkimage::Layout;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// The default time after which a test kernel that has not finished is reported as failed.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The size of the disk images that the test kernels are booted from.
+const IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The OVMF image downloaded by `make ovmf-x64`.
+const DEFAULT_OVMF: &str = "ovmf/OVMF-pure-efi.fd";
+
+/// An integration test, which boots a test kernel using the provided config.
+struct TestCase {
+    name: &'static str,
+    /// The directory containing the Makefile of the test kernel, which copies the kernel
+    /// into the `build` directory.
+    kernel_dir: &'static str,
+    /// The file name of the kernel in the `build` directory, which is copied into the
+    /// `boot` directory of the ESP.
+    kernel: &'static str,
+    config: &'static str,
+    /// Set for the test cases of boot protocols that are not implemented yet. They are only
+    /// run with `--include-ignored`.
+    ignored: bool,
+}
+
+const TESTS: &[TestCase] = &[
+    TestCase {
+        name: "stivale2",
+        kernel_dir: "test/stivale2",
+        kernel: "stivale2.elf",
+        config: "TIMEOUT=1\nSERIAL=yes\nVERBOSE=yes\n\n\
+                 :stivale2 conformance\n\
+                 PROTOCOL=stivale2\n\
+                 KERNEL_PATH=boot:///boot/stivale2.elf\n\
+                 KERNEL_CMDLINE=ion conformance test\n",
+        ignored: false,
+    },
+    TestCase {
+        name: "multiboot2",
+        kernel_dir: "test/multiboot2",
+        kernel: "multiboot2.elf",
+        config: "TIMEOUT=1\nSERIAL=yes\nVERBOSE=yes\n\n\
+                 :multiboot2 conformance\n\
+                 PROTOCOL=multiboot2\n\
+                 KERNEL_PATH=boot:///boot/multiboot2.elf\n\
+                 KERNEL_CMDLINE=ion conformance test\n",
+        ignored: false,
+    },
+    TestCase {
+        name: "linux",
+        kernel_dir: "test/linux",
+        kernel: "linux.bin",
+        config: "TIMEOUT=1\nSERIAL=yes\nVERBOSE=yes\n\n\
+                 :linux conformance\n\
+                 PROTOCOL=linux\n\
+                 KERNEL_PATH=boot:///boot/linux.bin\n\
+                 KERNEL_CMDLINE=ion conformance test\n",
+        ignored: false,
+    },
+];
+
+struct Options {
+    qemu: String,
+    ovmf: Option<PathBuf>,
+    timeout: Duration,
+    /// Also run the ignored tests.
+    include_ignored: bool,
+    /// Only the tests whose name contains this filter are run.
+    filter: Option<String>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: xtask test [--qemu <binary>] [--ovmf <path>] [--timeout <seconds>] \
+         [--include-ignored] [filter]"
+    );
+    process::exit(2)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options {
+        qemu: String::from("qemu-system-x86_64"),
+        ovmf: None,
+        timeout: DEFAULT_TIMEOUT,
+        include_ignored: false,
+        filter: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--qemu" => options.qemu = args.next().unwrap_or_else(|| usage()),
+            "--ovmf" => options.ovmf = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--include-ignored" => options.include_ignored = true,
+            "--timeout" => {
+                let seconds = args
+                    .next()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or_else(|| usage());
+
+                options.timeout = Duration::from_secs(seconds);
+            }
+
+            filter if !filter.starts_with("--") && options.filter.is_none() => {
+                options.filter = Some(String::from(filter))
+            }
+
+            _ => usage(),
+        }
+    }
+
+    options
+}
+
+/// Returns the root of the Ion repository.
+fn repository_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask: the xtask crate has to be in the repository")
+        .to_path_buf()
+}
+
+/// Runs the provided command and returns an error if it fails.
+fn run(command: &mut Command) -> Result<()> {
+    // The host tools are built with overridden flags and toolchain, which must not leak
+    // into the builds of Ion and the test kernels.
+    let status = command
+        .env_remove("RUSTFLAGS")
+        .env_remove("RUSTUP_TOOLCHAIN")
+        .env_remove("CARGO_BUILD_TARGET")
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("xtask: {:?} failed with {}", command, status).into());
+    }
+
+    Ok(())
+}
+
+/// Builds Ion and embeds its symbol table and its checksum, returning the path of the EFI
+/// image.
+fn build_ion(root: &Path) -> Result<PathBuf> {
+    run(Command::new("cargo")
+        .arg("build")
+        .arg("--release")
+        .current_dir(root))?;
+
+    let image = root.join("target/x86_64-unknown-uefi/release/ion.efi");
+
+    run(Command::new("python3")
+        .arg("tools/embed_symbols.py")
+        .arg(&image)
+        .arg("target/ion.map")
+        .current_dir(root))?;
+
+    run(Command::new("python3")
+        .arg("tools/embed_checksum.py")
+        .arg(&image)
+        .current_dir(root))?;
+
+    Ok(image)
+}
+
+/// Boots the test kernel of the provided test case and returns true if all of its checks
+/// passed.
+fn run_test(
+    root: &Path,
+    ion: &Path,
+    ovmf: &Path,
+    test: &TestCase,
+    options: &Options,
+) -> Result<bool> {
+    let build = root.join("build");
+
+    run(Command::new("make")
+        .arg("-C")
+        .arg(test.kernel_dir)
+        .arg("--no-print-directory")
+        .current_dir(root))?;
+
+    let config = build.join(format!("{}.cfg", test.name));
+    fs::write(&config, test.config)?;
+
+    let image = build.join(format!("{}.hdd", test.name));
+    let kernel = build.join(test.kernel);
+    let kernel_destination = format!("boot/{}", test.kernel);
+
+    ion_mkimage::create(
+        &image,
+        IMAGE_SIZE,
+        Layout::Gpt,
+        &[
+            ("EFI/BOOT/BOOTX64.EFI", ion),
+            ("ion.cfg", &config),
+            (&kernel_destination, &kernel),
+        ],
+    )?;
+
+    let result = qemu::run(&options.qemu, ovmf, &image, options.timeout)?;
+    let log = build.join(format!("{}.log", test.name));
+    fs::write(&log, &result.output)?;
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for line in result.output.lines().map(str::trim) {
+        if let Some(check) = line.strip_prefix("PASS: ") {
+            println!("    ok   {}", check);
+            passed += 1;
+        } else if let Some(check) = line.strip_prefix("FAIL: ") {
+            println!("    FAIL {}", check);
+            failed += 1;
+        }
+    }
+
+    let done = result
+        .output
+        .lines()
+        .any(|line| line.trim() == qemu::DONE_MARKER);
+
+    if result.timed_out {
+        println!("    FAIL timed out after {:?}", options.timeout);
+    } else if !done {
+        println!("    FAIL QEMU exited before the test kernel finished");
+    }
+
+    println!(
+        "    {} passed, {} failed (serial log: {})",
+        passed,
+        failed,
+        log.display()
+    );
+
+    Ok(done && failed == 0)
+}
+
+fn test(options: Options) -> Result<bool> {
+    let root = repository_root();
+    fs::create_dir_all(root.join("build"))?;
+
+    let ovmf = match &options.ovmf {
+        Some(ovmf) => ovmf.clone(),
+        None => {
+            run(Command::new("make")
+                .arg("ovmf-x64")
+                .arg("--no-print-directory")
+                .current_dir(&root))?;
+
+            root.join(DEFAULT_OVMF)
+        }
+    };
+
+    let ion = build_ion(&root)?;
+    let mut success = true;
+
+    for test in TESTS {
+        if let Some(filter) = &options.filter {
+            if !test.name.contains(filter.as_str()) {
+                continue;
+            }
+        }
+
+        if test.ignored && !options.include_ignored {
+            println!("test {}: ignored", test.name);
+            continue;
+        }
+
+        println!("test {}:", test.name);
+        success &= run_test(&root, &ion, &ovmf, test, &options)?;
+    }
+
+    Ok(success)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let result = match args.next().as_deref() {
+        Some("test") => test(parse_options(args)),
+        _ => usage(),
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The line that the test kernels print over the serial port once all of their checks have
+/// been run.
+pub const DONE_MARKER: &str = "DONE";
+
+/// The output of a test run in QEMU.
+pub struct Run {
+    /// Everything that was written to the serial port.
+    pub output: String,
+    /// Set to true if the test kernel did not finish before the timeout.
+    pub timed_out: bool,
+}
+
+/// Boots the provided ESP image in QEMU using the provided OVMF firmware and collects the
+/// serial output until the test kernel is done, QEMU exits or the timeout expires.
+///
+/// The test kernels exit QEMU using the `isa-debug-exit` device once they are done, so that
+/// QEMU does not have to be killed.
+pub fn run(qemu: &str, ovmf: &Path, image: &Path, timeout: Duration) -> io::Result<Run> {
+    let mut child = Command::new(qemu)
+        .arg("-machine")
+        .arg("type=q35")
+        .arg("-m")
+        .arg("256M")
+        .arg("-smp")
+        .arg("2")
+        .arg("-bios")
+        .arg(ovmf)
+        .arg("-drive")
+        .arg(format!("format=raw,file={}", image.display()))
+        .arg("-serial")
+        .arg("stdio")
+        .arg("-display")
+        .arg("none")
+        .arg("-device")
+        .arg("isa-debug-exit,iobase=0xf4,iosize=0x04")
+        .arg("--no-reboot")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .expect("xtask: QEMU stdout is not piped");
+    let (sender, receiver) = mpsc::channel();
+
+    // Read the serial output on a separate thread, so that we can stop waiting for it once
+    // the timeout expires.
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+
+        while let Ok(len) = stdout.read(&mut buffer) {
+            if len == 0 || sender.send(buffer[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut output = Vec::new();
+    let mut timed_out = true;
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(remaining) {
+            Ok(data) => output.extend_from_slice(&data),
+
+            // QEMU exited, so there will not be any more output.
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                timed_out = false;
+                break;
+            }
+
+            Err(mpsc::RecvTimeoutError::Timeout) => break,
+        }
+
+        if String::from_utf8_lossy(&output)
+            .lines()
+            .any(|line| line.trim() == DONE_MARKER)
+        {
+            timed_out = false;
+            break;
+        }
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+
+    Ok(Run {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        timed_out,
+    })
+}